@@ -1,6 +1,9 @@
 #!/bin/sh -ex
 
-FEATURES="bitcoinconsensus use-serde rand"
+FEATURES="bitcoinconsensus use-serde rand tokio secp-recovery base64 arbitrary rayon v2transport"
+# Features whose dependencies need a newer compiler than the minimum supported one
+MSRV_EXCLUDED_FEATURES="tokio rayon v2transport"
+MSRV="1.37.0"
 
 if [ "$DO_COV" = true ]
 then
@@ -17,9 +20,16 @@ fi
 # Test without any features first
 cargo test --verbose
 
+# Build without std
+cargo build --verbose --no-default-features
+
 # Test each feature
 for feature in ${FEATURES}
 do
+    if [ "$TRAVIS_RUST_VERSION" = "$MSRV" ] && echo " $MSRV_EXCLUDED_FEATURES " | grep -q " $feature "
+    then
+        continue
+    fi
     cargo test --verbose --features="$feature"
 done
 
@@ -0,0 +1,173 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Checkpoints
+//!
+//! Checkpoints are the hashes of known blocks of a chain. Headers
+//! contradicting them are refused, and so are forks from below the last
+//! checkpoint a chain has reached, so that peers cannot feed an SPV client
+//! another chain, even one signed by a former federation key.
+//!
+//! Checkpoints are embedded for the default networks, on top of the genesis
+//! hash of the network parameters, and applications may add the ones of the
+//! chains they follow.
+//!
+
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+use hash_types::BlockHash;
+use hashes::hex::FromHex;
+use network::constants::Network;
+
+/// Checkpoints of the default production network, as heights and block
+/// hashes in the byte order of RPC outputs
+///
+/// Entries must be taken from the published chain of the network: a wrong
+/// one makes every client refuse that chain.
+const PROD_CHECKPOINTS: &[(u32, &str)] = &[];
+
+/// Checkpoints of the default development network, in the same format as
+/// `PROD_CHECKPOINTS`
+const DEV_CHECKPOINTS: &[(u32, &str)] = &[];
+
+/// The checkpoints of a chain, by height
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Checkpoints(BTreeMap<u32, BlockHash>);
+
+impl Checkpoints {
+    /// Create an empty set of checkpoints
+    pub fn new() -> Checkpoints {
+        Checkpoints::default()
+    }
+
+    /// The checkpoints of `network`: the embedded ones of the default
+    /// networks, and the genesis block of its parameters, if known
+    pub fn for_network(network: Network) -> Checkpoints {
+        let embedded: &[(u32, &str)] = match network {
+            Network::Prod => PROD_CHECKPOINTS,
+            Network::Dev => DEV_CHECKPOINTS,
+            Network::Custom(_) => &[],
+        };
+        let mut checkpoints = Checkpoints::from_embedded(embedded);
+        if let Some(genesis_hash) = network.params().genesis_hash {
+            checkpoints.insert(0, genesis_hash);
+        }
+        checkpoints
+    }
+
+    /// Parse a list of embedded checkpoints
+    fn from_embedded(embedded: &[(u32, &str)]) -> Checkpoints {
+        embedded.iter().map(|&(height, hash)| {
+            (height, BlockHash::from_hex(hash).expect("valid embedded checkpoint"))
+        }).collect()
+    }
+
+    /// Add a checkpoint, returning the one it replaces at `height`
+    pub fn insert(&mut self, height: u32, hash: BlockHash) -> Option<BlockHash> {
+        self.0.insert(height, hash)
+    }
+
+    /// Add a checkpoint
+    pub fn with(mut self, height: u32, hash: BlockHash) -> Checkpoints {
+        self.insert(height, hash);
+        self
+    }
+
+    /// The checkpoint at `height`
+    pub fn get(&self, height: u32) -> Option<&BlockHash> {
+        self.0.get(&height)
+    }
+
+    /// Whether the block `hash` may be at `height`, i.e. there is no other
+    /// checkpoint at that height
+    pub fn allows(&self, height: u32, hash: &BlockHash) -> bool {
+        self.get(height).map_or(true, |checkpoint| checkpoint == hash)
+    }
+
+    /// Whether a block may fork from the block at `fork_height` of a chain
+    /// whose tip is at `tip_height`, i.e. no checkpoint of the chain is above
+    /// the fork
+    pub fn allows_fork(&self, fork_height: u32, tip_height: u32) -> bool {
+        fork_height >= tip_height || self.0.range(fork_height + 1..=tip_height).next().is_none()
+    }
+
+    /// The last checkpoint, with its height
+    pub fn last(&self) -> Option<(u32, &BlockHash)> {
+        self.0.iter().next_back().map(|(&height, hash)| (height, hash))
+    }
+
+    /// Iterate over the checkpoints, with their heights, by height
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (u32, &'a BlockHash)> + 'a {
+        self.0.iter().map(|(&height, hash)| (height, hash))
+    }
+
+    /// The number of checkpoints
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether there are no checkpoints
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<(u32, BlockHash)> for Checkpoints {
+    fn from_iter<I: IntoIterator<Item = (u32, BlockHash)>>(iter: I) -> Checkpoints {
+        Checkpoints(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hash_types::BlockHash;
+    use hashes::Hash;
+    use hashes::hex::ToHex;
+    use network::constants::{Network, NetworkParams};
+    use super::{Checkpoints, DEV_CHECKPOINTS, PROD_CHECKPOINTS};
+
+    #[test]
+    fn test_checkpoints() {
+        let genesis_hash = BlockHash::hash(&[0]);
+        let hash = BlockHash::hash(&[1]);
+        assert!(Checkpoints::for_network(Network::Dev).is_empty());
+        let params = NetworkParams::dev_with_id(10).with_genesis_hash(genesis_hash);
+        let checkpoints = Checkpoints::for_network(Network::Custom(params)).with(100, hash);
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints.get(0), Some(&genesis_hash));
+        assert_eq!(checkpoints.last(), Some((100, &hash)));
+        assert_eq!(checkpoints.iter().map(|(height, _)| height).collect::<Vec<_>>(), vec![0, 100]);
+
+        assert!(checkpoints.allows(100, &hash));
+        assert!(!checkpoints.allows(100, &genesis_hash));
+        assert!(checkpoints.allows(101, &genesis_hash));
+
+        // forks are refused from below a checkpoint the chain has reached
+        assert!(checkpoints.allows_fork(10, 99));
+        assert!(!checkpoints.allows_fork(10, 100));
+        assert!(!checkpoints.allows_fork(99, 150));
+        assert!(checkpoints.allows_fork(100, 150));
+        assert!(checkpoints.allows_fork(150, 150));
+    }
+
+    #[test]
+    fn test_embedded_checkpoints() {
+        // the embedded lists parse, by increasing height
+        for &(network, embedded) in &[(Network::Prod, PROD_CHECKPOINTS), (Network::Dev, DEV_CHECKPOINTS)] {
+            let checkpoints = Checkpoints::for_network(network);
+            assert_eq!(checkpoints.len(), embedded.len());
+            assert!(embedded.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+
+        // hashes are in the byte order of RPC outputs, and a block
+        // contradicting one is refused at its height
+        let hash = BlockHash::hash(&[1]);
+        let embedded = [(100, &hash.to_hex()[..])];
+        let checkpoints = Checkpoints::from_embedded(&embedded);
+        assert_eq!(checkpoints.get(100), Some(&hash));
+        assert!(checkpoints.allows(100, &hash));
+        assert!(!checkpoints.allows(100, &BlockHash::hash(&[2])));
+    }
+}
@@ -0,0 +1,442 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Header chain
+//!
+//! `HeaderChain` keeps the headers of the best chain in memory. Headers,
+//! typically received in `headers` messages, are only accepted if they
+//! extend the tip, are timestamped consistently and carry a proof by the
+//! aggregate public key in force at their height.
+//!
+//! As Tapyrus blocks are signed by the federation, there is a single valid
+//! chain and headers forking from it are rejected. Headers contradicting
+//! the checkpoints of the chain are rejected as well.
+//!
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{error, fmt};
+
+use blockchain::checkpoints::Checkpoints;
+use blockchain::header_store::locator_heights;
+use blockdata::aggkey::{self, AggregatePubkeyTracker};
+use blockdata::block::BlockHeader;
+use hash_types::BlockHash;
+use util::hash::BitcoinHash;
+use util::key::PublicKey;
+
+/// Number of blocks whose median time a new block must exceed
+const MEDIAN_TIME_SPAN: usize = 11;
+/// How far in the future a block may be timestamped, in seconds
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Header chain error
+#[derive(Debug)]
+pub enum Error {
+    /// The genesis block does not hold an aggregate public key
+    MissingGenesisKey,
+    /// The parent of the header is unknown
+    UnknownParent(BlockHash),
+    /// The header forks from the chain
+    Fork(BlockHash),
+    /// The header is not newer than the median time of the previous blocks
+    TimeTooOld(BlockHash),
+    /// The header is timestamped too far in the future
+    TimeTooNew(BlockHash),
+    /// The proof of the header is not a valid signature by the aggregate
+    /// public key
+    InvalidProof(BlockHash),
+    /// The header contradicts a checkpoint, or forks from below one
+    CheckpointMismatch(BlockHash),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MissingGenesisKey => f.write_str(error::Error::description(self)),
+            Error::UnknownParent(ref hash)
+            | Error::Fork(ref hash)
+            | Error::TimeTooOld(ref hash)
+            | Error::TimeTooNew(ref hash)
+            | Error::InvalidProof(ref hash)
+            | Error::CheckpointMismatch(ref hash) => write!(f, "{}: {}", error::Error::description(self), hash),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::MissingGenesisKey => "genesis block has no aggregate public key",
+            Error::UnknownParent(_) => "header with unknown parent",
+            Error::Fork(_) => "header forks from the chain",
+            Error::TimeTooOld(_) => "header timestamp too old",
+            Error::TimeTooNew(_) => "header timestamp too far in the future",
+            Error::InvalidProof(_) => "invalid header proof",
+            Error::CheckpointMismatch(_) => "header contradicts a checkpoint",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+/// The validated headers of a chain, starting from its genesis block
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+    heights: HashMap<BlockHash, u32>,
+    validator: Validator,
+}
+
+impl HeaderChain {
+    /// Start a chain from its genesis block, which must be signed by the
+    /// aggregate public key it holds
+    pub fn new(genesis: BlockHeader) -> Result<HeaderChain, Error> {
+        HeaderChain::with_checkpoints(genesis, Checkpoints::new())
+    }
+
+    /// Start a chain from its genesis block, refusing headers which
+    /// contradict `checkpoints`
+    pub fn with_checkpoints(genesis: BlockHeader, checkpoints: Checkpoints) -> Result<HeaderChain, Error> {
+        let validator = Validator::new(&genesis, checkpoints)?;
+        let mut heights = HashMap::new();
+        heights.insert(genesis.bitcoin_hash(), 0);
+        Ok(HeaderChain {
+            headers: vec![genesis],
+            heights: heights,
+            validator: validator,
+        })
+    }
+
+    /// Validate and append headers, as received in a `headers` message.
+    /// Headers already in the chain are skipped. Returns the number of
+    /// headers appended; on error, the headers preceding the invalid one
+    /// are kept.
+    pub fn accept(&mut self, headers: &[BlockHeader]) -> Result<usize, Error> {
+        self.accept_at(headers, unix_time())
+    }
+
+    /// Same as `accept`, with the current time given as a UNIX timestamp
+    pub fn accept_at(&mut self, headers: &[BlockHeader], now: u32) -> Result<usize, Error> {
+        let mut appended = 0;
+        for header in headers {
+            if self.push(header, now)? {
+                appended += 1;
+            }
+        }
+        Ok(appended)
+    }
+
+    /// Append a header, returning false if it is already in the chain
+    fn push(&mut self, header: &BlockHeader, now: u32) -> Result<bool, Error> {
+        let hash = header.bitcoin_hash();
+        if self.heights.contains_key(&hash) {
+            return Ok(false);
+        }
+        if header.prev_blockhash != self.tip_hash() {
+            return Err(self.validator.misplaced(hash, self.height_of(&header.prev_blockhash)));
+        }
+        self.validator.check(header, hash, now)?;
+        self.validator.push(header)?;
+        self.heights.insert(hash, self.headers.len() as u32);
+        self.headers.push(header.clone());
+        Ok(true)
+    }
+
+    /// The median timestamp of the last 11 blocks
+    pub fn median_time_past(&self) -> u32 {
+        self.validator.median_time_past()
+    }
+
+    /// The height of the tip
+    pub fn height(&self) -> u32 {
+        self.headers.len() as u32 - 1
+    }
+
+    /// The last header of the chain
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().expect("chain has a genesis block")
+    }
+
+    /// The hash of the last header of the chain
+    pub fn tip_hash(&self) -> BlockHash {
+        self.tip().bitcoin_hash()
+    }
+
+    /// The header at `height`
+    pub fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    /// The height of the header with the given hash
+    pub fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        self.heights.get(hash).cloned()
+    }
+
+    /// The aggregate public key signing the block at `height`, known up to
+    /// the block following the tip
+    pub fn aggregate_public_key_at(&self, height: u32) -> Option<&PublicKey> {
+        self.validator.keys.key_at(height)
+    }
+
+    /// The checkpoints headers are checked against
+    pub fn checkpoints(&self) -> &Checkpoints {
+        &self.validator.checkpoints
+    }
+
+    /// The aggregate public key signing the next block
+    pub fn next_aggregate_public_key(&self) -> &PublicKey {
+        self.validator.keys.next_key().expect("chain has a genesis block")
+    }
+
+    /// Locator hashes for `getheaders` and `getblocks` messages, from the
+    /// tip to the genesis block with exponentially growing gaps
+    pub fn locator(&self) -> Vec<BlockHash> {
+        locator_heights(self.height()).into_iter().map(|height| self.headers[height as usize].bitcoin_hash()).collect()
+    }
+}
+
+/// Validates the headers following the tip of a chain against the
+/// checkpoints, the timestamps of the previous headers and the aggregate
+/// public key in force. `HeaderChain` and `SyncManager` share it.
+#[derive(Clone, Debug)]
+pub(crate) struct Validator {
+    keys: AggregatePubkeyTracker,
+    checkpoints: Checkpoints,
+    /// The timestamps of the last headers, for their median time past
+    recent_times: VecDeque<u32>,
+}
+
+impl Validator {
+    /// Start from the genesis block, which must be signed by the aggregate
+    /// public key it holds
+    pub(crate) fn new(genesis: &BlockHeader, checkpoints: Checkpoints) -> Result<Validator, Error> {
+        let hash = genesis.bitcoin_hash();
+        if !checkpoints.allows(0, &hash) {
+            return Err(Error::CheckpointMismatch(hash));
+        }
+        let mut keys = AggregatePubkeyTracker::new();
+        keys.push_verified(genesis).map_err(|e| tracker_error(e, hash))?;
+        let mut validator = Validator {
+            keys: keys,
+            checkpoints: checkpoints,
+            recent_times: VecDeque::with_capacity(MEDIAN_TIME_SPAN),
+        };
+        validator.push_time(genesis.time);
+        Ok(validator)
+    }
+
+    /// The height of the tip
+    pub(crate) fn height(&self) -> u32 {
+        self.keys.height().expect("validator has a genesis block")
+    }
+
+    /// The hash of the tip
+    pub(crate) fn tip_hash(&self) -> BlockHash {
+        self.keys.tip().expect("validator has a genesis block")
+    }
+
+    /// The median timestamp of the last 11 blocks
+    pub(crate) fn median_time_past(&self) -> u32 {
+        let mut times: Vec<u32> = self.recent_times.iter().cloned().collect();
+        times.sort();
+        times[times.len() / 2]
+    }
+
+    /// The error for the header `hash` which does not follow the tip, given
+    /// the height of its parent if known
+    pub(crate) fn misplaced(&self, hash: BlockHash, parent_height: Option<u32>) -> Error {
+        match parent_height {
+            Some(height) if !self.checkpoints.allows_fork(height, self.height()) => Error::CheckpointMismatch(hash),
+            Some(_) => Error::Fork(hash),
+            None => Error::UnknownParent(hash),
+        }
+    }
+
+    /// Check the header `hash`, which follows the tip, against the
+    /// checkpoints, the median time past and `now`, and its proof
+    pub(crate) fn check(&self, header: &BlockHeader, hash: BlockHash, now: u32) -> Result<(), Error> {
+        if !self.checkpoints.allows(self.height() + 1, &hash) {
+            return Err(Error::CheckpointMismatch(hash));
+        }
+        if header.time <= self.median_time_past() {
+            return Err(Error::TimeTooOld(hash));
+        }
+        if header.time > now.saturating_add(MAX_FUTURE_BLOCK_TIME) {
+            return Err(Error::TimeTooNew(hash));
+        }
+        let key = self.keys.next_key().expect("validator has a genesis block");
+        header.verify_proof(key).map_err(|_| Error::InvalidProof(hash))
+    }
+
+    /// Apply the header following the tip, either checked with `check` or
+    /// validated before being stored. Only the checkpoints are checked.
+    pub(crate) fn push(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        let hash = header.bitcoin_hash();
+        if !self.checkpoints.allows(self.height() + 1, &hash) {
+            return Err(Error::CheckpointMismatch(hash));
+        }
+        self.keys.push(header).map_err(|e| tracker_error(e, hash))?;
+        self.push_time(header.time);
+        Ok(())
+    }
+
+    /// Record the timestamp of the next header, keeping the last ones
+    fn push_time(&mut self, time: u32) {
+        if self.recent_times.len() == MEDIAN_TIME_SPAN {
+            self.recent_times.pop_front();
+        }
+        self.recent_times.push_back(time);
+    }
+}
+
+/// The current time as a UNIX timestamp
+pub(crate) fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+fn tracker_error(err: aggkey::Error, hash: BlockHash) -> Error {
+    match err {
+        aggkey::Error::MissingGenesisKey => Error::MissingGenesisKey,
+        aggkey::Error::Disconnected(_) => Error::UnknownParent(hash),
+        aggkey::Error::InvalidProof(_) => Error::InvalidProof(hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use blockchain::checkpoints::Checkpoints;
+    use blockdata::block::{BlockHeader, XField};
+    use blockdata::constants::genesis_block_builder;
+    use blockdata::script::Script;
+    use hashes::Hash;
+    use util::hash::BitcoinHash;
+    use util::key::PrivateKey;
+    use util::signature::Signature;
+    use super::{Error, HeaderChain};
+
+    const GENESIS_TIME: u32 = 1_600_000_000;
+
+    fn next_header(prev: &BlockHeader, time: u32, xfield: XField, signer: &PrivateKey) -> BlockHeader {
+        let mut header = BlockHeader {
+            prev_blockhash: prev.bitcoin_hash(),
+            time: time,
+            xfield: xfield,
+            proof: None,
+            ..prev.clone()
+        };
+        header.proof = Some(Signature::sign(signer, &header.signature_hash().into_inner()).unwrap());
+        header
+    }
+
+    #[test]
+    fn header_chain_test() {
+        let secp = ::secp256k1::Secp256k1::new();
+        let first = PrivateKey::from_str("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let second = PrivateKey::from_str("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        let genesis = genesis_block_builder(first.public_key(&secp), Script::new(), GENESIS_TIME)
+            .sign(&first)
+            .unwrap()
+            .header;
+
+        let mut headers = vec![genesis.clone()];
+        for i in 1..30 {
+            let xfield = if i == 20 { XField::AggregatePublicKey(second.public_key(&secp)) } else { XField::None };
+            let signer = if i > 20 { &second } else { &first };
+            let header = next_header(&headers[i - 1], GENESIS_TIME + i as u32 * 60, xfield, signer);
+            headers.push(header);
+        }
+
+        let now = GENESIS_TIME + 3600;
+        let mut chain = HeaderChain::new(genesis).unwrap();
+        assert_eq!(chain.accept_at(&headers[..15], now).unwrap(), 14);
+        // overlapping headers are skipped
+        assert_eq!(chain.accept_at(&headers[10..], now).unwrap(), 15);
+        assert_eq!(chain.height(), 29);
+        assert_eq!(chain.tip_hash(), headers[29].bitcoin_hash());
+        assert_eq!(chain.height_of(&headers[7].bitcoin_hash()), Some(7));
+        assert_eq!(chain.header_at(7), Some(&headers[7]));
+        assert_eq!(chain.aggregate_public_key_at(20), Some(&first.public_key(&secp)));
+        assert_eq!(chain.aggregate_public_key_at(21), Some(&second.public_key(&secp)));
+        assert_eq!(chain.next_aggregate_public_key(), &second.public_key(&secp));
+        assert_eq!(chain.median_time_past(), GENESIS_TIME + 24 * 60);
+
+        let locator = chain.locator();
+        let heights: Vec<u32> = locator.iter().map(|h| chain.height_of(h).unwrap()).collect();
+        assert_eq!(heights, vec![29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 18, 14, 6, 0]);
+
+        let tip = chain.tip().clone();
+        match chain.accept_at(&[next_header(&tip, GENESIS_TIME + 24 * 60, XField::None, &second)], now) {
+            Err(Error::TimeTooOld(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match chain.accept_at(&[next_header(&tip, now + 3 * 3600, XField::None, &second)], now) {
+            Err(Error::TimeTooNew(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match chain.accept_at(&[next_header(&tip, now, XField::None, &first)], now) {
+            Err(Error::InvalidProof(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match chain.accept_at(&[next_header(&headers[28], now, XField::None, &second)], now) {
+            Err(Error::Fork(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        let orphan = next_header(&next_header(&tip, now, XField::None, &second), now, XField::None, &second);
+        match chain.accept_at(&[orphan], now) {
+            Err(Error::UnknownParent(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert_eq!(chain.height(), 29);
+    }
+
+    #[test]
+    fn header_chain_checkpoints_test() {
+        let secp = ::secp256k1::Secp256k1::new();
+        let key = PrivateKey::from_str("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let genesis = genesis_block_builder(key.public_key(&secp), Script::new(), GENESIS_TIME)
+            .sign(&key)
+            .unwrap()
+            .header;
+        let mut headers = vec![genesis.clone()];
+        for i in 1..10 {
+            let header = next_header(&headers[i - 1], GENESIS_TIME + i as u32 * 60, XField::None, &key);
+            headers.push(header);
+        }
+        let now = GENESIS_TIME + 3600;
+        let checkpoints = Checkpoints::new().with(0, genesis.bitcoin_hash()).with(5, headers[5].bitcoin_hash());
+
+        match HeaderChain::with_checkpoints(genesis.clone(), Checkpoints::new().with(0, headers[1].bitcoin_hash())) {
+            Err(Error::CheckpointMismatch(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // a chain contradicting a checkpoint is refused at its height
+        let mut chain = HeaderChain::with_checkpoints(genesis, checkpoints).unwrap();
+        let other = next_header(&headers[4], GENESIS_TIME + 4 * 60 + 30, XField::None, &key);
+        chain.accept_at(&headers[1..5], now).unwrap();
+        match chain.accept_at(&[other], now) {
+            Err(Error::CheckpointMismatch(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // forks are refused from below the last checkpoint reached
+        assert_eq!(chain.accept_at(&headers[5..], now).unwrap(), 5);
+        match chain.accept_at(&[next_header(&headers[7], now, XField::None, &key)], now) {
+            Err(Error::Fork(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match chain.accept_at(&[next_header(&headers[3], now, XField::None, &key)], now) {
+            Err(Error::CheckpointMismatch(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Header stores
+//!
+//! SPV clients persist the headers of the chain they follow, so as not to
+//! download them again on every start. `HeaderStore` abstracts over where
+//! the headers are kept: `MemoryHeaderStore` keeps them in memory, and
+//! `FileHeaderStore` appends them to a file, read back when it is opened.
+//!
+//! Stores only check that each header follows the previous one; headers
+//! are expected to be validated before, e.g. by a `HeaderChain`.
+//!
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::{error, fmt};
+
+use blockdata::block::BlockHeader;
+use consensus::encode::{self, Decodable};
+use hash_types::BlockHash;
+use util::hash::BitcoinHash;
+
+/// Header store error
+#[derive(Debug)]
+pub enum Error {
+    /// The header does not follow the tip of the store
+    Disconnected(BlockHash),
+    /// I/O error of the file backing the store
+    Io(io::Error),
+    /// The file backing the store holds an invalid header
+    Encode(encode::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Disconnected(ref hash) => write!(f, "header {} does not follow the tip of the store", hash),
+            Error::Io(ref e) => write!(f, "header store I/O error: {}", e),
+            Error::Encode(ref e) => write!(f, "invalid stored header: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Disconnected(_) => "header does not follow the tip of the store",
+            Error::Io(_) => "header store I/O error",
+            Error::Encode(_) => "invalid stored header",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Disconnected(_) => None,
+            Error::Io(ref e) => Some(e),
+            Error::Encode(ref e) => Some(e),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(e: encode::Error) -> Error {
+        Error::Encode(e)
+    }
+}
+
+/// Persistence of the headers of a chain, from its genesis block
+pub trait HeaderStore {
+    /// The error of the underlying storage
+    type Error;
+
+    /// Append a header, which must follow the tip of the store, if any
+    fn append(&mut self, header: &BlockHeader) -> Result<(), Self::Error>;
+
+    /// Remove the headers above `height`, e.g. to switch to a fork from
+    /// the header at `height`. Does nothing if `height` is not below the tip.
+    fn truncate(&mut self, height: u32) -> Result<(), Self::Error>;
+
+    /// The height of the last stored header, `None` if the store is empty
+    fn tip_height(&self) -> Option<u32>;
+
+    /// The header at `height`
+    fn header_at(&self, height: u32) -> Result<Option<BlockHeader>, Self::Error>;
+
+    /// The height of the header with the given hash
+    fn height_of(&self, hash: &BlockHash) -> Result<Option<u32>, Self::Error>;
+
+    /// The hash of the header at `height`
+    fn hash_at(&self, height: u32) -> Result<Option<BlockHash>, Self::Error> {
+        Ok(self.header_at(height)?.map(|header| header.bitcoin_hash()))
+    }
+
+    /// The header with the given hash
+    fn header_of(&self, hash: &BlockHash) -> Result<Option<BlockHeader>, Self::Error> {
+        match self.height_of(hash)? {
+            Some(height) => self.header_at(height),
+            None => Ok(None),
+        }
+    }
+
+    /// The last stored header
+    fn tip(&self) -> Result<Option<BlockHeader>, Self::Error> {
+        match self.tip_height() {
+            Some(height) => self.header_at(height),
+            None => Ok(None),
+        }
+    }
+
+    /// Locator hashes for `getheaders` and `getblocks` messages, from the
+    /// tip to the genesis block with exponentially growing gaps
+    fn locator(&self) -> Result<Vec<BlockHash>, Self::Error> {
+        let tip_height = match self.tip_height() {
+            Some(height) => height,
+            None => return Ok(vec![]),
+        };
+        let mut locator = vec![];
+        for height in locator_heights(tip_height) {
+            locator.extend(self.hash_at(height)?);
+        }
+        Ok(locator)
+    }
+}
+
+/// The heights of the headers of a locator, from `tip_height` to 0: the
+/// last 10 headers, then with gaps doubling at each step
+pub(crate) fn locator_heights(tip_height: u32) -> Vec<u32> {
+    let mut heights = vec![];
+    let mut height = tip_height;
+    let mut step = 1;
+    loop {
+        heights.push(height);
+        if height == 0 {
+            break;
+        }
+        if heights.len() >= 10 {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+    heights
+}
+
+/// The hashes of a chain of headers, indexed by height and by hash
+#[derive(Clone, Debug, Default)]
+struct HashIndex {
+    hashes: Vec<BlockHash>,
+    heights: HashMap<BlockHash, u32>,
+}
+
+impl HashIndex {
+    /// Check that `header` follows the indexed headers, returning its hash
+    fn check_next(&self, header: &BlockHeader) -> Result<BlockHash, Error> {
+        let hash = header.bitcoin_hash();
+        match self.hashes.last() {
+            Some(tip) if *tip != header.prev_blockhash => Err(Error::Disconnected(hash)),
+            _ => Ok(hash),
+        }
+    }
+
+    fn push(&mut self, hash: BlockHash) {
+        self.heights.insert(hash, self.hashes.len() as u32);
+        self.hashes.push(hash);
+    }
+
+    fn tip_height(&self) -> Option<u32> {
+        (self.hashes.len() as u32).checked_sub(1)
+    }
+
+    /// Remove the hashes above `height`
+    fn truncate(&mut self, height: u32) {
+        let len = height as usize + 1;
+        if len < self.hashes.len() {
+            for hash in self.hashes.drain(len..) {
+                self.heights.remove(&hash);
+            }
+        }
+    }
+}
+
+/// A header store keeping the headers in memory
+#[derive(Clone, Debug, Default)]
+pub struct MemoryHeaderStore {
+    headers: Vec<BlockHeader>,
+    index: HashIndex,
+}
+
+impl MemoryHeaderStore {
+    /// Create an empty store
+    pub fn new() -> MemoryHeaderStore {
+        MemoryHeaderStore::default()
+    }
+}
+
+impl HeaderStore for MemoryHeaderStore {
+    type Error = Error;
+
+    fn append(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        let hash = self.index.check_next(header)?;
+        self.index.push(hash);
+        self.headers.push(header.clone());
+        Ok(())
+    }
+
+    fn truncate(&mut self, height: u32) -> Result<(), Error> {
+        self.index.truncate(height);
+        self.headers.truncate(height as usize + 1);
+        Ok(())
+    }
+
+    fn tip_height(&self) -> Option<u32> {
+        self.index.tip_height()
+    }
+
+    fn header_at(&self, height: u32) -> Result<Option<BlockHeader>, Error> {
+        Ok(self.headers.get(height as usize).cloned())
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Result<Option<u32>, Error> {
+        Ok(self.index.heights.get(hash).cloned())
+    }
+
+    fn hash_at(&self, height: u32) -> Result<Option<BlockHash>, Error> {
+        Ok(self.index.hashes.get(height as usize).cloned())
+    }
+}
+
+/// A header store appending the headers to a file, one after the other in
+/// their consensus encoding. Only the offsets and hashes of the headers
+/// are kept in memory.
+#[derive(Debug)]
+pub struct FileHeaderStore {
+    file: File,
+    /// The offset of each header in the file, followed by the file length
+    offsets: Vec<u64>,
+    index: HashIndex,
+}
+
+impl FileHeaderStore {
+    /// Open the store backed by the file at `path`, created if missing.
+    /// A header left incomplete at the end of the file, as by a crash or a
+    /// failure while appending it, is discarded. As headers are not framed,
+    /// so is everything from the first one which fails to decode.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileHeaderStore, Error> {
+        let file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+        let len = file.metadata()?.len();
+        let mut store = FileHeaderStore { file: file, offsets: vec![0], index: HashIndex::default() };
+
+        let mut reader = CountingReader { inner: BufReader::new(&store.file), count: 0 };
+        while reader.count < len {
+            let header = match BlockHeader::consensus_decode(&mut reader) {
+                Ok(header) => header,
+                Err(encode::Error::Io(e)) => {
+                    if e.kind() != io::ErrorKind::UnexpectedEof {
+                        return Err(e.into());
+                    }
+                    break;
+                }
+                // a torn write
+                Err(_) => break,
+            };
+            let hash = store.index.check_next(&header)?;
+            store.index.push(hash);
+            store.offsets.push(reader.count);
+        }
+        let end = *store.offsets.last().expect("offsets start at 0");
+        if end < len {
+            store.file.set_len(end)?;
+        }
+        Ok(store)
+    }
+}
+
+impl HeaderStore for FileHeaderStore {
+    type Error = Error;
+
+    fn append(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        let hash = self.index.check_next(header)?;
+        let data = encode::serialize(header);
+        let start = *self.offsets.last().expect("offsets start at 0");
+        // discard the bytes of a previous failed write, if left
+        self.file.set_len(start)?;
+        self.file.seek(SeekFrom::Start(start))?;
+        if let Err(e) = self.file.write_all(&data) {
+            // the error of the write is the one worth reporting; the bytes it
+            // left are discarded again by the next append or `open`
+            let _ = self.file.set_len(start);
+            return Err(e.into());
+        }
+        self.index.push(hash);
+        self.offsets.push(start + data.len() as u64);
+        Ok(())
+    }
+
+    fn truncate(&mut self, height: u32) -> Result<(), Error> {
+        let len = height as usize + 2;
+        if len < self.offsets.len() {
+            self.file.set_len(self.offsets[len - 1])?;
+            self.offsets.truncate(len);
+            self.index.truncate(height);
+        }
+        Ok(())
+    }
+
+    fn tip_height(&self) -> Option<u32> {
+        self.index.tip_height()
+    }
+
+    fn header_at(&self, height: u32) -> Result<Option<BlockHeader>, Error> {
+        if height as usize + 1 >= self.offsets.len() {
+            return Ok(None);
+        }
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offsets[height as usize]))?;
+        Ok(Some(BlockHeader::consensus_decode(file)?))
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Result<Option<u32>, Error> {
+        Ok(self.index.heights.get(hash).cloned())
+    }
+
+    fn hash_at(&self, height: u32) -> Result<Option<BlockHash>, Error> {
+        Ok(self.index.hashes.get(height as usize).cloned())
+    }
+}
+
+/// A reader counting the bytes read
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::process;
+
+    use blockdata::block::{BlockHeader, XField};
+    use consensus::encode;
+    use util::hash::BitcoinHash;
+    use super::{locator_heights, Error, FileHeaderStore, HeaderStore, MemoryHeaderStore};
+
+    fn headers(count: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        for time in 0..count {
+            headers.push(BlockHeader {
+                version: 1,
+                prev_blockhash: headers.last().map(|h| h.bitcoin_hash()).unwrap_or_default(),
+                merkle_root: Default::default(),
+                im_merkle_root: Default::default(),
+                time: time,
+                xfield: XField::None,
+                proof: None,
+            });
+        }
+        headers
+    }
+
+    fn check_store<S: HeaderStore<Error = Error>>(store: &mut S, headers: &[BlockHeader]) {
+        assert_eq!(store.tip_height(), None);
+        assert!(store.tip().unwrap().is_none());
+        assert!(store.locator().unwrap().is_empty());
+
+        for header in headers {
+            store.append(header).unwrap();
+        }
+        match store.append(&headers[5]) {
+            Err(Error::Disconnected(hash)) => assert_eq!(hash, headers[5].bitcoin_hash()),
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let tip_height = headers.len() as u32 - 1;
+        assert_eq!(store.tip_height(), Some(tip_height));
+        assert_eq!(store.tip().unwrap().as_ref(), headers.last());
+        for (height, header) in headers.iter().enumerate() {
+            let hash = header.bitcoin_hash();
+            assert_eq!(store.header_at(height as u32).unwrap().as_ref(), Some(header));
+            assert_eq!(store.hash_at(height as u32).unwrap(), Some(hash));
+            assert_eq!(store.height_of(&hash).unwrap(), Some(height as u32));
+            assert_eq!(store.header_of(&hash).unwrap().as_ref(), Some(header));
+        }
+        assert!(store.header_at(tip_height + 1).unwrap().is_none());
+        assert!(store.height_of(&Default::default()).unwrap().is_none());
+
+        let locator: Vec<_> = locator_heights(tip_height).into_iter().map(|h| headers[h as usize].bitcoin_hash()).collect();
+        assert_eq!(store.locator().unwrap(), locator);
+
+        // headers above a height are removed, and others appended instead
+        store.truncate(tip_height + 1).unwrap();
+        assert_eq!(store.tip_height(), Some(tip_height));
+        store.truncate(10).unwrap();
+        assert_eq!(store.tip().unwrap().as_ref(), Some(&headers[10]));
+        assert!(store.header_at(11).unwrap().is_none());
+        assert!(store.height_of(&headers[11].bitcoin_hash()).unwrap().is_none());
+        for header in &headers[11..] {
+            store.append(header).unwrap();
+        }
+        assert_eq!(store.tip_height(), Some(tip_height));
+    }
+
+    #[test]
+    fn test_locator_heights() {
+        assert_eq!(locator_heights(0), vec![0]);
+        assert_eq!(locator_heights(29), vec![29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 18, 14, 6, 0]);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        check_store(&mut MemoryHeaderStore::new(), &headers(30));
+    }
+
+    #[test]
+    fn test_file_store() {
+        let path = ::std::env::temp_dir().join(format!("tapyrus-header-store-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let headers = headers(30);
+        check_store(&mut FileHeaderStore::open(&path).unwrap(), &headers);
+
+        // the headers are read back, and more can be appended
+        let mut store = FileHeaderStore::open(&path).unwrap();
+        assert_eq!(store.tip_height(), Some(29));
+        assert_eq!(store.header_at(17).unwrap().as_ref(), Some(&headers[17]));
+        let next = BlockHeader { prev_blockhash: headers[29].bitcoin_hash(), ..headers[0].clone() };
+        store.append(&next).unwrap();
+        drop(store);
+
+        // an incomplete header at the end of the file is discarded
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[1, 0, 0]).unwrap();
+        let mut store = FileHeaderStore::open(&path).unwrap();
+        assert_eq!(store.tip().unwrap(), Some(next.clone()));
+        let last = BlockHeader { prev_blockhash: next.bitcoin_hash(), ..next.clone() };
+        store.append(&last).unwrap();
+        assert_eq!(FileHeaderStore::open(&path).unwrap().tip_height(), Some(31));
+
+        // so is a torn header which fails to decode
+        let mut torn = encode::serialize(&last);
+        // an aggregate public key xfield, without the key
+        torn[104] = 1;
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&torn).unwrap();
+        let mut store = FileHeaderStore::open(&path).unwrap();
+        assert_eq!(store.tip().unwrap(), Some(last.clone()));
+
+        // bytes left after the tip are overwritten by the next header
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&torn).unwrap();
+        store.append(&BlockHeader { prev_blockhash: last.bitcoin_hash(), ..last.clone() }).unwrap();
+        assert_eq!(FileHeaderStore::open(&path).unwrap().tip_height(), Some(32));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
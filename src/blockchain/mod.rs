@@ -0,0 +1,20 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Blockchain
+//!
+//! This module builds on the block and network message types to follow a
+//! Tapyrus chain, as needed by SPV clients, to persist its headers and to
+//! download them from peers.
+//!
+
+pub mod checkpoints;
+pub mod header_chain;
+pub mod header_store;
+pub mod spv;
+
+pub use self::checkpoints::Checkpoints;
+pub use self::header_chain::HeaderChain;
+pub use self::header_store::{FileHeaderStore, HeaderStore, MemoryHeaderStore};
+pub use self::spv::SyncManager;
@@ -0,0 +1,623 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! SPV synchronization
+//!
+//! `SyncManager` downloads the headers of the chain from peers into a
+//! `HeaderStore`. It asks one peer at a time for headers with `getheaders`
+//! messages, validates the headers it receives as `HeaderChain` does,
+//! their proofs and aggregate public key changes included, and asks again
+//! until the peer has no more headers. Headers contradicting the
+//! checkpoints of the chain are refused. Headers forking from the stored
+//! chain at most `MAX_REORG_DEPTH` headers below its tip are validated from
+//! the fork, and replace the stored headers above it if they make a longer
+//! chain. Forks which do not are ignored, and count against the peer
+//! sending them; deeper forks are refused. A peer which takes too long to
+//! answer is reported as stalling, and another peer is asked instead.
+//!
+//! The manager does no I/O of its own: messages are sent through a
+//! `MessageSink`. Methods taking an `Instant` exist so that the manager can
+//! be driven by an external clock; the others use `Instant::now()`.
+//!
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+use std::{cmp, error, fmt};
+
+use blockchain::checkpoints::Checkpoints;
+use blockchain::header_chain::{self, unix_time, Validator};
+use blockchain::header_store::HeaderStore;
+use blockdata::block::BlockHeader;
+use hash_types::BlockHash;
+use network::elapsed;
+use network::message::NetworkMessage;
+use network::message_blockdata::GetHeadersMessage;
+use util::hash::BitcoinHash;
+
+/// The maximum number of headers of a `headers` message. A peer sending
+/// fewer has no more headers.
+pub const MAX_HEADERS: usize = 2000;
+
+/// The maximum number of stored headers a fork may replace
+pub const MAX_REORG_DEPTH: u32 = 1000;
+
+/// The misbehavior score of a fork which does not make a longer chain
+const FORK_MISBEHAVIOR: u32 = 20;
+/// The misbehavior score at which a peer is removed
+const MAX_MISBEHAVIOR: u32 = 100;
+
+/// Sends messages to peers, e.g. over their connections
+pub trait MessageSink {
+    /// Identifies a peer
+    type Peer: Clone + Eq + Hash;
+    /// The error of a failure to send
+    type Error;
+
+    /// Send `message` to `peer`
+    fn send(&mut self, peer: &Self::Peer, message: NetworkMessage) -> Result<(), Self::Error>;
+}
+
+/// SPV synchronization error
+#[derive(Debug)]
+pub enum Error<S, K> {
+    /// The header store failed
+    Store(S),
+    /// The message sink failed
+    Sink(K),
+    /// The header store starts with another genesis block
+    GenesisMismatch,
+    /// A peer sent an invalid header, and was removed from the peers
+    InvalidHeader(header_chain::Error),
+    /// A peer sent headers forking more than `MAX_REORG_DEPTH` headers below
+    /// the tip, and was removed from the peers
+    ForkTooDeep(BlockHash),
+    /// A peer sent too many forks not making a longer chain, and was removed
+    /// from the peers
+    Misbehaving,
+}
+
+impl<S: fmt::Display, K: fmt::Display> fmt::Display for Error<S, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Store(ref e) => write!(f, "header store error: {}", e),
+            Error::Sink(ref e) => write!(f, "message sink error: {}", e),
+            Error::GenesisMismatch => f.write_str("header store starts with another genesis block"),
+            Error::InvalidHeader(ref e) => write!(f, "invalid header: {}", e),
+            Error::ForkTooDeep(ref hash) => write!(f, "fork too deep: {}", hash),
+            Error::Misbehaving => f.write_str("too many forks not making a longer chain"),
+        }
+    }
+}
+
+impl<S: error::Error, K: error::Error> error::Error for Error<S, K> {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Store(_) => "header store error",
+            Error::Sink(_) => "message sink error",
+            Error::GenesisMismatch => "header store starts with another genesis block",
+            Error::InvalidHeader(_) => "invalid header",
+            Error::ForkTooDeep(_) => "fork too deep",
+            Error::Misbehaving => "too many forks not making a longer chain",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Store(ref e) => Some(e),
+            Error::Sink(ref e) => Some(e),
+            Error::GenesisMismatch | Error::ForkTooDeep(_) | Error::Misbehaving => None,
+            Error::InvalidHeader(ref e) => Some(e),
+        }
+    }
+}
+
+/// Downloads the headers of a chain from peers into a header store
+pub struct SyncManager<S: HeaderStore, K: MessageSink> {
+    store: S,
+    sink: K,
+    /// Validates the headers following the stored ones
+    validator: Validator,
+    /// The validators of the headers following the last stored ones, the
+    /// tip excluded, up to `MAX_REORG_DEPTH` of them, from which forks are
+    /// validated
+    recent: VecDeque<Validator>,
+    /// The peers, with the height of their best chain
+    peers: HashMap<K::Peer, u32>,
+    /// The misbehavior scores of the peers
+    scores: HashMap<K::Peer, u32>,
+    /// The peer asked for headers, and when
+    pending: Option<(K::Peer, Instant)>,
+    timeout: Duration,
+}
+
+impl<S: HeaderStore, K: MessageSink> SyncManager<S, K> {
+    /// Create a manager syncing the chain of `genesis` into `store`, which
+    /// may already hold the beginning of that chain. Peers taking longer
+    /// than `timeout` to answer are reported as stalling.
+    pub fn new(store: S, sink: K, genesis: &BlockHeader, timeout: Duration) -> Result<Self, Error<S::Error, K::Error>> {
+        SyncManager::with_checkpoints(store, sink, genesis, Checkpoints::new(), timeout)
+    }
+
+    /// Same as `new`, refusing headers which contradict `checkpoints`,
+    /// including those already stored
+    pub fn with_checkpoints(
+        mut store: S,
+        sink: K,
+        genesis: &BlockHeader,
+        checkpoints: Checkpoints,
+        timeout: Duration,
+    ) -> Result<Self, Error<S::Error, K::Error>> {
+        let validator = Validator::new(genesis, checkpoints).map_err(Error::InvalidHeader)?;
+        match store.header_at(0).map_err(Error::Store)? {
+            Some(ref stored) if stored == genesis => {}
+            Some(_) => return Err(Error::GenesisMismatch),
+            None => store.append(genesis).map_err(Error::Store)?,
+        }
+
+        let mut manager = SyncManager {
+            store: store,
+            sink: sink,
+            validator: validator,
+            recent: VecDeque::new(),
+            peers: HashMap::new(),
+            scores: HashMap::new(),
+            pending: None,
+            timeout: timeout,
+        };
+        manager.replay_store()?;
+        Ok(manager)
+    }
+
+    /// The header store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Give up syncing, returning the header store
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// The height of the last stored header
+    pub fn tip_height(&self) -> u32 {
+        self.store.tip_height().expect("store has a genesis block")
+    }
+
+    /// Whether no headers are being downloaded, and no peer is known to
+    /// have more headers
+    pub fn is_synced(&self) -> bool {
+        let tip_height = self.tip_height();
+        self.pending.is_none() && self.peers.values().all(|&height| height <= tip_height)
+    }
+
+    /// Add a peer, whose best chain is `height` high, as told by its
+    /// `version` message
+    pub fn add_peer(&mut self, peer: K::Peer, height: u32) -> Result<(), Error<S::Error, K::Error>> {
+        self.add_peer_at(peer, height, Instant::now())
+    }
+
+    /// Same as `add_peer`, at the given time
+    pub fn add_peer_at(&mut self, peer: K::Peer, height: u32, now: Instant) -> Result<(), Error<S::Error, K::Error>> {
+        self.peers.insert(peer, height);
+        self.request_if_idle(now)
+    }
+
+    /// Remove a disconnected peer. Headers it was asked for are asked
+    /// from another peer.
+    pub fn remove_peer(&mut self, peer: &K::Peer) -> Result<(), Error<S::Error, K::Error>> {
+        self.remove_peer_at(peer, Instant::now())
+    }
+
+    /// Same as `remove_peer`, at the given time
+    pub fn remove_peer_at(&mut self, peer: &K::Peer, now: Instant) -> Result<(), Error<S::Error, K::Error>> {
+        self.peers.remove(peer);
+        self.scores.remove(peer);
+        if self.pending.as_ref().map_or(false, |&(ref pending, _)| pending == peer) {
+            self.pending = None;
+        }
+        self.request_if_idle(now)
+    }
+
+    /// Handle the headers of a `headers` message from `peer`, returning the
+    /// number of headers stored. A peer sending an invalid header or a fork
+    /// too deep is removed, and should be disconnected; so is a peer which
+    /// sent too many forks not making a longer chain.
+    pub fn handle_headers(&mut self, peer: &K::Peer, headers: &[BlockHeader]) -> Result<usize, Error<S::Error, K::Error>> {
+        self.handle_headers_at(peer, headers, Instant::now(), unix_time())
+    }
+
+    /// Same as `handle_headers`, at the given time, with `time` the same
+    /// time as a UNIX timestamp against which header timestamps are checked
+    pub fn handle_headers_at(
+        &mut self,
+        peer: &K::Peer,
+        headers: &[BlockHeader],
+        now: Instant,
+        time: u32,
+    ) -> Result<usize, Error<S::Error, K::Error>> {
+        let requested = self.pending.as_ref().map_or(false, |&(ref pending, _)| pending == peer);
+        if requested {
+            self.pending = None;
+        }
+
+        let mut stored = 0;
+        let mut result = Ok(());
+        for (i, header) in headers.iter().enumerate() {
+            match self.accept(header, time) {
+                Ok(true) => stored += 1,
+                Ok(false) => {}
+                Err(Error::InvalidHeader(header_chain::Error::Fork(_))) => {
+                    result = self.accept_fork(peer, &headers[i..], time).map(|count| stored += count);
+                    break;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        match result {
+            Ok(()) => {}
+            // headers announced by a peer ahead of us
+            Err(Error::InvalidHeader(header_chain::Error::UnknownParent(_))) if !requested => {
+                self.request(peer.clone(), now)?;
+                return Ok(stored);
+            }
+            Err(e @ Error::InvalidHeader(_)) | Err(e @ Error::ForkTooDeep(_)) | Err(e @ Error::Misbehaving) => {
+                self.peers.remove(peer);
+                self.scores.remove(peer);
+                self.request_if_idle(now)?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+
+        let tip_height = self.tip_height();
+        let more = requested && headers.len() >= MAX_HEADERS;
+        if let Some(height) = self.peers.get_mut(peer) {
+            if requested && !more {
+                // the peer has no more headers
+                *height = tip_height;
+            } else if !headers.is_empty() {
+                *height = cmp::max(*height, tip_height);
+            }
+        }
+        if more {
+            self.request(peer.clone(), now)?;
+        } else {
+            self.request_if_idle(now)?;
+        }
+        Ok(stored)
+    }
+
+    /// Check whether the peer asked for headers is stalling. If so, it is
+    /// removed and returned, to be disconnected, and the headers are asked
+    /// from another peer.
+    pub fn check_stalled(&mut self) -> Result<Option<K::Peer>, Error<S::Error, K::Error>> {
+        self.check_stalled_at(Instant::now())
+    }
+
+    /// Same as `check_stalled`, at the given time
+    pub fn check_stalled_at(&mut self, now: Instant) -> Result<Option<K::Peer>, Error<S::Error, K::Error>> {
+        let stalled = match self.pending {
+            Some((ref peer, sent)) if elapsed(sent, now) > self.timeout => peer.clone(),
+            _ => return Ok(None),
+        };
+        self.pending = None;
+        self.peers.remove(&stalled);
+        self.request_if_idle(now)?;
+        Ok(Some(stalled))
+    }
+
+    /// Validate and store a header, at the UNIX time `now`, returning false
+    /// if it is already stored
+    fn accept(&mut self, header: &BlockHeader, now: u32) -> Result<bool, Error<S::Error, K::Error>> {
+        let hash = header.bitcoin_hash();
+        if self.store.height_of(&hash).map_err(Error::Store)?.is_some() {
+            return Ok(false);
+        }
+        if header.prev_blockhash != self.validator.tip_hash() {
+            let parent_height = self.store.height_of(&header.prev_blockhash).map_err(Error::Store)?;
+            return Err(Error::InvalidHeader(self.validator.misplaced(hash, parent_height)));
+        }
+        self.validator.check(header, hash, now).map_err(Error::InvalidHeader)?;
+        self.store.append(header).map_err(Error::Store)?;
+        push_recent(&mut self.recent, self.validator.clone());
+        self.validator.push(header).expect("header follows the tip");
+        Ok(true)
+    }
+
+    /// Validate consecutive headers from `peer` forking from the stored
+    /// chain, at the UNIX time `now`, and switch to them if they make a
+    /// longer chain. Returns the number of headers stored.
+    fn accept_fork(&mut self, peer: &K::Peer, headers: &[BlockHeader], now: u32) -> Result<usize, Error<S::Error, K::Error>> {
+        let fork_height = self.store.height_of(&headers[0].prev_blockhash)
+            .map_err(Error::Store)?
+            .expect("fork from a stored header");
+        let tip_height = self.tip_height();
+        let depth = tip_height - fork_height;
+        if depth > MAX_REORG_DEPTH {
+            return Err(Error::ForkTooDeep(headers[0].bitcoin_hash()));
+        }
+        // on a tie, the chain received first is kept
+        if fork_height as usize + headers.len() <= tip_height as usize {
+            let score = self.scores.entry(peer.clone()).or_insert(0);
+            *score += FORK_MISBEHAVIOR;
+            return if *score >= MAX_MISBEHAVIOR { Err(Error::Misbehaving) } else { Ok(0) };
+        }
+
+        let mut validator = self.recent[self.recent.len() - depth as usize].clone();
+        let mut fork_recent = Vec::with_capacity(headers.len());
+        for header in headers {
+            let hash = header.bitcoin_hash();
+            if header.prev_blockhash != validator.tip_hash() {
+                return Err(Error::InvalidHeader(header_chain::Error::UnknownParent(hash)));
+            }
+            validator.check(header, hash, now).map_err(Error::InvalidHeader)?;
+            fork_recent.push(validator.clone());
+            validator.push(header).expect("header follows the tip");
+        }
+
+        self.store.truncate(fork_height).map_err(Error::Store)?;
+        for header in headers {
+            self.store.append(header).map_err(Error::Store)?;
+        }
+        let kept = self.recent.len() - depth as usize;
+        self.recent.truncate(kept);
+        for fork_validator in fork_recent {
+            push_recent(&mut self.recent, fork_validator);
+        }
+        self.validator = validator;
+        Ok(headers.len())
+    }
+
+    /// Rebuild the validators of the headers following the stored ones.
+    /// The stored headers were validated when they were received, so only
+    /// the checkpoints are checked again.
+    fn replay_store(&mut self) -> Result<(), Error<S::Error, K::Error>> {
+        for height in 1..=self.tip_height() {
+            let header = self.stored_header(height)?;
+            push_recent(&mut self.recent, self.validator.clone());
+            self.validator.push(&header).map_err(Error::InvalidHeader)?;
+        }
+        Ok(())
+    }
+
+    /// The stored header at `height`, up to the tip
+    fn stored_header(&self, height: u32) -> Result<BlockHeader, Error<S::Error, K::Error>> {
+        Ok(self.store.header_at(height).map_err(Error::Store)?.expect("height up to the tip"))
+    }
+
+    /// Ask the peer with the best chain for headers, if there is none asked
+    /// yet and a peer has more headers
+    fn request_if_idle(&mut self, now: Instant) -> Result<(), Error<S::Error, K::Error>> {
+        if self.pending.is_some() {
+            return Ok(());
+        }
+        let tip_height = self.tip_height();
+        let best = self.peers.iter()
+            .filter(|&(_, &height)| height > tip_height)
+            .max_by_key(|&(_, &height)| height)
+            .map(|(peer, _)| peer.clone());
+        match best {
+            Some(peer) => self.request(peer, now),
+            None => Ok(()),
+        }
+    }
+
+    /// Ask `peer` for the headers following the stored ones
+    fn request(&mut self, peer: K::Peer, now: Instant) -> Result<(), Error<S::Error, K::Error>> {
+        let locator = self.store.locator().map_err(Error::Store)?;
+        let message = NetworkMessage::GetHeaders(GetHeadersMessage::new(locator, BlockHash::default()));
+        self.sink.send(&peer, message).map_err(Error::Sink)?;
+        self.pending = Some((peer, now));
+        Ok(())
+    }
+}
+
+/// Record the validator of the header following the tip, keeping those of
+/// the last `MAX_REORG_DEPTH` headers
+fn push_recent(recent: &mut VecDeque<Validator>, validator: Validator) {
+    if recent.len() == MAX_REORG_DEPTH as usize {
+        recent.pop_front();
+    }
+    recent.push_back(validator);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use blockchain::checkpoints::Checkpoints;
+    use blockchain::header_chain;
+    use blockchain::header_store::{HeaderStore, MemoryHeaderStore};
+    use blockdata::block::{BlockHeader, XField};
+    use blockdata::constants::genesis_block_builder;
+    use blockdata::script::Script;
+    use hash_types::BlockHash;
+    use hashes::Hash;
+    use network::message::NetworkMessage;
+    use util::hash::BitcoinHash;
+    use util::key::PrivateKey;
+    use util::signature::Signature;
+    use super::{Error, MessageSink, SyncManager};
+
+    const GENESIS_TIME: u32 = 1_600_000_000;
+    /// The UNIX time the headers are received at
+    const NOW: u32 = GENESIS_TIME + 1_000_000;
+
+    /// Records the messages sent, identifying peers by numbers
+    #[derive(Clone, Default)]
+    struct Sink(Rc<RefCell<Vec<(u32, NetworkMessage)>>>);
+
+    impl Sink {
+        /// The peers asked for headers, with the first hash of the locators
+        fn requests(&self) -> Vec<(u32, BlockHash)> {
+            self.0.borrow().iter().map(|&(peer, ref message)| match *message {
+                NetworkMessage::GetHeaders(ref getheaders) => (peer, getheaders.locator_hashes[0]),
+                ref message => panic!("unexpected message {:?}", message),
+            }).collect()
+        }
+    }
+
+    impl MessageSink for Sink {
+        type Peer = u32;
+        type Error = ();
+
+        fn send(&mut self, peer: &u32, message: NetworkMessage) -> Result<(), ()> {
+            self.0.borrow_mut().push((*peer, message));
+            Ok(())
+        }
+    }
+
+    fn next_header(prev: &BlockHeader, xfield: XField, signer: &PrivateKey) -> BlockHeader {
+        let mut header = BlockHeader {
+            prev_blockhash: prev.bitcoin_hash(),
+            time: prev.time + 60,
+            xfield: xfield,
+            proof: None,
+            ..prev.clone()
+        };
+        header.proof = Some(Signature::sign(signer, &header.signature_hash().into_inner()).unwrap());
+        header
+    }
+
+    #[test]
+    fn test_sync_manager() {
+        let secp = ::secp256k1::Secp256k1::new();
+        let first = PrivateKey::from_str("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let second = PrivateKey::from_str("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        let genesis = genesis_block_builder(first.public_key(&secp), Script::new(), GENESIS_TIME)
+            .sign(&first)
+            .unwrap()
+            .header;
+
+        let mut headers = vec![genesis.clone()];
+        for i in 1..2008 {
+            let xfield = if i == 20 { XField::AggregatePublicKey(second.public_key(&secp)) } else { XField::None };
+            let signer = if i > 20 { &second } else { &first };
+            let header = next_header(&headers[i - 1], xfield, signer);
+            headers.push(header);
+        }
+
+        let sink = Sink::default();
+        let timeout = Duration::from_secs(60);
+        let mut manager = SyncManager::new(MemoryHeaderStore::new(), sink.clone(), &genesis, timeout).unwrap();
+        assert_eq!(manager.tip_height(), 0);
+        assert!(manager.is_synced());
+
+        // headers are asked from one peer at a time
+        let start = Instant::now();
+        manager.add_peer_at(1, 2004, start).unwrap();
+        manager.add_peer_at(2, 2004, start).unwrap();
+        assert_eq!(sink.requests(), vec![(1, genesis.bitcoin_hash())]);
+        assert!(!manager.is_synced());
+
+        // a full batch is followed by another request to the same peer
+        assert_eq!(manager.handle_headers_at(&1, &headers[..2001], start, NOW).unwrap(), 2000);
+        assert_eq!(manager.tip_height(), 2000);
+        assert_eq!(sink.requests()[1], (1, headers[2000].bitcoin_hash()));
+
+        // a stalling peer is replaced
+        assert_eq!(manager.check_stalled_at(start + timeout / 2).unwrap(), None);
+        let later = start + timeout * 2;
+        assert_eq!(manager.check_stalled_at(later).unwrap(), Some(1));
+        assert_eq!(sink.requests()[2], (2, headers[2000].bitcoin_hash()));
+        assert_eq!(manager.handle_headers_at(&2, &headers[2001..2005], later, NOW).unwrap(), 4);
+        assert_eq!(manager.tip_height(), 2004);
+        assert!(manager.is_synced());
+        assert_eq!(sink.requests().len(), 3);
+
+        // announced headers extend the chain, or trigger a request if they
+        // do not connect
+        assert_eq!(manager.handle_headers_at(&2, &headers[2005..2006], later, NOW).unwrap(), 1);
+        assert_eq!(sink.requests().len(), 3);
+        assert_eq!(manager.handle_headers_at(&2, &headers[2007..], later, NOW).unwrap(), 0);
+        assert_eq!(sink.requests()[3], (2, headers[2005].bitcoin_hash()));
+
+        // a peer sending an invalid header is removed
+        let invalid = next_header(&headers[2005], XField::None, &first);
+        match manager.handle_headers_at(&2, &[invalid], later, NOW) {
+            Err(Error::InvalidHeader(header_chain::Error::InvalidProof(_))) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(manager.tip_height(), 2005);
+        assert!(manager.is_synced());
+
+        // the tracked keys are rebuilt from the store
+        let store = manager.into_store();
+        assert_eq!(store.tip().unwrap(), Some(headers[2005].clone()));
+        let mut manager = SyncManager::new(store, sink.clone(), &genesis, timeout).unwrap();
+        assert_eq!(manager.tip_height(), 2005);
+        assert_eq!(manager.handle_headers_at(&3, &headers[2006..], later, NOW).unwrap(), 2);
+        assert_eq!(manager.tip_height(), 2007);
+
+        // a shorter fork is ignored, without removing the peer, and a longer
+        // one replaces the stored headers above the fork
+        manager.add_peer_at(4, 2007, later).unwrap();
+        let mut fork = vec![next_header(&headers[2005], XField::AggregatePublicKey(second.public_key(&secp)), &second)];
+        for i in 1..3 {
+            let header = next_header(&fork[i - 1], XField::None, &second);
+            fork.push(header);
+        }
+        assert_eq!(manager.handle_headers_at(&4, &fork[..2], later, NOW).unwrap(), 0);
+        assert_eq!(manager.store().tip().unwrap(), Some(headers[2007].clone()));
+        assert_eq!(manager.handle_headers_at(&4, &fork, later, NOW).unwrap(), 3);
+        assert_eq!(manager.tip_height(), 2008);
+        assert_eq!(manager.store().hash_at(2006).unwrap(), Some(fork[0].bitcoin_hash()));
+        assert_eq!(manager.store().height_of(&headers[2007].bitcoin_hash()).unwrap(), None);
+        assert!(manager.is_synced());
+
+        // peers sending forks which do not make a longer chain are removed
+        // after a few of them
+        manager.add_peer_at(5, 2008, later).unwrap();
+        for _ in 0..4 {
+            assert_eq!(manager.handle_headers_at(&5, &headers[2006..2008], later, NOW).unwrap(), 0);
+        }
+        match manager.handle_headers_at(&5, &headers[2006..2008], later, NOW) {
+            Err(Error::Misbehaving) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(manager.tip_height(), 2008);
+
+        // and so are peers sending forks too deep
+        let deep = next_header(&headers[500], XField::None, &second);
+        match manager.handle_headers_at(&4, &[deep.clone()], later, NOW) {
+            Err(Error::ForkTooDeep(hash)) => assert_eq!(hash, deep.bitcoin_hash()),
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(manager.store().hash_at(501).unwrap(), Some(headers[501].bitcoin_hash()));
+
+        // headers contradicting a checkpoint are refused
+        let checkpoints = Checkpoints::new().with(5, headers[6].bitcoin_hash());
+        let mut manager = SyncManager::with_checkpoints(MemoryHeaderStore::new(), sink.clone(), &genesis, checkpoints, timeout).unwrap();
+        match manager.handle_headers_at(&3, &headers[1..10], later, NOW) {
+            Err(Error::InvalidHeader(header_chain::Error::CheckpointMismatch(_))) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(manager.tip_height(), 4);
+
+        // header timestamps are checked against the time given
+        let mut manager = SyncManager::new(MemoryHeaderStore::new(), sink.clone(), &genesis, timeout).unwrap();
+        match manager.handle_headers_at(&3, &headers[1..2], later, GENESIS_TIME - 10_000) {
+            Err(Error::InvalidHeader(header_chain::Error::TimeTooNew(_))) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(manager.handle_headers_at(&3, &headers[1..2], later, GENESIS_TIME).unwrap(), 1);
+
+        let other = genesis_block_builder(second.public_key(&secp), Script::new(), GENESIS_TIME)
+            .sign(&second)
+            .unwrap()
+            .header;
+        match SyncManager::new(manager.into_store(), sink, &other, timeout) {
+            Err(Error::GenesisMismatch) => {}
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+}
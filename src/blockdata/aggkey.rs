@@ -0,0 +1,240 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Aggregate public key tracking
+//!
+//! Tapyrus blocks are signed by a federation whose aggregate public key is
+//! announced in the xfield of the genesis block, and may later be replaced
+//! by a block carrying a new aggregate public key. Such a block is still
+//! signed by the previous key; the new key signs the blocks after it.
+//!
+//! `AggregatePubkeyTracker` follows these updates along a header chain and
+//! tells which key signs the block at a given height.
+//!
+
+use std::{error, fmt};
+
+use blockdata::block::BlockHeader;
+use hash_types::BlockHash;
+use util::hash::BitcoinHash;
+use util::key::PublicKey;
+use util::signature;
+
+/// Aggregate public key tracking error
+#[derive(Debug)]
+pub enum Error {
+    /// The genesis block does not hold an aggregate public key
+    MissingGenesisKey,
+    /// The header does not follow the last tracked header
+    Disconnected(BlockHash),
+    /// The proof of the header is invalid
+    InvalidProof(signature::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Disconnected(ref hash) => write!(f, "header {} does not follow the chain tip", hash),
+            Error::InvalidProof(ref e) => write!(f, "invalid block proof: {}", e),
+            Error::MissingGenesisKey => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::MissingGenesisKey => "genesis block has no aggregate public key",
+            Error::Disconnected(_) => "header does not follow the chain tip",
+            Error::InvalidProof(_) => "invalid block proof",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::InvalidProof(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks the aggregate public key along a chain of headers, starting
+/// from the genesis block
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct AggregatePubkeyTracker {
+    /// Heights of the blocks setting a new key, in increasing order. The
+    /// first one is the genesis block.
+    changes: Vec<(u32, PublicKey)>,
+    /// Hash of the last tracked header
+    tip: Option<BlockHash>,
+    /// Number of tracked headers
+    len: u32,
+}
+
+impl AggregatePubkeyTracker {
+    /// Create a tracker expecting the genesis block header
+    pub fn new() -> AggregatePubkeyTracker {
+        AggregatePubkeyTracker::default()
+    }
+
+    /// Track the headers of a chain, starting from the genesis block
+    pub fn from_headers<'a, I>(headers: I) -> Result<AggregatePubkeyTracker, Error>
+        where I: IntoIterator<Item = &'a BlockHeader>
+    {
+        let mut tracker = AggregatePubkeyTracker::new();
+        for header in headers {
+            tracker.push(header)?;
+        }
+        Ok(tracker)
+    }
+
+    /// Apply the next header of the chain, without checking its proof
+    pub fn push(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        match self.tip {
+            None => {
+                let key = header.aggregated_public_key().ok_or(Error::MissingGenesisKey)?;
+                self.changes.push((0, key));
+            },
+            Some(tip) => {
+                if header.prev_blockhash != tip {
+                    return Err(Error::Disconnected(header.bitcoin_hash()));
+                }
+                if let Some(key) = header.aggregated_public_key() {
+                    self.changes.push((self.len, key));
+                }
+            },
+        }
+        self.tip = Some(header.bitcoin_hash());
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Apply the next header of the chain, after checking that it is signed
+    /// by the current aggregate public key
+    pub fn push_verified(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        if self.tip.is_some() {
+            let key = self.next_key().expect("tracker has a key once the genesis block is pushed");
+            header.verify_proof(key).map_err(Error::InvalidProof)?;
+        } else if let Some(key) = header.aggregated_public_key() {
+            // the genesis block is signed by the key it announces
+            header.verify_proof(&key).map_err(Error::InvalidProof)?;
+        }
+        self.push(header)
+    }
+
+    /// The height of the last tracked header, `None` if no header was
+    /// tracked yet
+    pub fn height(&self) -> Option<u32> {
+        self.len.checked_sub(1)
+    }
+
+    /// The hash of the last tracked header
+    pub fn tip(&self) -> Option<BlockHash> {
+        self.tip
+    }
+
+    /// The key signing the block at `height`. Returns `None` beyond the
+    /// block following the last tracked header, as the keys of later
+    /// blocks are not known yet.
+    pub fn key_at(&self, height: u32) -> Option<&PublicKey> {
+        if self.changes.is_empty() || height > self.len {
+            return None;
+        }
+        let idx = match self.changes.binary_search_by_key(&height, |&(h, _)| h) {
+            // a block setting a new key is signed by the previous one, except
+            // the genesis block
+            Ok(0) => 0,
+            Ok(idx) | Err(idx) => idx - 1,
+        };
+        Some(&self.changes[idx].1)
+    }
+
+    /// The key signing the block following the last tracked header
+    pub fn next_key(&self) -> Option<&PublicKey> {
+        self.changes.last().map(|&(_, ref key)| key)
+    }
+
+    /// The heights of the blocks setting a new aggregate public key, with
+    /// these keys
+    pub fn changes(&self) -> &[(u32, PublicKey)] {
+        &self.changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+
+    use blockdata::block::{BlockHeader, XField};
+    use hash_types::{BlockHash, TxMerkleNode};
+    use hashes::Hash;
+    use util::hash::BitcoinHash;
+    use util::key::PrivateKey;
+    use util::signature::Signature;
+    use super::{AggregatePubkeyTracker, Error};
+
+    fn signed_header(prev_blockhash: BlockHash, xfield: XField, signer: &PrivateKey) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            prev_blockhash: prev_blockhash,
+            merkle_root: TxMerkleNode::default(),
+            im_merkle_root: TxMerkleNode::default(),
+            time: 1_600_000_000,
+            xfield: xfield,
+            proof: None,
+        };
+        header.proof = Some(Signature::sign(signer, &header.signature_hash().into_inner()).unwrap());
+        header
+    }
+
+    #[test]
+    fn tracker_test() {
+        let secp = Secp256k1::new();
+        let first = PrivateKey::from_str("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let second = PrivateKey::from_str("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        let first_pk = first.public_key(&secp);
+        let second_pk = second.public_key(&secp);
+
+        let genesis = signed_header(BlockHash::default(), XField::AggregatePublicKey(first_pk), &first);
+        let block1 = signed_header(genesis.bitcoin_hash(), XField::None, &first);
+        // announces the new key, but is still signed by the first one
+        let block2 = signed_header(block1.bitcoin_hash(), XField::AggregatePublicKey(second_pk), &first);
+        let block3 = signed_header(block2.bitcoin_hash(), XField::None, &second);
+
+        let mut tracker = AggregatePubkeyTracker::new();
+        assert_eq!(tracker.key_at(0), None);
+        for header in &[&genesis, &block1, &block2, &block3] {
+            tracker.push_verified(header).unwrap();
+        }
+        assert_eq!(tracker.height(), Some(3));
+        assert_eq!(tracker.tip(), Some(block3.bitcoin_hash()));
+        assert_eq!(tracker.key_at(0), Some(&first_pk));
+        assert_eq!(tracker.key_at(2), Some(&first_pk));
+        assert_eq!(tracker.key_at(3), Some(&second_pk));
+        assert_eq!(tracker.key_at(4), Some(&second_pk));
+        assert_eq!(tracker.key_at(5), None);
+        assert_eq!(tracker.next_key(), Some(&second_pk));
+        assert_eq!(tracker.changes(), &[(0, first_pk), (2, second_pk)]);
+
+        let unverified = AggregatePubkeyTracker::from_headers(&[genesis.clone(), block1.clone(), block2.clone()]).unwrap();
+        assert_eq!(unverified.key_at(3), Some(&second_pk));
+
+        // signed by the wrong key
+        let bad = signed_header(block3.bitcoin_hash(), XField::None, &first);
+        match tracker.push_verified(&bad) {
+            Err(Error::InvalidProof(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match tracker.push(&block1) {
+            Err(Error::Disconnected(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match AggregatePubkeyTracker::new().push(&block1) {
+            Err(Error::MissingGenesisKey) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
@@ -25,18 +25,20 @@
 //! these blocks and the blockchain.
 //!
 
-use std::io;
-use std::str::FromStr;
+use core::fmt;
+#[cfg(feature = "std")] use core::str::FromStr;
 
 use hashes::{Hash, HashEngine};
-use hashes::hex::FromHex;
 use hash_types::{Wtxid, BlockHash, BlockSigHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
-use consensus::{serialize, encode, Decodable, Encodable};
-use consensus::encode::serialize_hex;
+use consensus::{encode, serialize, ChainLimits, Decodable, Encodable};
+use consensus::encode::VarInt;
+use io::{self, Read};
+use prelude::*;
+use blockdata::constants::MIN_TRANSACTION_WEIGHT;
 use blockdata::transaction::Transaction;
 use util::hash::{bitcoin_merkle_root, BitcoinHash};
 use util::key::PublicKey;
-use util::signature::Signature;
+use util::signature::{self, Signature};
 
 /// A block header, which contains all the block's information except
 /// the actual transactions
@@ -58,15 +60,6 @@ pub struct BlockHeader {
     pub proof: Option<Signature>,
 }
 
-struct BlockHeaderWithoutProof {
-    version: u32,
-    prev_blockhash: BlockHash,
-    merkle_root: TxMerkleNode,
-    im_merkle_root: TxMerkleNode,
-    time: u32,
-    xfield: XField,
-}
-
 impl BlockHeader {
     /// Return the Aggregate public key in this BlockHeader
     pub fn aggregated_public_key(&self) -> Option<PublicKey> {
@@ -76,24 +69,43 @@ impl BlockHeader {
         }
     }
 
+    /// Encode the header without its proof, as signed by the federation
+    pub fn consensus_encode_for_signing<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.version.consensus_encode(&mut s)?;
+        len += self.prev_blockhash.consensus_encode(&mut s)?;
+        len += self.merkle_root.consensus_encode(&mut s)?;
+        len += self.im_merkle_root.consensus_encode(&mut s)?;
+        len += self.time.consensus_encode(&mut s)?;
+        len += self.xfield.consensus_encode(&mut s)?;
+        Ok(len)
+    }
+
+    /// Serialize the header without its proof, as signed by the federation.
+    /// The full header, proof included, is serialized with
+    /// `consensus::serialize`.
+    pub fn serialize_for_signing(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.consensus_encode_for_signing(&mut data).expect("in-memory writers don't error");
+        data
+    }
+
     /// Computes a signature hash for this block.
     /// Tapyrus signer needs to sign this hash. The signature will be added to
     /// the block header as the proof field and submitted to the tapyrus node.
     pub fn signature_hash(&self) -> BlockSigHash {
-        let block = BlockHeaderWithoutProof::from(&self);
-        BlockSigHash::hash(&serialize(&block))
+        let mut engine = BlockSigHash::engine();
+        self.consensus_encode_for_signing(&mut engine).expect("engines don't error");
+        BlockSigHash::from_engine(engine)
     }
-}
 
-impl BlockHeaderWithoutProof {
-    fn from(header: &BlockHeader) -> Self {
-        Self {
-            version: header.version,
-            prev_blockhash: header.prev_blockhash,
-            merkle_root: header.merkle_root,
-            im_merkle_root: header.im_merkle_root,
-            time: header.time,
-            xfield: header.xfield.clone(),
+    /// Verify the proof of this block against the aggregate public key of
+    /// the signers. Returns `Error::InvalidSignature` if the header has no
+    /// proof.
+    pub fn verify_proof(&self, aggregated_pubkey: &PublicKey) -> Result<(), signature::Error> {
+        match self.proof {
+            Some(ref proof) => proof.verify(&self.signature_hash().into_inner(), aggregated_pubkey),
+            None => Err(signature::Error::InvalidSignature),
         }
     }
 }
@@ -120,17 +132,31 @@ impl XField {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for XField {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<XField> {
+        Ok(match u.arbitrary::<u8>()? {
+            0 => XField::None,
+            1 => XField::AggregatePublicKey(u.arbitrary()?),
+            x_type => XField::Unknown(x_type, u.arbitrary()?),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
 impl FromStr for XField {
     type Err = encode::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let bytes: Vec<u8> = Vec::from_hex(&s[..]).map_err(|_| encode::Error::ParseFailed("invalid hex string"))?;
-        XField::consensus_decode(&bytes[..])
+        encode::deserialize_hex(s)
     }
 }
 
-impl std::fmt::Display for XField {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", serialize_hex(self))
+impl fmt::Display for XField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in serialize(self) {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
     }
 }
 
@@ -189,7 +215,7 @@ impl Decodable for XField {
             1 => {
                 let bytes: Vec<u8> = Decodable::consensus_decode(&mut d)?;
                 let pk = PublicKey::from_slice(&bytes)
-                    .map_err(|_| encode::Error::ParseFailed("aggregate public key"))?;
+                    .map_err(|_| encode::Error::Parse(encode::ParseError::Other("aggregate public key")))?;
                 Ok(XField::AggregatePublicKey(pk))
             },
             _ => {
@@ -229,10 +255,11 @@ pub struct Block {
 }
 
 impl Block {
-    /// check if merkle root of header matches merkle root of the transaction list
-    pub fn check_merkle_root (&self) -> bool {
-        self.header.merkle_root == self.merkle_root() &&
-            self.header.im_merkle_root == self.immutable_merkle_root()
+    /// Check that both the merkle root and the immutable merkle root of the
+    /// header match the transaction list
+    pub fn check_merkle_root(&self) -> bool {
+        self.header.merkle_root == self.compute_merkle_root() &&
+            self.header.im_merkle_root == self.compute_immutable_merkle_root()
     }
 
     /// check if witness commitment in coinbase is matching the transaction list
@@ -244,7 +271,7 @@ impl Block {
         }
         if self.txdata.len() > 0 {
             let coinbase = &self.txdata[0];
-            if coinbase.is_coin_base() {
+            if coinbase.is_coinbase() {
                 // commitment is in the last output that starts with below magic
                 if let Some(pos) = coinbase.output.iter()
                     .rposition(|o| {
@@ -263,17 +290,39 @@ impl Block {
     }
 
     /// Calculate the transaction merkle root.
-    pub fn merkle_root(&self) -> TxMerkleNode {
+    pub fn compute_merkle_root(&self) -> TxMerkleNode {
         let hashes = self.txdata.iter().map(|obj| obj.txid().as_hash());
         bitcoin_merkle_root(hashes).into()
     }
 
-    /// Calculate the immutable transaction merkle root.
-    fn immutable_merkle_root(&self) -> TxMerkleNode {
+    /// Calculate the transaction merkle root.
+    #[deprecated(note = "use compute_merkle_root")]
+    pub fn merkle_root(&self) -> TxMerkleNode {
+        self.compute_merkle_root()
+    }
+
+    /// Calculate the immutable merkle root, the merkle root of the
+    /// malleability-fixed transaction ids.
+    pub fn compute_immutable_merkle_root(&self) -> TxMerkleNode {
         let hashes = self.txdata.iter().map(|obj| obj.malfix_txid().as_hash());
         bitcoin_merkle_root(hashes).into()
     }
 
+    /// Get the size of the serialized block, witnesses included
+    pub fn size(&self) -> usize {
+        self.header.consensus_encode(io::sink()).expect("sinks don't error")
+            + VarInt(self.txdata.len() as u64).len()
+            + self.txdata.iter().map(Transaction::size).sum::<usize>()
+    }
+
+    /// Get the size of the serialized block without the witnesses of its
+    /// transactions
+    pub fn stripped_size(&self) -> usize {
+        self.header.consensus_encode(io::sink()).expect("sinks don't error")
+            + VarInt(self.txdata.len() as u64).len()
+            + self.txdata.iter().map(Transaction::stripped_size).sum::<usize>()
+    }
+
     /// compute witness commitment for the transaction list
     pub fn compute_witness_commitment (witness_root: &WitnessMerkleNode, witness_reserved_value: &[u8]) -> WitnessCommitment {
         let mut encoder = WitnessCommitment::engine();
@@ -294,11 +343,60 @@ impl Block {
         );
         bitcoin_merkle_root(hashes).into()
     }
+
+    /// Decode a block, rejecting it if it is larger than the maximum block
+    /// size of the chain
+    pub fn consensus_decode_with_limits<D: io::Read>(d: D, limits: &ChainLimits) -> Result<Block, encode::Error> {
+        let max = limits.max_block_size;
+        let mut d = d.take(max as u64);
+        Block::decode_limited(&mut d, max).map_err(|e| match e {
+            encode::Error::Io(_) if d.limit() == 0 => {
+                encode::Error::Policy(encode::PolicyError::OversizedBlock { max: max })
+            }
+            e => e,
+        })
+    }
+
+    fn decode_limited<D: io::Read>(mut d: D, max: usize) -> Result<Block, encode::Error> {
+        let header = BlockHeader::consensus_decode(&mut d)?;
+        let len = VarInt::consensus_decode(&mut d)?.0;
+        // a transaction is at least a quarter of its minimum weight in bytes,
+        // so larger counts cannot fit in the block and are not worth allocating
+        let max_len = (max / (MIN_TRANSACTION_WEIGHT / 4) as usize) as u64;
+        if len > max_len {
+            return Err(encode::Error::Policy(encode::PolicyError::OversizedBlock { max: max }));
+        }
+        let mut txdata = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            txdata.push(Transaction::consensus_decode(&mut d)?);
+        }
+        Ok(Block { header: header, txdata: txdata })
+    }
 }
 
 impl BitcoinHash<BlockHash> for BlockHeader {
     fn bitcoin_hash(&self) -> BlockHash {
-        BlockHash::hash(&serialize(self))
+        let mut engine = BlockHash::engine();
+        self.consensus_encode(&mut engine).expect("engines don't error");
+        BlockHash::from_engine(engine)
+    }
+}
+
+/// Hash `headers`, as `BlockHeader::bitcoin_hash` does. The headers are
+/// encoded straight into the hash engines, without allocating; with the
+/// `rayon` feature, large batches are also hashed on all cores.
+pub fn hash_headers(headers: &[BlockHeader]) -> Vec<BlockHash> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+
+        // below this many headers, splitting the work costs more than it saves
+        const MIN_HEADERS_PER_TASK: usize = 1024;
+        headers.par_iter().with_min_len(MIN_HEADERS_PER_TASK).map(|header| header.bitcoin_hash()).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        headers.iter().map(|header| header.bitcoin_hash()).collect()
     }
 }
 
@@ -319,16 +417,9 @@ impl_consensus_encoding!(
     proof
 );
 impl_consensus_encoding!(Block, header, txdata);
-impl_consensus_encoding!(
-    BlockHeaderWithoutProof,
-    version,
-    prev_blockhash,
-    merkle_root,
-    im_merkle_root,
-    time,
-    xfield
-);
-serde_struct_impl!(
+serde_consensus_impl!(BlockHeader);
+serde_consensus_impl!(Block);
+arbitrary_struct_impl!(
     BlockHeader,
     version,
     prev_blockhash,
@@ -337,18 +428,23 @@ serde_struct_impl!(
     time,
     xfield,
     proof);
-serde_struct_impl!(Block, header, txdata);
+arbitrary_struct_impl!(Block, header, txdata);
 
 #[cfg(test)]
 mod tests {
     use hex::decode as hex_decode;
     use std::str::FromStr;
 
-    use blockdata::block::{Block, XField};
-    use consensus::encode::{deserialize, serialize};
-    use util::key::PublicKey;
-    use hash_types::BlockSigHash;
+    use blockdata::block::{hash_headers, Block, XField};
+    use blockdata::locktime::LockTime;
+    use consensus::encode::{self, deserialize, serialize, ChainLimits};
+    use util::key::{PrivateKey, PublicKey};
+    use util::hash::BitcoinHash;
+    use util::signature::Signature;
+    use hash_types::{BlockHash, BlockSigHash};
+    use hashes::Hash;
     use hashes::hex::{FromHex};
+    use secp256k1::Secp256k1;
 
     #[test]
     fn block_test() {
@@ -372,10 +468,10 @@ mod tests {
         let real_decode = decode.unwrap();
         assert_eq!(real_decode.header.version, 1);
         assert_eq!(serialize(&real_decode.header.prev_blockhash), prevhash);
-        assert_eq!(real_decode.header.merkle_root, real_decode.merkle_root());
+        assert_eq!(real_decode.header.merkle_root, real_decode.compute_merkle_root());
         assert_eq!(
             real_decode.header.im_merkle_root,
-            real_decode.immutable_merkle_root()
+            real_decode.compute_immutable_merkle_root()
         );
         assert_eq!(serialize(&real_decode.header.merkle_root), merkle);
         assert_eq!(real_decode.header.time, 1231965655);
@@ -387,6 +483,17 @@ mod tests {
         assert!(real_decode.check_witness_commitment());
 
         assert_eq!(serialize(&real_decode), some_block);
+        assert_eq!(encode::serialized_len(&real_decode), some_block.len());
+        assert_eq!(real_decode.size(), some_block.len());
+        assert_eq!(real_decode.stripped_size(), some_block.len());
+
+        assert!(real_decode.check_merkle_root());
+        let mut tampered = real_decode.clone();
+        tampered.txdata[1].lock_time = LockTime::from(u32::from(tampered.txdata[1].lock_time) + 1);
+        assert!(!tampered.check_merkle_root());
+        let mut tampered = real_decode.clone();
+        tampered.header.im_merkle_root = tampered.header.merkle_root;
+        assert!(!tampered.check_merkle_root());
     }
 
     #[test]
@@ -401,6 +508,33 @@ mod tests {
         assert!(real_decode.header.proof.is_none());
     }
 
+    #[test]
+    fn decode_with_limits_test() {
+        let data = hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e4900000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap();
+        let block: Block = deserialize(&data).unwrap();
+
+        let decode = |data: &[u8], limits| Block::consensus_decode_with_limits(data, &limits);
+        assert_eq!(decode(&data, ChainLimits::default()).unwrap(), block);
+        assert_eq!(decode(&data, ChainLimits::new(data.len())).unwrap(), block);
+        match decode(&data, ChainLimits::new(data.len() - 1)) {
+            Err(encode::Error::Policy(encode::PolicyError::OversizedBlock { max })) => assert_eq!(max, data.len() - 1),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        // truncated blocks are still I/O errors
+        match decode(&data[..data.len() - 1], ChainLimits::default()) {
+            Err(encode::Error::Io(_)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // a transaction count which cannot fit is rejected before allocating
+        let mut huge = serialize(&block.header);
+        huge.extend_from_slice(&[0xfe, 0xff, 0xff, 0xff, 0xff]);
+        match decode(&huge, ChainLimits::default()) {
+            Err(encode::Error::Policy(encode::PolicyError::OversizedBlock { max: 1_000_000 })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
     #[test]
     fn xfield_none_test() {
         let bytes = hex_decode("00").unwrap();
@@ -470,10 +604,10 @@ mod tests {
         assert_eq!(real_decode.header.version, 0x20000000); // VERSIONBITS but no bits set
         assert_eq!(serialize(&real_decode.header.prev_blockhash), prevhash);
         assert_eq!(serialize(&real_decode.header.merkle_root), merkle);
-        assert_eq!(real_decode.header.merkle_root, real_decode.merkle_root());
+        assert_eq!(real_decode.header.merkle_root, real_decode.compute_merkle_root());
         assert_eq!(
             real_decode.header.im_merkle_root,
-            real_decode.immutable_merkle_root()
+            real_decode.compute_immutable_merkle_root()
         );
         assert_eq!(real_decode.header.time, 1472004949);
         // [test] TODO: check the transaction data
@@ -481,6 +615,53 @@ mod tests {
         assert!(real_decode.check_witness_commitment());
 
         assert_eq!(serialize(&real_decode), segwit_block);
+        assert_eq!(encode::serialized_len(&real_decode), segwit_block.len());
+        assert_eq!(real_decode.size(), segwit_block.len());
+        assert!(real_decode.stripped_size() < real_decode.size());
+    }
+
+    #[test]
+    fn verify_proof_test() {
+        let block = hex::decode("010000000000000000000000000000000000000000000000000000000000000000000000c1457ff3e5c527e69858108edf0ff1f49eea9c58d8d37300a164b3b4f8c8c7cef1a2e72770d547feae29f2dd40123a97c580d44fd4493de072416d53331997617b96f05d00403a4c09253c7b583e5260074380c9b99b895f938e37799d326ded984fb707e91fa4df2e0524a4ccf5fe224945b4fb94784b411a760eb730d95402d3383dd7ffdc01010000000100000000000000000000000000000000000000000000000000000000000000000000000022210366262690cbdf648132ce0c088962c6361112582364ede120f3780ab73438fc4bffffffff0100f2052a010000002776a9226d70757956774d32596a454d755a4b72687463526b614a787062715447417346484688ac00000000").unwrap();
+        let mut header = deserialize::<Block>(&block).unwrap().header;
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let pk = key.public_key(&secp);
+
+        header.xfield = XField::AggregatePublicKey(pk);
+        header.proof = None;
+        assert!(header.verify_proof(&pk).is_err());
+
+        header.proof = Some(Signature::sign(&key, &header.signature_hash().into_inner()).unwrap());
+        assert!(header.verify_proof(&pk).is_ok());
+
+        let other = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        assert!(header.verify_proof(&other).is_err());
+
+        header.time += 1;
+        assert!(header.verify_proof(&pk).is_err());
+    }
+
+    #[test]
+    fn hash_headers_test() {
+        let block = hex_decode("010000000000000000000000000000000000000000000000000000000000000000000000c1457ff3e5c527e69858108edf0ff1f49eea9c58d8d37300a164b3b4f8c8c7cef1a2e72770d547feae29f2dd40123a97c580d44fd4493de072416d53331997617b96f05d00403a4c09253c7b583e5260074380c9b99b895f938e37799d326ded984fb707e91fa4df2e0524a4ccf5fe224945b4fb94784b411a760eb730d95402d3383dd7ffdc01010000000100000000000000000000000000000000000000000000000000000000000000000000000022210366262690cbdf648132ce0c088962c6361112582364ede120f3780ab73438fc4bffffffff0100f2052a010000002776a9226d70757956774d32596a454d755a4b72687463526b614a787062715447417346484688ac00000000").unwrap();
+        let genesis = deserialize::<Block>(&block).unwrap().header;
+        let mut headers = vec![genesis.clone()];
+        for _ in 0..3000 {
+            let mut header = headers.last().unwrap().clone();
+            header.prev_blockhash = header.bitcoin_hash();
+            header.time += 1;
+            header.xfield = XField::None;
+            headers.push(header);
+        }
+
+        let hashes = hash_headers(&headers);
+        assert_eq!(hashes.len(), headers.len());
+        assert_eq!(hashes[0], BlockHash::hash(&serialize(&genesis)));
+        for (header, hash) in headers.iter().zip(hashes.iter()).skip(1) {
+            assert_eq!(BlockHash::hash(&serialize(header)), *hash);
+        }
+        assert!(hash_headers(&[]).is_empty());
     }
 
     #[test]
@@ -488,6 +669,12 @@ mod tests {
         let block = hex::decode("010000000000000000000000000000000000000000000000000000000000000000000000c1457ff3e5c527e69858108edf0ff1f49eea9c58d8d37300a164b3b4f8c8c7cef1a2e72770d547feae29f2dd40123a97c580d44fd4493de072416d53331997617b96f05d00403a4c09253c7b583e5260074380c9b99b895f938e37799d326ded984fb707e91fa4df2e0524a4ccf5fe224945b4fb94784b411a760eb730d95402d3383dd7ffdc01010000000100000000000000000000000000000000000000000000000000000000000000000000000022210366262690cbdf648132ce0c088962c6361112582364ede120f3780ab73438fc4bffffffff0100f2052a010000002776a9226d70757956774d32596a454d755a4b72687463526b614a787062715447417346484688ac00000000").unwrap();
         let decode: Result<Block, _> = deserialize(&block);
         assert!(decode.is_ok());
-        assert_eq!(decode.unwrap().header.signature_hash(), BlockSigHash::from_hex("3d856f50e0718f72bab6516c1ab020ce3390ebc97490b6d2bad4054dc7a40a93").unwrap());
+        let header = decode.unwrap().header;
+        assert_eq!(header.signature_hash(), BlockSigHash::from_hex("3d856f50e0718f72bab6516c1ab020ce3390ebc97490b6d2bad4054dc7a40a93").unwrap());
+
+        // the proof is a length byte followed by the signature
+        let serialized = serialize(&header);
+        assert_eq!(header.serialize_for_signing(), &serialized[..serialized.len() - 65]);
+        assert_eq!(header.signature_hash(), BlockSigHash::hash(&header.serialize_for_signing()));
     }
 }
@@ -20,11 +20,20 @@
 //! Blockdata constants
 //!
 //! This module provides various constants relating to the blockchain and
-//! consensus code. In particular, it defines the genesis block and its
+//! consensus code. In particular, it builds the genesis block and its
 //! single transaction
 //!
 
+
+use blockdata::block::{Block, BlockHeader, XField};
+use blockdata::script::Script;
+use blockdata::transaction::CoinbaseBuilder;
+use hash_types::{BlockHash, TxMerkleNode};
+use hashes::Hash;
 use network::constants::Network;
+use util::amount::Amount;
+use util::key::{PrivateKey, PublicKey};
+use util::signature::{self, Signature};
 
 /// The maximum allowable sequence number
 pub const MAX_SEQUENCE: u32 = 0xFFFFFFFF;
@@ -35,9 +44,130 @@ pub const MAX_BLOCK_WEIGHT: u32 = 4_000_000;
 /// The minimum transaction weight for a valid serialized transaction
 pub const MIN_TRANSACTION_WEIGHT: u32 = 4 * 60;
 
+/// The maximum value of an output, and of the outputs of a transaction
+pub const MAX_MONEY: u64 = 21_000_000 * COIN_VALUE;
+
 /// The maximum value allowed in an output (useful for sanity checking,
 /// since keeping everything below this value should prevent overflows
 /// if you are doing anything remotely sane with monetary values).
 pub fn max_money(_: Network) -> u64 {
-    21_000_000 * COIN_VALUE
+    MAX_MONEY
+}
+
+/// Start building the genesis block of a new network, signed by the
+/// federation owning `aggregate_public_key` and timestamped with `time`. The
+/// block reward of 50 coins is paid to `payout_script`.
+///
+/// The built block can be written with `consensus::encode::serialize_hex` to the
+/// genesis file loaded by Tapyrus Core.
+pub fn genesis_block_builder(aggregate_public_key: PublicKey, payout_script: Script, time: u32) -> GenesisBlockBuilder {
+    GenesisBlockBuilder {
+        aggregate_public_key: aggregate_public_key,
+        payout_script: payout_script,
+        time: time,
+        reward: 50 * COIN_VALUE,
+    }
+}
+
+/// Builder for genesis blocks, see `genesis_block_builder`
+#[derive(Clone, Debug)]
+pub struct GenesisBlockBuilder {
+    aggregate_public_key: PublicKey,
+    payout_script: Script,
+    time: u32,
+    reward: u64,
+}
+
+impl GenesisBlockBuilder {
+    /// Set the amount paid by the coinbase transaction
+    pub fn reward(mut self, reward: u64) -> GenesisBlockBuilder {
+        self.reward = reward;
+        self
+    }
+
+    /// Build the block without its proof. The federation signs the
+    /// `signature_hash` of its header, and the resulting signature is
+    /// attached with `with_proof`.
+    pub fn build(&self) -> Block {
+        // the coinbase script pushes the aggregate public key
+        let coinbase = CoinbaseBuilder::new(0)
+            .push_data(&self.aggregate_public_key.to_bytes())
+            .output(self.payout_script.clone(), Amount::from_sat(self.reward))
+            .build();
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: BlockHash::default(),
+                merkle_root: TxMerkleNode::default(),
+                im_merkle_root: TxMerkleNode::default(),
+                time: self.time,
+                xfield: XField::AggregatePublicKey(self.aggregate_public_key),
+                proof: None,
+            },
+            txdata: vec![coinbase],
+        };
+        block.header.merkle_root = block.compute_merkle_root();
+        block.header.im_merkle_root = block.compute_immutable_merkle_root();
+        block
+    }
+
+    /// Build the block with the given proof, checking that it is a valid
+    /// signature by the aggregate public key
+    pub fn with_proof(&self, proof: Signature) -> Result<Block, signature::Error> {
+        let mut block = self.build();
+        block.header.proof = Some(proof);
+        block.header.verify_proof(&self.aggregate_public_key)?;
+        Ok(block)
+    }
+
+    /// Build the block and sign it with the private key matching the
+    /// aggregate public key
+    pub fn sign(&self, key: &PrivateKey) -> Result<Block, signature::Error> {
+        let sighash = self.build().header.signature_hash();
+        self.with_proof(Signature::sign(key, &sighash.into_inner())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hex::decode as hex_decode;
+    use secp256k1::Secp256k1;
+
+    use blockdata::block::Block;
+    use blockdata::script::Script;
+    use consensus::encode::{deserialize, serialize};
+    use util::key::{PrivateKey, PublicKey};
+    use util::signature::Signature;
+    use super::genesis_block_builder;
+
+    #[test]
+    fn genesis_block_builder_test() {
+        // coinbase of a genesis block created by the Tapyrus genesis tool
+        let coinbase = hex_decode("010000000100000000000000000000000000000000000000000000000000000000000000000000000022210366262690cbdf648132ce0c088962c6361112582364ede120f3780ab73438fc4bffffffff0100f2052a010000002776a9226d70757956774d32596a454d755a4b72687463526b614a787062715447417346484688ac00000000").unwrap();
+        let pk = PublicKey::from_str("0366262690cbdf648132ce0c088962c6361112582364ede120f3780ab73438fc4b").unwrap();
+        let payout_script = Script::from(hex_decode("76a9226d70757956774d32596a454d755a4b72687463526b614a787062715447417346484688ac").unwrap());
+        let block = genesis_block_builder(pk, payout_script, 1575442161).build();
+        assert_eq!(serialize(&block.txdata[0]), coinbase);
+        assert!(block.txdata[0].is_coinbase());
+        assert!(block.check_merkle_root());
+        assert_eq!(block.header.aggregated_public_key(), Some(pk));
+        assert_eq!(block.header.time, 1575442161);
+        assert!(block.header.proof.is_none());
+
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let builder = genesis_block_builder(key.public_key(&secp), Script::new(), 1600000000).reward(0);
+        let block = builder.sign(&key).unwrap();
+        assert_eq!(block.txdata[0].output[0].value, 0);
+        assert!(block.header.verify_proof(&key.public_key(&secp)).is_ok());
+        assert_eq!(deserialize::<Block>(&serialize(&block)).unwrap(), block);
+
+        // a proof which is not made by the aggregate key
+        let other = PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        assert!(builder.sign(&other).is_err());
+        let proof = Signature::sign(&key, &[0; 32]).unwrap();
+        assert!(builder.with_proof(proof).is_err());
+    }
 }
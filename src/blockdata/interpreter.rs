@@ -0,0 +1,1574 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Script interpreter
+//!
+//! Executes the scripts of transaction inputs as Tapyrus Core does, so that
+//! transactions can be verified without linking an external consensus
+//! library. Signatures sign the legacy signature hash, and are either DER
+//! encoded ECDSA signatures or 64-byte Schnorr signatures, each followed by
+//! their sighash type. `OP_CHECKDATASIG` checks signatures of the SHA256
+//! hash of a message instead, which have no sighash type.
+//!
+//! Colored script_pubkeys start with `<color id> OP_COLOR`. `OP_COLOR` pops
+//! a valid color identifier; it may appear once in a script_pubkey, outside
+//! of conditional branches, and never in a script_sig or redeem script.
+//!
+//! The rules besides those of consensus that an `Interpreter` enforces are
+//! chosen with `VerifyFlags`, as the standardness policy of Tapyrus Core
+//! does.
+//!
+
+use core::{cmp, fmt, ops};
+#[cfg(feature = "std")] use std::error;
+
+use hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use secp256k1::{self, Secp256k1, VerifyOnly};
+
+use blockdata::locktime::{LockTime, RelativeLockTime, Sequence};
+use blockdata::opcodes::{self, Class, Ordinary};
+use blockdata::script::{build_scriptint, read_scriptbool, read_scriptint_of_len, Builder, ColorIdentifier, Instruction, Script, MAX_PUBKEYS_PER_MULTISIG};
+use blockdata::transaction::Transaction;
+use consensus::encode::deserialize;
+use prelude::*;
+use util::ecdsa::{is_defined_sighash_type, is_low_s, is_valid_der_encoding};
+use util::key::PublicKey;
+use util::sighash::SighashCache;
+use util::schnorr::{SchnorrSignature, SCHNORR_SIGNATURE_SIZE};
+
+/// Maximum size of a stack element, in bytes
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum number of non-push operations per script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum size of a script, in bytes
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum number of elements on the stack and the altstack together
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// Ways the execution of a script may fail, as Tapyrus Core's script errors
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The scripts left an empty stack or a false value on top of it
+    EvalFalse,
+    /// `OP_RETURN` was executed
+    OpReturn,
+    /// The script is larger than `MAX_SCRIPT_SIZE`
+    ScriptSize,
+    /// A pushed element is larger than `MAX_SCRIPT_ELEMENT_SIZE`
+    PushSize,
+    /// The script executes more than `MAX_OPS_PER_SCRIPT` operations
+    OpCount,
+    /// The stacks hold more than `MAX_STACK_SIZE` elements
+    StackSize,
+    /// Invalid number of signatures of a multisig
+    SigCount,
+    /// Invalid number of public keys of a multisig
+    PubkeyCount,
+    /// `OP_VERIFY` failed
+    Verify,
+    /// `OP_EQUALVERIFY` failed
+    EqualVerify,
+    /// `OP_CHECKSIGVERIFY` failed
+    CheckSigVerify,
+    /// `OP_CHECKMULTISIGVERIFY` failed
+    CheckMultiSigVerify,
+    /// `OP_CHECKDATASIGVERIFY` failed
+    CheckDataSigVerify,
+    /// `OP_NUMEQUALVERIFY` failed
+    NumEqualVerify,
+    /// An invalid opcode was executed, or the script could not be parsed
+    BadOpcode,
+    /// The script contains a disabled opcode
+    DisabledOpcode,
+    /// An operation needed more elements than the stack holds
+    InvalidStackOperation,
+    /// `OP_FROMALTSTACK` was executed with an empty altstack
+    InvalidAltstackOperation,
+    /// `OP_ELSE` or `OP_ENDIF` without `OP_IF`, or `OP_IF` without `OP_ENDIF`
+    UnbalancedConditional,
+    /// A number on the stack is too large
+    NumericOverflow,
+    /// `OP_CHECKLOCKTIMEVERIFY` or `OP_CHECKSEQUENCEVERIFY` with a negative
+    /// lock time
+    NegativeLockTime,
+    /// The lock time required by `OP_CHECKLOCKTIMEVERIFY` or
+    /// `OP_CHECKSEQUENCEVERIFY` is not satisfied
+    UnsatisfiedLockTime,
+    /// An ECDSA signature is not strictly DER encoded, per BIP66
+    SigDer,
+    /// The script_sig spending a P2SH output does not only push data
+    SigPushOnly,
+    /// A multisig mixes ECDSA and Schnorr signatures
+    MixedSignatureTypes,
+    /// `OP_COLOR` did not find a valid color identifier on the stack
+    ColorId,
+    /// `OP_COLOR` appears more than once in a script
+    ColorMultiple,
+    /// `OP_COLOR` appears in a conditional branch
+    ColorInBranch,
+    /// `OP_COLOR` appears in a script_sig or a redeem script
+    ColorUnexpected,
+    /// A signature has an undefined sighash type (`STRICTENC`)
+    SigHashType,
+    /// An ECDSA signature has a high S value (`LOW_S`)
+    SigHighS,
+    /// A public key is neither compressed nor uncompressed (`STRICTENC`)
+    PubkeyType,
+    /// The extra element consumed by a multisig is not empty (`NULLDUMMY`)
+    SigNullDummy,
+    /// Data is pushed, or a number encoded, with more bytes than necessary
+    /// (`MINIMALDATA`)
+    MinimalData,
+    /// An upgradable `OP_NOP` was executed (`DISCOURAGE_UPGRADABLE_NOPS`)
+    DiscourageUpgradableNops,
+    /// The scripts left more than one element on the stack (`CLEANSTACK`)
+    CleanStack,
+    /// A failed signature check had a non-empty signature (`NULLFAIL`)
+    NullFail,
+    /// `CLEANSTACK` is enabled without `P2SH`
+    InvalidFlags,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::EvalFalse => "script evaluated without error but finished with a false/empty top stack element",
+            Error::OpReturn => "OP_RETURN was encountered",
+            Error::ScriptSize => "script is too big",
+            Error::PushSize => "push value size limit exceeded",
+            Error::OpCount => "operation limit exceeded",
+            Error::StackSize => "stack size limit exceeded",
+            Error::SigCount => "signature count negative or greater than pubkey count",
+            Error::PubkeyCount => "pubkey count negative or limit exceeded",
+            Error::Verify => "script failed an OP_VERIFY operation",
+            Error::EqualVerify => "script failed an OP_EQUALVERIFY operation",
+            Error::CheckSigVerify => "script failed an OP_CHECKSIGVERIFY operation",
+            Error::CheckMultiSigVerify => "script failed an OP_CHECKMULTISIGVERIFY operation",
+            Error::CheckDataSigVerify => "script failed an OP_CHECKDATASIGVERIFY operation",
+            Error::NumEqualVerify => "script failed an OP_NUMEQUALVERIFY operation",
+            Error::BadOpcode => "opcode missing or not understood",
+            Error::DisabledOpcode => "attempted to use a disabled opcode",
+            Error::InvalidStackOperation => "operation not valid with the current stack size",
+            Error::InvalidAltstackOperation => "operation not valid with the current altstack size",
+            Error::UnbalancedConditional => "invalid OP_IF construction",
+            Error::NumericOverflow => "number on the stack too large",
+            Error::NegativeLockTime => "negative locktime",
+            Error::UnsatisfiedLockTime => "locktime requirement not satisfied",
+            Error::SigDer => "non-canonical DER signature",
+            Error::SigPushOnly => "only push operators allowed in signatures",
+            Error::MixedSignatureTypes => "mixed ECDSA and Schnorr signatures in a multisig",
+            Error::ColorId => "invalid color identifier for OP_COLOR",
+            Error::ColorMultiple => "multiple OP_COLOR in a script",
+            Error::ColorInBranch => "OP_COLOR in a conditional branch",
+            Error::ColorUnexpected => "OP_COLOR outside of a script_pubkey",
+            Error::SigHashType => "signature hash type missing or not understood",
+            Error::SigHighS => "non-canonical signature: S value is unnecessarily high",
+            Error::PubkeyType => "public key is neither compressed or uncompressed",
+            Error::SigNullDummy => "dummy CHECKMULTISIG argument must be zero",
+            Error::MinimalData => "data push or number larger than necessary",
+            Error::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+            Error::CleanStack => "stack size must be exactly one after execution",
+            Error::NullFail => "signature must be zero for failed CHECK(MULTI)SIG operation",
+            Error::InvalidFlags => "CLEANSTACK requires P2SH",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> { None }
+}
+
+/// Rules enforced by an `Interpreter`, besides the ones always enforced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    /// NONE means no rule besides the ones always enforced.
+    pub const NONE: VerifyFlags = VerifyFlags(0);
+
+    /// P2SH means the redeem scripts of P2SH and CP2SH outputs are
+    /// evaluated, per BIP16.
+    pub const P2SH: VerifyFlags = VerifyFlags(1 << 0);
+
+    /// STRICTENC means sighash types must be defined and public keys
+    /// compressed or uncompressed, and implies DERSIG.
+    pub const STRICTENC: VerifyFlags = VerifyFlags(1 << 1);
+
+    /// DERSIG means ECDSA signatures must be strictly DER encoded, per
+    /// BIP66.
+    pub const DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+
+    /// LOW_S means ECDSA signatures must have a low S value, and implies
+    /// DERSIG.
+    pub const LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+
+    /// NULLDUMMY means the extra element consumed by a multisig must be
+    /// empty.
+    pub const NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+
+    /// SIGPUSHONLY means every script_sig must only push data.
+    pub const SIGPUSHONLY: VerifyFlags = VerifyFlags(1 << 5);
+
+    /// MINIMALDATA means data must be pushed, and numbers encoded, with as
+    /// few bytes as possible.
+    pub const MINIMALDATA: VerifyFlags = VerifyFlags(1 << 6);
+
+    /// DISCOURAGE_UPGRADABLE_NOPS means the `OP_NOP`s reserved for future
+    /// soft forks fail the script.
+    pub const DISCOURAGE_UPGRADABLE_NOPS: VerifyFlags = VerifyFlags(1 << 7);
+
+    /// CLEANSTACK means the scripts must leave exactly one element on the
+    /// stack, and requires P2SH.
+    pub const CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+
+    /// CHECKLOCKTIMEVERIFY means `OP_CHECKLOCKTIMEVERIFY` is executed, per
+    /// BIP65, instead of being a no-op.
+    pub const CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+
+    /// CHECKSEQUENCEVERIFY means `OP_CHECKSEQUENCEVERIFY` is executed, per
+    /// BIP112, instead of being a no-op.
+    pub const CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+
+    /// NULLFAIL means failed signature checks must have empty signatures.
+    pub const NULLFAIL: VerifyFlags = VerifyFlags(1 << 14);
+
+    /// CONSENSUS is the set of rules every block enforces.
+    pub const CONSENSUS: VerifyFlags = VerifyFlags(
+        VerifyFlags::P2SH.0 | VerifyFlags::DERSIG.0 |
+        VerifyFlags::CHECKLOCKTIMEVERIFY.0 | VerifyFlags::CHECKSEQUENCEVERIFY.0
+    );
+
+    /// STANDARD is the set of rules transactions must follow to be relayed.
+    pub const STANDARD: VerifyFlags = VerifyFlags(
+        VerifyFlags::CONSENSUS.0 | VerifyFlags::STRICTENC.0 | VerifyFlags::LOW_S.0 |
+        VerifyFlags::NULLDUMMY.0 | VerifyFlags::MINIMALDATA.0 |
+        VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS.0 | VerifyFlags::CLEANSTACK.0 |
+        VerifyFlags::NULLFAIL.0
+    );
+
+    /// Add [VerifyFlags] together.
+    ///
+    /// Returns itself.
+    pub fn add(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 |= other.0;
+        *self
+    }
+
+    /// Remove [VerifyFlags] from this.
+    ///
+    /// Returns itself.
+    pub fn remove(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 &= !other.0;
+        *self
+    }
+
+    /// Check whether [VerifyFlags] are included in this one.
+    pub fn has(&self, flags: VerifyFlags) -> bool {
+        (self.0 | flags.0) == self.0
+    }
+
+    /// Get the integer representation of this [VerifyFlags].
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for VerifyFlags {
+    fn default() -> VerifyFlags {
+        VerifyFlags::CONSENSUS
+    }
+}
+
+impl From<u32> for VerifyFlags {
+    fn from(f: u32) -> Self {
+        VerifyFlags(f)
+    }
+}
+
+impl From<VerifyFlags> for u32 {
+    fn from(flags: VerifyFlags) -> u32 {
+        flags.0
+    }
+}
+
+impl ops::BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        self.add(rhs)
+    }
+}
+
+impl ops::BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.add(rhs);
+    }
+}
+
+/// Executes the scripts of transaction inputs under a set of `VerifyFlags`
+pub struct Interpreter {
+    flags: VerifyFlags,
+    secp: Secp256k1<VerifyOnly>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new(VerifyFlags::default()).expect("consensus flags are consistent")
+    }
+}
+
+impl Interpreter {
+    /// Create an interpreter enforcing `flags`, which may not have
+    /// `CLEANSTACK` without `P2SH`, as Tapyrus Core asserts
+    pub fn new(flags: VerifyFlags) -> Result<Interpreter, Error> {
+        if flags.has(VerifyFlags::CLEANSTACK) && !flags.has(VerifyFlags::P2SH) {
+            return Err(Error::InvalidFlags);
+        }
+        Ok(Interpreter {
+            flags: flags,
+            secp: Secp256k1::verification_only(),
+        })
+    }
+
+    /// The rules enforced by this interpreter
+    pub fn flags(&self) -> VerifyFlags {
+        self.flags
+    }
+
+    /// Verify that input `input_index` of `tx` may spend an output locked
+    /// by `script_pubkey`
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to the number of
+    /// inputs
+    pub fn verify(&self, tx: &Transaction, input_index: usize, script_pubkey: &Script) -> Result<(), Error> {
+        self.verify_with_cache(&mut SighashCache::new(tx), input_index, script_pubkey)
+    }
+
+    /// Same as `verify`, reusing the signature hash cache of the
+    /// transaction, as when verifying each of its inputs
+    pub fn verify_with_cache(
+        &self,
+        cache: &mut SighashCache,
+        input_index: usize,
+        script_pubkey: &Script,
+    ) -> Result<(), Error> {
+        let script_sig = &cache.transaction().input[input_index].script_sig;
+        if self.flags.has(VerifyFlags::SIGPUSHONLY) && !script_sig.is_push_only() {
+            return Err(Error::SigPushOnly);
+        }
+        let mut checker = Checker {
+            interpreter: self,
+            cache: cache,
+            input_index: input_index,
+        };
+
+        let mut stack = vec![];
+        checker.eval(script_sig, &mut stack, ScriptKind::ScriptSig)?;
+        let mut redeem_stack = stack.clone();
+        checker.eval(script_pubkey, &mut stack, ScriptKind::ScriptPubkey)?;
+        if !stack.last().map_or(false, |top| read_scriptbool(top)) {
+            return Err(Error::EvalFalse);
+        }
+
+        if self.flags.has(VerifyFlags::P2SH) && (script_pubkey.is_p2sh() || script_pubkey.is_cp2sh()) {
+            if !script_sig.is_push_only() {
+                return Err(Error::SigPushOnly);
+            }
+            let redeem_script = Script::from(redeem_stack.pop().ok_or(Error::EvalFalse)?);
+            checker.eval(&redeem_script, &mut redeem_stack, ScriptKind::RedeemScript)?;
+            if !redeem_stack.last().map_or(false, |top| read_scriptbool(top)) {
+                return Err(Error::EvalFalse);
+            }
+            stack = redeem_stack;
+        }
+
+        if self.flags.has(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+            return Err(Error::CleanStack);
+        }
+        Ok(())
+    }
+}
+
+/// Verify that input `input_index` of `tx` may spend an output locked by
+/// `script_pubkey`, under the consensus rules
+///
+/// # Panics
+/// Panics if `input_index` is greater than or equal to the number of inputs
+pub fn verify_script(tx: &Transaction, input_index: usize, script_pubkey: &Script) -> Result<(), Error> {
+    Interpreter::default().verify(tx, input_index, script_pubkey)
+}
+
+type Stack = Vec<Vec<u8>>;
+
+/// The part of an input a script comes from
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum ScriptKind {
+    ScriptSig,
+    ScriptPubkey,
+    RedeemScript,
+}
+
+/// The state of the execution of a script, besides its stack
+#[derive(Default)]
+struct State {
+    altstack: Stack,
+    /// Whether each enclosing conditional branch is executed
+    exec: Vec<bool>,
+    op_count: usize,
+    /// The position following the instruction being executed
+    pos: usize,
+    /// The position following the last executed `OP_CODESEPARATOR`
+    code_start: usize,
+    colored: bool,
+}
+
+impl State {
+    fn is_executing(&self) -> bool {
+        self.exec.iter().all(|&executed| executed)
+    }
+}
+
+/// Executes the scripts of an input, checking its signatures and lock times
+struct Checker<'a, 'tx: 'a> {
+    interpreter: &'a Interpreter,
+    cache: &'a mut SighashCache<'tx>,
+    input_index: usize,
+}
+
+impl<'a, 'tx: 'a> Checker<'a, 'tx> {
+    fn has(&self, flags: VerifyFlags) -> bool {
+        self.interpreter.flags.has(flags)
+    }
+
+    fn eval(&mut self, script: &Script, stack: &mut Stack, kind: ScriptKind) -> Result<(), Error> {
+        if script.len() > MAX_SCRIPT_SIZE {
+            return Err(Error::ScriptSize);
+        }
+        let bytes = script.as_bytes();
+        let mut state = State::default();
+
+        for instruction in script.iter(false) {
+            let executing = state.is_executing();
+            let start = state.pos;
+            state.pos += instruction_len(&bytes[start..], &instruction);
+            match instruction {
+                Instruction::Error(_) => return Err(Error::BadOpcode),
+                Instruction::PushBytes(data) => {
+                    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(Error::PushSize);
+                    }
+                    if executing {
+                        if self.has(VerifyFlags::MINIMALDATA) && !is_minimal_push(data, bytes[start]) {
+                            return Err(Error::MinimalData);
+                        }
+                        stack.push(data.to_vec());
+                    }
+                }
+                Instruction::Op(op) => {
+                    if op.into_u8() > opcodes::all::OP_PUSHNUM_16.into_u8() {
+                        state.op_count += 1;
+                        if state.op_count > MAX_OPS_PER_SCRIPT {
+                            return Err(Error::OpCount);
+                        }
+                    }
+                    // disabled opcodes fail the script even when not executed
+                    if op.classify() == Class::IllegalOp {
+                        if op == opcodes::all::OP_VERIF || op == opcodes::all::OP_VERNOTIF {
+                            return Err(Error::BadOpcode);
+                        }
+                        return Err(Error::DisabledOpcode);
+                    }
+                    let conditional = op == opcodes::all::OP_IF || op == opcodes::all::OP_NOTIF ||
+                        op == opcodes::all::OP_ELSE || op == opcodes::all::OP_ENDIF;
+                    if executing || conditional {
+                        self.step(op, bytes, stack, &mut state, kind)?;
+                    }
+                }
+            }
+            if stack.len() + state.altstack.len() > MAX_STACK_SIZE {
+                return Err(Error::StackSize);
+            }
+        }
+
+        if !state.exec.is_empty() {
+            return Err(Error::UnbalancedConditional);
+        }
+        Ok(())
+    }
+
+    /// Execute a non-push opcode of `script`
+    fn step(
+        &mut self,
+        op: opcodes::All,
+        script: &[u8],
+        stack: &mut Stack,
+        state: &mut State,
+        kind: ScriptKind,
+    ) -> Result<(), Error> {
+        if op == opcodes::all::OP_CLTV && self.has(VerifyFlags::CHECKLOCKTIMEVERIFY) {
+            return self.check_lock_time(stack);
+        }
+        if op == opcodes::all::OP_CSV && self.has(VerifyFlags::CHECKSEQUENCEVERIFY) {
+            return self.check_sequence(stack);
+        }
+        let ordinary = match op.classify() {
+            Class::PushNum(n) => {
+                stack.push(build_scriptint(n as i64));
+                return Ok(());
+            }
+            Class::NoOp => {
+                if op != opcodes::all::OP_NOP && self.has(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                    return Err(Error::DiscourageUpgradableNops);
+                }
+                return Ok(());
+            }
+            Class::ReturnOp => {
+                return Err(if op == opcodes::all::OP_RETURN { Error::OpReturn } else { Error::BadOpcode });
+            }
+            Class::Ordinary(ordinary) => ordinary,
+            Class::PushBytes(_) | Class::IllegalOp => unreachable!(),
+        };
+
+        match ordinary {
+            // control flow
+            Ordinary::OP_IF | Ordinary::OP_NOTIF => {
+                let mut value = false;
+                if state.is_executing() {
+                    value = read_scriptbool(&stack.pop().ok_or(Error::UnbalancedConditional)?);
+                    if ordinary == Ordinary::OP_NOTIF {
+                        value = !value;
+                    }
+                }
+                state.exec.push(value);
+            }
+            Ordinary::OP_ELSE => {
+                let executed = state.exec.last_mut().ok_or(Error::UnbalancedConditional)?;
+                *executed = !*executed;
+            }
+            Ordinary::OP_ENDIF => {
+                state.exec.pop().ok_or(Error::UnbalancedConditional)?;
+            }
+            Ordinary::OP_VERIFY => {
+                if !read_scriptbool(&pop(stack)?) {
+                    return Err(Error::Verify);
+                }
+            }
+            // stack
+            Ordinary::OP_TOALTSTACK => {
+                let top = pop(stack)?;
+                state.altstack.push(top);
+            }
+            Ordinary::OP_FROMALTSTACK => {
+                let top = state.altstack.pop().ok_or(Error::InvalidAltstackOperation)?;
+                stack.push(top);
+            }
+            Ordinary::OP_2DROP => {
+                let len = require(stack, 2)?;
+                stack.truncate(len - 2);
+            }
+            Ordinary::OP_2DUP => {
+                let len = require(stack, 2)?;
+                let copies = stack[len - 2..].to_vec();
+                stack.extend(copies);
+            }
+            Ordinary::OP_3DUP => {
+                let len = require(stack, 3)?;
+                let copies = stack[len - 3..].to_vec();
+                stack.extend(copies);
+            }
+            Ordinary::OP_2OVER => {
+                let len = require(stack, 4)?;
+                let copies = stack[len - 4..len - 2].to_vec();
+                stack.extend(copies);
+            }
+            Ordinary::OP_2ROT => {
+                let len = require(stack, 6)?;
+                let moved: Stack = stack.drain(len - 6..len - 4).collect();
+                stack.extend(moved);
+            }
+            Ordinary::OP_2SWAP => {
+                let len = require(stack, 4)?;
+                stack.swap(len - 4, len - 2);
+                stack.swap(len - 3, len - 1);
+            }
+            Ordinary::OP_DROP => {
+                pop(stack)?;
+            }
+            Ordinary::OP_DUP => {
+                let len = require(stack, 1)?;
+                let top = stack[len - 1].clone();
+                stack.push(top);
+            }
+            Ordinary::OP_NIP => {
+                let len = require(stack, 2)?;
+                stack.remove(len - 2);
+            }
+            Ordinary::OP_OVER => {
+                let len = require(stack, 2)?;
+                let second = stack[len - 2].clone();
+                stack.push(second);
+            }
+            Ordinary::OP_PICK | Ordinary::OP_ROLL => {
+                let n = self.read_num(&pop(stack)?, 4)?;
+                if n < 0 || n as usize >= stack.len() {
+                    return Err(Error::InvalidStackOperation);
+                }
+                let index = stack.len() - 1 - n as usize;
+                let value = if ordinary == Ordinary::OP_ROLL {
+                    stack.remove(index)
+                } else {
+                    stack[index].clone()
+                };
+                stack.push(value);
+            }
+            Ordinary::OP_ROT => {
+                let len = require(stack, 3)?;
+                stack.swap(len - 3, len - 2);
+                stack.swap(len - 2, len - 1);
+            }
+            Ordinary::OP_SWAP => {
+                let len = require(stack, 2)?;
+                stack.swap(len - 2, len - 1);
+            }
+            Ordinary::OP_TUCK => {
+                let len = require(stack, 2)?;
+                let top = stack[len - 1].clone();
+                stack.insert(len - 2, top);
+            }
+            Ordinary::OP_IFDUP => {
+                let len = require(stack, 1)?;
+                if read_scriptbool(&stack[len - 1]) {
+                    let top = stack[len - 1].clone();
+                    stack.push(top);
+                }
+            }
+            Ordinary::OP_DEPTH => {
+                let depth = stack.len() as i64;
+                stack.push(build_scriptint(depth));
+            }
+            Ordinary::OP_SIZE => {
+                let len = require(stack, 1)?;
+                let size = stack[len - 1].len() as i64;
+                stack.push(build_scriptint(size));
+            }
+            // equality
+            Ordinary::OP_EQUAL | Ordinary::OP_EQUALVERIFY => {
+                require(stack, 2)?;
+                let equal = pop(stack)? == pop(stack)?;
+                if ordinary == Ordinary::OP_EQUALVERIFY {
+                    if !equal {
+                        return Err(Error::EqualVerify);
+                    }
+                } else {
+                    push_bool(stack, equal);
+                }
+            }
+            // arithmetic
+            Ordinary::OP_1ADD | Ordinary::OP_1SUB | Ordinary::OP_NEGATE | Ordinary::OP_ABS |
+            Ordinary::OP_NOT | Ordinary::OP_0NOTEQUAL => {
+                let n = self.read_num(&pop(stack)?, 4)?;
+                let result = match ordinary {
+                    Ordinary::OP_1ADD => n + 1,
+                    Ordinary::OP_1SUB => n - 1,
+                    Ordinary::OP_NEGATE => -n,
+                    Ordinary::OP_ABS => n.abs(),
+                    Ordinary::OP_NOT => (n == 0) as i64,
+                    Ordinary::OP_0NOTEQUAL => (n != 0) as i64,
+                    _ => unreachable!(),
+                };
+                stack.push(build_scriptint(result));
+            }
+            Ordinary::OP_ADD | Ordinary::OP_SUB | Ordinary::OP_BOOLAND | Ordinary::OP_BOOLOR |
+            Ordinary::OP_NUMEQUAL | Ordinary::OP_NUMEQUALVERIFY | Ordinary::OP_NUMNOTEQUAL |
+            Ordinary::OP_LESSTHAN | Ordinary::OP_GREATERTHAN | Ordinary::OP_LESSTHANOREQUAL |
+            Ordinary::OP_GREATERTHANOREQUAL | Ordinary::OP_MIN | Ordinary::OP_MAX => {
+                require(stack, 2)?;
+                let b = self.read_num(&pop(stack)?, 4)?;
+                let a = self.read_num(&pop(stack)?, 4)?;
+                let result = match ordinary {
+                    Ordinary::OP_ADD => a + b,
+                    Ordinary::OP_SUB => a - b,
+                    Ordinary::OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    Ordinary::OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    Ordinary::OP_NUMEQUAL | Ordinary::OP_NUMEQUALVERIFY => (a == b) as i64,
+                    Ordinary::OP_NUMNOTEQUAL => (a != b) as i64,
+                    Ordinary::OP_LESSTHAN => (a < b) as i64,
+                    Ordinary::OP_GREATERTHAN => (a > b) as i64,
+                    Ordinary::OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    Ordinary::OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    Ordinary::OP_MIN => cmp::min(a, b),
+                    Ordinary::OP_MAX => cmp::max(a, b),
+                    _ => unreachable!(),
+                };
+                if ordinary == Ordinary::OP_NUMEQUALVERIFY {
+                    if result == 0 {
+                        return Err(Error::NumEqualVerify);
+                    }
+                } else {
+                    stack.push(build_scriptint(result));
+                }
+            }
+            Ordinary::OP_WITHIN => {
+                require(stack, 3)?;
+                let max = self.read_num(&pop(stack)?, 4)?;
+                let min = self.read_num(&pop(stack)?, 4)?;
+                let n = self.read_num(&pop(stack)?, 4)?;
+                push_bool(stack, (min..max).contains(&n));
+            }
+            // crypto
+            Ordinary::OP_RIPEMD160 | Ordinary::OP_SHA1 | Ordinary::OP_SHA256 |
+            Ordinary::OP_HASH160 | Ordinary::OP_HASH256 => {
+                let data = pop(stack)?;
+                let hash = match ordinary {
+                    Ordinary::OP_RIPEMD160 => ripemd160::Hash::hash(&data)[..].to_vec(),
+                    Ordinary::OP_SHA1 => sha1::Hash::hash(&data)[..].to_vec(),
+                    Ordinary::OP_SHA256 => sha256::Hash::hash(&data)[..].to_vec(),
+                    Ordinary::OP_HASH160 => hash160::Hash::hash(&data)[..].to_vec(),
+                    Ordinary::OP_HASH256 => sha256d::Hash::hash(&data)[..].to_vec(),
+                    _ => unreachable!(),
+                };
+                stack.push(hash);
+            }
+            Ordinary::OP_CODESEPARATOR => {
+                state.code_start = state.pos;
+            }
+            Ordinary::OP_CHECKSIG | Ordinary::OP_CHECKSIGVERIFY => {
+                let len = require(stack, 2)?;
+                let script_code = script_code(&script[state.code_start..], &[&stack[len - 2][..]]);
+                let success = self.check_sig(&stack[len - 2], &stack[len - 1], &script_code)?;
+                if !success && self.has(VerifyFlags::NULLFAIL) && !stack[len - 2].is_empty() {
+                    return Err(Error::NullFail);
+                }
+                stack.truncate(len - 2);
+                if ordinary == Ordinary::OP_CHECKSIGVERIFY {
+                    if !success {
+                        return Err(Error::CheckSigVerify);
+                    }
+                } else {
+                    push_bool(stack, success);
+                }
+            }
+            Ordinary::OP_CHECKMULTISIG | Ordinary::OP_CHECKMULTISIGVERIFY => {
+                let success = self.check_multisig(script, stack, state)?;
+                if ordinary == Ordinary::OP_CHECKMULTISIGVERIFY {
+                    if !success {
+                        return Err(Error::CheckMultiSigVerify);
+                    }
+                } else {
+                    push_bool(stack, success);
+                }
+            }
+            Ordinary::OP_CHECKDATASIG | Ordinary::OP_CHECKDATASIGVERIFY => {
+                let len = require(stack, 3)?;
+                let success = self.check_data_sig(&stack[len - 3], &stack[len - 2], &stack[len - 1])?;
+                if !success && self.has(VerifyFlags::NULLFAIL) && !stack[len - 3].is_empty() {
+                    return Err(Error::NullFail);
+                }
+                stack.truncate(len - 3);
+                if ordinary == Ordinary::OP_CHECKDATASIGVERIFY {
+                    if !success {
+                        return Err(Error::CheckDataSigVerify);
+                    }
+                } else {
+                    push_bool(stack, success);
+                }
+            }
+            Ordinary::OP_COLOR => {
+                if kind != ScriptKind::ScriptPubkey {
+                    return Err(Error::ColorUnexpected);
+                }
+                if state.colored {
+                    return Err(Error::ColorMultiple);
+                }
+                if !state.exec.is_empty() {
+                    return Err(Error::ColorInBranch);
+                }
+                if deserialize::<ColorIdentifier>(&pop(stack)?).is_err() {
+                    return Err(Error::ColorId);
+                }
+                state.colored = true;
+            }
+            // pushes are read by the instruction iterator
+            Ordinary::OP_PUSHDATA1 | Ordinary::OP_PUSHDATA2 | Ordinary::OP_PUSHDATA4 => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Pop the operands of a multisig, then check its signatures against
+    /// its public keys, in order
+    fn check_multisig(&mut self, script: &[u8], stack: &mut Stack, state: &mut State) -> Result<bool, Error> {
+        let len = require(stack, 1)?;
+        let keys_count = self.read_num(&stack[len - 1], 4)?;
+        if keys_count < 0 || keys_count as usize > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(Error::PubkeyCount);
+        }
+        let mut keys_count = keys_count as usize;
+        state.op_count += keys_count;
+        if state.op_count > MAX_OPS_PER_SCRIPT {
+            return Err(Error::OpCount);
+        }
+        // depths of the next public key and signature to check
+        let mut ikey = 2;
+        let sigs_depth = ikey + keys_count;
+        require(stack, sigs_depth)?;
+        let sigs_count = self.read_num(&stack[len - sigs_depth], 4)?;
+        if sigs_count < 0 || sigs_count as usize > keys_count {
+            return Err(Error::SigCount);
+        }
+        let mut sigs_count = sigs_count as usize;
+        let mut isig = sigs_depth + 1;
+        // the operands are followed by an extra element, which is consumed
+        // as well
+        let dummy_depth = isig + sigs_count;
+        require(stack, dummy_depth)?;
+
+        let success = {
+            let sigs: Vec<&[u8]> = (isig..isig + sigs_count).map(|depth| &stack[len - depth][..]).collect();
+            let has_schnorr = sigs.iter().any(|sig| sig.len() == 65);
+            let has_ecdsa = sigs.iter().any(|sig| !sig.is_empty() && sig.len() != 65);
+            if has_schnorr && has_ecdsa {
+                return Err(Error::MixedSignatureTypes);
+            }
+            let script_code = script_code(&script[state.code_start..], &sigs);
+
+            let mut success = true;
+            while success && sigs_count > 0 {
+                if self.check_sig(&stack[len - isig], &stack[len - ikey], &script_code)? {
+                    isig += 1;
+                    sigs_count -= 1;
+                }
+                ikey += 1;
+                keys_count -= 1;
+                // fail early if there are not enough keys left
+                if sigs_count > keys_count {
+                    success = false;
+                }
+            }
+            if !success && self.has(VerifyFlags::NULLFAIL) && sigs.iter().any(|sig| !sig.is_empty()) {
+                return Err(Error::NullFail);
+            }
+            success
+        };
+        if self.has(VerifyFlags::NULLDUMMY) && !stack[len - dummy_depth].is_empty() {
+            return Err(Error::SigNullDummy);
+        }
+        stack.truncate(len - dummy_depth);
+        Ok(success)
+    }
+
+    /// Check a signature, followed by its sighash type, of the input
+    /// against a public key. Invalid signatures or keys fail the check but
+    /// not the script, except for wrongly encoded signatures or keys.
+    fn check_sig(&mut self, sig: &[u8], pubkey: &[u8], script_code: &Script) -> Result<bool, Error> {
+        self.check_signature_encoding(sig)?;
+        if self.has(VerifyFlags::STRICTENC) && !is_valid_pubkey_encoding(pubkey) {
+            return Err(Error::PubkeyType);
+        }
+        let (&sighash_type, sig) = match sig.split_last() {
+            Some(split) => split,
+            None => return Ok(false),
+        };
+        let pubkey = match PublicKey::from_slice(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return Ok(false),
+        };
+        let sighash = self.cache.signature_hash(self.input_index, script_code, sighash_type as u32);
+        Ok(self.verify_signature(sig, &pubkey, &sighash.into_inner()))
+    }
+
+    /// `OP_CHECKDATASIG`: check a signature, without sighash type, of the
+    /// SHA256 hash of `message` against a public key. As with `check_sig`,
+    /// only wrongly encoded signatures or keys fail the script.
+    fn check_data_sig(&self, sig: &[u8], message: &[u8], pubkey: &[u8]) -> Result<bool, Error> {
+        if !sig.is_empty() && sig.len() != SCHNORR_SIGNATURE_SIZE {
+            // the DER checks expect a sighash type after the signature
+            let mut with_type = sig.to_vec();
+            with_type.push(0);
+            self.check_der_encoding(&with_type)?;
+        }
+        if self.has(VerifyFlags::STRICTENC) && !is_valid_pubkey_encoding(pubkey) {
+            return Err(Error::PubkeyType);
+        }
+        if sig.is_empty() {
+            return Ok(false);
+        }
+        let pubkey = match PublicKey::from_slice(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return Ok(false),
+        };
+        Ok(self.verify_signature(sig, &pubkey, &sha256::Hash::hash(message).into_inner()))
+    }
+
+    /// Verify a Schnorr or ECDSA signature, without sighash type, of `hash`
+    fn verify_signature(&self, sig: &[u8], pubkey: &PublicKey, hash: &[u8; 32]) -> bool {
+        if sig.len() == SCHNORR_SIGNATURE_SIZE {
+            let signature = SchnorrSignature::from_slice(sig).expect("64 bytes");
+            signature.verify(hash, pubkey).is_ok()
+        } else {
+            let mut signature = match secp256k1::Signature::from_der_lax(sig) {
+                Ok(signature) => signature,
+                Err(_) => return false,
+            };
+            signature.normalize_s();
+            let message = secp256k1::Message::from_slice(&hash[..]).expect("hashes are 32 bytes");
+            self.interpreter.secp.verify(&message, &signature, &pubkey.key).is_ok()
+        }
+    }
+
+    /// Check the encoding of a signature followed by its sighash type.
+    /// Schnorr signatures are 64 bytes long, while ECDSA signatures must be
+    /// strictly DER encoded under `DERSIG`, `LOW_S` or `STRICTENC`.
+    fn check_signature_encoding(&self, sig: &[u8]) -> Result<(), Error> {
+        // an empty signature is a compact way to provide an invalid one
+        let (&sighash_type, _) = match sig.split_last() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        if sig.len() != 65 {
+            self.check_der_encoding(sig)?;
+        }
+        if self.has(VerifyFlags::STRICTENC) && !is_defined_sighash_type(sighash_type) {
+            return Err(Error::SigHashType);
+        }
+        Ok(())
+    }
+
+    /// Check the encoding of an ECDSA signature followed by its sighash type
+    fn check_der_encoding(&self, sig: &[u8]) -> Result<(), Error> {
+        let der = self.has(VerifyFlags::DERSIG) || self.has(VerifyFlags::LOW_S) ||
+            self.has(VerifyFlags::STRICTENC);
+        if der && !is_valid_der_encoding(sig) {
+            return Err(Error::SigDer);
+        }
+        if self.has(VerifyFlags::LOW_S) && !is_low_s(sig) {
+            return Err(Error::SigHighS);
+        }
+        Ok(())
+    }
+
+    /// Decode a number of at most `max_len` bytes from the stack, which
+    /// must be minimally encoded under `MINIMALDATA`
+    fn read_num(&self, v: &[u8], max_len: usize) -> Result<i64, Error> {
+        if v.len() <= max_len && self.has(VerifyFlags::MINIMALDATA) && !is_minimal_num(v) {
+            return Err(Error::MinimalData);
+        }
+        read_scriptint_of_len(v, max_len).map_err(|_| Error::NumericOverflow)
+    }
+
+    /// `OP_CHECKLOCKTIMEVERIFY`: check that the lock time of the transaction
+    /// is at least the one on top of the stack, per BIP65
+    fn check_lock_time(&self, stack: &Stack) -> Result<(), Error> {
+        let required = self.read_num(stack.last().ok_or(Error::InvalidStackOperation)?, 5)?;
+        if required < 0 {
+            return Err(Error::NegativeLockTime);
+        }
+        let tx = self.cache.transaction();
+        if required > i64::from(u32::max_value()) ||
+            !LockTime::from_consensus(required as u32).is_implied_by(tx.lock_time) ||
+            tx.input[self.input_index].sequence.is_final() {
+            return Err(Error::UnsatisfiedLockTime);
+        }
+        Ok(())
+    }
+
+    /// `OP_CHECKSEQUENCEVERIFY`: check that the relative lock time of the
+    /// input is at least the one on top of the stack, per BIP112
+    fn check_sequence(&self, stack: &Stack) -> Result<(), Error> {
+        let required = self.read_num(stack.last().ok_or(Error::InvalidStackOperation)?, 5)?;
+        if required < 0 {
+            return Err(Error::NegativeLockTime);
+        }
+        // sequences with the disable flag set make it a no-op
+        let required = match Sequence(required as u32).to_relative_lock_time() {
+            Some(required) => required,
+            None => return Ok(()),
+        };
+        let tx = self.cache.transaction();
+        let actual = if tx.version >= 2 {
+            tx.input[self.input_index].sequence.to_relative_lock_time()
+        } else {
+            None
+        };
+        let satisfied = match (required, actual) {
+            (RelativeLockTime::Blocks(required), Some(RelativeLockTime::Blocks(actual))) |
+            (RelativeLockTime::Intervals(required), Some(RelativeLockTime::Intervals(actual))) => required <= actual,
+            _ => false,
+        };
+        if !satisfied {
+            return Err(Error::UnsatisfiedLockTime);
+        }
+        Ok(())
+    }
+}
+
+/// Check that the stack holds at least `n` elements, returning its length
+fn require(stack: &Stack, n: usize) -> Result<usize, Error> {
+    if stack.len() < n {
+        Err(Error::InvalidStackOperation)
+    } else {
+        Ok(stack.len())
+    }
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, Error> {
+    stack.pop().ok_or(Error::InvalidStackOperation)
+}
+
+fn push_bool(stack: &mut Stack, value: bool) {
+    stack.push(if value { vec![1] } else { vec![] });
+}
+
+/// The length of the encoding of `instruction`, found at the start of
+/// `script`
+fn instruction_len(script: &[u8], instruction: &Instruction) -> usize {
+    match *instruction {
+        Instruction::PushBytes(data) => data.len() + match opcodes::All::from(script[0]).classify() {
+            Class::Ordinary(Ordinary::OP_PUSHDATA1) => 2,
+            Class::Ordinary(Ordinary::OP_PUSHDATA2) => 3,
+            Class::Ordinary(Ordinary::OP_PUSHDATA4) => 5,
+            _ => 1,
+        },
+        Instruction::Op(_) => 1,
+        Instruction::Error(_) => script.len(),
+    }
+}
+
+/// The script signed by `sigs`: `script` without the pushes of the
+/// signatures nor any `OP_CODESEPARATOR`
+fn script_code(script: &[u8], sigs: &[&[u8]]) -> Script {
+    let sig_pushes: Vec<Script> = sigs.iter().map(|sig| Builder::new().push_slice(sig).into_script()).collect();
+    let mut code = Vec::with_capacity(script.len());
+    let mut pos = 0;
+    for instruction in Script::from(script.to_vec()).iter(false) {
+        let len = instruction_len(&script[pos..], &instruction);
+        let encoded = &script[pos..pos + len];
+        pos += len;
+        let removed = match instruction {
+            Instruction::PushBytes(_) => sig_pushes.iter().any(|push| push.as_bytes() == encoded),
+            Instruction::Op(op) => op == opcodes::all::OP_CODESEPARATOR,
+            Instruction::Error(_) => false,
+        };
+        if !removed {
+            code.extend_from_slice(encoded);
+        }
+    }
+    Script::from(code)
+}
+
+/// Whether `data`, pushed by `opcode`, could not be pushed with fewer bytes
+fn is_minimal_push(data: &[u8], opcode: u8) -> bool {
+    match data.len() {
+        0 => opcode == opcodes::all::OP_PUSHBYTES_0.into_u8(),
+        // should be pushed by OP_PUSHNUM_1 to OP_PUSHNUM_16 or OP_PUSHNUM_NEG1
+        1 if (1..=16).contains(&data[0]) || data[0] == 0x81 => false,
+        len if len <= 75 => opcode as usize == len,
+        len if len <= 0xff => opcode == opcodes::all::OP_PUSHDATA1.into_u8(),
+        len if len <= 0xffff => opcode == opcodes::all::OP_PUSHDATA2.into_u8(),
+        _ => true,
+    }
+}
+
+/// Whether a number on the stack has no unnecessary leading zero byte
+fn is_minimal_num(v: &[u8]) -> bool {
+    match v.split_last() {
+        // a zero byte, or a sign byte alone, is only needed if the previous
+        // byte has its most significant bit set
+        Some((&last, rest)) if last & 0x7f == 0 => rest.last().map_or(false, |&byte| byte & 0x80 != 0),
+        _ => true,
+    }
+}
+
+/// Whether a public key is compressed or uncompressed
+fn is_valid_pubkey_encoding(pubkey: &[u8]) -> bool {
+    match pubkey.len() {
+        33 => pubkey[0] == 0x02 || pubkey[0] == 0x03,
+        65 => pubkey[0] == 0x04,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{self, Secp256k1};
+
+    use blockdata::locktime::{LockTime, Sequence, LOCK_TIME_THRESHOLD};
+    use blockdata::opcodes::{self, all};
+    use blockdata::script::{Builder, ColorIdentifier, Script};
+    use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
+    use consensus::encode::serialize;
+    use hash_types::{MalFixTxid, PubkeyHash};
+    use hashes::{sha256, Hash};
+    use util::key::PrivateKey;
+    use util::sighash::SighashCache;
+    use util::schnorr::SchnorrSignature;
+    use super::{verify_script, Error, Interpreter, VerifyFlags};
+
+    fn keys() -> Vec<PrivateKey> {
+        vec![
+            PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap(),
+            PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap(),
+            PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap(),
+        ]
+    }
+
+    fn p2pkh(key: &PrivateKey) -> Script {
+        let pubkey = key.public_key(&Secp256k1::new());
+        Script::new_p2pkh(&PubkeyHash::hash(&pubkey.to_bytes()))
+    }
+
+    fn spending_tx() -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(MalFixTxid::hash(b"funding"), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut { value: 1000, script_pubkey: Script::new() }],
+        }
+    }
+
+    /// Sign the first input of `tx` with a Schnorr or an ECDSA signature
+    fn sign(tx: &Transaction, script_code: &Script, key: &PrivateKey, schnorr: bool) -> Vec<u8> {
+        let sighash = SighashCache::new(tx).signature_hash(0, script_code, SigHashType::All.as_u32());
+        let mut sig = if schnorr {
+            serialize(&SchnorrSignature::sign(key, &sighash.into_inner()).unwrap())
+        } else {
+            let message = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+            Secp256k1::new().sign(&message, &key.key).serialize_der().to_vec()
+        };
+        sig.push(SigHashType::All.as_u32() as u8);
+        sig
+    }
+
+    fn run(script_sig: Script, script_pubkey: Script) -> Result<(), Error> {
+        run_with(VerifyFlags::CONSENSUS, script_sig, script_pubkey)
+    }
+
+    fn run_with(flags: VerifyFlags, script_sig: Script, script_pubkey: Script) -> Result<(), Error> {
+        let mut tx = spending_tx();
+        tx.input[0].script_sig = script_sig;
+        Interpreter::new(flags)?.verify(&tx, 0, &script_pubkey)
+    }
+
+    #[test]
+    fn test_opcodes() {
+        let ops = |codes: &[opcodes::All]| codes.iter().fold(Builder::new(), |builder, &op| builder.push_opcode(op));
+
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_2, all::OP_PUSHNUM_3, all::OP_ADD, all::OP_PUSHNUM_5, all::OP_EQUAL]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_3, all::OP_PUSHNUM_2, all::OP_PUSHNUM_5, all::OP_WITHIN]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_NEG1, all::OP_ABS, all::OP_PUSHNUM_1, all::OP_EQUAL]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_5, all::OP_PUSHNUM_6, all::OP_PUSHNUM_7, all::OP_PUSHNUM_2, all::OP_PICK, all::OP_PUSHNUM_5, all::OP_EQUAL]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[
+            all::OP_PUSHNUM_1, all::OP_PUSHNUM_2, all::OP_PUSHNUM_3, all::OP_ROT,
+            all::OP_PUSHNUM_1, all::OP_EQUALVERIFY, all::OP_PUSHNUM_3, all::OP_EQUALVERIFY, all::OP_PUSHNUM_2, all::OP_EQUAL,
+        ]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_1, all::OP_TOALTSTACK, all::OP_FROMALTSTACK]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_FROMALTSTACK]).into_script()), Err(Error::InvalidAltstackOperation));
+        assert_eq!(run(Script::new(), ops(&[all::OP_DROP]).into_script()), Err(Error::InvalidStackOperation));
+
+        // conditionals
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_1, all::OP_IF, all::OP_PUSHBYTES_0, all::OP_ELSE, all::OP_PUSHNUM_1, all::OP_ENDIF]).into_script()), Err(Error::EvalFalse));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHBYTES_0, all::OP_NOTIF, all::OP_PUSHNUM_1, all::OP_ENDIF]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_1, all::OP_ENDIF]).into_script()), Err(Error::UnbalancedConditional));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHNUM_1, all::OP_IF]).into_script()), Err(Error::UnbalancedConditional));
+        // OP_RETURN only fails when executed, disabled opcodes always do
+        assert_eq!(run(Script::new(), ops(&[all::OP_RETURN]).into_script()), Err(Error::OpReturn));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHBYTES_0, all::OP_IF, all::OP_RETURN, all::OP_ENDIF, all::OP_PUSHNUM_1]).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), ops(&[all::OP_PUSHBYTES_0, all::OP_IF, all::OP_CAT, all::OP_ENDIF, all::OP_PUSHNUM_1]).into_script()), Err(Error::DisabledOpcode));
+
+        // numbers are at most 4 bytes long
+        assert_eq!(run(Script::new(), Builder::new().push_slice(&[1, 2, 3, 4]).push_opcode(all::OP_1ADD).into_script()), Ok(()));
+        assert_eq!(run(Script::new(), Builder::new().push_slice(&[1, 2, 3, 4, 5]).push_opcode(all::OP_1ADD).into_script()), Err(Error::NumericOverflow));
+
+        let data = b"tapyrus";
+        let hash_lock = Builder::new()
+            .push_opcode(all::OP_SHA256)
+            .push_slice(&sha256::Hash::hash(data)[..])
+            .push_opcode(all::OP_EQUAL)
+            .into_script();
+        assert_eq!(run(Builder::new().push_slice(data).into_script(), hash_lock.clone()), Ok(()));
+        assert_eq!(run(Builder::new().push_slice(b"bitcoin").into_script(), hash_lock), Err(Error::EvalFalse));
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let secp = Secp256k1::new();
+        let keys = keys();
+        let script_pubkey = p2pkh(&keys[0]);
+        for &schnorr in &[false, true] {
+            let mut tx = spending_tx();
+            let sig = sign(&tx, &script_pubkey, &keys[0], schnorr);
+            assert_eq!(sig.len() == 65, schnorr);
+            tx.input[0].script_sig = Builder::new().push_slice(&sig).push_key(&keys[0].public_key(&secp)).into_script();
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Ok(()));
+
+            // the public key does not match the script_pubkey
+            tx.input[0].script_sig = Builder::new().push_slice(&sig).push_key(&keys[1].public_key(&secp)).into_script();
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::EqualVerify));
+
+            // the signature does not sign the transaction
+            tx.input[0].script_sig = Builder::new().push_slice(&sig).push_key(&keys[0].public_key(&secp)).into_script();
+            tx.output[0].value += 1;
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::EvalFalse));
+        }
+
+        // ECDSA signatures must be strictly DER encoded
+        let mut tx = spending_tx();
+        let mut sig = sign(&tx, &script_pubkey, &keys[0], false);
+        sig[0] = 0x31;
+        tx.input[0].script_sig = Builder::new().push_slice(&sig).push_key(&keys[0].public_key(&secp)).into_script();
+        assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::SigDer));
+    }
+
+    #[test]
+    fn test_p2sh_multisig() {
+        let secp = Secp256k1::new();
+        let keys = keys();
+        let redeem_script = keys.iter()
+            .fold(Builder::new().push_int(2), |builder, key| builder.push_key(&key.public_key(&secp)))
+            .push_int(3)
+            .push_opcode(all::OP_CHECKMULTISIG)
+            .into_script();
+        let script_pubkey = redeem_script.to_p2sh();
+        let script_sig = |sigs: &[&[u8]]| {
+            sigs.iter()
+                .fold(Builder::new().push_int(0), |builder, sig| builder.push_slice(sig))
+                .push_slice(redeem_script.as_bytes())
+                .into_script()
+        };
+
+        for &schnorr in &[false, true] {
+            let mut tx = spending_tx();
+            let first = sign(&tx, &redeem_script, &keys[0], schnorr);
+            let third = sign(&tx, &redeem_script, &keys[2], schnorr);
+            tx.input[0].script_sig = script_sig(&[&first[..], &third[..]]);
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Ok(()));
+
+            // signatures are in the order of the public keys
+            tx.input[0].script_sig = script_sig(&[&third[..], &first[..]]);
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::EvalFalse));
+
+            // the extra element is required
+            tx.input[0].script_sig = Builder::new()
+                .push_slice(&first)
+                .push_slice(&third)
+                .push_slice(redeem_script.as_bytes())
+                .into_script();
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::InvalidStackOperation));
+        }
+
+        let mut tx = spending_tx();
+        let first = sign(&tx, &redeem_script, &keys[0], true);
+        let third = sign(&tx, &redeem_script, &keys[2], false);
+        tx.input[0].script_sig = script_sig(&[&first[..], &third[..]]);
+        assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::MixedSignatureTypes));
+
+        // the script_sig only pushes data
+        tx.input[0].script_sig = Builder::new()
+            .push_int(0)
+            .push_opcode(all::OP_NOP)
+            .push_slice(redeem_script.as_bytes())
+            .into_script();
+        assert_eq!(verify_script(&tx, 0, &script_pubkey), Err(Error::SigPushOnly));
+    }
+
+    #[test]
+    fn test_op_color() {
+        let secp = Secp256k1::new();
+        let keys = keys();
+        let color = ColorIdentifier::reissuable(p2pkh(&keys[1]));
+        let script_pubkey = p2pkh(&keys[0]).add_color(color.clone()).unwrap();
+        for &schnorr in &[false, true] {
+            let mut tx = spending_tx();
+            let sig = sign(&tx, &script_pubkey, &keys[0], schnorr);
+            tx.input[0].script_sig = Builder::new().push_slice(&sig).push_key(&keys[0].public_key(&secp)).into_script();
+            assert_eq!(verify_script(&tx, 0, &script_pubkey), Ok(()));
+        }
+
+        let push_color = || Builder::new().push_slice(&serialize(&color));
+        assert_eq!(run(Script::new(), push_color().push_opcode(all::OP_COLOR).push_int(1).into_script()), Ok(()));
+        assert_eq!(
+            run(Script::new(), push_color().push_opcode(all::OP_COLOR).push_slice(&serialize(&color)).push_opcode(all::OP_COLOR).push_int(1).into_script()),
+            Err(Error::ColorMultiple)
+        );
+        let mut invalid_color = serialize(&color);
+        invalid_color[0] = 0xc4;
+        assert_eq!(
+            run(Script::new(), Builder::new().push_slice(&invalid_color).push_opcode(all::OP_COLOR).push_int(1).into_script()),
+            Err(Error::ColorId)
+        );
+        assert_eq!(
+            run(Script::new(), Builder::new().push_slice(&invalid_color[..32]).push_opcode(all::OP_COLOR).push_int(1).into_script()),
+            Err(Error::ColorId)
+        );
+        assert_eq!(run(Script::new(), Builder::new().push_opcode(all::OP_COLOR).into_script()), Err(Error::InvalidStackOperation));
+        assert_eq!(
+            run(Script::new(), Builder::new().push_int(1).push_opcode(all::OP_IF).push_slice(&serialize(&color)).push_opcode(all::OP_COLOR).push_opcode(all::OP_ENDIF).push_int(1).into_script()),
+            Err(Error::ColorInBranch)
+        );
+
+        // OP_COLOR only colors script_pubkeys
+        assert_eq!(
+            run(push_color().push_opcode(all::OP_COLOR).into_script(), Builder::new().push_int(1).into_script()),
+            Err(Error::ColorUnexpected)
+        );
+        let redeem_script = push_color().push_opcode(all::OP_COLOR).push_int(1).into_script();
+        assert_eq!(
+            run(Builder::new().push_slice(redeem_script.as_bytes()).into_script(), redeem_script.to_p2sh()),
+            Err(Error::ColorUnexpected)
+        );
+    }
+
+    #[test]
+    fn test_lock_time() {
+        let cltv = Builder::new()
+            .push_int(100)
+            .push_opcode(all::OP_CLTV)
+            .push_opcode(all::OP_DROP)
+            .push_int(1)
+            .into_script();
+        let mut tx = spending_tx();
+        tx.input[0].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+        tx.lock_time = LockTime::Blocks(100);
+        assert_eq!(verify_script(&tx, 0, &cltv), Ok(()));
+        tx.lock_time = LockTime::Blocks(99);
+        assert_eq!(verify_script(&tx, 0, &cltv), Err(Error::UnsatisfiedLockTime));
+        tx.lock_time = LockTime::Seconds(LOCK_TIME_THRESHOLD + 100);
+        assert_eq!(verify_script(&tx, 0, &cltv), Err(Error::UnsatisfiedLockTime));
+        // final inputs disable the lock time
+        tx.lock_time = LockTime::Blocks(100);
+        tx.input[0].sequence = Sequence::MAX;
+        assert_eq!(verify_script(&tx, 0, &cltv), Err(Error::UnsatisfiedLockTime));
+        let negative = Builder::new().push_int(-1).push_opcode(all::OP_CLTV).into_script();
+        assert_eq!(verify_script(&tx, 0, &negative), Err(Error::NegativeLockTime));
+
+        let csv = Builder::new()
+            .push_int(10)
+            .push_opcode(all::OP_CSV)
+            .push_opcode(all::OP_DROP)
+            .push_int(1)
+            .into_script();
+        let mut tx = spending_tx();
+        tx.version = 2;
+        tx.input[0].sequence = Sequence::from_height(10);
+        assert_eq!(verify_script(&tx, 0, &csv), Ok(()));
+        tx.input[0].sequence = Sequence::from_height(9);
+        assert_eq!(verify_script(&tx, 0, &csv), Err(Error::UnsatisfiedLockTime));
+        tx.input[0].sequence = Sequence::from_512_second_intervals(10);
+        assert_eq!(verify_script(&tx, 0, &csv), Err(Error::UnsatisfiedLockTime));
+        // relative lock times need version 2 transactions
+        tx.version = 1;
+        tx.input[0].sequence = Sequence::from_height(10);
+        assert_eq!(verify_script(&tx, 0, &csv), Err(Error::UnsatisfiedLockTime));
+        // the disable flag makes OP_CHECKSEQUENCEVERIFY a no-op
+        let disabled = Builder::new()
+            .push_int(1 << 31)
+            .push_opcode(all::OP_CSV)
+            .into_script();
+        assert_eq!(verify_script(&tx, 0, &disabled), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_flags() {
+        let mut flags = VerifyFlags::NONE;
+        assert_eq!(flags.add(VerifyFlags::P2SH), VerifyFlags::P2SH);
+        flags |= VerifyFlags::DERSIG | VerifyFlags::CHECKLOCKTIMEVERIFY | VerifyFlags::CHECKSEQUENCEVERIFY;
+        assert_eq!(flags, VerifyFlags::CONSENSUS);
+        assert_eq!(VerifyFlags::default(), VerifyFlags::CONSENSUS);
+        assert!(VerifyFlags::STANDARD.has(VerifyFlags::CONSENSUS));
+        assert!(!VerifyFlags::CONSENSUS.has(VerifyFlags::STANDARD));
+        assert_eq!(flags.remove(VerifyFlags::DERSIG).as_u32(), 0x601);
+        // removing a missing flag keeps the others
+        assert_eq!(flags.remove(VerifyFlags::DERSIG).as_u32(), 0x601);
+        assert_eq!(VerifyFlags::from(0x601), flags);
+        assert_eq!(Interpreter::default().flags(), VerifyFlags::CONSENSUS);
+    }
+
+    #[test]
+    fn test_policy_flags() {
+        let ops = |codes: &[opcodes::All]| codes.iter().fold(Builder::new(), |builder, &op| builder.push_opcode(op));
+        let consensus = VerifyFlags::CONSENSUS;
+        let standard = VerifyFlags::STANDARD;
+
+        // upgradable NOPs
+        let nop1 = ops(&[all::OP_PUSHNUM_1, all::OP_NOP1]).into_script();
+        assert_eq!(run_with(consensus, Script::new(), nop1.clone()), Ok(()));
+        assert_eq!(run_with(standard, Script::new(), nop1), Err(Error::DiscourageUpgradableNops));
+        assert_eq!(run_with(standard, Script::new(), ops(&[all::OP_PUSHNUM_1, all::OP_NOP]).into_script()), Ok(()));
+        // OP_CHECKLOCKTIMEVERIFY is an upgradable NOP without its flag
+        let cltv = Builder::new().push_int(100).push_opcode(all::OP_CLTV).into_script();
+        assert_eq!(run_with(consensus, Script::new(), cltv.clone()), Err(Error::UnsatisfiedLockTime));
+        assert_eq!(run_with(VerifyFlags::NONE, Script::new(), cltv.clone()), Ok(()));
+        assert_eq!(run_with(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS, Script::new(), cltv), Err(Error::DiscourageUpgradableNops));
+
+        // minimal pushes and numbers
+        let push_five = Builder::new().push_slice(&[5]).push_opcode(all::OP_PUSHNUM_5).push_opcode(all::OP_EQUAL).into_script();
+        assert_eq!(run_with(consensus, Script::new(), push_five.clone()), Ok(()));
+        assert_eq!(run_with(standard, Script::new(), push_five), Err(Error::MinimalData));
+        let padded_one = Builder::new().push_slice(&[1, 0]).push_opcode(all::OP_1ADD).push_opcode(all::OP_PUSHNUM_2).push_opcode(all::OP_EQUAL).into_script();
+        assert_eq!(run_with(consensus, Script::new(), padded_one.clone()), Ok(()));
+        assert_eq!(run_with(standard, Script::new(), padded_one), Err(Error::MinimalData));
+        let negative_zero = Builder::new().push_slice(&[0xff, 0x80]).push_opcode(all::OP_1ADD).into_script();
+        assert_eq!(run_with(standard, Script::new(), negative_zero), Ok(()));
+
+        // clean stack
+        let two = ops(&[all::OP_PUSHNUM_1, all::OP_PUSHNUM_1]).into_script();
+        assert_eq!(run_with(consensus, Script::new(), two.clone()), Ok(()));
+        assert_eq!(run_with(standard, Script::new(), two), Err(Error::CleanStack));
+        assert_eq!(Interpreter::new(VerifyFlags::CLEANSTACK).err(), Some(Error::InvalidFlags));
+        assert!(Interpreter::new(VerifyFlags::CLEANSTACK | VerifyFlags::P2SH).is_ok());
+
+        // push only script_sigs, even for non-P2SH outputs
+        let script_sig = ops(&[all::OP_PUSHNUM_1, all::OP_NOP]).into_script();
+        assert_eq!(run_with(consensus, script_sig.clone(), Script::new()), Ok(()));
+        assert_eq!(run_with(VerifyFlags::SIGPUSHONLY, script_sig, Script::new()), Err(Error::SigPushOnly));
+
+        // redeem scripts are only evaluated under P2SH
+        let redeem_script = ops(&[all::OP_PUSHBYTES_0]).into_script();
+        let script_sig = Builder::new().push_slice(redeem_script.as_bytes()).into_script();
+        assert_eq!(run_with(consensus, script_sig.clone(), redeem_script.to_p2sh()), Err(Error::EvalFalse));
+        assert_eq!(run_with(VerifyFlags::NONE, script_sig, redeem_script.to_p2sh()), Ok(()));
+    }
+
+    #[test]
+    fn test_signature_flags() {
+        let secp = Secp256k1::new();
+        let keys = keys();
+        let pubkey = keys[0].public_key(&secp);
+        let p2pk = Builder::new().push_key(&pubkey).push_opcode(all::OP_CHECKSIG).into_script();
+        let consensus = VerifyFlags::CONSENSUS;
+        let standard = VerifyFlags::STANDARD;
+
+        // undefined sighash types
+        for &schnorr in &[false, true] {
+            let mut sig = sign(&spending_tx(), &p2pk, &keys[0], schnorr);
+            *sig.last_mut().unwrap() = 0x05;
+            let script_sig = Builder::new().push_slice(&sig).into_script();
+            assert_eq!(run_with(consensus, script_sig.clone(), p2pk.clone()), Err(Error::EvalFalse));
+            assert_eq!(run_with(VerifyFlags::STRICTENC, script_sig, p2pk.clone()), Err(Error::SigHashType));
+        }
+
+        // high S values
+        let sig = sign(&spending_tx(), &p2pk, &keys[0], false);
+        let mut compact = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap().serialize_compact();
+        let order = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+            0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+        ];
+        let mut borrow = 0i16;
+        for (s, &n) in compact[32..].iter_mut().zip(order.iter()).rev() {
+            let diff = i16::from(n) - i16::from(*s) - borrow;
+            borrow = if diff < 0 { 1 } else { 0 };
+            *s = (diff + (borrow << 8)) as u8;
+        }
+        let mut high_s = secp256k1::Signature::from_compact(&compact).unwrap().serialize_der().to_vec();
+        high_s.push(SigHashType::All.as_u32() as u8);
+        let script_sig = Builder::new().push_slice(&high_s).into_script();
+        assert_eq!(run_with(consensus, script_sig.clone(), p2pk.clone()), Ok(()));
+        assert_eq!(run_with(VerifyFlags::LOW_S, script_sig, p2pk.clone()), Err(Error::SigHighS));
+
+        // public key encodings
+        let mut hybrid = pubkey.to_bytes();
+        hybrid[0] = 0x05;
+        let not_hybrid = Builder::new().push_slice(&hybrid).push_opcode(all::OP_CHECKSIG).push_opcode(all::OP_NOT).into_script();
+        let empty_sig = Builder::new().push_int(0).into_script();
+        assert_eq!(run_with(consensus, empty_sig.clone(), not_hybrid.clone()), Ok(()));
+        assert_eq!(run_with(VerifyFlags::STRICTENC, empty_sig, not_hybrid), Err(Error::PubkeyType));
+
+        // failed signature checks need empty signatures
+        let not_p2pk = Builder::new().push_key(&pubkey).push_opcode(all::OP_CHECKSIG).push_opcode(all::OP_NOT).into_script();
+        let wrong_sig = Builder::new().push_slice(&sign(&spending_tx(), &not_p2pk, &keys[1], false)).into_script();
+        assert_eq!(run_with(consensus, wrong_sig.clone(), not_p2pk.clone()), Ok(()));
+        assert_eq!(run_with(standard, wrong_sig, not_p2pk.clone()), Err(Error::NullFail));
+        assert_eq!(run_with(standard, Builder::new().push_int(0).into_script(), not_p2pk), Ok(()));
+
+        // the extra element of multisigs must be empty
+        let multisig = Builder::new()
+            .push_int(1)
+            .push_key(&pubkey)
+            .push_int(1)
+            .push_opcode(all::OP_CHECKMULTISIG)
+            .into_script();
+        let sig = sign(&spending_tx(), &multisig, &keys[0], true);
+        let script_sig = Builder::new().push_int(1).push_slice(&sig).into_script();
+        assert_eq!(run_with(consensus, script_sig, multisig.clone()), Ok(()));
+        let script_sig = Builder::new().push_int(1).push_slice(&sig).into_script();
+        assert_eq!(run_with(VerifyFlags::NULLDUMMY, script_sig, multisig.clone()), Err(Error::SigNullDummy));
+        let script_sig = Builder::new().push_int(0).push_slice(&sig).into_script();
+        assert_eq!(run_with(standard, script_sig, multisig), Ok(()));
+    }
+
+    #[test]
+    fn test_checkdatasig() {
+        let secp = Secp256k1::new();
+        let keys = keys();
+        let pubkey = keys[0].public_key(&secp);
+        let message = b"tapyrus";
+        let hash = sha256::Hash::hash(message);
+        let schnorr = serialize(&SchnorrSignature::sign(&keys[0], &hash.into_inner()).unwrap());
+        let ecdsa = secp.sign(&secp256k1::Message::from_slice(&hash[..]).unwrap(), &keys[0].key).serialize_der().to_vec();
+
+        let checkdatasig = Builder::new().push_key(&pubkey).push_opcode(all::OP_CHECKDATASIG).into_script();
+        let checkdatasigverify = Builder::new()
+            .push_key(&pubkey)
+            .push_opcode(all::OP_CHECKDATASIGVERIFY)
+            .push_int(1)
+            .into_script();
+        for sig in &[&schnorr, &ecdsa] {
+            let script_sig = Builder::new().push_slice(sig).push_slice(message).into_script();
+            assert_eq!(run_with(VerifyFlags::STANDARD, script_sig.clone(), checkdatasig.clone()), Ok(()));
+            assert_eq!(run_with(VerifyFlags::STANDARD, script_sig, checkdatasigverify.clone()), Ok(()));
+
+            // the signature is of another message
+            let script_sig = Builder::new().push_slice(sig).push_slice(b"bitcoin").into_script();
+            assert_eq!(run(script_sig.clone(), checkdatasig.clone()), Err(Error::EvalFalse));
+            assert_eq!(run(script_sig.clone(), checkdatasigverify.clone()), Err(Error::CheckDataSigVerify));
+            assert_eq!(run_with(VerifyFlags::STANDARD, script_sig, checkdatasig.clone()), Err(Error::NullFail));
+        }
+        // data signatures have no sighash type
+        let mut with_type = ecdsa.clone();
+        with_type.push(SigHashType::All.as_u32() as u8);
+        let script_sig = Builder::new().push_slice(&with_type).push_slice(message).into_script();
+        assert_eq!(run(script_sig, checkdatasig), Err(Error::SigDer));
+    }
+
+    /// Vectors in the format of Tapyrus Core's script_tests.json: script_sig,
+    /// script_pubkey, flags and expected result, with the asm of `from_asm`
+    #[test]
+    fn test_checkdatasig_vectors() {
+        const G: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let schnorr_zero = "00".repeat(64);
+        let vectors: Vec<(String, &str, &str, Result<(), Error>)> = vec![
+            ("".to_owned(), "CHECKDATASIG", "", Err(Error::InvalidStackOperation)),
+            ("0".to_owned(), "0 CHECKDATASIG", "", Err(Error::InvalidStackOperation)),
+            ("0 0 0".to_owned(), "CHECKDATASIG", "", Err(Error::EvalFalse)),
+            ("0 0 0".to_owned(), "CHECKDATASIG NOT", "", Ok(())),
+            ("0 0 0".to_owned(), "CHECKDATASIG NOT", "STRICTENC", Err(Error::PubkeyType)),
+            (format!("0 0 {}", G), "CHECKDATASIG NOT", "STRICTENC", Ok(())),
+            (format!("0 0 {}", G), "CHECKDATASIGVERIFY 1", "", Err(Error::CheckDataSigVerify)),
+            (format!("300602010102010101 0 {}", G), "CHECKDATASIG NOT", "", Ok(())),
+            (format!("300602010102010101 0 {}", G), "CHECKDATASIG NOT", "NULLFAIL", Err(Error::NullFail)),
+            (format!("310602010102010101 0 {}", G), "CHECKDATASIG NOT", "", Ok(())),
+            (format!("310602010102010101 0 {}", G), "CHECKDATASIG NOT", "DERSIG", Err(Error::SigDer)),
+            (format!("{} 0 {}", schnorr_zero, G), "CHECKDATASIG NOT", "DERSIG,LOW_S,STRICTENC", Ok(())),
+            (format!("{} 0 {}", schnorr_zero, G), "CHECKDATASIG NOT", "NULLFAIL", Err(Error::NullFail)),
+        ];
+        let parse_flags = |names: &str| names.split(',').filter(|name| !name.is_empty()).fold(VerifyFlags::NONE, |flags, name| {
+            flags | match name {
+                "STRICTENC" => VerifyFlags::STRICTENC,
+                "DERSIG" => VerifyFlags::DERSIG,
+                "LOW_S" => VerifyFlags::LOW_S,
+                "NULLFAIL" => VerifyFlags::NULLFAIL,
+                _ => panic!("unknown flag {}", name),
+            }
+        });
+        for (script_sig, script_pubkey, flags, expected) in vectors {
+            let result = run_with(parse_flags(flags), Script::from_asm(&script_sig).unwrap(), Script::from_asm(script_pubkey).unwrap());
+            assert_eq!(result, expected, "{} | {} | {}", script_sig, script_pubkey, flags);
+        }
+    }
+}
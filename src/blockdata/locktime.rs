@@ -0,0 +1,410 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Lock times and sequence numbers
+//!
+//! A transaction's lock time is either a block height or a UNIX timestamp,
+//! depending on whether it is below `LOCK_TIME_THRESHOLD`. `LockTime` keeps
+//! the two apart, so that heights are never compared with timestamps.
+//!
+//! The sequence number of an input signals replaceability and, per BIP68,
+//! encodes a relative lock time. `Sequence` decodes these.
+//!
+
+use core::cmp::Ordering;
+use core::fmt;
+use io;
+
+use consensus::encode::{self, Decodable, Encodable};
+
+/// Lock times below this value are block heights, the others UNIX timestamps
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// An absolute lock time, the block height or time from which a transaction
+/// may be included in a block
+///
+/// Lock times of different units are not ordered: `partial_cmp` returns
+/// `None` when comparing a height with a time.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LockTime {
+    /// A block height, below `LOCK_TIME_THRESHOLD`
+    Blocks(u32),
+    /// A UNIX timestamp, at or above `LOCK_TIME_THRESHOLD`
+    Seconds(u32),
+}
+
+impl LockTime {
+    /// The lock time of 0, which does not lock transactions
+    pub const ZERO: LockTime = LockTime::Blocks(0);
+
+    /// Decode a lock time as found in transactions
+    pub fn from_consensus(n: u32) -> LockTime {
+        if n < LOCK_TIME_THRESHOLD {
+            LockTime::Blocks(n)
+        } else {
+            LockTime::Seconds(n)
+        }
+    }
+
+    /// A lock time at block `height`. Returns `None` if `height` is not
+    /// below `LOCK_TIME_THRESHOLD`.
+    pub fn from_height(height: u32) -> Option<LockTime> {
+        if height < LOCK_TIME_THRESHOLD {
+            Some(LockTime::Blocks(height))
+        } else {
+            None
+        }
+    }
+
+    /// A lock time at UNIX timestamp `time`. Returns `None` if `time` is
+    /// below `LOCK_TIME_THRESHOLD`.
+    pub fn from_time(time: u32) -> Option<LockTime> {
+        if time >= LOCK_TIME_THRESHOLD {
+            Some(LockTime::Seconds(time))
+        } else {
+            None
+        }
+    }
+
+    /// The lock time as found in transactions
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Blocks(n) | LockTime::Seconds(n) => n,
+        }
+    }
+
+    /// Whether this lock time is a block height
+    pub fn is_block_height(self) -> bool {
+        match self {
+            LockTime::Blocks(_) => true,
+            LockTime::Seconds(_) => false,
+        }
+    }
+
+    /// Whether this lock time is a UNIX timestamp
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether both lock times are heights, or both are timestamps
+    pub fn is_same_unit(self, other: LockTime) -> bool {
+        self.is_block_height() == other.is_block_height()
+    }
+
+    /// Whether a transaction locked until this lock time may be included
+    /// in the block at `height`, whose previous blocks have the median time
+    /// `time`
+    pub fn is_satisfied_by(self, height: u32, time: u32) -> bool {
+        match self {
+            LockTime::Blocks(n) => n < height,
+            LockTime::Seconds(n) => n < time,
+        }
+    }
+
+    /// Whether a transaction with lock time `other` satisfies a
+    /// `CHECKLOCKTIMEVERIFY` for this lock time: both are of the same unit
+    /// and `other` is not earlier.
+    pub fn is_implied_by(self, other: LockTime) -> bool {
+        self.is_same_unit(other) && self.to_consensus_u32() <= other.to_consensus_u32()
+    }
+}
+
+impl PartialOrd for LockTime {
+    fn partial_cmp(&self, other: &LockTime) -> Option<Ordering> {
+        match (*self, *other) {
+            (LockTime::Blocks(a), LockTime::Blocks(b)) | (LockTime::Seconds(a), LockTime::Seconds(b)) => a.partial_cmp(&b),
+            _ => None,
+        }
+    }
+}
+
+impl Default for LockTime {
+    fn default() -> LockTime {
+        LockTime::ZERO
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockTime::Blocks(n) => write!(f, "block {}", n),
+            LockTime::Seconds(n) => write!(f, "time {}", n),
+        }
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(n: u32) -> LockTime {
+        LockTime::from_consensus(n)
+    }
+}
+
+impl From<LockTime> for u32 {
+    fn from(lock_time: LockTime) -> u32 {
+        lock_time.to_consensus_u32()
+    }
+}
+
+impl Encodable for LockTime {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.to_consensus_u32().consensus_encode(s)
+    }
+}
+
+impl Decodable for LockTime {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        u32::consensus_decode(d).map(LockTime::from_consensus)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for LockTime {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ::serde::Serialize::serialize(&self.to_consensus_u32(), s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for LockTime {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<LockTime, D::Error> {
+        <u32 as ::serde::Deserialize>::deserialize(d).map(LockTime::from_consensus)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for LockTime {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<LockTime> {
+        Ok(LockTime::from_consensus(u.arbitrary()?))
+    }
+}
+
+/// A relative lock time, decoded from a sequence number per BIP68
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum RelativeLockTime {
+    /// A number of blocks
+    Blocks(u16),
+    /// A number of 512-second intervals
+    Intervals(u16),
+}
+
+impl RelativeLockTime {
+    /// Whether an input spending an output confirmed `blocks` blocks and
+    /// `seconds` seconds of median time ago satisfies this lock time
+    pub fn is_satisfied_by(self, blocks: u32, seconds: u32) -> bool {
+        match self {
+            RelativeLockTime::Blocks(n) => u32::from(n) <= blocks,
+            RelativeLockTime::Intervals(n) => u32::from(n) * 512 <= seconds,
+        }
+    }
+}
+
+/// The sequence number of a transaction input
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Sequence(pub u32);
+
+impl Sequence {
+    /// The final sequence number, which disables the lock time of the
+    /// transaction if all its inputs have it
+    pub const MAX: Sequence = Sequence(0xFFFFFFFF);
+    /// The highest sequence number enabling the lock time without
+    /// signaling replaceability
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xFFFFFFFE);
+    /// The highest sequence number signaling replaceability, without a
+    /// relative lock time
+    pub const ENABLE_RBF_NO_LOCKTIME: Sequence = Sequence(0xFFFFFFFD);
+    /// The sequence number of 0
+    pub const ZERO: Sequence = Sequence(0);
+
+    /// Set if the sequence number does not encode a relative lock time
+    const LOCK_TIME_DISABLE_FLAG: u32 = 1 << 31;
+    /// Set if the relative lock time is a number of 512-second intervals
+    const LOCK_TIME_TYPE_FLAG: u32 = 1 << 22;
+    /// The bits holding the relative lock time
+    const LOCK_TIME_MASK: u32 = 0x0000FFFF;
+
+    /// A sequence number with a relative lock time of `blocks` blocks
+    pub fn from_height(blocks: u16) -> Sequence {
+        Sequence(u32::from(blocks))
+    }
+
+    /// A sequence number with a relative lock time of `intervals` times
+    /// 512 seconds
+    pub fn from_512_second_intervals(intervals: u16) -> Sequence {
+        Sequence(u32::from(intervals) | Sequence::LOCK_TIME_TYPE_FLAG)
+    }
+
+    /// A sequence number with a relative lock time of at least `seconds`.
+    /// Returns `None` if it does not fit in 65535 intervals of 512 seconds.
+    pub fn from_seconds_ceil(seconds: u32) -> Option<Sequence> {
+        let intervals = seconds / 512 + if seconds % 512 == 0 { 0 } else { 1 };
+        if intervals > u32::from(u16::max_value()) {
+            return None;
+        }
+        Some(Sequence::from_512_second_intervals(intervals as u16))
+    }
+
+    /// The sequence number as found in transactions
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Whether this is the final sequence number
+    pub fn is_final(self) -> bool {
+        self == Sequence::MAX
+    }
+
+    /// Whether the input signals that its transaction may be replaced, per
+    /// BIP125
+    pub fn is_rbf(self) -> bool {
+        self < Sequence::ENABLE_LOCKTIME_NO_RBF
+    }
+
+    /// Whether the input lets the lock time of its transaction apply
+    pub fn enables_absolute_lock_time(self) -> bool {
+        !self.is_final()
+    }
+
+    /// Whether the sequence number encodes a relative lock time
+    pub fn is_relative_lock_time(self) -> bool {
+        self.0 & Sequence::LOCK_TIME_DISABLE_FLAG == 0
+    }
+
+    /// The relative lock time encoded by the sequence number, if any
+    pub fn to_relative_lock_time(self) -> Option<RelativeLockTime> {
+        if !self.is_relative_lock_time() {
+            return None;
+        }
+        let value = (self.0 & Sequence::LOCK_TIME_MASK) as u16;
+        if self.0 & Sequence::LOCK_TIME_TYPE_FLAG != 0 {
+            Some(RelativeLockTime::Intervals(value))
+        } else {
+            Some(RelativeLockTime::Blocks(value))
+        }
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence::MAX
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<u32> for Sequence {
+    fn from(n: u32) -> Sequence {
+        Sequence(n)
+    }
+}
+
+impl From<Sequence> for u32 {
+    fn from(sequence: Sequence) -> u32 {
+        sequence.0
+    }
+}
+
+impl Encodable for Sequence {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for Sequence {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        u32::consensus_decode(d).map(Sequence)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Sequence {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ::serde::Serialize::serialize(&self.0, s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Sequence {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<Sequence, D::Error> {
+        <u32 as ::serde::Deserialize>::deserialize(d).map(Sequence)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for Sequence {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<Sequence> {
+        Ok(Sequence(u.arbitrary()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use consensus::encode::{deserialize, serialize};
+    use super::{LockTime, RelativeLockTime, Sequence, LOCK_TIME_THRESHOLD};
+
+    #[test]
+    fn lock_time_test() {
+        assert_eq!(LockTime::from_consensus(100), LockTime::Blocks(100));
+        assert_eq!(LockTime::from(LOCK_TIME_THRESHOLD), LockTime::Seconds(LOCK_TIME_THRESHOLD));
+        assert_eq!(LockTime::from_height(LOCK_TIME_THRESHOLD), None);
+        assert_eq!(LockTime::from_time(100), None);
+        assert_eq!(u32::from(LockTime::Seconds(1_600_000_000)), 1_600_000_000);
+        assert!(LockTime::ZERO.is_block_height());
+        assert!(LockTime::Seconds(1_600_000_000).is_block_time());
+
+        let height = LockTime::Blocks(100);
+        let time = LockTime::Seconds(1_600_000_000);
+        assert!(height < LockTime::Blocks(101));
+        assert_eq!(height.partial_cmp(&time), None);
+        assert!(!height.is_same_unit(time));
+        assert!(height.is_implied_by(LockTime::Blocks(100)));
+        assert!(!height.is_implied_by(LockTime::Blocks(99)));
+        assert!(!height.is_implied_by(time));
+
+        assert!(height.is_satisfied_by(101, 0));
+        assert!(!height.is_satisfied_by(100, u32::max_value()));
+        assert!(time.is_satisfied_by(0, 1_600_000_001));
+        assert!(!time.is_satisfied_by(u32::max_value(), 1_600_000_000));
+
+        assert_eq!(serialize(&time), serialize(&1_600_000_000u32));
+        assert_eq!(deserialize::<LockTime>(&serialize(&time)).unwrap(), time);
+        assert_eq!(time.to_string(), "time 1600000000");
+    }
+
+    #[test]
+    fn sequence_test() {
+        assert!(Sequence::MAX.is_final());
+        assert!(!Sequence::MAX.is_rbf());
+        assert!(!Sequence::MAX.enables_absolute_lock_time());
+        assert!(!Sequence::ENABLE_LOCKTIME_NO_RBF.is_rbf());
+        assert!(Sequence::ENABLE_LOCKTIME_NO_RBF.enables_absolute_lock_time());
+        assert!(Sequence::ENABLE_RBF_NO_LOCKTIME.is_rbf());
+        assert_eq!(Sequence::ENABLE_RBF_NO_LOCKTIME.to_relative_lock_time(), None);
+        assert_eq!(Sequence::default(), Sequence::MAX);
+
+        assert_eq!(Sequence::from_height(144).to_relative_lock_time(), Some(RelativeLockTime::Blocks(144)));
+        assert_eq!(Sequence::from_512_second_intervals(10), Sequence(0x0040000a));
+        assert_eq!(Sequence::from_seconds_ceil(5121), Some(Sequence::from_512_second_intervals(11)));
+        assert_eq!(Sequence::from_seconds_ceil(5120), Some(Sequence::from_512_second_intervals(10)));
+        assert_eq!(Sequence::from_seconds_ceil(512 * 65536), None);
+        // bits outside the flags and the mask are ignored
+        assert_eq!(Sequence(0x0040ff0a).to_relative_lock_time(), Some(RelativeLockTime::Intervals(0xff0a)));
+
+        assert!(RelativeLockTime::Blocks(144).is_satisfied_by(144, 0));
+        assert!(!RelativeLockTime::Blocks(144).is_satisfied_by(143, u32::max_value()));
+        assert!(RelativeLockTime::Intervals(10).is_satisfied_by(0, 5120));
+        assert!(!RelativeLockTime::Intervals(10).is_satisfied_by(0, 5119));
+
+        assert_eq!(serialize(&Sequence(0xfffffffd)), vec![0xfd, 0xff, 0xff, 0xff]);
+        assert_eq!(deserialize::<Sequence>(&[0xfd, 0xff, 0xff, 0xff]).unwrap(), Sequence::ENABLE_RBF_NO_LOCKTIME);
+        assert_eq!(u32::from(Sequence::ZERO), 0);
+    }
+}
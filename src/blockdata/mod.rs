@@ -18,8 +18,11 @@
 //! transactions which make up the Bitcoin system.
 //!
 
+#[cfg(feature = "std")] pub mod aggkey;
 pub mod block;
 pub mod constants;
+pub mod interpreter;
+pub mod locktime;
 pub mod opcodes;
 pub mod script;
 pub mod transaction;
@@ -22,7 +22,7 @@
 
 #[cfg(feature = "serde")] use serde;
 
-use std::fmt;
+use core::fmt;
 
 // Note: I am deliberately not implementing PartialOrd or Ord on the
 //       opcode enum. If you want to check ranges of opcodes, etc.,
@@ -414,10 +414,11 @@ pub mod all {
     pub const OP_NOP9: All = All {code: 0xb8};
     /// Does nothing
     pub const OP_NOP10: All = All {code: 0xb9};
-    /// Synonym for OP_RETURN
-    pub const OP_RETURN_186: All = All {code: 0xba};
-    /// Synonym for OP_RETURN
-    pub const OP_RETURN_187: All = All {code: 0xbb};
+    /// Pop a public key, a message and a signature of the SHA256 hash of
+    /// the message; push 1 if the signature is valid, 0 otherwise
+    pub const OP_CHECKDATASIG: All = All {code: 0xba};
+    /// Same as OP_CHECKDATASIG, but fail the script rather than pushing 0
+    pub const OP_CHECKDATASIGVERIFY: All = All {code: 0xbb};
     /// Synonym for OP_RETURN
     pub const OP_COLOR: All = All {code: 0xbc};
     // Every other opcode acts as OP_RETURN
@@ -649,6 +650,8 @@ impl fmt::Debug for All {
             all::OP_CHECKMULTISIGVERIFY => write!(f, "CHECKMULTISIGVERIFY"),
             all::OP_CLTV => write!(f, "CLTV"),
             all::OP_CSV => write!(f, "CSV"),
+            all::OP_CHECKDATASIG => write!(f, "CHECKDATASIG"),
+            all::OP_CHECKDATASIGVERIFY => write!(f, "CHECKDATASIGVERIFY"),
             all::OP_COLOR => write!(f, "COLOR"),
             All {code: x} if x >= all::OP_NOP1.code && x <= all::OP_NOP10.code => write!(f, "NOP{}", x - all::OP_NOP1.code + 1),
             All {code: x} => write!(f, "RETURN_{}", x),
@@ -675,11 +678,9 @@ impl All {
                   (all::OP_NOP1.code <= self.code &&
                    self.code <= all::OP_NOP10.code) {
             Class::NoOp
-        // 74 opcodes
+        // 72 opcodes
         } else if *self == all::OP_RESERVED || *self == all::OP_VER || *self == all::OP_RETURN ||
                   *self == all::OP_RESERVED1 || *self == all::OP_RESERVED2 ||
-                  *self == all::OP_RETURN_186 ||
-                  *self == all::OP_RETURN_187 ||
                   self.code >= all::OP_RETURN_189.code {
             Class::ReturnOp
         // 1 opcode
@@ -692,7 +693,7 @@ impl All {
         // 76 opcodes
         } else if self.code <= all::OP_PUSHBYTES_75.code {
             Class::PushBytes(self.code as u32)
-        // 61 opcodes
+        // 63 opcodes
         } else {
             Class::Ordinary(Ordinary::try_from_all(*self).unwrap())
         }
@@ -807,6 +808,7 @@ ordinary_opcode! {
     OP_RIPEMD160, OP_SHA1, OP_SHA256, OP_HASH160, OP_HASH256,
     OP_CODESEPARATOR, OP_CHECKSIG, OP_CHECKSIGVERIFY,
     OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY,
+    OP_CHECKDATASIG, OP_CHECKDATASIGVERIFY,
     OP_COLOR
 }
 
@@ -1025,8 +1027,8 @@ mod tests {
         roundtrip!(unique, OP_NOP8);
         roundtrip!(unique, OP_NOP9);
         roundtrip!(unique, OP_NOP10);
-        roundtrip!(unique, OP_RETURN_186);
-        roundtrip!(unique, OP_RETURN_187);
+        roundtrip!(unique, OP_CHECKDATASIG);
+        roundtrip!(unique, OP_CHECKDATASIGVERIFY);
         roundtrip!(unique, OP_COLOR);
         roundtrip!(unique, OP_RETURN_189);
         roundtrip!(unique, OP_RETURN_190);
@@ -24,21 +24,27 @@
 //! This module provides the structures and functions needed to support scripts.
 //!
 
-use std::default::Default;
-use std::{error, fmt, io};
+use core::default::Default;
+use core::{cmp, fmt};
+#[cfg(feature = "std")] use std::error;
 
 #[cfg(feature = "serde")] use serde;
 
-use hash_types::{ScriptHash, WScriptHash};
+use hash_types::{PubkeyHash, ScriptHash, WScriptHash};
+use blockdata::interpreter;
+use blockdata::locktime::{LockTime, Sequence};
+pub use blockdata::interpreter::{Interpreter, VerifyFlags};
 use blockdata::opcodes;
 use blockdata::transaction::OutPoint;
-use consensus::{encode, Decodable, Encodable};
-use consensus::encode::serialize_hex;
+use consensus::{encode, serialize, Decodable, Encodable};
 use hashes::{sha256, Hash};
+#[cfg(feature = "std")] use hashes::hex::FromHex;
 #[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
-#[cfg(feature="bitcoinconsensus")] use std::convert;
+#[cfg(feature="bitcoinconsensus")] use core::convert;
+use io;
+use prelude::*;
 
-use util::key::PublicKey;
+use util::key::{self, PublicKey};
 
 #[derive(Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
 /// A Bitcoin script
@@ -47,7 +53,7 @@ pub struct Script(Box<[u8]>);
 impl fmt::Debug for Script {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str("Script(")?;
-        self.fmt_asm(f)?;
+        self.fmt_pushes(f)?;
         f.write_str(")")
     }
 }
@@ -76,6 +82,23 @@ impl fmt::UpperHex for Script {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for Script {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<Script> {
+        // Build the script from whole instructions so that it always parses
+        let mut builder = Builder::new();
+        for _ in 0..u.arbitrary_len::<u8>()? {
+            let op: u8 = u.arbitrary()?;
+            builder = if op <= opcodes::all::OP_PUSHDATA4.into_u8() {
+                builder.push_slice(&u.arbitrary::<Vec<u8>>()?)
+            } else {
+                builder.push_opcode(opcodes::All::from(op))
+            };
+        }
+        Ok(builder.into_script())
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 /// An object which can be used to construct a script piece by piece
 pub struct Builder(Vec<u8>, Option<opcodes::All>);
@@ -96,9 +119,20 @@ pub enum Error {
     #[cfg(feature="bitcoinconsensus")]
     /// Error validating the script with bitcoinconsensus library
     BitcoinConsensus(bitcoinconsensus::Error),
-    #[cfg(feature="bitcoinconsensus")]
     /// Can not find the spent output
     UnknownSpentOutput(OutPoint),
+    /// The transaction spends the same output twice
+    DuplicateInput(OutPoint),
+    /// Executing the scripts of the input at the given index failed
+    Execution(usize, interpreter::Error),
+    /// The verify flags are inconsistent, such as `CLEANSTACK` without `P2SH`
+    InvalidFlags,
+    /// The transaction creates tokens of the color without issuing them
+    TokenBalance(ColorIdentifier),
+    /// An OP_RETURN script of the given size exceeds `MAX_OP_RETURN_RELAY`
+    OpReturnSize(usize),
+    /// A multisig can not require this many signatures of this many keys
+    Multisig(usize, usize),
     #[cfg(feature="bitcoinconsensus")]
     /// Can not serialize the spending transaction
     SerializationError
@@ -106,24 +140,31 @@ pub enum Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(error::Error::description(self))
+        match *self {
+            Error::NonMinimalPush => f.write_str("non-minimal datapush"),
+            Error::EarlyEndOfScript => f.write_str("unexpected end of script"),
+            Error::NumericOverflow => f.write_str("numeric overflow (number on stack larger than 4 bytes)"),
+            #[cfg(feature="bitcoinconsensus")]
+            Error::BitcoinConsensus(ref _n) => f.write_str("bitcoinconsensus verification failed"),
+            Error::UnknownSpentOutput(ref _point) => f.write_str("unknown spent output Transaction::verify()"),
+            Error::DuplicateInput(ref _point) => f.write_str("output spent twice in Transaction::verify()"),
+            Error::Execution(index, ref e) => write!(f, "failed to execute the scripts of input {}: {}", index, e),
+            Error::InvalidFlags => f.write_str("inconsistent script verify flags"),
+            Error::TokenBalance(ref _color) => f.write_str("tokens created without issuance in Transaction::verify()"),
+            Error::OpReturnSize(size) => write!(f, "OP_RETURN script larger than relayed by default: {} bytes", size),
+            Error::Multisig(required, total) => write!(f, "invalid number of multisig keys: {} of {}", required, total),
+            #[cfg(feature="bitcoinconsensus")]
+            Error::SerializationError => f.write_str("can not serialize the spending transaction in Transaction::verify()"),
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> { None }
-
-    fn description(&self) -> &'static str {
+    fn cause(&self) -> Option<&error::Error> {
         match *self {
-            Error::NonMinimalPush => "non-minimal datapush",
-            Error::EarlyEndOfScript => "unexpected end of script",
-            Error::NumericOverflow => "numeric overflow (number on stack larger than 4 bytes)",
-            #[cfg(feature="bitcoinconsensus")]
-            Error::BitcoinConsensus(ref _n) => "bitcoinconsensus verification failed",
-            #[cfg(feature="bitcoinconsensus")]
-            Error::UnknownSpentOutput(ref _point) => "unknown spent output Transaction::verify()",
-            #[cfg(feature="bitcoinconsensus")]
-            Error::SerializationError => "can not serialize the spending transaction in Transaction::verify()",
+            Error::Execution(_, ref e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -137,8 +178,22 @@ impl convert::From<bitcoinconsensus::Error> for Error {
         }
     }
 }
+/// The maximum size of the OP_RETURN script pubkeys relayed by default, in
+/// bytes: `OP_RETURN` followed by the push of 80 bytes of data
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// The maximum number of keys `OP_CHECKMULTISIG` accepts
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// The maximum number of keys of a multisig script built by
+/// `Script::new_multisig`, as it must fit in a push of
+/// `MAX_SCRIPT_ELEMENT_SIZE` bytes to be a P2SH redeem script: 15 compressed
+/// keys make a script of 513 bytes, 16 of 547 bytes
+pub const MAX_P2SH_MULTISIG_KEYS: usize = 15;
+/// The maximum number of keys of a standard bare multisig
+pub const MAX_STANDARD_MULTISIG_KEYS: usize = 3;
+
 /// Helper to encode an integer in script format
-fn build_scriptint(n: i64) -> Vec<u8> {
+pub(crate) fn build_scriptint(n: i64) -> Vec<u8> {
     if n == 0 { return vec![] }
 
     let neg = n < 0;
@@ -178,9 +233,15 @@ fn build_scriptint(n: i64) -> Vec<u8> {
 /// simply say, anything in excess of 32 bits is no longer a number.
 /// This is basically a ranged type implementation.
 pub fn read_scriptint(v: &[u8]) -> Result<i64, Error> {
+    read_scriptint_of_len(v, 4)
+}
+
+/// Decode an integer in script format of up to `max_len` bytes, as lock
+/// times are read with 5 bytes
+pub(crate) fn read_scriptint_of_len(v: &[u8], max_len: usize) -> Result<i64, Error> {
     let len = v.len();
     if len == 0 { return Ok(0); }
-    if len > 4 { return Err(Error::NumericOverflow); }
+    if len > max_len { return Err(Error::NumericOverflow); }
 
     let (mut ret, sh) = v.iter()
                          .fold((0, 0), |(acc, sh), n| (acc + ((*n as i64) << sh), sh + 8));
@@ -220,6 +281,79 @@ pub enum ColoredCoinError {
     UnsuppotedScriptType,
 }
 
+/// The template of a script_pubkey, as classified by Tapyrus Core, along
+/// with the data it embeds
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ScriptType {
+    /// Pay to a public key
+    P2pk(PublicKey),
+    /// Pay to a public key hash
+    P2pkh(PubkeyHash),
+    /// Pay to a script hash
+    P2sh(ScriptHash),
+    /// Colored pay to a public key hash
+    Cp2pkh(ColorIdentifier, PubkeyHash),
+    /// Colored pay to a script hash
+    Cp2sh(ColorIdentifier, ScriptHash),
+    /// Bare multisig, requiring signatures of `required` of its keys
+    Multisig {
+        /// The number of signatures required
+        required: usize,
+        /// The public keys allowed to sign
+        keys: Vec<PublicKey>,
+    },
+    /// `OP_RETURN` followed by data pushes only
+    OpReturn,
+    /// Any other script
+    NonStandard,
+}
+
+impl ScriptType {
+    /// The public key hash of a P2PKH or CP2PKH script
+    pub fn pubkey_hash(&self) -> Option<PubkeyHash> {
+        match *self {
+            ScriptType::P2pkh(hash) | ScriptType::Cp2pkh(_, hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// The script hash of a P2SH or CP2SH script
+    pub fn script_hash(&self) -> Option<ScriptHash> {
+        match *self {
+            ScriptType::P2sh(hash) | ScriptType::Cp2sh(_, hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// The color identifier of a colored script
+    pub fn color_id(&self) -> Option<&ColorIdentifier> {
+        match *self {
+            ScriptType::Cp2pkh(ref color_id, _) | ScriptType::Cp2sh(ref color_id, _) => Some(color_id),
+            _ => None,
+        }
+    }
+
+    /// Whether the script is colored
+    pub fn is_colored(&self) -> bool {
+        self.color_id().is_some()
+    }
+}
+
+/// A hash time locked contract, paying to `receiver` on revealing the
+/// preimage of `payment_hash`, or back to `sender` after `lock_time`, as
+/// atomic swaps do
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Htlc {
+    /// The SHA256 hash of the secret revealed by the receiver
+    pub payment_hash: sha256::Hash,
+    /// The public key hash of the receiver
+    pub receiver: PubkeyHash,
+    /// The public key hash of the sender, refunded after `lock_time`
+    pub sender: PubkeyHash,
+    /// The lock time after which the sender is refunded
+    pub lock_time: LockTime,
+}
+
 impl Script {
     /// Creates a new empty script
     pub fn new() -> Script { Script(vec![].into_boxed_slice()) }
@@ -239,6 +373,17 @@ impl Script {
     /// Convert the script into a byte vector
     pub fn into_bytes(self) -> Vec<u8> { self.0.into_vec() }
 
+    /// Create a P2PKH script pubkey paying to `pubkey_hash`:
+    /// `OP_DUP OP_HASH160 <pubkey_hash> OP_EQUALVERIFY OP_CHECKSIG`
+    pub fn new_p2pkh(pubkey_hash: &PubkeyHash) -> Script {
+        Builder::new().push_opcode(opcodes::all::OP_DUP)
+                      .push_opcode(opcodes::all::OP_HASH160)
+                      .push_slice(&pubkey_hash[..])
+                      .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                      .push_opcode(opcodes::all::OP_CHECKSIG)
+                      .into_script()
+    }
+
     /// Compute the P2SH output corresponding to this redeem script
     pub fn to_p2sh(&self) -> Script {
         Builder::new().push_opcode(opcodes::all::OP_HASH160)
@@ -327,6 +472,44 @@ impl Script {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]) == opcodes::all::OP_RETURN)
     }
 
+    /// Create an OP_RETURN script pubkey carrying `data`, failing if it
+    /// would not be relayed by default
+    pub fn new_op_return(data: &[u8]) -> Result<Script, Error> {
+        Script::new_op_return_pushes(&[data])
+    }
+
+    /// Create an OP_RETURN script pubkey carrying each of `pushes`, failing
+    /// if it would not be relayed by default
+    pub fn new_op_return_pushes(pushes: &[&[u8]]) -> Result<Script, Error> {
+        let script = pushes.iter()
+            .fold(Builder::new().push_opcode(opcodes::all::OP_RETURN), |builder, data| builder.push_slice(data))
+            .into_script();
+        if script.len() > MAX_OP_RETURN_RELAY {
+            return Err(Error::OpReturnSize(script.len()));
+        }
+        Ok(script)
+    }
+
+    /// The data carried by an OP_RETURN script which pushes it at once
+    pub fn op_return_data(&self) -> Option<&[u8]> {
+        match self.op_return_pushes() {
+            Some(ref pushes) if pushes.len() == 1 => Some(pushes[0]),
+            _ => None,
+        }
+    }
+
+    /// The data pushes following `OP_RETURN`, if the script only pushes data
+    /// after it. `OP_1NEGATE` and `OP_1` to `OP_16` push the script number
+    /// they stand for.
+    pub fn op_return_pushes(&self) -> Option<Vec<&[u8]>> {
+        if !self.is_op_return() {
+            return None;
+        }
+        Instructions { data: &self.0[1..], len: self.0.len(), enforce_minimal: false, lenient: false }
+            .map(push_data)
+            .collect()
+    }
+
     /// Whether a script can be proven to have no satisfying input
     pub fn is_provably_unspendable(&self) -> bool {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]).classify() == opcodes::Class::ReturnOp ||
@@ -362,27 +545,313 @@ impl Script {
         self.is_cp2pkh() || self.is_cp2sh()
     }
 
+    /// The color identifier of a colored coin script
+    pub fn color_id(&self) -> Option<ColorIdentifier> {
+        if !self.is_colored() {
+            return None;
+        }
+        encode::deserialize(&self.0[1..34]).ok()
+    }
+
+    /// Whether the script only pushes data, as script_sigs spending P2SH
+    /// outputs must
+    pub fn is_push_only(&self) -> bool {
+        is_push_only(self.iter(false))
+    }
+
+    /// Count the signature operations of the script as Tapyrus Core does.
+    /// Unless `accurate`, each `OP_CHECKMULTISIG(VERIFY)` counts as many as
+    /// the most keys a multisig may have, as for the legacy limit on blocks;
+    /// otherwise it counts as many as the number of keys pushed just before
+    /// it. Counting stops at the first instruction which can not be parsed.
+    pub fn count_sigops(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last_op = None;
+        for instruction in self.iter(false) {
+            let op = match instruction {
+                Instruction::Op(op) => op,
+                Instruction::PushBytes(_) => {
+                    last_op = None;
+                    continue;
+                }
+                Instruction::Error(_) => break,
+            };
+            if op == opcodes::all::OP_CHECKSIG || op == opcodes::all::OP_CHECKSIGVERIFY {
+                count += 1;
+            } else if op == opcodes::all::OP_CHECKMULTISIG || op == opcodes::all::OP_CHECKMULTISIGVERIFY {
+                count += match last_op.map(|op: opcodes::All| op.classify()) {
+                    Some(opcodes::Class::PushNum(n)) if accurate && n >= 1 => n as usize,
+                    _ => MAX_PUBKEYS_PER_MULTISIG,
+                };
+            }
+            last_op = Some(op);
+        }
+        count
+    }
+
+    /// Count the signature operations of spending this script pubkey with
+    /// `script_sig`: those of the redeem script `script_sig` pushes last when
+    /// this is a P2SH or CP2SH script, counted accurately, or else those of
+    /// this script. A script_sig doing anything but pushes counts no
+    /// signature operations, as it can not spend a P2SH output.
+    pub fn count_p2sh_sigops(&self, script_sig: &Script) -> usize {
+        if !self.is_p2sh() && !self.is_cp2sh() {
+            return self.count_sigops(true);
+        }
+        let mut redeem_script = &[][..];
+        for instruction in script_sig.iter(false) {
+            match instruction {
+                Instruction::PushBytes(data) => redeem_script = data,
+                Instruction::Op(op) if op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8() => redeem_script = &[],
+                _ => return 0,
+            }
+        }
+        Script::from(redeem_script.to_vec()).count_sigops(true)
+    }
+
+    /// Classify a script pubkey into the templates Tapyrus Core knows of,
+    /// extracting the hashes, keys and color identifier it embeds
+    pub fn classify(&self) -> ScriptType {
+        if self.is_p2pkh() {
+            ScriptType::P2pkh(PubkeyHash::from_slice(&self.0[3..23]).expect("20 bytes"))
+        } else if self.is_p2sh() {
+            ScriptType::P2sh(ScriptHash::from_slice(&self.0[2..22]).expect("20 bytes"))
+        } else if self.is_cp2pkh() || self.is_cp2sh() {
+            let color_id = match self.color_id() {
+                Some(color_id) => color_id,
+                None => return ScriptType::NonStandard,
+            };
+            if self.is_cp2pkh() {
+                ScriptType::Cp2pkh(color_id, PubkeyHash::from_slice(&self.0[38..58]).expect("20 bytes"))
+            } else {
+                ScriptType::Cp2sh(color_id, ScriptHash::from_slice(&self.0[37..57]).expect("20 bytes"))
+            }
+        } else if self.is_p2pk() {
+            match PublicKey::from_slice(&self.0[1..self.0.len() - 1]) {
+                Ok(key) => ScriptType::P2pk(key),
+                Err(_) => ScriptType::NonStandard,
+            }
+        } else if self.is_op_return() {
+            if self.op_return_pushes().is_some() { ScriptType::OpReturn } else { ScriptType::NonStandard }
+        } else {
+            match self.parse_multisig() {
+                Some((required, keys)) => ScriptType::Multisig { required: required, keys: keys },
+                None => ScriptType::NonStandard,
+            }
+        }
+    }
+
+    /// Create a multisig script `m <pubkey>... n OP_CHECKMULTISIG` requiring
+    /// signatures of `required` of `keys`, in their given order. It can lock
+    /// outputs as is, or be used as the redeem script of a P2SH output, so
+    /// it is limited to `MAX_SCRIPT_ELEMENT_SIZE` bytes: 15 compressed keys,
+    /// or fewer uncompressed ones.
+    pub fn new_multisig(required: usize, keys: &[PublicKey]) -> Result<Script, Error> {
+        if required == 0 || required > keys.len() || keys.len() > MAX_P2SH_MULTISIG_KEYS {
+            return Err(Error::Multisig(required, keys.len()));
+        }
+        let script = keys.iter()
+            .fold(Builder::new().push_int(required as i64), |builder, key| builder.push_key(key))
+            .push_int(keys.len() as i64)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        if script.len() > interpreter::MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(Error::Multisig(required, keys.len()));
+        }
+        Ok(script)
+    }
+
+    /// Create a multisig script as `new_multisig` does, with `keys` sorted by
+    /// their serialization as BIP67 specifies, so that the script does not
+    /// depend on the order in which cosigners share their keys
+    pub fn new_sorted_multisig(required: usize, keys: &[PublicKey]) -> Result<Script, Error> {
+        let mut keys = keys.to_vec();
+        key::sort_keys(&mut keys);
+        Script::new_multisig(required, &keys)
+    }
+
+    /// The number of signatures required and the keys of a multisig script
+    /// `m <pubkey>... n OP_CHECKMULTISIG`, whether a bare multisig or the
+    /// redeem script of a P2SH output
+    pub fn parse_multisig(&self) -> Option<(usize, Vec<PublicKey>)> {
+        let instructions: Vec<Instruction> = self.iter(false).collect();
+        let (last, rest) = instructions.split_last()?;
+        if *last != Instruction::Op(opcodes::all::OP_CHECKMULTISIG) {
+            return None;
+        }
+        let (total, rest) = rest.split_last()?;
+        let (required, keys) = rest.split_first()?;
+        let count = |instruction: &Instruction| match *instruction {
+            Instruction::Op(op) => match op.classify() {
+                opcodes::Class::PushNum(n) if n >= 1 => Some(n as usize),
+                _ => None,
+            },
+            _ => None,
+        };
+        let (required, total) = (count(required)?, count(total)?);
+        if required > total || keys.len() != total {
+            return None;
+        }
+        let keys = keys.iter().map(|instruction| match *instruction {
+            Instruction::PushBytes(key) => PublicKey::from_slice(key).ok(),
+            _ => None,
+        }).collect::<Option<Vec<PublicKey>>>()?;
+        Some((required, keys))
+    }
+
+    /// Whether this is a multisig script with its keys sorted as BIP67
+    /// specifies
+    pub fn is_sorted_multisig(&self) -> bool {
+        match self.parse_multisig() {
+            Some((_, keys)) => keys.windows(2).all(|pair| pair[0].cmp_serialized(&pair[1]) != cmp::Ordering::Greater),
+            None => false,
+        }
+    }
+
+    /// Create a script paying to `pubkey_hash` as P2PKH does, which can not
+    /// be spent before `lock_time`:
+    /// `<lock_time> OP_CHECKLOCKTIMEVERIFY OP_DROP OP_DUP OP_HASH160 <pubkey_hash> OP_EQUALVERIFY OP_CHECKSIG`
+    pub fn new_cltv_p2pkh(lock_time: LockTime, pubkey_hash: &PubkeyHash) -> Script {
+        Builder::new()
+            .push_int(lock_time.to_consensus_u32() as i64)
+            .push_opcode(opcodes::all::OP_CLTV)
+            .push_opcode(opcodes::all::OP_DROP)
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(&pubkey_hash[..])
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script()
+    }
+
+    /// The lock time and public key hash of a script created by
+    /// `new_cltv_p2pkh`
+    pub fn parse_cltv_p2pkh(&self) -> Option<(LockTime, PubkeyHash)> {
+        let instructions: Vec<Instruction> = self.iter(false).take(9).collect();
+        if instructions.len() != 8 {
+            return None;
+        }
+        let lock_time = LockTime::from_consensus(read_lock_num(&instructions[0])?);
+        let pubkey_hash = match instructions[4] {
+            Instruction::PushBytes(hash) => PubkeyHash::from_slice(hash).ok()?,
+            _ => return None,
+        };
+        if *self != Script::new_cltv_p2pkh(lock_time, &pubkey_hash) {
+            return None;
+        }
+        Some((lock_time, pubkey_hash))
+    }
+
+    /// Create a script paying to `pubkey_hash` as P2PKH does, which can only
+    /// be spent by inputs with a relative lock time of at least `sequence`:
+    /// `<sequence> OP_CHECKSEQUENCEVERIFY OP_DROP OP_DUP OP_HASH160 <pubkey_hash> OP_EQUALVERIFY OP_CHECKSIG`
+    pub fn new_csv_p2pkh(sequence: Sequence, pubkey_hash: &PubkeyHash) -> Script {
+        Builder::new()
+            .push_int(sequence.to_consensus_u32() as i64)
+            .push_opcode(opcodes::all::OP_CSV)
+            .push_opcode(opcodes::all::OP_DROP)
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(&pubkey_hash[..])
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script()
+    }
+
+    /// The relative lock time and public key hash of a script created by
+    /// `new_csv_p2pkh`
+    pub fn parse_csv_p2pkh(&self) -> Option<(Sequence, PubkeyHash)> {
+        let instructions: Vec<Instruction> = self.iter(false).take(9).collect();
+        if instructions.len() != 8 {
+            return None;
+        }
+        let sequence = Sequence(read_lock_num(&instructions[0])?);
+        let pubkey_hash = match instructions[4] {
+            Instruction::PushBytes(hash) => PubkeyHash::from_slice(hash).ok()?,
+            _ => return None,
+        };
+        if *self != Script::new_csv_p2pkh(sequence, &pubkey_hash) {
+            return None;
+        }
+        Some((sequence, pubkey_hash))
+    }
+
+    /// Create the script of a hash time locked contract:
+    /// ```text
+    /// OP_IF
+    ///     OP_SHA256 <payment_hash> OP_EQUALVERIFY OP_DUP OP_HASH160 <receiver>
+    /// OP_ELSE
+    ///     <lock_time> OP_CHECKLOCKTIMEVERIFY OP_DROP OP_DUP OP_HASH160 <sender>
+    /// OP_ENDIF
+    /// OP_EQUALVERIFY OP_CHECKSIG
+    /// ```
+    /// The receiver spends it with `<sig> <pubkey> <preimage> OP_TRUE`, and
+    /// the sender with `<sig> <pubkey> OP_FALSE` once `lock_time` passed.
+    pub fn new_htlc(htlc: &Htlc) -> Script {
+        Builder::new()
+            .push_opcode(opcodes::all::OP_IF)
+            .push_opcode(opcodes::all::OP_SHA256)
+            .push_slice(&htlc.payment_hash[..])
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(&htlc.receiver[..])
+            .push_opcode(opcodes::all::OP_ELSE)
+            .push_int(htlc.lock_time.to_consensus_u32() as i64)
+            .push_opcode(opcodes::all::OP_CLTV)
+            .push_opcode(opcodes::all::OP_DROP)
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(&htlc.sender[..])
+            .push_opcode(opcodes::all::OP_ENDIF)
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script()
+    }
+
+    /// The hash time locked contract of a script created by `new_htlc`
+    pub fn parse_htlc(&self) -> Option<Htlc> {
+        let instructions: Vec<Instruction> = self.iter(false).take(18).collect();
+        if instructions.len() != 17 {
+            return None;
+        }
+        let push = |index: usize| match instructions[index] {
+            Instruction::PushBytes(data) => Some(data),
+            _ => None,
+        };
+        let htlc = Htlc {
+            payment_hash: sha256::Hash::from_slice(push(2)?).ok()?,
+            receiver: PubkeyHash::from_slice(push(6)?).ok()?,
+            sender: PubkeyHash::from_slice(push(13)?).ok()?,
+            lock_time: LockTime::from_consensus(read_lock_num(&instructions[8])?),
+        };
+        if *self != Script::new_htlc(&htlc) {
+            return None;
+        }
+        Some(htlc)
+    }
+
     /// Create new script with color identifier
     pub fn add_color(&self, color_id: ColorIdentifier) -> Result<Script, ColoredCoinError> {
         if !self.is_p2pkh() && !self.is_p2sh() {
             return Err(ColoredCoinError::UnsuppotedScriptType);
         }
-        let mut payload = vec![];
-        payload.push(opcodes::all::OP_PUSHBYTES_33.into_u8());
-        payload.extend(encode::serialize(&color_id));
-        payload.push(opcodes::all::OP_COLOR.into_u8());
+        let mut payload = Builder::new().push_color(&color_id).into_script().into_bytes();
         payload.extend(self.to_bytes());
         Ok(Script::from(payload))
     }
 
     /// Iterate over the script in the form of `Instruction`s, which are an enum covering
     /// opcodes, datapushes and errors. At most one error will be returned and then the
-    /// iterator will end. To instead iterate over the script as sequence of bytes, treat
-    /// it as a slice using `script[..]` or convert it to a vector using `into_bytes()`.
+    /// iterator will end, unless it is made `lenient`. To instead iterate over the script
+    /// as sequence of bytes, treat it as a slice using `script[..]` or convert it to a
+    /// vector using `into_bytes()`.
     pub fn iter(&self, enforce_minimal: bool) -> Instructions {
         Instructions {
             data: &self.0[..],
+            len: self.0.len(),
             enforce_minimal: enforce_minimal,
+            lenient: false,
         }
     }
 
@@ -396,8 +865,34 @@ impl Script {
         Ok(bitcoinconsensus::verify (&self.0[..], amount, spending, index)?)
     }
 
-    /// Write the assembly decoding of the script to the formatter.
+    /// Write the assembly decoding of the script to the formatter, as Tapyrus
+    /// Core's `decodescript` does: pushes of at most 4 bytes are written as
+    /// numbers, longer ones as hex data, and other opcodes by name.
     pub fn fmt_asm(&self, f: &mut fmt::Write) -> fmt::Result {
+        for (index, instruction) in self.iter(false).enumerate() {
+            if index > 0 { f.write_str(" ")?; }
+            match instruction {
+                Instruction::PushBytes(data) if data.len() <= 4 => {
+                    write!(f, "{}", read_scriptint(data).expect("at most 4 bytes"))?;
+                }
+                Instruction::PushBytes(data) => {
+                    for ch in data {
+                        write!(f, "{:02x}", ch)?;
+                    }
+                }
+                Instruction::Op(op) => fmt_op_name(f, op)?,
+                Instruction::Error(_) => {
+                    f.write_str("[error]")?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the script to the formatter with the opcodes of its pushes, as
+    /// its `Debug` output does
+    fn fmt_pushes(&self, f: &mut fmt::Write) -> fmt::Result {
         let mut index = 0;
         while index < self.0.len() {
             let opcode = opcodes::All::from(self.0[index]);
@@ -471,8 +966,274 @@ impl Script {
         self.fmt_asm(&mut buf).unwrap();
         buf
     }
+
+    /// Parse a script from its assembly, as written by `asm` or `Debug`.
+    ///
+    /// Tokens are separated by whitespace, and are either opcode names, with
+    /// or without their `OP_` prefix, decimal numbers or hex data. Numbers
+    /// and data are pushed with their shortest encoding, unless they follow
+    /// an explicit push opcode such as `OP_PUSHBYTES_20` or `OP_PUSHDATA1`.
+    /// Since `asm` writes short pushes as numbers, a token such as
+    /// `1234567890` is read as a number rather than as 5 bytes of data.
+    #[cfg(feature = "std")]
+    pub fn from_asm(asm: &str) -> Result<Script, ParseAsmError> {
+        let mut builder = Builder::new();
+        let mut tokens = asm.split_whitespace();
+        while let Some(token) = tokens.next() {
+            builder = match opcode_from_name(token) {
+                Some(op) => match push_len_size(op) {
+                    Some(len_size) => {
+                        let data = tokens.next()
+                            .and_then(|data| Vec::<u8>::from_hex(data).ok())
+                            .ok_or_else(|| ParseAsmError::BadPush(token.to_owned()))?;
+                        builder.push_with_opcode(op, len_size, &data)
+                            .ok_or_else(|| ParseAsmError::BadPush(token.to_owned()))?
+                    }
+                    None => builder.push_opcode(op),
+                },
+                None => match token.parse::<i64>() {
+                    // numbers written by `asm` are 4-byte script numbers, without
+                    // leading zeros
+                    Ok(n) if (-0x7fff_ffff..=0x7fff_ffff).contains(&n) && n.to_string() == token => {
+                        builder.push_int(n)
+                    }
+                    _ => match Vec::<u8>::from_hex(token) {
+                        Ok(data) => builder.push_minimal(&data),
+                        Err(_) => return Err(ParseAsmError::UnknownToken(token.to_owned())),
+                    },
+                },
+            };
+        }
+        Ok(builder.into_script())
+    }
+}
+
+/// Read the non-negative number of up to 5 bytes pushed by `instruction`,
+/// as lock times and sequences are
+fn read_lock_num(instruction: &Instruction) -> Option<u32> {
+    match *instruction {
+        Instruction::PushBytes(data) if data.len() <= 5 => {
+            if data.last().map_or(false, |last| last & 0x80 != 0) {
+                return None;
+            }
+            let n = data.iter().rev().fold(0u64, |n, &byte| n << 8 | byte as u64);
+            if n > u32::max_value() as u64 { None } else { Some(n as u32) }
+        }
+        Instruction::Op(op) => match op.classify() {
+            opcodes::Class::PushNum(n) if n >= 1 => Some(n as u32),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The script numbers pushed by `OP_1NEGATE` and `OP_1` to `OP_16`
+static SMALL_INTS: [u8; 17] = [0x81, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+/// The data pushed by an instruction, `OP_1NEGATE` and `OP_1` to `OP_16`
+/// included
+fn push_data<'a>(instruction: Instruction<'a>) -> Option<&'a [u8]> {
+    match instruction {
+        Instruction::PushBytes(data) => Some(data),
+        Instruction::Op(op) => match op.classify() {
+            opcodes::Class::PushNum(-1) => Some(&SMALL_INTS[0..1]),
+            opcodes::Class::PushNum(n) if n >= 1 => Some(&SMALL_INTS[n as usize..n as usize + 1]),
+            _ => None,
+        },
+        Instruction::Error(_) => None,
+    }
+}
+
+/// Whether the instructions only push data, `OP_RESERVED` included
+fn is_push_only(mut instructions: Instructions) -> bool {
+    instructions.all(|instruction| match instruction {
+        Instruction::PushBytes(_) => true,
+        Instruction::Op(op) => op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8(),
+        Instruction::Error(_) => false,
+    })
+}
+
+/// Write the name of a non-push opcode as Tapyrus Core does
+fn fmt_op_name(f: &mut fmt::Write, op: opcodes::All) -> fmt::Result {
+    match op.classify() {
+        opcodes::Class::PushNum(n) => write!(f, "{}", n),
+        _ if op == opcodes::all::OP_CLTV => f.write_str("OP_CHECKLOCKTIMEVERIFY"),
+        _ if op == opcodes::all::OP_CSV => f.write_str("OP_CHECKSEQUENCEVERIFY"),
+        _ if op == opcodes::all::OP_RETURN_255 => f.write_str("OP_INVALIDOPCODE"),
+        opcodes::Class::ReturnOp if op.into_u8() >= opcodes::all::OP_RETURN_189.into_u8() => f.write_str("OP_UNKNOWN"),
+        _ => write!(f, "{:?}", op),
+    }
+}
+
+/// Find the opcode of a name written by Tapyrus Core or by `Debug`, with or
+/// without its `OP_` prefix
+#[cfg(feature = "std")]
+fn opcode_from_name(name: &str) -> Option<opcodes::All> {
+    use blockdata::opcodes::all::*;
+
+    let prefixed = name.starts_with("OP_");
+    let name = name.trim_start_matches("OP_");
+    // the numbered opcodes, as `Debug` writes them
+    let numbered = |prefix: &str, first: opcodes::All, min: u8, max: u8| {
+        if !name.starts_with(prefix) {
+            return None;
+        }
+        match opcode_index(&name[prefix.len()..]) {
+            Some(n) if (min..=max).contains(&n) => Some(opcodes::All::from(first.into_u8() + n - min)),
+            _ => None,
+        }
+    };
+    if let Some(op) = numbered("PUSHBYTES_", OP_PUSHBYTES_0, 0, 75)
+        .or_else(|| numbered("PUSHNUM_", OP_PUSHNUM_1, 1, 16))
+        .or_else(|| numbered("NOP", OP_NOP1, 1, 10))
+        .or_else(|| numbered("RETURN_", OP_RETURN_189, 189, 255)) {
+        return Some(op);
+    }
+    match opcode_index(name) {
+        Some(n) if prefixed && (1..=16).contains(&n) => return Some(opcodes::All::from(OP_PUSHNUM_1.into_u8() + n - 1)),
+        _ => {}
+    }
+    Some(match name {
+        "0" | "FALSE" => OP_PUSHBYTES_0,
+        "TRUE" => OP_PUSHNUM_1,
+        "1NEGATE" | "PUSHNUM_NEG1" => OP_PUSHNUM_NEG1,
+        "PUSHDATA1" => OP_PUSHDATA1,
+        "PUSHDATA2" => OP_PUSHDATA2,
+        "PUSHDATA4" => OP_PUSHDATA4,
+        "RESERVED" => OP_RESERVED,
+        "NOP" => OP_NOP,
+        "VER" => OP_VER,
+        "IF" => OP_IF,
+        "NOTIF" => OP_NOTIF,
+        "VERIF" => OP_VERIF,
+        "VERNOTIF" => OP_VERNOTIF,
+        "ELSE" => OP_ELSE,
+        "ENDIF" => OP_ENDIF,
+        "VERIFY" => OP_VERIFY,
+        "RETURN" => OP_RETURN,
+        "TOALTSTACK" => OP_TOALTSTACK,
+        "FROMALTSTACK" => OP_FROMALTSTACK,
+        "2DROP" => OP_2DROP,
+        "2DUP" => OP_2DUP,
+        "3DUP" => OP_3DUP,
+        "2OVER" => OP_2OVER,
+        "2ROT" => OP_2ROT,
+        "2SWAP" => OP_2SWAP,
+        "IFDUP" => OP_IFDUP,
+        "DEPTH" => OP_DEPTH,
+        "DROP" => OP_DROP,
+        "DUP" => OP_DUP,
+        "NIP" => OP_NIP,
+        "OVER" => OP_OVER,
+        "PICK" => OP_PICK,
+        "ROLL" => OP_ROLL,
+        "ROT" => OP_ROT,
+        "SWAP" => OP_SWAP,
+        "TUCK" => OP_TUCK,
+        "CAT" => OP_CAT,
+        "SUBSTR" => OP_SUBSTR,
+        "LEFT" => OP_LEFT,
+        "RIGHT" => OP_RIGHT,
+        "SIZE" => OP_SIZE,
+        "INVERT" => OP_INVERT,
+        "AND" => OP_AND,
+        "OR" => OP_OR,
+        "XOR" => OP_XOR,
+        "EQUAL" => OP_EQUAL,
+        "EQUALVERIFY" => OP_EQUALVERIFY,
+        "RESERVED1" => OP_RESERVED1,
+        "RESERVED2" => OP_RESERVED2,
+        "1ADD" => OP_1ADD,
+        "1SUB" => OP_1SUB,
+        "2MUL" => OP_2MUL,
+        "2DIV" => OP_2DIV,
+        "NEGATE" => OP_NEGATE,
+        "ABS" => OP_ABS,
+        "NOT" => OP_NOT,
+        "0NOTEQUAL" => OP_0NOTEQUAL,
+        "ADD" => OP_ADD,
+        "SUB" => OP_SUB,
+        "MUL" => OP_MUL,
+        "DIV" => OP_DIV,
+        "MOD" => OP_MOD,
+        "LSHIFT" => OP_LSHIFT,
+        "RSHIFT" => OP_RSHIFT,
+        "BOOLAND" => OP_BOOLAND,
+        "BOOLOR" => OP_BOOLOR,
+        "NUMEQUAL" => OP_NUMEQUAL,
+        "NUMEQUALVERIFY" => OP_NUMEQUALVERIFY,
+        "NUMNOTEQUAL" => OP_NUMNOTEQUAL,
+        "LESSTHAN" => OP_LESSTHAN,
+        "GREATERTHAN" => OP_GREATERTHAN,
+        "LESSTHANOREQUAL" => OP_LESSTHANOREQUAL,
+        "GREATERTHANOREQUAL" => OP_GREATERTHANOREQUAL,
+        "MIN" => OP_MIN,
+        "MAX" => OP_MAX,
+        "WITHIN" => OP_WITHIN,
+        "RIPEMD160" => OP_RIPEMD160,
+        "SHA1" => OP_SHA1,
+        "SHA256" => OP_SHA256,
+        "HASH160" => OP_HASH160,
+        "HASH256" => OP_HASH256,
+        "CODESEPARATOR" => OP_CODESEPARATOR,
+        "CHECKSIG" => OP_CHECKSIG,
+        "CHECKSIGVERIFY" => OP_CHECKSIGVERIFY,
+        "CHECKMULTISIG" => OP_CHECKMULTISIG,
+        "CHECKMULTISIGVERIFY" => OP_CHECKMULTISIGVERIFY,
+        "CLTV" | "CHECKLOCKTIMEVERIFY" => OP_CLTV,
+        "CSV" | "CHECKSEQUENCEVERIFY" => OP_CSV,
+        "CHECKDATASIG" => OP_CHECKDATASIG,
+        "CHECKDATASIGVERIFY" => OP_CHECKDATASIGVERIFY,
+        "COLOR" => OP_COLOR,
+        "INVALIDOPCODE" => OP_RETURN_255,
+        _ => return None,
+    })
+}
+
+/// The decimal number at the end of an opcode name, without leading zeros
+#[cfg(feature = "std")]
+fn opcode_index(digits: &str) -> Option<u8> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) || (digits.len() > 1 && digits.starts_with('0')) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The size of the length of the data pushed by `op` when it is an explicit
+/// push opcode: 0 for `OP_PUSHBYTES_n`, whose length is implied
+#[cfg(feature = "std")]
+fn push_len_size(op: opcodes::All) -> Option<usize> {
+    match op.classify() {
+        opcodes::Class::PushBytes(0) => None,
+        opcodes::Class::PushBytes(_) => Some(0),
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA1) => Some(1),
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA2) => Some(2),
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA4) => Some(4),
+        _ => None,
+    }
+}
+
+/// An error in parsing the assembly of a script
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParseAsmError {
+    /// A token is neither an opcode name, a number nor hex data
+    UnknownToken(String),
+    /// An explicit push opcode is not followed by hex data it can push
+    BadPush(String),
+}
+
+impl fmt::Display for ParseAsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseAsmError::UnknownToken(ref t) => write!(f, "unknown script token: {}", t),
+            ParseAsmError::BadPush(ref op) => write!(f, "{} not followed by valid push data", op),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl error::Error for ParseAsmError {}
+
 /// Creates a new script from an existing vector
 impl From<Vec<u8>> for Script {
     fn from(v: Vec<u8>) -> Script { Script(v.into_boxed_slice()) }
@@ -494,7 +1255,66 @@ pub enum Instruction<'a> {
 /// Iterator over a script returning parsed opcodes
 pub struct Instructions<'a> {
     data: &'a [u8],
+    len: usize,
     enforce_minimal: bool,
+    lenient: bool,
+}
+
+impl<'a> Instructions<'a> {
+    /// Make the iterator lenient: rather than ending after an error, it
+    /// reports it and carries on parsing. A non-minimal push is skipped as a
+    /// whole, while a push running past the end of the script is skipped by
+    /// its opcode only, with the bytes following it parsed as instructions.
+    /// This lets malformed scripts, such as some found in historical blocks,
+    /// be analysed to their end.
+    pub fn lenient(mut self) -> Instructions<'a> {
+        self.lenient = true;
+        self
+    }
+
+    /// The byte offset in the script of the next instruction
+    pub fn offset(&self) -> usize {
+        self.len - self.data.len()
+    }
+
+    /// Iterate over the instructions along with their byte offset in the script
+    pub fn with_offsets(self) -> InstructionOffsets<'a> {
+        InstructionOffsets(self)
+    }
+
+    /// Report an error for the instruction of `size` bytes at the start of
+    /// the data, killing the iterator unless it is lenient so that it does
+    /// not return an infinite stream of errors
+    fn error(&mut self, error: Error, size: usize) -> Option<Instruction<'a>> {
+        if self.lenient {
+            self.data = &self.data[cmp::min(size, self.data.len())..];
+        } else {
+            self.data = &[];
+        }
+        Some(Instruction::Error(error))
+    }
+
+    /// Read a push of `n` bytes of data following `size` bytes of opcode and
+    /// length, unless its length is not minimal or it exceeds the script
+    fn push(&mut self, size: usize, n: usize, non_minimal: bool) -> Option<Instruction<'a>> {
+        if self.enforce_minimal && non_minimal {
+            return self.error(Error::NonMinimalPush, size + n);
+        }
+        if self.data.len() < size + n {
+            return self.error(Error::EarlyEndOfScript, 1);
+        }
+        let ret = Some(Instruction::PushBytes(&self.data[size..size + n]));
+        self.data = &self.data[size + n..];
+        ret
+    }
+
+    /// Read the length of a push taking `size` bytes after its opcode
+    fn push_len(&mut self, size: usize) -> Result<usize, Option<Instruction<'a>>> {
+        if self.data.len() < size + 1 {
+            return Err(self.error(Error::EarlyEndOfScript, 1));
+        }
+        read_uint(&self.data[1..], size).map_err(|e| self.error(e, 1))
+    }
 }
 
 impl<'a> Iterator for Instructions<'a> {
@@ -509,90 +1329,34 @@ impl<'a> Iterator for Instructions<'a> {
             opcodes::Class::PushBytes(n) => {
                 let n = n as usize;
                 if self.data.len() < n + 1 {
-                    self.data = &[];  // Kill iterator so that it does not return an infinite stream of errors
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                if self.enforce_minimal {
-                    if n == 1 && (self.data[1] == 0x81 || (self.data[1] > 0 && self.data[1] <= 16)) {
-                        self.data = &[];
-                        return Some(Instruction::Error(Error::NonMinimalPush));
-                    }
+                    return self.error(Error::EarlyEndOfScript, 1);
                 }
-                let ret = Some(Instruction::PushBytes(&self.data[1..n+1]));
-                self.data = &self.data[n + 1..];
-                ret
+                let non_minimal = n == 1 && (self.data[1] == 0x81 || (self.data[1] > 0 && self.data[1] <= 16));
+                self.push(1, n, non_minimal)
             }
             opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA1) => {
-                if self.data.len() < 2 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                let n = match read_uint(&self.data[1..], 1) {
+                let n = match self.push_len(1) {
                     Ok(n) => n,
-                    Err(e) => {
-                        self.data = &[];
-                        return Some(Instruction::Error(e));
-                    }
+                    Err(e) => return e,
                 };
                 if self.data.len() < n + 2 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                if self.enforce_minimal && n < 76 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::NonMinimalPush));
+                    return self.error(Error::EarlyEndOfScript, 1);
                 }
-                let ret = Some(Instruction::PushBytes(&self.data[2..n+2]));
-                self.data = &self.data[n + 2..];
-                ret
+                self.push(2, n, n < 76)
             }
             opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA2) => {
-                if self.data.len() < 3 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                let n = match read_uint(&self.data[1..], 2) {
+                let n = match self.push_len(2) {
                     Ok(n) => n,
-                    Err(e) => {
-                        self.data = &[];
-                        return Some(Instruction::Error(e));
-                    }
+                    Err(e) => return e,
                 };
-                if self.enforce_minimal && n < 0x100 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::NonMinimalPush));
-                }
-                if self.data.len() < n + 3 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                let ret = Some(Instruction::PushBytes(&self.data[3..n + 3]));
-                self.data = &self.data[n + 3..];
-                ret
+                self.push(3, n, n < 0x100)
             }
             opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA4) => {
-                if self.data.len() < 5 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                let n = match read_uint(&self.data[1..], 4) {
+                let n = match self.push_len(4) {
                     Ok(n) => n,
-                    Err(e) => {
-                        self.data = &[];
-                        return Some(Instruction::Error(e));
-                    }
+                    Err(e) => return e,
                 };
-                if self.enforce_minimal && n < 0x10000 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::NonMinimalPush));
-                }
-                if self.data.len() < n + 5 {
-                    self.data = &[];
-                    return Some(Instruction::Error(Error::EarlyEndOfScript));
-                }
-                let ret = Some(Instruction::PushBytes(&self.data[5..n + 5]));
-                self.data = &self.data[n + 5..];
-                ret
+                self.push(5, n, n < 0x10000)
             }
             // Everything else we can push right through
             _ => {
@@ -604,6 +1368,19 @@ impl<'a> Iterator for Instructions<'a> {
     }
 }
 
+/// Iterator over a script returning parsed opcodes along with their byte
+/// offset in the script
+pub struct InstructionOffsets<'a>(Instructions<'a>);
+
+impl<'a> Iterator for InstructionOffsets<'a> {
+    type Item = (usize, Instruction<'a>);
+
+    fn next(&mut self) -> Option<(usize, Instruction<'a>)> {
+        let offset = self.0.offset();
+        self.0.next().map(|instruction| (offset, instruction))
+    }
+}
+
 impl Builder {
     /// Creates a new empty script
     pub fn new() -> Builder {
@@ -641,6 +1418,38 @@ impl Builder {
         self.push_slice(&build_scriptint(data))
     }
 
+    /// Adds instructions to push some arbitrary data onto the stack, with
+    /// a single opcode for the data pushed by `OP_0`, `OP_1NEGATE` and
+    /// `OP_1` to `OP_16`
+    #[cfg(feature = "std")]
+    fn push_minimal(self, data: &[u8]) -> Builder {
+        match data {
+            [] => self.push_opcode(opcodes::all::OP_PUSHBYTES_0),
+            [n] if (1..=16).contains(n) => self.push_int(i64::from(*n)),
+            [0x81] => self.push_int(-1),
+            _ => self.push_slice(data),
+        }
+    }
+
+    /// Push `data` with the push opcode `op`, followed by the length of the
+    /// data on `len_size` bytes. Returns `None` if `op` cannot push data of
+    /// this length.
+    #[cfg(feature = "std")]
+    fn push_with_opcode(mut self, op: opcodes::All, len_size: usize, data: &[u8]) -> Option<Builder> {
+        let fits = match op.classify() {
+            opcodes::Class::PushBytes(n) => n as usize == data.len(),
+            _ => len_size >= 4 || data.len() >> (8 * len_size) == 0,
+        };
+        if !fits {
+            return None;
+        }
+        self.0.push(op.into_u8());
+        self.0.extend((0..len_size).map(|i| (data.len() >> (8 * i)) as u8));
+        self.0.extend_from_slice(data);
+        self.1 = None;
+        Some(self)
+    }
+
     /// Adds instructions to push some arbitrary data onto the stack
     pub fn push_slice(mut self, data: &[u8]) -> Builder {
         // Start with a PUSH opcode
@@ -679,6 +1488,34 @@ impl Builder {
         }
     }
 
+    /// Adds instructions to color the rest of the script with a color
+    /// identifier: `<color id> OP_COLOR`
+    pub fn push_color(self, color_id: &ColorIdentifier) -> Builder {
+        self.push_slice(&encode::serialize(color_id)).push_opcode(opcodes::all::OP_COLOR)
+    }
+
+    /// Adds the colored pay to public key hash template to the script, which
+    /// is a CP2PKH script pubkey when the builder is empty, and finishes it
+    pub fn into_cp2pkh(self, color_id: &ColorIdentifier, pubkey_hash: &PubkeyHash) -> Script {
+        self.push_color(color_id)
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(&pubkey_hash[..])
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script()
+    }
+
+    /// Adds the colored pay to script hash template to the script, which is
+    /// a CP2SH script pubkey when the builder is empty, and finishes it
+    pub fn into_cp2sh(self, color_id: &ColorIdentifier, script_hash: &ScriptHash) -> Script {
+        self.push_color(color_id)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(&script_hash[..])
+            .push_opcode(opcodes::all::OP_EQUAL)
+            .into_script()
+    }
+
     /// Adds a single opcode to the script
     pub fn push_opcode(mut self, data: opcodes::All) -> Builder {
         self.0.push(data.into_u8());
@@ -773,20 +1610,43 @@ impl<'de> serde::Deserialize<'de> for Script {
             {
                 self.visit_str(&v)
             }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Script::from(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(Script::from(v))
+            }
         }
 
-        deserializer.deserialize_str(Visitor)
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(Visitor)
+        } else {
+            deserializer.deserialize_byte_buf(Visitor)
+        }
     }
 }
 
 #[cfg(feature = "serde")]
 impl serde::Serialize for Script {
-    /// User-facing serialization for `Script`.
+    /// User-facing serialization for `Script`: hex in human-readable
+    /// formats, raw bytes in the others.
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&format!("{:x}", self))
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&format!("{:x}", self))
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
     }
 }
 
@@ -799,6 +1659,11 @@ impl Encodable for Script {
     ) -> Result<usize, encode::Error> {
         self.0.consensus_encode(s)
     }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        self.0.consensus_encoded_len()
+    }
 }
 
 impl Decodable for Script {
@@ -808,11 +1673,12 @@ impl Decodable for Script {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct ColorIdentifierPayload(sha256::Hash);
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-/// ColorIdentifier
+/// Identifies the tokens of a colored coin: their type, and a 32-byte
+/// payload committing to how they are issued
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ColorIdentifier {
     /// Token type
     pub token_type: TokenTypes,
@@ -820,7 +1686,8 @@ pub struct ColorIdentifier {
 }
 
 impl ColorIdentifier {
-    /// initialize ColorIdentifier with type Reissuable
+    /// initialize ColorIdentifier with type Reissuable, issued by spending
+    /// coins locked by `script_pubkey`
     pub fn reissuable(script_pubkey: Script) -> Self {
         let mut enc = sha256::Hash::engine();
         script_pubkey.consensus_encode(&mut enc).unwrap();
@@ -831,7 +1698,8 @@ impl ColorIdentifier {
         }
     }
 
-    /// initialize ColorIdentifier with type Non Reissuable
+    /// initialize ColorIdentifier with type Non Reissuable, issued by
+    /// spending `out_point`
     pub fn non_reissuable(out_point: OutPoint) -> Self {
         let mut enc = sha256::Hash::engine();
         out_point.consensus_encode(&mut enc).unwrap();
@@ -842,7 +1710,8 @@ impl ColorIdentifier {
         }
     }
 
-    /// initialize ColorIdentifier with type Nft
+    /// initialize ColorIdentifier with type Nft, issued by spending
+    /// `out_point`
     pub fn nft(out_point: OutPoint) -> Self {
         let mut enc = sha256::Hash::engine();
         out_point.consensus_encode(&mut enc).unwrap();
@@ -852,6 +1721,20 @@ impl ColorIdentifier {
             payload: ColorIdentifierPayload(hash)
         }
     }
+
+    /// initialize ColorIdentifier from its token type and payload
+    pub fn from_payload(token_type: TokenTypes, payload: [u8; 32]) -> Self {
+        ColorIdentifier {
+            token_type: token_type,
+            payload: ColorIdentifierPayload(sha256::Hash::from_inner(payload)),
+        }
+    }
+
+    /// The 32-byte payload, the hash of the script or outpoint issuing the
+    /// tokens
+    pub fn payload(&self) -> [u8; 32] {
+        self.payload.0.into_inner()
+    }
 }
 
 impl Encodable for ColorIdentifier {
@@ -870,14 +1753,10 @@ impl Decodable for ColorIdentifier {
     #[inline]
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
         let bytes: [u8; 33] = Decodable::consensus_decode(&mut d)?;
-        let token_type = match bytes[0] {
-            0xC1 => TokenTypes::Reissuable,
-            0xC2 => TokenTypes::NonReissuable,
-            0xC3 => TokenTypes::Nft,
-            _ => return Err(encode::Error::ParseFailed("invalid token type."))
-        };
+        let token_type = TokenTypes::from_u8(bytes[0])
+            .ok_or(encode::Error::Parse(encode::ParseError::Other("invalid token type.")))?;
 
-        let payload = sha256::Hash::from_slice(&bytes[1..]).map_err(|_| encode::Error::ParseFailed("invalid payload."))?;
+        let payload = sha256::Hash::from_slice(&bytes[1..]).map_err(|_| encode::Error::Parse(encode::ParseError::Other("invalid payload.")))?;
         Ok(ColorIdentifier {
             token_type: token_type,
             payload: ColorIdentifierPayload(payload)
@@ -885,14 +1764,67 @@ impl Decodable for ColorIdentifier {
     }
 }
 
-impl std::fmt::Display for ColorIdentifier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", serialize_hex(self))
+impl fmt::Display for ColorIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in serialize(self) {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::str::FromStr for ColorIdentifier {
+    type Err = ParseColorIdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = Vec::<u8>::from_hex(s).map_err(ParseColorIdentifierError::Hex)?;
+        if bytes.len() != 33 {
+            return Err(ParseColorIdentifierError::InvalidLength(bytes.len()));
+        }
+        let token_type = TokenTypes::from_u8(bytes[0])
+            .ok_or(ParseColorIdentifierError::InvalidTokenType(bytes[0]))?;
+        let mut payload = [0u8; 32];
+        payload.copy_from_slice(&bytes[1..]);
+        Ok(ColorIdentifier::from_payload(token_type, payload))
+    }
+}
+
+serde_string_impl!(ColorIdentifier, "a hex-encoded color identifier");
+
+/// An error in parsing a hex-encoded ColorIdentifier
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParseColorIdentifierError {
+    /// Invalid hex
+    Hex(::hashes::hex::Error),
+    /// The color identifier is not 33 bytes long
+    InvalidLength(usize),
+    /// Unknown token type
+    InvalidTokenType(u8),
+}
+
+impl fmt::Display for ParseColorIdentifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseColorIdentifierError::Hex(ref e) => write!(f, "error parsing color identifier hex: {}", e),
+            ParseColorIdentifierError::InvalidLength(len) => write!(f, "color identifier of {} bytes, expected 33", len),
+            ParseColorIdentifierError::InvalidTokenType(t) => write!(f, "invalid token type: {:#x}", t),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ParseColorIdentifierError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ParseColorIdentifierError::Hex(ref e) => Some(e),
+            _ => None,
+        }
     }
 }
 
 /// Token types
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TokenTypes {
     /// Reissuable
     Reissuable = 0xc1,
@@ -905,7 +1837,17 @@ pub enum TokenTypes {
 impl TokenTypes {
     /// return true if token type is supported
     pub fn is_valid(token_type: &u8) -> bool {
-        vec![TokenTypes::Reissuable, TokenTypes::NonReissuable, TokenTypes::Nft].iter().any(|e| e.clone() as u8 == *token_type)
+        TokenTypes::from_u8(*token_type).is_some()
+    }
+
+    /// return the token type encoded as `token_type`, if supported
+    pub fn from_u8(token_type: u8) -> Option<TokenTypes> {
+        match token_type {
+            0xc1 => Some(TokenTypes::Reissuable),
+            0xc2 => Some(TokenTypes::NonReissuable),
+            0xc3 => Some(TokenTypes::Nft),
+            _ => None,
+        }
     }
 }
 
@@ -920,7 +1862,8 @@ mod test {
     use consensus::encode::{deserialize, serialize};
     use blockdata::opcodes;
     use util::key::PublicKey;
-    use hash_types::Txid;
+    use hash_types::{MalFixTxid, PubkeyHash, ScriptHash};
+    use test_helpers::test_pubkey;
     use hashes::hex::FromHex;
 
     #[test]
@@ -969,6 +1912,9 @@ mod test {
                                    .push_opcode(opcodes::all::OP_CHECKSIG)
                                    .into_script();
         assert_eq!(&format!("{:x}", script), "76a91416e1ae70ff0fa102905d4af297f6912bda6cce1988ac");
+        let pubkey_hash = PubkeyHash::from_hex("16e1ae70ff0fa102905d4af297f6912bda6cce19").unwrap();
+        assert_eq!(Script::new_p2pkh(&pubkey_hash), script);
+        assert_eq!(Script::new_p2pkh(&pubkey_hash).classify(), ScriptType::P2pkh(pubkey_hash));
     }
 
     #[test]
@@ -1094,19 +2040,82 @@ mod test {
         assert_eq!(json, serde_json::Value::String("827651a0698faaa9a8a7a687".to_owned()));
         let des = serde_json::from_value(json).unwrap();
         assert_eq!(original, des);
+
+        use serde_test::{Configure, Token, assert_tokens};
+        assert_tokens(&original.clone().readable(), &[Token::Str("827651a0698faaa9a8a7a687")]);
+        assert_tokens(&original.compact(), &[Token::Bytes(&[0x82, 0x76, 0x51, 0xa0, 0x69, 0x8f, 0xaa, 0xa9, 0xa8, 0xa7, 0xa6, 0x87])]);
     }
 
     #[test]
     fn script_asm() {
         assert_eq!(hex_script!("6363636363686868686800").asm(),
-                   "OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0");
-        assert_eq!(hex_script!("6363636363686868686800").asm(),
-                   "OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0");
+                   "OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF 0");
+        assert_eq!(format!("{:?}", hex_script!("6363636363686868686800")),
+                   "Script(OP_IF OP_IF OP_IF OP_IF OP_IF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_ENDIF OP_0)");
         assert_eq!(hex_script!("2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac").asm(),
-                   "OP_PUSHBYTES_33 02715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699 OP_CHECKSIG");
+                   "02715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699 OP_CHECKSIG");
+        assert_eq!(format!("{:?}", hex_script!("2102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699ac")),
+                   "Script(OP_PUSHBYTES_33 02715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b699 OP_CHECKSIG)");
         // Elements Alpha peg-out transaction with some signatures removed for brevity. Mainly to test PUSHDATA1
         assert_eq!(hex_script!("0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae").asm(),
-                   "OP_0 OP_PUSHBYTES_71 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 OP_0 OP_PUSHDATA1 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae");
+                   "0 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 0 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae");
+        assert_eq!(format!("{:?}", hex_script!("0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae")),
+                   "Script(OP_0 OP_PUSHBYTES_71 304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401 OP_0 OP_PUSHDATA1 552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae)");
+        // short pushes are numbers, as in Tapyrus Core
+        assert_eq!(hex_script!("4f516002900003ffffff0480000000bd").asm(),
+                   "-1 1 16 144 -8388607 128 OP_UNKNOWN");
+        assert_eq!(hex_script!("b1b2babbbcff").asm(),
+                   "OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_CHECKDATASIG OP_CHECKDATASIGVERIFY OP_COLOR OP_INVALIDOPCODE");
+        assert_eq!(hex_script!("76a94c").asm(), "OP_DUP OP_HASH160 [error]");
+    }
+
+    #[test]
+    fn script_from_asm() {
+        // round trips of scripts with minimal pushes
+        let scripts = [
+            "76a91402306a7c23f3e8010de41e9e591348bb83f11daa88ac",
+            "a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87",
+            "21c1f335bd3240ddfd87a2c2a1ff7d8d8ef0b22906a6b8a6d6ab2bd26ac7ffd63d9fbc76a914a8b3f8d2d8d0a14d1b7ea7d1d9e1d4aab1b2b3b488ac",
+            "5221022afc20bf379bc96a2f4e9e63ffceb8652b2b6a097f63fbee6ecec2a49a48010e2103a767c7221e9f15f870f1ad9311f5ab937d79fcaeee15bb2c722bca515581b4c052ae",
+            "0395b400b17576a914a8b3f8d2d8d0a14d1b7ea7d1d9e1d4aab1b2b3b488ac",
+            "6a0b68656c6c6f20776f726c64",
+            "0047304402202457e78cc1b7f50d0543863c27de75d07982bde8359b9e3316adec0aec165f2f02200203fd331c4e4a4a02f48cf1c291e2c0d6b2f7078a784b5b3649fca41f8794d401004cf1552103244e602b46755f24327142a0517288cebd159eccb6ccf41ea6edf1f601e9af952103bbbacc302d19d29dbfa62d23f37944ae19853cf260c745c2bea739c95328fcb721039227e83246bd51140fe93538b2301c9048be82ef2fb3c7fc5d78426ed6f609ad210229bf310c379b90033e2ecb07f77ecf9b8d59acb623ab7be25a0caed539e2e6472103703e2ed676936f10b3ce9149fa2d4a32060fb86fa9a70a4efe3f21d7ab90611921031e9b7c6022400a6bb0424bbcde14cff6c016b91ee3803926f3440abf5c146d05210334667f975f55a8455d515a2ef1c94fdfa3315f12319a14515d2a13d82831f62f57ae",
+        ];
+        let long_push = format!("4c4c{}", "ab".repeat(76));
+        for script in scripts.iter().cloned().chain(Some(&long_push[..])) {
+            let script = Script::from(Vec::<u8>::from_hex(script).unwrap());
+            assert_eq!(Script::from_asm(&script.asm()), Ok(script.clone()));
+            let debug = format!("{:?}", script);
+            assert_eq!(Script::from_asm(&debug[7..debug.len() - 1]), Ok(script));
+        }
+
+        // numbers and data are pushed minimally
+        assert_eq!(Script::from_asm("0 -1 1 16 17 -8388608"), Ok(hex_script!("004f516001110400008080")));
+        assert_eq!(Script::from_asm("00 01 0a 0011"), Ok(hex_script!("0100515a020011")));
+        assert_eq!(Script::from_asm("0000000000"), Ok(hex_script!("050000000000")));
+        // opcode names, with or without prefix, as Tapyrus Core or Debug write them
+        assert_eq!(Script::from_asm("OP_DUP HASH160 OP_CLTV OP_CHECKSEQUENCEVERIFY NOP2 OP_TRUE OP_16 COLOR"),
+                   Ok(hex_script!("76a9b1b2b15160bc")));
+        assert_eq!(Script::from_asm("OP_CHECKDATASIG CHECKDATASIGVERIFY"), Ok(hex_script!("babb")));
+        for code in 0..=255u8 {
+            let op = opcodes::All::from(code);
+            assert_eq!(opcode_from_name(&format!("{:?}", op)), Some(op));
+        }
+        assert_eq!(opcode_from_name("OP_PUSHBYTES_76"), None);
+        assert_eq!(opcode_from_name("OP_NOP01"), None);
+        assert_eq!(opcode_from_name("OP_RETURN_186"), None);
+        assert_eq!(opcode_from_name("OP_RETURN_188"), None);
+        assert_eq!(opcode_from_name("OP_17"), None);
+        // explicit pushes keep their encoding
+        assert_eq!(Script::from_asm("OP_PUSHBYTES_1 05 OP_PUSHDATA1 0102 OP_PUSHDATA2 03 OP_PUSHDATA4 04"),
+                   Ok(hex_script!("01054c0201024d0100034e0100000004")));
+        assert_eq!(Script::from_asm("OP_PUSHBYTES_2 05"), Err(ParseAsmError::BadPush("OP_PUSHBYTES_2".to_owned())));
+        assert_eq!(Script::from_asm("OP_PUSHDATA1"), Err(ParseAsmError::BadPush("OP_PUSHDATA1".to_owned())));
+        assert_eq!(Script::from_asm("OP_PUSHDATA1 0g"), Err(ParseAsmError::BadPush("OP_PUSHDATA1".to_owned())));
+        assert_eq!(Script::from_asm("OP_DUP OP_FOO"), Err(ParseAsmError::UnknownToken("OP_FOO".to_owned())));
+        assert_eq!(Script::from_asm("abc"), Err(ParseAsmError::UnknownToken("abc".to_owned())));
+        assert_eq!(Script::from_asm("OP_UNKNOWN"), Err(ParseAsmError::UnknownToken("OP_UNKNOWN".to_owned())));
+        assert_eq!(Script::from_asm("  "), Ok(Script::new()));
     }
 
     #[test]
@@ -1163,6 +2172,257 @@ mod test {
         assert!(!hex_script!("a9147620a79e8657d066cff10e21228bf983cf546ac687").is_colored());
     }
 
+    #[test]
+    fn script_classify() {
+        let p2pkh = hex_script!("76a91446c2fbfbecc99a63148fa076de58cf29b0bcf0b088ac");
+        let pubkey_hash = PubkeyHash::from_slice(&hex_decode("46c2fbfbecc99a63148fa076de58cf29b0bcf0b0").unwrap()).unwrap();
+        assert_eq!(p2pkh.classify(), ScriptType::P2pkh(pubkey_hash));
+        let p2sh = hex_script!("a9147620a79e8657d066cff10e21228bf983cf546ac687");
+        let script_hash = ScriptHash::from_slice(&hex_decode("7620a79e8657d066cff10e21228bf983cf546ac6").unwrap()).unwrap();
+        assert_eq!(p2sh.classify(), ScriptType::P2sh(script_hash));
+
+        let color_id = ColorIdentifier::reissuable(p2pkh.clone());
+        let cp2pkh = p2pkh.add_color(color_id.clone()).unwrap().classify();
+        assert_eq!(cp2pkh, ScriptType::Cp2pkh(color_id.clone(), pubkey_hash));
+        assert_eq!(cp2pkh.pubkey_hash(), Some(pubkey_hash));
+        assert_eq!(cp2pkh.script_hash(), None);
+        assert_eq!(cp2pkh.color_id(), Some(&color_id));
+        let cp2sh = p2sh.add_color(color_id.clone()).unwrap().classify();
+        assert_eq!(cp2sh, ScriptType::Cp2sh(color_id.clone(), script_hash));
+        assert_eq!(cp2sh.script_hash(), Some(script_hash));
+        assert!(cp2sh.is_colored());
+        assert!(!ScriptType::P2sh(script_hash).is_colored());
+
+        let compressed = test_pubkey();
+        let uncompressed = PublicKey::from_str("042e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af191923a2964c177f5b5923ae500fca49e99492d534aa3759d6b25a8bc971b133").unwrap();
+        let p2pk = Builder::new().push_key(&compressed).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        assert_eq!(p2pk.classify(), ScriptType::P2pk(compressed));
+        // not a point of the curve
+        assert_eq!(hex_script!("2102ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffac").classify(), ScriptType::NonStandard);
+
+        let multisig = Builder::new()
+            .push_int(1)
+            .push_key(&compressed)
+            .push_key(&uncompressed)
+            .push_int(2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(multisig.classify(), ScriptType::Multisig { required: 1, keys: vec![compressed, uncompressed] });
+        assert_eq!(multisig.classify().pubkey_hash(), None);
+        // more signatures than keys
+        let multisig = Builder::new()
+            .push_int(2)
+            .push_key(&compressed)
+            .push_int(1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(multisig.classify(), ScriptType::NonStandard);
+        // wrong number of keys
+        let multisig = Builder::new()
+            .push_int(1)
+            .push_key(&compressed)
+            .push_int(2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(multisig.classify(), ScriptType::NonStandard);
+
+        assert_eq!(hex_script!("6a").classify(), ScriptType::OpReturn);
+        assert_eq!(hex_script!("6a0b68656c6c6f20776f726c6451").classify(), ScriptType::OpReturn);
+        assert_eq!(hex_script!("6a0b68656c6c6f20776f726c6476").classify(), ScriptType::NonStandard);
+        assert_eq!(hex_script!("6a4c").classify(), ScriptType::NonStandard);
+        // OP_RESERVED pushes nothing
+        assert_eq!(hex_script!("6a50").classify(), ScriptType::NonStandard);
+        assert_eq!(Script::new().classify(), ScriptType::NonStandard);
+        assert_eq!(hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6").classify(), ScriptType::NonStandard);
+    }
+
+    #[test]
+    fn script_count_sigops() {
+        let key = test_pubkey();
+        let p2pkh = Script::new_p2pkh(&PubkeyHash::hash(&key.to_bytes()));
+        assert_eq!(p2pkh.count_sigops(false), 1);
+        assert_eq!(p2pkh.count_sigops(true), 1);
+
+        let multisig = Script::new_multisig(2, &[key, key, key]).unwrap();
+        assert_eq!(multisig.count_sigops(false), 20);
+        assert_eq!(multisig.count_sigops(true), 3);
+        // the number of keys must be pushed by OP_N to be counted
+        let multisig = Builder::new().push_int(17).push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script();
+        assert_eq!(multisig.count_sigops(true), 20);
+        assert_eq!(hex_script!("acadaeaf").count_sigops(true), 42);
+        assert_eq!(Script::new().count_sigops(true), 0);
+        // counting stops at a truncated push
+        assert_eq!(hex_script!("ac05ac").count_sigops(false), 1);
+
+        let redeem_script = Script::new_multisig(2, &[key, key, key]).unwrap();
+        let script_sig = Builder::new()
+            .push_int(0)
+            .push_slice(&[0x30; 72])
+            .push_slice(&[0x30; 72])
+            .push_slice(&redeem_script[..])
+            .into_script();
+        assert_eq!(redeem_script.to_p2sh().count_p2sh_sigops(&script_sig), 3);
+        let color_id = ColorIdentifier::reissuable(p2pkh.clone());
+        assert_eq!(redeem_script.to_p2sh().add_color(color_id).unwrap().count_p2sh_sigops(&script_sig), 3);
+        let script_sig = Builder::new().push_slice(&redeem_script[..]).push_opcode(opcodes::all::OP_NOP).into_script();
+        assert_eq!(redeem_script.to_p2sh().count_p2sh_sigops(&script_sig), 0);
+        let script_sig = Builder::new().push_slice(&redeem_script[..]).push_int(1).into_script();
+        assert_eq!(redeem_script.to_p2sh().count_p2sh_sigops(&script_sig), 0);
+        assert_eq!(p2pkh.count_p2sh_sigops(&Script::new()), 1);
+    }
+
+    #[test]
+    fn script_timelocks() {
+        let pubkey_hash = PubkeyHash::hash(b"pubkey");
+        let cltv = Script::new_cltv_p2pkh(LockTime::Blocks(100), &pubkey_hash);
+        assert_eq!(
+            cltv.asm(),
+            format!("100 OP_CHECKLOCKTIMEVERIFY OP_DROP OP_DUP OP_HASH160 {} OP_EQUALVERIFY OP_CHECKSIG", pubkey_hash)
+        );
+        assert_eq!(cltv.parse_cltv_p2pkh(), Some((LockTime::Blocks(100), pubkey_hash)));
+        assert_eq!(cltv.parse_csv_p2pkh(), None);
+        assert_eq!(cltv.classify(), ScriptType::NonStandard);
+        for &lock_time in &[LockTime::ZERO, LockTime::Blocks(16), LockTime::Seconds(1_600_000_000), LockTime::Seconds(0xffffffff)] {
+            let cltv = Script::new_cltv_p2pkh(lock_time, &pubkey_hash);
+            assert_eq!(cltv.parse_cltv_p2pkh(), Some((lock_time, pubkey_hash)));
+        }
+        // the lock time must be pushed minimally
+        let mut bytes = vec![0x02, 100, 0];
+        bytes.extend_from_slice(&cltv[2..]);
+        assert_eq!(Script::from(bytes).parse_cltv_p2pkh(), None);
+        let mut bytes = cltv.to_bytes();
+        bytes.push(opcodes::all::OP_NOP.into_u8());
+        assert_eq!(Script::from(bytes).parse_cltv_p2pkh(), None);
+        assert_eq!(Script::new().parse_cltv_p2pkh(), None);
+
+        let csv = Script::new_csv_p2pkh(Sequence::from_height(144), &pubkey_hash);
+        assert_eq!(
+            csv.asm(),
+            format!("144 OP_CHECKSEQUENCEVERIFY OP_DROP OP_DUP OP_HASH160 {} OP_EQUALVERIFY OP_CHECKSIG", pubkey_hash)
+        );
+        assert_eq!(csv.parse_csv_p2pkh(), Some((Sequence::from_height(144), pubkey_hash)));
+        assert_eq!(csv.parse_cltv_p2pkh(), None);
+        let csv = Script::new_csv_p2pkh(Sequence::from_512_second_intervals(10), &pubkey_hash);
+        assert_eq!(csv.parse_csv_p2pkh(), Some((Sequence::from_512_second_intervals(10), pubkey_hash)));
+
+        let htlc = Htlc {
+            payment_hash: sha256::Hash::hash(b"secret"),
+            receiver: PubkeyHash::hash(b"receiver"),
+            sender: PubkeyHash::hash(b"sender"),
+            lock_time: LockTime::Blocks(500_000),
+        };
+        let script = Script::new_htlc(&htlc);
+        assert_eq!(
+            script.asm(),
+            format!(
+                "OP_IF OP_SHA256 {} OP_EQUALVERIFY OP_DUP OP_HASH160 {} OP_ELSE 500000 OP_CHECKLOCKTIMEVERIFY OP_DROP OP_DUP OP_HASH160 {} OP_ENDIF OP_EQUALVERIFY OP_CHECKSIG",
+                htlc.payment_hash, htlc.receiver, htlc.sender,
+            )
+        );
+        assert_eq!(script.parse_htlc(), Some(htlc));
+        assert_eq!(script.to_p2sh().parse_htlc(), None);
+        assert_eq!(cltv.parse_htlc(), None);
+    }
+
+    #[test]
+    fn script_multisig() {
+        let g = PublicKey::from_str("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        let g2 = PublicKey::from_str("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5").unwrap();
+        let g3 = PublicKey::from_str("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9").unwrap();
+
+        let multisig = Script::new_multisig(2, &[g3, g, g2]).unwrap();
+        assert_eq!(
+            multisig,
+            Builder::new()
+                .push_int(2)
+                .push_key(&g3)
+                .push_key(&g)
+                .push_key(&g2)
+                .push_int(3)
+                .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+                .into_script()
+        );
+        assert_eq!(multisig.parse_multisig(), Some((2, vec![g3, g, g2])));
+        assert_eq!(multisig.classify(), ScriptType::Multisig { required: 2, keys: vec![g3, g, g2] });
+        assert!(!multisig.is_sorted_multisig());
+
+        let sorted = Script::new_sorted_multisig(2, &[g3, g, g2]).unwrap();
+        assert_eq!(sorted, Script::new_multisig(2, &[g, g2, g3]).unwrap());
+        assert_eq!(sorted, Script::new_sorted_multisig(2, &[g2, g3, g]).unwrap());
+        assert!(sorted.is_sorted_multisig());
+        // as a P2SH redeem script
+        assert_eq!(sorted.to_p2sh().classify(), ScriptType::P2sh(ScriptHash::hash(&sorted[..])));
+        assert_eq!(sorted.to_p2sh().parse_multisig(), None);
+
+        assert_eq!(Script::new_multisig(0, &[g]), Err(Error::Multisig(0, 1)));
+        assert_eq!(Script::new_multisig(2, &[g]), Err(Error::Multisig(2, 1)));
+        // redeem scripts are limited to 520 bytes
+        assert_eq!(Script::new_multisig(1, &[g; 16]), Err(Error::Multisig(1, 16)));
+        let max = Script::new_multisig(15, &[g; 15]).unwrap();
+        assert_eq!(max.len(), 513);
+        assert!(max.parse_multisig().is_some());
+        let uncompressed = PublicKey::from_str("0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8").unwrap();
+        assert!(Script::new_multisig(1, &[uncompressed; 7]).is_ok());
+        assert_eq!(Script::new_multisig(1, &[uncompressed; 8]), Err(Error::Multisig(1, 8)));
+        assert_eq!(Script::new().parse_multisig(), None);
+        assert!(!Script::new().is_sorted_multisig());
+    }
+
+    #[test]
+    fn script_builder_colored() {
+        let color_id = ColorIdentifier::nft(OutPoint::new(MalFixTxid::hash(b"issuance"), 0));
+        let colored = Builder::new().push_color(&color_id).push_int(1).into_script();
+        let mut expected = vec![0x21];
+        expected.extend(serialize(&color_id));
+        expected.extend_from_slice(&[0xbc, 0x51]);
+        assert_eq!(colored, Script::from(expected));
+
+        let pubkey_hash = PubkeyHash::hash(b"pubkey");
+        let p2pkh = Script::new_p2pkh(&pubkey_hash);
+        let cp2pkh = Builder::new().into_cp2pkh(&color_id, &pubkey_hash);
+        assert!(cp2pkh.is_cp2pkh());
+        assert_eq!(cp2pkh, p2pkh.add_color(color_id.clone()).unwrap());
+        assert_eq!(cp2pkh.classify(), ScriptType::Cp2pkh(color_id.clone(), pubkey_hash));
+
+        let script_hash = ScriptHash::hash(b"redeem script");
+        let cp2sh = Builder::new().into_cp2sh(&color_id, &script_hash);
+        assert!(cp2sh.is_cp2sh());
+        assert_eq!(cp2sh, Script::from(b"redeem script".to_vec()).to_p2sh().add_color(color_id.clone()).unwrap());
+        assert_eq!(cp2sh.color_id(), Some(color_id));
+    }
+
+    #[test]
+    fn script_op_return() {
+        let data = b"tapyrus token metadata";
+        let script = Script::new_op_return(data).unwrap();
+        assert_eq!(script, Builder::new().push_opcode(opcodes::all::OP_RETURN).push_slice(data).into_script());
+        assert_eq!(script.classify(), ScriptType::OpReturn);
+        assert_eq!(script.op_return_data(), Some(&data[..]));
+        assert_eq!(script.op_return_pushes(), Some(vec![&data[..]]));
+
+        // 80 bytes of data are pushed with OP_PUSHDATA1
+        let script = Script::new_op_return(&[0xab; 80]).unwrap();
+        assert_eq!(script.len(), MAX_OP_RETURN_RELAY);
+        assert_eq!(script.op_return_data(), Some(&[0xab; 80][..]));
+        assert_eq!(Script::new_op_return(&[0xab; 81]), Err(Error::OpReturnSize(84)));
+
+        let script = Script::new_op_return_pushes(&[&b"ipfs"[..], &[0x12; 34][..]]).unwrap();
+        assert_eq!(script.op_return_pushes(), Some(vec![&b"ipfs"[..], &[0x12; 34][..]]));
+        assert_eq!(script.op_return_data(), None);
+        assert_eq!(Script::new_op_return_pushes(&[&[0; 40][..], &[0; 41][..]]), Err(Error::OpReturnSize(84)));
+
+        let script = Script::new_op_return_pushes(&[]).unwrap();
+        assert_eq!(script, hex_script!("6a"));
+        assert_eq!(script.op_return_pushes(), Some(vec![]));
+        assert_eq!(script.op_return_data(), None);
+        assert_eq!(hex_script!("6a0b68656c6c6f20776f726c6476").op_return_pushes(), None);
+        assert_eq!(hex_script!("6a51").op_return_pushes(), Some(vec![&[1][..]]));
+        assert_eq!(hex_script!("6a4f60").op_return_pushes(), Some(vec![&[0x81][..], &[16][..]]));
+        assert_eq!(hex_script!("6a50").op_return_pushes(), None);
+        assert_eq!(hex_script!("6a4c").op_return_pushes(), None);
+        assert_eq!(hex_script!("76a91446c2fbfbecc99a63148fa076de58cf29b0bcf0b088ac").op_return_data(), None);
+    }
+
     #[test]
     fn color_identifier_test() {
         let color_id = ColorIdentifier::reissuable(hex_script!("76a91446c2fbfbecc99a63148fa076de58cf29b0bcf0b088ac"));
@@ -1185,7 +2445,7 @@ mod test {
 
     #[test]
     fn add_color_test() {
-        let out_point = OutPoint::new(Txid::from_hex("0101010101010101010101010101010101010101010101010101010101010101").unwrap(), 1);
+        let out_point = OutPoint::new(MalFixTxid::from_hex("0101010101010101010101010101010101010101010101010101010101010101").unwrap(), 1);
         let color_id = ColorIdentifier::nft(out_point);
         let p2pkh = hex_script!("76a91446c2fbfbecc99a63148fa076de58cf29b0bcf0b088ac");
         let p2sh = hex_script!("a9147620a79e8657d066cff10e21228bf983cf546ac687");
@@ -1208,7 +2468,7 @@ mod test {
 
     #[test]
     fn serialize_color_id() {
-        let out_point = OutPoint::new(Txid::from_hex("0101010101010101010101010101010101010101010101010101010101010101").unwrap(), 1);
+        let out_point = OutPoint::new(MalFixTxid::from_hex("0101010101010101010101010101010101010101010101010101010101010101").unwrap(), 1);
         let color_id = ColorIdentifier::nft(out_point);
 
         assert_eq!(format!("{}",color_id), "c3ec2fd806701a3f55808cbec3922c38dafaa3070c48c803e9043ee3642c660b46");
@@ -1221,6 +2481,45 @@ mod test {
         assert_eq!(serialize(&color_id), hex_script);
     }
 
+    #[test]
+    fn color_identifier_from_str() {
+        let out_point = OutPoint::new(MalFixTxid::from_hex("0101010101010101010101010101010101010101010101010101010101010101").unwrap(), 1);
+        let color_id = ColorIdentifier::nft(out_point);
+        let s = "c3ec2fd806701a3f55808cbec3922c38dafaa3070c48c803e9043ee3642c660b46";
+
+        assert_eq!(ColorIdentifier::from_str(s), Ok(color_id.clone()));
+        assert_eq!(ColorIdentifier::from_str(&color_id.to_string()), Ok(color_id.clone()));
+        assert_eq!(
+            ColorIdentifier::from_payload(TokenTypes::Nft, color_id.payload()),
+            color_id
+        );
+        assert_eq!(&color_id.payload()[..], &hex_decode(&s[2..]).unwrap()[..]);
+
+        assert_eq!(
+            ColorIdentifier::from_str("c4ec2fd806701a3f55808cbec3922c38dafaa3070c48c803e9043ee3642c660b46"),
+            Err(ParseColorIdentifierError::InvalidTokenType(0xc4))
+        );
+        assert_eq!(
+            ColorIdentifier::from_str("c3ec2fd806701a3f55808cbec3922c38dafaa3070c48c803e9043ee3642c660b"),
+            Err(ParseColorIdentifierError::InvalidLength(32))
+        );
+        assert!(ColorIdentifier::from_str("c3zz").is_err());
+        let invalid = hex_decode("c4ec2fd806701a3f55808cbec3922c38dafaa3070c48c803e9043ee3642c660b46").unwrap();
+        assert!(deserialize::<ColorIdentifier>(&invalid).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn color_identifier_serde() {
+        use serde_json;
+
+        let color_id = ColorIdentifier::reissuable(hex_script!("76a91446c2fbfbecc99a63148fa076de58cf29b0bcf0b088ac"));
+        let json = serde_json::to_value(&color_id).unwrap();
+        assert_eq!(json, serde_json::Value::String(color_id.to_string()));
+        let des: ColorIdentifier = serde_json::from_value(json).unwrap();
+        assert_eq!(color_id, des);
+    }
+
     #[test]
     fn p2sh_p2wsh_conversion() {
         // Test vectors taken from Core tests/data/script_tests.json
@@ -1306,6 +2605,71 @@ mod test {
         assert_eq!(v_nonmin_alt, slop_v_nonmin_alt);
     }
 
+    #[test]
+    fn test_iterator_offsets() {
+        let script = hex_script!("0169b24c0201026a");
+        let v: Vec<(usize, Instruction)> = script.iter(false).with_offsets().collect();
+        assert_eq!(
+            v,
+            vec![
+                (0, Instruction::PushBytes(&[105])),
+                (2, Instruction::Op(opcodes::OP_NOP3)),
+                (3, Instruction::PushBytes(&[1, 2])),
+                (7, Instruction::Op(opcodes::all::OP_RETURN)),
+            ]
+        );
+
+        let mut iter = script.iter(false);
+        assert_eq!(iter.offset(), 0);
+        iter.next();
+        assert_eq!(iter.offset(), 2);
+        assert_eq!(iter.by_ref().count(), 3);
+        assert_eq!(iter.offset(), script.len());
+    }
+
+    #[test]
+    fn test_iterator_lenient() {
+        // a non-minimal push is skipped as a whole
+        let nonminimal = hex_script!("4c0169b2");
+        let v: Vec<(usize, Instruction)> = nonminimal.iter(true).lenient().with_offsets().collect();
+        assert_eq!(
+            v,
+            vec![
+                (0, Instruction::Error(Error::NonMinimalPush)),
+                (3, Instruction::Op(opcodes::OP_NOP3)),
+            ]
+        );
+        let v: Vec<Instruction> = nonminimal.iter(false).lenient().collect();
+        assert_eq!(v, vec![Instruction::PushBytes(&[105]), Instruction::Op(opcodes::OP_NOP3)]);
+
+        // a push past the end of the script is skipped by its opcode only
+        let truncated = hex_script!("b2050102ac");
+        let v: Vec<Instruction> = truncated.iter(false).collect();
+        assert_eq!(v, vec![Instruction::Op(opcodes::OP_NOP3), Instruction::Error(Error::EarlyEndOfScript)]);
+        let v: Vec<(usize, Instruction)> = truncated.iter(false).lenient().with_offsets().collect();
+        assert_eq!(
+            v,
+            vec![
+                (0, Instruction::Op(opcodes::OP_NOP3)),
+                (1, Instruction::Error(Error::EarlyEndOfScript)),
+                (2, Instruction::PushBytes(&[2])),
+                (4, Instruction::Op(opcodes::all::OP_CHECKSIG)),
+            ]
+        );
+
+        let truncated_len = hex_script!("4d01");
+        let v: Vec<Instruction> = truncated_len.iter(false).collect();
+        assert_eq!(v, vec![Instruction::Error(Error::EarlyEndOfScript)]);
+        let v: Vec<(usize, Instruction)> = truncated_len.iter(false).lenient().with_offsets().collect();
+        assert_eq!(
+            v,
+            vec![
+                (0, Instruction::Error(Error::EarlyEndOfScript)),
+                (1, Instruction::Error(Error::EarlyEndOfScript)),
+            ]
+        );
+    }
+
 	#[test]
     fn script_ord() {
         let script_1 = Builder::new().push_slice(&[1,2,3,4]).into_script();
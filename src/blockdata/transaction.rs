@@ -28,33 +28,44 @@
 //! This module provides the structures and functions needed to support transactions.
 //!
 
-use std::default::Default;
-use std::{fmt, io};
+use core::default::Default;
+use core::fmt;
+#[cfg(feature = "std")] use std::error;
 
 use hashes::{self, Hash, sha256d};
 use hashes::hex::FromHex;
 
 use util::endian;
-#[cfg(feature="bitcoinconsensus")] use blockdata::script;
-use blockdata::script::Script;
+use util::amount::Amount;
+use util::token::{Color, TokenBalances};
+use util::fee::{FeeRate, Weight};
+use util::policy;
+use util::sighash::SighashCache;
+use blockdata::constants::MAX_MONEY;
+use blockdata::interpreter::{Interpreter, VerifyFlags};
+use blockdata::locktime::{LockTime, Sequence};
+use blockdata::script::{self, Builder, ColorIdentifier, Script, TokenTypes};
 use consensus::{encode, serialize, Decodable, Encodable};
 use hash_types::*;
+use io;
+use prelude::*;
 use VarInt;
 
 /// A reference to a transaction output
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct OutPoint {
-    /// The referenced transaction's txid
-    pub txid: Txid,
+    /// The referenced transaction's malleability-fixed txid
+    pub txid: MalFixTxid,
     /// The index of the referenced output in its transaction's vout
     pub vout: u32,
 }
-serde_struct_human_string_impl!(OutPoint, "an OutPoint", txid, vout);
+serde_consensus_impl!(OutPoint);
+arbitrary_struct_impl!(OutPoint, txid, vout);
 
 impl OutPoint {
     /// Create a new [OutPoint].
     #[inline]
-    pub fn new(txid: Txid, vout: u32) -> OutPoint {
+    pub fn new(txid: MalFixTxid, vout: u32) -> OutPoint {
         OutPoint {
             txid: txid,
             vout: vout,
@@ -95,7 +106,7 @@ pub enum ParseOutPointError {
     /// Error in TXID part.
     Txid(hashes::hex::Error),
     /// Error in vout part.
-    Vout(::std::num::ParseIntError),
+    Vout(::core::num::ParseIntError),
     /// Error in general format.
     Format,
     /// Size exceeds max.
@@ -116,7 +127,8 @@ impl fmt::Display for ParseOutPointError {
     }
 }
 
-impl ::std::error::Error for ParseOutPointError {
+#[cfg(feature = "std")]
+impl error::Error for ParseOutPointError {
     fn description(&self) -> &str {
         match *self {
             ParseOutPointError::Txid(_) => "TXID parse error",
@@ -127,7 +139,7 @@ impl ::std::error::Error for ParseOutPointError {
         }
     }
 
-    fn cause(&self) -> Option<&::std::error::Error> {
+    fn cause(&self) -> Option<&error::Error> {
         match *self {
             ParseOutPointError::Txid(ref e) => Some(e),
             ParseOutPointError::Vout(ref e) => Some(e),
@@ -148,7 +160,7 @@ fn parse_vout(s: &str) -> Result<u32, ParseOutPointError> {
     Ok(s.parse().map_err(ParseOutPointError::Vout)?)
 }
 
-impl ::std::str::FromStr for OutPoint {
+impl ::core::str::FromStr for OutPoint {
     type Err = ParseOutPointError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -164,7 +176,7 @@ impl ::std::str::FromStr for OutPoint {
             return Err(ParseOutPointError::Format);
         }
         Ok(OutPoint {
-            txid: Txid::from_hex(&s[..colon]).map_err(ParseOutPointError::Txid)?,
+            txid: MalFixTxid::from_hex(&s[..colon]).map_err(ParseOutPointError::Txid)?,
             vout: parse_vout(&s[colon+1..])?,
         })
     }
@@ -182,7 +194,7 @@ pub struct TxIn {
     /// conflicting transactions should be preferred, or 0xFFFFFFFF
     /// to ignore this feature. This is generally never used since
     /// the miner behaviour cannot be enforced.
-    pub sequence: u32,
+    pub sequence: Sequence,
     /// Witness data: an array of byte-arrays.
     /// Note that this field is *not* (de)serialized with the rest of the TxIn in
     /// Encodable/Decodable, as it is (de)serialized at the end of the full
@@ -191,13 +203,14 @@ pub struct TxIn {
     pub witness: Vec<Vec<u8>>
 }
 serde_struct_impl!(TxIn, previous_output, script_sig, sequence, witness);
+arbitrary_struct_impl!(TxIn, previous_output, script_sig, sequence, witness);
 
 impl Default for TxIn {
     fn default() -> TxIn {
         TxIn {
             previous_output: OutPoint::default(),
             script_sig: Script::new(),
-            sequence: u32::max_value(),
+            sequence: Sequence::MAX,
             witness: Vec::new(),
         }
     }
@@ -212,6 +225,7 @@ pub struct TxOut {
     pub script_pubkey: Script
 }
 serde_struct_impl!(TxOut, value, script_pubkey);
+arbitrary_struct_impl!(TxOut, value, script_pubkey);
 
 // This is used as a "null txout" in consensus signing code
 impl Default for TxOut {
@@ -220,6 +234,32 @@ impl Default for TxOut {
     }
 }
 
+impl TxOut {
+    /// The value of the output. The value of colored outputs is an amount
+    /// of their tokens rather than of TPC.
+    pub fn amount(&self) -> Amount {
+        Amount::from_sat(self.value)
+    }
+
+    /// Set the value of the output
+    pub fn set_amount(&mut self, amount: Amount) {
+        self.value = amount.as_sat();
+    }
+
+    /// The value below which this output is dust, costing more than it is
+    /// worth to create and spend at `dust_relay_fee`. Computed as Tapyrus
+    /// Core's `GetDustThreshold`; provably unspendable outputs are never
+    /// dust.
+    pub fn dust_threshold(&self, dust_relay_fee: FeeRate) -> u64 {
+        policy::dust_threshold(&self.script_pubkey, dust_relay_fee)
+    }
+
+    /// Whether this output is dust at `dust_relay_fee`
+    pub fn is_dust(&self, dust_relay_fee: FeeRate) -> bool {
+        self.value < self.dust_threshold(dust_relay_fee)
+    }
+}
+
 /// A Bitcoin transaction, which describes an authenticated movement of coins.
 ///
 /// If any inputs have nonempty witnesses, the entire transaction is serialized
@@ -256,13 +296,14 @@ pub struct Transaction {
     pub version: u32,
     /// Block number before which this transaction is valid, or 0 for
     /// valid immediately.
-    pub lock_time: u32,
+    pub lock_time: LockTime,
     /// List of inputs
     pub input: Vec<TxIn>,
     /// List of outputs
     pub output: Vec<TxOut>,
 }
-serde_struct_impl!(Transaction, version, lock_time, input, output);
+serde_consensus_impl!(Transaction);
+arbitrary_struct_impl!(Transaction, version, lock_time, input, output);
 
 impl Transaction {
     /// Computes a "normalized TXID" which does not include any signatures.
@@ -302,8 +343,8 @@ impl Transaction {
 
     /// Computes an "immutable TXID".  The double SHA256 taken from a transaction
     /// after stripping it of all input scripts including their length prefixes.
-    pub fn malfix_txid(&self) -> Txid {
-        let mut enc = sha256d::Hash::engine();
+    pub fn malfix_txid(&self) -> MalFixTxid {
+        let mut enc = MalFixTxid::engine();
         self.version.consensus_encode(&mut enc).unwrap();
         VarInt(self.input.len() as u64)
             .consensus_encode(&mut enc)
@@ -314,7 +355,7 @@ impl Transaction {
         }
         self.output.consensus_encode(&mut enc).unwrap();
         self.lock_time.consensus_encode(&mut enc).unwrap();
-        Txid::from_engine(enc)
+        MalFixTxid::from_engine(enc)
     }
 
     /// Computes a signature hash for a given input index with a given sighash flag.
@@ -364,7 +405,7 @@ impl Transaction {
                 tx.input.push(TxIn {
                     previous_output: input.previous_output,
                     script_sig: if n == input_index { script_pubkey.clone() } else { Script::new() },
-                    sequence: if n != input_index && (sighash == SigHashType::Single || sighash == SigHashType::None) { 0 } else { input.sequence },
+                    sequence: if n != input_index && (sighash == SigHashType::Single || sighash == SigHashType::None) { Sequence::ZERO } else { input.sequence },
                     witness: vec![],
                 });
             }
@@ -430,29 +471,451 @@ impl Transaction {
         }
     }
 
-    #[cfg(feature="bitcoinconsensus")]
-    /// Verify that this transaction is able to spend its inputs
-    /// The lambda spent should not return the same TxOut twice!
-    pub fn verify<S>(&self, mut spent: S) -> Result<(), script::Error>
+    /// Gets the size of the serialized transaction, witnesses included
+    pub fn size(&self) -> usize {
+        self.consensus_encode(io::sink()).expect("sinks don't error")
+    }
+
+    /// Gets the size of the serialized transaction without its witnesses
+    pub fn stripped_size(&self) -> usize {
+        // version and lock_time
+        8 + self.input.consensus_encode(io::sink()).expect("sinks don't error")
+            + self.output.consensus_encode(io::sink()).expect("sinks don't error")
+    }
+
+    /// Gets the virtual size of the transaction, its weight divided by 4 and
+    /// rounded up, as defined by BIP141
+    pub fn vsize(&self) -> usize {
+        (self.get_weight() + 3) / 4
+    }
+
+    /// Gets the weight of this transaction as a [Weight]
+    pub fn weight(&self) -> Weight {
+        Weight::from_wu(self.get_weight() as u64)
+    }
+
+    /// Gets the fee rate of this transaction, paying `fee`, rounded down.
+    /// Saturates if the fee is too large to be expressed as a rate.
+    pub fn fee_rate(&self, fee: Amount) -> FeeRate {
+        FeeRate::from_fee_and_weight(fee, self.weight())
+            .unwrap_or(FeeRate::from_sat_per_kwu(u64::max_value()))
+    }
+
+    /// The amounts of TPC and of tokens sent to the outputs of the
+    /// transaction, `None` if the total of a color overflows
+    pub fn output_balances(&self) -> Option<TokenBalances> {
+        TokenBalances::from_outputs(&self.output)
+    }
+
+    /// The amounts of TPC and of tokens spent by the transaction. `spent`
+    /// looks up the outputs spent by the transaction; returns `None` if one
+    /// of them is unknown or if the total of a color overflows.
+    pub fn input_balances<S>(&self, mut spent: S) -> Option<TokenBalances>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let mut balances = TokenBalances::new();
+        if self.is_coinbase() {
+            return Some(balances);
+        }
+        for input in &self.input {
+            let output = spent(&input.previous_output)?;
+            balances.checked_add_amount(Color::of(&output.script_pubkey), output.value)?;
+        }
+        Some(balances)
+    }
+
+    /// The colored outputs issuing new tokens, with their color and amount.
+    /// An output issues tokens when one of the inputs identifies its color:
+    /// the script of the spent output for reissuable tokens, the spent
+    /// outpoint for the others. `spent` looks up the outputs spent by the
+    /// transaction; returns `None` if one of them is unknown.
+    pub fn token_issuances<S>(&self, spent: S) -> Option<Vec<(ColorIdentifier, u64)>>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let spent_outputs = self.spent_outputs(spent)?;
+        Some(self.colored_outputs().filter(|&(ref color, _)| self.issues(&spent_outputs, color)).collect())
+    }
+
+    /// The colored outputs transferring tokens spent by the transaction,
+    /// with their color and amount, the tokens issued by the transaction
+    /// excepted. `spent` looks up the outputs spent by the transaction;
+    /// returns `None` if one of them is unknown.
+    pub fn token_transfers<S>(&self, spent: S) -> Option<Vec<(ColorIdentifier, u64)>>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let spent_outputs = self.spent_outputs(spent)?;
+        let inputs = colored_totals(&spent_outputs);
+        Some(self.colored_outputs().filter(|&(ref color, _)| {
+            inputs.contains_key(color) && !self.issues(&spent_outputs, color)
+        }).collect())
+    }
+
+    /// The amounts of tokens burnt by the transaction, spent but not sent
+    /// to its outputs, by color. `spent` looks up the outputs spent by the
+    /// transaction; returns `None` if one of them is unknown.
+    pub fn token_burns<S>(&self, spent: S) -> Option<Vec<(ColorIdentifier, u64)>>
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let spent_outputs = self.spent_outputs(spent)?;
+        let mut totals = colored_totals(&spent_outputs);
+        for (color, value) in self.colored_outputs() {
+            if let Some(total) = totals.get_mut(&color) {
+                *total = total.saturating_sub(value);
+            }
+        }
+        Some(totals.into_iter().filter(|&(_, value)| value > 0).collect())
+    }
+
+    /// The colored outputs of the transaction, with their color and amount
+    fn colored_outputs<'a>(&'a self) -> impl Iterator<Item = (ColorIdentifier, u64)> + 'a {
+        self.output.iter().filter_map(|output| output.script_pubkey.color_id().map(|color| (color, output.value)))
+    }
+
+    /// The outputs spent by the transaction, none for a coinbase
+    fn spent_outputs<S>(&self, mut spent: S) -> Option<Vec<TxOut>>
         where S: FnMut(&OutPoint) -> Option<TxOut> {
-        let tx = serialize(&*self);
-        for (idx, input) in self.input.iter().enumerate() {
-            if let Some(output) = spent(&input.previous_output) {
-                output.script_pubkey.verify(idx, output.value, tx.as_slice())?;
+        if self.is_coinbase() {
+            return Some(Vec::new());
+        }
+        self.input.iter().map(|input| spent(&input.previous_output)).collect()
+    }
+
+    /// Whether the transaction, spending `spent_outputs`, issues tokens of
+    /// `color`: reissuable tokens are identified by the script of a spent
+    /// output, the others by a spent outpoint
+    fn issues(&self, spent_outputs: &[TxOut], color: &ColorIdentifier) -> bool {
+        match color.token_type {
+            TokenTypes::Reissuable => spent_outputs.iter()
+                .any(|output| ColorIdentifier::reissuable(output.script_pubkey.clone()) == *color),
+            TokenTypes::NonReissuable => self.input.iter()
+                .any(|input| ColorIdentifier::non_reissuable(input.previous_output) == *color),
+            TokenTypes::Nft => self.input.iter()
+                .any(|input| ColorIdentifier::nft(input.previous_output) == *color),
+        }
+    }
+
+    /// Verify that this transaction is able to spend its inputs: the
+    /// scripts of each input execute successfully, `OP_COLOR` included, no
+    /// output is spent twice and tokens are only created by issuances.
+    /// `spent` looks up the outputs spent by the transaction.
+    pub fn verify<F>(&self, spent: F) -> Result<(), script::Error>
+        where F: Fn(&OutPoint) -> Option<TxOut> {
+        self.verify_with_flags(spent, VerifyFlags::CONSENSUS)
+    }
+
+    /// Same as `verify`, executing the scripts of the inputs under `flags`,
+    /// which may not have `CLEANSTACK` without `P2SH`
+    pub fn verify_with_flags<F>(&self, spent: F, flags: VerifyFlags) -> Result<(), script::Error>
+        where F: Fn(&OutPoint) -> Option<TxOut> {
+        let interpreter = Interpreter::new(flags).map_err(|_| script::Error::InvalidFlags)?;
+        if self.is_coinbase() {
+            return Ok(());
+        }
+        let mut outpoints = BTreeSet::new();
+        let mut spent_outputs = Vec::with_capacity(self.input.len());
+        for input in &self.input {
+            if !outpoints.insert(input.previous_output) {
+                return Err(script::Error::DuplicateInput(input.previous_output));
+            }
+            match spent(&input.previous_output) {
+                Some(output) => spent_outputs.push(output),
+                None => return Err(script::Error::UnknownSpentOutput(input.previous_output)),
+            }
+        }
+
+        let mut cache = SighashCache::new(self);
+        for (idx, output) in spent_outputs.iter().enumerate() {
+            interpreter.verify_with_cache(&mut cache, idx, &output.script_pubkey)
+                .map_err(|e| script::Error::Execution(idx, e))?;
+        }
+        self.check_token_balance(&spent_outputs)
+    }
+
+    /// Count the signature operations of the transaction as Tapyrus Core
+    /// does to limit them per block: those of its script_sigs and script
+    /// pubkeys counted inaccurately, plus those of the redeem scripts of the
+    /// P2SH and CP2SH outputs it spends, counted accurately. The outputs
+    /// spent are looked up with `spent`; a coinbase spends none.
+    pub fn total_sigops<F>(&self, spent: F) -> Result<usize, script::Error>
+        where F: Fn(&OutPoint) -> Option<TxOut> {
+        let legacy = self.input.iter().map(|input| input.script_sig.count_sigops(false)).sum::<usize>()
+            + self.output.iter().map(|output| output.script_pubkey.count_sigops(false)).sum::<usize>();
+        if self.is_coinbase() {
+            return Ok(legacy);
+        }
+        let mut p2sh = 0;
+        for input in &self.input {
+            let output = spent(&input.previous_output)
+                .ok_or(script::Error::UnknownSpentOutput(input.previous_output))?;
+            if output.script_pubkey.is_p2sh() || output.script_pubkey.is_cp2sh() {
+                p2sh += output.script_pubkey.count_p2sh_sigops(&input.script_sig);
+            }
+        }
+        Ok(legacy + p2sh)
+    }
+
+    /// Check that the tokens sent to the outputs, by color, are spent by
+    /// the inputs or issued by the transaction
+    pub(crate) fn check_token_balance(&self, spent_outputs: &[TxOut]) -> Result<(), script::Error> {
+        let inputs = colored_totals(spent_outputs);
+        let outputs = colored_totals(&self.output);
+
+        for (color, value) in outputs {
+            let balanced = if self.issues(spent_outputs, &color) {
+                // a single non fungible token is issued
+                color.token_type != TokenTypes::Nft || value == 1
             } else {
-                return Err(script::Error::UnknownSpentOutput(input.previous_output.clone()));
+                value <= inputs.get(&color).cloned().unwrap_or(0)
+            };
+            if !balanced {
+                return Err(script::Error::TokenBalance(color));
             }
         }
         Ok(())
     }
 
-    /// Is this a coin base transaction?
-    /// 
+    /// Is this a coinbase transaction?
+    ///
     /// In Tapyrus, a coinbase transaction has a default txid ("0000000000000000000000000000000000000000000000000000000000000000") in its input.
     /// See https://github.com/chaintope/tapyrus-core/blob/master/doc/tapyrus/fix_transaction_malleability.md#tapyrus-transaction-structure for more information.
-    pub fn is_coin_base(&self) -> bool {
+    pub fn is_coinbase(&self) -> bool {
         self.input.len() == 1 && self.input[0].previous_output.txid == Default::default()
     }
+
+    /// Is this a coin base transaction?
+    #[deprecated(note = "use is_coinbase")]
+    pub fn is_coin_base(&self) -> bool {
+        self.is_coinbase()
+    }
+
+    /// Whether the transaction may be included in the block at `height`,
+    /// whose previous blocks have the median time `time`: its lock time is
+    /// 0 or satisfied, or all its inputs have the final sequence number.
+    pub fn is_final(&self, height: u32, time: u32) -> bool {
+        self.lock_time == LockTime::ZERO
+            || self.lock_time.is_satisfied_by(height, time)
+            || self.input.iter().all(|input| input.sequence.is_final())
+    }
+
+    /// The height of the block of a coinbase transaction, which is the
+    /// index of the output spent by its input. Returns `None` if this is not
+    /// a coinbase transaction.
+    pub fn coinbase_height(&self) -> Option<u32> {
+        if self.is_coinbase() {
+            Some(self.input[0].previous_output.vout)
+        } else {
+            None
+        }
+    }
+}
+
+/// The amounts of tokens in `outputs`, by color
+fn colored_totals(outputs: &[TxOut]) -> BTreeMap<ColorIdentifier, u64> {
+    let mut totals = BTreeMap::new();
+    for output in outputs {
+        if let Some(color) = output.script_pubkey.color_id() {
+            let total = totals.entry(color).or_insert(0u64);
+            *total = total.saturating_add(output.value);
+        }
+    }
+    totals
+}
+
+/// Builds the coinbase transaction of a block
+///
+/// As Tapyrus transaction ids do not commit to script_sigs, the coinbase
+/// input spends the null txid at the index of the block height, which keeps
+/// the coinbase transactions of different blocks distinct. The script_sig
+/// holds arbitrary data.
+#[derive(Clone, Debug)]
+pub struct CoinbaseBuilder {
+    tx: Transaction,
+    script_sig: Builder,
+}
+
+impl CoinbaseBuilder {
+    /// Start building a version 1 coinbase transaction for the block at
+    /// `height`, with an empty script_sig, no outputs and a lock time of 0
+    pub fn new(height: u32) -> CoinbaseBuilder {
+        CoinbaseBuilder {
+            tx: Transaction {
+                version: 1,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Default::default(), height),
+                    script_sig: Script::new(),
+                    sequence: Sequence::MAX,
+                    witness: vec![],
+                }],
+                output: vec![],
+            },
+            script_sig: Builder::new(),
+        }
+    }
+
+    /// Set the version of the transaction
+    pub fn version(mut self, version: u32) -> CoinbaseBuilder {
+        self.tx.version = version;
+        self
+    }
+
+    /// Set the lock time of the transaction
+    pub fn lock_time(mut self, lock_time: LockTime) -> CoinbaseBuilder {
+        self.tx.lock_time = lock_time;
+        self
+    }
+
+    /// Push `data` to the script_sig
+    pub fn push_data(mut self, data: &[u8]) -> CoinbaseBuilder {
+        self.script_sig = self.script_sig.push_slice(data);
+        self
+    }
+
+    /// Replace the script_sig
+    pub fn script_sig(mut self, script_sig: Script) -> CoinbaseBuilder {
+        self.script_sig = Builder::from(script_sig.into_bytes());
+        self
+    }
+
+    /// Add an output paying `value` to `script_pubkey`
+    pub fn output(mut self, script_pubkey: Script, value: Amount) -> CoinbaseBuilder {
+        self.tx.output.push(TxOut {
+            value: value.as_sat(),
+            script_pubkey: script_pubkey,
+        });
+        self
+    }
+
+    /// Build the coinbase transaction
+    pub fn build(self) -> Transaction {
+        let mut tx = self.tx;
+        tx.input[0].script_sig = self.script_sig.into_script();
+        tx
+    }
+}
+
+/// An error building a transaction with `TxBuilder`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TxBuilderError {
+    /// The transaction has no inputs
+    NoInputs,
+    /// The transaction has no outputs
+    NoOutputs,
+    /// The values of the outputs, or of the outputs of a color, exceed
+    /// `MAX_MONEY`
+    ValueOverflow,
+    /// The output at this index is dust
+    Dust(usize),
+}
+
+impl fmt::Display for TxBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TxBuilderError::NoInputs => f.write_str("transaction has no inputs"),
+            TxBuilderError::NoOutputs => f.write_str("transaction has no outputs"),
+            TxBuilderError::ValueOverflow => f.write_str("output values exceed the maximum"),
+            TxBuilderError::Dust(index) => write!(f, "output {} is dust", index),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for TxBuilderError {
+    fn cause(&self) -> Option<&error::Error> { None }
+}
+
+/// Builds unsigned transactions, checking the values of their outputs
+#[derive(Clone, Debug)]
+pub struct TxBuilder {
+    tx: Transaction,
+    dust_relay_fee: FeeRate,
+}
+
+impl TxBuilder {
+    /// Start building a version 1 transaction, with a lock time of 0 and
+    /// outputs checked against the default dust relay fee
+    pub fn new() -> TxBuilder {
+        TxBuilder {
+            tx: Transaction {
+                version: 1,
+                lock_time: LockTime::ZERO,
+                input: vec![],
+                output: vec![],
+            },
+            dust_relay_fee: FeeRate::DEFAULT_DUST_RELAY,
+        }
+    }
+
+    /// Set the version of the transaction
+    pub fn version(mut self, version: u32) -> TxBuilder {
+        self.tx.version = version;
+        self
+    }
+
+    /// Set the lock time of the transaction
+    pub fn lock_time(mut self, lock_time: LockTime) -> TxBuilder {
+        self.tx.lock_time = lock_time;
+        self
+    }
+
+    /// Add an input spending `previous_output`, with an empty script_sig
+    pub fn input(mut self, previous_output: OutPoint, sequence: Sequence) -> TxBuilder {
+        self.tx.input.push(TxIn {
+            previous_output: previous_output,
+            script_sig: Script::new(),
+            sequence: sequence,
+            witness: vec![],
+        });
+        self
+    }
+
+    /// Add an output paying `value` to `script_pubkey`. The value of a
+    /// colored output is an amount of tokens.
+    pub fn output(mut self, script_pubkey: Script, value: Amount) -> TxBuilder {
+        self.tx.output.push(TxOut {
+            value: value.as_sat(),
+            script_pubkey: script_pubkey,
+        });
+        self
+    }
+
+    /// Set the fee rate used to detect dust outputs, `FeeRate::ZERO`
+    /// allowing them
+    pub fn dust_relay_fee(mut self, dust_relay_fee: FeeRate) -> TxBuilder {
+        self.dust_relay_fee = dust_relay_fee;
+        self
+    }
+
+    /// Build the unsigned transaction. Values are summed by color, the
+    /// uncolored outputs paying TPC and colored ones tokens; only uncolored
+    /// outputs may be dust.
+    pub fn build(self) -> Result<Transaction, TxBuilderError> {
+        if self.tx.input.is_empty() {
+            return Err(TxBuilderError::NoInputs);
+        }
+        if self.tx.output.is_empty() {
+            return Err(TxBuilderError::NoOutputs);
+        }
+        let mut totals: BTreeMap<&[u8], u64> = BTreeMap::new();
+        for (index, output) in self.tx.output.iter().enumerate() {
+            let color: &[u8] = if output.script_pubkey.is_colored() {
+                // the color identifier pushed at the start of the script
+                &output.script_pubkey.as_bytes()[1..34]
+            } else {
+                if output.is_dust(self.dust_relay_fee) {
+                    return Err(TxBuilderError::Dust(index));
+                }
+                &[]
+            };
+            let total = totals.entry(color).or_insert(0);
+            *total = total.checked_add(output.value)
+                .filter(|&total| total <= MAX_MONEY)
+                .ok_or(TxBuilderError::ValueOverflow)?;
+        }
+        Ok(self.tx)
+    }
+}
+
+impl Default for TxBuilder {
+    fn default() -> TxBuilder {
+        TxBuilder::new()
+    }
 }
 
 impl_consensus_encoding!(TxOut, value, script_pubkey);
@@ -527,6 +990,19 @@ impl Encodable for Transaction {
         len += self.lock_time.consensus_encode(s)?;
         Ok(len)
     }
+
+    fn consensus_encoded_len(&self) -> usize {
+        let mut len = self.version.consensus_encoded_len();
+        len += self.input.consensus_encoded_len();
+        len += self.output.consensus_encoded_len();
+        if self.input.is_empty() || self.input.iter().any(|input| !input.witness.is_empty()) {
+            len += 2;
+            for input in &self.input {
+                len += input.witness.consensus_encoded_len();
+            }
+        }
+        len + self.lock_time.consensus_encoded_len()
+    }
 }
 
 impl Decodable for Transaction {
@@ -545,7 +1021,7 @@ impl Decodable for Transaction {
                         txin.witness = Decodable::consensus_decode(&mut d)?;
                     }
                     if !input.is_empty() && input.iter().all(|input| input.witness.is_empty()) {
-                        Err(encode::Error::ParseFailed("witness flag set but no witnesses present"))
+                        Err(encode::Error::Parse(encode::ParseError::Other("witness flag set but no witnesses present")))
                     } else {
                         Ok(Transaction {
                             version: version,
@@ -557,7 +1033,7 @@ impl Decodable for Transaction {
                 }
                 // We don't support anything else
                 x => {
-                    Err(encode::Error::UnsupportedSegwitFlag(x))
+                    Err(encode::Error::Parse(encode::ParseError::UnsupportedSegwitFlag(x)))
                 }
             }
         // non-segwit
@@ -595,7 +1071,7 @@ pub enum SigHashType {
 
 impl SigHashType {
      /// Break the sighash flag into the "real" sighash flag and the ANYONECANPAY boolean
-     fn split_anyonecanpay_flag(&self) -> (SigHashType, bool) {
+     pub(crate) fn split_anyonecanpay_flag(&self) -> (SigHashType, bool) {
          match *self {
              SigHashType::All		=> (SigHashType::All, false),
              SigHashType::None		=> (SigHashType::None, false),
@@ -629,11 +1105,13 @@ impl SigHashType {
 
 #[cfg(test)]
 mod tests {
-    use super::{OutPoint, ParseOutPointError, Transaction, TxIn};
+    use super::{CoinbaseBuilder, OutPoint, ParseOutPointError, Transaction, TxBuilder, TxBuilderError, TxIn, TxOut};
 
     use std::str::FromStr;
+    use blockdata::locktime::{LockTime, Sequence};
     use blockdata::script::Script;
     use consensus::encode::serialize;
+    use consensus::encode::serialized_len;
     use consensus::encode::deserialize;
 
     use hashes::Hash;
@@ -664,12 +1142,12 @@ mod tests {
 
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:42"),
                    Ok(OutPoint{
-                       txid: Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
+                       txid: MalFixTxid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
                        vout: 42,
                    }));
         assert_eq!(OutPoint::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0"),
                    Ok(OutPoint{
-                       txid: Txid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
+                       txid: MalFixTxid::from_hex("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456").unwrap(),
                        vout: 0,
                    }));
     }
@@ -685,20 +1163,30 @@ mod tests {
         let txin = TxIn::default();
         assert_eq!(txin.previous_output, OutPoint::default());
         assert_eq!(txin.script_sig, Script::new());
-        assert_eq!(txin.sequence, 0xFFFFFFFF);
+        assert_eq!(txin.sequence, Sequence::MAX);
         assert_eq!(txin.previous_output, OutPoint::default());
         assert_eq!(txin.witness.len(), 0 as usize);
     }
 
+    #[test]
+    fn test_txout_amount() {
+        use util::amount::Amount;
+
+        let mut txout = TxOut { value: 5000, script_pubkey: Script::new() };
+        assert_eq!(txout.amount(), Amount::from_sat(5000));
+        txout.set_amount(Amount::ONE_TPC);
+        assert_eq!(txout.value, 100_000_000);
+    }
+
     #[test]
     fn test_is_coinbase () {
         use blockdata::block::Block;
         let hex_block = Vec::<u8>::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000c0d6961ad2819f74eb6d085f04f9cceb0a9a6d5c153fd3c39fc47c3ca0bb548f85fbd09a5f7d8ac4c9552e52931ef6672984f64e52ad6d05d1cdb18907da8527db317c5e012103addb2555f37abf8f28f11f498bec7bd1460e7243c1813847c49a7ae326a97d1c40e2d9e39bfb25f5534cf3fa235697e18efa81ce02e161946a73b9b20b3641576a9636f19747447ab8b02a97a9d96ecdaf0aa3a56b93f9e8f81d55252854270617010100000001000000000000000000000000000000000000000000000000000000000000000000000000222103addb2555f37abf8f28f11f498bec7bd1460e7243c1813847c49a7ae326a97d1cffffffff0100f2052a010000001976a914a15f16ea2ba840d178e4c19781abca5f4fb1b4c288ac00000000").unwrap();
         let block: Block = deserialize(&hex_block).unwrap();
-        assert! (block.txdata[0].is_coin_base());
+        assert! (block.txdata[0].is_coinbase());
         let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
         let tx: Transaction = deserialize(&hex_tx).unwrap();
-        assert!(!tx.is_coin_base());
+        assert!(!tx.is_coinbase());
     }
 
     #[test]
@@ -717,13 +1205,14 @@ mod tests {
                    "ce9ea9f6f5e422c6a9dbcddb3b9a14d1c78fab9ab520cb281aa2a74a09575da1".to_string());
         assert_eq!(realtx.input[0].previous_output.vout, 1);
         assert_eq!(realtx.output.len(), 1);
-        assert_eq!(realtx.lock_time, 0);
+        assert_eq!(realtx.lock_time, LockTime::ZERO);
 
         assert_eq!(format!("{:x}", realtx.txid()),
                    "a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7".to_string());
         assert_eq!(format!("{:x}", realtx.wtxid()),
                    "a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7".to_string());
         assert_eq!(realtx.get_weight(), 193*4);
+        assert_eq!(serialized_len(&realtx), hex_tx.len());
     }
 
     #[test]
@@ -748,13 +1237,14 @@ mod tests {
                    "7cac3cf9a112cf04901a51d605058615d56ffe6d04b45270e89d1720ea955859".to_string());
         assert_eq!(realtx.input[0].previous_output.vout, 1);
         assert_eq!(realtx.output.len(), 1);
-        assert_eq!(realtx.lock_time, 0);
+        assert_eq!(realtx.lock_time, LockTime::ZERO);
 
         assert_eq!(format!("{:x}", realtx.txid()),
                    "f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206".to_string());
         assert_eq!(format!("{:x}", realtx.wtxid()),
                    "80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5".to_string());
         assert_eq!(realtx.get_weight(), 442);
+        assert_eq!(serialized_len(&realtx), hex_tx.len());
     }
 
     #[test]
@@ -769,6 +1259,7 @@ mod tests {
 
         let reser = serialize(&tx);
         assert_eq!(hex_tx, reser);
+        assert_eq!(serialized_len(&tx), hex_tx.len());
     }
 
     #[test]
@@ -786,6 +1277,23 @@ mod tests {
         assert!(old_ntxid != tx.ntxid());
     }
 
+    #[test]
+    fn test_malfix_txid() {
+        let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+        let mut tx: Transaction = deserialize(&hex_tx).unwrap();
+
+        let old_txid = tx.txid();
+        let old_malfix_txid = tx.malfix_txid();
+        assert!(old_txid.as_hash() != old_malfix_txid.as_hash());
+        // changing sigs only affects the txid
+        tx.input[0].script_sig = Script::new();
+        assert_eq!(old_malfix_txid, tx.malfix_txid());
+        assert!(old_txid != tx.txid());
+        // changing pks affects both
+        tx.output[0].script_pubkey = Script::new();
+        assert!(old_malfix_txid != tx.malfix_txid());
+    }
+
     #[test]
     fn test_txid() {
         // segwit tx from Liquid integration tests, txid/hash from Core decoderawtransaction
@@ -824,6 +1332,9 @@ mod tests {
         assert_eq!(format!("{:x}", tx.wtxid()), "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4");
         assert_eq!(format!("{:x}", tx.txid()), "9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec");
         assert_eq!(tx.get_weight(), 2718);
+        assert_eq!(tx.size(), hex_tx.len());
+        assert_eq!(tx.get_weight(), tx.stripped_size() * 3 + tx.size());
+        assert_eq!(tx.vsize(), 680);
 
         // non-segwit tx from my mempool
         let hex_tx = Vec::<u8>::from_hex(
@@ -838,6 +1349,193 @@ mod tests {
 
         assert_eq!(format!("{:x}", tx.wtxid()), "971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd");
         assert_eq!(format!("{:x}", tx.txid()), "971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd");
+        assert_eq!(tx.size(), hex_tx.len());
+        assert_eq!(tx.stripped_size(), hex_tx.len());
+        assert_eq!(tx.vsize(), hex_tx.len());
+    }
+
+    #[test]
+    fn test_is_final() {
+        let outpoint = OutPoint::from_str("c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d0:1").unwrap();
+        let tx = TxBuilder::new()
+            .lock_time(LockTime::Blocks(100))
+            .input(outpoint, Sequence::ENABLE_LOCKTIME_NO_RBF)
+            .output(hex_script!("6a"), ::util::amount::Amount::ZERO)
+            .build()
+            .unwrap();
+        assert!(!tx.is_final(100, 0));
+        assert!(tx.is_final(101, 0));
+
+        let mut tx = tx;
+        tx.lock_time = LockTime::Seconds(1_600_000_000);
+        assert!(!tx.is_final(u32::max_value(), 1_600_000_000));
+        assert!(tx.is_final(0, 1_600_000_001));
+        tx.input[0].sequence = Sequence::MAX;
+        assert!(tx.is_final(0, 0));
+    }
+
+    #[test]
+    fn test_coinbase_builder() {
+        use util::amount::Amount;
+
+        let p2pkh = hex_script!("76a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688ac");
+        let tx = CoinbaseBuilder::new(1234)
+            .push_data(b"tapyrus")
+            .output(p2pkh.clone(), Amount::from_sat(5000))
+            .build();
+        assert!(tx.is_coinbase());
+        assert_eq!(tx.coinbase_height(), Some(1234));
+        assert_eq!(tx.input[0].previous_output, OutPoint::new(Default::default(), 1234));
+        assert_eq!(tx.input[0].script_sig, hex_script!("0774617079727573"));
+        assert_eq!(tx.input[0].sequence, Sequence::MAX);
+        assert_eq!(tx.output, vec![TxOut { value: 5000, script_pubkey: p2pkh.clone() }]);
+
+        // coinbase transactions at different heights have different ids
+        let other = CoinbaseBuilder::new(1235)
+            .script_sig(hex_script!("0774617079727573"))
+            .output(p2pkh, Amount::from_sat(5000))
+            .build();
+        assert_eq!(other.input[0].script_sig, tx.input[0].script_sig);
+        assert_ne!(other.malfix_txid(), tx.malfix_txid());
+
+        let spending = TxBuilder::new()
+            .input(OutPoint::new(tx.malfix_txid(), 0), Sequence::MAX)
+            .output(hex_script!("6a"), Amount::ZERO)
+            .build()
+            .unwrap();
+        assert_eq!(spending.coinbase_height(), None);
+    }
+
+    #[test]
+    fn test_tx_builder() {
+        use blockdata::constants::MAX_MONEY;
+        use blockdata::script::ColorIdentifier;
+        use util::amount::Amount;
+        use util::fee::FeeRate;
+
+        let p2pkh = hex_script!("76a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688ac");
+        let colored = p2pkh.add_color(ColorIdentifier::reissuable(p2pkh.clone())).unwrap();
+        let op_return = hex_script!("6a0401020304");
+        let outpoint = OutPoint::from_str("c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d0:1").unwrap();
+
+        let tx = TxBuilder::new()
+            .version(2)
+            .lock_time(LockTime::Blocks(100))
+            .input(outpoint, Sequence::ENABLE_LOCKTIME_NO_RBF)
+            .output(p2pkh.clone(), Amount::from_sat(546))
+            .output(colored.clone(), Amount::from_sat(u64::max_value()))
+            .output(op_return.clone(), Amount::ZERO)
+            .build()
+            .unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, LockTime::Blocks(100));
+        assert_eq!(tx.input, vec![TxIn { previous_output: outpoint, sequence: Sequence::ENABLE_LOCKTIME_NO_RBF, ..TxIn::default() }]);
+        assert_eq!(tx.output[0], TxOut { value: 546, script_pubkey: p2pkh.clone() });
+        assert_eq!(tx.output[1].value, u64::max_value());
+        assert_eq!(tx.output[2].value, 0);
+
+        assert_eq!(tx.output[0].dust_threshold(FeeRate::DEFAULT_DUST_RELAY), 546);
+        assert_eq!(tx.output[2].dust_threshold(FeeRate::DEFAULT_DUST_RELAY), 0);
+        assert_eq!(
+            TxBuilder::new().input(outpoint, Sequence::ZERO).output(p2pkh.clone(), Amount::from_sat(545)).build(),
+            Err(TxBuilderError::Dust(0))
+        );
+        assert!(TxBuilder::new()
+            .input(outpoint, Sequence::ZERO)
+            .output(p2pkh.clone(), Amount::from_sat(1))
+            .dust_relay_fee(FeeRate::ZERO)
+            .build()
+            .is_ok());
+        assert_eq!(
+            TxBuilder::new()
+                .input(outpoint, Sequence::ZERO)
+                .output(colored.clone(), Amount::from_sat(u64::max_value()))
+                .output(colored.clone(), Amount::ONE_SAT)
+                .build(),
+            Err(TxBuilderError::ValueOverflow)
+        );
+        assert!(TxBuilder::new()
+            .input(outpoint, Sequence::ZERO)
+            .output(p2pkh.clone(), Amount::from_sat(MAX_MONEY - 1000))
+            .output(colored.clone(), Amount::from_sat(MAX_MONEY))
+            .output(p2pkh.clone(), Amount::from_sat(1000))
+            .build()
+            .is_ok());
+        assert_eq!(
+            TxBuilder::new()
+                .input(outpoint, Sequence::ZERO)
+                .output(p2pkh.clone(), Amount::from_sat(MAX_MONEY - 1000))
+                .output(p2pkh.clone(), Amount::from_sat(1001))
+                .build(),
+            Err(TxBuilderError::ValueOverflow)
+        );
+        assert_eq!(TxBuilder::new().output(p2pkh.clone(), Amount::ONE_BTC).build(), Err(TxBuilderError::NoInputs));
+        assert_eq!(TxBuilder::new().input(outpoint, Sequence::ZERO).build(), Err(TxBuilderError::NoOutputs));
+    }
+
+    #[test]
+    fn test_token_classification() {
+        use blockdata::script::ColorIdentifier;
+        use util::amount::Amount;
+
+        let p2pkh = hex_script!("76a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688ac");
+        let uncolored = OutPoint::from_str("c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d0:0").unwrap();
+        let colored = OutPoint::from_str("c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d0:1").unwrap();
+        let spent_color = ColorIdentifier::reissuable(hex_script!("51"));
+        let issued_color = ColorIdentifier::non_reissuable(uncolored);
+        let burnt_color = ColorIdentifier::nft(colored);
+
+        let spent = |outpoint: &OutPoint| match outpoint.vout {
+            0 => Some(TxOut { value: 1000, script_pubkey: p2pkh.clone() }),
+            1 => Some(TxOut { value: 100, script_pubkey: p2pkh.add_color(spent_color.clone()).unwrap() }),
+            2 => Some(TxOut { value: 1, script_pubkey: p2pkh.add_color(burnt_color.clone()).unwrap() }),
+            _ => None,
+        };
+        let tx = TxBuilder::new()
+            .input(uncolored, Sequence::MAX)
+            .input(colored, Sequence::MAX)
+            .input(OutPoint { vout: 2, ..colored }, Sequence::MAX)
+            .output(p2pkh.add_color(spent_color.clone()).unwrap(), Amount::from_sat(60))
+            .output(p2pkh.add_color(issued_color.clone()).unwrap(), Amount::from_sat(500))
+            .output(p2pkh.clone(), Amount::from_sat(900))
+            .build()
+            .unwrap();
+
+        assert_eq!(tx.output[0].script_pubkey.color_id(), Some(spent_color.clone()));
+        assert_eq!(tx.output[2].script_pubkey.color_id(), None);
+        assert_eq!(tx.token_issuances(spent), Some(vec![(issued_color.clone(), 500)]));
+        assert_eq!(tx.token_transfers(spent), Some(vec![(spent_color.clone(), 60)]));
+        let mut burns = vec![(spent_color.clone(), 40), (burnt_color.clone(), 1)];
+        burns.sort();
+        assert_eq!(tx.token_burns(spent), Some(burns));
+        assert_eq!(tx.token_burns(|_| None), None);
+
+        let outputs = tx.output_balances().unwrap();
+        assert_eq!(outputs.tpc(), 900);
+        assert_eq!(outputs.token(&spent_color), 60);
+        assert_eq!(outputs.token(&issued_color), 500);
+        let inputs = tx.input_balances(spent).unwrap();
+        assert_eq!(inputs.tpc(), 1000);
+        assert_eq!(inputs.token(&spent_color), 100);
+        assert_eq!(inputs.token(&burnt_color), 1);
+        assert_eq!(tx.input_balances(|_| None), None);
+
+        // reissuing tokens of a color the transaction also spends
+        let reissued_color = ColorIdentifier::reissuable(p2pkh.clone());
+        let spent = |outpoint: &OutPoint| match outpoint.vout {
+            0 => Some(TxOut { value: 1000, script_pubkey: p2pkh.clone() }),
+            1 => Some(TxOut { value: 100, script_pubkey: p2pkh.add_color(reissued_color.clone()).unwrap() }),
+            _ => None,
+        };
+        let tx = TxBuilder::new()
+            .input(uncolored, Sequence::MAX)
+            .input(colored, Sequence::MAX)
+            .output(p2pkh.add_color(reissued_color.clone()).unwrap(), Amount::from_sat(150))
+            .build()
+            .unwrap();
+        assert_eq!(tx.token_issuances(spent), Some(vec![(reissued_color.clone(), 150)]));
+        assert_eq!(tx.token_transfers(spent), Some(vec![]));
+        assert_eq!(tx.token_burns(spent), Some(vec![]));
     }
 
     #[test]
@@ -846,6 +1544,29 @@ mod tests {
         let hex_tx = Vec::<u8>::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
         let tx: Transaction = deserialize(&hex_tx).unwrap();
         serde_round_trip!(tx);
+
+        use hashes::hex::ToHex;
+        use serde_json;
+        let json = serde_json::to_value(&tx).unwrap();
+        assert_eq!(json, serde_json::Value::String(hex_tx.to_hex()));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_outpoint_serde() {
+        use serde_test::{Configure, Token, assert_tokens};
+
+        static HEX: &'static str = "9c12cfdc04c74584d787ac3d23772132c18524bc7ab28dec4219b8fc5b425f7002000000";
+        static BYTES: [u8; 36] = [
+            0x9c, 0x12, 0xcf, 0xdc, 0x04, 0xc7, 0x45, 0x84, 0xd7, 0x87, 0xac, 0x3d,
+            0x23, 0x77, 0x21, 0x32, 0xc1, 0x85, 0x24, 0xbc, 0x7a, 0xb2, 0x8d, 0xec,
+            0x42, 0x19, 0xb8, 0xfc, 0x5b, 0x42, 0x5f, 0x70, 0x02, 0x00, 0x00, 0x00,
+        ];
+
+        let outpoint = OutPoint::new(MalFixTxid::hash(&[1]), 2);
+        assert_eq!(serialize(&outpoint), &BYTES[..]);
+        assert_tokens(&outpoint.readable(), &[Token::Str(HEX)]);
+        assert_tokens(&outpoint.compact(), &[Token::Bytes(&BYTES)]);
     }
 
     fn run_test_sighash(tx: &str, script: &str, input_index: usize, hash_type: i32, expected_result: &str) {
@@ -1171,58 +1892,158 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature="bitcoinconsensus")]
-    fn test_transaction_verify () {
-        use hex::decode as hex_decode;
+    fn test_transaction_verify() {
         use std::collections::HashMap;
-        use blockdata::script;
-        // a random recent segwit transaction from blockchain using both old and segwit inputs
-        let mut spending: Transaction = deserialize(hex_decode("020000000001031cfbc8f54fbfa4a33a30068841371f80dbfe166211242213188428f437445c91000000006a47304402206fbcec8d2d2e740d824d3d36cc345b37d9f65d665a99f5bd5c9e8d42270a03a8022013959632492332200c2908459547bf8dbf97c65ab1a28dec377d6f1d41d3d63e012103d7279dfb90ce17fe139ba60a7c41ddf605b25e1c07a4ddcb9dfef4e7d6710f48feffffff476222484f5e35b3f0e43f65fc76e21d8be7818dd6a989c160b1e5039b7835fc00000000171600140914414d3c94af70ac7e25407b0689e0baa10c77feffffffa83d954a62568bbc99cc644c62eb7383d7c2a2563041a0aeb891a6a4055895570000000017160014795d04cc2d4f31480d9a3710993fbd80d04301dffeffffff06fef72f000000000017a91476fd7035cd26f1a32a5ab979e056713aac25796887a5000f00000000001976a914b8332d502a529571c6af4be66399cd33379071c588ac3fda0500000000001976a914fc1d692f8de10ae33295f090bea5fe49527d975c88ac522e1b00000000001976a914808406b54d1044c429ac54c0e189b0d8061667e088ac6eb68501000000001976a914dfab6085f3a8fb3e6710206a5a959313c5618f4d88acbba20000000000001976a914eb3026552d7e3f3073457d0bee5d4757de48160d88ac0002483045022100bee24b63212939d33d513e767bc79300051f7a0d433c3fcf1e0e3bf03b9eb1d70220588dc45a9ce3a939103b4459ce47500b64e23ab118dfc03c9caa7d6bfc32b9c601210354fd80328da0f9ae6eef2b3a81f74f9a6f66761fadf96f1d1d22b1fd6845876402483045022100e29c7e3a5efc10da6269e5fc20b6a1cb8beb92130cc52c67e46ef40aaa5cac5f0220644dd1b049727d991aece98a105563416e10a5ac4221abac7d16931842d5c322012103960b87412d6e169f30e12106bdf70122aabb9eb61f455518322a18b920a4dfa887d30700")
-            .unwrap().as_slice()).unwrap();
-        let spent1: Transaction = deserialize(hex_decode("020000000001040aacd2c49f5f3c0968cfa8caf9d5761436d95385252e3abb4de8f5dcf8a582f20000000017160014bcadb2baea98af0d9a902e53a7e9adff43b191e9feffffff96cd3c93cac3db114aafe753122bd7d1afa5aa4155ae04b3256344ecca69d72001000000171600141d9984579ceb5c67ebfbfb47124f056662fe7adbfeffffffc878dd74d3a44072eae6178bb94b9253177db1a5aaa6d068eb0e4db7631762e20000000017160014df2a48cdc53dae1aba7aa71cb1f9de089d75aac3feffffffe49f99275bc8363f5f593f4eec371c51f62c34ff11cc6d8d778787d340d6896c0100000017160014229b3b297a0587e03375ab4174ef56eeb0968735feffffff03360d0f00000000001976a9149f44b06f6ee92ddbc4686f71afe528c09727a5c788ac24281b00000000001976a9140277b4f68ff20307a2a9f9b4487a38b501eb955888ac227c0000000000001976a9148020cd422f55eef8747a9d418f5441030f7c9c7788ac0247304402204aa3bd9682f9a8e101505f6358aacd1749ecf53a62b8370b97d59243b3d6984f02200384ad449870b0e6e89c92505880411285ecd41cf11e7439b973f13bad97e53901210205b392ffcb83124b1c7ce6dd594688198ef600d34500a7f3552d67947bbe392802473044022033dfd8d190a4ae36b9f60999b217c775b96eb10dee3a1ff50fb6a75325719106022005872e4e36d194e49ced2ebcf8bb9d843d842e7b7e0eb042f4028396088d292f012103c9d7cbf369410b090480de2aa15c6c73d91b9ffa7d88b90724614b70be41e98e0247304402207d952de9e59e4684efed069797e3e2d993e9f98ec8a9ccd599de43005fe3f713022076d190cc93d9513fc061b1ba565afac574e02027c9efbfa1d7b71ab8dbb21e0501210313ad44bc030cc6cb111798c2bf3d2139418d751c1e79ec4e837ce360cc03b97a024730440220029e75edb5e9413eb98d684d62a077b17fa5b7cc19349c1e8cc6c4733b7b7452022048d4b9cae594f03741029ff841e35996ef233701c1ea9aa55c301362ea2e2f68012103590657108a72feb8dc1dec022cf6a230bb23dc7aaa52f4032384853b9f8388baf9d20700")
-            .unwrap().as_slice()).unwrap();
-        let spent2: Transaction = deserialize(hex_decode("0200000000010166c3d39490dc827a2594c7b17b7d37445e1f4b372179649cd2ce4475e3641bbb0100000017160014e69aa750e9bff1aca1e32e57328b641b611fc817fdffffff01e87c5d010000000017a914f3890da1b99e44cd3d52f7bcea6a1351658ea7be87024830450221009eb97597953dc288de30060ba02d4e91b2bde1af2ecf679c7f5ab5989549aa8002202a98f8c3bd1a5a31c0d72950dd6e2e3870c6c5819a6c3db740e91ebbbc5ef4800121023f3d3b8e74b807e32217dea2c75c8d0bd46b8665b3a2d9b3cb310959de52a09bc9d20700")
-            .unwrap().as_slice()).unwrap();
-        let spent3: Transaction = deserialize(hex_decode("01000000027a1120a30cef95422638e8dab9dedf720ec614b1b21e451a4957a5969afb869d000000006a47304402200ecc318a829a6cad4aa9db152adbf09b0cd2de36f47b53f5dade3bc7ef086ca702205722cda7404edd6012eedd79b2d6f24c0a0c657df1a442d0a2166614fb164a4701210372f4b97b34e9c408741cd1fc97bcc7ffdda6941213ccfde1cb4075c0f17aab06ffffffffc23b43e5a18e5a66087c0d5e64d58e8e21fcf83ce3f5e4f7ecb902b0e80a7fb6010000006b483045022100f10076a0ea4b4cf8816ed27a1065883efca230933bf2ff81d5db6258691ff75202206b001ef87624e76244377f57f0c84bc5127d0dd3f6e0ef28b276f176badb223a01210309a3a61776afd39de4ed29b622cd399d99ecd942909c36a8696cfd22fc5b5a1affffffff0200127a000000000017a914f895e1dd9b29cb228e9b06a15204e3b57feaf7cc8769311d09000000001976a9144d00da12aaa51849d2583ae64525d4a06cd70fde88ac00000000")
-            .unwrap().as_slice()).unwrap();
-
+        use secp256k1::Secp256k1;
+        use blockdata::interpreter::{self, VerifyFlags};
+        use blockdata::script::{self, Builder, ColorIdentifier};
+        use blockdata::transaction::SigHashType;
+        use util::amount::Amount;
+        use util::key::PrivateKey;
+        use util::sighash::SighashCache;
+        use util::signature::Signature;
+
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let pubkey = key.public_key(&Secp256k1::new());
+        let p2pkh = Script::new_p2pkh(&PubkeyHash::hash(&pubkey.to_bytes()));
+        let color = ColorIdentifier::reissuable(p2pkh.clone());
+        let cp2pkh = p2pkh.add_color(color.clone()).unwrap();
+
+        let funding_tx = TxBuilder::new()
+            .input(OutPoint::new(MalFixTxid::hash(b"coinbase"), 0), Sequence::MAX)
+            .output(p2pkh.clone(), Amount::from_sat(10_000))
+            .output(cp2pkh.clone(), Amount::from_sat(100))
+            .build()
+            .unwrap();
+        // outpoints refer to the malleability-fixed txid
+        let funding = OutPoint::new(funding_tx.malfix_txid(), 0);
+        let tokens = OutPoint::new(funding_tx.malfix_txid(), 1);
         let mut spent = HashMap::new();
-        spent.insert(spent1.txid(), spent1);
-        spent.insert(spent2.txid(), spent2);
-        spent.insert(spent3.txid(), spent3);
-        let mut spent2 = spent.clone();
-        let mut spent3 = spent.clone();
-
-        spending.verify(|point: &OutPoint| {
-            if let Some(tx) = spent.remove(&point.txid) {
-                return tx.output.get(point.vout as usize).cloned();
+        spent.insert(funding_tx.malfix_txid(), funding_tx);
+        let lookup = |point: &OutPoint| spent.get(&point.txid).and_then(|tx| tx.output.get(point.vout as usize)).cloned();
+
+        let sign = |tx: &mut Transaction| {
+            let sigs: Vec<Vec<u8>> = {
+                let mut cache = SighashCache::new(tx);
+                tx.input.iter().enumerate().map(|(idx, input)| {
+                    let script_pubkey = lookup(&input.previous_output).unwrap().script_pubkey;
+                    let sighash = cache.signature_hash(idx, &script_pubkey, SigHashType::All.as_u32());
+                    let mut sig = serialize(&Signature::sign(&key, &sighash.into_inner()).unwrap());
+                    sig.push(SigHashType::All.as_u32() as u8);
+                    sig
+                }).collect()
+            };
+            for (input, sig) in tx.input.iter_mut().zip(sigs) {
+                input.script_sig = Builder::new().push_slice(&sig).push_key(&pubkey).into_script();
             }
-            None
-        }).unwrap();
+        };
+
+        // transfer 60 tokens, burning the 40 others
+        let mut tx = TxBuilder::new()
+            .input(funding, Sequence::MAX)
+            .input(tokens, Sequence::MAX)
+            .output(cp2pkh.clone(), Amount::from_sat(60))
+            .output(p2pkh.clone(), Amount::from_sat(9_000))
+            .build()
+            .unwrap();
+        sign(&mut tx);
+        assert_eq!(tx.verify(&lookup), Ok(()));
+
+        assert_eq!(tx.verify(|_: &OutPoint| None), Err(script::Error::UnknownSpentOutput(funding)));
+        assert_eq!(tx.verify_with_flags(&lookup, VerifyFlags::STANDARD), Ok(()));
+        assert_eq!(tx.verify_with_flags(&lookup, VerifyFlags::CLEANSTACK), Err(script::Error::InvalidFlags));
 
         // test that we fail with repeated use of same input
-        let mut double_spending = spending.clone();
+        let mut double_spending = tx.clone();
         let re_use = double_spending.input[0].clone();
-        double_spending.input.push (re_use);
+        double_spending.input.push(re_use);
+        assert_eq!(double_spending.verify(&lookup), Err(script::Error::DuplicateInput(funding)));
+
+        // test that we get a failure if the signatures do not sign the transaction
+        let mut corrupted = tx.clone();
+        corrupted.output[1].value -= 1;
+        assert_eq!(corrupted.verify(&lookup), Err(script::Error::Execution(0, interpreter::Error::EvalFalse)));
+
+        // tokens can not be created by transfers
+        let mut inflating = tx.clone();
+        inflating.output[0].value = 101;
+        sign(&mut inflating);
+        assert_eq!(inflating.verify(&lookup), Err(script::Error::TokenBalance(color.clone())));
+
+        // but are by issuances
+        let nft = ColorIdentifier::nft(funding);
+        let mut issuing = TxBuilder::new()
+            .input(funding, Sequence::MAX)
+            .output(p2pkh.add_color(nft.clone()).unwrap(), Amount::from_sat(1))
+            .output(cp2pkh.clone(), Amount::from_sat(1_000))
+            .build()
+            .unwrap();
+        sign(&mut issuing);
+        assert_eq!(issuing.verify(&lookup), Ok(()));
+        issuing.output[0].value = 2;
+        sign(&mut issuing);
+        assert_eq!(issuing.verify(&lookup), Err(script::Error::TokenBalance(nft)));
+    }
 
-        assert!(double_spending.verify(|point: &OutPoint| {
-            if let Some(tx) = spent2.remove(&point.txid) {
-                return tx.output.get(point.vout as usize).cloned();
-            }
-            None
-        }).is_err());
+    #[test]
+    fn test_total_sigops() {
+        use std::collections::HashMap;
+        use blockdata::opcodes::all;
+        use blockdata::script::{self, Builder};
+        use test_helpers::test_pubkey;
 
-        // test that we get a failure if we corrupt a signature
-        spending.input[1].witness[0][10] = 42;
-        match spending.verify(|point: &OutPoint| {
-            if let Some(tx) = spent3.remove(&point.txid) {
-                return tx.output.get(point.vout as usize).cloned();
-            }
-            None
-        }).err().unwrap() {
-            script::Error::BitcoinConsensus(_) => {},
-            _ => panic!("Wrong error type"),
-        }
+        let key = test_pubkey();
+        let p2pkh = Script::new_p2pkh(&PubkeyHash::hash(&key.to_bytes()));
+        let multisig = Script::new_multisig(2, &[key, key, key]).unwrap();
+
+        let funding = OutPoint::new(MalFixTxid::hash(b"funding"), 0);
+        let shared = OutPoint::new(MalFixTxid::hash(b"funding"), 1);
+        let mut spent = HashMap::new();
+        spent.insert(funding, TxOut { value: 10_000, script_pubkey: p2pkh.clone() });
+        spent.insert(shared, TxOut { value: 10_000, script_pubkey: multisig.to_p2sh() });
+        let lookup = |point: &OutPoint| spent.get(point).cloned();
+
+        let tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    previous_output: funding,
+                    script_sig: Builder::new().push_slice(&[0x30; 72]).push_key(&key).into_script(),
+                    ..Default::default()
+                },
+                TxIn {
+                    previous_output: shared,
+                    script_sig: Builder::new()
+                        .push_int(0)
+                        .push_slice(&[0x30; 72])
+                        .push_slice(&[0x30; 72])
+                        .push_slice(&multisig[..])
+                        .into_script(),
+                    ..Default::default()
+                },
+            ],
+            output: vec![
+                TxOut { value: 9_000, script_pubkey: p2pkh.clone() },
+                TxOut { value: 9_000, script_pubkey: multisig.clone() },
+            ],
+        };
+        // 1 for the P2PKH output, 20 for the bare multisig output counted
+        // inaccurately and 3 for the multisig redeem script
+        assert_eq!(tx.total_sigops(&lookup), Ok(24));
+        assert_eq!(tx.total_sigops(|_: &OutPoint| None), Err(script::Error::UnknownSpentOutput(funding)));
+
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Default::default(), 0),
+                script_sig: Builder::new().push_opcode(all::OP_CHECKSIG).into_script(),
+                ..Default::default()
+            }],
+            output: vec![TxOut { value: 5_000_000_000, script_pubkey: p2pkh }],
+        };
+        assert_eq!(coinbase.total_sigops(|_: &OutPoint| None), Ok(2));
     }
 }
 
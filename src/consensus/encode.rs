@@ -29,46 +29,54 @@
 //! big-endian decimals, etc.)
 //!
 
-use std::{fmt, error, io, mem, u32};
-use std::borrow::Cow;
-use std::io::{Cursor, Read, Write};
-use hashes::hex::ToHex;
+use core::{cmp, fmt, mem, u32};
+#[cfg(feature = "std")] use std::error;
+#[cfg(feature = "std")] use hashes::hex::{self, FromHex, ToHex};
 
 use hashes::{sha256d, Hash};
 use hash_types::{BlockHash, FilterHash, TxMerkleNode};
+use io::{self, Cursor, Read, Write};
+use prelude::*;
 
 use util::endian;
-use util::psbt;
+#[cfg(feature = "std")] use util::psbt;
 use util::key;
 use util::signature::Signature;
 
 use blockdata::transaction::{TxOut, Transaction, TxIn};
-use network::message_blockdata::Inventory;
-use network::address::Address;
+#[cfg(feature = "std")] use network::message_blockdata::Inventory;
+#[cfg(feature = "std")] use network::message_compact::ShortId;
+#[cfg(feature = "std")] use network::address::{Address, AddrV2Message};
 
 /// Encoding error
+///
+/// More variants may be added in later versions, so matches should include
+/// a wildcard arm.
 #[derive(Debug)]
 pub enum Error {
     /// And I/O error
     Io(io::Error),
+    /// The data is malformed
+    Parse(ParseError),
+    /// The data exceeds a limit of the decoder
+    Policy(PolicyError),
     /// PSBT-related error
+    #[cfg(feature = "std")]
     Psbt(psbt::Error),
     /// key error
     Key(key::Error),
-    /// Network magic was not expected
-    UnexpectedNetworkMagic {
-        /// The expected network magic
-        expected: u32,
-        /// The unexpected network magic
-        actual: u32,
-    },
-    /// Tried to allocate an oversized vector
-    OversizedVectorAllocation{
-        /// The capacity requested
-        requested: usize,
-        /// The maximum capacity
-        max: usize,
-    },
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// The ways data can be malformed
+///
+/// More variants may be added in later versions, so matches should include
+/// a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// VarInt was encoded in a non-minimal way
+    NonMinimalVarInt,
     /// Checksum was invalid
     InvalidChecksum {
         /// The expected checksum
@@ -76,59 +84,133 @@ pub enum Error {
         /// The invalid checksum
         actual: [u8; 4],
     },
-    /// VarInt was encoded in a non-minimal way
-    NonMinimalVarInt,
+    /// Network magic was not expected
+    UnexpectedNetworkMagic {
+        /// The expected network magic
+        expected: u32,
+        /// The unexpected network magic
+        actual: u32,
+    },
     /// Network magic was unknown
     UnknownNetworkMagic(u32),
-    /// Parsing error
-    ParseFailed(&'static str),
     /// Unsupported Segwit flag
     UnsupportedSegwitFlag(u8),
     /// Unrecognized network command
     UnrecognizedNetworkCommand(String),
     /// Invalid Inventory type
     UnknownInventoryType(u32),
+    /// The data was not consumed entirely
+    DataNotConsumed,
+    /// A string was not valid UTF-8
+    InvalidUtf8,
+    /// A length overflows
+    InvalidLength,
+    /// A hex string was invalid
+    #[cfg(feature = "std")]
+    InvalidHex(hex::Error),
+    /// Any other malformation, described
+    Other(&'static str),
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// The limits of the decoder data may exceed
+///
+/// More variants may be added in later versions, so matches should include
+/// a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    /// Tried to allocate an oversized vector
+    OversizedVectorAllocation {
+        /// The capacity requested
+        requested: usize,
+        /// The maximum capacity
+        max: usize,
+    },
+    /// A message contains more elements than allowed
+    TooManyElements {
+        /// The number of elements in the message
+        requested: u64,
+        /// The maximum number of elements
+        max: u64,
+    },
+    /// A block is larger than the maximum block size of the chain
+    OversizedBlock {
+        /// The maximum block size, in bytes
+        max: usize,
+    },
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Parse(ref e) => fmt::Display::fmt(e, f),
+            Error::Policy(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "std")]
             Error::Psbt(ref e) => write!(f, "PSBT error: {}", e),
             Error::Key(ref e) => write!(f, "Key error: {}", e),
-            Error::UnexpectedNetworkMagic { expected: ref e, actual: ref a } => write!(f,
+            Error::__Nonexhaustive => f.write_str("unknown encoding error"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::NonMinimalVarInt => write!(f, "non-minimal varint"),
+            ParseError::InvalidChecksum { expected: ref e, actual: ref a } => {
+                f.write_str("invalid checksum: expected ")?;
+                fmt_hex(f, e)?;
+                f.write_str(", actual ")?;
+                fmt_hex(f, a)
+            }
+            ParseError::UnexpectedNetworkMagic { expected: ref e, actual: ref a } => write!(f,
                 "unexpected network magic: expected {}, actual {}", e, a),
-            Error::OversizedVectorAllocation { requested: ref r, max: ref m } => write!(f,
-                "allocation of oversized vector: requested {}, maximum {}", r, m),
-            Error::InvalidChecksum { expected: ref e, actual: ref a } => write!(f,
-                "invalid checksum: expected {}, actual {}", e.to_hex(), a.to_hex()),
-            Error::NonMinimalVarInt => write!(f, "non-minimal varint"),
-            Error::UnknownNetworkMagic(ref m) => write!(f, "unknown network magic: {}", m),
-            Error::ParseFailed(ref e) => write!(f, "parse failed: {}", e),
-            Error::UnsupportedSegwitFlag(ref swflag) => write!(f,
+            ParseError::UnknownNetworkMagic(ref m) => write!(f, "unknown network magic: {}", m),
+            ParseError::UnsupportedSegwitFlag(ref swflag) => write!(f,
                 "unsupported segwit version: {}", swflag),
-            Error::UnrecognizedNetworkCommand(ref nwcmd) => write!(f,
+            ParseError::UnrecognizedNetworkCommand(ref nwcmd) => write!(f,
                 "unrecognized network command: {}", nwcmd),
-            Error::UnknownInventoryType(ref tp) => write!(f, "Unknown Inventory type: {}", tp),
+            ParseError::UnknownInventoryType(ref tp) => write!(f, "Unknown Inventory type: {}", tp),
+            ParseError::DataNotConsumed => f.write_str(
+                "parse failed: data not consumed entirely when explicitly deserializing"),
+            ParseError::InvalidUtf8 => f.write_str("parse failed: String was not valid UTF8"),
+            ParseError::InvalidLength => f.write_str("parse failed: Invalid length"),
+            #[cfg(feature = "std")]
+            ParseError::InvalidHex(ref e) => write!(f, "parse failed: invalid hex: {}", e),
+            ParseError::Other(ref e) => write!(f, "parse failed: {}", e),
+            ParseError::__Nonexhaustive => f.write_str("parse failed: unknown error"),
         }
     }
 }
 
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PolicyError::OversizedVectorAllocation { requested: ref r, max: ref m } => write!(f,
+                "allocation of oversized vector: requested {}, maximum {}", r, m),
+            PolicyError::TooManyElements { requested: ref r, max: ref m } => write!(f,
+                "too many elements: requested {}, maximum {}", r, m),
+            PolicyError::OversizedBlock { max: ref m } => write!(f,
+                "block larger than the maximum block size of {} bytes", m),
+            PolicyError::__Nonexhaustive => f.write_str("unknown decoding limit exceeded"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Io(ref e) => Some(e),
+            Error::Parse(ref e) => Some(e),
+            Error::Policy(ref e) => Some(e),
             Error::Psbt(ref e) => Some(e),
             Error::Key(ref e) => Some(e),
-            Error::UnexpectedNetworkMagic { .. }
-            | Error::OversizedVectorAllocation { .. }
-            | Error::InvalidChecksum { .. }
-            | Error::NonMinimalVarInt
-            | Error::UnknownNetworkMagic(..)
-            | Error::ParseFailed(..)
-            | Error::UnsupportedSegwitFlag(..)
-            | Error::UnrecognizedNetworkCommand(..)
-            | Error::UnknownInventoryType(..) => None,
+            Error::__Nonexhaustive => None,
         }
     }
 
@@ -137,7 +219,27 @@ impl error::Error for Error {
     }
 }
 
-#[doc(hidden)]
+#[cfg(feature = "std")]
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        "malformed data"
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for PolicyError {
+    fn description(&self) -> &str {
+        "data exceeding a decoding limit"
+    }
+}
+
+/// Write `data` in hex
+fn fmt_hex(f: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+    for byte in data {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
 
 #[doc(hidden)]
 impl From<io::Error> for Error {
@@ -146,6 +248,21 @@ impl From<io::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<PolicyError> for Error {
+    fn from(e: PolicyError) -> Error {
+        Error::Policy(e)
+    }
+}
+
+#[cfg(feature = "std")]
 #[doc(hidden)]
 impl From<psbt::Error> for Error {
     fn from(e: psbt::Error) -> Error {
@@ -162,16 +279,46 @@ impl From<key::Error> for Error {
 
 /// Encode an object into a vector
 pub fn serialize<T: Encodable + ?Sized>(data: &T) -> Vec<u8> {
-    let mut encoder = Cursor::new(vec![]);
+    let mut encoder = Vec::with_capacity(data.consensus_encoded_len());
     data.consensus_encode(&mut encoder).unwrap();
-    encoder.into_inner()
+    encoder
+}
+
+/// The length of the consensus encoding of an object, in bytes
+pub fn serialized_len<T: Encodable + ?Sized>(data: &T) -> usize {
+    data.consensus_encoded_len()
+}
+
+/// A writer counting the bytes written to it
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 /// Encode an object into a hex-encoded string
+#[cfg(feature = "std")]
 pub fn serialize_hex<T: Encodable + ?Sized>(data: &T) -> String {
     serialize(data)[..].to_hex()
 }
 
+/// Deserialize an object from a hex string, will error if said
+/// deserialization doesn't consume the entire string.
+#[cfg(feature = "std")]
+pub fn deserialize_hex<T: Decodable>(hex: &str) -> Result<T, Error> {
+    let data = Vec::<u8>::from_hex(hex).map_err(ParseError::InvalidHex)?;
+    deserialize(&data)
+}
+
 /// Deserialize an object from a vector, will error if said deserialization
 /// doesn't consume the entire vector.
 pub fn deserialize<'a, T: Decodable>(data: &'a [u8]) -> Result<T, Error> {
@@ -181,7 +328,7 @@ pub fn deserialize<'a, T: Decodable>(data: &'a [u8]) -> Result<T, Error> {
     if consumed == data.len() {
         Ok(rv)
     } else {
-        Err(Error::ParseFailed("data not consumed entirely when explicitly deserializing"))
+        Err(Error::Parse(ParseError::DataNotConsumed))
     }
 }
 
@@ -265,7 +412,7 @@ macro_rules! decoder_fn {
     ($name:ident, $val_type:ty, $readfn:ident, $byte_len: expr) => {
         #[inline]
         fn $name(&mut self) -> Result<$val_type, Error> {
-            assert_eq!(::std::mem::size_of::<$val_type>(), $byte_len); // size_of isn't a constfn in 1.22
+            assert_eq!(mem::size_of::<$val_type>(), $byte_len); // size_of isn't a constfn in 1.22
             let mut val = [0; $byte_len];
             self.read_exact(&mut val[..]).map_err(Error::Io)?;
             Ok(endian::$readfn(&val))
@@ -332,12 +479,55 @@ impl<R: Read> ReadExt for R {
 /// Maximum size, in bytes, of a vector we are allowed to decode
 pub const MAX_VEC_SIZE: usize = 4_000_000;
 
+/// Decoding limits which depend on the parameters of a chain
+///
+/// Tapyrus networks can raise their maximum block size through the block
+/// header xfield, so the limits applied to blocks and to the messages
+/// carrying them cannot be hard-coded.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ChainLimits {
+    /// Maximum serialized size of a block, in bytes
+    pub max_block_size: usize,
+}
+
+impl ChainLimits {
+    /// Limits of a chain accepting blocks of up to `max_block_size` bytes
+    pub fn new(max_block_size: usize) -> ChainLimits {
+        ChainLimits { max_block_size: max_block_size }
+    }
+
+    /// Maximum size, in bytes, of a vector decoded from this chain's data
+    pub fn max_vec_size(&self) -> usize {
+        cmp::max(self.max_block_size, MAX_VEC_SIZE)
+    }
+
+    /// Maximum payload size, in bytes, of a network message of this chain
+    pub fn max_message_size(&self) -> usize {
+        self.max_vec_size()
+    }
+}
+
+impl Default for ChainLimits {
+    /// The limits of a chain whose xfield never changed the maximum block size
+    fn default() -> ChainLimits {
+        ChainLimits::new(1_000_000)
+    }
+}
+
 /// Data which can be encoded in a consensus-consistent way
 pub trait Encodable {
     /// Encode an object with a well-defined format, should only ever error if
     /// the underlying `Write` errors. Returns the number of bytes written on
     /// success
     fn consensus_encode<W: io::Write>(&self, e: W) -> Result<usize, Error>;
+
+    /// The length of the consensus encoding of the object, in bytes. By
+    /// default, the object is encoded into a writer counting the bytes.
+    fn consensus_encoded_len(&self) -> usize {
+        let mut counter = ByteCounter(0);
+        self.consensus_encode(&mut counter).expect("counting bytes never fails");
+        counter.0
+    }
 }
 
 /// Data which can be encoded in a consensus-consistent way
@@ -373,6 +563,11 @@ macro_rules! impl_int_encodable{
                 s.$meth_enc(self.to_le())?;
                 Ok(mem::size_of::<$ty>())
             }
+
+            #[inline]
+            fn consensus_encoded_len(&self) -> usize {
+                mem::size_of::<$ty>()
+            }
         }
     )
 }
@@ -404,27 +599,31 @@ impl VarInt {
 impl Encodable for VarInt {
     #[inline]
     fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, Error> {
-        match self.0 {
-            0...0xFC => {
-                (self.0 as u8).consensus_encode(s)?;
-                Ok(1)
+        // Write the prefix and the integer at once
+        let mut buf = [0u8; 9];
+        let len = self.len();
+        match len {
+            1 => buf[0] = self.0 as u8,
+            3 => {
+                buf[0] = 0xFD;
+                buf[1..3].copy_from_slice(&endian::u16_to_array_le(self.0 as u16));
             },
-            0xFD...0xFFFF => {
-                s.emit_u8(0xFD)?;
-                (self.0 as u16).consensus_encode(s)?;
-                Ok(3)
-            },
-            0x10000...0xFFFFFFFF => {
-                s.emit_u8(0xFE)?;
-                (self.0 as u32).consensus_encode(s)?;
-                Ok(5)
+            5 => {
+                buf[0] = 0xFE;
+                buf[1..5].copy_from_slice(&endian::u32_to_array_le(self.0 as u32));
             },
             _ => {
-                s.emit_u8(0xFF)?;
-                (self.0 as u64).consensus_encode(s)?;
-                Ok(9)
+                buf[0] = 0xFF;
+                buf[1..9].copy_from_slice(&endian::u64_to_array_le(self.0));
             },
         }
+        s.emit_slice(&buf[..len])?;
+        Ok(len)
+    }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        self.len()
     }
 }
 
@@ -436,7 +635,7 @@ impl Decodable for VarInt {
             0xFF => {
                 let x = ReadExt::read_u64(&mut d)?;
                 if x < 0x100000000 {
-                    Err(self::Error::NonMinimalVarInt)
+                    Err(self::Error::Parse(self::ParseError::NonMinimalVarInt))
                 } else {
                     Ok(VarInt(x))
                 }
@@ -444,7 +643,7 @@ impl Decodable for VarInt {
             0xFE => {
                 let x = ReadExt::read_u32(&mut d)?;
                 if x < 0x10000 {
-                    Err(self::Error::NonMinimalVarInt)
+                    Err(self::Error::Parse(self::ParseError::NonMinimalVarInt))
                 } else {
                     Ok(VarInt(x as u64))
                 }
@@ -452,7 +651,7 @@ impl Decodable for VarInt {
             0xFD => {
                 let x = ReadExt::read_u16(&mut d)?;
                 if x < 0xFD {
-                    Err(self::Error::NonMinimalVarInt)
+                    Err(self::Error::Parse(self::ParseError::NonMinimalVarInt))
                 } else {
                     Ok(VarInt(x as u64))
                 }
@@ -480,13 +679,27 @@ impl Decodable for bool {
 }
 
 // Strings
+impl Encodable for str {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        self.as_bytes().consensus_encode(s)
+    }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        self.as_bytes().consensus_encoded_len()
+    }
+}
+
 impl Encodable for String {
     #[inline]
-    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, Error> {
-        let b = self.as_bytes();
-        let vi_len = VarInt(b.len() as u64).consensus_encode(&mut s)?;
-        s.emit_slice(&b)?;
-        Ok(vi_len + b.len())
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        self.as_str().consensus_encode(s)
+    }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        self.as_str().consensus_encoded_len()
     }
 }
 
@@ -494,18 +707,32 @@ impl Decodable for String {
     #[inline]
     fn consensus_decode<D: io::Read>(d: D) -> Result<String, Error> {
         String::from_utf8(Decodable::consensus_decode(d)?)
-            .map_err(|_| self::Error::ParseFailed("String was not valid UTF8"))
+            .map_err(|_| self::Error::Parse(self::ParseError::InvalidUtf8))
     }
 }
 
-// Cow<'static, str>
-impl Encodable for Cow<'static, str> {
+// Borrowed data
+impl<'a, T: Encodable + ?Sized> Encodable for &'a T {
     #[inline]
-    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, Error> {
-        let b = self.as_bytes();
-        let vi_len = VarInt(b.len() as u64).consensus_encode(&mut s)?;
-        s.emit_slice(&b)?;
-        Ok(vi_len + b.len())
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        (**self).consensus_encode(s)
+    }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        (**self).consensus_encoded_len()
+    }
+}
+
+impl<'a, T: Encodable + ToOwned + ?Sized> Encodable for Cow<'a, T> {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        (**self).consensus_encode(s)
+    }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        (**self).consensus_encoded_len()
     }
 }
 
@@ -513,7 +740,7 @@ impl Decodable for Cow<'static, str> {
     #[inline]
     fn consensus_decode<D: io::Read>(d: D) -> Result<Cow<'static, str>, Error> {
         String::from_utf8(Decodable::consensus_decode(d)?)
-            .map_err(|_| self::Error::ParseFailed("String was not valid UTF8"))
+            .map_err(|_| self::Error::Parse(self::ParseError::InvalidUtf8))
             .map(Cow::Owned)
     }
 }
@@ -531,6 +758,11 @@ macro_rules! impl_array {
                 s.emit_slice(&self[..])?;
                 Ok(self.len())
             }
+
+            #[inline]
+            fn consensus_encoded_len(&self) -> usize {
+                $size
+            }
         }
 
         impl Decodable for [u8; $size] {
@@ -546,7 +778,9 @@ macro_rules! impl_array {
 
 impl_array!(2);
 impl_array!(4);
+impl_array!(6);
 impl_array!(8);
+impl_array!(10);
 impl_array!(12);
 impl_array!(16);
 impl_array!(32);
@@ -572,9 +806,9 @@ impl Encodable for [u16; 8] {
 }
 
 // Vectors
-macro_rules! impl_vec {
+macro_rules! impl_vec_encodable {
     ($type: ty) => {
-        impl Encodable for Vec<$type> {
+        impl Encodable for [$type] {
             #[inline]
             fn consensus_encode<S: io::Write>(
                 &self,
@@ -587,7 +821,34 @@ macro_rules! impl_vec {
                 }
                 Ok(len)
             }
+
+            #[inline]
+            fn consensus_encoded_len(&self) -> usize {
+                let mut len = VarInt(self.len() as u64).len();
+                for c in self.iter() {
+                    len += c.consensus_encoded_len();
+                }
+                len
+            }
+        }
+
+        impl Encodable for Vec<$type> {
+            #[inline]
+            fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+                self[..].consensus_encode(s)
+            }
+
+            #[inline]
+            fn consensus_encoded_len(&self) -> usize {
+                self[..].consensus_encoded_len()
+            }
         }
+    }
+}
+
+macro_rules! impl_vec {
+    ($type: ty) => {
+        impl_vec_encodable!($type);
 
         impl Decodable for Vec<$type> {
             #[inline]
@@ -595,9 +856,9 @@ macro_rules! impl_vec {
                 let len = VarInt::consensus_decode(&mut d)?.0;
                 let byte_size = (len as usize)
                                     .checked_mul(mem::size_of::<$type>())
-                                    .ok_or(self::Error::ParseFailed("Invalid length"))?;
+                                    .ok_or(self::Error::Parse(self::ParseError::InvalidLength))?;
                 if byte_size > MAX_VEC_SIZE {
-                    return Err(self::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE })
+                    return Err(self::Error::Policy(self::PolicyError::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE }))
                 }
                 let mut ret = Vec::with_capacity(len as usize);
                 for _ in 0..len {
@@ -608,61 +869,99 @@ macro_rules! impl_vec {
         }
     }
 }
-impl_vec!(BlockHash);
-impl_vec!(FilterHash);
-impl_vec!(TxMerkleNode);
 impl_vec!(Transaction);
 impl_vec!(TxOut);
 impl_vec!(TxIn);
-impl_vec!(Inventory);
 impl_vec!(Vec<u8>);
-impl_vec!((u32, Address));
-impl_vec!(u64);
+#[cfg(feature = "std")] impl_vec!(AddrV2Message);
 
-impl Encodable for Vec<u8> {
+// Vectors of fixed-size elements, read at once and decoded from memory
+macro_rules! impl_vec_fixed {
+    ($type: ty, $size: expr) => {
+        impl_vec_encodable!($type);
+
+        impl Decodable for Vec<$type> {
+            #[inline]
+            fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, Error> {
+                let len = VarInt::consensus_decode(&mut d)?.0;
+                let byte_size = (len as usize)
+                                    .checked_mul($size)
+                                    .ok_or(self::Error::Parse(self::ParseError::InvalidLength))?;
+                let data = read_bytes(&mut d, byte_size)?;
+                let mut ret = Vec::with_capacity(len as usize);
+                for mut chunk in data.chunks($size) {
+                    ret.push(Decodable::consensus_decode(&mut chunk)?);
+                }
+                Ok(ret)
+            }
+        }
+    }
+}
+impl_vec_fixed!(BlockHash, 32);
+impl_vec_fixed!(FilterHash, 32);
+impl_vec_fixed!(TxMerkleNode, 32);
+#[cfg(feature = "std")] impl_vec_fixed!(Inventory, 36);
+#[cfg(feature = "std")] impl_vec_fixed!((u32, Address), 30);
+impl_vec_fixed!(u64, 8);
+#[cfg(feature = "std")] impl_vec_fixed!(ShortId, 6);
+
+/// Read `len` bytes at once, after checking they can be allocated
+#[inline]
+fn read_bytes<D: io::Read>(mut d: D, len: usize) -> Result<Vec<u8>, Error> {
+    if len > MAX_VEC_SIZE {
+        return Err(self::Error::Policy(self::PolicyError::OversizedVectorAllocation { requested: len, max: MAX_VEC_SIZE }))
+    }
+    let mut ret = vec![0u8; len];
+    d.read_slice(&mut ret)?;
+    Ok(ret)
+}
+
+impl Encodable for [u8] {
     #[inline]
     fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, Error> {
         let vi_len = VarInt(self.len() as u64).consensus_encode(&mut s)?;
-        s.emit_slice(&self)?;
+        s.emit_slice(self)?;
         Ok(vi_len + self.len())
     }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        VarInt(self.len() as u64).len() + self.len()
+    }
+}
+
+impl Encodable for Vec<u8> {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        self[..].consensus_encode(s)
+    }
+
+    #[inline]
+    fn consensus_encoded_len(&self) -> usize {
+        self[..].consensus_encoded_len()
+    }
 }
 
 impl Decodable for Vec<u8> {
     #[inline]
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, Error> {
         let len = VarInt::consensus_decode(&mut d)?.0 as usize;
-        if len > MAX_VEC_SIZE {
-            return Err(self::Error::OversizedVectorAllocation { requested: len, max: MAX_VEC_SIZE })
-        }
-        let mut ret = Vec::with_capacity(len);
-        ret.resize(len, 0);
-        d.read_slice(&mut ret)?;
-        Ok(ret)
+        read_bytes(d, len)
     }
 }
 
 impl Encodable for Box<[u8]> {
     #[inline]
-    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, Error> {
-        let vi_len = VarInt(self.len() as u64).consensus_encode(&mut s)?;
-        s.emit_slice(&self)?;
-        Ok(vi_len + self.len())
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, Error> {
+        self[..].consensus_encode(s)
     }
 }
 
 impl Decodable for Box<[u8]> {
     #[inline]
     fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, Error> {
-        let len = VarInt::consensus_decode(&mut d)?.0;
-        let len = len as usize;
-        if len > MAX_VEC_SIZE {
-            return Err(self::Error::OversizedVectorAllocation { requested: len, max: MAX_VEC_SIZE })
-        }
-        let mut ret = Vec::with_capacity(len);
-        ret.resize(len, 0);
-        d.read_slice(&mut ret)?;
-        Ok(ret.into_boxed_slice())
+        let len = VarInt::consensus_decode(&mut d)?.0 as usize;
+        read_bytes(d, len).map(Vec::into_boxed_slice)
     }
 }
 
@@ -684,29 +983,108 @@ impl Encodable for CheckedData {
     }
 }
 
-impl Decodable for CheckedData {
-    #[inline]
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, Error> {
+impl CheckedData {
+    /// Encode `payload` as checked data without buffering it. The payload
+    /// is encoded twice: once to compute its length and checksum, then
+    /// straight into `s`.
+    pub fn encode_payload<T, S>(payload: &T, mut s: S) -> Result<usize, Error>
+    where
+        T: Encodable + ?Sized,
+        S: io::Write,
+    {
+        let mut engine = sha256d::Hash::engine();
+        let payload_len = payload.consensus_encode(&mut engine)?;
+        let checksum = sha256d::Hash::from_engine(engine);
+
+        let mut len = 0;
+        len += (payload_len as u32).consensus_encode(&mut s)?;
+        s.emit_slice(&checksum[0..4])?;
+        len += 4;
+        len += payload.consensus_encode(&mut s)?;
+        Ok(len)
+    }
+
+    /// Decode checked data whose length may not exceed `max_len` bytes,
+    /// instead of the default `MAX_VEC_SIZE`
+    pub fn consensus_decode_with_limit<D: io::Read>(d: D, max_len: usize) -> Result<Self, Error> {
+        CheckedData::decode_reader(d, max_len, true)
+    }
+
+    /// Decode checked data without verifying its checksum. This must only be
+    /// used for data coming from a trusted source.
+    pub fn consensus_decode_unchecked<D: io::Read>(d: D, max_len: usize) -> Result<Self, Error> {
+        CheckedData::decode_reader(d, max_len, false)
+    }
+
+    /// Verify the checked data at the start of `data` in place and return
+    /// its payload without copying it. The payload may not exceed `max_len`
+    /// bytes.
+    pub fn decode_slice_with_limit(data: &[u8], max_len: usize) -> Result<&[u8], Error> {
+        CheckedData::decode_slice(data, max_len, true)
+    }
+
+    /// Same as `decode_slice_with_limit`, without verifying the checksum.
+    /// This must only be used for data coming from a trusted source.
+    pub fn decode_slice_unchecked(data: &[u8], max_len: usize) -> Result<&[u8], Error> {
+        CheckedData::decode_slice(data, max_len, false)
+    }
+
+    fn decode_reader<D: io::Read>(mut d: D, max_len: usize, verify: bool) -> Result<Self, Error> {
         let len = u32::consensus_decode(&mut d)?;
-        if len > MAX_VEC_SIZE as u32 {
-            return Err(self::Error::OversizedVectorAllocation {
+        if len as usize > max_len {
+            return Err(self::Error::Policy(self::PolicyError::OversizedVectorAllocation {
                 requested: len as usize,
-                max: MAX_VEC_SIZE
-            });
+                max: max_len
+            }));
         }
         let checksum = <[u8; 4]>::consensus_decode(&mut d)?;
         let mut ret = Vec::with_capacity(len as usize);
         ret.resize(len as usize, 0);
         d.read_slice(&mut ret)?;
-        let expected_checksum = sha2_checksum(&ret);
-        if expected_checksum != checksum {
-            Err(self::Error::InvalidChecksum {
-                expected: expected_checksum,
-                actual: checksum,
-            })
-        } else {
-            Ok(CheckedData(ret))
+        if verify {
+            verify_checksum(&ret, checksum)?;
         }
+        Ok(CheckedData(ret))
+    }
+
+    fn decode_slice(data: &[u8], max_len: usize, verify: bool) -> Result<&[u8], Error> {
+        let mut d = data;
+        let len = u32::consensus_decode(&mut d)? as usize;
+        if len > max_len {
+            return Err(self::Error::Policy(self::PolicyError::OversizedVectorAllocation {
+                requested: len,
+                max: max_len
+            }));
+        }
+        let checksum = <[u8; 4]>::consensus_decode(&mut d)?;
+        if d.len() < len {
+            return Err(self::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        let payload = &d[..len];
+        if verify {
+            verify_checksum(payload, checksum)?;
+        }
+        Ok(payload)
+    }
+}
+
+/// Check that `checksum` is the checksum of `data`
+fn verify_checksum(data: &[u8], checksum: [u8; 4]) -> Result<(), Error> {
+    let expected_checksum = sha2_checksum(data);
+    if expected_checksum != checksum {
+        Err(self::Error::Parse(self::ParseError::InvalidChecksum {
+            expected: expected_checksum,
+            actual: checksum,
+        }))
+    } else {
+        Ok(())
+    }
+}
+
+impl Decodable for CheckedData {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, Error> {
+        CheckedData::consensus_decode_with_limit(d, MAX_VEC_SIZE)
     }
 }
 
@@ -763,7 +1141,7 @@ macro_rules! impl_option {
                 let mut len = 0;
                 match self {
                     Some(c) => {
-                        len += VarInt(serialize(c).len() as u64).consensus_encode(&mut s)?;
+                        len += VarInt(c.consensus_encoded_len() as u64).consensus_encode(&mut s)?;
                         len += c.consensus_encode(&mut s)?;
                     }
                     None => {
@@ -772,6 +1150,17 @@ macro_rules! impl_option {
                 }
                 Ok(len)
             }
+
+            #[inline]
+            fn consensus_encoded_len(&self) -> usize {
+                match self {
+                    Some(c) => {
+                        let len = c.consensus_encoded_len();
+                        VarInt(len as u64).len() + len
+                    }
+                    None => VarInt(0).len(),
+                }
+            }
         }
 
         impl Decodable for Option<$type> {
@@ -783,7 +1172,7 @@ macro_rules! impl_option {
                 }
                 let ret = Decodable::consensus_decode(&mut d)?;
                 if len != serialize(&ret).len() as u64 {
-                    return Err(Error::ParseFailed("Invalid length"));
+                    return Err(Error::Parse(ParseError::InvalidLength));
                 }
                 Ok(Some(ret))
             }
@@ -791,13 +1180,16 @@ macro_rules! impl_option {
     };
 }
 impl_option!(Signature);
+impl_option!(Transaction);
 
 // Tests
 #[cfg(test)]
 mod tests {
     use super::{CheckedData, VarInt};
 
-    use super::{deserialize, serialize, Error};
+    use super::{deserialize, serialize, Error, ParseError};
+    use hashes::Hash;
+    use hash_types::BlockHash;
     use util::signature::Signature;
 
     #[test]
@@ -857,32 +1249,37 @@ mod tests {
         assert_eq!(serialize(&VarInt(0xFFF)), vec![0xFDu8, 0xFF, 0xF]);
         assert_eq!(serialize(&VarInt(0xF0F0F0F)), vec![0xFEu8, 0xF, 0xF, 0xF, 0xF]);
         assert_eq!(serialize(&VarInt(0xF0F0F0F0F0E0)), vec![0xFFu8, 0xE0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0, 0]);
+        for n in &[0, 0xFC, 0xFD, 0xFFFF, 0x10000, 0xFFFFFFFF, 0x100000000, u64::max_value()] {
+            let ser = serialize(&VarInt(*n));
+            assert_eq!(ser.len(), VarInt(*n).len());
+            assert_eq!(deserialize::<VarInt>(&ser).unwrap(), VarInt(*n));
+        }
     }
 
     #[test]
     fn deserialize_nonminimal_vec() {
         match deserialize::<Vec<u8>>(&[0xfd, 0x00, 0x00]) {
-            Err(Error::NonMinimalVarInt) => {},
+            Err(Error::Parse(ParseError::NonMinimalVarInt)) => {},
             x => panic!(x)
         }
         match deserialize::<Vec<u8>>(&[0xfd, 0xfc, 0x00]) {
-            Err(Error::NonMinimalVarInt) => {},
+            Err(Error::Parse(ParseError::NonMinimalVarInt)) => {},
             x => panic!(x)
         }
         match deserialize::<Vec<u8>>(&[0xfe, 0xff, 0x00, 0x00, 0x00]) {
-            Err(Error::NonMinimalVarInt) => {},
+            Err(Error::Parse(ParseError::NonMinimalVarInt)) => {},
             x => panic!(x)
         }
         match deserialize::<Vec<u8>>(&[0xfe, 0xff, 0xff, 0x00, 0x00]) {
-            Err(Error::NonMinimalVarInt) => {},
+            Err(Error::Parse(ParseError::NonMinimalVarInt)) => {},
             x => panic!(x)
         }
         match deserialize::<Vec<u8>>(&[0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]) {
-            Err(Error::NonMinimalVarInt) => {},
+            Err(Error::Parse(ParseError::NonMinimalVarInt)) => {},
             x => panic!(x)
         }
         match deserialize::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00]) {
-            Err(Error::NonMinimalVarInt) => {},
+            Err(Error::Parse(ParseError::NonMinimalVarInt)) => {},
             x => panic!(x)
         }
 
@@ -903,6 +1300,11 @@ mod tests {
     fn serialize_checkeddata_test() {
         let cd = CheckedData(vec![1u8, 2, 3, 4, 5]);
         assert_eq!(serialize(&cd), vec![5, 0, 0, 0, 162, 107, 175, 90, 1, 2, 3, 4, 5]);
+
+        let payload = [1u8, 2, 3, 4, 5];
+        let mut encoded = vec![];
+        assert_eq!(CheckedData::encode_payload(&payload[..], &mut encoded).unwrap(), 14);
+        assert_eq!(encoded, serialize(&CheckedData(serialize(&payload[..]))));
     }
 
     #[test]
@@ -964,6 +1366,49 @@ mod tests {
         assert!((deserialize(&[4u8, 2, 3, 4, 5, 6]) as Result<Vec<u8>, _>).is_err());
         // found by cargo fuzz
         assert!(deserialize::<Vec<u64>>(&[0xff,0xff,0xff,0xff,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0x6b,0xa,0xa,0x3a]).is_err());
+
+        let ints = vec![1u64, 0xFFFF, u64::max_value()];
+        assert_eq!(deserialize::<Vec<u64>>(&serialize(&ints)).unwrap(), ints);
+        assert!(deserialize::<Vec<u64>>(&serialize(&ints)[..24]).is_err());
+        let hashes = vec![BlockHash::hash(&[1]), BlockHash::hash(&[2])];
+        assert_eq!(deserialize::<Vec<BlockHash>>(&serialize(&hashes)).unwrap(), hashes);
+    }
+
+    #[test]
+    fn error_categories_test() {
+        use std::error::Error as StdError;
+
+        let err = deserialize::<Vec<u8>>(&[1, 2, 3]).unwrap_err();
+        match err {
+            Error::Parse(ParseError::DataNotConsumed) => {},
+            ref e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(err.to_string(), "parse failed: data not consumed entirely when explicitly deserializing");
+        assert_eq!(err.source().unwrap().to_string(), err.to_string());
+
+        match deserialize::<Vec<u8>>(&[0xfe, 0x01, 0x09, 0x3d, 0x00]) {
+            Err(Error::Policy(super::PolicyError::OversizedVectorAllocation { requested: 4000001, max: 4000000 })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn serialize_borrowed_test() {
+        use std::borrow::Cow;
+
+        let ints = vec![1u64, 2, 3];
+        assert_eq!(serialize(&ints[..]), serialize(&ints));
+        assert_eq!(serialize(&&ints), serialize(&ints));
+        assert_eq!(serialize(&Cow::Borrowed(&ints[..])), serialize(&ints));
+        let owned: Cow<[u64]> = Cow::Owned(ints.clone());
+        assert_eq!(serialize(&owned), serialize(&ints));
+
+        let bytes = vec![1u8, 2, 3];
+        assert_eq!(serialize(&bytes[..]), vec![3u8, 1, 2, 3]);
+        assert_eq!(serialize(&Cow::Borrowed(&bytes[..])), vec![3u8, 1, 2, 3]);
+
+        assert_eq!(serialize("Andrew"), serialize(&"Andrew".to_string()));
+        assert_eq!(serialize(&Cow::Borrowed("Andrew")), vec![6u8, 0x41, 0x6e, 0x64, 0x72, 0x65, 0x77]);
     }
 
     #[test]
@@ -975,10 +1420,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn serialized_len_test() {
+        use super::serialized_len;
+
+        assert_eq!(serialized_len(&1u32), 4);
+        assert_eq!(serialized_len(&VarInt(0x10000)), 5);
+        assert_eq!(serialized_len(&vec![0u8; 300]), 303);
+        assert_eq!(serialized_len("Andrew"), 7);
+        assert_eq!(serialized_len(&vec![1u64, 2]), 17);
+        assert_eq!(serialized_len(&vec![vec![1u8], vec![]]), 4);
+        let cd = CheckedData(vec![1u8, 2, 3]);
+        assert_eq!(serialized_len(&cd), serialize(&cd).len());
+    }
+
+    #[test]
+    fn hex_test() {
+        use super::{deserialize_hex, serialize_hex};
+
+        assert_eq!(serialize_hex(&vec![1u8, 2, 3]), "03010203");
+        assert_eq!(deserialize_hex::<Vec<u8>>("03010203").unwrap(), vec![1u8, 2, 3]);
+        match deserialize_hex::<Vec<u8>>("0301020") {
+            Err(Error::Parse(ParseError::InvalidHex(_))) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match deserialize_hex::<Vec<u8>>("0301020304") {
+            Err(Error::Parse(ParseError::DataNotConsumed)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
     #[test]
     fn deserialize_checkeddata_test() {
         let cd: Result<CheckedData, _> = deserialize(&[5u8, 0, 0, 0, 162, 107, 175, 90, 1, 2, 3, 4, 5]);
         assert_eq!(cd.ok(), Some(CheckedData(vec![1u8, 2, 3, 4, 5])));
+
+        let data = [5u8, 0, 0, 0, 162, 107, 175, 90, 1, 2, 3, 4, 5, 6];
+        assert_eq!(CheckedData::decode_slice_with_limit(&data, 5).unwrap(), &[1u8, 2, 3, 4, 5][..]);
+        assert!(CheckedData::decode_slice_with_limit(&data, 4).is_err());
+        assert!(CheckedData::decode_slice_with_limit(&data[..12], 5).is_err());
+
+        let bad = [5u8, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5];
+        assert!(CheckedData::decode_slice_with_limit(&bad, 5).is_err());
+        assert_eq!(CheckedData::decode_slice_unchecked(&bad, 5).unwrap(), &[1u8, 2, 3, 4, 5][..]);
+        assert_eq!(CheckedData::consensus_decode_unchecked(&bad[..], 5).unwrap(), CheckedData(vec![1u8, 2, 3, 4, 5]));
     }
 
     #[test]
@@ -19,8 +19,10 @@
 //!
 
 pub mod encode;
-pub mod params;
+#[cfg(feature = "std")] pub mod params;
+#[cfg(feature = "std")] pub mod stream;
+#[cfg(feature = "serde")] pub mod serde_hex;
 
 pub use self::encode::{deserialize, deserialize_partial, serialize};
-pub use self::encode::{Decodable, Encodable, ReadExt, WriteExt};
-pub use self::params::Params;
+pub use self::encode::{ChainLimits, Decodable, Encodable, ReadExt, WriteExt};
+#[cfg(feature = "std")] pub use self::params::Params;
@@ -22,6 +22,7 @@
 //! This module provides predefined set of parameters for different chains.
 //!
 
+use consensus::encode::ChainLimits;
 use network::constants::Network;
 
 #[derive(Debug, Clone)]
@@ -29,18 +30,17 @@ use network::constants::Network;
 pub struct Params {
     /// Network for which parameters are valid.
     pub network: Network,
+    /// Decoding limits of the chain. The maximum block size can be raised
+    /// by the xfield of the block headers, update it accordingly.
+    pub limits: ChainLimits,
 }
 
 impl Params {
     /// Creates parameters set for the given network.
     pub fn new(network: Network) -> Self {
-        match network {
-            Network::Prod => Params {
-                network: Network::Prod,
-            },
-            Network::Dev => Params {
-                network: Network::Dev,
-            },
+        Params {
+            network: network,
+            limits: ChainLimits::default(),
         }
     }
 }
@@ -0,0 +1,104 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Serde through the consensus encoding
+//!
+//! This module (de)serializes any consensus-encodable type as a hex string in
+//! human-readable formats and as raw bytes in the others. It backs the serde
+//! implementations of `Transaction`, `Block`, `BlockHeader` and `OutPoint`, and
+//! can be used with `#[serde(with = "tapyrus::consensus::serde_hex")]` on
+//! fields of other consensus-encodable types.
+//!
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use hashes::hex::ToHex;
+use serde::{de, Deserializer, Serializer};
+
+use consensus::encode::{self, Decodable, Encodable};
+
+/// Serialize `data` as its hex-encoded consensus encoding in human-readable
+/// formats, and as the raw consensus encoding in the others
+pub fn serialize<T, S>(data: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Encodable,
+    S: Serializer,
+{
+    let bytes = encode::serialize(data);
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&bytes.to_hex())
+    } else {
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+/// Deserialize a value serialized with [`serialize`]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Decodable,
+    D: Deserializer<'de>,
+{
+    struct Visitor<T>(PhantomData<T>);
+    impl<'de, T: Decodable> de::Visitor<'de> for Visitor<T> {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a consensus-encoded object")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+            encode::deserialize_hex(v).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<T, E> {
+            encode::deserialize(v).map_err(E::custom)
+        }
+    }
+
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(Visitor(PhantomData))
+    } else {
+        deserializer.deserialize_bytes(Visitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use serde_test::{assert_tokens, Configure, Token};
+
+    use blockdata::transaction::OutPoint;
+    use hash_types::MalFixTxid;
+    use hashes::Hash;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Payload {
+        #[serde(with = "::consensus::serde_hex")]
+        outpoint: OutPoint,
+    }
+
+    #[test]
+    fn test_serde_hex() {
+        static HEX: &'static str = "9c12cfdc04c74584d787ac3d23772132c18524bc7ab28dec4219b8fc5b425f7002000000";
+        static BYTES: [u8; 36] = [
+            0x9c, 0x12, 0xcf, 0xdc, 0x04, 0xc7, 0x45, 0x84, 0xd7, 0x87, 0xac, 0x3d,
+            0x23, 0x77, 0x21, 0x32, 0xc1, 0x85, 0x24, 0xbc, 0x7a, 0xb2, 0x8d, 0xec,
+            0x42, 0x19, 0xb8, 0xfc, 0x5b, 0x42, 0x5f, 0x70, 0x02, 0x00, 0x00, 0x00,
+        ];
+
+        let payload = Payload { outpoint: OutPoint::new(MalFixTxid::hash(&[1]), 2) };
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(json, format!("{{\"outpoint\":\"{}\"}}", HEX));
+        assert_eq!(serde_json::from_str::<Payload>(&json).unwrap(), payload);
+
+        let tokens = [
+            Token::Struct { name: "Payload", len: 1 },
+            Token::Str("outpoint"),
+            Token::Bytes(&BYTES),
+            Token::StructEnd,
+        ];
+        assert_tokens(&payload.compact(), &tokens);
+    }
+}
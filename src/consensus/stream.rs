@@ -0,0 +1,213 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Block file streaming
+//!
+//! Tapyrus Core stores the blocks it receives in `blk*.dat` files, each
+//! block prefixed by the network magic and its serialized length. This
+//! module reads such files lazily, one block at a time.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use tapyrus::consensus::stream::BlockFileIterator;
+//! use tapyrus::network::constants::Network;
+//!
+//! let file = BufReader::new(File::open("blk00000.dat").unwrap());
+//! for block in BlockFileIterator::new(file, Network::Prod.magic()) {
+//!     println!("{}", block.unwrap().header.time);
+//! }
+//! ```
+//!
+
+use io::{self, Read};
+
+use blockdata::block::Block;
+use consensus::encode::{self, deserialize, ReadExt, MAX_VEC_SIZE};
+use util::endian;
+
+/// An iterator over the blocks of a `blk*.dat` file
+///
+/// Files are preallocated by Tapyrus Core, so the zero bytes following
+/// the last block are skipped. The iterator stops after the first error.
+pub struct BlockFileIterator<R: Read> {
+    reader: R,
+    magic: u32,
+    done: bool,
+}
+
+impl<R: Read> BlockFileIterator<R> {
+    /// Create an iterator over the blocks read from `reader`, framed by
+    /// the network `magic`
+    pub fn new(reader: R, magic: u32) -> BlockFileIterator<R> {
+        BlockFileIterator { reader: reader, magic: magic, done: false }
+    }
+
+    /// Return the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the magic of the next block, or `None` at the end of the data
+    fn read_magic(&mut self) -> Result<Option<u32>, encode::Error> {
+        let mut buf = [0u8; 4];
+        let mut read = 0;
+        while read < buf.len() {
+            match self.reader.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(encode::Error::Io(e)),
+            }
+        }
+        if read == 0 {
+            return Ok(None);
+        }
+        if buf[..read].iter().all(|b| *b == 0) {
+            self.skip_padding()?;
+            return Ok(None);
+        }
+        if read < buf.len() {
+            return Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        Ok(Some(endian::slice_to_u32_le(&buf)))
+    }
+
+    /// Check that only zero bytes remain
+    fn skip_padding(&mut self) -> Result<(), encode::Error> {
+        let mut buf = [0u8; 256];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => if buf[..n].iter().any(|b| *b != 0) {
+                    return Err(encode::Error::Parse(encode::ParseError::Other("data after block file padding")));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(encode::Error::Io(e)),
+            }
+        }
+    }
+
+    fn next_block(&mut self) -> Result<Option<Block>, encode::Error> {
+        let magic = match self.read_magic()? {
+            Some(magic) => magic,
+            None => return Ok(None),
+        };
+        if magic != self.magic {
+            return Err(encode::Error::Parse(encode::ParseError::UnexpectedNetworkMagic { expected: self.magic, actual: magic }));
+        }
+        let len = self.reader.read_u32()? as usize;
+        if len > MAX_VEC_SIZE {
+            return Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation { requested: len, max: MAX_VEC_SIZE }));
+        }
+        let mut data = vec![0u8; len];
+        self.reader.read_slice(&mut data)?;
+        deserialize(&data).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for BlockFileIterator<R> {
+    type Item = Result<Block, encode::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_block() {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockFileIterator;
+
+    use blockdata::block::{Block, BlockHeader, XField};
+    use consensus::encode::{serialize, Error, ParseError};
+    use hashes::Hash;
+    use hash_types::{BlockHash, TxMerkleNode};
+
+    const MAGIC: u32 = 0x00F0FF01;
+
+    fn block(time: u32) -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: BlockHash::hash(&[0]),
+                merkle_root: TxMerkleNode::hash(&[0]),
+                im_merkle_root: TxMerkleNode::hash(&[1]),
+                time: time,
+                xfield: XField::None,
+                proof: None,
+            },
+            txdata: vec![],
+        }
+    }
+
+    fn framed(magic: u32, block: &Block) -> Vec<u8> {
+        let data = serialize(block);
+        let mut ret = serialize(&magic);
+        ret.extend(serialize(&(data.len() as u32)));
+        ret.extend(data);
+        ret
+    }
+
+    #[test]
+    fn test_block_file_iterator() {
+        let mut file = framed(MAGIC, &block(1));
+        file.extend(framed(MAGIC, &block(2)));
+        let blocks: Vec<Block> = BlockFileIterator::new(&file[..], MAGIC).map(Result::unwrap).collect();
+        assert_eq!(blocks, vec![block(1), block(2)]);
+
+        // trailing zero padding, including a partial magic
+        for padding in &[1, 3, 4, 1000] {
+            let mut padded = file.clone();
+            padded.extend(vec![0u8; *padding]);
+            assert_eq!(BlockFileIterator::new(&padded[..], MAGIC).count(), 2);
+        }
+
+        assert_eq!(BlockFileIterator::new(&[][..], MAGIC).count(), 0);
+    }
+
+    #[test]
+    fn test_block_file_iterator_errors() {
+        let mut file = framed(MAGIC, &block(1));
+        file.extend(framed(0x0B110907, &block(2)));
+        file.extend(framed(MAGIC, &block(3)));
+        let mut iter = BlockFileIterator::new(&file[..], MAGIC);
+        assert_eq!(iter.next().unwrap().unwrap(), block(1));
+        match iter.next() {
+            Some(Err(Error::Parse(ParseError::UnexpectedNetworkMagic { expected, actual }))) => {
+                assert_eq!(expected, MAGIC);
+                assert_eq!(actual, 0x0B110907);
+            }
+            _ => panic!("expected a magic error"),
+        }
+        assert!(iter.next().is_none());
+
+        // truncated block
+        let file = framed(MAGIC, &block(1));
+        let mut iter = BlockFileIterator::new(&file[..file.len() - 1], MAGIC);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        // data after the padding
+        let mut file = framed(MAGIC, &block(1));
+        file.extend(vec![0, 0, 0, 0, 1]);
+        let mut iter = BlockFileIterator::new(&file[..], MAGIC);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_err());
+    }
+}
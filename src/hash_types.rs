@@ -16,7 +16,7 @@
 //! to avoid mixing data of the same hash format (like SHA256d) but of different meaning
 //! (transaction id, block hash etc).
 
-use std::io;
+use io;
 
 use consensus::encode::{Encodable, Decodable, Error};
 use hashes::{sha256, sha256d, hash160, Hash};
@@ -38,7 +38,21 @@ macro_rules! impl_hashencode {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+macro_rules! impl_arbitrary_hash {
+    ($($hashtype:ident),*) => {
+        $(
+            impl ::arbitrary::Arbitrary for $hashtype {
+                fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<Self> {
+                    Ok(Self::from_inner(u.arbitrary()?))
+                }
+            }
+        )*
+    }
+}
+
 hash_newtype!(Txid, sha256d::Hash, 32, doc="A bitcoin transaction hash/transaction ID.");
+hash_newtype!(MalFixTxid, sha256d::Hash, 32, doc="A malleability-fixed transaction ID, which excludes input scripts. Outpoints refer to transactions by this ID.");
 hash_newtype!(Wtxid, sha256d::Hash, 32, doc="A bitcoin witness transaction ID.");
 hash_newtype!(BlockHash, sha256d::Hash, 32, doc="A bitcoin block hash.");
 hash_newtype!(BlockSigHash, sha256d::Hash, 32, doc="Hash of the block for sigining.");
@@ -58,6 +72,7 @@ hash_newtype!(FilterHash, sha256d::Hash, 32, doc="Bloom filter souble-SHA256 loc
 
 
 impl_hashencode!(Txid);
+impl_hashencode!(MalFixTxid);
 impl_hashencode!(Wtxid);
 impl_hashencode!(SigHash);
 impl_hashencode!(BlockHash);
@@ -65,3 +80,8 @@ impl_hashencode!(BlockSigHash);
 impl_hashencode!(TxMerkleNode);
 impl_hashencode!(WitnessMerkleNode);
 impl_hashencode!(FilterHash);
+
+#[cfg(feature = "arbitrary")]
+impl_arbitrary_hash!(Txid, MalFixTxid, Wtxid, BlockHash, BlockSigHash, SigHash, PubkeyHash, ScriptHash,
+                     WPubkeyHash, WScriptHash, TxMerkleNode, WitnessMerkleNode,
+                     WitnessCommitment, XpubIdentifier, FilterHash);
@@ -0,0 +1,338 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! I/O
+//!
+//! The readers and writers consensus encoding works with. With the `std`
+//! feature, these are those of `std::io`. Without it, this module provides
+//! the minimal subset of `std::io` the library needs, so that transactions
+//! and blocks can be encoded and decoded with `alloc` only, e.g. on embedded
+//! signing devices. Implement `Read` and `Write` to stream data from and to
+//! other sources.
+//!
+
+#[cfg(feature = "std")]
+pub use std::io::{sink, Cursor, Error, ErrorKind, Read, Result, Sink, Take, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::core_io::*;
+
+#[cfg(any(not(feature = "std"), test))]
+#[cfg_attr(feature = "std", allow(dead_code))] // only tested with std
+mod core_io {
+    use core::{cmp, fmt, result};
+
+    use hashes::{hash160, ripemd160, sha1, sha256, sha256d, sha512, HashEngine};
+    use prelude::*;
+
+    /// The result of an I/O operation
+    pub type Result<T> = result::Result<T, Error>;
+
+    /// The kinds of I/O errors, a subset of those of `std::io`
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+    pub enum ErrorKind {
+        /// The data ended before all the expected bytes could be read
+        UnexpectedEof,
+        /// No bytes could be written
+        WriteZero,
+        /// A parameter was incorrect
+        InvalidInput,
+        /// The data read is not valid
+        InvalidData,
+        /// The operation was interrupted and can be retried
+        Interrupted,
+        /// Any other error
+        Other,
+    }
+
+    impl ErrorKind {
+        fn description(self) -> &'static str {
+            match self {
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::InvalidInput => "invalid input parameter",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::Other => "other os error",
+            }
+        }
+    }
+
+    /// An I/O error, of a kind and with a static message
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: &'static str,
+    }
+
+    impl Error {
+        /// Create an error of `kind`, described by `message`
+        pub fn new(kind: ErrorKind, message: &'static str) -> Error {
+            Error { kind: kind, message: message }
+        }
+
+        /// The kind of the error
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error::new(kind, kind.description())
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    /// A source of bytes
+    pub trait Read {
+        /// Read some bytes into `buf`, returning how many were read, 0 at
+        /// the end of the data
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Read exactly enough bytes to fill `buf`
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => return Err(Error::from(ErrorKind::UnexpectedEof)),
+                    Ok(n) => {
+                        let rest = buf;
+                        buf = &mut rest[n..];
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        /// A reader reading at most `limit` bytes from this one
+        fn take(self, limit: u64) -> Take<Self> where Self: Sized {
+            Take { inner: self, limit: limit }
+        }
+    }
+
+    /// A sink of bytes
+    pub trait Write {
+        /// Write some bytes of `buf`, returning how many were written
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Flush the bytes buffered, if any
+        fn flush(&mut self) -> Result<()>;
+
+        /// Write all the bytes of `buf`
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(Error::from(ErrorKind::WriteZero)),
+                    Ok(n) => buf = &buf[n..],
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<'a, R: Read + ?Sized> Read for &'a mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<'a> Read for &'a [u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = cmp::min(buf.len(), self.len());
+            let (head, tail) = self.split_at(n);
+            buf[..n].copy_from_slice(head);
+            *self = tail;
+            Ok(n)
+        }
+    }
+
+    impl<'a, W: Write + ?Sized> Write for &'a mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A reader over an in-memory buffer, keeping track of its position
+    #[derive(Clone, Debug)]
+    pub struct Cursor<T> {
+        inner: T,
+        position: u64,
+    }
+
+    impl<T> Cursor<T> {
+        /// A cursor at the start of `inner`
+        pub fn new(inner: T) -> Cursor<T> {
+            Cursor { inner: inner, position: 0 }
+        }
+
+        /// The buffer the cursor reads
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+
+        /// A reference to the buffer the cursor reads
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        /// The position of the cursor in the buffer
+        pub fn position(&self) -> u64 {
+            self.position
+        }
+
+        /// Move the cursor to `position`
+        pub fn set_position(&mut self, position: u64) {
+            self.position = position;
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let data = self.inner.as_ref();
+            let start = cmp::min(self.position, data.len() as u64) as usize;
+            let n = (&data[start..]).read(buf)?;
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    /// A reader reading at most a limited number of bytes from another
+    #[derive(Debug)]
+    pub struct Take<R> {
+        inner: R,
+        limit: u64,
+    }
+
+    impl<R> Take<R> {
+        /// The number of bytes which may still be read
+        pub fn limit(&self) -> u64 {
+            self.limit
+        }
+
+        /// The reader read from
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+    }
+
+    impl<R: Read> Read for Take<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.limit == 0 {
+                return Ok(0);
+            }
+            let max = cmp::min(buf.len() as u64, self.limit) as usize;
+            let n = self.inner.read(&mut buf[..max])?;
+            self.limit -= n as u64;
+            Ok(n)
+        }
+    }
+
+    /// A writer discarding all the bytes written to it
+    #[derive(Copy, Clone, Debug)]
+    pub struct Sink {
+        _private: (),
+    }
+
+    /// A writer discarding all the bytes written to it, to count them
+    pub fn sink() -> Sink {
+        Sink { _private: () }
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    macro_rules! impl_write_engine {
+        ($($engine:ty),*) => {
+            $(
+                impl Write for $engine {
+                    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+                        self.input(buf);
+                        Ok(buf.len())
+                    }
+
+                    fn flush(&mut self) -> Result<()> {
+                        Ok(())
+                    }
+                }
+            )*
+        }
+    }
+
+    impl_write_engine!(sha1::HashEngine, sha256::HashEngine, sha256d::HashEngine, sha512::HashEngine,
+                       ripemd160::HashEngine, hash160::HashEngine);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::core_io::{self, Read, Write};
+
+    #[test]
+    fn test_read() {
+        let data = [1u8, 2, 3, 4, 5];
+        let mut cursor = core_io::Cursor::new(&data[..]);
+        let mut buf = [0u8; 2];
+        cursor.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(cursor.position(), 2);
+
+        let mut take = (&mut cursor).take(2);
+        let mut buf = [0u8; 3];
+        assert_eq!(take.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [3, 4, 0]);
+        assert_eq!(take.read(&mut buf).unwrap(), 0);
+        assert_eq!(cursor.position(), 4);
+
+        let mut buf = [0u8; 2];
+        assert_eq!(cursor.read_exact(&mut buf).unwrap_err().kind(), core_io::ErrorKind::UnexpectedEof);
+
+        let mut slice = &data[..];
+        let mut buf = [0u8; 4];
+        slice.read_exact(&mut buf).unwrap();
+        assert_eq!(slice, [5]);
+    }
+
+    #[test]
+    fn test_write() {
+        let mut vec = Vec::new();
+        core_io::Write::write_all(&mut vec, &[1, 2]).unwrap();
+        (&mut vec).write_all(&[3]).unwrap();
+        assert_eq!(vec, [1, 2, 3]);
+        assert_eq!(core_io::sink().write(&[0; 10]).unwrap(), 10);
+        assert_eq!(
+            core_io::Error::from(core_io::ErrorKind::WriteZero).to_string(),
+            "write zero"
+        );
+    }
+}
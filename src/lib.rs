@@ -27,6 +27,10 @@
 #![crate_type = "dylib"]
 #![crate_type = "rlib"]
 
+// Without the `std` feature, the consensus encoding, keys, scripts,
+// transactions and blocks are built with `alloc`
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
 // Experimental features we need
 #![cfg_attr(all(test, feature = "unstable"), feature(test))]
 
@@ -51,6 +55,12 @@
 #![allow(bare_trait_objects)]
 #![allow(ellipsis_inclusive_range_patterns)]
 
+#[cfg(all(not(feature = "std"), not(test)))]
+#[macro_use]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+
 // Re-exported dependencies.
 #[macro_use] pub extern crate bitcoin_hashes as hashes;
 pub extern crate secp256k1;
@@ -62,8 +72,15 @@ pub extern crate secp256k1;
 #[cfg(all(test, feature = "serde"))] extern crate serde_test;
 #[cfg(all(test, feature = "unstable"))] extern crate test;
 #[cfg(feature="bitcoinconsensus")] extern crate bitcoinconsensus;
+#[cfg(feature = "arbitrary")] extern crate arbitrary;
+#[cfg(feature = "tokio")] extern crate bytes;
+#[cfg(feature = "tokio")] extern crate tokio_util;
+#[cfg(feature = "base64")] extern crate base64;
+#[cfg(feature = "rayon")] extern crate rayon;
+#[cfg(feature = "v2transport")] extern crate chacha20;
+#[cfg(feature = "v2transport")] extern crate chacha20poly1305;
 
-extern crate rug;
+extern crate num_bigint;
 
 #[cfg(target_pointer_width = "16")]
 compile_error!("rust-bitcoin cannot be used on 16-bit architectures");
@@ -75,9 +92,13 @@ mod test_macros;
 mod test_helpers;
 #[macro_use]
 mod internal_macros;
+mod prelude;
+pub mod io;
 #[macro_use]
 pub mod network;
 pub mod blockdata;
+#[cfg(feature = "std")]
+pub mod blockchain;
 pub mod util;
 pub mod consensus;
 // Do not remove: required in order to get hash types implementation macros to work correctly
@@ -94,15 +115,18 @@ pub use blockdata::transaction::TxIn;
 pub use blockdata::transaction::TxOut;
 pub use blockdata::transaction::OutPoint;
 pub use blockdata::transaction::SigHashType;
+pub use blockdata::locktime::LockTime;
+pub use blockdata::locktime::Sequence;
 pub use consensus::encode::VarInt;
 pub use network::constants::Network;
-pub use util::Error;
-pub use util::address::Address;
-pub use util::address::AddressType;
+#[cfg(feature = "std")] pub use util::Error;
+#[cfg(feature = "std")] pub use util::address::Address;
+#[cfg(feature = "std")] pub use util::address::AddressType;
 pub use util::amount::Amount;
 pub use util::amount::SignedAmount;
 pub use util::hash::BitcoinHash;
 pub use util::key::PrivateKey;
 pub use util::key::PublicKey;
-pub use util::merkleblock::MerkleBlock;
+#[cfg(feature = "std")] pub use util::merkleblock::MerkleBlock;
+pub use util::schnorr::SchnorrSignature;
 pub use util::signature::Signature;
@@ -20,10 +20,10 @@
 
 use std::io;
 use std::fmt;
-use std::net::{SocketAddr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::net::{SocketAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 
 use network::constants::ServiceFlags;
-use consensus::encode::{self, Decodable, Encodable};
+use consensus::encode::{self, Decodable, Encodable, ReadExt, VarInt, WriteExt};
 
 /// A message which can be sent on the Bitcoin network
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -97,6 +97,9 @@ impl Decodable for Address {
     }
 }
 
+serde_struct_impl!(Address, services, address, port);
+arbitrary_struct_impl!(Address, services, address, port);
+
 impl fmt::Debug for Address {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let ipv6 = Ipv6Addr::from(self.address);
@@ -110,14 +113,236 @@ impl fmt::Debug for Address {
     }
 }
 
+/// Maximum length of an address in an `addrv2` message
+const MAX_ADDRV2_LEN: u64 = 512;
+
+/// Network address as defined by BIP155, able to represent addresses which
+/// do not fit in the 16 bytes of the legacy format
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AddrV2 {
+    /// IPv4 address
+    Ipv4(Ipv4Addr),
+    /// IPv6 address
+    Ipv6(Ipv6Addr),
+    /// Tor v2 onion service
+    TorV2([u8; 10]),
+    /// Tor v3 onion service, identified by its ed25519 public key
+    TorV3([u8; 32]),
+    /// I2P address, identified by the SHA256 of its destination
+    I2p([u8; 32]),
+    /// CJDNS address
+    Cjdns(Ipv6Addr),
+    /// Address of an unknown network, kept as raw bytes
+    Unknown(u8, Vec<u8>),
+}
+
+impl AddrV2 {
+    /// The BIP155 network ID of this address
+    pub fn network_id(&self) -> u8 {
+        match *self {
+            AddrV2::Ipv4(_) => 1,
+            AddrV2::Ipv6(_) => 2,
+            AddrV2::TorV2(_) => 3,
+            AddrV2::TorV3(_) => 4,
+            AddrV2::I2p(_) => 5,
+            AddrV2::Cjdns(_) => 6,
+            AddrV2::Unknown(id, _) => id,
+        }
+    }
+}
+
+fn encode_addrv2_bytes<S: io::Write>(mut s: S, network_id: u8, bytes: &[u8]) -> Result<usize, encode::Error> {
+    let len = network_id.consensus_encode(&mut s)?
+        + VarInt(bytes.len() as u64).consensus_encode(&mut s)?;
+    s.emit_slice(bytes)?;
+    Ok(len + bytes.len())
+}
+
+impl Encodable for AddrV2 {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        let id = self.network_id();
+        match *self {
+            AddrV2::Ipv4(ref addr) => encode_addrv2_bytes(s, id, &addr.octets()),
+            AddrV2::Ipv6(ref addr) | AddrV2::Cjdns(ref addr) => encode_addrv2_bytes(s, id, &addr.octets()),
+            AddrV2::TorV2(ref bytes) => encode_addrv2_bytes(s, id, bytes),
+            AddrV2::TorV3(ref bytes) | AddrV2::I2p(ref bytes) => encode_addrv2_bytes(s, id, bytes),
+            AddrV2::Unknown(_, ref bytes) => encode_addrv2_bytes(s, id, bytes),
+        }
+    }
+}
+
+impl Decodable for AddrV2 {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let network_id = u8::consensus_decode(&mut d)?;
+        let len = VarInt::consensus_decode(&mut d)?.0;
+        if len > MAX_ADDRV2_LEN {
+            return Err(encode::Error::Parse(encode::ParseError::Other("addrv2 address too long")));
+        }
+        let expected_len = match network_id {
+            1 => Some(4),
+            2 | 6 => Some(16),
+            3 => Some(10),
+            4 | 5 => Some(32),
+            _ => None,
+        };
+        if expected_len.map_or(false, |expected| expected != len) {
+            return Err(encode::Error::Parse(encode::ParseError::Other("invalid addrv2 address length")));
+        }
+
+        let mut bytes = vec![0u8; len as usize];
+        d.read_slice(&mut bytes)?;
+        Ok(match network_id {
+            1 => AddrV2::Ipv4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+            2 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&bytes);
+                let addr = Ipv6Addr::from(octets);
+                // IPv4-mapped and Tor v2 addresses have their own network IDs
+                let segments = addr.segments();
+                if segments[0..3] == ONION || segments[0..6] == [0, 0, 0, 0, 0, 0xffff] {
+                    return Err(encode::Error::Parse(encode::ParseError::Other("invalid addrv2 IPv6 address")));
+                }
+                AddrV2::Ipv6(addr)
+            },
+            3 => {
+                let mut id = [0u8; 10];
+                id.copy_from_slice(&bytes);
+                AddrV2::TorV2(id)
+            },
+            4 => {
+                let mut pubkey = [0u8; 32];
+                pubkey.copy_from_slice(&bytes);
+                AddrV2::TorV3(pubkey)
+            },
+            5 => {
+                let mut hash = [0u8; 32];
+                hash.copy_from_slice(&bytes);
+                AddrV2::I2p(hash)
+            },
+            6 => {
+                if bytes[0] != 0xfc {
+                    return Err(encode::Error::Parse(encode::ParseError::Other("invalid CJDNS address")));
+                }
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&bytes);
+                AddrV2::Cjdns(Ipv6Addr::from(octets))
+            },
+            id => AddrV2::Unknown(id, bytes),
+        })
+    }
+}
+
+// Addresses are serialized as the hex of their BIP155 encoding
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for AddrV2 {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&encode::serialize_hex(self))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for AddrV2 {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<AddrV2, D::Error> {
+        use hashes::hex::FromHex;
+        use serde::de::Error;
+
+        let hex: String = ::serde::Deserialize::deserialize(d)?;
+        let bytes = Vec::<u8>::from_hex(&hex).map_err(D::Error::custom)?;
+        encode::deserialize(&bytes).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for AddrV2 {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<AddrV2> {
+        Ok(match u.int_in_range(1u8..=7)? {
+            1 => AddrV2::Ipv4(Ipv4Addr::from(u.arbitrary::<u32>()?)),
+            2 => {
+                let addr = Ipv6Addr::from(u.arbitrary::<[u8; 16]>()?);
+                let segments = addr.segments();
+                if segments[0..3] == ONION || segments[0..6] == [0, 0, 0, 0, 0, 0xffff] {
+                    return Err(::arbitrary::Error::IncorrectFormat);
+                }
+                AddrV2::Ipv6(addr)
+            },
+            3 => AddrV2::TorV2(u.arbitrary()?),
+            4 => AddrV2::TorV3(u.arbitrary()?),
+            5 => AddrV2::I2p(u.arbitrary()?),
+            6 => {
+                let mut octets: [u8; 16] = u.arbitrary()?;
+                octets[0] = 0xfc;
+                AddrV2::Cjdns(Ipv6Addr::from(octets))
+            },
+            _ => {
+                // Skip the network IDs known to the decoder
+                let id = u.int_in_range(7u8..=255)?;
+                let mut bytes: Vec<u8> = u.arbitrary()?;
+                bytes.truncate(MAX_ADDRV2_LEN as usize);
+                AddrV2::Unknown(id, bytes)
+            },
+        })
+    }
+}
+
+/// An entry of the `addrv2` message
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AddrV2Message {
+    /// Time the peer was last seen
+    pub time: u32,
+    /// Services provided by the peer
+    pub services: ServiceFlags,
+    /// Network address of the peer
+    pub addr: AddrV2,
+    /// Network port
+    pub port: u16,
+}
+
+serde_struct_impl!(AddrV2Message, time, services, addr, port);
+arbitrary_struct_impl!(AddrV2Message, time, services, addr, port);
+
+impl AddrV2Message {
+    /// Extract the socket address. This will return io::Error
+    /// ErrorKind::AddrNotAvailable for addresses other than IPv4 and IPv6.
+    pub fn socket_addr(&self) -> Result<SocketAddr, io::Error> {
+        match self.addr {
+            AddrV2::Ipv4(ref addr) => Ok(SocketAddr::V4(SocketAddrV4::new(*addr, self.port))),
+            AddrV2::Ipv6(ref addr) => Ok(SocketAddr::V6(SocketAddrV6::new(*addr, self.port, 0, 0))),
+            _ => Err(io::Error::from(io::ErrorKind::AddrNotAvailable)),
+        }
+    }
+}
+
+impl Encodable for AddrV2Message {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let len = self.time.consensus_encode(&mut s)?
+            // Services are encoded as a CompactSize in addrv2
+            + VarInt(self.services.as_u64()).consensus_encode(&mut s)?
+            + self.addr.consensus_encode(&mut s)?
+            + self.port.to_be().consensus_encode(s)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for AddrV2Message {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        Ok(AddrV2Message {
+            time: Decodable::consensus_decode(&mut d)?,
+            services: ServiceFlags::from(VarInt::consensus_decode(&mut d)?.0),
+            addr: Decodable::consensus_decode(&mut d)?,
+            port: u16::from_be(Decodable::consensus_decode(d)?),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
-    use super::Address;
+    use super::{Address, AddrV2, AddrV2Message};
     use network::constants::ServiceFlags;
     use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
 
     use consensus::encode::{deserialize, serialize};
+    use hex::decode as hex_decode;
 
     #[test]
     fn serialize_address_test() {
@@ -191,5 +416,64 @@ mod test {
         let addr = Address::new(&onionaddr, ServiceFlags::NONE);
         assert!(addr.socket_addr().is_err());
     }
-}
 
+    #[test]
+    fn addrv2_test() {
+        let cases = vec![
+            ("010401020304", AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4))),
+            ("02101a1b2a2b3a3b4a4b5a5b6a6b7a7b8a8b",
+             AddrV2::Ipv6(Ipv6Addr::from_str("1a1b:2a2b:3a3b:4a4b:5a5b:6a6b:7a7b:8a8b").unwrap())),
+            ("030af1f2f3f4f5f6f7f8f9fa",
+             AddrV2::TorV2([0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa])),
+            ("042079bcc625184b05194975c28b66b66b0469f7f6556fb1ac3189a79b40dda32f1f",
+             AddrV2::TorV3([0x79, 0xbc, 0xc6, 0x25, 0x18, 0x4b, 0x05, 0x19, 0x49, 0x75, 0xc2, 0x8b,
+                            0x66, 0xb6, 0x6b, 0x04, 0x69, 0xf7, 0xf6, 0x55, 0x6f, 0xb1, 0xac, 0x31,
+                            0x89, 0xa7, 0x9b, 0x40, 0xdd, 0xa3, 0x2f, 0x1f])),
+            ("0520a2894dabaec08c0051a481a6dac88b64f98232ae42d4b6fd2fa81952dfe36a87",
+             AddrV2::I2p([0xa2, 0x89, 0x4d, 0xab, 0xae, 0xc0, 0x8c, 0x00, 0x51, 0xa4, 0x81, 0xa6,
+                          0xda, 0xc8, 0x8b, 0x64, 0xf9, 0x82, 0x32, 0xae, 0x42, 0xd4, 0xb6, 0xfd,
+                          0x2f, 0xa8, 0x19, 0x52, 0xdf, 0xe3, 0x6a, 0x87])),
+            ("0610fc000001000200030004000500060007",
+             AddrV2::Cjdns(Ipv6Addr::from_str("fc00:1:2:3:4:5:6:7").unwrap())),
+            ("aa0401020304", AddrV2::Unknown(0xaa, vec![1, 2, 3, 4])),
+        ];
+        for (hex, addr) in cases {
+            let bytes = hex_decode(hex).unwrap();
+            assert_eq!(deserialize::<AddrV2>(&bytes).unwrap(), addr);
+            assert_eq!(serialize(&addr), bytes);
+        }
+
+        let invalid = vec![
+            // IPv4 with a wrong length
+            "01050102030405",
+            // IPv4-mapped IPv6
+            "021000000000000000000000ffff01020304",
+            // Tor v2 prefix in IPv6
+            "0210fd87d87eeb430102030405060708090a",
+            // CJDNS without the fc prefix
+            "0610aa000001000200030004000500060007",
+            // address longer than 512 bytes
+            "aafd0102",
+        ];
+        for hex in invalid {
+            assert!(deserialize::<AddrV2>(&hex_decode(hex).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn addrv2_message_test() {
+        let msg = AddrV2Message {
+            time: 0x4966bc61,
+            services: ServiceFlags::NETWORK | ServiceFlags::WITNESS,
+            addr: AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            port: 8333,
+        };
+        let bytes = serialize(&msg);
+        assert_eq!(bytes, hex_decode("61bc664909010401020304208d").unwrap());
+        assert_eq!(deserialize::<AddrV2Message>(&bytes).unwrap(), msg);
+        assert_eq!(msg.socket_addr().unwrap(), SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 8333));
+
+        let onion = AddrV2Message { addr: AddrV2::TorV3([1u8; 32]), ..msg };
+        assert!(onion.socket_addr().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Tokio codec
+//!
+//! This module defines `RawNetworkMessageCodec` which implements tokio's
+//! `Encoder` and `Decoder` traits, so that a stream of `RawNetworkMessage`s
+//! can be framed directly off an asynchronous socket.
+//!
+
+use std::io;
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use consensus::encode::{self, Encodable};
+use network::message::{DecodeConfig, RawNetworkMessage};
+use util::endian;
+
+/// Size of the message header: magic (4), command (12), length (4) and checksum (4)
+const HEADER_SIZE: usize = 24;
+
+/// Codec framing `RawNetworkMessage`s on a byte stream
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawNetworkMessageCodec {
+    config: DecodeConfig,
+}
+
+impl RawNetworkMessageCodec {
+    /// Creates a new codec using the default size limits
+    pub fn new() -> RawNetworkMessageCodec {
+        RawNetworkMessageCodec::default()
+    }
+
+    /// Creates a new codec enforcing the size limits of `config`
+    pub fn with_config(config: DecodeConfig) -> RawNetworkMessageCodec {
+        RawNetworkMessageCodec { config: config }
+    }
+}
+
+impl Decoder for RawNetworkMessageCodec {
+    type Item = RawNetworkMessage;
+    type Error = encode::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RawNetworkMessage>, encode::Error> {
+        if src.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let payload_len = endian::slice_to_u32_le(&src[16..20]) as usize;
+        if payload_len > self.config.max_message_size {
+            return Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation {
+                requested: payload_len,
+                max: self.config.max_message_size,
+            }));
+        }
+
+        let frame_len = HEADER_SIZE + payload_len;
+        if src.len() < frame_len {
+            // Make room for the rest of the frame so that the next reads
+            // do not need to reallocate.
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_len);
+        // Decoding the full frame also verifies the payload checksum and
+        // the per-command limits. The payload is decoded straight out of
+        // the frame without being copied.
+        RawNetworkMessage::consensus_decode_from_slice(&frame[..], &self.config).map(|(msg, _)| Some(msg))
+    }
+}
+
+impl Encoder<RawNetworkMessage> for RawNetworkMessageCodec {
+    type Error = encode::Error;
+
+    fn encode(&mut self, item: RawNetworkMessage, dst: &mut BytesMut) -> Result<(), encode::Error> {
+        dst.reserve(item.consensus_encoded_len());
+        item.consensus_encode(BytesWriter(dst))?;
+        Ok(())
+    }
+}
+
+/// Writer appending to a `BytesMut`
+struct BytesWriter<'a>(&'a mut BytesMut);
+
+impl<'a> io::Write for BytesWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::RawNetworkMessageCodec;
+    use consensus::encode::{self, serialize};
+    use network::message::{NetworkMessage, RawNetworkMessage};
+
+    const MSG_VERACK: [u8; 24] = [
+        0xf9, 0xbe, 0xb4, 0xd9, 0x76, 0x65, 0x72, 0x61,
+        0x63, 0x6b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x5d, 0xf6, 0xe0, 0xe2
+    ];
+
+    const MSG_PING: [u8; 32] = [
+        0xf9, 0xbe, 0xb4, 0xd9, 0x70, 0x69, 0x6e, 0x67,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x24, 0x67, 0xf1, 0x1d,
+        0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decode_partial_test() {
+        let mut codec = RawNetworkMessageCodec::new();
+        let mut buf = BytesMut::new();
+
+        buf.extend_from_slice(&MSG_PING[..20]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&MSG_PING[20..30]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&MSG_PING[30..]);
+        buf.extend_from_slice(&MSG_VERACK[..]);
+
+        let msg = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.magic, 0xd9b4bef9);
+        assert_eq!(msg.payload, NetworkMessage::Ping(100));
+
+        let msg = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Verack);
+        assert!(buf.is_empty());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_bad_checksum_test() {
+        let mut codec = RawNetworkMessageCodec::new();
+        let mut data = MSG_PING;
+        data[20] ^= 0xff;
+        let mut buf = BytesMut::from(&data[..]);
+        match codec.decode(&mut buf) {
+            Err(encode::Error::Parse(encode::ParseError::InvalidChecksum { .. })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_oversized_test() {
+        let mut codec = RawNetworkMessageCodec::new();
+        let mut data = MSG_VERACK;
+        data[16..20].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f]);
+        let mut buf = BytesMut::from(&data[..]);
+        match codec.decode(&mut buf) {
+            Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation { .. })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn encode_test() {
+        let mut codec = RawNetworkMessageCodec::new();
+        let msg = RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::Ping(100) };
+        let mut buf = BytesMut::new();
+        codec.encode(msg, &mut buf).unwrap();
+        assert_eq!(&buf[..], &MSG_PING[..]);
+
+        let decoded = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(serialize(&decoded), MSG_PING.to_vec());
+    }
+}
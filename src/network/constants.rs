@@ -41,10 +41,12 @@
 //! assert_eq!(&bytes[..], &[0x01, 0xFF, 0xF0, 0x00]);
 //! ```
 
-use std::{fmt, io, ops};
-use std::str::FromStr;
+use core::{fmt, ops};
+use core::str::FromStr;
 
 use consensus::encode::{self, Encodable, Decodable};
+use hash_types::BlockHash;
+use io;
 
 /// Version of the protocol as appearing in network message headers
 pub const PROTOCOL_VERSION: u32 = 10000;
@@ -81,7 +83,7 @@ impl From<u32> for NetworkId {
 }
 
 impl FromStr for NetworkId {
-    type Err = std::num::ParseIntError;
+    type Err = ::core::num::ParseIntError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(NetworkId::from(s.parse::<u32>()?))
     }
@@ -121,14 +123,223 @@ impl<'de> ::serde::Deserialize<'de> for NetworkId {
     }
 }
 
-user_enum! {
-    /// The cryptocurrency to act on
-    #[derive(Copy, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
-    pub enum Network {
-        /// For production
-        Prod <-> "prod",
-        /// For development
-        Dev <-> "dev"
+/// Parameters of a Tapyrus network
+///
+/// Besides the production and development networks, Tapyrus allows
+/// arbitrary networks to be created. Their parameters can be described with
+/// this struct and used through `Network::Custom`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NetworkParams {
+    /// The network ID, from which the network magic is derived
+    pub network_id: u32,
+    /// Version byte of pay-to-pubkey-hash addresses
+    pub p2pkh_prefix: u8,
+    /// Version byte of pay-to-script-hash addresses
+    pub p2sh_prefix: u8,
+    /// Version byte of colored pay-to-pubkey-hash addresses
+    pub cp2pkh_prefix: u8,
+    /// Version byte of colored pay-to-script-hash addresses
+    pub cp2sh_prefix: u8,
+    /// Version byte of WIF private keys
+    pub wif_prefix: u8,
+    /// Version bytes of BIP32 extended public keys
+    pub xpub_version: [u8; 4],
+    /// Version bytes of BIP32 extended private keys
+    pub xpriv_version: [u8; 4],
+    /// Hash of the genesis block, if known. Tapyrus genesis blocks are
+    /// signed by each network's federation, so this is not fixed by the
+    /// network kind.
+    pub genesis_hash: Option<BlockHash>,
+}
+
+serde_struct_impl!(NetworkParams, network_id, p2pkh_prefix, p2sh_prefix, cp2pkh_prefix, cp2sh_prefix,
+                   wif_prefix, xpub_version, xpriv_version, genesis_hash);
+
+impl NetworkParams {
+    /// Parameters of the default production network
+    pub const PROD: NetworkParams = NetworkParams {
+        network_id: 1,
+        p2pkh_prefix: 0,
+        p2sh_prefix: 5,
+        cp2pkh_prefix: 1,
+        cp2sh_prefix: 6,
+        wif_prefix: 128,
+        xpub_version: [0x04, 0x88, 0xB2, 0x1E],
+        xpriv_version: [0x04, 0x88, 0xAD, 0xE4],
+        genesis_hash: None,
+    };
+
+    /// Parameters of the default development network
+    pub const DEV: NetworkParams = NetworkParams {
+        network_id: 1905960821,
+        p2pkh_prefix: 111,
+        p2sh_prefix: 196,
+        cp2pkh_prefix: 112,
+        cp2sh_prefix: 197,
+        wif_prefix: 239,
+        xpub_version: [0x04, 0x35, 0x87, 0xCF],
+        xpriv_version: [0x04, 0x35, 0x83, 0x94],
+        genesis_hash: None,
+    };
+
+    /// Return the parameters of the development network kind with the given
+    /// network ID, e.g. for a paradium or private network
+    pub fn dev_with_id(network_id: u32) -> NetworkParams {
+        NetworkParams { network_id: network_id, ..NetworkParams::DEV }
+    }
+
+    /// Set the hash of the genesis block
+    pub fn with_genesis_hash(mut self, genesis_hash: BlockHash) -> NetworkParams {
+        self.genesis_hash = Some(genesis_hash);
+        self
+    }
+
+    /// Return the network magic bytes
+    pub fn magic(&self) -> u32 {
+        NetworkId::from(self.network_id).magic()
+    }
+}
+
+/// The cryptocurrency to act on
+#[derive(Copy, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub enum Network {
+    /// For production
+    Prod,
+    /// For development
+    Dev,
+    /// Any other network, described by its parameters
+    Custom(NetworkParams),
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Network::Custom(ref params) => write!(f, "Custom({:?})", params),
+            _ => fmt::Display::fmt(self, f),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Network::Prod => f.pad("prod"),
+            Network::Dev => f.pad("dev"),
+            Network::Custom(ref params) => f.pad(&format!("custom-{}", params.network_id)),
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = io::Error;
+
+    /// Parse the name of a default network. Custom networks cannot be
+    /// parsed from their name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prod" => Ok(Network::Prod),
+            "dev" => Ok(Network::Dev),
+            #[cfg(feature = "std")]
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown network (type {})", s),
+            )),
+            #[cfg(not(feature = "std"))]
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Unknown network")),
+        }
+    }
+}
+
+// Default networks are serialized as their name and custom ones as their
+// parameters
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Network {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Network::Custom(ref params) => ::serde::Serialize::serialize(params, s),
+            _ => s.collect_str(self),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Network {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<Network, D::Error> {
+        use std::fmt::Formatter;
+        use serde::de::{self, MapAccess, Visitor};
+
+        struct NetworkVisitor;
+
+        impl<'de> Visitor<'de> for NetworkVisitor {
+            type Value = Network;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                f.write_str("a network name or network parameters")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Network, E> {
+                static NAMES: &'static [&'static str] = &["prod", "dev"];
+                v.parse().map_err(|_| E::unknown_variant(v, NAMES))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Network, A::Error> {
+                let params = ::serde::Deserialize::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(Network::Custom(params))
+            }
+        }
+
+        d.deserialize_any(NetworkVisitor)
+    }
+}
+
+impl Network {
+    /// Return the parameters of this network
+    pub fn params(&self) -> NetworkParams {
+        match *self {
+            Network::Prod => NetworkParams::PROD,
+            Network::Dev => NetworkParams::DEV,
+            Network::Custom(params) => params,
+        }
+    }
+
+    /// Return the network ID of this network, i.e. 1 for the default
+    /// production network and 1905960821 for the default development
+    /// network.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use tapyrus::network::constants::{Network, NetworkId};
+    ///
+    /// assert_eq!(Network::Prod.network_id(), NetworkId::from(1));
+    /// ```
+    pub fn network_id(&self) -> NetworkId {
+        NetworkId::from(self.params().network_id)
+    }
+
+    /// Return the magic bytes of this network
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use tapyrus::network::constants::Network;
+    ///
+    /// assert_eq!(Network::Prod.magic(), 0x00F0FF01);
+    /// ```
+    pub fn magic(&self) -> u32 {
+        self.network_id().magic()
+    }
+
+    /// Return the default network whose magic bytes are `magic`, or `None`
+    /// if no default network uses them
+    pub fn from_magic(magic: u32) -> Option<Network> {
+        Network::defaults().iter().cloned().find(|n| n.magic() == magic)
+    }
+
+    /// Return the default networks, which are recognized when parsing
+    /// addresses and keys
+    pub fn defaults() -> [Network; 2] {
+        [Network::Prod, Network::Dev]
     }
 }
 
@@ -304,10 +515,31 @@ impl Decodable for ServiceFlags {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for ServiceFlags {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<ServiceFlags> {
+        Ok(ServiceFlags(u.arbitrary()?))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for ServiceFlags {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for ServiceFlags {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<ServiceFlags, D::Error> {
+        ::serde::Deserialize::deserialize(d).map(ServiceFlags)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
-    use super::{NetworkId, Network, ServiceFlags};
+    use super::{NetworkId, Network, NetworkParams, ServiceFlags};
     use consensus::encode::{deserialize, serialize};
 
     #[test]
@@ -365,6 +597,35 @@ mod tests {
         assert!("fakenet".parse::<Network>().is_err());
     }
 
+    #[test]
+    fn network_magic_test() {
+        assert_eq!(serialize(&Network::Prod.magic()), &[0x01, 0xff, 0xf0, 0x00]);
+        assert_eq!(serialize(&Network::Dev.magic()), &[0x73, 0x9a, 0x97, 0x74]);
+
+        assert_eq!(Network::from_magic(Network::Prod.magic()), Some(Network::Prod));
+        assert_eq!(Network::from_magic(Network::Dev.magic()), Some(Network::Dev));
+        assert_eq!(Network::from_magic(NetworkId::from(101).magic()), None);
+    }
+
+    #[test]
+    fn custom_network_test() {
+        let paradium = Network::Custom(NetworkParams::dev_with_id(101));
+        assert_eq!(paradium.network_id(), NetworkId::from(101));
+        assert_eq!(serialize(&paradium.magic()), &[0x01, 0xff, 0xf0, 0x64]);
+        assert_eq!(paradium.params().p2pkh_prefix, Network::Dev.params().p2pkh_prefix);
+        assert_eq!(paradium.to_string(), "custom-101");
+        assert!(paradium != Network::Dev);
+        assert_eq!(Network::from_magic(paradium.magic()), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn network_serde_test() {
+        serde_round_trip!(Network::Prod);
+        serde_round_trip!(Network::Custom(NetworkParams::dev_with_id(101)));
+        assert_eq!(::serde_json::to_value(&Network::Dev).unwrap(), ::serde_json::Value::String("dev".to_owned()));
+    }
+
     #[test]
     fn network_id_from_str_test() {
         assert_eq!(NetworkId::from_str("1").unwrap(), NetworkId::from(1));
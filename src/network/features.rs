@@ -0,0 +1,205 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Protocol feature negotiation
+//!
+//! This module defines `NegotiatedFeatures`, which records the capabilities
+//! advertised by both sides of a connection during and after the handshake.
+//! It is updated by feeding it every message sent to and received from the
+//! peer, and answers which optional protocol features may be used.
+//!
+
+use std::cmp;
+
+use network::constants::ServiceFlags;
+use network::message::NetworkMessage;
+use network::message_compact::SendCmpct;
+
+/// Per-side record of what was advertised
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct Advertised {
+    /// Protocol version from the `version` message
+    version: Option<u32>,
+    /// Whether `verack` was sent
+    verack: bool,
+    /// Whether `sendheaders` was sent
+    send_headers: bool,
+    /// Whether `sendaddrv2` was sent before `verack`
+    send_addr_v2: bool,
+    /// The last `sendcmpct` sent
+    send_cmpct: Option<SendCmpct>,
+}
+
+impl Advertised {
+    fn update(&mut self, msg: &NetworkMessage) {
+        match *msg {
+            NetworkMessage::Version(ref v) => self.version = Some(v.version),
+            NetworkMessage::Verack => self.verack = true,
+            NetworkMessage::SendHeaders => self.send_headers = true,
+            // BIP155: sendaddrv2 is only meaningful before verack
+            NetworkMessage::SendAddrV2 => if !self.verack {
+                self.send_addr_v2 = true;
+            },
+            NetworkMessage::SendCmpct(cmpct) => self.send_cmpct = Some(cmpct),
+            _ => {}
+        }
+    }
+}
+
+/// Features negotiated with a single peer
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NegotiatedFeatures {
+    local: Advertised,
+    remote: Advertised,
+    remote_services: ServiceFlags,
+}
+
+impl Default for NegotiatedFeatures {
+    fn default() -> NegotiatedFeatures {
+        NegotiatedFeatures::new()
+    }
+}
+
+impl NegotiatedFeatures {
+    /// Create an empty record for a new connection
+    pub fn new() -> NegotiatedFeatures {
+        NegotiatedFeatures {
+            local: Advertised::default(),
+            remote: Advertised::default(),
+            remote_services: ServiceFlags::NONE,
+        }
+    }
+
+    /// Record a message sent to the peer
+    pub fn on_send(&mut self, msg: &NetworkMessage) {
+        self.local.update(msg);
+    }
+
+    /// Record a message received from the peer
+    pub fn on_receive(&mut self, msg: &NetworkMessage) {
+        if let NetworkMessage::Version(ref v) = *msg {
+            self.remote_services = v.services;
+        }
+        self.remote.update(msg);
+    }
+
+    /// Whether both sides have exchanged `version` and `verack`
+    pub fn is_handshake_complete(&self) -> bool {
+        self.local.version.is_some() && self.remote.version.is_some()
+            && self.local.verack && self.remote.verack
+    }
+
+    /// The protocol version used on the connection, the lower of both
+    /// advertised versions
+    pub fn version(&self) -> Option<u32> {
+        match (self.local.version, self.remote.version) {
+            (Some(local), Some(remote)) => Some(cmp::min(local, remote)),
+            _ => None,
+        }
+    }
+
+    /// The protocol version advertised by the peer
+    pub fn remote_version(&self) -> Option<u32> {
+        self.remote.version
+    }
+
+    /// The services advertised by the peer
+    pub fn remote_services(&self) -> ServiceFlags {
+        self.remote_services
+    }
+
+    /// Whether the peer asked for new blocks to be announced with `headers`
+    /// rather than `inv`
+    pub fn announce_with_headers(&self) -> bool {
+        self.remote.send_headers
+    }
+
+    /// Whether the peer announces new blocks to us with `headers`, which we
+    /// asked for
+    pub fn receive_headers_announcements(&self) -> bool {
+        self.local.send_headers
+    }
+
+    /// Whether addresses may be exchanged with `addrv2`, which requires both
+    /// sides to have sent `sendaddrv2` before `verack`
+    pub fn addr_v2(&self) -> bool {
+        self.local.send_addr_v2 && self.remote.send_addr_v2
+    }
+
+    /// The compact block version supported by both sides, if any
+    pub fn compact_block_version(&self) -> Option<u64> {
+        match (self.local.send_cmpct, self.remote.send_cmpct) {
+            (Some(local), Some(remote)) if local.version == remote.version => Some(local.version),
+            _ => None,
+        }
+    }
+
+    /// Whether the peer asked for new blocks to be announced with
+    /// `cmpctblock` (high-bandwidth mode)
+    pub fn announce_with_cmpct(&self) -> bool {
+        match self.remote.send_cmpct {
+            Some(ref cmpct) => cmpct.send_compact && self.compact_block_version().is_some(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NegotiatedFeatures;
+    use network::constants::ServiceFlags;
+    use network::message::NetworkMessage;
+    use network::message_compact::SendCmpct;
+    use network::message_network::VersionMessage;
+
+    fn version(version: u32, services: ServiceFlags) -> NetworkMessage {
+        let mut msg = VersionMessage::builder().services(services).build();
+        msg.version = version;
+        NetworkMessage::Version(msg)
+    }
+
+    #[test]
+    fn handshake_test() {
+        let mut features = NegotiatedFeatures::new();
+        assert!(!features.is_handshake_complete());
+        assert_eq!(features.version(), None);
+
+        features.on_send(&version(10000, ServiceFlags::NONE));
+        features.on_receive(&version(9000, ServiceFlags::NETWORK));
+        assert_eq!(features.version(), Some(9000));
+        assert_eq!(features.remote_version(), Some(9000));
+        assert_eq!(features.remote_services(), ServiceFlags::NETWORK);
+
+        features.on_send(&NetworkMessage::SendAddrV2);
+        features.on_receive(&NetworkMessage::Verack);
+        // too late, the peer already sent verack
+        features.on_receive(&NetworkMessage::SendAddrV2);
+        features.on_send(&NetworkMessage::Verack);
+        assert!(features.is_handshake_complete());
+        assert!(!features.addr_v2());
+    }
+
+    #[test]
+    fn announcement_test() {
+        let mut features = NegotiatedFeatures::new();
+        features.on_send(&NetworkMessage::SendAddrV2);
+        features.on_receive(&NetworkMessage::SendAddrV2);
+        assert!(features.addr_v2());
+
+        features.on_receive(&NetworkMessage::SendHeaders);
+        assert!(features.announce_with_headers());
+        assert!(!features.receive_headers_announcements());
+
+        features.on_receive(&NetworkMessage::SendCmpct(SendCmpct { send_compact: true, version: 1 }));
+        assert_eq!(features.compact_block_version(), None);
+        assert!(!features.announce_with_cmpct());
+
+        features.on_send(&NetworkMessage::SendCmpct(SendCmpct { send_compact: false, version: 1 }));
+        assert_eq!(features.compact_block_version(), Some(1));
+        assert!(features.announce_with_cmpct());
+
+        features.on_receive(&NetworkMessage::SendCmpct(SendCmpct { send_compact: false, version: 1 }));
+        assert!(!features.announce_with_cmpct());
+    }
+}
@@ -19,22 +19,26 @@
 //! also defines (de)serialization routines for many primitives.
 //!
 
-use std::{io, iter, mem, fmt};
+use std::{cmp, io, iter, mem, fmt};
 use std::borrow::Cow;
-use std::io::Cursor;
+use std::collections::{HashMap, HashSet};
 
 use blockdata::block;
 use blockdata::transaction;
-use network::address::Address;
+use network::address::{Address, AddrV2Message};
+use network::constants::{Network, NetworkId};
 use network::message_network;
 use network::message_blockdata;
+use network::message_compact;
 use network::message_filter;
-use consensus::encode::{CheckedData, Decodable, Encodable, VarInt};
+use util::bloom::BloomFilter;
+use util::merkleblock::MerkleBlock;
+use consensus::encode::{CheckedData, Decodable, Encodable, VarInt, WriteExt};
 use consensus::{encode, serialize};
-use consensus::encode::MAX_VEC_SIZE;
+use consensus::encode::{ChainLimits, MAX_VEC_SIZE};
 
 /// Serializer for command string
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
 pub struct CommandString(Cow<'static, str>);
 
 impl fmt::Display for CommandString {
@@ -70,7 +74,7 @@ impl Encodable for CommandString {
         let mut rawbytes = [0u8; 12];
         let strbytes = self.0.as_bytes();
         if strbytes.len() > 12 {
-            return Err(encode::Error::UnrecognizedNetworkCommand(self.0.clone().into_owned()));
+            return Err(encode::Error::Parse(encode::ParseError::UnrecognizedNetworkCommand(self.0.clone().into_owned())));
         }
         for x in 0..strbytes.len() {
             rawbytes[x] = strbytes[x];
@@ -92,6 +96,179 @@ impl Decodable for CommandString {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for CommandString {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<CommandString> {
+        // Commands are up to 12 bytes of printable ASCII
+        let len = u.int_in_range(0usize..=12)?;
+        let mut command = String::with_capacity(len);
+        for _ in 0..len {
+            command.push(u.int_in_range(0x21u8..=0x7e)? as char);
+        }
+        Ok(CommandString::from(command))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for CommandString {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_ref())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for CommandString {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<CommandString, D::Error> {
+        let command: String = ::serde::Deserialize::deserialize(d)?;
+        Ok(CommandString::from(command))
+    }
+}
+
+/// An application-level message carried over the P2P transport
+///
+/// Applications layering their own protocol over Tapyrus connections
+/// implement this trait for each of their messages, wrap them with
+/// `NetworkMessage::custom` and register their commands in the
+/// `DecodeConfig` used to read messages from peers.
+pub trait CustomMessage: Encodable + Decodable {
+    /// The command of this message, at most 12 bytes long
+    const COMMAND: &'static str;
+}
+
+/// Maximum number of elements accepted in the lists of a message. The
+/// default limits are the ones enforced by Tapyrus Core; applications only
+/// reading messages from trusted sources may opt out with
+/// `Limits::unlimited`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Limits {
+    /// Maximum number of entries in `addr` and `addrv2` messages
+    pub max_addr: usize,
+    /// Maximum number of entries in `inv`, `getdata` and `notfound` messages
+    pub max_inv: usize,
+    /// Maximum number of headers in a `headers` message
+    pub max_headers: usize,
+    /// Maximum number of locator hashes in `getblocks` and `getheaders` messages
+    pub max_locator_hashes: usize,
+}
+
+impl Limits {
+    /// The limits enforced by Tapyrus Core
+    pub fn core() -> Limits {
+        Limits {
+            max_addr: 1000,
+            max_inv: 50_000,
+            max_headers: 2000,
+            max_locator_hashes: 101,
+        }
+    }
+
+    /// No limit on the number of elements
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_addr: usize::max_value(),
+            max_inv: usize::max_value(),
+            max_headers: usize::max_value(),
+            max_locator_hashes: usize::max_value(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::core()
+    }
+}
+
+/// Limits applied when decoding a `RawNetworkMessage`
+///
+/// Networks whose xfield allows blocks larger than the default limit need
+/// to set their `ChainLimits` in order to receive them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DecodeConfig {
+    /// Maximum payload size of any message, in bytes
+    pub max_message_size: usize,
+    /// Lower payload size limits for specific commands, in bytes
+    pub payload_limits: HashMap<CommandString, usize>,
+    /// Application-level commands decoded as `NetworkMessage::Custom`
+    pub custom_commands: HashSet<CommandString>,
+    /// Whether payload checksums are verified
+    pub verify_checksums: bool,
+    /// Limits on the number of elements of lists in messages
+    pub limits: Limits,
+    /// Limits derived from the parameters of the chain
+    pub chain_limits: ChainLimits,
+}
+
+impl DecodeConfig {
+    /// Create a config accepting payloads of up to `max_message_size` bytes
+    /// for every command
+    pub fn new(max_message_size: usize) -> DecodeConfig {
+        DecodeConfig {
+            max_message_size: max_message_size,
+            payload_limits: HashMap::new(),
+            custom_commands: HashSet::new(),
+            verify_checksums: true,
+            limits: Limits::default(),
+            chain_limits: ChainLimits::default(),
+        }
+    }
+
+    /// Replace the limits derived from the chain parameters, raising
+    /// `max_message_size` if the chain allows larger messages
+    pub fn with_chain_limits(mut self, chain_limits: ChainLimits) -> DecodeConfig {
+        self.max_message_size = cmp::max(self.max_message_size, chain_limits.max_message_size());
+        self.chain_limits = chain_limits;
+        self
+    }
+
+    /// Replace the element count limits
+    pub fn with_limits(mut self, limits: Limits) -> DecodeConfig {
+        self.limits = limits;
+        self
+    }
+
+    /// Do not verify payload checksums. Hashing the payloads is a large part
+    /// of the decoding cost, but skipping it is only safe on transports which
+    /// already guarantee integrity, such as local or authenticated ones.
+    pub fn skip_checksums(mut self) -> DecodeConfig {
+        self.verify_checksums = false;
+        self
+    }
+
+    /// Cap the payload size of messages with the given command
+    pub fn with_payload_limit<C: Into<CommandString>>(mut self, command: C, limit: usize) -> DecodeConfig {
+        self.payload_limits.insert(command.into(), limit);
+        self
+    }
+
+    /// Register an application-level command. Messages with this command
+    /// are decoded as `NetworkMessage::Custom` instead of
+    /// `NetworkMessage::Unknown`; the commands of this crate take precedence.
+    pub fn with_custom_command<C: Into<CommandString>>(mut self, command: C) -> DecodeConfig {
+        self.custom_commands.insert(command.into());
+        self
+    }
+
+    /// Whether the given command was registered as an application-level one
+    pub fn is_custom_command(&self, command: &CommandString) -> bool {
+        self.custom_commands.contains(command)
+    }
+
+    /// Return the maximum payload size of messages with the given command
+    pub fn payload_limit(&self, command: &CommandString) -> usize {
+        match self.payload_limits.get(command) {
+            Some(&limit) if limit < self.max_message_size => limit,
+            _ => self.max_message_size,
+        }
+    }
+}
+
+impl Default for DecodeConfig {
+    fn default() -> DecodeConfig {
+        DecodeConfig::new(MAX_VEC_SIZE)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 /// A Network message
 pub struct RawNetworkMessage {
@@ -100,6 +277,8 @@ pub struct RawNetworkMessage {
     /// The actual message data
     pub payload: NetworkMessage
 }
+serde_struct_impl!(RawNetworkMessage, magic, payload);
+arbitrary_struct_impl!(RawNetworkMessage, magic, payload);
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 /// A Network message payload. Proper documentation is available on at
@@ -111,6 +290,10 @@ pub enum NetworkMessage {
     Verack,
     /// `addr`
     Addr(Vec<(u32, Address)>),
+    /// BIP155 `addrv2`
+    AddrV2(Vec<AddrV2Message>),
+    /// BIP155 `sendaddrv2`
+    SendAddrV2,
     /// `inv`
     Inv(Vec<message_blockdata::Inventory>),
     /// `getdata`
@@ -140,7 +323,14 @@ pub enum NetworkMessage {
     Ping(u64),
     /// `pong`
     Pong(u64),
-    // TODO: bloom filtering
+    /// BIP37 filterload
+    FilterLoad(BloomFilter),
+    /// BIP37 filteradd
+    FilterAdd(Vec<u8>),
+    /// BIP37 filterclear
+    FilterClear,
+    /// BIP37 merkleblock
+    MerkleBlock(MerkleBlock),
     /// BIP157 getcfilters
     GetCFilters(message_filter::GetCFilters),
     /// BIP157 cfilter
@@ -156,16 +346,37 @@ pub enum NetworkMessage {
     /// `alert`
     Alert(Vec<u8>),
     /// `reject`
-    Reject(message_network::Reject)
+    Reject(message_network::Reject),
+    /// BIP152 sendcmpct
+    SendCmpct(message_compact::SendCmpct),
+    /// BIP152 cmpctblock
+    CmpctBlock(message_compact::CmpctBlock),
+    /// BIP152 getblocktxn
+    GetBlockTxn(message_compact::GetBlockTxn),
+    /// BIP152 blocktxn
+    BlockTxn(message_compact::BlockTxn),
+    /// Application-level message whose command is registered in `DecodeConfig`
+    Custom(CommandString, Vec<u8>),
+    /// Any other message
+    Unknown {
+        /// The command of this message
+        command: CommandString,
+        /// The raw payload of this message
+        payload: Vec<u8>,
+    },
 }
 
 impl NetworkMessage {
     /// Return the message command. This is useful for debug outputs.
+    /// Unknown and custom messages are reported as "unknown" and "custom";
+    /// use `command()` to get their actual command.
     pub fn cmd(&self) -> &'static str {
         match *self {
             NetworkMessage::Version(_) => "version",
             NetworkMessage::Verack     => "verack",
             NetworkMessage::Addr(_)    => "addr",
+            NetworkMessage::AddrV2(_)  => "addrv2",
+            NetworkMessage::SendAddrV2 => "sendaddrv2",
             NetworkMessage::Inv(_)     => "inv",
             NetworkMessage::GetData(_) => "getdata",
             NetworkMessage::NotFound(_) => "notfound",
@@ -179,6 +390,10 @@ impl NetworkMessage {
             NetworkMessage::GetAddr    => "getaddr",
             NetworkMessage::Ping(_)    => "ping",
             NetworkMessage::Pong(_)    => "pong",
+            NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
+            NetworkMessage::MerkleBlock(_) => "merkleblock",
             NetworkMessage::GetCFilters(_) => "getcfilters",
             NetworkMessage::CFilter(_) => "cfilter",
             NetworkMessage::GetCFHeaders(_) => "getcfheaders",
@@ -187,16 +402,279 @@ impl NetworkMessage {
             NetworkMessage::CFCheckpt(_) => "cfcheckpt",
             NetworkMessage::Alert(_)    => "alert",
             NetworkMessage::Reject(_)    => "reject",
+            NetworkMessage::SendCmpct(_) => "sendcmpct",
+            NetworkMessage::CmpctBlock(_) => "cmpctblock",
+            NetworkMessage::GetBlockTxn(_) => "getblocktxn",
+            NetworkMessage::BlockTxn(_) => "blocktxn",
+            NetworkMessage::Custom(..) => "custom",
+            NetworkMessage::Unknown { .. } => "unknown",
         }
     }
 
     /// Return the CommandString for the message command.
     pub fn command(&self) -> CommandString {
-        self.cmd().into()
+        match *self {
+            NetworkMessage::Custom(ref command, _) => command.clone(),
+            NetworkMessage::Unknown { ref command, .. } => command.clone(),
+            _ => self.cmd().into(),
+        }
+    }
+
+    /// Wrap an application-level message
+    pub fn custom<M: CustomMessage>(msg: &M) -> NetworkMessage {
+        NetworkMessage::Custom(M::COMMAND.into(), serialize(msg))
+    }
+
+    /// Decode the payload of a custom message of type `M`. Returns `None` if
+    /// this is not a custom message with the command of `M`.
+    pub fn decode_custom<M: CustomMessage>(&self) -> Option<Result<M, encode::Error>> {
+        match *self {
+            NetworkMessage::Custom(ref command, ref payload) if command.as_ref() == M::COMMAND => {
+                Some(encode::deserialize(payload))
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode the payload of the message, without the envelope of
+    /// `RawNetworkMessage`
+    pub fn consensus_encode_payload<W: io::Write>(&self, w: W) -> Result<usize, encode::Error> {
+        PayloadSerializationWrapper(self).consensus_encode(w)
+    }
+
+    /// Decode the payload of a `command` message, enforcing the limits of
+    /// `config`
+    pub fn consensus_decode_payload(
+        command: CommandString,
+        payload: &[u8],
+        config: &DecodeConfig,
+    ) -> Result<NetworkMessage, encode::Error> {
+        let limit = config.payload_limit(&command);
+        if payload.len() > limit {
+            return Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation { requested: payload.len(), max: limit }));
+        }
+        decode_payload(command, payload, config)
+    }
+}
+
+// Messages are serialized as a map with a single entry from the command to
+// the payload, e.g. `{"ping": 42}`. Messages without payload map to `null`.
+// Custom and unknown messages are never generated, as they depend on the
+// commands registered in the `DecodeConfig`
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for NetworkMessage {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<NetworkMessage> {
+        Ok(match u.int_in_range(0u8..=33)? {
+            0 => NetworkMessage::Version(u.arbitrary()?),
+            1 => NetworkMessage::Verack,
+            2 => NetworkMessage::Addr(u.arbitrary()?),
+            3 => NetworkMessage::AddrV2(u.arbitrary()?),
+            4 => NetworkMessage::SendAddrV2,
+            5 => NetworkMessage::Inv(u.arbitrary()?),
+            6 => NetworkMessage::GetData(u.arbitrary()?),
+            7 => NetworkMessage::NotFound(u.arbitrary()?),
+            8 => NetworkMessage::GetBlocks(u.arbitrary()?),
+            9 => NetworkMessage::GetHeaders(u.arbitrary()?),
+            10 => NetworkMessage::MemPool,
+            11 => NetworkMessage::Tx(u.arbitrary()?),
+            12 => NetworkMessage::Block(u.arbitrary()?),
+            13 => NetworkMessage::Headers(u.arbitrary()?),
+            14 => NetworkMessage::SendHeaders,
+            15 => NetworkMessage::GetAddr,
+            16 => NetworkMessage::Ping(u.arbitrary()?),
+            17 => NetworkMessage::Pong(u.arbitrary()?),
+            18 => NetworkMessage::FilterLoad(u.arbitrary()?),
+            19 => NetworkMessage::FilterAdd(u.arbitrary()?),
+            20 => NetworkMessage::FilterClear,
+            21 => NetworkMessage::MerkleBlock(u.arbitrary()?),
+            22 => NetworkMessage::GetCFilters(u.arbitrary()?),
+            23 => NetworkMessage::CFilter(u.arbitrary()?),
+            24 => NetworkMessage::GetCFHeaders(u.arbitrary()?),
+            25 => NetworkMessage::CFHeaders(u.arbitrary()?),
+            26 => NetworkMessage::GetCFCheckpt(u.arbitrary()?),
+            27 => NetworkMessage::CFCheckpt(u.arbitrary()?),
+            28 => NetworkMessage::Alert(u.arbitrary()?),
+            29 => NetworkMessage::Reject(u.arbitrary()?),
+            30 => NetworkMessage::SendCmpct(u.arbitrary()?),
+            31 => NetworkMessage::CmpctBlock(u.arbitrary()?),
+            32 => NetworkMessage::GetBlockTxn(u.arbitrary()?),
+            _ => NetworkMessage::BlockTxn(u.arbitrary()?),
+        })
+    }
+}
+
+/// The serde key of custom messages, longer than the 12 bytes of a command
+/// so that no unknown message can have it as its command
+#[cfg(feature = "serde")]
+const SERDE_CUSTOM_KEY: &str = "custom message";
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for NetworkMessage {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let command = self.command();
+        let mut map = s.serialize_map(Some(1))?;
+        match *self {
+            NetworkMessage::Version(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Addr(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::AddrV2(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Inv(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetData(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::NotFound(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetBlocks(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetHeaders(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Tx(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Block(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Headers(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Ping(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Pong(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::FilterLoad(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::FilterAdd(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::MerkleBlock(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetCFilters(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::CFilter(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetCFHeaders(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::CFHeaders(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetCFCheckpt(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::CFCheckpt(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Alert(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Reject(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::SendCmpct(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::CmpctBlock(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::GetBlockTxn(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::BlockTxn(ref m) => map.serialize_entry(command.as_ref(), m)?,
+            NetworkMessage::Verack |
+            NetworkMessage::SendAddrV2 |
+            NetworkMessage::MemPool |
+            NetworkMessage::SendHeaders |
+            NetworkMessage::GetAddr |
+            NetworkMessage::FilterClear => map.serialize_entry(command.as_ref(), &())?,
+            // custom commands are not known when deserializing, so they are
+            // nested under their own entry
+            NetworkMessage::Custom(_, ref payload) => map.serialize_entry(SERDE_CUSTOM_KEY, &(&command, payload))?,
+            NetworkMessage::Unknown { ref payload, .. } => map.serialize_entry(command.as_ref(), payload)?,
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for NetworkMessage {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<NetworkMessage, D::Error> {
+        use std::fmt::{self, Formatter};
+        use serde::de::{Error, MapAccess, Visitor};
+
+        struct MessageVisitor;
+
+        impl<'de> Visitor<'de> for MessageVisitor {
+            type Value = NetworkMessage;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                f.write_str("a map from a message command to its payload")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<NetworkMessage, A::Error> {
+                let command: String = match map.next_key()? {
+                    Some(command) => command,
+                    None => return Err(A::Error::invalid_length(0, &self)),
+                };
+                let msg = match &command[..] {
+                    "version" => NetworkMessage::Version(map.next_value()?),
+                    "verack" => {
+                        map.next_value::<()>()?;
+                        NetworkMessage::Verack
+                    }
+                    "addr" => NetworkMessage::Addr(map.next_value()?),
+                    "addrv2" => NetworkMessage::AddrV2(map.next_value()?),
+                    "sendaddrv2" => {
+                        map.next_value::<()>()?;
+                        NetworkMessage::SendAddrV2
+                    }
+                    "inv" => NetworkMessage::Inv(map.next_value()?),
+                    "getdata" => NetworkMessage::GetData(map.next_value()?),
+                    "notfound" => NetworkMessage::NotFound(map.next_value()?),
+                    "getblocks" => NetworkMessage::GetBlocks(map.next_value()?),
+                    "getheaders" => NetworkMessage::GetHeaders(map.next_value()?),
+                    "mempool" => {
+                        map.next_value::<()>()?;
+                        NetworkMessage::MemPool
+                    }
+                    "tx" => NetworkMessage::Tx(map.next_value()?),
+                    "block" => NetworkMessage::Block(map.next_value()?),
+                    "headers" => NetworkMessage::Headers(map.next_value()?),
+                    "sendheaders" => {
+                        map.next_value::<()>()?;
+                        NetworkMessage::SendHeaders
+                    }
+                    "getaddr" => {
+                        map.next_value::<()>()?;
+                        NetworkMessage::GetAddr
+                    }
+                    "ping" => NetworkMessage::Ping(map.next_value()?),
+                    "pong" => NetworkMessage::Pong(map.next_value()?),
+                    "filterload" => NetworkMessage::FilterLoad(map.next_value()?),
+                    "filteradd" => NetworkMessage::FilterAdd(map.next_value()?),
+                    "filterclear" => {
+                        map.next_value::<()>()?;
+                        NetworkMessage::FilterClear
+                    }
+                    "merkleblock" => NetworkMessage::MerkleBlock(map.next_value()?),
+                    "getcfilters" => NetworkMessage::GetCFilters(map.next_value()?),
+                    "cfilter" => NetworkMessage::CFilter(map.next_value()?),
+                    "getcfheaders" => NetworkMessage::GetCFHeaders(map.next_value()?),
+                    "cfheaders" => NetworkMessage::CFHeaders(map.next_value()?),
+                    "getcfcheckpt" => NetworkMessage::GetCFCheckpt(map.next_value()?),
+                    "cfcheckpt" => NetworkMessage::CFCheckpt(map.next_value()?),
+                    "alert" => NetworkMessage::Alert(map.next_value()?),
+                    "reject" => NetworkMessage::Reject(map.next_value()?),
+                    "sendcmpct" => NetworkMessage::SendCmpct(map.next_value()?),
+                    "cmpctblock" => NetworkMessage::CmpctBlock(map.next_value()?),
+                    "getblocktxn" => NetworkMessage::GetBlockTxn(map.next_value()?),
+                    "blocktxn" => NetworkMessage::BlockTxn(map.next_value()?),
+                    SERDE_CUSTOM_KEY => {
+                        let (custom_command, payload): (CommandString, Vec<u8>) = map.next_value()?;
+                        NetworkMessage::Custom(custom_command, payload)
+                    }
+                    _ => NetworkMessage::Unknown {
+                        payload: map.next_value()?,
+                        command: CommandString::from(command),
+                    },
+                };
+                if map.next_key::<String>()?.is_some() {
+                    return Err(A::Error::invalid_length(2, &self));
+                }
+                Ok(msg)
+            }
+        }
+
+        d.deserialize_map(MessageVisitor)
     }
 }
 
 impl RawNetworkMessage {
+    /// Create a message for the default network of the given kind
+    pub fn new(network: Network, payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage {
+            magic: network.magic(),
+            payload: payload,
+        }
+    }
+
+    /// Create a message for the network with the given ID
+    pub fn with_network_id(network_id: &NetworkId, payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage {
+            magic: network_id.magic(),
+            payload: payload,
+        }
+    }
+
+    /// Return the network this message is meant for, or `None` if its magic
+    /// does not belong to a default network
+    pub fn network(&self) -> Option<Network> {
+        Network::from_magic(self.magic)
+    }
+
     /// Return the message command. This is useful for debug outputs.
     pub fn cmd(&self) -> &'static str {
         self.payload.cmd()
@@ -208,7 +686,7 @@ impl RawNetworkMessage {
     }
 }
 
-struct HeaderSerializationWrapper<'a>(&'a Vec<block::BlockHeader>);
+struct HeaderSerializationWrapper<'a>(&'a [block::BlockHeader]);
 
 impl<'a> Encodable for HeaderSerializationWrapper<'a> {
     #[inline]
@@ -226,125 +704,261 @@ impl<'a> Encodable for HeaderSerializationWrapper<'a> {
     }
 }
 
+struct PayloadSerializationWrapper<'a>(&'a NetworkMessage);
+
+impl<'a> Encodable for PayloadSerializationWrapper<'a> {
+    #[inline]
+    fn consensus_encode<S: io::Write>(
+        &self,
+        mut s: S,
+    ) -> Result<usize, encode::Error> {
+        match *self.0 {
+            NetworkMessage::Version(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::Addr(ref dat)    => dat.consensus_encode(&mut s),
+            NetworkMessage::AddrV2(ref dat)  => dat.consensus_encode(&mut s),
+            NetworkMessage::Inv(ref dat)     => dat.consensus_encode(&mut s),
+            NetworkMessage::GetData(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::NotFound(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::GetBlocks(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::GetHeaders(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::Tx(ref dat)      => dat.consensus_encode(&mut s),
+            NetworkMessage::Block(ref dat)   => dat.consensus_encode(&mut s),
+            NetworkMessage::Headers(ref dat) => HeaderSerializationWrapper(dat).consensus_encode(&mut s),
+            NetworkMessage::Ping(ref dat)    => dat.consensus_encode(&mut s),
+            NetworkMessage::Pong(ref dat)    => dat.consensus_encode(&mut s),
+            NetworkMessage::FilterLoad(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::FilterAdd(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::MerkleBlock(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::GetCFilters(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::CFilter(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::GetCFHeaders(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::CFHeaders(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::GetCFCheckpt(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::CFCheckpt(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::Alert(ref dat)    => dat.consensus_encode(&mut s),
+            NetworkMessage::Reject(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::SendCmpct(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::CmpctBlock(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::GetBlockTxn(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::BlockTxn(ref dat) => dat.consensus_encode(&mut s),
+            NetworkMessage::Custom(_, ref payload)
+            | NetworkMessage::Unknown { ref payload, .. } => {
+                s.emit_slice(payload)?;
+                Ok(payload.len())
+            },
+            NetworkMessage::Verack
+            | NetworkMessage::SendHeaders
+            | NetworkMessage::MemPool
+            | NetworkMessage::GetAddr
+            | NetworkMessage::FilterClear
+            | NetworkMessage::SendAddrV2 => Ok(0),
+        }
+    }
+}
+
 impl Encodable for RawNetworkMessage {
     fn consensus_encode<S: io::Write>(
         &self,
         mut s: S,
     ) -> Result<usize, encode::Error> {
+        // Large payloads such as blocks are never buffered
         let mut len = 0;
         len += self.magic.consensus_encode(&mut s)?;
         len += self.command().consensus_encode(&mut s)?;
-        len += CheckedData(match self.payload {
-            NetworkMessage::Version(ref dat) => serialize(dat),
-            NetworkMessage::Addr(ref dat)    => serialize(dat),
-            NetworkMessage::Inv(ref dat)     => serialize(dat),
-            NetworkMessage::GetData(ref dat) => serialize(dat),
-            NetworkMessage::NotFound(ref dat) => serialize(dat),
-            NetworkMessage::GetBlocks(ref dat) => serialize(dat),
-            NetworkMessage::GetHeaders(ref dat) => serialize(dat),
-            NetworkMessage::Tx(ref dat)      => serialize(dat),
-            NetworkMessage::Block(ref dat)   => serialize(dat),
-            NetworkMessage::Headers(ref dat) => serialize(&HeaderSerializationWrapper(dat)),
-            NetworkMessage::Ping(ref dat)    => serialize(dat),
-            NetworkMessage::Pong(ref dat)    => serialize(dat),
-            NetworkMessage::GetCFilters(ref dat) => serialize(dat),
-            NetworkMessage::CFilter(ref dat) => serialize(dat),
-            NetworkMessage::GetCFHeaders(ref dat) => serialize(dat),
-            NetworkMessage::CFHeaders(ref dat) => serialize(dat),
-            NetworkMessage::GetCFCheckpt(ref dat) => serialize(dat),
-            NetworkMessage::CFCheckpt(ref dat) => serialize(dat),
-            NetworkMessage::Alert(ref dat)    => serialize(dat),
-            NetworkMessage::Reject(ref dat) => serialize(dat),
-            NetworkMessage::Verack
-            | NetworkMessage::SendHeaders
-            | NetworkMessage::MemPool
-            | NetworkMessage::GetAddr => vec![],
-        }).consensus_encode(&mut s)?;
+        len += CheckedData::encode_payload(&PayloadSerializationWrapper(&self.payload), &mut s)?;
         Ok(len)
     }
+
+    fn consensus_encoded_len(&self) -> usize {
+        // magic, command, length and checksum, then the payload
+        24 + PayloadSerializationWrapper(&self.payload).consensus_encoded_len()
+    }
 }
 
 struct HeaderDeserializationWrapper(Vec<block::BlockHeader>);
 
-impl Decodable for HeaderDeserializationWrapper {
-    #[inline]
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+impl HeaderDeserializationWrapper {
+    fn consensus_decode_with_limits<D: io::Read>(mut d: D, limits: &ChainLimits) -> Result<Self, encode::Error> {
         let len = VarInt::consensus_decode(&mut d)?.0;
         let byte_size = (len as usize)
                             .checked_mul(mem::size_of::<block::BlockHeader>())
-                            .ok_or(encode::Error::ParseFailed("Invalid length"))?;
-        if byte_size > MAX_VEC_SIZE {
-            return Err(encode::Error::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE })
+                            .ok_or(encode::Error::Parse(encode::ParseError::InvalidLength))?;
+        let max = limits.max_vec_size();
+        if byte_size > max {
+            return Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation { requested: byte_size, max: max }))
         }
         let mut ret = Vec::with_capacity(len as usize);
         for _ in 0..len {
             ret.push(Decodable::consensus_decode(&mut d)?);
             if u8::consensus_decode(&mut d)? != 0u8 {
-                return Err(encode::Error::ParseFailed("Headers message should not contain transactions"));
+                return Err(encode::Error::Parse(encode::ParseError::Other("Headers message should not contain transactions")));
             }
         }
         Ok(HeaderDeserializationWrapper(ret))
     }
 }
 
-impl Decodable for RawNetworkMessage {
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+/// Decode the payload of a `command` message. Payloads are decoded in place
+/// and only unknown and custom messages keep a copy of their raw bytes.
+fn decode_payload<P>(
+    command: CommandString,
+    payload: P,
+    config: &DecodeConfig,
+) -> Result<NetworkMessage, encode::Error>
+    where P: AsRef<[u8]> + Into<Vec<u8>>
+{
+    let mut d = payload.as_ref();
+    let limits = &config.limits;
+    match command.as_ref() {
+        "addr" | "addrv2" => check_count(d, limits.max_addr)?,
+        "inv" | "getdata" | "notfound" => check_count(d, limits.max_inv)?,
+        "headers" => check_count(d, limits.max_headers)?,
+        // the locator follows the protocol version
+        "getblocks" | "getheaders" if d.len() >= 4 => check_count(&d[4..], limits.max_locator_hashes)?,
+        _ => {},
+    }
+    Ok(match command.as_ref() {
+        "version" => NetworkMessage::Version(Decodable::consensus_decode(&mut d)?),
+        "verack"  => NetworkMessage::Verack,
+        "addr"    => NetworkMessage::Addr(Decodable::consensus_decode(&mut d)?),
+        "addrv2"  => NetworkMessage::AddrV2(Decodable::consensus_decode(&mut d)?),
+        "sendaddrv2" => NetworkMessage::SendAddrV2,
+        "inv"     => NetworkMessage::Inv(Decodable::consensus_decode(&mut d)?),
+        "getdata" => NetworkMessage::GetData(Decodable::consensus_decode(&mut d)?),
+        "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode(&mut d)?),
+        "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode(&mut d)?),
+        "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode(&mut d)?),
+        "mempool" => NetworkMessage::MemPool,
+        "block"   => NetworkMessage::Block(
+            block::Block::consensus_decode_with_limits(&mut d, &config.chain_limits)?
+        ),
+        "headers" => NetworkMessage::Headers(
+            HeaderDeserializationWrapper::consensus_decode_with_limits(&mut d, &config.chain_limits)?.0
+        ),
+        "sendheaders" => NetworkMessage::SendHeaders,
+        "getaddr" => NetworkMessage::GetAddr,
+        "ping"    => NetworkMessage::Ping(Decodable::consensus_decode(&mut d)?),
+        "pong"    => NetworkMessage::Pong(Decodable::consensus_decode(&mut d)?),
+        "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode(&mut d)?),
+        "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode(&mut d)?),
+        "filterclear" => NetworkMessage::FilterClear,
+        "merkleblock" => NetworkMessage::MerkleBlock(Decodable::consensus_decode(&mut d)?),
+        "tx"      => NetworkMessage::Tx(Decodable::consensus_decode(&mut d)?),
+        "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut d)?),
+        "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut d)?),
+        "getcfheaders" => NetworkMessage::GetCFHeaders(Decodable::consensus_decode(&mut d)?),
+        "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode(&mut d)?),
+        "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode(&mut d)?),
+        "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode(&mut d)?),
+        "reject" => NetworkMessage::Reject(Decodable::consensus_decode(&mut d)?),
+        "alert"   => NetworkMessage::Alert(Decodable::consensus_decode(&mut d)?),
+        "sendcmpct" => NetworkMessage::SendCmpct(Decodable::consensus_decode(&mut d)?),
+        "cmpctblock" => NetworkMessage::CmpctBlock(Decodable::consensus_decode(&mut d)?),
+        "getblocktxn" => NetworkMessage::GetBlockTxn(Decodable::consensus_decode(&mut d)?),
+        "blocktxn" => NetworkMessage::BlockTxn(Decodable::consensus_decode(&mut d)?),
+        _ => if config.is_custom_command(&command) {
+            NetworkMessage::Custom(command, payload.into())
+        } else {
+            NetworkMessage::Unknown {
+                payload: payload.into(),
+                command: command,
+            }
+        },
+    })
+}
+
+/// Fail if the list at the start of `data` has more than `max` elements.
+/// This is checked before decoding so that no allocation is attempted.
+fn check_count(data: &[u8], max: usize) -> Result<(), encode::Error> {
+    let count = VarInt::consensus_decode(data)?.0;
+    if count > max as u64 {
+        return Err(encode::Error::Policy(encode::PolicyError::TooManyElements { requested: count, max: max as u64 }));
+    }
+    Ok(())
+}
+
+impl RawNetworkMessage {
+    /// Decode a message, enforcing the size limits of `config` instead of
+    /// the default ones
+    pub fn consensus_decode_with_config<D: io::Read>(
+        mut d: D,
+        config: &DecodeConfig,
+    ) -> Result<Self, encode::Error> {
         let magic = Decodable::consensus_decode(&mut d)?;
-        let cmd = CommandString::consensus_decode(&mut d)?.0;
-        let raw_payload = CheckedData::consensus_decode(&mut d)?.0;
-
-        let mut mem_d = Cursor::new(raw_payload);
-        let payload = match &cmd[..] {
-            "version" => NetworkMessage::Version(Decodable::consensus_decode(&mut mem_d)?),
-            "verack"  => NetworkMessage::Verack,
-            "addr"    => NetworkMessage::Addr(Decodable::consensus_decode(&mut mem_d)?),
-            "inv"     => NetworkMessage::Inv(Decodable::consensus_decode(&mut mem_d)?),
-            "getdata" => NetworkMessage::GetData(Decodable::consensus_decode(&mut mem_d)?),
-            "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode(&mut mem_d)?),
-            "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode(&mut mem_d)?),
-            "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode(&mut mem_d)?),
-            "mempool" => NetworkMessage::MemPool,
-            "block"   => NetworkMessage::Block(Decodable::consensus_decode(&mut mem_d)?),
-            "headers" => NetworkMessage::Headers(
-                HeaderDeserializationWrapper::consensus_decode(&mut mem_d)?.0
-            ),
-            "sendheaders" => NetworkMessage::SendHeaders,
-            "getaddr" => NetworkMessage::GetAddr,
-            "ping"    => NetworkMessage::Ping(Decodable::consensus_decode(&mut mem_d)?),
-            "pong"    => NetworkMessage::Pong(Decodable::consensus_decode(&mut mem_d)?),
-            "tx"      => NetworkMessage::Tx(Decodable::consensus_decode(&mut mem_d)?),
-            "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut mem_d)?),
-            "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut mem_d)?),
-            "getcfheaders" => NetworkMessage::GetCFHeaders(Decodable::consensus_decode(&mut mem_d)?),
-            "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode(&mut mem_d)?),
-            "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
-            "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
-            "reject" => NetworkMessage::Reject(Decodable::consensus_decode(&mut mem_d)?),
-            "alert"   => NetworkMessage::Alert(Decodable::consensus_decode(&mut mem_d)?),
-            _ => return Err(encode::Error::UnrecognizedNetworkCommand(cmd.into_owned())),
+        let command = CommandString::consensus_decode(&mut d)?;
+        let limit = config.payload_limit(&command);
+        let raw_payload = if config.verify_checksums {
+            CheckedData::consensus_decode_with_limit(&mut d, limit)?.0
+        } else {
+            CheckedData::consensus_decode_unchecked(&mut d, limit)?.0
         };
         Ok(RawNetworkMessage {
             magic: magic,
-            payload: payload
+            payload: decode_payload(command, raw_payload, config)?,
         })
     }
+
+    /// Decode a message with the default size limits, without verifying its
+    /// checksum. This must only be used on trusted transports; see
+    /// `DecodeConfig::skip_checksums`.
+    pub fn consensus_decode_unchecked<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        RawNetworkMessage::consensus_decode_with_config(d, &DecodeConfig::default().skip_checksums())
+    }
+
+    /// Decode the message at the start of `data`, returning it along with
+    /// the number of bytes consumed.
+    ///
+    /// Unlike `consensus_decode_with_config`, the payload is checksummed and
+    /// decoded directly from `data` rather than being copied into a buffer
+    /// first, which matters for large `block` messages.
+    pub fn consensus_decode_from_slice(
+        data: &[u8],
+        config: &DecodeConfig,
+    ) -> Result<(Self, usize), encode::Error> {
+        let mut d = data;
+        let magic = Decodable::consensus_decode(&mut d)?;
+        let command = CommandString::consensus_decode(&mut d)?;
+        let limit = config.payload_limit(&command);
+        let raw_payload = if config.verify_checksums {
+            CheckedData::decode_slice_with_limit(d, limit)?
+        } else {
+            CheckedData::decode_slice_unchecked(d, limit)?
+        };
+        // length and checksum precede the payload
+        let consumed = data.len() - d.len() + 8 + raw_payload.len();
+        let msg = RawNetworkMessage {
+            magic: magic,
+            payload: decode_payload(command, raw_payload, config)?,
+        };
+        Ok((msg, consumed))
+    }
+}
+
+impl Decodable for RawNetworkMessage {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        RawNetworkMessage::consensus_decode_with_config(d, &DecodeConfig::default())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::io;
-    use super::{RawNetworkMessage, NetworkMessage, CommandString};
-    use network::constants::ServiceFlags;
-    use consensus::encode::{Encodable, deserialize, deserialize_partial, serialize};
+    use super::{RawNetworkMessage, NetworkMessage, CommandString, CustomMessage, DecodeConfig, Limits};
+    use network::constants::{Network, NetworkId, ServiceFlags};
+    use consensus::encode::{self, CheckedData, ChainLimits, Encodable, VarInt, deserialize, deserialize_partial, serialize, MAX_VEC_SIZE};
     use hex::decode as hex_decode;
     use hashes::sha256d::Hash;
     use hashes::Hash as HashTrait;
-    use network::address::Address;
+    use network::address::{Address, AddrV2, AddrV2Message};
     use super::message_network::{Reject, RejectReason, VersionMessage};
     use network::message_blockdata::{Inventory, GetBlocksMessage, GetHeadersMessage};
     use blockdata::block::{Block, BlockHeader};
+    use network::message_compact::{SendCmpct, CmpctBlock, ShortId, PrefilledTransaction, GetBlockTxn, BlockTxn};
     use network::message_filter::{GetCFilters, CFilter, GetCFHeaders, CFHeaders, GetCFCheckpt, CFCheckpt};
     use blockdata::transaction::Transaction;
+    use util::bloom::{BloomFilter, BloomFlags};
+    use util::merkleblock::MerkleBlock;
+    use std::collections::HashSet;
 
     fn hash(slice: [u8;32]) -> Hash {
         Hash::from_slice(&slice).unwrap()
@@ -352,7 +966,6 @@ mod test {
 
     #[test]
     fn full_round_ser_der_raw_network_message_test() {
-        // TODO: Impl Rand traits here to easily generate random values.
         let version_msg: VersionMessage = deserialize(&hex_decode("721101000100000000000000e6e0845300000000010000000000000000000000000000000000ffff0000000000000100000000000000fd87d87eeb4364f22cf54dca59412db7208d47d920cffce83ee8102f5361746f7368693a302e392e39392f2c9f040001").unwrap()).unwrap();
         let tx: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
         let block: Block = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap()).unwrap();
@@ -362,19 +975,25 @@ mod test {
             NetworkMessage::Version(version_msg),
             NetworkMessage::Verack,
             NetworkMessage::Addr(vec![(45, Address::new(&([123,255,000,100], 833).into(), ServiceFlags::NETWORK))]),
+            NetworkMessage::AddrV2(vec![AddrV2Message{time: 45, services: ServiceFlags::NETWORK, addr: AddrV2::TorV3([7u8; 32]), port: 833}]),
+            NetworkMessage::SendAddrV2,
             NetworkMessage::Inv(vec![Inventory::Block(hash([8u8; 32]).into())]),
             NetworkMessage::GetData(vec![Inventory::Transaction(hash([45u8; 32]).into())]),
             NetworkMessage::NotFound(vec![Inventory::Error]),
             NetworkMessage::GetBlocks(GetBlocksMessage::new(vec![hash([1u8; 32]).into(), hash([4u8; 32]).into()], hash([5u8; 32]).into())),
             NetworkMessage::GetHeaders(GetHeadersMessage::new(vec![hash([10u8; 32]).into(), hash([40u8; 32]).into()], hash([50u8; 32]).into())),
             NetworkMessage::MemPool,
-            NetworkMessage::Tx(tx),
-            NetworkMessage::Block(block),
-            NetworkMessage::Headers(vec![header]),
+            NetworkMessage::Tx(tx.clone()),
+            NetworkMessage::Block(block.clone()),
+            NetworkMessage::Headers(vec![header.clone()]),
             NetworkMessage::SendHeaders,
             NetworkMessage::GetAddr,
             NetworkMessage::Ping(15),
             NetworkMessage::Pong(23),
+            NetworkMessage::FilterLoad(BloomFilter::new(10, 0.0001, 7, BloomFlags::All)),
+            NetworkMessage::FilterAdd(vec![1, 2, 3, 4]),
+            NetworkMessage::FilterClear,
+            NetworkMessage::MerkleBlock(MerkleBlock::from_block(&block, &HashSet::new())),
             NetworkMessage::GetCFilters(GetCFilters{filter_type: 2, start_height: 52, stop_hash: hash([42u8; 32]).into()}),
             NetworkMessage::CFilter(CFilter{filter_type: 7, block_hash: hash([25u8; 32]).into(), filter: vec![1,2,3]}),
             NetworkMessage::GetCFHeaders(GetCFHeaders{filter_type: 4, start_height: 102, stop_hash: hash([47u8; 32]).into()}),
@@ -383,6 +1002,11 @@ mod test {
             NetworkMessage::CFCheckpt(CFCheckpt{filter_type: 27, stop_hash: hash([77u8; 32]).into(), filter_headers: vec![hash([3u8; 32]).into(), hash([99u8; 32]).into()]}),
             NetworkMessage::Alert(vec![45,66,3,2,6,8,9,12,3,130]),
             NetworkMessage::Reject(Reject{message: "Test reject".into(), ccode: RejectReason::Duplicate, reason: "Cause".into(), hash: hash([255u8; 32])}),
+            NetworkMessage::SendCmpct(SendCmpct{send_compact: true, version: 1}),
+            NetworkMessage::CmpctBlock(CmpctBlock{header: header.clone(), nonce: 42, short_ids: vec![ShortId([1, 2, 3, 4, 5, 6])], prefilled_txs: vec![PrefilledTransaction{idx: 0, tx: tx.clone()}, PrefilledTransaction{idx: 2, tx: tx.clone()}]}),
+            NetworkMessage::GetBlockTxn(GetBlockTxn{block_hash: hash([33u8; 32]).into(), indexes: vec![1, 3, 4]}),
+            NetworkMessage::BlockTxn(BlockTxn{block_hash: hash([33u8; 32]).into(), transactions: vec![tx.clone()]}),
+            NetworkMessage::Unknown { command: "tapyrus".into(), payload: vec![1, 2, 3, 4, 5] },
         ];
 
         for msg in msgs {
@@ -392,6 +1016,297 @@ mod test {
 
     }
 
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_round_trip_test() {
+        use arbitrary::{Arbitrary, Unstructured};
+        use hashes::sha256;
+
+        // Deterministic pseudo-random input
+        let mut data = Vec::new();
+        let mut chunk = sha256::Hash::hash(b"arbitrary");
+        for _ in 0..256 {
+            data.extend_from_slice(&chunk[..]);
+            chunk = sha256::Hash::hash(&chunk[..]);
+        }
+
+        let mut generated = 0;
+        for start in 0..1024 {
+            let mut u = Unstructured::new(&data[start..]);
+            let raw_msg = match RawNetworkMessage::arbitrary(&mut u) {
+                Ok(raw_msg) => raw_msg,
+                Err(_) => continue,
+            };
+            assert_eq!(deserialize::<RawNetworkMessage>(&serialize(&raw_msg)).unwrap(), raw_msg);
+            generated += 1;
+        }
+        assert!(generated > 0);
+    }
+
+    #[test]
+    fn streaming_encode_test() {
+        let tx: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        let raw_msg = RawNetworkMessage { magic: 57, payload: NetworkMessage::Tx(tx.clone()) };
+
+        // same bytes as wrapping the serialized payload in `CheckedData`
+        let mut expected = serialize(&57u32);
+        expected.extend(serialize(&CommandString::from("tx")));
+        expected.extend(serialize(&CheckedData(serialize(&tx))));
+
+        let mut encoded = Vec::new();
+        let len = raw_msg.consensus_encode(&mut encoded).unwrap();
+        assert_eq!(encoded, expected);
+        assert_eq!(len, expected.len());
+        assert_eq!(raw_msg.consensus_encoded_len(), expected.len());
+    }
+
+    #[test]
+    fn decode_config_test() {
+        let ping = serialize(&RawNetworkMessage { magic: 57, payload: NetworkMessage::Ping(1) });
+        let addr = serialize(&RawNetworkMessage {
+            magic: 57,
+            payload: NetworkMessage::Addr(vec![(45, Address::new(&([123, 255, 0, 100], 833).into(), ServiceFlags::NETWORK))]),
+        });
+
+        let config = DecodeConfig::new(16).with_payload_limit("addr", 8);
+        assert_eq!(config.payload_limit(&"ping".into()), 16);
+        assert_eq!(config.payload_limit(&"addr".into()), 8);
+        assert!(RawNetworkMessage::consensus_decode_with_config(&ping[..], &config).is_ok());
+        match RawNetworkMessage::consensus_decode_with_config(&addr[..], &config) {
+            Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation { requested: 31, max: 8 })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // a message larger than the default limit
+        let big = RawNetworkMessage { magic: 57, payload: NetworkMessage::Alert(vec![0u8; MAX_VEC_SIZE]) };
+        let data = serialize(&big);
+        assert!(deserialize::<RawNetworkMessage>(&data).is_err());
+        let config = DecodeConfig::new(2 * MAX_VEC_SIZE);
+        assert_eq!(RawNetworkMessage::consensus_decode_with_config(&data[..], &config).unwrap(), big);
+    }
+
+    #[test]
+    fn chain_limits_test() {
+        let block: Block = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e49000000").unwrap()).unwrap();
+        let block_data = serialize(&block);
+        let msg = serialize(&RawNetworkMessage { magic: 57, payload: NetworkMessage::Block(block.clone()) });
+
+        let config = DecodeConfig::default().with_chain_limits(ChainLimits::new(block_data.len() - 1));
+        assert_eq!(config.max_message_size, MAX_VEC_SIZE);
+        match RawNetworkMessage::consensus_decode_with_config(&msg[..], &config) {
+            Err(encode::Error::Policy(encode::PolicyError::OversizedBlock { .. })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        let config = DecodeConfig::default().with_chain_limits(ChainLimits::new(block_data.len()));
+        assert_eq!(RawNetworkMessage::consensus_decode_with_config(&msg[..], &config).unwrap().payload, NetworkMessage::Block(block));
+
+        // chains with larger blocks accept larger messages
+        let config = DecodeConfig::default().with_chain_limits(ChainLimits::new(8_000_000));
+        assert_eq!(config.max_message_size, 8_000_000);
+        assert_eq!(config.chain_limits.max_vec_size(), 8_000_000);
+    }
+
+    #[test]
+    fn decode_from_slice_test() {
+        let block: Block = deserialize(&hex_decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap()).unwrap();
+        let block_msg = RawNetworkMessage { magic: 57, payload: NetworkMessage::Block(block) };
+        let unknown_msg = RawNetworkMessage {
+            magic: 57,
+            payload: NetworkMessage::Unknown { command: "newcmd".into(), payload: vec![0xde, 0xad] },
+        };
+
+        let mut data = serialize(&block_msg);
+        let block_len = data.len();
+        data.extend_from_slice(&serialize(&unknown_msg));
+
+        let config = DecodeConfig::default();
+        let (msg, consumed) = RawNetworkMessage::consensus_decode_from_slice(&data, &config).unwrap();
+        assert_eq!(msg, block_msg);
+        assert_eq!(consumed, block_len);
+        let (msg, consumed) = RawNetworkMessage::consensus_decode_from_slice(&data[block_len..], &config).unwrap();
+        assert_eq!(msg, unknown_msg);
+        assert_eq!(consumed, data.len() - block_len);
+
+        // truncated payload
+        match RawNetworkMessage::consensus_decode_from_slice(&data[..block_len - 1], &config) {
+            Err(encode::Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // corrupted payload
+        data[100] ^= 0xff;
+        match RawNetworkMessage::consensus_decode_from_slice(&data, &config) {
+            Err(encode::Error::Parse(encode::ParseError::InvalidChecksum { .. })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[derive(PartialEq, Eq, Debug)]
+    struct Greeting {
+        text: String,
+        count: u32,
+    }
+    impl_consensus_encoding!(Greeting, text, count);
+
+    impl CustomMessage for Greeting {
+        const COMMAND: &'static str = "greeting";
+    }
+
+    #[test]
+    fn custom_message_test() {
+        let greeting = Greeting { text: "hello".to_owned(), count: 3 };
+        let raw_msg = RawNetworkMessage { magic: 57, payload: NetworkMessage::custom(&greeting) };
+        assert_eq!(raw_msg.cmd(), "custom");
+        assert_eq!(raw_msg.command(), "greeting".into());
+        assert_eq!(raw_msg.payload.decode_custom::<Greeting>().unwrap().unwrap(), greeting);
+        let data = serialize(&raw_msg);
+
+        // without registration the message is unknown
+        let decoded: RawNetworkMessage = deserialize(&data).unwrap();
+        assert_eq!(decoded.payload, NetworkMessage::Unknown { command: "greeting".into(), payload: serialize(&greeting) });
+        assert!(decoded.payload.decode_custom::<Greeting>().is_none());
+
+        let config = DecodeConfig::default().with_custom_command(Greeting::COMMAND);
+        let decoded = RawNetworkMessage::consensus_decode_with_config(&data[..], &config).unwrap();
+        assert_eq!(decoded, raw_msg);
+        let (decoded, _) = RawNetworkMessage::consensus_decode_from_slice(&data, &config).unwrap();
+        assert_eq!(decoded, raw_msg);
+
+        // built-in commands cannot be overridden
+        let ping = serialize(&RawNetworkMessage { magic: 57, payload: NetworkMessage::Ping(1) });
+        let config = config.with_custom_command("ping");
+        assert_eq!(RawNetworkMessage::consensus_decode_with_config(&ping[..], &config).unwrap().payload, NetworkMessage::Ping(1));
+    }
+
+    #[test]
+    fn decode_unchecked_test() {
+        let raw_msg = RawNetworkMessage { magic: 57, payload: NetworkMessage::Ping(100) };
+        let mut data = serialize(&raw_msg);
+        // corrupt the checksum
+        data[20] ^= 0xff;
+
+        assert!(deserialize::<RawNetworkMessage>(&data).is_err());
+        assert_eq!(RawNetworkMessage::consensus_decode_unchecked(&data[..]).unwrap(), raw_msg);
+        let config = DecodeConfig::default().skip_checksums();
+        let (msg, consumed) = RawNetworkMessage::consensus_decode_from_slice(&data, &config).unwrap();
+        assert_eq!(msg, raw_msg);
+        assert_eq!(consumed, data.len());
+    }
+
+    #[test]
+    fn element_limits_test() {
+        let inv = RawNetworkMessage {
+            magic: 57,
+            payload: NetworkMessage::Inv(vec![Inventory::Error; 3]),
+        };
+        let getheaders = RawNetworkMessage {
+            magic: 57,
+            payload: NetworkMessage::GetHeaders(GetHeadersMessage::new(vec![hash([1u8; 32]).into(); 3], hash([5u8; 32]).into())),
+        };
+        let inv_data = serialize(&inv);
+        let getheaders_data = serialize(&getheaders);
+        assert_eq!(deserialize::<RawNetworkMessage>(&inv_data).unwrap(), inv);
+        assert_eq!(deserialize::<RawNetworkMessage>(&getheaders_data).unwrap(), getheaders);
+
+        let limits = Limits { max_inv: 2, max_locator_hashes: 2, ..Limits::core() };
+        let config = DecodeConfig::default().with_limits(limits);
+        match RawNetworkMessage::consensus_decode_with_config(&inv_data[..], &config) {
+            Err(encode::Error::Policy(encode::PolicyError::TooManyElements { requested: 3, max: 2 })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match RawNetworkMessage::consensus_decode_from_slice(&getheaders_data, &config) {
+            Err(encode::Error::Policy(encode::PolicyError::TooManyElements { requested: 3, max: 2 })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // a huge count is rejected before anything is allocated
+        let mut payload = vec![0xff];
+        payload.extend_from_slice(&[0xff; 8]);
+        let huge = serialize(&RawNetworkMessage {
+            magic: 57,
+            payload: NetworkMessage::Unknown { command: "headers".into(), payload: payload },
+        });
+        match deserialize::<RawNetworkMessage>(&huge) {
+            Err(encode::Error::Policy(encode::PolicyError::TooManyElements { requested: 0xffffffffffffffff, max: 2000 })) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // the default limits are Tapyrus Core's, unless opted out of
+        assert_eq!(Limits::default(), Limits::core());
+        let oversized = |command: &'static str, count: u64| {
+            let mut payload = serialize(&VarInt(count));
+            payload.resize(payload.len() + 8, 0);
+            serialize(&RawNetworkMessage {
+                magic: 57,
+                payload: NetworkMessage::Unknown { command: command.into(), payload: payload },
+            })
+        };
+        for &(command, max) in &[("addr", 1000), ("inv", 50_000), ("headers", 2000)] {
+            match deserialize::<RawNetworkMessage>(&oversized(command, max + 1)) {
+                Err(encode::Error::Policy(encode::PolicyError::TooManyElements { requested, max: m })) => {
+                    assert_eq!((requested, m), (max + 1, max));
+                }
+                x => panic!("unexpected result for {}: {:?}", command, x),
+            }
+            let config = DecodeConfig::default().with_limits(Limits::unlimited());
+            if let Err(encode::Error::Policy(_)) = RawNetworkMessage::consensus_decode_with_config(&oversized(command, max + 1)[..], &config) {
+                panic!("{} rejected by count without limits", command);
+            }
+        }
+    }
+
+    #[test]
+    fn network_test() {
+        let msg = RawNetworkMessage::new(Network::Dev, NetworkMessage::Verack);
+        assert_eq!(msg.magic, Network::Dev.magic());
+        assert_eq!(msg.network(), Some(Network::Dev));
+
+        let msg = RawNetworkMessage::with_network_id(&NetworkId::from(101), NetworkMessage::Verack);
+        assert_eq!(msg.magic, NetworkId::from(101).magic());
+        assert_eq!(msg.network(), None);
+    }
+
+    #[test]
+    fn unknown_command_test() {
+        let raw_msg = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::Unknown { command: "newcmd".into(), payload: vec![0xde, 0xad] },
+        };
+        assert_eq!(raw_msg.cmd(), "unknown");
+        assert_eq!(raw_msg.command(), "newcmd".into());
+
+        let data = serialize(&raw_msg);
+        assert_eq!(&data[4..16], &[0x6e, 0x65, 0x77, 0x63, 0x6d, 0x64, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&data[24..], &[0xde, 0xad]);
+        assert_eq!(deserialize::<RawNetworkMessage>(&data).unwrap(), raw_msg);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_raw_network_message_test() {
+        let msgs = vec![
+            NetworkMessage::Verack,
+            NetworkMessage::Ping(15),
+            NetworkMessage::Addr(vec![(45, Address::new(&([123, 255, 0, 100], 833).into(), ServiceFlags::NETWORK))]),
+            NetworkMessage::AddrV2(vec![AddrV2Message{time: 45, services: ServiceFlags::NETWORK, addr: AddrV2::TorV3([7u8; 32]), port: 833}]),
+            NetworkMessage::Inv(vec![Inventory::Block(hash([8u8; 32]).into()), Inventory::Error]),
+            NetworkMessage::GetCFilters(GetCFilters{filter_type: 2, start_height: 52, stop_hash: hash([42u8; 32]).into()}),
+            NetworkMessage::Reject(Reject{message: "Test reject".into(), ccode: RejectReason::Duplicate, reason: "Cause".into(), hash: hash([255u8; 32])}),
+            NetworkMessage::Unknown { command: "tapyrus".into(), payload: vec![1, 2, 3, 4, 5] },
+            NetworkMessage::Unknown { command: "custom".into(), payload: vec![1, 2] },
+            NetworkMessage::Custom("greeting".into(), vec![6, 7]),
+            NetworkMessage::Custom("custom".into(), vec![6, 7]),
+        ];
+
+        for msg in msgs {
+            let raw_msg = RawNetworkMessage {magic: 57, payload: msg};
+            serde_round_trip!(raw_msg);
+        }
+
+        let json = ::serde_json::to_value(&NetworkMessage::Ping(42)).unwrap();
+        assert_eq!(json.to_string(), r#"{"ping":42}"#);
+    }
+
     #[test]
     fn serialize_commandstring_test() {
         let cs = CommandString("Andrew".into());
@@ -21,24 +21,61 @@
 use std::io;
 
 use hashes::sha256d;
+#[cfg(feature = "arbitrary")] use hashes::Hash;
 
 use network::constants;
 use consensus::encode::{self, Decodable, Encodable};
-use hash_types::{BlockHash, Txid, Wtxid};
+use hash_types::{BlockHash, MalFixTxid, Wtxid};
 
 /// An inventory item.
 #[derive(PartialEq, Eq, Clone, Debug, Copy, Hash)]
 pub enum Inventory {
     /// Error --- these inventories can be ignored
     Error,
-    /// Transaction
-    Transaction(Txid),
+    /// Transaction, by its malleability-fixed txid
+    Transaction(MalFixTxid),
     /// Block
     Block(BlockHash),
     /// Witness Transaction
     WitnessTransaction(Wtxid),
     /// Witness Block
     WitnessBlock(BlockHash),
+    /// BIP37 filtered block, answered with a `merkleblock`
+    FilteredBlock(BlockHash),
+    /// BIP152 compact block, answered with a `cmpctblock`
+    CompactBlock(BlockHash),
+    /// Any other inventory type
+    Unknown {
+        /// The inventory type code
+        inv_type: u32,
+        /// The hash of the item
+        hash: sha256d::Hash,
+    },
+}
+
+impl Inventory {
+    /// Return the type code of this inventory on the wire
+    pub fn inv_type(&self) -> u32 {
+        match *self {
+            Inventory::Error => 0,
+            Inventory::Transaction(_) => 1,
+            Inventory::Block(_) => 2,
+            Inventory::FilteredBlock(_) => 3,
+            Inventory::CompactBlock(_) => 4,
+            Inventory::WitnessTransaction(_) => 0x40000001,
+            Inventory::WitnessBlock(_) => 0x40000002,
+            Inventory::Unknown { inv_type, .. } => inv_type,
+        }
+    }
+
+    /// Decode an inventory, failing on unknown inventory types instead of
+    /// returning `Inventory::Unknown`
+    pub fn consensus_decode_strict<D: io::Read>(d: D) -> Result<Inventory, encode::Error> {
+        match Inventory::consensus_decode(d)? {
+            Inventory::Unknown { inv_type, .. } => Err(encode::Error::Parse(encode::ParseError::UnknownInventoryType(inv_type))),
+            inv => Ok(inv),
+        }
+    }
 }
 
 impl Encodable for Inventory {
@@ -53,12 +90,16 @@ impl Encodable for Inventory {
                 $item.consensus_encode(&mut s)?
             }
         }
+        let inv_type = self.inv_type();
         Ok(match *self {
-            Inventory::Error => encode_inv!(0, sha256d::Hash::default()),
-            Inventory::Transaction(ref t) => encode_inv!(1, t),
-            Inventory::Block(ref b) => encode_inv!(2, b),
-            Inventory::WitnessTransaction(ref t) => encode_inv!(0x40000001, t),
-            Inventory::WitnessBlock(ref b) => encode_inv!(0x40000002, b),
+            Inventory::Error => encode_inv!(inv_type, sha256d::Hash::default()),
+            Inventory::Transaction(ref t) => encode_inv!(inv_type, t),
+            Inventory::Block(ref b) => encode_inv!(inv_type, b),
+            Inventory::FilteredBlock(ref b) => encode_inv!(inv_type, b),
+            Inventory::CompactBlock(ref b) => encode_inv!(inv_type, b),
+            Inventory::WitnessTransaction(ref t) => encode_inv!(inv_type, t),
+            Inventory::WitnessBlock(ref b) => encode_inv!(inv_type, b),
+            Inventory::Unknown { ref hash, .. } => encode_inv!(inv_type, hash),
         })
     }
 }
@@ -71,13 +112,112 @@ impl Decodable for Inventory {
             0 => Inventory::Error,
             1 => Inventory::Transaction(Decodable::consensus_decode(&mut d)?),
             2 => Inventory::Block(Decodable::consensus_decode(&mut d)?),
+            3 => Inventory::FilteredBlock(Decodable::consensus_decode(&mut d)?),
+            4 => Inventory::CompactBlock(Decodable::consensus_decode(&mut d)?),
             0x40000001 => Inventory::WitnessTransaction(Decodable::consensus_decode(&mut d)?),
             0x40000002 => Inventory::WitnessBlock(Decodable::consensus_decode(&mut d)?),
-            tp => return Err(encode::Error::UnknownInventoryType(tp)),
+            tp => Inventory::Unknown {
+                inv_type: tp,
+                hash: Decodable::consensus_decode(&mut d)?,
+            },
+        })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for Inventory {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<Inventory> {
+        Ok(match u.int_in_range(0u8..=7)? {
+            0 => Inventory::Error,
+            1 => Inventory::Transaction(u.arbitrary()?),
+            2 => Inventory::Block(u.arbitrary()?),
+            3 => Inventory::FilteredBlock(u.arbitrary()?),
+            4 => Inventory::CompactBlock(u.arbitrary()?),
+            5 => Inventory::WitnessTransaction(u.arbitrary()?),
+            6 => Inventory::WitnessBlock(u.arbitrary()?),
+            _ => {
+                let inv_type: u32 = u.arbitrary()?;
+                if inv_type <= 4 || inv_type == 0x40000001 || inv_type == 0x40000002 {
+                    return Err(::arbitrary::Error::IncorrectFormat);
+                }
+                Inventory::Unknown {
+                    inv_type: inv_type,
+                    hash: sha256d::Hash::from_inner(u.arbitrary()?),
+                }
+            },
         })
     }
 }
 
+// Inventories are serialized as a map from their type to their hash
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Inventory {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = s.serialize_map(Some(1))?;
+        match *self {
+            Inventory::Error => map.serialize_entry("error", &())?,
+            Inventory::Transaction(ref t) => map.serialize_entry("tx", t)?,
+            Inventory::Block(ref b) => map.serialize_entry("block", b)?,
+            Inventory::WitnessTransaction(ref t) => map.serialize_entry("witness_tx", t)?,
+            Inventory::WitnessBlock(ref b) => map.serialize_entry("witness_block", b)?,
+            Inventory::FilteredBlock(ref b) => map.serialize_entry("filtered_block", b)?,
+            Inventory::CompactBlock(ref b) => map.serialize_entry("compact_block", b)?,
+            Inventory::Unknown { inv_type, ref hash } => map.serialize_entry("unknown", &(inv_type, hash))?,
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Inventory {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<Inventory, D::Error> {
+        struct Visitor;
+
+        impl<'de> ::serde::de::Visitor<'de> for Visitor {
+            type Value = Inventory;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                formatter.write_str("a map from an inventory type to a hash")
+            }
+
+            fn visit_map<A: ::serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Inventory, A::Error> {
+                use serde::de::Error;
+
+                static TYPES: &'static [&'static str] = &[
+                    "error", "tx", "block", "witness_tx", "witness_block",
+                    "filtered_block", "compact_block", "unknown",
+                ];
+
+                let inv_type: String = match map.next_key()? {
+                    Some(t) => t,
+                    None => return Err(A::Error::invalid_length(0, &self)),
+                };
+                Ok(match &inv_type[..] {
+                    "error" => {
+                        map.next_value::<()>()?;
+                        Inventory::Error
+                    },
+                    "tx" => Inventory::Transaction(map.next_value()?),
+                    "block" => Inventory::Block(map.next_value()?),
+                    "witness_tx" => Inventory::WitnessTransaction(map.next_value()?),
+                    "witness_block" => Inventory::WitnessBlock(map.next_value()?),
+                    "filtered_block" => Inventory::FilteredBlock(map.next_value()?),
+                    "compact_block" => Inventory::CompactBlock(map.next_value()?),
+                    "unknown" => {
+                        let (inv_type, hash) = map.next_value()?;
+                        Inventory::Unknown { inv_type: inv_type, hash: hash }
+                    },
+                    t => return Err(A::Error::unknown_variant(t, TYPES)),
+                })
+            }
+        }
+
+        d.deserialize_map(Visitor)
+    }
+}
+
 // Some simple messages
 
 /// The `getblocks` message
@@ -118,6 +258,8 @@ impl GetBlocksMessage {
 }
 
 impl_consensus_encoding!(GetBlocksMessage, version, locator_hashes, stop_hash);
+serde_struct_impl!(GetBlocksMessage, version, locator_hashes, stop_hash);
+arbitrary_struct_impl!(GetBlocksMessage, version, locator_hashes, stop_hash);
 
 impl GetHeadersMessage {
     /// Construct a new `getheaders` message
@@ -131,14 +273,17 @@ impl GetHeadersMessage {
 }
 
 impl_consensus_encoding!(GetHeadersMessage, version, locator_hashes, stop_hash);
+serde_struct_impl!(GetHeadersMessage, version, locator_hashes, stop_hash);
+arbitrary_struct_impl!(GetHeadersMessage, version, locator_hashes, stop_hash);
 
 #[cfg(test)]
 mod tests {
-    use super::{GetHeadersMessage, GetBlocksMessage};
+    use super::{GetHeadersMessage, GetBlocksMessage, Inventory};
 
+    use hashes::{sha256d, Hash};
     use hex::decode as hex_decode;
 
-    use consensus::encode::{deserialize, serialize};
+    use consensus::encode::{self, deserialize, serialize};
     use std::default::Default;
 
     #[test]
@@ -172,5 +317,34 @@ mod tests {
 
         assert_eq!(serialize(&real_decode), from_sat);
     }
-}
 
+    #[test]
+    fn inventory_test() {
+        let hash = sha256d::Hash::from_slice(&[7u8; 32]).unwrap();
+        let mut data = vec![0x04, 0, 0, 0];
+        data.extend_from_slice(&[7u8; 32]);
+
+        let inv: Inventory = deserialize(&data).unwrap();
+        assert_eq!(inv, Inventory::CompactBlock(hash.into()));
+        assert_eq!(inv.inv_type(), 4);
+        assert_eq!(serialize(&inv), data);
+
+        data[0] = 0x07;
+        let inv: Inventory = deserialize(&data).unwrap();
+        assert_eq!(inv, Inventory::Unknown { inv_type: 7, hash: hash });
+        assert_eq!(serialize(&inv), data);
+        match Inventory::consensus_decode_strict(&data[..]) {
+            Err(encode::Error::Parse(encode::ParseError::UnknownInventoryType(7))) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn inventory_serde_test() {
+        let hash = sha256d::Hash::from_slice(&[7u8; 32]).unwrap();
+        serde_round_trip!(Inventory::Error);
+        serde_round_trip!(Inventory::FilteredBlock(hash.into()));
+        serde_round_trip!(Inventory::Unknown { inv_type: 7, hash: hash });
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! BIP152 Compact Block Relay network messages
+//!
+//! This module defines the `sendcmpct`, `cmpctblock`, `getblocktxn` and
+//! `blocktxn` messages. Compact blocks carry the full Tapyrus block header,
+//! including its xfield and block proof, so a receiver can check the
+//! signature before reconstructing the block from its mempool.
+//!
+//! Transaction indexes are stored as absolute positions in the block and
+//! are differentially encoded on the wire as described in BIP152.
+//!
+//! Short transaction IDs are computed from the malleability-fixed txids,
+//! which identify transactions on the Tapyrus network.
+//!
+
+use std::{io, mem};
+
+use hashes::{sha256, siphash24, Hash};
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::transaction::Transaction;
+use consensus::encode::{self, Decodable, Encodable, VarInt, MAX_VEC_SIZE};
+use hash_types::{BlockHash, MalFixTxid};
+use util::endian;
+
+/// sendcmpct message
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SendCmpct {
+    /// Whether the peer wants new blocks announced with `cmpctblock`
+    pub send_compact: bool,
+    /// The compact block protocol version
+    pub version: u64,
+}
+impl_consensus_encoding!(SendCmpct, send_compact, version);
+serde_struct_impl!(SendCmpct, send_compact, version);
+arbitrary_struct_impl!(SendCmpct, send_compact, version);
+
+/// A 6-byte short transaction ID
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash, Default)]
+pub struct ShortId(pub [u8; 6]);
+
+impl Encodable for ShortId {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for ShortId {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(ShortId(Decodable::consensus_decode(d)?))
+    }
+}
+
+impl ShortId {
+    /// Calculate the SipHash keys used to compute the short IDs of the
+    /// transactions announced along with `header` and `nonce`
+    pub fn calculate_siphash_keys(header: &BlockHeader, nonce: u64) -> (u64, u64) {
+        // keys are the first two little-endian words of SHA256(header || nonce)
+        let mut engine = sha256::Hash::engine();
+        header.consensus_encode(&mut engine).expect("engines don't error");
+        nonce.consensus_encode(&mut engine).expect("engines don't error");
+        let hash = sha256::Hash::from_engine(engine);
+        (endian::slice_to_u64_le(&hash[0..8]), endian::slice_to_u64_le(&hash[8..16]))
+    }
+
+    /// Calculate the short ID of a transaction with the given SipHash keys
+    pub fn with_siphash_keys(txid: &MalFixTxid, siphash_keys: (u64, u64)) -> ShortId {
+        let hash = siphash24::Hash::hash_to_u64_with_keys(siphash_keys.0, siphash_keys.1, &txid[..]);
+        let mut id = ShortId([0; 6]);
+        id.0.copy_from_slice(&endian::u64_to_array_le(hash)[0..6]);
+        id
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for ShortId {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<ShortId> {
+        Ok(ShortId(u.arbitrary()?))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for ShortId {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ::serde::Serialize::serialize(&self.0, s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for ShortId {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<ShortId, D::Error> {
+        ::serde::Deserialize::deserialize(d).map(ShortId)
+    }
+}
+
+/// A transaction sent along with a compact block
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PrefilledTransaction {
+    /// Position of the transaction in the block
+    pub idx: u16,
+    /// The transaction
+    pub tx: Transaction,
+}
+serde_struct_impl!(PrefilledTransaction, idx, tx);
+arbitrary_struct_impl!(PrefilledTransaction, idx, tx);
+
+/// cmpctblock message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CmpctBlock {
+    /// The header of the block, including its proof
+    pub header: BlockHeader,
+    /// Nonce used to compute the short IDs
+    pub nonce: u64,
+    /// Short IDs of the transactions which are not prefilled
+    pub short_ids: Vec<ShortId>,
+    /// Transactions sent in full. They are encoded ordered by their position
+    /// in the block, only the first one at each position being kept.
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+serde_struct_impl!(CmpctBlock, header, nonce, short_ids, prefilled_txs);
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for CmpctBlock {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<CmpctBlock> {
+        // Prefilled transactions must be ordered by strictly increasing index
+        let mut prefilled_txs: Vec<PrefilledTransaction> = u.arbitrary()?;
+        prefilled_txs.sort_by_key(|p| p.idx);
+        prefilled_txs.dedup_by_key(|p| p.idx);
+        Ok(CmpctBlock {
+            header: u.arbitrary()?,
+            nonce: u.arbitrary()?,
+            short_ids: u.arbitrary()?,
+            prefilled_txs: prefilled_txs,
+        })
+    }
+}
+
+impl CmpctBlock {
+    /// Build the compact version of `block`, sending only the coinbase
+    /// transaction in full
+    pub fn from_block(block: &Block, nonce: u64) -> CmpctBlock {
+        let siphash_keys = ShortId::calculate_siphash_keys(&block.header, nonce);
+        let mut prefilled_txs = Vec::with_capacity(1);
+        let mut short_ids = Vec::with_capacity(block.txdata.len().saturating_sub(1));
+        for (idx, tx) in block.txdata.iter().enumerate() {
+            if idx == 0 {
+                prefilled_txs.push(PrefilledTransaction { idx: 0, tx: tx.clone() });
+            } else {
+                short_ids.push(ShortId::with_siphash_keys(&tx.malfix_txid(), siphash_keys));
+            }
+        }
+        CmpctBlock {
+            header: block.header.clone(),
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled_txs,
+        }
+    }
+
+    /// Return the SipHash keys of the short IDs of this block
+    pub fn siphash_keys(&self) -> (u64, u64) {
+        ShortId::calculate_siphash_keys(&self.header, self.nonce)
+    }
+
+    /// Return the short ID `tx` would have in this block
+    pub fn short_id(&self, tx: &Transaction) -> ShortId {
+        ShortId::with_siphash_keys(&tx.malfix_txid(), self.siphash_keys())
+    }
+}
+
+/// getblocktxn message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetBlockTxn {
+    /// The block whose transactions are requested
+    pub block_hash: BlockHash,
+    /// Positions of the requested transactions in the block. They are
+    /// encoded in increasing order, without duplicates.
+    pub indexes: Vec<u16>,
+}
+serde_struct_impl!(GetBlockTxn, block_hash, indexes);
+
+impl GetBlockTxn {
+    /// Request the transactions of `block_hash` at `indexes`, which are
+    /// sorted and deduplicated
+    pub fn new(block_hash: BlockHash, mut indexes: Vec<u16>) -> GetBlockTxn {
+        indexes.sort();
+        indexes.dedup();
+        GetBlockTxn {
+            block_hash: block_hash,
+            indexes: indexes,
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for GetBlockTxn {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<GetBlockTxn> {
+        // Indexes must be strictly increasing
+        let mut indexes: Vec<u16> = u.arbitrary()?;
+        indexes.sort();
+        indexes.dedup();
+        Ok(GetBlockTxn {
+            block_hash: u.arbitrary()?,
+            indexes: indexes,
+        })
+    }
+}
+
+/// blocktxn message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BlockTxn {
+    /// The block the transactions belong to
+    pub block_hash: BlockHash,
+    /// The requested transactions, in the order they were requested
+    pub transactions: Vec<Transaction>,
+}
+impl_consensus_encoding!(BlockTxn, block_hash, transactions);
+serde_struct_impl!(BlockTxn, block_hash, transactions);
+arbitrary_struct_impl!(BlockTxn, block_hash, transactions);
+
+/// The elements of `items` by strictly increasing `index`, keeping the first
+/// of the elements with the same index
+fn by_increasing_index<T, F: Fn(&T) -> u16>(items: &[T], index: F) -> Vec<&T> {
+    let mut sorted: Vec<&T> = items.iter().collect();
+    // the sort is stable
+    sorted.sort_by_key(|item| index(item));
+    sorted.dedup_by_key(|item| index(item));
+    sorted
+}
+
+/// Encode `idx` relative to the previously encoded index, which is lower
+fn encode_index<S: io::Write>(idx: u16, last: Option<u16>, s: S) -> Result<usize, encode::Error> {
+    let diff = match last {
+        None => idx,
+        Some(last) => idx - last - 1,
+    };
+    VarInt(diff as u64).consensus_encode(s)
+}
+
+/// Decode an index relative to the previously decoded index
+fn decode_index<D: io::Read>(last: Option<u16>, d: D) -> Result<u16, encode::Error> {
+    let diff = VarInt::consensus_decode(d)?.0;
+    let offset = match last {
+        None => 0,
+        Some(last) => last as u64 + 1,
+    };
+    match offset.checked_add(diff) {
+        Some(idx) if idx <= u16::max_value() as u64 => Ok(idx as u16),
+        _ => Err(encode::Error::Parse(encode::ParseError::Other("compact block index overflow"))),
+    }
+}
+
+/// Decode the length of a differentially encoded list of indexes
+fn decode_index_count<D: io::Read, T>(d: D) -> Result<usize, encode::Error> {
+    let len = VarInt::consensus_decode(d)?.0;
+    if len > u16::max_value() as u64 + 1 {
+        return Err(encode::Error::Parse(encode::ParseError::Other("too many compact block indexes")));
+    }
+    let byte_size = len as usize * mem::size_of::<T>();
+    if byte_size > MAX_VEC_SIZE {
+        return Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation { requested: byte_size, max: MAX_VEC_SIZE }));
+    }
+    Ok(len as usize)
+}
+
+impl Encodable for CmpctBlock {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.header.consensus_encode(&mut s)?;
+        len += self.nonce.consensus_encode(&mut s)?;
+        len += self.short_ids.consensus_encode(&mut s)?;
+        let prefilled_txs = by_increasing_index(&self.prefilled_txs, |prefilled| prefilled.idx);
+        len += VarInt(prefilled_txs.len() as u64).consensus_encode(&mut s)?;
+        let mut last = None;
+        for prefilled in prefilled_txs {
+            len += encode_index(prefilled.idx, last, &mut s)?;
+            len += prefilled.tx.consensus_encode(&mut s)?;
+            last = Some(prefilled.idx);
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for CmpctBlock {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let header = Decodable::consensus_decode(&mut d)?;
+        let nonce = Decodable::consensus_decode(&mut d)?;
+        let short_ids = Decodable::consensus_decode(&mut d)?;
+        let count = decode_index_count::<_, PrefilledTransaction>(&mut d)?;
+        let mut prefilled_txs = Vec::with_capacity(count);
+        let mut last = None;
+        for _ in 0..count {
+            let idx = decode_index(last, &mut d)?;
+            let tx = Decodable::consensus_decode(&mut d)?;
+            prefilled_txs.push(PrefilledTransaction { idx: idx, tx: tx });
+            last = Some(idx);
+        }
+        Ok(CmpctBlock {
+            header: header,
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled_txs,
+        })
+    }
+}
+
+impl Encodable for GetBlockTxn {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = 0;
+        len += self.block_hash.consensus_encode(&mut s)?;
+        let indexes = by_increasing_index(&self.indexes, |idx| *idx);
+        len += VarInt(indexes.len() as u64).consensus_encode(&mut s)?;
+        let mut last = None;
+        for idx in indexes {
+            len += encode_index(*idx, last, &mut s)?;
+            last = Some(*idx);
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for GetBlockTxn {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let count = decode_index_count::<_, u16>(&mut d)?;
+        let mut indexes = Vec::with_capacity(count);
+        let mut last = None;
+        for _ in 0..count {
+            let idx = decode_index(last, &mut d)?;
+            indexes.push(idx);
+            last = Some(idx);
+        }
+        Ok(GetBlockTxn {
+            block_hash: block_hash,
+            indexes: indexes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CmpctBlock, GetBlockTxn, PrefilledTransaction, SendCmpct, ShortId};
+
+    use hashes::Hash;
+    use hex::decode as hex_decode;
+
+    use blockdata::block::Block;
+    use consensus::encode::{self, deserialize, serialize};
+    use hash_types::{BlockHash, MalFixTxid};
+
+    const BLOCK_HEX: &str = "010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e490121032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000";
+
+    #[test]
+    fn sendcmpct_test() {
+        let msg = SendCmpct { send_compact: true, version: 1 };
+        assert_eq!(serialize(&msg), hex_decode("010100000000000000").unwrap());
+        assert_eq!(deserialize::<SendCmpct>(&serialize(&msg)).unwrap(), msg);
+    }
+
+    #[test]
+    fn getblocktxn_differential_encoding_test() {
+        let msg = GetBlockTxn {
+            block_hash: BlockHash::from_slice(&[1u8; 32]).unwrap(),
+            indexes: vec![0, 1, 5, 300],
+        };
+        let data = serialize(&msg);
+        // count, then 0, 1 - 0 - 1, 5 - 1 - 1 and 300 - 5 - 1
+        assert_eq!(&data[32..], &[0x04, 0x00, 0x00, 0x03, 0xfd, 0x26, 0x01][..]);
+        assert_eq!(deserialize::<GetBlockTxn>(&data).unwrap(), msg);
+    }
+
+    #[test]
+    fn getblocktxn_invalid_indexes_test() {
+        // indexes are sent sorted and deduplicated
+        let block_hash = BlockHash::from_slice(&[1u8; 32]).unwrap();
+        let msg = GetBlockTxn { block_hash: block_hash, indexes: vec![5, 3, 5] };
+        let sorted = GetBlockTxn::new(block_hash, vec![5, 3, 5]);
+        assert_eq!(sorted.indexes, vec![3, 5]);
+        assert_eq!(serialize(&msg), serialize(&sorted));
+        assert_eq!(deserialize::<GetBlockTxn>(&serialize(&msg)).unwrap(), sorted);
+
+        // the second index overflows u16
+        let mut data = vec![1u8; 32];
+        data.extend_from_slice(&[0x02, 0xfd, 0xff, 0xff, 0x00]);
+        match deserialize::<GetBlockTxn>(&data) {
+            Err(encode::Error::Parse(encode::ParseError::Other(_))) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn short_id_test() {
+        let block: Block = deserialize(&hex_decode(BLOCK_HEX).unwrap()).unwrap();
+
+        let cmpct = CmpctBlock::from_block(&block, 42);
+        assert_eq!(cmpct.prefilled_txs.len(), 1);
+        assert_eq!(cmpct.prefilled_txs[0].tx, block.txdata[0]);
+        assert_eq!(cmpct.short_ids.len(), block.txdata.len() - 1);
+        assert_eq!(cmpct.short_ids[0], cmpct.short_id(&block.txdata[1]));
+        assert_eq!(deserialize::<CmpctBlock>(&serialize(&cmpct)).unwrap(), cmpct);
+
+        // only the first transaction at each position is sent
+        let mut duplicated = cmpct.clone();
+        duplicated.prefilled_txs.push(PrefilledTransaction { idx: 0, tx: block.txdata[1].clone() });
+        assert_eq!(serialize(&duplicated), serialize(&cmpct));
+
+        // the keys depend on the nonce
+        let keys = ShortId::calculate_siphash_keys(&block.header, 42);
+        assert_eq!(cmpct.siphash_keys(), keys);
+        assert!(ShortId::calculate_siphash_keys(&block.header, 43) != keys);
+        let txid = block.txdata[1].malfix_txid();
+        assert_eq!(ShortId::with_siphash_keys(&txid, keys), cmpct.short_ids[0]);
+        assert!(ShortId::with_siphash_keys(&txid, (keys.1, keys.0)) != cmpct.short_ids[0]);
+    }
+
+    #[test]
+    fn short_id_vectors_test() {
+        let block: Block = deserialize(&hex_decode(BLOCK_HEX).unwrap()).unwrap();
+        // the keys are the first two little-endian words of SHA256(header || nonce)
+        assert_eq!(ShortId::calculate_siphash_keys(&block.header, 42), (0x78236bf4e16aec72, 0xa28b983041e1c9c8));
+
+        // the SipHash-2-4 reference vector of a 32-byte message is
+        // 0x7127512f72f27cce, of which short IDs keep the 6 low bytes
+        let keys = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+        let txid = MalFixTxid::from_slice(&(0..32).collect::<Vec<u8>>()).unwrap();
+        assert_eq!(ShortId::with_siphash_keys(&txid, keys), ShortId([0xce, 0x7c, 0xf2, 0x72, 0x2f, 0x51]));
+    }
+}
@@ -4,6 +4,17 @@
 
 use hash_types::{BlockHash, FilterHash};
 
+use blockdata::block::Block;
+use blockdata::script::Script;
+use blockdata::transaction::OutPoint;
+use util::bip158::{self, BlockFilter};
+use util::hash::BitcoinHash;
+
+/// Filter type of the basic filter defined in BIP158
+pub const BASIC_FILTER_TYPE: u8 = 0;
+/// Number of blocks between two filter headers of a `cfcheckpt` message
+pub const CHECKPOINT_INTERVAL: usize = 1000;
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// getcfilters message
 pub struct GetCFilters {
@@ -15,6 +26,8 @@ pub struct GetCFilters {
     pub stop_hash: BlockHash,
 }
 impl_consensus_encoding!(GetCFilters, filter_type, start_height, stop_hash);
+serde_struct_impl!(GetCFilters, filter_type, start_height, stop_hash);
+arbitrary_struct_impl!(GetCFilters, filter_type, start_height, stop_hash);
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// cfilter message
@@ -27,6 +40,37 @@ pub struct CFilter {
     pub filter: Vec<u8>,
 }
 impl_consensus_encoding!(CFilter, filter_type, block_hash, filter);
+serde_struct_impl!(CFilter, filter_type, block_hash, filter);
+arbitrary_struct_impl!(CFilter, filter_type, block_hash, filter);
+
+impl CFilter {
+    /// Build the basic filter of `block`, looking up the scripts spent by
+    /// its inputs with `script_for_coin`
+    pub fn from_block<M>(block: &Block, script_for_coin: M) -> Result<CFilter, bip158::Error>
+        where M: Fn(&OutPoint) -> Result<Script, bip158::Error> {
+        let filter = BlockFilter::new_script_filter(block, script_for_coin)?;
+        Ok(CFilter {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash: block.bitcoin_hash(),
+            filter: filter.content,
+        })
+    }
+
+    /// Whether any of `query_scripts` is in this basic filter, i.e. whether
+    /// the block should be fetched to look for the transactions of a wallet.
+    /// An empty query matches any filter.
+    pub fn match_any_script<'a, I>(&self, query_scripts: I) -> Result<bool, bip158::Error>
+        where I: IntoIterator<Item=&'a Script> {
+        BlockFilter::new(&self.filter).match_any_script(&self.block_hash, query_scripts)
+    }
+
+    /// Whether all of `query_scripts` are in this basic filter. An empty
+    /// query matches any filter.
+    pub fn match_all_scripts<'a, I>(&self, query_scripts: I) -> Result<bool, bip158::Error>
+        where I: IntoIterator<Item=&'a Script> {
+        BlockFilter::new(&self.filter).match_all_scripts(&self.block_hash, query_scripts)
+    }
+}
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// getcfheaders message
@@ -39,6 +83,8 @@ pub struct GetCFHeaders {
     pub stop_hash: BlockHash,
 }
 impl_consensus_encoding!(GetCFHeaders, filter_type, start_height, stop_hash);
+serde_struct_impl!(GetCFHeaders, filter_type, start_height, stop_hash);
+arbitrary_struct_impl!(GetCFHeaders, filter_type, start_height, stop_hash);
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// cfheaders message
@@ -59,6 +105,58 @@ impl_consensus_encoding!(
     previous_filter,
     filter_hashes
 );
+serde_struct_impl!(
+    CFHeaders,
+    filter_type,
+    stop_hash,
+    previous_filter,
+    filter_hashes
+);
+arbitrary_struct_impl!(
+    CFHeaders,
+    filter_type,
+    stop_hash,
+    previous_filter,
+    filter_hashes
+);
+
+impl CFHeaders {
+    /// Build the basic filter headers of consecutive `blocks`, where
+    /// `previous_filter` is the filter header of the block preceding the
+    /// first one. The last block is the stop block.
+    ///
+    /// # Panics
+    /// If `blocks` is empty.
+    pub fn from_blocks<M>(
+        blocks: &[Block],
+        previous_filter: FilterHash,
+        script_for_coin: M,
+    ) -> Result<CFHeaders, bip158::Error>
+        where M: Fn(&OutPoint) -> Result<Script, bip158::Error> {
+        let stop_hash = blocks.last().expect("no blocks").bitcoin_hash();
+        let mut filter_hashes = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            let filter = BlockFilter::new_script_filter(block, &script_for_coin)?;
+            filter_hashes.push(filter.filter_hash());
+        }
+        Ok(CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: stop_hash,
+            previous_filter: previous_filter,
+            filter_hashes: filter_hashes,
+        })
+    }
+
+    /// Compute the filter header of each block in the range by chaining the
+    /// filter hashes onto `previous_filter`
+    pub fn filter_headers(&self) -> Vec<FilterHash> {
+        let mut previous = self.previous_filter;
+        self.filter_hashes.iter().map(|filter_hash| {
+            previous = bip158::filter_header(filter_hash, &previous);
+            previous
+        }).collect()
+    }
+}
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// getcfcheckpt message
@@ -69,6 +167,8 @@ pub struct GetCFCheckpt {
     pub stop_hash: BlockHash,
 }
 impl_consensus_encoding!(GetCFCheckpt, filter_type, stop_hash);
+serde_struct_impl!(GetCFCheckpt, filter_type, stop_hash);
+arbitrary_struct_impl!(GetCFCheckpt, filter_type, stop_hash);
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 /// cfcheckpt message
@@ -81,3 +181,85 @@ pub struct CFCheckpt {
     pub filter_headers: Vec<FilterHash>,
 }
 impl_consensus_encoding!(CFCheckpt, filter_type, stop_hash, filter_headers);
+serde_struct_impl!(CFCheckpt, filter_type, stop_hash, filter_headers);
+arbitrary_struct_impl!(CFCheckpt, filter_type, stop_hash, filter_headers);
+
+impl CFCheckpt {
+    /// Build the basic filter checkpoints of a chain, where `blocks` starts
+    /// at the genesis block and ends at the stop block. A filter header is
+    /// included for every `CHECKPOINT_INTERVAL`th block.
+    ///
+    /// # Panics
+    /// If `blocks` is empty.
+    pub fn from_blocks<M>(blocks: &[Block], script_for_coin: M) -> Result<CFCheckpt, bip158::Error>
+        where M: Fn(&OutPoint) -> Result<Script, bip158::Error> {
+        let stop_hash = blocks.last().expect("no blocks").bitcoin_hash();
+        let mut filter_headers = Vec::with_capacity(blocks.len() / CHECKPOINT_INTERVAL);
+        let mut previous = FilterHash::default();
+        for (height, block) in blocks.iter().enumerate() {
+            let filter = BlockFilter::new_script_filter(block, &script_for_coin)?;
+            previous = filter.filter_header(&previous);
+            if height > 0 && height % CHECKPOINT_INTERVAL == 0 {
+                filter_headers.push(previous);
+            }
+        }
+        Ok(CFCheckpt {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: stop_hash,
+            filter_headers: filter_headers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+    use hash_types::FilterHash;
+
+    use blockdata::block::Block;
+    use blockdata::script::Script;
+    use blockdata::transaction::OutPoint;
+    use consensus::encode::deserialize;
+    use util::bip158::{self, BlockFilter};
+    use util::hash::BitcoinHash;
+
+    use super::{CFCheckpt, CFHeaders, CFilter, BASIC_FILTER_TYPE, CHECKPOINT_INTERVAL};
+
+    fn script_for_coin(_: &OutPoint) -> Result<Script, bip158::Error> {
+        Ok(Script::from(vec![0x51]))
+    }
+
+    #[test]
+    fn filter_messages_test() {
+        let block: Block = deserialize(&Vec::<u8>::from_hex("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914c364243a74762685f916378ce87c5384ad39b594aca206426d9d244ef51d644d2d74d6e4900000201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap()).unwrap();
+        let expected = BlockFilter::new_script_filter(&block, script_for_coin).unwrap();
+
+        let cfilter = CFilter::from_block(&block, script_for_coin).unwrap();
+        assert_eq!(cfilter.filter_type, BASIC_FILTER_TYPE);
+        assert_eq!(cfilter.block_hash, block.bitcoin_hash());
+        assert_eq!(cfilter.filter, expected.content);
+        let missing = CFilter::from_block(&block, |o| Err(bip158::Error::UtxoMissing(*o)));
+        assert!(missing.is_err());
+        let spent = script_for_coin(&OutPoint::default()).unwrap();
+        let unknown = Script::from(vec![0x52]);
+        assert!(cfilter.match_any_script(&[unknown.clone(), spent.clone()]).unwrap());
+        assert!(!cfilter.match_any_script(&[unknown.clone()]).unwrap());
+        assert!(cfilter.match_any_script(Vec::<Script>::new().iter()).unwrap());
+        assert!(cfilter.match_all_scripts(Vec::<Script>::new().iter()).unwrap());
+        assert!(cfilter.match_all_scripts(&[spent.clone(), block.txdata[0].output[0].script_pubkey.clone()]).unwrap());
+        assert!(!cfilter.match_all_scripts(&[spent, unknown]).unwrap());
+
+        let blocks = vec![block.clone(), block.clone()];
+        let previous = FilterHash::default();
+        let cfheaders = CFHeaders::from_blocks(&blocks, previous, script_for_coin).unwrap();
+        assert_eq!(cfheaders.stop_hash, block.bitcoin_hash());
+        assert_eq!(cfheaders.filter_hashes.len(), 2);
+        let first = expected.filter_id(&previous);
+        assert_eq!(cfheaders.filter_headers(), vec![first, expected.filter_id(&first)]);
+
+        let blocks = vec![block.clone(); CHECKPOINT_INTERVAL + 1];
+        let cfcheckpt = CFCheckpt::from_blocks(&blocks, script_for_coin).unwrap();
+        let cfheaders = CFHeaders::from_blocks(&blocks, previous, script_for_coin).unwrap();
+        assert_eq!(cfcheckpt.filter_headers, vec![cfheaders.filter_headers()[CHECKPOINT_INTERVAL]]);
+    }
+}
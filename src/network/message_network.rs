@@ -20,13 +20,18 @@
 
 use std::io;
 use std::borrow::Cow;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use network::address::Address;
 use network::constants::{self, ServiceFlags};
+use network::random_nonce;
 use consensus::{Encodable, Decodable, ReadExt};
 use consensus::encode;
 use network::message::CommandString;
 use hashes::sha256d;
+#[cfg(feature = "arbitrary")] use hashes::Hash;
+use hash_types::{BlockHash, MalFixTxid};
 
 /// Some simple messages
 
@@ -78,43 +83,169 @@ impl VersionMessage {
             relay: false,
         }
     }
+
+    /// Returns a builder for a `version` message. Unless overridden, the
+    /// message uses the current protocol version and time, a random nonce,
+    /// no services, unspecified addresses, a start height of 0, `relay` set
+    /// to false and a BIP14 user agent naming this library.
+    pub fn builder() -> VersionMessageBuilder {
+        VersionMessageBuilder::new()
+    }
+}
+
+/// Builder for `version` messages, see `VersionMessage::builder`
+#[derive(Clone, Debug)]
+pub struct VersionMessageBuilder {
+    msg: VersionMessage,
+}
+
+impl VersionMessageBuilder {
+    fn new() -> VersionMessageBuilder {
+        let unspecified = Address::new(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)), ServiceFlags::NONE);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        VersionMessageBuilder {
+            msg: VersionMessage {
+                version: constants::PROTOCOL_VERSION,
+                services: ServiceFlags::from(constants::SERVICES),
+                timestamp: timestamp,
+                receiver: unspecified.clone(),
+                sender: unspecified,
+                nonce: random_nonce(),
+                user_agent: concat!("/tapyrus-rust:", env!("CARGO_PKG_VERSION"), "/").to_owned(),
+                start_height: 0,
+                relay: false,
+            },
+        }
+    }
+
+    /// Set the protocol version
+    pub fn version(mut self, version: u32) -> VersionMessageBuilder {
+        self.msg.version = version;
+        self
+    }
+
+    /// Set the services supported by this node
+    pub fn services(mut self, services: ServiceFlags) -> VersionMessageBuilder {
+        self.msg.services = services;
+        self
+    }
+
+    /// Set the time at which the message is sent, in seconds since the epoch
+    pub fn timestamp(mut self, timestamp: i64) -> VersionMessageBuilder {
+        self.msg.timestamp = timestamp;
+        self
+    }
+
+    /// Set the address of the peer receiving the message
+    pub fn receiver(mut self, receiver: Address) -> VersionMessageBuilder {
+        self.msg.receiver = receiver;
+        self
+    }
+
+    /// Set the address of this node
+    pub fn sender(mut self, sender: Address) -> VersionMessageBuilder {
+        self.msg.sender = sender;
+        self
+    }
+
+    /// Set the nonce used to detect connections to self
+    pub fn nonce(mut self, nonce: u64) -> VersionMessageBuilder {
+        self.msg.nonce = nonce;
+        self
+    }
+
+    /// Replace the whole user agent string
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> VersionMessageBuilder {
+        self.msg.user_agent = user_agent.into();
+        self
+    }
+
+    /// Append a `name:version` component to the user agent as described in
+    /// BIP14, e.g. `/tapyrus-rust:0.3.0/MyWallet:1.2/`
+    pub fn user_agent_component(mut self, name: &str, version: &str) -> VersionMessageBuilder {
+        if !self.msg.user_agent.ends_with('/') {
+            self.msg.user_agent.push('/');
+        }
+        self.msg.user_agent.push_str(&format!("{}:{}/", name, version));
+        self
+    }
+
+    /// Set the height of the best chain known by this node
+    pub fn start_height(mut self, start_height: i32) -> VersionMessageBuilder {
+        self.msg.start_height = start_height;
+        self
+    }
+
+    /// Set whether the peer should relay transactions before a filter is loaded
+    pub fn relay(mut self, relay: bool) -> VersionMessageBuilder {
+        self.msg.relay = relay;
+        self
+    }
+
+    /// Build the `version` message
+    pub fn build(self) -> VersionMessage {
+        self.msg
+    }
 }
 
 impl_consensus_encoding!(VersionMessage, version, services, timestamp,
                          receiver, sender, nonce,
                          user_agent, start_height, relay);
+serde_struct_impl!(VersionMessage, version, services, timestamp,
+                   receiver, sender, nonce,
+                   user_agent, start_height, relay);
+arbitrary_struct_impl!(VersionMessage, version, services, timestamp,
+                       receiver, sender, nonce,
+                       user_agent, start_height, relay);
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 /// message rejection reason as a code
 pub enum RejectReason {
     /// malformed message
-    Malformed = 0x01,
+    Malformed,
     /// invalid message
-    Invalid = 0x10,
+    Invalid,
     /// obsolete message
-    Obsolete = 0x11,
+    Obsolete,
     /// duplicate message
-    Duplicate = 0x12,
+    Duplicate,
     /// nonstandard transaction
-    NonStandard = 0x40,
+    NonStandard,
     /// an output is below dust limit
-    Dust = 0x41,
+    Dust,
     /// insufficient fee
-    Fee = 0x42,
+    Fee,
     /// checkpoint
-    Checkpoint = 0x43
+    Checkpoint,
+    /// any code not defined above, as sent by peers using nonstandard codes
+    Unknown(u8),
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
-impl Encodable for RejectReason {
-    fn consensus_encode<W: io::Write>(&self, mut e: W) -> Result<usize, encode::Error> {
-        e.write_all(&[*self as u8])?;
-        Ok(1)
+impl RejectReason {
+    /// Return the code of this reason on the wire
+    pub fn code(&self) -> u8 {
+        match *self {
+            RejectReason::Malformed => 0x01,
+            RejectReason::Invalid => 0x10,
+            RejectReason::Obsolete => 0x11,
+            RejectReason::Duplicate => 0x12,
+            RejectReason::NonStandard => 0x40,
+            RejectReason::Dust => 0x41,
+            RejectReason::Fee => 0x42,
+            RejectReason::Checkpoint => 0x43,
+            RejectReason::Unknown(code) => code,
+            RejectReason::__Nonexhaustive => 0x00,
+        }
     }
-}
 
-impl Decodable for RejectReason {
-    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
-        Ok(match d.read_u8()? {
+    /// Return the reason for the given code
+    pub fn from_code(code: u8) -> RejectReason {
+        match code {
             0x01 => RejectReason::Malformed,
             0x10 => RejectReason::Invalid,
             0x11 => RejectReason::Obsolete,
@@ -123,8 +254,42 @@ impl Decodable for RejectReason {
             0x41 => RejectReason::Dust,
             0x42 => RejectReason::Fee,
             0x43 => RejectReason::Checkpoint,
-            _ => return Err(encode::Error::ParseFailed("unknown reject code"))
-        })
+            code => RejectReason::Unknown(code),
+        }
+    }
+}
+
+impl Encodable for RejectReason {
+    fn consensus_encode<W: io::Write>(&self, mut e: W) -> Result<usize, encode::Error> {
+        e.write_all(&[self.code()])?;
+        Ok(1)
+    }
+}
+
+impl Decodable for RejectReason {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        Ok(RejectReason::from_code(d.read_u8()?))
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for RejectReason {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<RejectReason> {
+        Ok(RejectReason::from_code(u.arbitrary()?))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for RejectReason {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u8(self.code())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for RejectReason {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<RejectReason, D::Error> {
+        ::serde::Deserialize::deserialize(d).map(RejectReason::from_code)
     }
 }
 
@@ -142,16 +307,130 @@ pub struct Reject {
 }
 
 impl_consensus_encoding!(Reject, message, ccode, reason, hash);
+serde_struct_impl!(Reject, message, ccode, reason, hash);
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for Reject {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<Reject> {
+        Ok(Reject {
+            message: u.arbitrary()?,
+            ccode: u.arbitrary()?,
+            reason: Cow::Owned(u.arbitrary()?),
+            hash: sha256d::Hash::from_inner(u.arbitrary()?),
+        })
+    }
+}
+
+impl Reject {
+    /// Reject a malformed message with the given command
+    pub fn malformed<C, R>(message: C, reason: R) -> Reject
+        where C: Into<CommandString>, R: Into<Cow<'static, str>>
+    {
+        Reject {
+            message: message.into(),
+            ccode: RejectReason::Malformed,
+            reason: reason.into(),
+            hash: sha256d::Hash::default(),
+        }
+    }
+
+    /// Reject an invalid transaction
+    pub fn invalid_tx<R: Into<Cow<'static, str>>>(txid: MalFixTxid, reason: R) -> Reject {
+        Reject::tx(txid, RejectReason::Invalid, reason)
+    }
+
+    /// Reject a transaction which is valid but not standard
+    pub fn nonstandard_tx<R: Into<Cow<'static, str>>>(txid: MalFixTxid, reason: R) -> Reject {
+        Reject::tx(txid, RejectReason::NonStandard, reason)
+    }
+
+    /// Reject a transaction paying an insufficient fee
+    pub fn insufficient_fee<R: Into<Cow<'static, str>>>(txid: MalFixTxid, reason: R) -> Reject {
+        Reject::tx(txid, RejectReason::Fee, reason)
+    }
+
+    /// Reject a transaction for the given reason
+    pub fn tx<R: Into<Cow<'static, str>>>(txid: MalFixTxid, ccode: RejectReason, reason: R) -> Reject {
+        Reject {
+            message: "tx".into(),
+            ccode: ccode,
+            reason: reason.into(),
+            hash: txid.as_hash(),
+        }
+    }
+
+    /// Reject an invalid block
+    pub fn invalid_block<R: Into<Cow<'static, str>>>(block_hash: BlockHash, reason: R) -> Reject {
+        Reject {
+            message: "block".into(),
+            ccode: RejectReason::Invalid,
+            reason: reason.into(),
+            hash: block_hash.as_hash(),
+        }
+    }
+
+    /// Reject the `version` message of a peer using an obsolete protocol
+    pub fn obsolete_version() -> Reject {
+        Reject {
+            message: "version".into(),
+            ccode: RejectReason::Obsolete,
+            reason: "Obsolete version".into(),
+            hash: sha256d::Hash::default(),
+        }
+    }
+
+    /// Reject a second `version` message from a peer
+    pub fn duplicate_version() -> Reject {
+        Reject {
+            message: "version".into(),
+            ccode: RejectReason::Duplicate,
+            reason: "Duplicate version message".into(),
+            hash: sha256d::Hash::default(),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use super::VersionMessage;
+    use super::{Reject, RejectReason, VersionMessage};
 
+    use hashes::Hash;
     use hex::decode as hex_decode;
-    use network::constants::ServiceFlags;
+    use hash_types::MalFixTxid;
+    use network::address::Address;
+    use network::constants::{self, ServiceFlags};
 
     use consensus::encode::{deserialize, serialize};
 
+    #[test]
+    fn version_message_builder_test() {
+        let msg = VersionMessage::builder().build();
+        assert_eq!(msg.version, constants::PROTOCOL_VERSION);
+        assert_eq!(msg.services, ServiceFlags::NONE);
+        assert!(msg.timestamp > 0);
+        assert_eq!(msg.user_agent, format!("/tapyrus-rust:{}/", env!("CARGO_PKG_VERSION")));
+        assert_eq!(msg.start_height, 0);
+        assert!(!msg.relay);
+
+        let sender = Address::new(&"127.0.0.1:2357".parse().unwrap(), ServiceFlags::NETWORK);
+        let msg = VersionMessage::builder()
+            .services(ServiceFlags::NETWORK)
+            .sender(sender.clone())
+            .nonce(42)
+            .start_height(1000)
+            .relay(true)
+            .user_agent("/tapyrus:0.4.0/")
+            .user_agent_component("MyWallet", "1.2")
+            .build();
+        assert_eq!(msg.services, ServiceFlags::NETWORK);
+        assert_eq!(msg.sender, sender);
+        assert_eq!(msg.nonce, 42);
+        assert_eq!(msg.start_height, 1000);
+        assert!(msg.relay);
+        assert_eq!(msg.user_agent, "/tapyrus:0.4.0/MyWallet:1.2/");
+        assert_eq!(deserialize::<VersionMessage>(&serialize(&msg)).unwrap(), msg);
+    }
+
     #[test]
     fn version_message_test() {
         // This message is from my satoshi node, morning of May 27 2014
@@ -171,4 +450,27 @@ mod tests {
 
         assert_eq!(serialize(&real_decode), from_sat);
     }
+
+    #[test]
+    fn reject_message_test() {
+        let txid = MalFixTxid::from_slice(&[7u8; 32]).unwrap();
+        let reject = Reject::invalid_tx(txid, "bad-txns-inputs-missingorspent");
+        assert_eq!(reject.message, "tx".into());
+        assert_eq!(reject.ccode, RejectReason::Invalid);
+        assert_eq!(reject.hash, txid.as_hash());
+        assert_eq!(deserialize::<Reject>(&serialize(&reject)).unwrap(), reject);
+
+        let reject = Reject::obsolete_version();
+        assert_eq!(reject.message, "version".into());
+        assert_eq!(reject.ccode, RejectReason::Obsolete);
+        assert_eq!(serialize(&reject)[12], 0x11);
+
+        // a nonstandard code
+        let mut data = hex_decode("747800000000000000000000990178").unwrap();
+        data.extend_from_slice(&[0u8; 32]);
+        let reject: Reject = deserialize(&data).unwrap();
+        assert_eq!(reject.ccode, RejectReason::Unknown(0x99));
+        assert_eq!(reject.ccode.code(), 0x99);
+        assert_eq!(serialize(&reject), data);
+    }
 }
@@ -15,24 +15,37 @@
 //! Network Support
 //!
 //! This module defines support for (de)serialization and network transport
-//! of Bitcoin data and network messages.
+//! of Bitcoin data and network messages. Without the `std` feature, only
+//! the network constants are built.
 //!
 
-use std::error;
-use std::fmt;
-use std::io;
+#[cfg(feature = "std")] use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")] use std::error;
+#[cfg(feature = "std")] use std::fmt;
+#[cfg(feature = "std")] use std::hash::{BuildHasher, Hasher};
+#[cfg(feature = "std")] use std::io;
+#[cfg(feature = "std")] use std::time::{Duration, Instant};
 
 pub mod constants;
 
-pub mod address;
-pub use self::address::Address;
-pub mod message;
-pub mod message_blockdata;
-pub mod message_filter;
-pub mod message_network;
-pub mod stream_reader;
+#[cfg(feature = "std")] pub mod address;
+#[cfg(feature = "std")] pub use self::address::Address;
+#[cfg(feature = "std")] pub mod features;
+#[cfg(feature = "std")] pub mod message;
+#[cfg(feature = "std")] pub mod message_blockdata;
+#[cfg(feature = "std")] pub mod message_compact;
+#[cfg(feature = "std")] pub mod message_filter;
+#[cfg(feature = "std")] pub mod message_network;
+#[cfg(feature = "std")] pub mod ping;
+#[cfg(feature = "std")] pub mod socks;
+#[cfg(feature = "std")] pub mod stream_reader;
+#[cfg(feature = "v2transport")]
+pub mod v2transport;
+#[cfg(feature = "tokio")]
+pub mod codec;
 
 /// Network error
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum Error {
     /// And I/O error
@@ -43,6 +56,7 @@ pub enum Error {
     SocketNotConnectedToPeer,
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -54,6 +68,7 @@ impl fmt::Display for Error {
     }
 }
 
+#[cfg(feature = "std")]
 #[doc(hidden)]
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
@@ -61,6 +76,7 @@ impl From<io::Error> for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
@@ -77,3 +93,27 @@ impl error::Error for Error {
         }
     }
 }
+
+/// Generate a random non-zero nonce, as carried by `version` and `ping`
+/// messages. The randomly keyed hasher of the standard library provides
+/// the randomness, so that `rand` is not needed.
+#[cfg(feature = "std")]
+pub(crate) fn random_nonce() -> u64 {
+    loop {
+        let nonce = RandomState::new().build_hasher().finish();
+        if nonce != 0 {
+            return nonce;
+        }
+    }
+}
+
+/// The time elapsed from `earlier` to `now`, zero if `earlier` is later,
+/// where `Instant::duration_since` would panic
+#[cfg(feature = "std")]
+pub(crate) fn elapsed(earlier: Instant, now: Instant) -> Duration {
+    if now > earlier {
+        now - earlier
+    } else {
+        Duration::from_secs(0)
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Ping/pong tracking
+//!
+//! This module defines `PingState`, which keeps track of the `ping` sent to
+//! a peer, matches the `pong` answering it and measures the round-trip
+//! time. A peer which does not answer within the configured timeout is
+//! reported as unresponsive.
+//!
+//! Methods taking an `Instant` exist so that the state can be driven by an
+//! external clock; the others use `Instant::now()`.
+//!
+
+use std::time::{Duration, Instant};
+
+use network::message::NetworkMessage;
+use network::{elapsed, random_nonce};
+
+/// Liveness and latency state of a single peer
+#[derive(Clone, Debug)]
+pub struct PingState {
+    timeout: Duration,
+    /// Nonce and send time of the unanswered ping
+    pending: Option<(u64, Instant)>,
+    latency: Option<Duration>,
+    min_latency: Option<Duration>,
+}
+
+impl PingState {
+    /// Create a state flagging peers which take longer than `timeout` to
+    /// answer a ping
+    pub fn new(timeout: Duration) -> PingState {
+        PingState {
+            timeout: timeout,
+            pending: None,
+            latency: None,
+            min_latency: None,
+        }
+    }
+
+    /// Return a `ping` message with a fresh nonce, or `None` if the previous
+    /// ping has not been answered yet
+    pub fn ping(&mut self) -> Option<NetworkMessage> {
+        self.ping_at(Instant::now())
+    }
+
+    /// Same as `ping`, recording the ping as sent at `now`
+    pub fn ping_at(&mut self, now: Instant) -> Option<NetworkMessage> {
+        if self.pending.is_some() {
+            return None;
+        }
+        let nonce = random_nonce();
+        self.pending = Some((nonce, now));
+        Some(NetworkMessage::Ping(nonce))
+    }
+
+    /// Handle the nonce of a `pong` message received from the peer. Returns
+    /// whether it answered the pending ping; unexpected pongs are ignored.
+    pub fn handle_pong(&mut self, nonce: u64) -> bool {
+        self.handle_pong_at(nonce, Instant::now())
+    }
+
+    /// Same as `handle_pong`, recording the pong as received at `now`
+    pub fn handle_pong_at(&mut self, nonce: u64, now: Instant) -> bool {
+        match self.pending {
+            Some((expected, sent)) if expected == nonce => {
+                let latency = elapsed(sent, now);
+                self.pending = None;
+                self.latency = Some(latency);
+                self.min_latency = Some(match self.min_latency {
+                    Some(min) if min < latency => min,
+                    _ => latency,
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a ping is waiting for its pong
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Whether the pending ping has been waiting for longer than the timeout
+    pub fn is_unresponsive(&self) -> bool {
+        self.is_unresponsive_at(Instant::now())
+    }
+
+    /// Same as `is_unresponsive`, at the given time
+    pub fn is_unresponsive_at(&self, now: Instant) -> bool {
+        match self.pending {
+            Some((_, sent)) => elapsed(sent, now) > self.timeout,
+            None => false,
+        }
+    }
+
+    /// Round-trip time of the last answered ping
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Lowest round-trip time seen so far
+    pub fn min_latency(&self) -> Option<Duration> {
+        self.min_latency
+    }
+}
+
+/// Return the `pong` answering a `ping` message
+pub fn pong_for(msg: &NetworkMessage) -> Option<NetworkMessage> {
+    match *msg {
+        NetworkMessage::Ping(nonce) => Some(NetworkMessage::Pong(nonce)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{pong_for, PingState};
+    use network::message::NetworkMessage;
+
+    #[test]
+    fn ping_pong_test() {
+        let start = Instant::now();
+        let mut state = PingState::new(Duration::from_secs(20));
+        assert!(!state.is_pending());
+        assert_eq!(state.latency(), None);
+
+        let nonce = match state.ping_at(start) {
+            Some(NetworkMessage::Ping(nonce)) => nonce,
+            x => panic!("unexpected message: {:?}", x),
+        };
+        assert!(state.is_pending());
+        // only one ping may be in flight
+        assert!(state.ping_at(start).is_none());
+
+        assert!(!state.handle_pong_at(nonce.wrapping_add(1), start + Duration::from_millis(50)));
+        assert!(state.handle_pong_at(nonce, start + Duration::from_millis(300)));
+        assert!(!state.is_pending());
+        assert_eq!(state.latency(), Some(Duration::from_millis(300)));
+        // answered pings are not matched twice
+        assert!(!state.handle_pong_at(nonce, start + Duration::from_millis(400)));
+
+        let start = start + Duration::from_secs(1);
+        let nonce = match state.ping_at(start) {
+            Some(NetworkMessage::Ping(nonce)) => nonce,
+            x => panic!("unexpected message: {:?}", x),
+        };
+        assert!(state.handle_pong_at(nonce, start + Duration::from_millis(500)));
+        assert_eq!(state.latency(), Some(Duration::from_millis(500)));
+        assert_eq!(state.min_latency(), Some(Duration::from_millis(300)));
+    }
+
+    #[test]
+    fn unresponsive_test() {
+        let start = Instant::now();
+        let mut state = PingState::new(Duration::from_secs(20));
+        assert!(!state.is_unresponsive_at(start + Duration::from_secs(60)));
+
+        state.ping_at(start).unwrap();
+        assert!(!state.is_unresponsive_at(start + Duration::from_secs(20)));
+        assert!(state.is_unresponsive_at(start + Duration::from_secs(21)));
+
+        // an external clock going backwards does not panic
+        let later = start + Duration::from_secs(60);
+        let mut state = PingState::new(Duration::from_secs(20));
+        let nonce = match state.ping_at(later) {
+            Some(NetworkMessage::Ping(nonce)) => nonce,
+            x => panic!("unexpected message: {:?}", x),
+        };
+        assert!(!state.is_unresponsive_at(start));
+        assert!(state.handle_pong_at(nonce, start));
+        assert_eq!(state.latency(), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn pong_for_test() {
+        assert_eq!(pong_for(&NetworkMessage::Ping(42)), Some(NetworkMessage::Pong(42)));
+        assert_eq!(pong_for(&NetworkMessage::Pong(42)), None);
+    }
+}
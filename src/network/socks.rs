@@ -0,0 +1,503 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! SOCKS5 proxy support
+//!
+//! This module defines `Dialer`, which opens outbound P2P connections
+//! through a SOCKS5 proxy such as Tor, as described in RFC 1928. Onion and
+//! I2P targets from `AddrV2` are handed to the proxy as hostnames so that
+//! they are resolved on the proxy side. The returned `TcpStream` can be
+//! wrapped in a `StreamReader` or converted for use with the tokio codec.
+//!
+
+use std::{error, fmt, io};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use network::address::{AddrV2, AddrV2Message};
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NO_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// SOCKS5 error
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error
+    Io(io::Error),
+    /// The proxy sent a reply which does not follow the protocol
+    InvalidReply,
+    /// The proxy does not accept any of the offered authentication methods
+    NoAcceptableAuthMethod,
+    /// The proxy rejected the username and password
+    AuthenticationFailed,
+    /// The username or password is longer than 255 bytes
+    CredentialsTooLong,
+    /// The proxy could not connect to the target, with the given reply code
+    Rejected(u8),
+    /// The target address cannot be reached through a SOCKS5 proxy
+    UnsupportedAddress,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => fmt::Display::fmt(e, f),
+            Error::Rejected(code) => write!(f, "proxy rejected the connection: {}", reply_message(code)),
+            Error::InvalidReply
+            | Error::NoAcceptableAuthMethod
+            | Error::AuthenticationFailed
+            | Error::CredentialsTooLong
+            | Error::UnsupportedAddress => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(ref e) => e.description(),
+            Error::InvalidReply => "invalid reply from proxy",
+            Error::NoAcceptableAuthMethod => "no acceptable authentication method",
+            Error::AuthenticationFailed => "proxy authentication failed",
+            Error::CredentialsTooLong => "proxy username or password too long",
+            Error::Rejected(_) => "proxy rejected the connection",
+            Error::UnsupportedAddress => "address not supported by SOCKS5",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Describe a SOCKS5 reply code
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// Opens connections to peers through a SOCKS5 proxy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dialer {
+    proxy: SocketAddr,
+    credentials: Option<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl Dialer {
+    /// Create a dialer using the proxy listening on `proxy`
+    pub fn new(proxy: SocketAddr) -> Dialer {
+        Dialer {
+            proxy: proxy,
+            credentials: None,
+            timeout: None,
+        }
+    }
+
+    /// Authenticate with the proxy using a username and password. Tor uses
+    /// distinct credentials to isolate the circuits of different streams.
+    pub fn with_credentials<U, P>(mut self, username: U, password: P) -> Dialer
+        where U: Into<String>, P: Into<String>
+    {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Give up connecting to the proxy and completing the handshake after
+    /// `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Dialer {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Connect to `target` on `port` through the proxy
+    pub fn connect(&self, target: &AddrV2, port: u16) -> Result<TcpStream, Error> {
+        let stream = match self.timeout {
+            Some(timeout) => TcpStream::connect_timeout(&self.proxy, timeout)?,
+            None => TcpStream::connect(&self.proxy)?,
+        };
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        let credentials = self.credentials.as_ref().map(|&(ref u, ref p)| (&u[..], &p[..]));
+        handshake(&stream, target, port, credentials)?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        Ok(stream)
+    }
+
+    /// Connect to the peer advertised in an `addrv2` entry
+    pub fn connect_addr(&self, addr: &AddrV2Message) -> Result<TcpStream, Error> {
+        self.connect(&addr.addr, addr.port)
+    }
+}
+
+/// Perform the SOCKS5 handshake asking the proxy to connect to `target`
+fn handshake<S: Read + Write>(
+    mut stream: S,
+    target: &AddrV2,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<(), Error> {
+    // Build the request first so that unsupported targets fail before
+    // anything is sent.
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match *target {
+        AddrV2::Ipv4(ref addr) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.octets());
+        }
+        AddrV2::Ipv6(ref addr) | AddrV2::Cjdns(ref addr) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.octets());
+        }
+        AddrV2::TorV2(ref id) => push_hostname(&mut request, &format!("{}.onion", base32_encode(id))),
+        AddrV2::TorV3(ref pubkey) => push_hostname(&mut request, &onion_v3_hostname(pubkey)),
+        AddrV2::I2p(ref hash) => push_hostname(&mut request, &format!("{}.b32.i2p", base32_encode(hash))),
+        AddrV2::Unknown(..) => return Err(Error::UnsupportedAddress),
+    }
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+
+    // method selection
+    let method = match credentials {
+        Some(_) => METHOD_USERNAME_PASSWORD,
+        None => METHOD_NO_AUTH,
+    };
+    stream.write_all(&[SOCKS_VERSION, 0x01, method])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::InvalidReply);
+    }
+    if reply[1] == METHOD_NO_ACCEPTABLE {
+        return Err(Error::NoAcceptableAuthMethod);
+    }
+    if reply[1] != method {
+        return Err(Error::InvalidReply);
+    }
+
+    // RFC 1929 username/password authentication
+    if let Some((username, password)) = credentials {
+        if username.len() > 255 || password.len() > 255 {
+            return Err(Error::CredentialsTooLong);
+        }
+        let mut auth = vec![AUTH_VERSION, username.len() as u8];
+        auth.extend_from_slice(username.as_bytes());
+        auth.push(password.len() as u8);
+        auth.extend_from_slice(password.as_bytes());
+        stream.write_all(&auth)?;
+        stream.read_exact(&mut reply)?;
+        if reply[0] != AUTH_VERSION {
+            return Err(Error::InvalidReply);
+        }
+        if reply[1] != 0x00 {
+            return Err(Error::AuthenticationFailed);
+        }
+    }
+
+    stream.write_all(&request)?;
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::InvalidReply);
+    }
+    if reply[1] != 0x00 {
+        return Err(Error::Rejected(reply[1]));
+    }
+    // skip the bound address and port
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(Error::InvalidReply),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(())
+}
+
+/// Append a hostname target to a connect request
+fn push_hostname(request: &mut Vec<u8>, hostname: &str) {
+    request.push(ATYP_DOMAIN);
+    request.push(hostname.len() as u8);
+    request.extend_from_slice(hostname.as_bytes());
+}
+
+/// Return the `.onion` hostname of a Tor v3 hidden service
+fn onion_v3_hostname(pubkey: &[u8; 32]) -> String {
+    const VERSION: u8 = 0x03;
+
+    let mut preimage = b".onion checksum".to_vec();
+    preimage.extend_from_slice(pubkey);
+    preimage.push(VERSION);
+    let checksum = sha3_256(&preimage);
+
+    let mut data = pubkey.to_vec();
+    data.extend_from_slice(&checksum[..2]);
+    data.push(VERSION);
+    format!("{}.onion", base32_encode(&data))
+}
+
+/// Lowercase RFC 4648 base32 without padding
+fn base32_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    let mut ret = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            ret.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        ret.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    ret
+}
+
+const KECCAK_ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001, 0x0000000000008082, 0x800000000000808a, 0x8000000080008000,
+    0x000000000000808b, 0x0000000080000001, 0x8000000080008081, 0x8000000000008009,
+    0x000000000000008a, 0x0000000000000088, 0x0000000080008009, 0x000000008000000a,
+    0x000000008000808b, 0x800000000000008b, 0x8000000000008089, 0x8000000000008003,
+    0x8000000000008002, 0x8000000000000080, 0x000000000000800a, 0x800000008000000a,
+    0x8000000080008081, 0x8000000000008080, 0x0000000080000001, 0x8000000080008008,
+];
+const KECCAK_RHO: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+const KECCAK_PI: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// The Keccak-f[1600] permutation
+fn keccak_f(state: &mut [u64; 25]) {
+    for rc in KECCAK_ROUND_CONSTANTS.iter() {
+        // theta
+        let mut parity = [0u64; 5];
+        for x in 0..5 {
+            parity[x] = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+        }
+        for x in 0..5 {
+            let t = parity[(x + 4) % 5] ^ parity[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[5 * y + x] ^= t;
+            }
+        }
+        // rho and pi
+        let mut last = state[1];
+        for i in 0..24 {
+            let j = KECCAK_PI[i];
+            let tmp = state[j];
+            state[j] = last.rotate_left(KECCAK_RHO[i]);
+            last = tmp;
+        }
+        // chi
+        for y in 0..5 {
+            let mut row = [0u64; 5];
+            row.copy_from_slice(&state[5 * y..5 * y + 5]);
+            for x in 0..5 {
+                state[5 * y + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+        // iota
+        state[0] ^= *rc;
+    }
+}
+
+/// SHA3-256, only needed to compute Tor v3 address checksums
+fn sha3_256(data: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+
+    let mut padded = data.to_vec();
+    padded.push(0x06);
+    while padded.len() % RATE != 0 {
+        padded.push(0x00);
+    }
+    *padded.last_mut().unwrap() |= 0x80;
+
+    let mut state = [0u64; 25];
+    for block in padded.chunks(RATE) {
+        for (lane, word) in state.iter_mut().zip(block.chunks(8)) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(word);
+            *lane ^= u64::from_le_bytes(bytes);
+        }
+        keccak_f(&mut state);
+    }
+
+    let mut ret = [0u8; 32];
+    for (chunk, lane) in ret.chunks_mut(8).zip(state.iter()) {
+        chunk.copy_from_slice(&lane.to_le_bytes());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::Ipv4Addr;
+
+    use hex::decode as hex_decode;
+
+    use super::{base32_encode, handshake, onion_v3_hostname, sha3_256, Error};
+    use network::address::AddrV2;
+
+    /// A stream replaying canned proxy replies and recording what is sent
+    struct MockProxy {
+        replies: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl MockProxy {
+        fn new(replies: &[u8]) -> MockProxy {
+            MockProxy { replies: Cursor::new(replies.to_vec()), sent: vec![] }
+        }
+    }
+
+    impl Read for MockProxy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for MockProxy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sha3_256_test() {
+        // FIPS 202 example values
+        assert_eq!(
+            sha3_256(b"").to_vec(),
+            hex_decode("a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a").unwrap()
+        );
+        assert_eq!(
+            sha3_256(b"abc").to_vec(),
+            hex_decode("3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532").unwrap()
+        );
+        assert_eq!(
+            sha3_256(&[0xa3; 200]).to_vec(),
+            hex_decode("79f38adec5c20307a98ef76e8324afbfd46cfd81b22e3973c65fa1bd9de31787").unwrap()
+        );
+        // around the rate, where the padding takes one byte or a whole block
+        assert_eq!(
+            sha3_256(&[b'a'; 135]).to_vec(),
+            hex_decode("8094bb53c44cfb1e67b7c30447f9a1c33696d2463ecc1d9c92538913392843c9").unwrap()
+        );
+        assert_eq!(
+            sha3_256(&[b'a'; 136]).to_vec(),
+            hex_decode("3fc5559f14db8e453a0a3091edbd2bc25e11528d81c66fa570a4efdcc2695ee1").unwrap()
+        );
+    }
+
+    #[test]
+    fn base32_test() {
+        // RFC 4648 test vectors, lowercase and unpadded
+        let vectors = [
+            ("", ""), ("f", "my"), ("fo", "mzxq"), ("foo", "mzxw6"),
+            ("foob", "mzxw6yq"), ("fooba", "mzxw6ytb"), ("foobar", "mzxw6ytboi"),
+        ];
+        for &(data, encoded) in vectors.iter() {
+            assert_eq!(base32_encode(data.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn onion_hostname_test() {
+        // the address of Tor's test_build_address (src/test/test_hs_common.c)
+        let mut pubkey = [0u8; 32];
+        pubkey.copy_from_slice(&hex_decode("79bcc625184b05194975c28b66b66b0469f7f6556fb1ac3189a79b40dda32f1f").unwrap());
+        assert_eq!(onion_v3_hostname(&pubkey), "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion");
+
+        // a live hidden service
+        pubkey.copy_from_slice(&hex_decode("1d04a1d04a338c6e6ae970bfabee49049d6702250984ca950c01673f4ec034ad").unwrap());
+        assert_eq!(onion_v3_hostname(&pubkey), "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion");
+    }
+
+    #[test]
+    fn handshake_test() {
+        let ok = [0x05, 0x00, 0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        let mut proxy = MockProxy::new(&ok);
+        let target = AddrV2::TorV2([0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa]);
+        handshake(&mut proxy, &target, 2357, None).unwrap();
+        let mut expected = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 22];
+        expected.extend_from_slice(b"6hzph5hv6337r6p2.onion");
+        expected.extend_from_slice(&[0x09, 0x35]);
+        assert_eq!(proxy.sent, expected);
+
+        // with authentication
+        let replies = [0x05, 0x02, 0x01, 0x00, 0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        let mut proxy = MockProxy::new(&replies);
+        let target = AddrV2::Ipv4(Ipv4Addr::new(127, 0, 0, 1));
+        handshake(&mut proxy, &target, 2357, Some(("user", "pw"))).unwrap();
+        assert_eq!(proxy.sent, vec![
+            0x05, 0x01, 0x02,
+            0x01, 0x04, b'u', b's', b'e', b'r', 0x02, b'p', b'w',
+            0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0x09, 0x35,
+        ]);
+
+        // connection refused
+        let mut proxy = MockProxy::new(&[0x05, 0x00, 0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        match handshake(&mut proxy, &target, 2357, None) {
+            Err(Error::Rejected(0x05)) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // no acceptable authentication method
+        let mut proxy = MockProxy::new(&[0x05, 0xff]);
+        match handshake(&mut proxy, &target, 2357, None) {
+            Err(Error::NoAcceptableAuthMethod) => {},
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        let mut proxy = MockProxy::new(&[]);
+        match handshake(&mut proxy, &AddrV2::Unknown(42, vec![1, 2, 3]), 2357, None) {
+            Err(Error::UnsupportedAddress) => assert!(proxy.sent.is_empty()),
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+}
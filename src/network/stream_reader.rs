@@ -57,26 +57,64 @@ impl<R: Read> StreamReader<R> {
     /// also taking into account previously unparsed partial message (if there was such).
     pub fn read_next<D: Decodable>(&mut self) -> Result<D, encode::Error> {
         loop {
-            match encode::deserialize_partial::<D>(&self.unparsed) {
-                // In this case we just have an incomplete data, so we need to read more
-                Err(encode::Error::Io(ref err)) if err.kind () == io::ErrorKind::UnexpectedEof => {
-                    let count = self.stream.read(&mut self.data)?;
-                    if count > 0 {
-                        self.unparsed.extend(self.data[0..count].iter());
-                    }
-                    else {
-                        return Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
-                    }
-                },
-                Err(err) => return Err(err),
-                // We have successfully read from the buffer
-                Ok((message, index)) => {
-                    self.unparsed.drain(..index);
-                    return Ok(message)
-                },
+            if let Some(message) = self.try_next()? {
+                return Ok(message);
+            }
+            // In this case we just have an incomplete data, so we need to read more
+            let count = self.stream.read(&mut self.data)?;
+            if count > 0 {
+                self.unparsed.extend(self.data[0..count].iter());
+            }
+            else {
+                return Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
             }
         }
     }
+
+    /// Parses next message from the already buffered input without reading
+    /// the stream. Returns `Ok(None)` if the buffer only holds a partial message.
+    pub fn try_next<D: Decodable>(&mut self) -> Result<Option<D>, encode::Error> {
+        match encode::deserialize_partial::<D>(&self.unparsed) {
+            // In this case we just have an incomplete data
+            Err(encode::Error::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+            // We have successfully read from the buffer
+            Ok((message, index)) => {
+                self.unparsed.drain(..index);
+                Ok(Some(message))
+            },
+        }
+    }
+
+    /// Reads the stream at most once and parses next message from the buffered input.
+    /// Returns `Ok(None)` if more data is needed to complete the message, including
+    /// when a non-blocking stream has no data available yet.
+    pub fn try_read_next<D: Decodable>(&mut self) -> Result<Option<D>, encode::Error> {
+        if let Some(message) = self.try_next()? {
+            return Ok(Some(message));
+        }
+        match self.stream.read(&mut self.data) {
+            Ok(0) => Err(encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof))),
+            Ok(count) => {
+                self.unparsed.extend(self.data[0..count].iter());
+                self.try_next()
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock
+                || err.kind() == io::ErrorKind::Interrupted => Ok(None),
+            Err(err) => Err(encode::Error::Io(err)),
+        }
+    }
+
+    /// Appends data received by other means to the buffered input, so that
+    /// it is parsed by the next call to `try_next` or `read_next`.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.unparsed.extend_from_slice(data);
+    }
+
+    /// Returns the number of buffered bytes not parsed yet
+    pub fn unparsed_len(&self) -> usize {
+        self.unparsed.len()
+    }
 }
 
 #[cfg(test)]
@@ -233,6 +271,45 @@ mod test {
         }
     }
 
+    // Reader returning the given chunks one per read, then `WouldBlock`
+    // like a non-blocking socket with no pending data
+    struct ChunkedReader(Vec<Vec<u8>>);
+
+    impl io::Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn try_read_next_test() {
+        let mut second = MSG_PING[10..].to_vec();
+        second.extend(&MSG_VERACK[..5]);
+        let stream = ChunkedReader(vec![MSG_PING[..10].to_vec(), second]);
+        let mut reader = StreamReader::new(stream, None);
+
+        assert!(reader.try_read_next::<RawNetworkMessage>().unwrap().is_none());
+        assert_eq!(reader.unparsed_len(), 10);
+
+        let msg: RawNetworkMessage = reader.try_read_next().unwrap().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Ping(100));
+        assert_eq!(reader.unparsed_len(), 5);
+
+        // the stream has no more data for now
+        assert!(reader.try_read_next::<RawNetworkMessage>().unwrap().is_none());
+
+        reader.feed(&MSG_VERACK[5..]);
+        let msg: RawNetworkMessage = reader.try_next().unwrap().unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Verack);
+        assert_eq!(reader.unparsed_len(), 0);
+        assert!(reader.try_next::<RawNetworkMessage>().unwrap().is_none());
+    }
+
     // Helper function that set ups emulation of client-server TCP connection for
     // testing message transfer via TCP packets
     fn serve_tcp(pieces: Vec<Vec<u8>>) -> (JoinHandle<()>, BufReader<TcpStream>) {
@@ -0,0 +1,687 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Encrypted P2P transport
+//!
+//! This module implements an opt-in encrypted and authenticated transport
+//! modelled on BIP324. Both sides exchange ephemeral public keys, derive
+//! session keys with ECDH and HKDF-SHA256, and then frame every message as
+//! a ChaCha20-Poly1305 packet whose length is encrypted separately. Keys are
+//! rotated every 224 packets for forward secrecy.
+//!
+//! It differs from BIP324 in that public keys are sent as plain compressed
+//! points instead of ElligatorSwift encodings, the key derivation labels
+//! and salt are Tapyrus specific, and commands are always sent in full
+//! rather than as short message IDs.
+//!
+//! `Handshake` and `Session` perform the cryptography without any I/O,
+//! while `V2Stream` runs the whole protocol over a blocking stream. The
+//! ciphers are those of the `chacha20` and `chacha20poly1305` crates, and
+//! this module requires the `v2transport` feature.
+//!
+
+use std::{error, fmt, io};
+use std::io::{Read, Write};
+
+use chacha20::ChaCha20;
+use chacha20::cipher::{NewStreamCipher, SyncStreamCipher};
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use secp256k1::{self, Secp256k1, SecretKey, Signing, Verification};
+
+use consensus::encode::{self, Encodable, MAX_VEC_SIZE};
+use network::message::{CommandString, DecodeConfig, NetworkMessage};
+
+/// Number of packets after which the keys of a direction are rotated
+const REKEY_INTERVAL: u64 = 224;
+/// Length of a garbage terminator
+const GARBAGE_TERMINATOR_LEN: usize = 16;
+/// Maximum amount of garbage a peer may send before its terminator
+const MAX_GARBAGE_LEN: usize = 4095;
+/// Length of the encrypted packet length
+const LENGTH_LEN: usize = 3;
+/// Length of the packet header, holding the flags
+const HEADER_LEN: usize = 1;
+/// Length of the Poly1305 tag
+const TAG_LEN: usize = 16;
+/// Flag of decoy packets, which must be ignored by the receiver
+const IGNORE_BIT: u8 = 0x80;
+/// Length of the encoding of a message command in the packet contents
+const COMMAND_LEN: usize = 1 + 12;
+
+/// Encrypted transport error
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error
+    Io(io::Error),
+    /// A message could not be encoded or decoded
+    Encode(encode::Error),
+    /// The peer sent an invalid public key
+    InvalidPublicKey,
+    /// The peer's garbage terminator was not found
+    MissingGarbageTerminator,
+    /// A packet failed authentication
+    DecryptionFailed,
+    /// A packet exceeds the maximum allowed size, with the given size
+    PacketTooLarge(usize),
+    /// A packet carries a short message ID, which is not supported
+    UnsupportedShortId(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => fmt::Display::fmt(e, f),
+            Error::Encode(ref e) => fmt::Display::fmt(e, f),
+            Error::PacketTooLarge(len) => write!(f, "packet too large: {} bytes", len),
+            Error::UnsupportedShortId(id) => write!(f, "unsupported short message ID: {}", id),
+            Error::InvalidPublicKey
+            | Error::MissingGarbageTerminator
+            | Error::DecryptionFailed => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(err: encode::Error) -> Self {
+        Error::Encode(err)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(ref e) => e.description(),
+            Error::Encode(ref e) => e.description(),
+            Error::InvalidPublicKey => "invalid public key",
+            Error::MissingGarbageTerminator => "garbage terminator not found",
+            Error::DecryptionFailed => "packet authentication failed",
+            Error::PacketTooLarge(_) => "packet too large",
+            Error::UnsupportedShortId(_) => "unsupported short message ID",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Encode(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Side of the connection
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    /// The side which opened the connection
+    Initiator,
+    /// The side which accepted the connection
+    Responder,
+}
+
+/// First step of the key exchange, holding our ephemeral key
+pub struct Handshake {
+    role: Role,
+    magic: u32,
+    secret_key: SecretKey,
+    public_key: [u8; 33],
+}
+
+impl Handshake {
+    /// Start a handshake on the network with the given magic. `secret_key`
+    /// must be freshly generated for every connection.
+    pub fn new<C: Signing>(secp: &Secp256k1<C>, role: Role, magic: u32, secret_key: SecretKey) -> Handshake {
+        let public_key = secp256k1::PublicKey::from_secret_key(secp, &secret_key).serialize();
+        Handshake {
+            role: role,
+            magic: magic,
+            secret_key: secret_key,
+            public_key: public_key,
+        }
+    }
+
+    /// The public key to send to the peer
+    pub fn public_key(&self) -> [u8; 33] {
+        self.public_key
+    }
+
+    /// Derive the session keys from the public key received from the peer
+    pub fn complete<C: Verification>(self, secp: &Secp256k1<C>, peer_key: &[u8; 33]) -> Result<Session, Error> {
+        let mut point = secp256k1::PublicKey::from_slice(&peer_key[..])
+            .map_err(|_| Error::InvalidPublicKey)?;
+        point.mul_assign(secp, &self.secret_key[..]).map_err(|_| Error::InvalidPublicKey)?;
+
+        let (initiator_key, responder_key) = match self.role {
+            Role::Initiator => (&self.public_key, peer_key),
+            Role::Responder => (peer_key, &self.public_key),
+        };
+        let mut engine = tagged_engine(b"tapyrus_v2_xonly_ecdh");
+        engine.input(&initiator_key[..]);
+        engine.input(&responder_key[..]);
+        engine.input(&point.serialize()[1..]);
+        let ecdh_secret = sha256::Hash::from_engine(engine);
+
+        let mut salt = b"tapyrus_v2_shared_secret".to_vec();
+        self.magic.consensus_encode(&mut salt)?;
+        let mut engine = HmacEngine::<sha256::Hash>::new(&salt);
+        engine.input(&ecdh_secret[..]);
+        let prk = Hmac::<sha256::Hash>::from_engine(engine);
+
+        let initiator_length = hkdf_expand(&prk, b"initiator_L");
+        let initiator_packet = hkdf_expand(&prk, b"initiator_P");
+        let responder_length = hkdf_expand(&prk, b"responder_L");
+        let responder_packet = hkdf_expand(&prk, b"responder_P");
+        let garbage_terminators = hkdf_expand(&prk, b"garbage_terminators");
+        let session_id = hkdf_expand(&prk, b"session_id");
+
+        let mut initiator_terminator = [0u8; GARBAGE_TERMINATOR_LEN];
+        let mut responder_terminator = [0u8; GARBAGE_TERMINATOR_LEN];
+        initiator_terminator.copy_from_slice(&garbage_terminators[..GARBAGE_TERMINATOR_LEN]);
+        responder_terminator.copy_from_slice(&garbage_terminators[GARBAGE_TERMINATOR_LEN..]);
+
+        let initiator = Direction::new(initiator_length, initiator_packet, initiator_terminator);
+        let responder = Direction::new(responder_length, responder_packet, responder_terminator);
+        let (send, recv) = match self.role {
+            Role::Initiator => (initiator, responder),
+            Role::Responder => (responder, initiator),
+        };
+        Ok(Session {
+            send: send,
+            recv: recv,
+            session_id: session_id,
+        })
+    }
+}
+
+/// Keys and counters of one direction of the connection
+struct Direction {
+    length_cipher: FSChaCha20,
+    packet_cipher: FSChaCha20Poly1305,
+    garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+}
+
+impl Direction {
+    fn new(length_key: [u8; 32], packet_key: [u8; 32], garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN]) -> Direction {
+        Direction {
+            length_cipher: FSChaCha20::new(length_key),
+            packet_cipher: FSChaCha20Poly1305::new(packet_key),
+            garbage_terminator: garbage_terminator,
+        }
+    }
+}
+
+/// An established encrypted session
+pub struct Session {
+    send: Direction,
+    recv: Direction,
+    session_id: [u8; 32],
+}
+
+impl Session {
+    /// Identifier of the session, equal on both sides. Comparing it out of
+    /// band detects man-in-the-middle attacks.
+    pub fn session_id(&self) -> [u8; 32] {
+        self.session_id
+    }
+
+    /// The terminator to send after our garbage
+    pub fn garbage_terminator(&self) -> [u8; GARBAGE_TERMINATOR_LEN] {
+        self.send.garbage_terminator
+    }
+
+    /// The terminator ending the peer's garbage
+    pub fn peer_garbage_terminator(&self) -> [u8; GARBAGE_TERMINATOR_LEN] {
+        self.recv.garbage_terminator
+    }
+
+    /// Encrypt a packet with the given contents. `aad` is authenticated
+    /// along with the packet; it is the garbage sent before the first
+    /// packet, and empty for the others. Decoy packets have `ignore` set.
+    pub fn encrypt_packet(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Result<Vec<u8>, Error> {
+        if contents.len() >= 1 << 24 {
+            return Err(Error::PacketTooLarge(contents.len()));
+        }
+        let mut packet = Vec::with_capacity(LENGTH_LEN + HEADER_LEN + contents.len() + TAG_LEN);
+        let len = contents.len() as u32;
+        packet.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8]);
+        self.send.length_cipher.crypt(&mut packet[..LENGTH_LEN]);
+
+        let mut plaintext = Vec::with_capacity(HEADER_LEN + contents.len());
+        plaintext.push(if ignore { IGNORE_BIT } else { 0 });
+        plaintext.extend_from_slice(contents);
+        packet.extend(self.send.packet_cipher.encrypt(aad, plaintext));
+        Ok(packet)
+    }
+
+    /// Encrypt a packet carrying `msg`
+    pub fn encrypt_message(&mut self, msg: &NetworkMessage) -> Result<Vec<u8>, Error> {
+        let mut contents = vec![0u8];
+        msg.command().consensus_encode(&mut contents)?;
+        msg.consensus_encode_payload(&mut contents)?;
+        self.encrypt_packet(&contents, &[], false)
+    }
+
+    /// Decrypt the length of the next packet. The rest of the packet is
+    /// `packet_len(length)` bytes long.
+    pub fn decrypt_length(&mut self, encrypted: [u8; LENGTH_LEN]) -> usize {
+        let mut len = encrypted;
+        self.recv.length_cipher.crypt(&mut len);
+        len[0] as usize | (len[1] as usize) << 8 | (len[2] as usize) << 16
+    }
+
+    /// Decrypt the rest of a packet, following its encrypted length.
+    /// Returns `None` for decoy packets.
+    pub fn decrypt_packet(&mut self, packet: &[u8], aad: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if packet.len() < HEADER_LEN + TAG_LEN {
+            return Err(Error::DecryptionFailed);
+        }
+        let mut plaintext = self.recv.packet_cipher.decrypt(aad, packet)?;
+        if plaintext[0] & IGNORE_BIT != 0 {
+            return Ok(None);
+        }
+        plaintext.remove(0);
+        Ok(Some(plaintext))
+    }
+
+    /// Decode the message carried by the decrypted contents of a packet
+    pub fn decode_message(contents: &[u8], config: &DecodeConfig) -> Result<NetworkMessage, Error> {
+        if contents.is_empty() {
+            return Err(Error::Encode(encode::Error::Parse(encode::ParseError::Other("empty packet contents"))));
+        }
+        if contents[0] != 0 {
+            return Err(Error::UnsupportedShortId(contents[0]));
+        }
+        if contents.len() < COMMAND_LEN {
+            return Err(Error::Encode(encode::Error::Parse(encode::ParseError::Other("truncated message command"))));
+        }
+        let command: CommandString = encode::deserialize(&contents[1..COMMAND_LEN])?;
+        Ok(NetworkMessage::consensus_decode_payload(command, &contents[COMMAND_LEN..], config)?)
+    }
+}
+
+/// Number of bytes following the encrypted length of a packet whose
+/// contents are `len` bytes long
+pub fn packet_len(len: usize) -> usize {
+    HEADER_LEN + len + TAG_LEN
+}
+
+/// A blocking stream carrying encrypted messages
+pub struct V2Stream<S: Read + Write> {
+    stream: S,
+    session: Session,
+    config: DecodeConfig,
+}
+
+impl<S: Read + Write> V2Stream<S> {
+    /// Run the handshake over `stream`, using a fresh `secret_key`
+    pub fn handshake<C: Signing + Verification>(
+        secp: &Secp256k1<C>,
+        mut stream: S,
+        role: Role,
+        magic: u32,
+        secret_key: SecretKey,
+    ) -> Result<V2Stream<S>, Error> {
+        let handshake = Handshake::new(secp, role, magic, secret_key);
+        stream.write_all(&handshake.public_key())?;
+        stream.flush()?;
+
+        let mut peer_key = [0u8; 33];
+        stream.read_exact(&mut peer_key)?;
+        let mut session = handshake.complete(secp, &peer_key)?;
+
+        // We send no garbage, so our version packet has no associated data
+        let version = session.encrypt_packet(&[], &[], false)?;
+        stream.write_all(&session.garbage_terminator())?;
+        stream.write_all(&version)?;
+        stream.flush()?;
+
+        let garbage = read_garbage(&mut stream, &session.peer_garbage_terminator())?;
+        let mut v2 = V2Stream {
+            stream: stream,
+            session: session,
+            config: DecodeConfig::default(),
+        };
+        // The contents of the version packet are reserved for future use
+        let mut aad = garbage;
+        while v2.read_packet(&aad)?.is_none() {
+            aad.clear();
+        }
+        Ok(v2)
+    }
+
+    /// Replace the limits applied to received messages
+    pub fn with_decode_config(mut self, config: DecodeConfig) -> V2Stream<S> {
+        self.config = config;
+        self
+    }
+
+    /// Identifier of the session, equal on both sides
+    pub fn session_id(&self) -> [u8; 32] {
+        self.session.session_id()
+    }
+
+    /// Send a message
+    pub fn send(&mut self, msg: &NetworkMessage) -> Result<(), Error> {
+        let packet = self.session.encrypt_message(msg)?;
+        self.stream.write_all(&packet)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Receive the next message, skipping decoy packets
+    pub fn receive(&mut self) -> Result<NetworkMessage, Error> {
+        loop {
+            if let Some(contents) = self.read_packet(&[])? {
+                return Session::decode_message(&contents, &self.config);
+            }
+        }
+    }
+
+    /// Return the underlying stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Read and decrypt a packet, returning `None` for decoys
+    fn read_packet(&mut self, aad: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let mut encrypted_len = [0u8; LENGTH_LEN];
+        self.stream.read_exact(&mut encrypted_len)?;
+        let len = self.session.decrypt_length(encrypted_len);
+        let max = COMMAND_LEN + self.config.max_message_size;
+        if len > max || len > MAX_VEC_SIZE {
+            return Err(Error::PacketTooLarge(len));
+        }
+        let mut packet = vec![0u8; packet_len(len)];
+        self.stream.read_exact(&mut packet)?;
+        self.session.decrypt_packet(&packet, aad)
+    }
+}
+
+/// Read the peer's garbage up to and excluding its terminator
+fn read_garbage<R: Read>(r: &mut R, terminator: &[u8; GARBAGE_TERMINATOR_LEN]) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0u8; GARBAGE_TERMINATOR_LEN];
+    r.read_exact(&mut buf)?;
+    while &buf[buf.len() - GARBAGE_TERMINATOR_LEN..] != &terminator[..] {
+        if buf.len() - GARBAGE_TERMINATOR_LEN >= MAX_GARBAGE_LEN {
+            return Err(Error::MissingGarbageTerminator);
+        }
+        let mut byte = [0u8; 1];
+        r.read_exact(&mut byte)?;
+        buf.push(byte[0]);
+    }
+    buf.truncate(buf.len() - GARBAGE_TERMINATOR_LEN);
+    Ok(buf)
+}
+
+/// Start a BIP340-style tagged hash
+fn tagged_engine(tag: &[u8]) -> sha256::HashEngine {
+    let tag_hash = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    engine
+}
+
+/// HKDF-SHA256 expansion of a single 32-byte block
+fn hkdf_expand(prk: &Hmac<sha256::Hash>, label: &[u8]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(&prk[..]);
+    engine.input(label);
+    engine.input(&[1]);
+    Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+}
+
+/// ChaCha20 stream cipher for packet lengths, rekeyed every
+/// `REKEY_INTERVAL` chunks
+struct FSChaCha20 {
+    cipher: ChaCha20,
+    chunk_counter: u64,
+}
+
+impl FSChaCha20 {
+    fn new(key: [u8; 32]) -> FSChaCha20 {
+        FSChaCha20 {
+            cipher: FSChaCha20::cipher(key, 0),
+            chunk_counter: 0,
+        }
+    }
+
+    /// The cipher of the keys rotated `rekeys` times, with its keystream
+    /// continuing across the chunks until the next rotation
+    fn cipher(key: [u8; 32], rekeys: u64) -> ChaCha20 {
+        ChaCha20::new(&chacha20::Key::from(key), &chacha20::Nonce::from(nonce(0, rekeys)))
+    }
+
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter % REKEY_INTERVAL == 0 {
+            let mut key = [0u8; 32];
+            self.cipher.apply_keystream(&mut key);
+            self.cipher = FSChaCha20::cipher(key, self.chunk_counter / REKEY_INTERVAL);
+        }
+    }
+}
+
+/// ChaCha20-Poly1305 AEAD for packets, rekeyed every `REKEY_INTERVAL`
+/// packets
+struct FSChaCha20Poly1305 {
+    aead: ChaCha20Poly1305,
+    packet_counter: u64,
+}
+
+impl FSChaCha20Poly1305 {
+    fn new(key: [u8; 32]) -> FSChaCha20Poly1305 {
+        FSChaCha20Poly1305 {
+            aead: ChaCha20Poly1305::new(&chacha20poly1305::Key::from(key)),
+            packet_counter: 0,
+        }
+    }
+
+    fn encrypt(&mut self, aad: &[u8], plaintext: Vec<u8>) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        let payload = Payload { msg: &plaintext, aad: aad };
+        let ciphertext = self.aead.encrypt(&nonce, payload).expect("packets are shorter than 2^24 bytes");
+        self.advance(&nonce);
+        ciphertext
+    }
+
+    fn decrypt(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = self.next_nonce();
+        let plaintext = self.aead.decrypt(&nonce, Payload { msg: ciphertext, aad: aad });
+        self.advance(&nonce);
+        plaintext.map_err(|_| Error::DecryptionFailed)
+    }
+
+    fn next_nonce(&self) -> chacha20poly1305::Nonce {
+        let nonce = nonce((self.packet_counter % REKEY_INTERVAL) as u32, self.packet_counter / REKEY_INTERVAL);
+        chacha20poly1305::Nonce::from(nonce)
+    }
+
+    fn advance(&mut self, nonce: &chacha20poly1305::Nonce) {
+        if (self.packet_counter + 1) % REKEY_INTERVAL == 0 {
+            let mut rekey_nonce = *nonce;
+            rekey_nonce[0..4].copy_from_slice(&[0xff; 4]);
+            let sealed = self.aead.encrypt(&rekey_nonce, &[0u8; 32][..]).expect("32 bytes can be encrypted");
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&sealed[..32]);
+            self.aead = ChaCha20Poly1305::new(&chacha20poly1305::Key::from(key));
+        }
+        self.packet_counter += 1;
+    }
+}
+
+/// Build a nonce from a 32-bit and a 64-bit counter
+fn nonce(low: u32, high: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[0..4].copy_from_slice(&low.to_le_bytes());
+    nonce[4..12].copy_from_slice(&high.to_le_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use hashes::{sha256, Hash, Hmac};
+    use hex::decode as hex_decode;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::{hkdf_expand, Error, FSChaCha20, FSChaCha20Poly1305, Handshake, Role, Session, V2Stream};
+    use network::message::{DecodeConfig, NetworkMessage};
+
+    #[test]
+    fn cipher_test() {
+        // RFC 5869 test case 1, whose output is a single block long
+        let prk = Hmac::<sha256::Hash>::from_slice(&hex_decode("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5").unwrap()).unwrap();
+        assert_eq!(
+            hkdf_expand(&prk, &hex_decode("f0f1f2f3f4f5f6f7f8f9").unwrap()).to_vec(),
+            hex_decode("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf").unwrap()
+        );
+
+        // The rotation of the keys, checked against the ChaCha20 and
+        // ChaCha20-Poly1305 of OpenSSL
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&hex_decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f").unwrap());
+        let mut lengths = FSChaCha20::new(key);
+        for i in 0..226 {
+            let mut chunk = [i as u8, 1, 2];
+            lengths.crypt(&mut chunk);
+            match i {
+                0 => assert_eq!(chunk.to_vec(), hex_decode("39fc29").unwrap()),
+                223 => assert_eq!(chunk.to_vec(), hex_decode("f06fcb").unwrap()),
+                224 => assert_eq!(chunk.to_vec(), hex_decode("d9fefd").unwrap()),
+                225 => assert_eq!(chunk.to_vec(), hex_decode("139ce7").unwrap()),
+                _ => {}
+            }
+        }
+
+        let mut sender = FSChaCha20Poly1305::new(key);
+        let mut receiver = FSChaCha20Poly1305::new(key);
+        for i in 0..225 {
+            let aad: &[u8] = if i == 0 { b"aad" } else { b"" };
+            let plaintext = format!("packet {}", i).into_bytes();
+            let ciphertext = sender.encrypt(aad, plaintext.clone());
+            match i {
+                0 => assert_eq!(ciphertext, hex_decode("68d9215ac89286e1439598de591e2bf077fc2afb59c18129").unwrap()),
+                223 => assert_eq!(ciphertext, hex_decode("9dfd2166aec405b2ee994ded18b34a082a3fd403d0cdc07b4a7b").unwrap()),
+                224 => assert_eq!(ciphertext, hex_decode("55c7418601121cc120e09dcc74c1a23f6a5b45a2b449a948d9a9").unwrap()),
+                _ => {}
+            }
+            assert_eq!(receiver.decrypt(aad, &ciphertext).unwrap(), plaintext);
+        }
+        let mut ciphertext = sender.encrypt(&[], b"tampered".to_vec());
+        ciphertext[0] ^= 1;
+        match receiver.decrypt(&[], &ciphertext) {
+            Err(Error::DecryptionFailed) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    fn sessions() -> (Session, Session) {
+        let secp = Secp256k1::new();
+        let initiator_key = SecretKey::from_slice(&hex_decode("0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20").unwrap()).unwrap();
+        let responder_key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let initiator = Handshake::new(&secp, Role::Initiator, 0x12345678, initiator_key);
+        let responder = Handshake::new(&secp, Role::Responder, 0x12345678, responder_key);
+        assert_eq!(
+            initiator.public_key().to_vec(),
+            hex_decode("0284bf7562262bbd6940085748f3be6afa52ae317155181ece31b66351ccffa4b0").unwrap()
+        );
+
+        let initiator_pk = initiator.public_key();
+        let responder_pk = responder.public_key();
+        (
+            initiator.complete(&secp, &responder_pk).unwrap(),
+            responder.complete(&secp, &initiator_pk).unwrap(),
+        )
+    }
+
+    // The vectors of the sessions are checked against an implementation
+    // using the secp256k1 ECDH and the ChaCha20-Poly1305 of OpenSSL
+    #[test]
+    fn session_test() {
+        let (mut initiator, mut responder) = sessions();
+        assert_eq!(
+            initiator.session_id().to_vec(),
+            hex_decode("1ec7520dce630ee6f8a56117f0f15f594792c0487436d861bc66274fbf8a03c6").unwrap()
+        );
+        assert_eq!(initiator.session_id(), responder.session_id());
+        assert_eq!(initiator.garbage_terminator(), responder.peer_garbage_terminator());
+        assert_eq!(initiator.peer_garbage_terminator(), responder.garbage_terminator());
+        assert_eq!(
+            initiator.garbage_terminator().to_vec(),
+            hex_decode("34e130ac52b2c5a68adaf027cba75fa3").unwrap()
+        );
+
+        let version = initiator.encrypt_packet(&[], &[], false).unwrap();
+        assert_eq!(version, hex_decode("98064eb722a774e93eabe391fdb769d678639fad").unwrap());
+        let ping = initiator.encrypt_message(&NetworkMessage::Ping(42)).unwrap();
+        assert_eq!(ping, hex_decode("7c1af88470b6fb2676b6c269e1e5486a9576be08f831c64e2bb387114bc0f9442b5b470fd8b8aa1d52").unwrap());
+
+        let config = DecodeConfig::default();
+        for packet in &[version, ping] {
+            let len = responder.decrypt_length([packet[0], packet[1], packet[2]]);
+            assert_eq!(super::packet_len(len), packet.len() - 3);
+            responder.decrypt_packet(&packet[3..], &[]).unwrap().unwrap();
+        }
+
+        // past the key rotation
+        for i in 0..300 {
+            let packet = initiator.encrypt_message(&NetworkMessage::Ping(i)).unwrap();
+            if i == 299 {
+                assert_eq!(packet, hex_decode("b45cc5f859ddcc51a11c16248fb37affe0f05ac0fa739411d92e9975ef6ce72d6924e15cba0732ac5a").unwrap());
+            }
+            responder.decrypt_length([packet[0], packet[1], packet[2]]);
+            let contents = responder.decrypt_packet(&packet[3..], &[]).unwrap().unwrap();
+            assert_eq!(Session::decode_message(&contents, &config).unwrap(), NetworkMessage::Ping(i));
+        }
+
+        // decoys are skipped, tampering is detected
+        let decoy = responder.encrypt_packet(&[1, 2, 3], &[], true).unwrap();
+        initiator.decrypt_length([decoy[0], decoy[1], decoy[2]]);
+        assert_eq!(initiator.decrypt_packet(&decoy[3..], &[]).unwrap(), None);
+        let mut pong = responder.encrypt_message(&NetworkMessage::Pong(7)).unwrap();
+        pong[5] ^= 1;
+        initiator.decrypt_length([pong[0], pong[1], pong[2]]);
+        match initiator.decrypt_packet(&pong[3..], &[]) {
+            Err(Error::DecryptionFailed) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn stream_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let secp = Secp256k1::new();
+            let (stream, _) = listener.accept().unwrap();
+            let key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+            let mut v2 = V2Stream::handshake(&secp, stream, Role::Responder, 0x12345678, key).unwrap();
+            match v2.receive().unwrap() {
+                NetworkMessage::Ping(nonce) => v2.send(&NetworkMessage::Pong(nonce)).unwrap(),
+                msg => panic!("unexpected message: {:?}", msg),
+            }
+            v2.session_id()
+        });
+
+        let secp = Secp256k1::new();
+        let stream = TcpStream::connect(addr).unwrap();
+        let key = SecretKey::from_slice(&[0x24; 32]).unwrap();
+        let mut v2 = V2Stream::handshake(&secp, stream, Role::Initiator, 0x12345678, key).unwrap();
+        v2.send(&NetworkMessage::Ping(99)).unwrap();
+        assert_eq!(v2.receive().unwrap(), NetworkMessage::Pong(99));
+        assert_eq!(server.join().unwrap(), v2.session_id());
+    }
+}
@@ -0,0 +1,23 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Prelude
+//!
+//! The types of `alloc` which the standard prelude brings in scope, for the
+//! modules built without the `std` feature to import with `use prelude::*`.
+//!
+
+#[cfg(all(not(feature = "std"), not(test)))]
+#[allow(unused_imports)]
+pub use alloc::{borrow::{Cow, ToOwned}, boxed::Box, string::{String, ToString}, vec::Vec};
+#[cfg(all(not(feature = "std"), not(test)))]
+#[allow(unused_imports)]
+pub use alloc::collections::{btree_map, BTreeMap, BTreeSet};
+
+#[cfg(any(feature = "std", test))]
+#[allow(unused_imports)]
+pub use std::{borrow::{Cow, ToOwned}, boxed::Box, string::{String, ToString}, vec::Vec};
+#[cfg(any(feature = "std", test))]
+#[allow(unused_imports)]
+pub use std::collections::{btree_map, BTreeMap, BTreeSet};
@@ -6,7 +6,10 @@
 //!
 //!
 
+use std::str::FromStr;
+
 use secp256k1::SecretKey;
+use util::key::PublicKey;
 use util::signature::Signature;
 use consensus::deserialize;
 
@@ -35,4 +38,8 @@ pub fn pk_from(sk: &SecretKey) -> secp256k1::PublicKey {
 pub fn decode_signature(sig_hex: &str) -> Signature {
     let sig = hex::decode(sig_hex).unwrap();
     deserialize(&sig[..]).unwrap()
-}
\ No newline at end of file
+}
+
+pub fn test_pubkey() -> PublicKey {
+    PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap()
+}
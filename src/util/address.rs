@@ -18,7 +18,10 @@
 
 //! Addresses
 //!
-//! Support for ordinary base58 Bitcoin addresses and private keys
+//! Support for ordinary base58 Bitcoin addresses and private keys, and for
+//! the colored addresses Tapyrus uses to receive tokens. A colored address
+//! is the base58check encoding of its version byte, the color identifier of
+//! the tokens and the hash.
 //!
 //! # Example: creating a new address from a randomly-generated key pair
 //!
@@ -52,8 +55,9 @@ use hashes::Hash;
 
 use hash_types::{PubkeyHash, ScriptHash};
 use blockdata::opcodes;
-use blockdata::script;
+use blockdata::script::{self, ColorIdentifier};
 use network::constants::Network;
+use consensus::encode::{deserialize, serialize};
 use util::base58;
 use util::key;
 
@@ -62,12 +66,24 @@ use util::key;
 pub enum Error {
     /// Base58 encoding error
     Base58(base58::Error),
+    /// The color identifier of a colored address is invalid
+    InvalidColorId,
+    /// The address is not valid on the network it is required for
+    NetworkMismatch {
+        /// The network required
+        expected: Network,
+        /// The network of the address
+        found: Network,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Base58(ref e) => write!(f, "base58: {}", e),
+            Error::InvalidColorId => f.write_str("invalid color identifier"),
+            Error::NetworkMismatch { expected, found } => write!(f,
+                "address of network {} is not valid on network {}", found, expected),
         }
     }
 }
@@ -76,6 +92,7 @@ impl ::std::error::Error for Error {
     fn cause(&self) -> Option<&::std::error::Error> {
         match *self {
             Error::Base58(ref e) => Some(e),
+            Error::InvalidColorId | Error::NetworkMismatch { .. } => None,
         }
     }
 
@@ -98,6 +115,10 @@ pub enum AddressType {
     P2pkh,
     /// pay-to-script-hash
     P2sh,
+    /// colored pay-to-pubkey-hash
+    Cp2pkh,
+    /// colored pay-to-script-hash
+    Cp2sh,
 }
 
 impl fmt::Display for AddressType {
@@ -105,16 +126,30 @@ impl fmt::Display for AddressType {
         f.write_str(match *self {
             AddressType::P2pkh => "p2pkh",
             AddressType::P2sh => "p2sh",
+            AddressType::Cp2pkh => "cp2pkh",
+            AddressType::Cp2sh => "cp2sh",
         })
     }
 }
 
+impl AddressType {
+    /// Whether addresses of this type receive tokens
+    pub fn is_colored(self) -> bool {
+        match self {
+            AddressType::Cp2pkh | AddressType::Cp2sh => true,
+            AddressType::P2pkh | AddressType::P2sh => false,
+        }
+    }
+}
+
 impl FromStr for AddressType {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "p2pkh" => Ok(AddressType::P2pkh),
             "p2sh" => Ok(AddressType::P2sh),
+            "cp2pkh" => Ok(AddressType::Cp2pkh),
+            "cp2sh" => Ok(AddressType::Cp2sh),
             _ => Err(()),
         }
     }
@@ -127,6 +162,10 @@ pub enum Payload {
     PubkeyHash(PubkeyHash),
     /// P2SH address
     ScriptHash(ScriptHash),
+    /// colored pay-to-pkhash address
+    ColoredPubkeyHash(ColorIdentifier, PubkeyHash),
+    /// colored P2SH address
+    ColoredScriptHash(ColorIdentifier, ScriptHash),
 }
 
 impl Payload {
@@ -136,6 +175,16 @@ impl Payload {
             Payload::PubkeyHash(PubkeyHash::from_slice(&script.as_bytes()[3..23]).unwrap())
         } else if script.is_p2sh() {
             Payload::ScriptHash(ScriptHash::from_slice(&script.as_bytes()[2..22]).unwrap())
+        } else if script.is_cp2pkh() {
+            Payload::ColoredPubkeyHash(
+                script.color_id()?,
+                PubkeyHash::from_slice(&script.as_bytes()[38..58]).unwrap(),
+            )
+        } else if script.is_cp2sh() {
+            Payload::ColoredScriptHash(
+                script.color_id()?,
+                ScriptHash::from_slice(&script.as_bytes()[37..57]).unwrap(),
+            )
         } else {
             return None;
         })
@@ -144,18 +193,27 @@ impl Payload {
     /// Generates a script pubkey spending to this [Payload].
     pub fn script_pubkey(&self) -> script::Script {
         match *self {
-            Payload::PubkeyHash(ref hash) => script::Builder::new()
-                .push_opcode(opcodes::all::OP_DUP)
-                .push_opcode(opcodes::all::OP_HASH160)
-                .push_slice(&hash[..])
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_CHECKSIG),
+            Payload::PubkeyHash(ref hash) => script::Script::new_p2pkh(hash),
             Payload::ScriptHash(ref hash) => script::Builder::new()
                 .push_opcode(opcodes::all::OP_HASH160)
                 .push_slice(&hash[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
+                .push_opcode(opcodes::all::OP_EQUAL)
+                .into_script(),
+            Payload::ColoredPubkeyHash(ref color_id, ref hash) => {
+                script::Builder::new().into_cp2pkh(color_id, hash)
+            }
+            Payload::ColoredScriptHash(ref color_id, ref hash) => {
+                script::Builder::new().into_cp2sh(color_id, hash)
+            }
+        }
+    }
+
+    /// The color identifier of the tokens paid to a colored payload
+    pub fn color_id(&self) -> Option<&ColorIdentifier> {
+        match *self {
+            Payload::ColoredPubkeyHash(ref color_id, _) | Payload::ColoredScriptHash(ref color_id, _) => Some(color_id),
+            Payload::PubkeyHash(_) | Payload::ScriptHash(_) => None,
         }
-        .into_script()
     }
 }
 
@@ -193,15 +251,51 @@ impl Address {
         }
     }
 
+    /// Creates a colored pay to public key hash address, receiving the
+    /// tokens of `color_id`
+    #[inline]
+    pub fn cp2pkh(pk: &key::PublicKey, color_id: &ColorIdentifier, network: Network) -> Address {
+        let mut hash_engine = PubkeyHash::engine();
+        pk.write_into(&mut hash_engine);
+
+        Address {
+            network: network,
+            payload: Payload::ColoredPubkeyHash(color_id.clone(), PubkeyHash::from_engine(hash_engine)),
+        }
+    }
+
+    /// Creates a colored pay to script hash address, receiving the tokens of
+    /// `color_id`
+    #[inline]
+    pub fn cp2sh(script: &script::Script, color_id: &ColorIdentifier, network: Network) -> Address {
+        Address {
+            network: network,
+            payload: Payload::ColoredScriptHash(color_id.clone(), ScriptHash::hash(&script[..])),
+        }
+    }
+
     /// Get the address type of the address.
     /// None if unknown or non-standard.
     pub fn address_type(&self) -> Option<AddressType> {
         match self.payload {
             Payload::PubkeyHash(_) => Some(AddressType::P2pkh),
             Payload::ScriptHash(_) => Some(AddressType::P2sh),
+            Payload::ColoredPubkeyHash(..) => Some(AddressType::Cp2pkh),
+            Payload::ColoredScriptHash(..) => Some(AddressType::Cp2sh),
         }
     }
 
+    /// The color identifier of the tokens a colored address receives, `None`
+    /// for addresses receiving TPC
+    pub fn color_id(&self) -> Option<&ColorIdentifier> {
+        self.payload.color_id()
+    }
+
+    /// Whether the address receives tokens
+    pub fn is_colored(&self) -> bool {
+        self.color_id().is_some()
+    }
+
     /// Check whether or not the address is following Bitcoin
     /// standardness rules.
     ///
@@ -211,7 +305,8 @@ impl Address {
         self.address_type().is_some()
     }
 
-    /// Get an [Address] from an output script (scriptPubkey).
+    /// Get an [Address] from an output script (scriptPubkey), colored or
+    /// not. None if the script matches none of the address templates.
     pub fn from_script(script: &script::Script, network: Network) -> Option<Address> {
         Some(Address {
             payload: Payload::from_script(script)?,
@@ -223,70 +318,223 @@ impl Address {
     pub fn script_pubkey(&self) -> script::Script {
         self.payload.script_pubkey()
     }
+
+    /// Whether the address can be used on `network`, that is whether its
+    /// string form is the same on both networks. Development networks with
+    /// different IDs share their version bytes, so their addresses are
+    /// valid on each other.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        let (ours, theirs) = (self.network.params(), network.params());
+        match self.payload {
+            Payload::PubkeyHash(_) => ours.p2pkh_prefix == theirs.p2pkh_prefix,
+            Payload::ScriptHash(_) => ours.p2sh_prefix == theirs.p2sh_prefix,
+            Payload::ColoredPubkeyHash(..) => ours.cp2pkh_prefix == theirs.cp2pkh_prefix,
+            Payload::ColoredScriptHash(..) => ours.cp2sh_prefix == theirs.cp2sh_prefix,
+        }
+    }
+
+    /// Check that the address is valid on `network`, as parsed addresses
+    /// should before being paid to, and return it as an address of
+    /// `network`
+    pub fn require_network(self, network: Network) -> Result<Address, Error> {
+        if !self.is_valid_for_network(network) {
+            return Err(Error::NetworkMismatch { expected: network, found: self.network });
+        }
+        Ok(Address { network: network, ..self })
+    }
+}
+
+/// Base58check encode `hash`, prefixed by `prefix` and the serialized
+/// `color_id` of colored addresses
+fn fmt_base58(fmt: &mut Formatter, prefix: u8, color_id: Option<&ColorIdentifier>, hash: &[u8]) -> fmt::Result {
+    let mut prefixed = [0; 54];
+    prefixed[0] = prefix;
+    let len = match color_id {
+        Some(color_id) => {
+            prefixed[1..34].copy_from_slice(&serialize(color_id));
+            prefixed[34..].copy_from_slice(hash);
+            54
+        }
+        None => {
+            prefixed[1..21].copy_from_slice(hash);
+            21
+        }
+    };
+    base58::check_encode_slice_to_fmt(fmt, &prefixed[..len])
 }
 
 impl Display for Address {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let params = self.network.params();
         match self.payload {
-            Payload::PubkeyHash(ref hash) => {
-                let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Prod => 0,
-                    Network::Dev => 111,
-                };
-                prefixed[1..].copy_from_slice(&hash[..]);
-                base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
+            Payload::PubkeyHash(ref hash) => fmt_base58(fmt, params.p2pkh_prefix, None, &hash[..]),
+            Payload::ScriptHash(ref hash) => fmt_base58(fmt, params.p2sh_prefix, None, &hash[..]),
+            Payload::ColoredPubkeyHash(ref color_id, ref hash) => {
+                fmt_base58(fmt, params.cp2pkh_prefix, Some(color_id), &hash[..])
             }
-            Payload::ScriptHash(ref hash) => {
-                let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Prod => 5,
-                    Network::Dev => 196,
-                };
-                prefixed[1..].copy_from_slice(&hash[..]);
-                base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
+            Payload::ColoredScriptHash(ref color_id, ref hash) => {
+                fmt_base58(fmt, params.cp2sh_prefix, Some(color_id), &hash[..])
             }
         }
     }
 }
 
-impl FromStr for Address {
-    type Err = Error;
+/// An address string which may be of a type this library does not know, as
+/// parsed by [Address::from_str_allow_unknown]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ParsedAddress {
+    /// An address of a known type
+    Known(Address),
+    /// An address with a version byte this library does not know. It can be
+    /// displayed again, but not paid to.
+    Unknown {
+        /// The version byte
+        version: u8,
+        /// The data following the version byte
+        payload: Vec<u8>,
+    },
+}
 
-    fn from_str(s: &str) -> Result<Address, Error> {
-        // Base58
-        if s.len() > 50 {
+impl Display for ParsedAddress {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            ParsedAddress::Known(ref address) => Display::fmt(address, fmt),
+            ParsedAddress::Unknown { version, ref payload } => {
+                let mut prefixed = Vec::with_capacity(1 + payload.len());
+                prefixed.push(version);
+                prefixed.extend_from_slice(payload);
+                base58::check_encode_slice_to_fmt(fmt, &prefixed)
+            }
+        }
+    }
+}
+
+impl Address {
+    /// Parse an address of the given network, which may be a custom one.
+    /// `FromStr` only recognizes the addresses of the default networks.
+    pub fn from_str_for_network(s: &str, network: Network) -> Result<Address, Error> {
+        Address::parse(s, &[network])
+    }
+
+    /// Parse an address of the given network like `from_str_for_network`,
+    /// but accept any version byte and data length as an address of an
+    /// unknown type, e.g. one introduced after this library.
+    pub fn from_str_allow_unknown(s: &str, network: Network) -> Result<ParsedAddress, Error> {
+        match Address::parse(s, &[network]) {
+            Err(Error::Base58(base58::Error::InvalidVersion(_)))
+            | Err(Error::Base58(base58::Error::InvalidLength(_))) => {}
+            result => return result.map(ParsedAddress::Known),
+        }
+        // bound the decoding work all the same
+        if s.len() > 128 {
+            return Err(Error::Base58(base58::Error::InvalidLength(s.len() * 11 / 15)));
+        }
+        let data = base58::from_check(s)?;
+        if data.is_empty() {
+            return Err(Error::Base58(base58::Error::InvalidLength(0)));
+        }
+        Ok(ParsedAddress::Unknown { version: data[0], payload: data[1..].to_vec() })
+    }
+
+    /// Parse an address of one of `networks`, the first matching network
+    /// being picked when several share version bytes
+    fn parse(s: &str, networks: &[Network]) -> Result<Address, Error> {
+        // Base58, colored addresses being the longest
+        if s.len() > 80 {
             return Err(Error::Base58(base58::Error::InvalidLength(s.len() * 11 / 15)));
         }
         let data = base58::from_check(s)?;
-        if data.len() != 21 {
+        if data.len() != 21 && data.len() != 54 {
             return Err(Error::Base58(base58::Error::InvalidLength(data.len())));
         }
 
-        let (network, payload) = match data[0] {
-            0 => (
-                Network::Prod,
-                Payload::PubkeyHash(PubkeyHash::from_slice(&data[1..]).unwrap()),
-            ),
-            5 => (
-                Network::Prod,
-                Payload::ScriptHash(ScriptHash::from_slice(&data[1..]).unwrap()),
-            ),
-            111 => (
-                Network::Dev,
-                Payload::PubkeyHash(PubkeyHash::from_slice(&data[1..]).unwrap()),
-            ),
-            196 => (
-                Network::Dev,
-                Payload::ScriptHash(ScriptHash::from_slice(&data[1..]).unwrap()),
-            ),
-            x => return Err(Error::Base58(base58::Error::InvalidVersion(vec![x]))),
-        };
+        for network in networks {
+            let params = network.params();
+            let payload = if data.len() == 21 {
+                if data[0] == params.p2pkh_prefix {
+                    Payload::PubkeyHash(PubkeyHash::from_slice(&data[1..]).unwrap())
+                } else if data[0] == params.p2sh_prefix {
+                    Payload::ScriptHash(ScriptHash::from_slice(&data[1..]).unwrap())
+                } else {
+                    continue;
+                }
+            } else if data[0] == params.cp2pkh_prefix || data[0] == params.cp2sh_prefix {
+                let color_id: ColorIdentifier = deserialize(&data[1..34])
+                    .map_err(|_| Error::InvalidColorId)?;
+                if data[0] == params.cp2pkh_prefix {
+                    Payload::ColoredPubkeyHash(color_id, PubkeyHash::from_slice(&data[34..]).unwrap())
+                } else {
+                    Payload::ColoredScriptHash(color_id, ScriptHash::from_slice(&data[34..]).unwrap())
+                }
+            } else {
+                continue;
+            };
+            return Ok(Address {
+                network: *network,
+                payload: payload,
+            });
+        }
+        Err(Error::Base58(base58::Error::InvalidVersion(vec![data[0]])))
+    }
+}
 
-        Ok(Address {
-            network: network,
-            payload: payload,
-        })
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Address, Error> {
+        Address::parse(s, &Network::defaults())
+    }
+}
+
+#[cfg(feature = "serde")]
+pub mod serde {
+    //! Serialize and deserialize addresses which must be valid on a given
+    //! network, rejecting those of other networks. Use with
+    //! `#[serde(with = "address::serde::prod")]` or
+    //! `#[serde(with = "address::serde::dev")]`.
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde::de::Error;
+
+    use network::constants::Network;
+    use util::address::Address;
+
+    fn deserialize_for<'d, D: Deserializer<'d>>(d: D, network: Network) -> Result<Address, D::Error> {
+        Address::deserialize(d)?.require_network(network).map_err(D::Error::custom)
+    }
+
+    pub mod prod {
+        //! Addresses of the production network
+
+        use super::*;
+
+        /// Serialize an address of the production network
+        pub fn serialize<S: Serializer>(a: &Address, s: S) -> Result<S::Ok, S::Error> {
+            a.serialize(s)
+        }
+
+        /// Deserialize an address, failing unless it is valid on the
+        /// production network
+        pub fn deserialize<'d, D: Deserializer<'d>>(d: D) -> Result<Address, D::Error> {
+            deserialize_for(d, Network::Prod)
+        }
+    }
+
+    pub mod dev {
+        //! Addresses of the development network
+
+        use super::*;
+
+        /// Serialize an address of the development network
+        pub fn serialize<S: Serializer>(a: &Address, s: S) -> Result<S::Ok, S::Error> {
+            a.serialize(s)
+        }
+
+        /// Deserialize an address, failing unless it is valid on the
+        /// development network
+        pub fn deserialize<'d, D: Deserializer<'d>>(d: D) -> Result<Address, D::Error> {
+            deserialize_for(d, Network::Dev)
+        }
     }
 }
 
@@ -305,6 +553,7 @@ mod tests {
     use hex::{decode as hex_decode};
 
     use blockdata::script::Script;
+    use network::constants::NetworkParams;
     use network::constants::Network::{Prod, Dev};
     use util::key::PublicKey;
 
@@ -329,7 +578,16 @@ mod tests {
             "script round-trip failed for {}",
             addr,
         );
-        //TODO: add serde roundtrip after no-strason PR
+        #[cfg(feature = "serde")]
+        {
+            let json = ::serde_json::to_string(addr).unwrap();
+            assert_eq!(
+                ::serde_json::from_str::<Address>(&json).unwrap().require_network(addr.network).as_ref(),
+                Ok(addr),
+                "serde round-trip failed for {}",
+                addr,
+            );
+        }
     }
 
     #[test]
@@ -361,6 +619,25 @@ mod tests {
         roundtrips(&addr);
     }
 
+    #[test]
+    fn test_custom_network_address() {
+        let params = NetworkParams { p2pkh_prefix: 50, ..NetworkParams::dev_with_id(101) };
+        let network = Network::Custom(params);
+        let key = hex_key!(&"03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f");
+        let addr = Address::p2pkh(&key, network);
+        let s = addr.to_string();
+
+        assert_eq!(Address::from_str_for_network(&s, network).unwrap(), addr);
+        assert!(Address::from_str(&s).is_err());
+        assert!(Address::from_str_for_network(&s, Prod).is_err());
+
+        // the p2sh prefix is shared with the default development network
+        let addr = Address::p2sh(&hex_script!("51"), network);
+        let s = addr.to_string();
+        assert_eq!(Address::from_str_for_network(&s, network).unwrap(), addr);
+        assert_eq!(Address::from_str(&s).unwrap().network, Dev);
+    }
+
     #[test]
     fn test_p2sh_address_58() {
         let addr = Address {
@@ -387,6 +664,188 @@ mod tests {
         roundtrips(&addr);
     }
 
+    #[test]
+    fn test_cp2pkh_address_58() {
+        let color_id = ColorIdentifier::reissuable(hex_script!("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac"));
+        let addr = Address {
+            network: Prod,
+            payload: Payload::ColoredPubkeyHash(color_id.clone(), hex_pubkeyhash!("162c5ea71c0b23f5b9022ef047c4a86470a5b070")),
+        };
+
+        assert_eq!(
+            addr.script_pubkey(),
+            hex_script!("21c14f18505f71db5ff7deca8ee1d5185c8da27b326a7f736b5fe819724c62c9dd53bc76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac")
+        );
+        assert_eq!(&addr.to_string(), "vhyzbhTuZAS1k4MqmpCkbMtdkE6Nqms3BK3YK8DPGjd88hA6Bw4xw7KTAAmEV3stvsa4NULBaM5CAB");
+        assert_eq!(addr.address_type(), Some(AddressType::Cp2pkh));
+        assert_eq!(addr.color_id(), Some(&color_id));
+        roundtrips(&addr);
+
+        let addr = Address { network: Dev, ..addr };
+        assert_eq!(&addr.to_string(), "22VKH85CPp9qEq1CNbS1YGCbWG8uehupUXyWTCKBEVnyVZXab1FR6MtwNmT2UXXWYXheWXe6KcP8ZgFC");
+        roundtrips(&addr);
+    }
+
+    #[test]
+    fn test_cp2sh_address_58() {
+        let color_id = ColorIdentifier::reissuable(hex_script!("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac"));
+        let addr = Address {
+            network: Prod,
+            payload: Payload::ColoredScriptHash(color_id.clone(), hex_scripthash!("162c5ea71c0b23f5b9022ef047c4a86470a5b070")),
+        };
+
+        assert_eq!(
+            addr.script_pubkey(),
+            hex_script!("21c14f18505f71db5ff7deca8ee1d5185c8da27b326a7f736b5fe819724c62c9dd53bca914162c5ea71c0b23f5b9022ef047c4a86470a5b07087")
+        );
+        assert_eq!(&addr.to_string(), "4Zi1Hf2XuK3pVzrBRcZZVCuragYbXYC1xCsS2g2buMETuAmZgTF3yy6sUWG8x1U53Nve5biVZcv1bev");
+        assert_eq!(addr.address_type(), Some(AddressType::Cp2sh));
+        roundtrips(&addr);
+
+        let addr = Address { network: Dev, ..addr };
+        assert_eq!(&addr.to_string(), "2oLKf83nZk1tq7LdKWiickcxuN4LCFku65S13WSXqK7Rnm8sW3jgofUomALbqhSbp1LYgtXF54guYpRG");
+        roundtrips(&addr);
+    }
+
+    #[test]
+    fn test_colored_from_key() {
+        let key = hex_key!(&"03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f");
+        let color_id = ColorIdentifier::reissuable(hex_script!("51"));
+        let addr = Address::cp2pkh(&key, &color_id, Dev);
+        let uncolored = Address::p2pkh(&key, Dev);
+        assert!(addr.is_colored());
+        assert!(!uncolored.is_colored());
+        assert_eq!(addr.script_pubkey(), uncolored.script_pubkey().add_color(color_id.clone()).unwrap());
+        roundtrips(&addr);
+
+        let addr = Address::cp2sh(&hex_script!("51"), &color_id, Dev);
+        assert_eq!(addr.script_pubkey(), Address::p2sh(&hex_script!("51"), Dev).script_pubkey().add_color(color_id).unwrap());
+        roundtrips(&addr);
+    }
+
+    #[test]
+    fn test_from_script() {
+        let scripts = [
+            "76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac",
+            "a914162c5ea71c0b23f5b9022ef047c4a86470a5b07087",
+            "21c14f18505f71db5ff7deca8ee1d5185c8da27b326a7f736b5fe819724c62c9dd53bc76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac",
+            "21c14f18505f71db5ff7deca8ee1d5185c8da27b326a7f736b5fe819724c62c9dd53bca914162c5ea71c0b23f5b9022ef047c4a86470a5b07087",
+        ];
+        for script in scripts.iter() {
+            let script = hex_script!(script);
+            let addr = Address::from_script(&script, Dev).unwrap();
+            assert_eq!(addr.script_pubkey(), script);
+            assert_eq!(addr.network, Dev);
+        }
+
+        // non-standard scripts, and a colored script with an invalid token type
+        for script in ["51", "6a", "21c44f18505f71db5ff7deca8ee1d5185c8da27b326a7f736b5fe819724c62c9dd53bca914162c5ea71c0b23f5b9022ef047c4a86470a5b07087"].iter() {
+            assert_eq!(Address::from_script(&hex_script!(script), Dev), None);
+        }
+    }
+
+    #[test]
+    fn test_is_valid_for_network() {
+        let key = hex_key!(&"03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f");
+        let color_id = ColorIdentifier::reissuable(hex_script!("51"));
+        let paradium = Network::Custom(NetworkParams::dev_with_id(101));
+        let custom = Network::Custom(NetworkParams { p2pkh_prefix: 50, ..NetworkParams::dev_with_id(102) });
+
+        let addr = Address::p2pkh(&key, Dev);
+        assert!(addr.is_valid_for_network(Dev));
+        assert!(addr.is_valid_for_network(paradium));
+        assert!(!addr.is_valid_for_network(Prod));
+        assert!(!addr.is_valid_for_network(custom));
+
+        // only the version byte of the address type matters
+        let addr = Address::p2sh(&hex_script!("51"), Dev);
+        assert!(addr.is_valid_for_network(custom));
+        assert!(!addr.is_valid_for_network(Prod));
+
+        let addr = Address::cp2pkh(&key, &color_id, Prod);
+        assert!(addr.is_valid_for_network(Prod));
+        assert!(!addr.is_valid_for_network(Dev));
+        let addr = Address::cp2sh(&hex_script!("51"), &color_id, Dev);
+        assert!(addr.is_valid_for_network(paradium));
+        assert!(!addr.is_valid_for_network(Prod));
+    }
+
+    #[test]
+    fn test_require_network() {
+        let addr = Address::from_str("132F25rTsvBdp9JzLLBHP5mvGY66i1xdiM").unwrap();
+        assert_eq!(addr.network, Prod);
+        assert_eq!(addr.address_type(), Some(AddressType::P2pkh));
+        assert!(!AddressType::P2pkh.is_colored());
+        assert_eq!(addr.clone().require_network(Prod), Ok(addr.clone()));
+        assert_eq!(
+            addr.clone().require_network(Dev),
+            Err(Error::NetworkMismatch { expected: Dev, found: Prod })
+        );
+
+        // addresses of custom development networks parse as development ones
+        let paradium = Network::Custom(NetworkParams::dev_with_id(101));
+        let addr = Address::p2sh(&hex_script!("51"), paradium);
+        let parsed = Address::from_str(&addr.to_string()).unwrap();
+        assert_eq!(parsed.network, Dev);
+        assert_eq!(parsed.require_network(paradium), Ok(addr));
+
+        // unknown version bytes
+        assert_eq!(
+            Address::from_str("LKDxGDJq5fF4FohAB8zJH24mDDNHDNtqsE"),
+            Err(Error::Base58(base58::Error::InvalidVersion(vec![0x30])))
+        );
+    }
+
+    #[test]
+    fn test_unknown_address() {
+        let s = "LKDxGDJq5fF4FohAB8zJH24mDDNHDNtqsE";
+        assert!(Address::from_str(s).is_err());
+        let addr = Address::from_str_allow_unknown(s, Prod).unwrap();
+        assert_eq!(addr, ParsedAddress::Unknown { version: 0x30, payload: vec![0; 20] });
+        assert_eq!(addr.to_string(), s);
+
+        // known addresses parse as usual
+        let s = "132F25rTsvBdp9JzLLBHP5mvGY66i1xdiM";
+        let known = Address::from_str(s).unwrap();
+        assert_eq!(Address::from_str_allow_unknown(s, Prod), Ok(ParsedAddress::Known(known)));
+        // including the errors unrelated to the version
+        assert!(Address::from_str_allow_unknown("132F25rTsvBdp9JzLLBHP5mvGY66i1xdiN", Prod).is_err());
+
+        // an address of another network is unknown on this one
+        let addr = Address::from_str_allow_unknown(s, Dev).unwrap();
+        assert_eq!(addr, ParsedAddress::Unknown { version: 0x00, payload: known.script_pubkey()[3..23].to_vec() });
+        assert_eq!(addr.to_string(), s);
+
+        // any length round-trips
+        let addr = ParsedAddress::Unknown { version: 0x42, payload: vec![1; 40] };
+        assert_eq!(Address::from_str_allow_unknown(&addr.to_string(), Dev), Ok(addr));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_network() {
+        use serde_json;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct T {
+            #[serde(with = "::util::address::serde::prod")]
+            prod: Address,
+            #[serde(with = "::util::address::serde::dev")]
+            dev: Address,
+        }
+
+        let prod = "132F25rTsvBdp9JzLLBHP5mvGY66i1xdiM";
+        let dev = "2N3zXjbwdTcPsJiy8sUK9FhWJhqQCxA8Jjr";
+        let json = format!("{{\"prod\":\"{}\",\"dev\":\"{}\"}}", prod, dev);
+        let t: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(t.prod, Address::from_str(prod).unwrap());
+        assert_eq!(t.dev, Address::from_str(dev).unwrap());
+        assert_eq!(serde_json::to_string(&t).unwrap(), json);
+
+        let mixed = format!("{{\"prod\":\"{}\",\"dev\":\"{}\"}}", dev, dev);
+        assert!(serde_json::from_str::<T>(&mixed).is_err());
+    }
+
     #[test]
     #[cfg(feature = "serde")]
     fn test_json_serialize() {
@@ -13,16 +13,26 @@
 //! This module mainly introduces the [Amount] and [SignedAmount] types.
 //! We refer to the documentation on the types for more information.
 //!
+//! Amounts of TPC are counted in tapyrus, the smallest unit, just as bitcoins
+//! are counted in satoshis: one TPC is 100,000,000 tapyrus. The Bitcoin
+//! denominations are kept since they share the same precisions.
+//!
+
+use core::default;
+use core::fmt::{self, Write};
+use core::ops;
+use core::str::FromStr;
+#[cfg(feature = "std")] use std::error;
 
-use std::default;
-use std::error;
-use std::fmt::{self, Write};
-use std::ops;
-use std::str::FromStr;
+use prelude::*;
 
 /// A set of denominations in which amounts can be expressed.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Denomination {
+    /// TPC
+    Tpc,
+    /// tapyrus, the smallest unit of TPC
+    Tapyrus,
     /// BTC
     Bitcoin,
     /// mBTC
@@ -41,6 +51,8 @@ impl Denomination {
     /// The number of decimal places more than a satoshi.
     fn precision(self) -> i32 {
         match self {
+            Denomination::Tpc => -8,
+            Denomination::Tapyrus => 0,
             Denomination::Bitcoin => -8,
             Denomination::MilliBitcoin => -5,
             Denomination::MicroBitcoin => -2,
@@ -54,6 +66,8 @@ impl Denomination {
 impl fmt::Display for Denomination {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(match *self {
+            Denomination::Tpc => "TPC",
+            Denomination::Tapyrus => "tapyrus",
             Denomination::Bitcoin => "BTC",
             Denomination::MilliBitcoin => "mBTC",
             Denomination::MicroBitcoin => "uBTC",
@@ -69,6 +83,8 @@ impl FromStr for Denomination {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "TPC" => Ok(Denomination::Tpc),
+            "tapyrus" => Ok(Denomination::Tapyrus),
             "BTC" => Ok(Denomination::Bitcoin),
             "mBTC" => Ok(Denomination::MilliBitcoin),
             "uBTC" => Ok(Denomination::MicroBitcoin),
@@ -102,31 +118,23 @@ pub enum ParseAmountError {
 
 impl fmt::Display for ParseAmountError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let desc = ::std::error::Error::description(self);
         match *self {
-            ParseAmountError::InvalidCharacter(c) => write!(f, "{}: {}", desc, c),
-            ParseAmountError::UnknownDenomination(ref d) => write!(f, "{}: {}", desc, d),
-            _ => f.write_str(desc),
+            ParseAmountError::Negative => f.write_str("amount is negative"),
+            ParseAmountError::TooBig => f.write_str("amount is too big"),
+            ParseAmountError::TooPrecise => f.write_str("amount has a too high precision"),
+            ParseAmountError::InvalidFormat => f.write_str("invalid number format"),
+            ParseAmountError::InputTooLarge => f.write_str("input string was too large"),
+            ParseAmountError::InvalidCharacter(c) => write!(f, "invalid character in input: {}", c),
+            ParseAmountError::UnknownDenomination(ref d) => write!(f, "unknown denomination: {}", d),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for ParseAmountError {
     fn cause(&self) -> Option<&error::Error> {
         None
     }
-
-    fn description(&self) -> &'static str {
-        match *self {
-            ParseAmountError::Negative => "amount is negative",
-            ParseAmountError::TooBig => "amount is too big",
-            ParseAmountError::TooPrecise => "amount has a too high precision",
-            ParseAmountError::InvalidFormat => "invalid number format",
-            ParseAmountError::InputTooLarge => "input string was too large",
-            ParseAmountError::InvalidCharacter(_) => "invalid character in input",
-            ParseAmountError::UnknownDenomination(_) => "unknown denomination",
-        }
-    }
 }
 
 
@@ -256,7 +264,7 @@ fn fmt_satoshi_in(
 
 /// Amount
 ///
-/// The [Amount] type can be used to express Bitcoin amounts that supports
+/// The [Amount] type can be used to express TPC amounts that supports
 /// arithmetic and conversion to various denominations.
 ///
 ///
@@ -281,6 +289,8 @@ impl Amount {
     pub const ONE_SAT: Amount = Amount(1);
     /// Exactly one bitcoin.
     pub const ONE_BTC: Amount = Amount(100_000_000);
+    /// Exactly one TPC.
+    pub const ONE_TPC: Amount = Amount(100_000_000);
 
     /// Create an [Amount] with satoshi precision and the given number of satoshis.
     pub fn from_sat(satoshi: u64) -> Amount {
@@ -307,6 +317,11 @@ impl Amount {
         Amount::from_float_in(btc, Denomination::Bitcoin)
     }
 
+    /// Convert from a value expressing TPC to an [Amount].
+    pub fn from_tpc(tpc: f64) -> Result<Amount, ParseAmountError> {
+        Amount::from_float_in(tpc, Denomination::Tpc)
+    }
+
     /// Parse a decimal string as a value in the given denomination.
     ///
     /// Note: This only parses the value string.  If you want to parse a value
@@ -353,6 +368,15 @@ impl Amount {
         self.to_float_in(Denomination::Bitcoin)
     }
 
+    /// Express this [Amount] as a floating-point value in TPC.
+    ///
+    /// Equivalent to `to_float_in(Denomination::Tpc)`.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn as_tpc(&self) -> f64 {
+        self.to_float_in(Denomination::Tpc)
+    }
+
     /// Convert this [Amount] in floating-point notation with a given
     /// denomination.
     /// Can return error if the amount is too big, too precise or negative.
@@ -450,13 +474,13 @@ impl PartialEq for Amount {
 impl Eq for Amount {}
 
 impl PartialOrd for Amount {
-    fn partial_cmp(&self, other: &Amount) -> Option<::std::cmp::Ordering> {
+    fn partial_cmp(&self, other: &Amount) -> Option<::core::cmp::Ordering> {
         PartialOrd::partial_cmp(&self.0, &other.0)
     }
 }
 
 impl Ord for Amount {
-    fn cmp(&self, other: &Amount) -> ::std::cmp::Ordering {
+    fn cmp(&self, other: &Amount) -> ::core::cmp::Ordering {
         Ord::cmp(&self.0, &other.0)
     }
 }
@@ -468,11 +492,11 @@ impl fmt::Debug for Amount {
 }
 
 // No one should depend on a binding contract for Display for this type.
-// Just using Bitcoin denominated string.
+// Just using TPC denominated string.
 impl fmt::Display for Amount {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.fmt_value_in(f, Denomination::Bitcoin)?;
-        write!(f, " {}", Denomination::Bitcoin)
+        self.fmt_value_in(f, Denomination::Tpc)?;
+        write!(f, " {}", Denomination::Tpc)
     }
 }
 
@@ -556,7 +580,7 @@ impl FromStr for Amount {
 
 /// SignedAmount
 ///
-/// The [SignedAmount] type can be used to express Bitcoin amounts that supports
+/// The [SignedAmount] type can be used to express TPC amounts that supports
 /// arithmetic and conversion to various denominations.
 ///
 ///
@@ -578,6 +602,8 @@ impl SignedAmount {
     pub const ONE_SAT: SignedAmount = SignedAmount(1);
     /// Exactly one bitcoin.
     pub const ONE_BTC: SignedAmount = SignedAmount(100_000_000);
+    /// Exactly one TPC.
+    pub const ONE_TPC: SignedAmount = SignedAmount(100_000_000);
 
     /// Create an [SignedAmount] with satoshi precision and the given number of satoshis.
     pub fn from_sat(satoshi: i64) -> SignedAmount {
@@ -604,6 +630,11 @@ impl SignedAmount {
         SignedAmount::from_float_in(btc, Denomination::Bitcoin)
     }
 
+    /// Convert from a value expressing TPC to an [SignedAmount].
+    pub fn from_tpc(tpc: f64) -> Result<SignedAmount, ParseAmountError> {
+        SignedAmount::from_float_in(tpc, Denomination::Tpc)
+    }
+
     /// Parse a decimal string as a value in the given denomination.
     ///
     /// Note: This only parses the value string.  If you want to parse a value
@@ -650,6 +681,15 @@ impl SignedAmount {
         self.to_float_in(Denomination::Bitcoin)
     }
 
+    /// Express this [SignedAmount] as a floating-point value in TPC.
+    ///
+    /// Equivalent to `to_float_in(Denomination::Tpc)`.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn as_tpc(&self) -> f64 {
+        self.to_float_in(Denomination::Tpc)
+    }
+
     /// Convert this [SignedAmount] in floating-point notation with a given
     /// denomination.
     /// Can return error if the amount is too big, too precise or negative.
@@ -783,13 +823,13 @@ impl PartialEq for SignedAmount {
 impl Eq for SignedAmount {}
 
 impl PartialOrd for SignedAmount {
-    fn partial_cmp(&self, other: &SignedAmount) -> Option<::std::cmp::Ordering> {
+    fn partial_cmp(&self, other: &SignedAmount) -> Option<::core::cmp::Ordering> {
         PartialOrd::partial_cmp(&self.0, &other.0)
     }
 }
 
 impl Ord for SignedAmount {
-    fn cmp(&self, other: &SignedAmount) -> ::std::cmp::Ordering {
+    fn cmp(&self, other: &SignedAmount) -> ::core::cmp::Ordering {
         Ord::cmp(&self.0, &other.0)
     }
 }
@@ -801,11 +841,11 @@ impl fmt::Debug for SignedAmount {
 }
 
 // No one should depend on a binding contract for Display for this type.
-// Just using Bitcoin denominated string.
+// Just using TPC denominated string.
 impl fmt::Display for SignedAmount {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.fmt_value_in(f, Denomination::Bitcoin)?;
-        write!(f, " {}", Denomination::Bitcoin)
+        self.fmt_value_in(f, Denomination::Tpc)?;
+        write!(f, " {}", Denomination::Tpc)
     }
 }
 
@@ -1034,6 +1074,48 @@ pub mod serde {
             }
         }
     }
+
+    pub mod as_tpc {
+        //! Serialize and deserialize [Amount] as JSON numbers denominated in TPC,
+        //! as Tapyrus Core RPCs do.
+        //! Use with `#[serde(with = "amount::serde::as_tpc")]`.
+
+        use serde::{Deserializer, Serializer};
+        use util::amount::serde::SerdeAmount;
+
+        // TPC has the precision of BTC
+        pub fn serialize<A: SerdeAmount, S: Serializer>(a: &A, s: S) -> Result<S::Ok, S::Error> {
+            a.ser_btc(s)
+        }
+
+        pub fn deserialize<'d, A: SerdeAmount, D: Deserializer<'d>>(d: D) -> Result<A, D::Error> {
+            A::des_btc(d)
+        }
+
+        pub mod opt {
+            //! Serialize and deserialize [Option<Amount>] as JSON numbers denominated in TPC.
+            //! Use with `#[serde(default, with = "amount::serde::as_tpc::opt")]`.
+
+            use serde::{Deserializer, Serializer};
+            use util::amount::serde::SerdeAmount;
+
+            pub fn serialize<A: SerdeAmount, S: Serializer>(
+                a: &Option<A>,
+                s: S,
+            ) -> Result<S::Ok, S::Error> {
+                match *a {
+                    Some(a) => a.ser_btc(s),
+                    None => s.serialize_none(),
+                }
+            }
+
+            pub fn deserialize<'d, A: SerdeAmount, D: Deserializer<'d>>(
+                d: D,
+            ) -> Result<Option<A>, D::Error> {
+                Ok(Some(A::des_btc(d)?))
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1228,6 +1310,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tpc_denominations() {
+        use super::Denomination as D;
+
+        assert_eq!(Amount::ONE_TPC, Amount::from_sat(100_000_000));
+        assert_eq!(Amount::ONE_TPC.to_string(), "1.00000000 TPC");
+        assert_eq!(SignedAmount::from_sat(-42).to_string(), "-0.00000042 TPC");
+        assert_eq!(Amount::ONE_SAT.to_string_with_denomination(D::Tapyrus), "1 tapyrus");
+        assert_eq!(Amount::from_tpc(0.5).unwrap().as_tpc(), 0.5);
+        assert_eq!(SignedAmount::from_tpc(-0.5).unwrap().as_sat(), -50_000_000);
+
+        assert_eq!(Amount::from_str("0.00253583 TPC"), Ok(Amount::from_sat(253583)));
+        assert_eq!(Amount::from_str("42 tapyrus"), Ok(Amount::from_sat(42)));
+        assert_eq!(Amount::from_str_in("0.1", D::Tpc), Ok(Amount::from_sat(10_000_000)));
+        assert_eq!(Amount::from_str_in("0.1", D::Tapyrus), Err(ParseAmountError::TooPrecise));
+        let amt = Amount::from_sat(42);
+        assert_eq!(Amount::from_str(&amt.to_string()), Ok(amt));
+        assert_eq!(Amount::from_str(&amt.to_string_with_denomination(D::Tapyrus)), Ok(amt));
+    }
+
     #[test]
     fn from_str() {
         use super::ParseAmountError as E;
@@ -1348,6 +1450,30 @@ mod tests {
             .contains(&ParseAmountError::Negative.to_string()));
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_as_tpc() {
+        use serde_json;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct T {
+            #[serde(with = "::util::amount::serde::as_tpc")]
+            pub amt: Amount,
+            #[serde(default, with = "::util::amount::serde::as_tpc::opt")]
+            pub samt: Option<SignedAmount>,
+        }
+
+        let orig = T {
+            amt: Amount::from_sat(2__500_000_01),
+            samt: Some(SignedAmount::from_sat(-2__500_000_00)),
+        };
+        let t: T = serde_json::from_str("{\"amt\": 2.50000001, \"samt\": -2.5}").unwrap();
+        assert_eq!(t, orig);
+        assert_eq!(serde_json::from_str::<T>(&serde_json::to_string(&orig).unwrap()).unwrap(), orig);
+        let t: T = serde_json::from_str("{\"amt\": 1}").unwrap();
+        assert_eq!(t, T { amt: Amount::ONE_TPC, samt: None });
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn serde_as_btc_opt() {
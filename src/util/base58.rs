@@ -14,10 +14,12 @@
 
 //! Base58 encoder and decoder
 
-use std::{error, fmt, iter, slice, str};
+use core::{fmt, iter, slice, str};
+#[cfg(feature = "std")] use std::error;
 
-use hashes::{sha256d, Hash};
+use hashes::{sha256d, Hash, HashEngine};
 
+use prelude::*;
 use util::endian;
 
 /// An error that might occur during base58 decoding
@@ -58,6 +60,7 @@ impl fmt::Display for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         None
@@ -368,6 +371,35 @@ pub fn check_encode_slice_to_fmt(fmt: &mut fmt::Formatter, data: &[u8]) -> fmt::
     format_iter(fmt, iter)
 }
 
+/// Obtain a string with the base58check encoding of `payload`, prefixed
+/// by the `version` bytes identifying its kind, as for addresses and keys
+pub fn encode_check_with_version(version: &[u8], payload: &[u8]) -> String {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(version);
+    engine.input(payload);
+    let checksum = sha256d::Hash::from_engine(engine);
+    encode_iter(
+        version.iter().cloned()
+            .chain(payload.iter().cloned())
+            .chain(checksum[0..4].iter().cloned())
+    )
+}
+
+/// Decode a base58check-encoded string prefixed by the `version` bytes,
+/// returning the payload which follows them. Fails with
+/// `Error::InvalidVersion` if the string has other version bytes.
+pub fn decode_check_expect_version(data: &str, version: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut ret = from_check(data)?;
+    if ret.len() < version.len() {
+        return Err(Error::InvalidLength(ret.len()));
+    }
+    if &ret[..version.len()] != version {
+        return Err(Error::InvalidVersion(ret[..version.len()].to_vec()));
+    }
+    ret.drain(..version.len());
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -423,6 +455,26 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_base58_version() {
+        let payload = hex_decode("f8917303bfa8ef24f292e8fa1419b20460ba064d").unwrap();
+        let s = encode_check_with_version(&[0], &payload);
+        assert_eq!(&s, "1PfJpZsjreyVrqeoAfabrRwwjQyoSQMmHH");
+        assert_eq!(decode_check_expect_version(&s, &[0]), Ok(payload.clone()));
+        assert_eq!(decode_check_expect_version(&s, &[5]), Err(Error::InvalidVersion(vec![0])));
+
+        // multi-byte versions, as those of BIP32 keys
+        let version = [0x04, 0x88, 0xB2, 0x1E];
+        let s = encode_check_with_version(&version, &payload);
+        assert_eq!(s, check_encode_slice(&[&version[..], &payload[..]].concat()));
+        assert_eq!(decode_check_expect_version(&s, &version), Ok(payload));
+        assert_eq!(decode_check_expect_version(&s, &[0x04, 0x88, 0xAD, 0xE4]),
+                   Err(Error::InvalidVersion(version.to_vec())));
+        assert_eq!(decode_check_expect_version(&check_encode_slice(&[4, 0x88]), &version),
+                   Err(Error::InvalidLength(2)));
+        assert_eq!(decode_check_expect_version(&check_encode_slice(&[]), &[]), Ok(vec![]));
+    }
+
     #[test]
     fn test_base58_roundtrip() {
         let s = "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs";
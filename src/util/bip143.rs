@@ -71,7 +71,7 @@ impl SighashComponents {
 
         SighashComponents {
             tx_version: tx.version,
-            tx_locktime: tx.lock_time,
+            tx_locktime: tx.lock_time.into(),
             hash_prevouts: hash_prevouts,
             hash_sequence: hash_sequence,
             hash_outputs: hash_outputs,
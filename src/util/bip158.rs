@@ -63,6 +63,7 @@ use blockdata::script::Script;
 use blockdata::transaction::OutPoint;
 use consensus::{Decodable, Encodable};
 use consensus::encode::VarInt;
+use network::message_filter::{CFCheckpt, CFHeaders, BASIC_FILTER_TYPE, CHECKPOINT_INTERVAL};
 use util::hash::BitcoinHash;
 use util::endian;
 
@@ -75,6 +76,8 @@ const M: u64 = 784931;
 pub enum Error {
     /// missing UTXO, can not calculate script filter
     UtxoMissing(OutPoint),
+    /// more scripts than the inputs of the block spend
+    ExtraPrevoutScripts,
     /// some IO error reading or writing binary serialization of the filter
     Io(io::Error),
 }
@@ -83,6 +86,7 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::UtxoMissing(_) => "unresolved UTXO",
+            Error::ExtraPrevoutScripts => "more prevout scripts than inputs",
             Error::Io(_) => "IO Error"
         }
     }
@@ -92,6 +96,7 @@ impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         match *self {
             Error::UtxoMissing(ref coin) => write!(f, "unresolved UTXO {}", coin),
+            Error::ExtraPrevoutScripts => write!(f, "more prevout scripts than inputs"),
             Error::Io(ref io) => write!(f, "{}", io)
         }
     }
@@ -113,11 +118,18 @@ pub struct BlockFilter {
 impl BlockFilter {
     /// compute this filter's id in a chain of filters
     pub fn filter_id(&self, previous_filter_id: &FilterHash) -> FilterHash {
-        let filter_hash = FilterHash::hash(self.content.as_slice());
-        let mut header_data = [0u8; 64];
-        header_data[0..32].copy_from_slice(&filter_hash[..]);
-        header_data[32..64].copy_from_slice(&previous_filter_id[..]);
-        FilterHash::hash(&header_data)
+        self.filter_header(previous_filter_id)
+    }
+
+    /// compute this filter's hash, as sent in `cfheaders` messages
+    pub fn filter_hash(&self) -> FilterHash {
+        FilterHash::hash(self.content.as_slice())
+    }
+
+    /// compute this filter's header, chained onto the filter header of the
+    /// previous block, as BIP157 defines it
+    pub fn filter_header(&self, previous_filter_header: &FilterHash) -> FilterHash {
+        filter_header(&self.filter_hash(), previous_filter_header)
     }
 
     /// create a new filter from pre-computed data
@@ -138,6 +150,20 @@ impl BlockFilter {
         Ok(BlockFilter { content: out.into_inner() })
     }
 
+    /// Compute the basic filter of a block, as Tapyrus Core does, from the
+    /// scripts spent by the inputs of the block but the coinbase, in the
+    /// order of the inputs
+    pub fn from_block(block: &Block, prevout_scripts: &[Script]) -> Result<BlockFilter, Error> {
+        let mut out = Cursor::new(Vec::new());
+        {
+            let mut writer = BlockFilterWriter::new(&mut out, block);
+            writer.add_output_scripts();
+            writer.add_prevout_scripts(prevout_scripts)?;
+            writer.finish()?;
+        }
+        Ok(BlockFilter { content: out.into_inner() })
+    }
+
     /// match any query pattern
     pub fn match_any(&self, block_hash: &BlockHash, query: &mut Iterator<Item=&[u8]>) -> Result<bool, Error> {
         let filter_reader = BlockFilterReader::new(block_hash);
@@ -149,6 +175,22 @@ impl BlockFilter {
         let filter_reader = BlockFilterReader::new(block_hash);
         filter_reader.match_all(&mut Cursor::new(self.content.as_slice()), query)
     }
+
+    /// Whether any of `query_scripts` is in this filter of the block of
+    /// `block_hash`, e.g. whether a light client should fetch the block to
+    /// find the transactions of its wallet. As with `match_any`, an empty
+    /// query matches any filter.
+    pub fn match_any_script<'a, I>(&self, block_hash: &BlockHash, query_scripts: I) -> Result<bool, Error>
+        where I: IntoIterator<Item=&'a Script> {
+        self.match_any(block_hash, &mut query_scripts.into_iter().map(Script::as_bytes))
+    }
+
+    /// Whether all of `query_scripts` are in this filter of the block of
+    /// `block_hash`. An empty query matches any filter.
+    pub fn match_all_scripts<'a, I>(&self, block_hash: &BlockHash, query_scripts: I) -> Result<bool, Error>
+        where I: IntoIterator<Item=&'a Script> {
+        self.match_all(block_hash, &mut query_scripts.into_iter().map(Script::as_bytes))
+    }
 }
 
 /// Compiles and writes a block filter
@@ -193,6 +235,22 @@ impl<'a> BlockFilterWriter<'a> {
         Ok(())
     }
 
+    /// Add the scripts spent by the inputs of the block but the coinbase,
+    /// given in the order of the inputs
+    pub fn add_prevout_scripts(&mut self, prevout_scripts: &[Script]) -> Result<(), Error> {
+        let mut scripts = prevout_scripts.iter();
+        for input in self.block.txdata.iter().skip(1).flat_map(|t| t.input.iter()) {
+            match scripts.next() {
+                Some(script) => self.writer.add_element(script.as_bytes()),
+                None => return Err(Error::UtxoMissing(input.previous_output)),
+            }
+        }
+        if scripts.next().is_some() {
+            return Err(Error::ExtraPrevoutScripts);
+        }
+        Ok(())
+    }
+
     /// Add arbitrary element to a filter
     pub fn add_element(&mut self, data: &[u8]) {
         self.writer.add_element(data);
@@ -325,6 +383,161 @@ impl GCSFilterReader {
     }
 }
 
+/// Compute a filter header from the filter hash and the previous header
+pub(crate) fn filter_header(filter_hash: &FilterHash, previous: &FilterHash) -> FilterHash {
+    let mut data = [0u8; 64];
+    data[0..32].copy_from_slice(&filter_hash[..]);
+    data[32..64].copy_from_slice(&previous[..]);
+    FilterHash::hash(&data)
+}
+
+/// An inconsistency found in the filter headers, or filters, served by a
+/// peer, which should then be disconnected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FilterHeaderError {
+    /// The filters are not basic filters
+    UnexpectedFilterType(u8),
+    /// The headers do not follow the filter header at the height before
+    /// their start height, or the headers are not known up to this height
+    Unconnected(u32),
+    /// The filter header at this height differs from the one known
+    HeaderMismatch(u32),
+    /// The filter header at this height differs from the checkpoint
+    CheckpointMismatch(u32),
+}
+
+impl error::Error for FilterHeaderError {
+    fn description(&self) -> &str {
+        match *self {
+            FilterHeaderError::UnexpectedFilterType(_) => "unexpected filter type",
+            FilterHeaderError::Unconnected(_) => "unconnected filter headers",
+            FilterHeaderError::HeaderMismatch(_) => "filter header mismatch",
+            FilterHeaderError::CheckpointMismatch(_) => "filter header checkpoint mismatch",
+        }
+    }
+}
+
+impl Display for FilterHeaderError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FilterHeaderError::UnexpectedFilterType(filter_type) => write!(f, "unexpected filter type {}", filter_type),
+            FilterHeaderError::Unconnected(height) => write!(f, "unconnected filter headers at height {}", height),
+            FilterHeaderError::HeaderMismatch(height) => write!(f, "filter header mismatch at height {}", height),
+            FilterHeaderError::CheckpointMismatch(height) => write!(f, "filter header checkpoint mismatch at height {}", height),
+        }
+    }
+}
+
+/// The chain of basic filter headers from the genesis block, as a light
+/// client learns it from `cfcheckpt` and `cfheaders` messages. Headers are
+/// only accepted if they follow the known ones and agree with the
+/// checkpoints, so that peers serving other filter headers are detected.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct FilterHeaderChain {
+    /// The filter header of each block, from the genesis block
+    headers: Vec<FilterHash>,
+    /// The filter header of every `CHECKPOINT_INTERVAL`th block
+    checkpoints: Vec<FilterHash>,
+}
+
+impl FilterHeaderChain {
+    /// Create an empty chain of filter headers
+    pub fn new() -> FilterHeaderChain {
+        FilterHeaderChain::default()
+    }
+
+    /// The height of the last known filter header, `None` if none is known
+    pub fn tip_height(&self) -> Option<u32> {
+        (self.headers.len() as u32).checked_sub(1)
+    }
+
+    /// The filter header of the block at `height`, if known
+    pub fn header(&self, height: u32) -> Option<&FilterHash> {
+        self.headers.get(height as usize)
+    }
+
+    /// The checkpoints known, the filter headers of every
+    /// `CHECKPOINT_INTERVAL`th block
+    pub fn checkpoints(&self) -> &[FilterHash] {
+        &self.checkpoints
+    }
+
+    /// Add the checkpoints of a `cfcheckpt` message. They must agree with
+    /// the checkpoints and headers already known.
+    pub fn add_checkpoints(&mut self, cfcheckpt: &CFCheckpt) -> Result<(), FilterHeaderError> {
+        if cfcheckpt.filter_type != BASIC_FILTER_TYPE {
+            return Err(FilterHeaderError::UnexpectedFilterType(cfcheckpt.filter_type));
+        }
+        for (index, checkpoint) in cfcheckpt.filter_headers.iter().enumerate() {
+            let height = ((index + 1) * CHECKPOINT_INTERVAL) as u32;
+            let known = self.checkpoints.get(index).or_else(|| self.header(height));
+            if known.map_or(false, |known| known != checkpoint) {
+                return Err(FilterHeaderError::CheckpointMismatch(height));
+            }
+        }
+        if cfcheckpt.filter_headers.len() > self.checkpoints.len() {
+            self.checkpoints = cfcheckpt.filter_headers.clone();
+        }
+        Ok(())
+    }
+
+    /// Add the headers of a `cfheaders` message, answering a `getcfheaders`
+    /// message of `start_height`. They must follow the header at the
+    /// height before, and agree with the headers and checkpoints already
+    /// known. Returns the number of headers added.
+    pub fn add_headers(&mut self, start_height: u32, cfheaders: &CFHeaders) -> Result<usize, FilterHeaderError> {
+        if cfheaders.filter_type != BASIC_FILTER_TYPE {
+            return Err(FilterHeaderError::UnexpectedFilterType(cfheaders.filter_type));
+        }
+        let previous = match start_height.checked_sub(1) {
+            None => Some(FilterHash::default()),
+            Some(height) => self.header(height).cloned(),
+        };
+        if previous != Some(cfheaders.previous_filter) {
+            return Err(FilterHeaderError::Unconnected(start_height));
+        }
+
+        let headers = cfheaders.filter_headers();
+        for (height, header) in (start_height..).zip(headers.iter()) {
+            if self.header(height).map_or(false, |known| known != header) {
+                return Err(FilterHeaderError::HeaderMismatch(height));
+            }
+            if let Some(checkpoint) = self.checkpoint(height) {
+                if checkpoint != header {
+                    return Err(FilterHeaderError::CheckpointMismatch(height));
+                }
+            }
+        }
+        let known = self.headers.len() - start_height as usize;
+        let added = headers.len().saturating_sub(known);
+        self.headers.extend(headers.into_iter().skip(known));
+        Ok(added)
+    }
+
+    /// Check that `filter`, received for the block at `height`, is the one
+    /// committed to by the filter headers
+    pub fn verify_filter(&self, height: u32, filter: &BlockFilter) -> Result<(), FilterHeaderError> {
+        let previous = match height.checked_sub(1) {
+            None => Some(FilterHash::default()),
+            Some(height) => self.header(height).cloned(),
+        };
+        match (previous, self.header(height)) {
+            (Some(previous), Some(header)) if filter.filter_header(&previous) == *header => Ok(()),
+            (Some(_), Some(_)) => Err(FilterHeaderError::HeaderMismatch(height)),
+            _ => Err(FilterHeaderError::Unconnected(height)),
+        }
+    }
+
+    /// The checkpoint at `height`, if it is a known checkpoint
+    fn checkpoint(&self, height: u32) -> Option<&FilterHash> {
+        let height = height as usize;
+        if height == 0 || height % CHECKPOINT_INTERVAL != 0 {
+            return None;
+        }
+        self.checkpoints.get(height / CHECKPOINT_INTERVAL - 1)
+    }
+}
+
 // fast reduction of hash to [0, nm) range
 fn map_to_range(hash: u64, nm: u64) -> u64 {
     // Use this once we upgrade to rustc >= 1.26
@@ -538,6 +751,9 @@ mod test {
     extern crate serde_json;
     use self::serde_json::{Value};
 
+    use blockdata::block::{BlockHeader, XField};
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{Transaction, TxIn, TxOut};
     use consensus::encode::deserialize;
 
     #[test]
@@ -606,6 +822,126 @@ mod test {
         }
     }
 
+    fn test_block() -> Block {
+        let spending = |vout| TxIn {
+            previous_output: OutPoint { txid: Default::default(), vout: vout },
+            ..Default::default()
+        };
+        let tx = |input, output| Transaction { version: 1, lock_time: LockTime::ZERO, input: input, output: output };
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                im_merkle_root: Default::default(),
+                time: 0,
+                xfield: XField::None,
+                proof: None,
+            },
+            txdata: vec![
+                tx(vec![TxIn::default()], vec![TxOut { value: 50, script_pubkey: Script::from(vec![0x51]) }]),
+                tx(vec![spending(0), spending(1)], vec![
+                    TxOut { value: 10, script_pubkey: Script::from(vec![0x52]) },
+                    TxOut { value: 0, script_pubkey: Script::from(vec![0x6a, 0x01, 0x53]) },
+                    TxOut { value: 0, script_pubkey: Script::new() },
+                ]),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_from_block() {
+        let block = test_block();
+        let prevout_scripts = vec![Script::from(vec![0x54]), Script::new()];
+        let filter = BlockFilter::from_block(&block, &prevout_scripts).unwrap();
+        let expected = BlockFilter::new_script_filter(&block, |o| Ok(prevout_scripts[o.vout as usize].clone())).unwrap();
+        assert_eq!(filter.content, expected.content);
+
+        // empty and OP_RETURN scripts are left out
+        let block_hash = block.bitcoin_hash();
+        let mut query = [vec![0x51], vec![0x52], vec![0x54]];
+        assert!(filter.match_all(&block_hash, &mut query.iter().map(|q| q.as_slice())).unwrap());
+        query[0] = vec![0x6a, 0x01, 0x53];
+        assert!(!filter.match_any(&block_hash, &mut query[..1].iter().map(|q| q.as_slice())).unwrap());
+
+        match BlockFilter::from_block(&block, &prevout_scripts[..1]) {
+            Err(Error::UtxoMissing(coin)) => assert_eq!(coin.vout, 1),
+            r => panic!("unexpected {:?}", r.map(|f| f.content)),
+        }
+        let mut extra = prevout_scripts.clone();
+        extra.push(Script::new());
+        match BlockFilter::from_block(&block, &extra) {
+            Err(Error::ExtraPrevoutScripts) => {}
+            r => panic!("unexpected {:?}", r.map(|f| f.content)),
+        }
+
+        // filter headers chain the filter hashes
+        let previous = FilterHash::hash(&[1u8]);
+        let mut data = filter.filter_hash().into_inner().to_vec();
+        data.extend(&previous[..]);
+        assert_eq!(filter.filter_header(&previous), FilterHash::hash(&data));
+        assert_eq!(filter.filter_id(&previous), filter.filter_header(&previous));
+    }
+
+    #[test]
+    fn test_filter_header_chain() {
+        let filter = BlockFilter::from_block(&test_block(), &[Script::from(vec![0x54]), Script::new()]).unwrap();
+        let cfheaders = |start: usize, end: usize, headers: &[FilterHash]| CFHeaders {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: Default::default(),
+            previous_filter: if start == 0 { FilterHash::default() } else { headers[start - 1] },
+            filter_hashes: vec![filter.filter_hash(); end - start],
+        };
+        let headers = cfheaders(0, CHECKPOINT_INTERVAL + 1, &[]).filter_headers();
+        let mut cfcheckpt = CFCheckpt {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: Default::default(),
+            filter_headers: vec![headers[CHECKPOINT_INTERVAL]],
+        };
+
+        let mut chain = FilterHeaderChain::new();
+        assert_eq!(chain.tip_height(), None);
+        assert_eq!(chain.add_checkpoints(&cfcheckpt), Ok(()));
+        assert_eq!(chain.checkpoints(), &[headers[CHECKPOINT_INTERVAL]][..]);
+        cfcheckpt.filter_type = 1;
+        assert_eq!(chain.add_checkpoints(&cfcheckpt), Err(FilterHeaderError::UnexpectedFilterType(1)));
+        cfcheckpt.filter_type = BASIC_FILTER_TYPE;
+
+        assert_eq!(chain.add_headers(0, &cfheaders(0, 10, &headers)), Ok(10));
+        assert_eq!(chain.tip_height(), Some(9));
+        assert_eq!(chain.add_headers(11, &cfheaders(11, 20, &headers)), Err(FilterHeaderError::Unconnected(11)));
+        let mut unconnected = cfheaders(10, 20, &headers);
+        unconnected.previous_filter = headers[8];
+        assert_eq!(chain.add_headers(10, &unconnected), Err(FilterHeaderError::Unconnected(10)));
+
+        // headers overlapping the known ones must agree with them
+        let mut conflicting = cfheaders(5, 20, &headers);
+        conflicting.filter_hashes[0] = FilterHash::hash(&[1u8]);
+        assert_eq!(chain.add_headers(5, &conflicting), Err(FilterHeaderError::HeaderMismatch(5)));
+        // and with the checkpoints
+        let mut conflicting = cfheaders(5, CHECKPOINT_INTERVAL + 1, &headers);
+        conflicting.filter_hashes[CHECKPOINT_INTERVAL - 5] = FilterHash::hash(&[1u8]);
+        assert_eq!(chain.add_headers(5, &conflicting), Err(FilterHeaderError::CheckpointMismatch(CHECKPOINT_INTERVAL as u32)));
+        assert_eq!(chain.tip_height(), Some(9));
+
+        assert_eq!(chain.add_headers(5, &cfheaders(5, CHECKPOINT_INTERVAL + 1, &headers)), Ok(CHECKPOINT_INTERVAL - 9));
+        assert_eq!(chain.tip_height(), Some(CHECKPOINT_INTERVAL as u32));
+        assert_eq!(chain.header(CHECKPOINT_INTERVAL as u32), Some(&headers[CHECKPOINT_INTERVAL]));
+        assert_eq!(chain.add_headers(0, &cfheaders(0, 10, &headers)), Ok(0));
+
+        // checkpoints must agree with the known headers
+        let mut other = FilterHeaderChain::new();
+        other.add_headers(0, &cfheaders(0, CHECKPOINT_INTERVAL + 1, &headers)).unwrap();
+        cfcheckpt.filter_headers[0] = headers[0];
+        assert_eq!(other.add_checkpoints(&cfcheckpt), Err(FilterHeaderError::CheckpointMismatch(CHECKPOINT_INTERVAL as u32)));
+        assert_eq!(chain.add_checkpoints(&cfcheckpt), Err(FilterHeaderError::CheckpointMismatch(CHECKPOINT_INTERVAL as u32)));
+
+        assert_eq!(chain.verify_filter(0, &filter), Ok(()));
+        assert_eq!(chain.verify_filter(3, &filter), Ok(()));
+        assert_eq!(chain.verify_filter(3, &BlockFilter::new(&[0u8])), Err(FilterHeaderError::HeaderMismatch(3)));
+        assert_eq!(chain.verify_filter(CHECKPOINT_INTERVAL as u32 + 1, &filter), Err(FilterHeaderError::Unconnected(1001)));
+    }
+
     #[test]
     fn test_filter () {
         let mut patterns = HashSet::new();
@@ -27,14 +27,21 @@ use std::default::Default;
 use std::str::FromStr;
 use std::{error, fmt};
 
+use blockdata::script::Script;
 use hash_types::XpubIdentifier;
 use hashes::{hex, sha512, Hash, HashEngine, Hmac, HmacEngine};
 use secp256k1::{self, Secp256k1};
 
 use network::constants::Network;
 use util::{base58, endian};
+use util::address::Address;
 use util::key::{PublicKey, PrivateKey};
 
+/// Version bytes of Bitcoin mainnet extended public keys
+pub const BITCOIN_XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+/// Version bytes of Bitcoin mainnet extended private keys
+pub const BITCOIN_XPRIV_VERSION: [u8; 4] = [0x04, 0x88, 0xAD, 0xE4];
+
 /// A chain code
 pub struct ChainCode([u8; 32]);
 impl_array_newtype!(ChainCode, u8, 32);
@@ -216,6 +223,61 @@ impl serde::Serialize for ChildNumber {
     }
 }
 
+/// An inclusive range of child numbers of the same kind, such as `0-19` or
+/// `0'-4'`, to derive the children of a [DerivationPath] in that range.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ChildNumberRange {
+    first: ChildNumber,
+    last: ChildNumber,
+}
+
+impl ChildNumberRange {
+    /// Create the range from `first` to `last` included. Both must be of the
+    /// same kind, and `first` not after `last`.
+    pub fn new(first: ChildNumber, last: ChildNumber) -> Result<ChildNumberRange, Error> {
+        if first.is_hardened() != last.is_hardened() || u32::from(first) > u32::from(last) {
+            return Err(Error::InvalidChildNumberRange);
+        }
+        Ok(ChildNumberRange { first: first, last: last })
+    }
+
+    /// The first child number of the range
+    pub fn first(&self) -> ChildNumber {
+        self.first
+    }
+
+    /// The last child number of the range
+    pub fn last(&self) -> ChildNumber {
+        self.last
+    }
+}
+
+impl fmt::Display for ChildNumberRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first == self.last {
+            fmt::Display::fmt(&self.first, f)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+impl FromStr for ChildNumberRange {
+    type Err = Error;
+
+    /// Parse a range `first-last`, where both ends carry the hardened marker
+    /// of hardened ranges, or a single child number.
+    fn from_str(inp: &str) -> Result<ChildNumberRange, Error> {
+        let mut ends = inp.splitn(2, '-');
+        let first: ChildNumber = ends.next().unwrap().parse()?;
+        let last = match ends.next() {
+            Some(last) => last.parse()?,
+            None => first,
+        };
+        ChildNumberRange::new(first, last)
+    }
+}
+
 /// A BIP-32 derivation path.
 #[derive(Clone, PartialEq, Eq)]
 pub struct DerivationPath(Vec<ChildNumber>);
@@ -277,10 +339,12 @@ impl FromStr for DerivationPath {
 /// An iterator over children of a [DerivationPath].
 ///
 /// It is returned by the methods [DerivationPath::children_since],
-/// [DerivationPath::normal_children] and [DerivationPath::hardened_children].
+/// [DerivationPath::normal_children], [DerivationPath::hardened_children]
+/// and [DerivationPath::children_in].
 pub struct DerivationPathIterator<'a> {
     base: &'a DerivationPath,
     next_child: Option<ChildNumber>,
+    last_child: Option<ChildNumber>,
 }
 
 impl<'a> DerivationPathIterator<'a> {
@@ -289,6 +353,16 @@ impl<'a> DerivationPathIterator<'a> {
         DerivationPathIterator {
             base: path,
             next_child: Some(start),
+            last_child: None,
+        }
+    }
+
+    /// Start a new [DerivationPathIterator] over the children in `range`.
+    pub fn in_range(path: &'a DerivationPath, range: ChildNumberRange) -> DerivationPathIterator<'a> {
+        DerivationPathIterator {
+            base: path,
+            next_child: Some(range.first),
+            last_child: Some(range.last),
         }
     }
 }
@@ -302,7 +376,11 @@ impl<'a> Iterator for DerivationPathIterator<'a> {
         }
 
         let ret = self.next_child.unwrap();
-        self.next_child = ret.increment().ok();
+        self.next_child = if self.last_child == Some(ret) {
+            None
+        } else {
+            ret.increment().ok()
+        };
         Some(self.base.child(ret))
     }
 }
@@ -337,6 +415,12 @@ impl DerivationPath {
     pub fn hardened_children(&self) -> DerivationPathIterator {
         DerivationPathIterator::start_from(&self, ChildNumber::Hardened{ index: 0 })
     }
+
+    /// Get an [Iterator] over the children of this [DerivationPath] in the
+    /// given [ChildNumberRange].
+    pub fn children_in(&self, range: ChildNumberRange) -> DerivationPathIterator {
+        DerivationPathIterator::in_range(&self, range)
+    }
 }
 
 impl fmt::Display for DerivationPath {
@@ -393,6 +477,78 @@ impl serde::Serialize for DerivationPath {
     }
 }
 
+/// The origin of a key: the fingerprint of the master key it derives from,
+/// and its derivation path, as recorded in PSBTs
+pub type KeySource = (Fingerprint, DerivationPath);
+
+/// The origin of a key, identifying the key a signer is requested to sign
+/// with in multi-device signing flows.
+///
+/// It is written as in output descriptors, e.g. `[d34db33f/44'/0'/0']`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyOriginInfo {
+    /// Fingerprint of the master key
+    pub master_fingerprint: Fingerprint,
+    /// Derivation path from the master key
+    pub path: DerivationPath,
+}
+serde_string_impl!(KeyOriginInfo, "a BIP-32 key origin");
+
+impl KeyOriginInfo {
+    /// Create the origin of the key at `path` from the master key of
+    /// fingerprint `master_fingerprint`
+    pub fn new(master_fingerprint: Fingerprint, path: DerivationPath) -> KeyOriginInfo {
+        KeyOriginInfo {
+            master_fingerprint: master_fingerprint,
+            path: path,
+        }
+    }
+}
+
+impl From<KeySource> for KeyOriginInfo {
+    fn from(source: KeySource) -> KeyOriginInfo {
+        KeyOriginInfo::new(source.0, source.1)
+    }
+}
+
+impl From<KeyOriginInfo> for KeySource {
+    fn from(origin: KeyOriginInfo) -> KeySource {
+        (origin.master_fingerprint, origin.path)
+    }
+}
+
+impl fmt::Display for KeyOriginInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}", self.master_fingerprint)?;
+        for cn in self.path.0.iter() {
+            write!(f, "/{}", cn)?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl FromStr for KeyOriginInfo {
+    type Err = Error;
+
+    fn from_str(inp: &str) -> Result<KeyOriginInfo, Error> {
+        if !inp.starts_with('[') || !inp.ends_with(']') {
+            return Err(Error::InvalidKeyOriginFormat);
+        }
+        let inner = &inp[1..inp.len() - 1];
+        let (fingerprint, path) = match inner.find('/') {
+            Some(pos) => (&inner[..pos], &inner[pos..]),
+            None => (inner, ""),
+        };
+        if fingerprint.len() != 8 {
+            return Err(Error::InvalidKeyOriginFormat);
+        }
+        Ok(KeyOriginInfo {
+            master_fingerprint: Fingerprint::from_str(fingerprint).map_err(|_| Error::InvalidKeyOriginFormat)?,
+            path: DerivationPath::from_str(&format!("m{}", path))?,
+        })
+    }
+}
+
 /// A BIP32 error
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Error {
@@ -408,6 +564,11 @@ pub enum Error {
     InvalidChildNumberFormat,
     /// Invalid derivation path format.
     InvalidDerivationPathFormat,
+    /// Invalid child number range, with ends of different kinds or in the
+    /// wrong order.
+    InvalidChildNumberRange,
+    /// Invalid key origin format.
+    InvalidKeyOriginFormat,
 }
 
 impl fmt::Display for Error {
@@ -419,6 +580,8 @@ impl fmt::Display for Error {
             Error::RngError(ref s) => write!(f, "rng error {}", s),
             Error::InvalidChildNumberFormat => f.write_str("invalid child number format"),
             Error::InvalidDerivationPathFormat => f.write_str("invalid derivation path format"),
+            Error::InvalidChildNumberRange => f.write_str("invalid child number range"),
+            Error::InvalidKeyOriginFormat => f.write_str("invalid key origin format"),
         }
     }
 }
@@ -440,6 +603,8 @@ impl error::Error for Error {
             Error::RngError(_) => "rng error",
             Error::InvalidChildNumberFormat => "invalid child number format",
             Error::InvalidDerivationPathFormat => "invalid derivation path format",
+            Error::InvalidChildNumberRange => "invalid child number range",
+            Error::InvalidKeyOriginFormat => "invalid key origin format",
         }
     }
 }
@@ -559,6 +724,30 @@ impl ExtendedPubKey {
         Ok(pk)
     }
 
+    /// Derive the public keys of the children in `range` of the key at
+    /// `path_prefix`, along with their P2PKH script pubkeys, e.g. to scan
+    /// the receive addresses of a wallet.
+    ///
+    /// The key at `path_prefix` is derived once, so that each child costs a
+    /// single derivation. Children for which derivation fails are skipped,
+    /// as BIP32 specifies.
+    pub fn derive_batch<'a, C: secp256k1::Verification>(
+        &self,
+        secp: &'a Secp256k1<C>,
+        path_prefix: &DerivationPath,
+        range: ChildNumberRange,
+    ) -> Result<DeriveBatch<'a, C>, Error> {
+        if range.first.is_hardened() {
+            return Err(Error::CannotDeriveFromHardenedKey);
+        }
+        Ok(DeriveBatch {
+            secp: secp,
+            parent: self.derive_pub(secp, path_prefix)?,
+            next_child: Some(range.first),
+            last_child: range.last,
+        })
+    }
+
     /// Compute the scalar tweak added to this key to get a child key
     pub fn ckd_pub_tweak(&self, i: ChildNumber) -> Result<(PrivateKey, ChainCode), Error> {
         match i {
@@ -616,13 +805,40 @@ impl ExtendedPubKey {
     }
 }
 
+/// An iterator over the children of an extended public key in a range,
+/// yielding their child number, public key and P2PKH script pubkey.
+///
+/// It is returned by [ExtendedPubKey::derive_batch].
+pub struct DeriveBatch<'a, C: 'a> {
+    secp: &'a Secp256k1<C>,
+    parent: ExtendedPubKey,
+    next_child: Option<ChildNumber>,
+    last_child: ChildNumber,
+}
+
+impl<'a, C: secp256k1::Verification> Iterator for DeriveBatch<'a, C> {
+    type Item = (ChildNumber, PublicKey, Script);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(child) = self.next_child {
+            self.next_child = if child == self.last_child {
+                None
+            } else {
+                child.increment().ok()
+            };
+            if let Ok(pk) = self.parent.ckd_pub(self.secp, child) {
+                let script_pubkey = Address::p2pkh(&pk.public_key, pk.network).script_pubkey();
+                return Some((child, pk.public_key, script_pubkey));
+            }
+        }
+        None
+    }
+}
+
 impl fmt::Display for ExtendedPrivKey {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let mut ret = [0; 78];
-        ret[0..4].copy_from_slice(&match self.network {
-            Network::Prod => [0x04, 0x88, 0xAD, 0xE4],
-            Network::Dev => [0x04, 0x35, 0x83, 0x94],
-        }[..]);
+        ret[0..4].copy_from_slice(&self.network.params().xpriv_version[..]);
         ret[4] = self.depth as u8;
         ret[5..9].copy_from_slice(&self.parent_fingerprint[..]);
         ret[9..13].copy_from_slice(&endian::u32_to_array_be(u32::from(self.child_number)));
@@ -637,6 +853,26 @@ impl FromStr for ExtendedPrivKey {
     type Err = base58::Error;
 
     fn from_str(inp: &str) -> Result<ExtendedPrivKey, base58::Error> {
+        ExtendedPrivKey::parse(inp, &Network::defaults(), false)
+    }
+}
+
+impl ExtendedPrivKey {
+    /// Parse an extended private key of the given network, which may be a
+    /// custom one. `FromStr` only recognizes the default networks.
+    pub fn from_str_for_network(inp: &str, network: Network) -> Result<ExtendedPrivKey, base58::Error> {
+        ExtendedPrivKey::parse(inp, &[network], false)
+    }
+
+    /// Parse an extended private key of the given network, also accepting the
+    /// version bytes of Bitcoin mainnet, e.g. for keys exported by Bitcoin
+    /// wallets. These are otherwise rejected, unless they are the version
+    /// bytes of the network, as for the production network.
+    pub fn from_str_allowing_bitcoin(inp: &str, network: Network) -> Result<ExtendedPrivKey, base58::Error> {
+        ExtendedPrivKey::parse(inp, &[network], true)
+    }
+
+    fn parse(inp: &str, networks: &[Network], allow_bitcoin: bool) -> Result<ExtendedPrivKey, base58::Error> {
         let data = base58::from_check(inp)?;
 
         if data.len() != 78 {
@@ -646,12 +882,10 @@ impl FromStr for ExtendedPrivKey {
         let cn_int: u32 = endian::slice_to_u32_be(&data[9..13]);
         let child_number: ChildNumber = ChildNumber::from(cn_int);
 
-        let network = if &data[0..4] == [0x04u8, 0x88, 0xAD, 0xE4] {
-            Network::Prod
-        } else if &data[0..4] == [0x04u8, 0x35, 0x83, 0x94] {
-            Network::Dev
-        } else {
-            return Err(base58::Error::InvalidVersion((&data[0..4]).to_vec()));
+        let network = match networks.iter().find(|n| n.params().xpriv_version[..] == data[0..4]) {
+            Some(network) => *network,
+            None if allow_bitcoin && data[0..4] == BITCOIN_XPRIV_VERSION[..] => networks[0],
+            None => return Err(base58::Error::InvalidVersion((&data[0..4]).to_vec())),
         };
 
         Ok(ExtendedPrivKey {
@@ -676,10 +910,7 @@ impl FromStr for ExtendedPrivKey {
 impl fmt::Display for ExtendedPubKey {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let mut ret = [0; 78];
-        ret[0..4].copy_from_slice(&match self.network {
-            Network::Prod => [0x04u8, 0x88, 0xB2, 0x1E],
-            Network::Dev => [0x04u8, 0x35, 0x87, 0xCF],
-        }[..]);
+        ret[0..4].copy_from_slice(&self.network.params().xpub_version[..]);
         ret[4] = self.depth as u8;
         ret[5..9].copy_from_slice(&self.parent_fingerprint[..]);
         ret[9..13].copy_from_slice(&endian::u32_to_array_be(u32::from(self.child_number)));
@@ -693,6 +924,26 @@ impl FromStr for ExtendedPubKey {
     type Err = base58::Error;
 
     fn from_str(inp: &str) -> Result<ExtendedPubKey, base58::Error> {
+        ExtendedPubKey::parse(inp, &Network::defaults(), false)
+    }
+}
+
+impl ExtendedPubKey {
+    /// Parse an extended public key of the given network, which may be a
+    /// custom one. `FromStr` only recognizes the default networks.
+    pub fn from_str_for_network(inp: &str, network: Network) -> Result<ExtendedPubKey, base58::Error> {
+        ExtendedPubKey::parse(inp, &[network], false)
+    }
+
+    /// Parse an extended public key of the given network, also accepting the
+    /// version bytes of Bitcoin mainnet, e.g. for keys exported by Bitcoin
+    /// wallets. These are otherwise rejected, unless they are the version
+    /// bytes of the network, as for the production network.
+    pub fn from_str_allowing_bitcoin(inp: &str, network: Network) -> Result<ExtendedPubKey, base58::Error> {
+        ExtendedPubKey::parse(inp, &[network], true)
+    }
+
+    fn parse(inp: &str, networks: &[Network], allow_bitcoin: bool) -> Result<ExtendedPubKey, base58::Error> {
         let data = base58::from_check(inp)?;
 
         if data.len() != 78 {
@@ -702,14 +953,14 @@ impl FromStr for ExtendedPubKey {
         let cn_int: u32 = endian::slice_to_u32_be(&data[9..13]);
         let child_number: ChildNumber = ChildNumber::from(cn_int);
 
+        let network = match networks.iter().find(|n| n.params().xpub_version[..] == data[0..4]) {
+            Some(network) => *network,
+            None if allow_bitcoin && data[0..4] == BITCOIN_XPUB_VERSION[..] => networks[0],
+            None => return Err(base58::Error::InvalidVersion((&data[0..4]).to_vec())),
+        };
+
         Ok(ExtendedPubKey {
-            network: if &data[0..4] == [0x04u8, 0x88, 0xB2, 0x1E] {
-                Network::Prod
-            } else if &data[0..4] == [0x04u8, 0x35, 0x87, 0xCF] {
-                Network::Dev
-            } else {
-                return Err(base58::Error::InvalidVersion((&data[0..4]).to_vec()));
-            },
+            network: network,
             depth: data[4],
             parent_fingerprint: Fingerprint::from(&data[5..9]),
             child_number: child_number,
@@ -732,7 +983,8 @@ mod tests {
     use secp256k1::{self, Secp256k1};
     use hex::decode as hex_decode;
 
-    use network::constants::Network::{self, Prod};
+    use network::constants::{Network, NetworkParams};
+    use network::constants::Network::Prod;
 
     #[test]
     fn test_parse_derivation_path() {
@@ -852,6 +1104,129 @@ mod tests {
         assert_eq!(Ok(pk), decoded_pk);
     }
 
+    #[test]
+    fn test_child_number_range() {
+        assert_eq!(
+            ChildNumberRange::from_str("0-19"),
+            ChildNumberRange::new(ChildNumber::from_normal_idx(0).unwrap(), ChildNumber::from_normal_idx(19).unwrap())
+        );
+        let range = ChildNumberRange::from_str("2'-4h").unwrap();
+        assert_eq!(range.first(), ChildNumber::from_hardened_idx(2).unwrap());
+        assert_eq!(range.last(), ChildNumber::from_hardened_idx(4).unwrap());
+        assert_eq!(range.to_string(), "2'-4'");
+        let single = ChildNumberRange::from_str("7").unwrap();
+        assert_eq!(single.first(), single.last());
+        assert_eq!(single.to_string(), "7");
+
+        assert_eq!(ChildNumberRange::from_str("0-4'"), Err(Error::InvalidChildNumberRange));
+        assert_eq!(ChildNumberRange::from_str("5-4"), Err(Error::InvalidChildNumberRange));
+        assert_eq!(ChildNumberRange::from_str("0-"), Err(Error::InvalidChildNumberFormat));
+        assert_eq!(ChildNumberRange::from_str("0-1-2"), Err(Error::InvalidChildNumberFormat));
+
+        let path = DerivationPath::from_str("m/44h/0'").unwrap();
+        let children: Vec<DerivationPath> = path.children_in(range).collect();
+        assert_eq!(children, vec![
+            "m/44'/0'/2'".parse().unwrap(),
+            "m/44'/0'/3'".parse().unwrap(),
+            "m/44'/0'/4'".parse().unwrap(),
+        ]);
+        assert_eq!(path.children_in(single).count(), 1);
+        let max = ChildNumber::from_normal_idx((1 << 31) - 1).unwrap();
+        assert_eq!(path.children_in(ChildNumberRange::new(max, max).unwrap()).count(), 1);
+    }
+
+    #[test]
+    fn test_derive_batch() {
+        let secp = Secp256k1::new();
+        let seed = hex_decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let sk = ExtendedPrivKey::new_master(Prod, &seed).unwrap();
+        let pk = ExtendedPubKey::from_private(&secp, &sk);
+        let prefix = DerivationPath::from_str("m/0/1").unwrap();
+        let range = ChildNumberRange::from_str("5-9").unwrap();
+
+        let batch: Vec<_> = pk.derive_batch(&secp, &prefix, range).unwrap().collect();
+        assert_eq!(batch.len(), 5);
+        for ((child, public_key, script_pubkey), path) in batch.into_iter().zip(prefix.children_in(range)) {
+            assert_eq!(child, path[2]);
+            let derived = pk.derive_pub(&secp, &path).unwrap();
+            assert_eq!(public_key, derived.public_key);
+            assert_eq!(script_pubkey, Address::p2pkh(&derived.public_key, Prod).script_pubkey());
+        }
+
+        let hardened = ChildNumberRange::from_str("0'-9'").unwrap();
+        assert_eq!(pk.derive_batch(&secp, &prefix, hardened).err(), Some(Error::CannotDeriveFromHardenedKey));
+        let hardened_prefix = DerivationPath::from_str("m/0'").unwrap();
+        assert_eq!(pk.derive_batch(&secp, &hardened_prefix, range).err(), Some(Error::CannotDeriveFromHardenedKey));
+    }
+
+    #[test]
+    fn test_network_versions() {
+        let secp = Secp256k1::new();
+        let seed = hex_decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let custom = Network::Custom(NetworkParams {
+            xpub_version: [0x01, 0x02, 0x03, 0x04],
+            xpriv_version: [0x05, 0x06, 0x07, 0x08],
+            ..NetworkParams::DEV
+        });
+
+        for &network in [Network::Dev, custom].iter() {
+            let sk = ExtendedPrivKey::new_master(network, &seed).unwrap();
+            let pk = ExtendedPubKey::from_private(&secp, &sk);
+            let sk_str = sk.to_string();
+            let pk_str = pk.to_string();
+            assert_eq!(base58::from_check(&sk_str).unwrap()[0..4], network.params().xpriv_version[..]);
+            assert_eq!(base58::from_check(&pk_str).unwrap()[0..4], network.params().xpub_version[..]);
+            assert_eq!(ExtendedPrivKey::from_str_for_network(&sk_str, network), Ok(sk));
+            assert_eq!(ExtendedPubKey::from_str_for_network(&pk_str, network), Ok(pk));
+
+            // keys with the version bytes of Bitcoin mainnet are only
+            // accepted when opted in
+            let mut bitcoin_sk = sk;
+            bitcoin_sk.network = Prod;
+            bitcoin_sk.private_key.network = Prod;
+            let mut bitcoin_pk = pk;
+            bitcoin_pk.network = Prod;
+            assert_eq!(
+                ExtendedPrivKey::from_str_for_network(&bitcoin_sk.to_string(), network),
+                Err(base58::Error::InvalidVersion(BITCOIN_XPRIV_VERSION.to_vec()))
+            );
+            assert_eq!(
+                ExtendedPubKey::from_str_for_network(&bitcoin_pk.to_string(), network),
+                Err(base58::Error::InvalidVersion(BITCOIN_XPUB_VERSION.to_vec()))
+            );
+            assert_eq!(ExtendedPrivKey::from_str_allowing_bitcoin(&bitcoin_sk.to_string(), network), Ok(sk));
+            assert_eq!(ExtendedPubKey::from_str_allowing_bitcoin(&bitcoin_pk.to_string(), network), Ok(pk));
+        }
+
+        // custom version bytes are not recognized without the network
+        let sk = ExtendedPrivKey::new_master(custom, &seed).unwrap();
+        assert_eq!(
+            ExtendedPrivKey::from_str(&sk.to_string()),
+            Err(base58::Error::InvalidVersion(vec![0x05, 0x06, 0x07, 0x08]))
+        );
+    }
+
+    #[test]
+    fn test_key_origin() {
+        let origin = KeyOriginInfo::from_str("[d34db33f/44'/0h/0']").unwrap();
+        assert_eq!(origin.master_fingerprint, Fingerprint::from(&[0xd3, 0x4d, 0xb3, 0x3f][..]));
+        assert_eq!(origin.path, DerivationPath::from_str("m/44'/0'/0'").unwrap());
+        assert_eq!(origin.to_string(), "[d34db33f/44'/0'/0']");
+
+        let source: KeySource = origin.clone().into();
+        assert_eq!(KeyOriginInfo::from(source), origin);
+
+        let master = KeyOriginInfo::from_str("[d34db33f]").unwrap();
+        assert_eq!(master.path, DerivationPath::from(vec![]));
+        assert_eq!(master.to_string(), "[d34db33f]");
+
+        assert_eq!(KeyOriginInfo::from_str("d34db33f/0"), Err(Error::InvalidKeyOriginFormat));
+        assert_eq!(KeyOriginInfo::from_str("[d34db3/0]"), Err(Error::InvalidKeyOriginFormat));
+        assert_eq!(KeyOriginInfo::from_str("[d34db33g/0]"), Err(Error::InvalidKeyOriginFormat));
+        assert_eq!(KeyOriginInfo::from_str("[d34db33f/0x]"), Err(Error::InvalidChildNumberFormat));
+        assert_eq!(KeyOriginInfo::from_str("]"), Err(Error::InvalidKeyOriginFormat));
+    }
+
     #[test]
     fn test_increment() {
         let idx = 9345497; // randomly generated, I promise
@@ -1006,6 +1381,7 @@ mod tests {
 
         serde_round_trip!(fp);
         serde_round_trip!(cc);
+        serde_round_trip!(KeyOriginInfo::new(fp, "m/0'/1".parse().unwrap()));
 
         assert_eq!("\"0102032a\"", serde_json::to_string(&fp).unwrap());
         assert_eq!(
@@ -0,0 +1,328 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! BIP37 Bloom filter
+//!
+//! This module implements the bloom filter which light clients send to
+//! their peers with `filterload` so that only matching transactions are
+//! relayed to them, together with `merkleblock` proofs.
+//!
+//! As outpoints in Tapyrus refer to the malleability-fixed transaction id,
+//! transactions are matched and outpoints are built using `malfix_txid()`.
+//!
+
+use std::f64::consts::LN_2;
+use std::io;
+
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script};
+use blockdata::transaction::{OutPoint, Transaction};
+use consensus::encode::{self, serialize, Decodable, Encodable};
+use util::endian;
+
+/// Maximum size of a bloom filter in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// Maximum number of hash functions of a bloom filter
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Controls how the filter is updated when a transaction output matches
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BloomFlags {
+    /// Never update the filter
+    None,
+    /// Add the outpoint of every matching output
+    All,
+    /// Add the outpoint of matching pay-to-pubkey and bare multisig outputs only
+    PubkeyOnly,
+}
+
+impl Encodable for BloomFlags {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        let flags: u8 = match *self {
+            BloomFlags::None => 0,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        };
+        flags.consensus_encode(s)
+    }
+}
+
+impl Decodable for BloomFlags {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(match u8::consensus_decode(d)? {
+            0 => BloomFlags::None,
+            1 => BloomFlags::All,
+            2 => BloomFlags::PubkeyOnly,
+            _ => return Err(encode::Error::Parse(encode::ParseError::Other("unknown bloom filter flags"))),
+        })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for BloomFlags {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<BloomFlags> {
+        Ok(*u.choose(&[BloomFlags::None, BloomFlags::All, BloomFlags::PubkeyOnly])?)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for BloomFlags {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ::serde::Serialize::serialize(&serialize(self)[0], s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for BloomFlags {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<BloomFlags, D::Error> {
+        use serde::de::Error;
+
+        let flags: u8 = ::serde::Deserialize::deserialize(d)?;
+        encode::deserialize(&[flags]).map_err(D::Error::custom)
+    }
+}
+
+/// A BIP37 bloom filter, sent to peers in the `filterload` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BloomFilter {
+    /// The filter bit field
+    pub content: Vec<u8>,
+    /// The number of hash functions
+    pub hash_funcs: u32,
+    /// Random value added to the seed of each hash function
+    pub tweak: u32,
+    /// How the filter is updated when an output matches
+    pub flags: BloomFlags,
+}
+impl_consensus_encoding!(BloomFilter, content, hash_funcs, tweak, flags);
+serde_struct_impl!(BloomFilter, content, hash_funcs, tweak, flags);
+arbitrary_struct_impl!(BloomFilter, content, hash_funcs, tweak, flags);
+
+impl BloomFilter {
+    /// Create an empty filter sized to hold `elements` items with a false
+    /// positive rate of `fp_rate`, capped to the maximum filter size and
+    /// number of hash functions. An `elements` of zero is treated as one.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = elements.max(1) as f64;
+        let bits = (-1.0 / (LN_2 * LN_2) * elements * fp_rate.ln()) as usize;
+        let size = bits.min(MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((size * 8) as f64 / elements * LN_2) as u32;
+        BloomFilter {
+            content: vec![0; size],
+            hash_funcs: hash_funcs.min(MAX_HASH_FUNCS),
+            tweak: tweak,
+            flags: flags,
+        }
+    }
+
+    /// Whether the filter respects the limits peers enforce on `filterload`
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.content.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    /// Index of the bit set by hash function `n` for `data`
+    fn bit_index(&self, n: u32, data: &[u8]) -> usize {
+        let seed = n.wrapping_mul(0xfba4c795).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.content.len() * 8)
+    }
+
+    /// Add `data` to the filter
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.content.is_empty() {
+            return;
+        }
+        for n in 0..self.hash_funcs {
+            let idx = self.bit_index(n, data);
+            self.content[idx >> 3] |= 1 << (idx & 7);
+        }
+    }
+
+    /// Check whether `data` matches the filter. An empty filter matches
+    /// everything.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.content.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|n| {
+            let idx = self.bit_index(n, data);
+            self.content[idx >> 3] & (1 << (idx & 7)) != 0
+        })
+    }
+
+    /// Add an outpoint to the filter
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Check whether an outpoint matches the filter
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Check whether any data push of `script` matches the filter
+    pub fn contains_script(&self, script: &Script) -> bool {
+        script.iter(false).any(|ins| match ins {
+            Instruction::PushBytes(data) => !data.is_empty() && self.contains(data),
+            _ => false,
+        })
+    }
+
+    /// Check whether `tx` is relevant to the filter, i.e. whether its
+    /// txid, one of its output scripts, one of its spent outpoints or one of
+    /// its input scripts matches. Outpoints of matching outputs are added
+    /// to the filter according to its flags, so that transactions spending
+    /// them match as well.
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.malfix_txid();
+        let mut found = self.contains(&txid[..]);
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            if !self.contains_script(&output.script_pubkey) {
+                continue;
+            }
+            found = true;
+            let update = match self.flags {
+                BloomFlags::None => false,
+                BloomFlags::All => true,
+                BloomFlags::PubkeyOnly => {
+                    output.script_pubkey.is_p2pk() || is_bare_multisig(&output.script_pubkey)
+                }
+            };
+            if update {
+                self.insert_outpoint(&OutPoint::new(txid, vout as u32));
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output) || self.contains_script(&input.script_sig)
+        })
+    }
+}
+
+/// Check for `<m> <pubkey>... <n> OP_CHECKMULTISIG`
+fn is_bare_multisig(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    let is_pushnum = |b: u8| {
+        b >= opcodes::all::OP_PUSHNUM_1.into_u8() && b <= opcodes::all::OP_PUSHNUM_16.into_u8()
+    };
+    bytes.len() >= 3 &&
+        is_pushnum(bytes[0]) &&
+        is_pushnum(bytes[bytes.len() - 2]) &&
+        bytes[bytes.len() - 1] == opcodes::all::OP_CHECKMULTISIG.into_u8()
+}
+
+/// 32-bit MurmurHash3, as used by BIP37
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h1 = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k1 = endian::slice_to_u32_le(chunk)
+            .wrapping_mul(C1)
+            .rotate_left(15)
+            .wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k1 = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k1 ^= (*byte as u32) << (8 * i);
+        }
+        h1 ^= k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85ebca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{murmur3, BloomFilter, BloomFlags};
+
+    use hex::decode as hex_decode;
+
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+
+    #[test]
+    fn murmur3_test() {
+        assert_eq!(murmur3(0, &[]), 0);
+        assert_eq!(murmur3(0xfba4c795, &[]), 0x6a396f08);
+        assert_eq!(murmur3(0xffffffff, &[]), 0x81f16f39);
+        assert_eq!(murmur3(0, &[0x00]), 0x514e28b7);
+    }
+
+    #[test]
+    fn create_insert_serialize_test() {
+        let mut filter = BloomFilter::new(3, 0.01, 0, BloomFlags::All);
+
+        let data = hex_decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+        filter.insert(&data);
+        assert!(filter.contains(&data));
+        assert!(!filter.contains(&hex_decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+
+        filter.insert(&hex_decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+        filter.insert(&hex_decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+        assert_eq!(serialize(&filter), hex_decode("03614e9b050000000000000001").unwrap());
+        assert_eq!(deserialize::<BloomFilter>(&serialize(&filter)).unwrap(), filter);
+        assert!(filter.is_within_size_constraints());
+    }
+
+    #[test]
+    fn create_insert_serialize_with_tweak_test() {
+        let mut filter = BloomFilter::new(3, 0.01, 2147483649, BloomFlags::All);
+        filter.insert(&hex_decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap());
+        filter.insert(&hex_decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+        filter.insert(&hex_decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+        assert_eq!(serialize(&filter), hex_decode("03ce4299050000000100008001").unwrap());
+    }
+
+    #[test]
+    fn is_relevant_and_update_test() {
+        let tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: 100,
+                script_pubkey: hex_script!("76a9149f3cab7a8a41f1e3bb3d6ef2c4e3bb8bd8cb9eb888ac"),
+            }],
+        };
+        let spend = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(tx.malfix_txid(), 0),
+                ..Default::default()
+            }],
+            output: vec![],
+        };
+
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::All);
+        assert!(!filter.is_relevant_and_update(&tx));
+        filter.insert(&hex_decode("9f3cab7a8a41f1e3bb3d6ef2c4e3bb8bd8cb9eb8").unwrap());
+        assert!(filter.is_relevant_and_update(&tx));
+        assert!(filter.contains_outpoint(&OutPoint::new(tx.malfix_txid(), 0)));
+        assert!(filter.is_relevant_and_update(&spend));
+
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::PubkeyOnly);
+        filter.insert(&hex_decode("9f3cab7a8a41f1e3bb3d6ef2c4e3bb8bd8cb9eb8").unwrap());
+        assert!(filter.is_relevant_and_update(&tx));
+        assert!(!filter.is_relevant_and_update(&spend));
+    }
+}
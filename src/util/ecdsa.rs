@@ -0,0 +1,248 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! ECDSA signatures
+//!
+//! ECDSA signatures are pushed by script signatures DER encoded, followed
+//! by the byte of their sighash type. [Signature] bundles both, parsed as
+//! strictly as the script verification flags require.
+//!
+
+use core::fmt;
+#[cfg(feature = "std")] use std::error;
+
+use secp256k1::{self, Secp256k1};
+
+use blockdata::interpreter::VerifyFlags;
+use blockdata::transaction::SigHashType;
+use prelude::*;
+use util::key::PublicKey;
+
+/// An ECDSA signature parsing or verification error
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The signature is empty, without a sighash type
+    EmptySignature,
+    /// The signature is not strictly DER encoded, as BIP66 requires
+    NonStrictDer,
+    /// The S value of the signature is not in the lower half of the range
+    HighS,
+    /// The sighash type is not one of the defined ones
+    InvalidSigHashType(u8),
+    /// secp256k1 error
+    Secp256k1(secp256k1::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EmptySignature => f.write_str("empty signature"),
+            Error::NonStrictDer => f.write_str("signature is not strictly DER encoded"),
+            Error::HighS => f.write_str("signature has a high S value"),
+            Error::InvalidSigHashType(sighash_type) => write!(f, "invalid sighash type {}", sighash_type),
+            Error::Secp256k1(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Secp256k1(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Secp256k1(e)
+    }
+}
+
+/// An ECDSA signature along with its sighash type
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Signature {
+    /// The signature
+    pub signature: secp256k1::Signature,
+    /// The sighash type of the signature
+    pub sighash_type: SigHashType,
+}
+
+impl Signature {
+    /// Create a signature of `sighash_type`
+    pub fn new(signature: secp256k1::Signature, sighash_type: SigHashType) -> Signature {
+        Signature { signature: signature, sighash_type: sighash_type }
+    }
+
+    /// Parse a DER encoded signature followed by its sighash type, as the
+    /// standardness rules require: strictly DER encoded, with a low S value
+    /// and a defined sighash type
+    pub fn from_slice(sig: &[u8]) -> Result<Signature, Error> {
+        Signature::from_slice_with_flags(sig, VerifyFlags::STANDARD)
+    }
+
+    /// Parse a DER encoded signature followed by its sighash type, as
+    /// strictly as `flags` require. Without `DERSIG`, `LOW_S` or
+    /// `STRICTENC`, signatures are parsed as laxly as OpenSSL did, and
+    /// without `STRICTENC`, undefined sighash types are read as
+    /// `SigHashType::from_u32` does.
+    pub fn from_slice_with_flags(sig: &[u8], flags: VerifyFlags) -> Result<Signature, Error> {
+        let (&sighash_type, der) = sig.split_last().ok_or(Error::EmptySignature)?;
+        let strict = flags.has(VerifyFlags::DERSIG) || flags.has(VerifyFlags::LOW_S) ||
+            flags.has(VerifyFlags::STRICTENC);
+        if strict && !is_valid_der_encoding(sig) {
+            return Err(Error::NonStrictDer);
+        }
+        if flags.has(VerifyFlags::LOW_S) && !is_low_s(sig) {
+            return Err(Error::HighS);
+        }
+        if flags.has(VerifyFlags::STRICTENC) && !is_defined_sighash_type(sighash_type) {
+            return Err(Error::InvalidSigHashType(sighash_type));
+        }
+        let signature = if strict {
+            secp256k1::Signature::from_der(der)?
+        } else {
+            secp256k1::Signature::from_der_lax(der)?
+        };
+        Ok(Signature::new(signature, SigHashType::from_u32(sighash_type as u32)))
+    }
+
+    /// Serialize the signature as pushed by script signatures, DER encoded
+    /// and followed by its sighash type
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = self.signature.serialize_der().to_vec();
+        ret.push(self.sighash_type.as_u32() as u8);
+        ret
+    }
+
+    /// Verify the signature of `sighash` by `pubkey`. Signatures with a high
+    /// S value are normalized first, as script verification does.
+    pub fn verify<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        sighash: &[u8; 32],
+        pubkey: &PublicKey,
+    ) -> Result<(), Error> {
+        let mut signature = self.signature;
+        signature.normalize_s();
+        let message = secp256k1::Message::from_slice(&sighash[..]).expect("sighashes are 32 bytes");
+        secp.verify(&message, &signature, &pubkey.key)?;
+        Ok(())
+    }
+}
+
+/// Whether a sighash type is one of the defined ones
+pub(crate) fn is_defined_sighash_type(sighash_type: u8) -> bool {
+    (1..=3).contains(&(sighash_type & !0x80))
+}
+
+/// Whether a DER encoded ECDSA signature followed by its sighash type has a
+/// low S value
+pub(crate) fn is_low_s(sig: &[u8]) -> bool {
+    match secp256k1::Signature::from_der_lax(&sig[..sig.len() - 1]) {
+        Ok(signature) => {
+            let mut normalized = signature;
+            normalized.normalize_s();
+            normalized == signature
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether an ECDSA signature followed by its sighash type is strictly DER
+/// encoded, per BIP66
+pub(crate) fn is_valid_der_encoding(sig: &[u8]) -> bool {
+    // 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    // R and S are positive integers, without unnecessary leading zeros
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Signature};
+
+    use hex::decode as hex_decode;
+    use secp256k1::{self, Secp256k1};
+
+    use blockdata::interpreter::VerifyFlags;
+    use blockdata::transaction::SigHashType;
+    use util::key::PrivateKey;
+
+    #[test]
+    fn test_parse_serialize() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let sighash = [7u8; 32];
+        let sig = key.sign_for_script(&secp, &sighash, SigHashType::SinglePlusAnyoneCanPay);
+
+        let signature = Signature::from_slice(&sig).unwrap();
+        assert_eq!(signature.sighash_type, SigHashType::SinglePlusAnyoneCanPay);
+        assert_eq!(signature.serialize(), sig);
+        assert_eq!(signature.verify(&secp, &sighash, &key.public_key(&secp)), Ok(()));
+        assert!(signature.verify(&secp, &[8u8; 32], &key.public_key(&secp)).is_err());
+
+        // high S values are rejected by standardness but verify
+        let mut compact = signature.signature.serialize_compact();
+        let mut high_s = secp256k1::SecretKey::from_slice(&compact[32..]).unwrap();
+        high_s.negate_assign();
+        compact[32..].copy_from_slice(&high_s[..]);
+        let high = Signature::new(secp256k1::Signature::from_compact(&compact).unwrap(), SigHashType::All);
+        assert_eq!(Signature::from_slice(&high.serialize()), Err(Error::HighS));
+        let parsed = Signature::from_slice_with_flags(&high.serialize(), VerifyFlags::CONSENSUS).unwrap();
+        assert_eq!(parsed, high);
+        assert_eq!(parsed.verify(&secp, &sighash, &key.public_key(&secp)), Ok(()));
+    }
+
+    #[test]
+    fn test_strict_encoding() {
+        let der = hex_decode("3044022078493302\
+            4c094ea2473553ddafcd1f19d000221062a3b434e039ec188739833902204ee9abe094c2ffcb0a8f9ce9a6e0770fcb09a4ae65f3c924a6a841f20a0b78e3").unwrap();
+
+        assert_eq!(Signature::from_slice(&[]), Err(Error::EmptySignature));
+
+        let mut sig = der.clone();
+        sig.push(0x84);
+        assert_eq!(Signature::from_slice(&sig), Err(Error::InvalidSigHashType(0x84)));
+        let parsed = Signature::from_slice_with_flags(&sig, VerifyFlags::CONSENSUS).unwrap();
+        assert_eq!(parsed.sighash_type, SigHashType::AllPlusAnyoneCanPay);
+
+        // R padded with an unnecessary zero
+        let mut padded = vec![0x30, 0x45, 0x02, 0x21, 0x00];
+        padded.extend(&der[4..]);
+        padded.push(0x01);
+        assert_eq!(Signature::from_slice(&padded), Err(Error::NonStrictDer));
+        assert_eq!(Signature::from_slice_with_flags(&padded, VerifyFlags::CONSENSUS), Err(Error::NonStrictDer));
+        let lax = Signature::from_slice_with_flags(&padded, VerifyFlags::NONE).unwrap();
+        assert_eq!(lax.serialize(), [&der[..], &[0x01][..]].concat());
+    }
+}
@@ -0,0 +1,15 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Federation
+//!
+//! Tapyrus blocks are signed by a federation of signers, any `threshold` of
+//! which can produce the Schnorr signature of the aggregate public key
+//! announced in the chain. Each signer holds a share of the aggregate
+//! private key, the value at its index of a polynomial whose value at 0 is
+//! the aggregate private key, and signers are identified by these indexes,
+//! starting from 1.
+//!
+
+pub mod signing;
@@ -0,0 +1,518 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Threshold signing sessions
+//!
+//! A [SigningSession] follows the rounds in which signers of the federation
+//! sign the signature hash of a block header:
+//!
+//! 1. each signer draws a fresh secret nonce and sends its
+//!    [NonceCommitment]; the first `threshold` signers to commit form the
+//!    signing set,
+//! 2. each signer of the signing set sends the [PartialSignature] computed
+//!    with its nonce and its share of the aggregate private key, which is
+//!    checked against its commitment and its public key share,
+//! 3. the partial signatures are aggregated, by Lagrange interpolation, into
+//!    the Schnorr signature of the aggregate public key, the block proof.
+//!
+//! Nonces must never be reused across sessions: two partial signatures made
+//! with the same nonce reveal the key share of the signer.
+//!
+
+use std::collections::BTreeMap;
+use std::{error, fmt};
+
+use num_bigint::BigUint;
+use secp256k1::{self, Secp256k1, SecretKey};
+
+use hash_types::BlockSigHash;
+use hashes::Hash;
+use util::key::PublicKey;
+use util::prime::{self, jacobi};
+use util::schnorr::{self, SchnorrSignature};
+
+/// A threshold signing error
+#[derive(Debug)]
+pub enum Error {
+    /// The threshold is 0 or higher than the number of signers, or a signer
+    /// has index 0
+    InvalidFederation,
+    /// The signer is not part of the federation
+    UnknownSigner(u32),
+    /// The signer is not part of the signing set
+    NotInSigningSet(u32),
+    /// The signing set already has `threshold` signers
+    SigningSetComplete,
+    /// The signer already sent its nonce commitment
+    DuplicateCommitment(u32),
+    /// The signer already sent its partial signature
+    DuplicatePartialSignature(u32),
+    /// Fewer than `threshold` signers sent their nonce commitment
+    NotEnoughCommitments {
+        /// The threshold
+        required: usize,
+        /// The number of commitments
+        received: usize,
+    },
+    /// Fewer than `threshold` signers sent their partial signature
+    NotEnoughPartialSignatures {
+        /// The threshold
+        required: usize,
+        /// The number of partial signatures
+        received: usize,
+    },
+    /// The secret nonce of the signer does not match its commitment
+    NonceMismatch(u32),
+    /// The key share of the signer does not match its public key share
+    KeyShareMismatch(u32),
+    /// The partial signature of the signer is invalid
+    InvalidPartialSignature(u32),
+    /// secp256k1 error
+    Secp256k1(secp256k1::Error),
+    /// The aggregated signature is invalid
+    Signature(schnorr::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownSigner(index) => write!(f, "signer {} is not part of the federation", index),
+            Error::NotInSigningSet(index) => write!(f, "signer {} is not part of the signing set", index),
+            Error::DuplicateCommitment(index) => write!(f, "duplicate nonce commitment of signer {}", index),
+            Error::DuplicatePartialSignature(index) => write!(f, "duplicate partial signature of signer {}", index),
+            Error::NotEnoughCommitments { required, received } => write!(f,
+                "{} nonce commitments received, {} required", received, required),
+            Error::NotEnoughPartialSignatures { required, received } => write!(f,
+                "{} partial signatures received, {} required", received, required),
+            Error::NonceMismatch(index) => write!(f, "nonce of signer {} does not match its commitment", index),
+            Error::KeyShareMismatch(index) => write!(f, "key share of signer {} does not match its public key share", index),
+            Error::InvalidPartialSignature(index) => write!(f, "invalid partial signature of signer {}", index),
+            Error::Secp256k1(ref e) => fmt::Display::fmt(e, f),
+            Error::Signature(ref e) => write!(f, "invalid aggregated signature: {}", e),
+            Error::InvalidFederation => f.write_str("invalid threshold or signer index"),
+            Error::SigningSetComplete => f.write_str("signing set is complete"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Secp256k1(ref e) => Some(e),
+            Error::Signature(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Secp256k1(e)
+    }
+}
+
+/// The secret nonce of a signer for one session. It is consumed by signing,
+/// not to be reused.
+pub struct SecretNonce(SecretKey);
+
+impl SecretNonce {
+    /// Create a nonce from a secret drawn uniformly at random
+    pub fn new(secret: SecretKey) -> SecretNonce {
+        SecretNonce(secret)
+    }
+
+    /// Generate a random nonce
+    #[cfg(feature = "rand")]
+    pub fn generate<R: ::secp256k1::rand::Rng + ?Sized>(rng: &mut R) -> SecretNonce {
+        SecretNonce(SecretKey::new(rng))
+    }
+
+    /// The commitment to send to the other signers
+    pub fn commitment<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>) -> NonceCommitment {
+        NonceCommitment(secp256k1::PublicKey::from_secret_key(secp, &self.0))
+    }
+}
+
+impl fmt::Debug for SecretNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[secret nonce data]")
+    }
+}
+
+/// The commitment of a signer to its secret nonce, the nonce times the
+/// generator
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct NonceCommitment(pub secp256k1::PublicKey);
+
+/// The share of a signer in the aggregated signature
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PartialSignature {
+    /// The nonce plus the challenge times the key share of the signer
+    pub sigma: [u8; 32],
+}
+
+/// A session in which the federation signs a block header
+#[derive(Clone, Debug)]
+pub struct SigningSession {
+    sighash: BlockSigHash,
+    aggregate_public_key: PublicKey,
+    threshold: usize,
+    public_shares: BTreeMap<u32, PublicKey>,
+    commitments: BTreeMap<u32, NonceCommitment>,
+    partial_signatures: BTreeMap<u32, PartialSignature>,
+}
+
+impl SigningSession {
+    /// Start a session signing `sighash`, the signature hash of a block
+    /// header, for `aggregate_public_key`. `public_shares` are the public
+    /// keys of the key shares of the signers, by index.
+    pub fn new(
+        sighash: BlockSigHash,
+        aggregate_public_key: PublicKey,
+        threshold: usize,
+        public_shares: BTreeMap<u32, PublicKey>,
+    ) -> Result<SigningSession, Error> {
+        if threshold == 0 || threshold > public_shares.len() || public_shares.contains_key(&0) {
+            return Err(Error::InvalidFederation);
+        }
+        Ok(SigningSession {
+            sighash: sighash,
+            aggregate_public_key: aggregate_public_key,
+            threshold: threshold,
+            public_shares: public_shares,
+            commitments: BTreeMap::new(),
+            partial_signatures: BTreeMap::new(),
+        })
+    }
+
+    /// The signature hash being signed
+    pub fn sighash(&self) -> BlockSigHash {
+        self.sighash
+    }
+
+    /// The number of signers needed to sign
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// The indexes of the signers of the signing set, known so far
+    pub fn signing_set(&self) -> Vec<u32> {
+        self.commitments.keys().cloned().collect()
+    }
+
+    /// Record the nonce commitment of `signer`, which joins the signing set
+    pub fn add_commitment(&mut self, signer: u32, commitment: NonceCommitment) -> Result<(), Error> {
+        if !self.public_shares.contains_key(&signer) {
+            return Err(Error::UnknownSigner(signer));
+        }
+        if self.commitments.contains_key(&signer) {
+            return Err(Error::DuplicateCommitment(signer));
+        }
+        if self.commitments.len() == self.threshold {
+            return Err(Error::SigningSetComplete);
+        }
+        self.commitments.insert(signer, commitment);
+        Ok(())
+    }
+
+    /// The aggregated nonce point of the signing set, whose y coordinate is
+    /// a quadratic residue as Tapyrus requires
+    pub fn nonce_point<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>) -> Result<secp256k1::PublicKey, Error> {
+        let (r, negated) = self.aggregated_nonce(secp)?;
+        Ok(if negated { negate(&r) } else { r })
+    }
+
+    /// Compute the partial signature of `signer`, with its `key_share` and
+    /// the `nonce` of its commitment
+    pub fn sign<C: secp256k1::Signing + secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        signer: u32,
+        key_share: &SecretKey,
+        nonce: SecretNonce,
+    ) -> Result<PartialSignature, Error> {
+        match self.commitments.get(&signer) {
+            Some(commitment) if *commitment == nonce.commitment(secp) => {}
+            Some(_) => return Err(Error::NonceMismatch(signer)),
+            None => return Err(Error::NotInSigningSet(signer)),
+        }
+        if self.public_shares[&signer].key != secp256k1::PublicKey::from_secret_key(secp, key_share) {
+            return Err(Error::KeyShareMismatch(signer));
+        }
+
+        let (r, negated) = self.aggregated_nonce(secp)?;
+        let e = self.challenge(&r)?;
+
+        let n = order();
+        let mut k = scalar(&nonce.0[..]);
+        if negated {
+            k = &n - k;
+        }
+        let sigma = (k + scalar(&e[..]) * scalar(&key_share[..])) % &n;
+        Ok(PartialSignature { sigma: to_bytes(&sigma) })
+    }
+
+    /// Record the partial signature of `signer`, after checking it
+    pub fn add_partial_signature<C: secp256k1::Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        signer: u32,
+        partial_signature: PartialSignature,
+    ) -> Result<(), Error> {
+        let commitment = match self.commitments.get(&signer) {
+            Some(commitment) => commitment.0,
+            None => return Err(Error::NotInSigningSet(signer)),
+        };
+        if self.partial_signatures.contains_key(&signer) {
+            return Err(Error::DuplicatePartialSignature(signer));
+        }
+
+        // Check that sigma * G = R + e * P for the nonce and key shares
+        let (r, negated) = self.aggregated_nonce(secp)?;
+        let e = self.challenge(&r)?;
+        let expected = {
+            let mut ep = self.public_shares[&signer].key;
+            ep.mul_assign(secp, &e[..])?;
+            let r = if negated { negate(&commitment) } else { commitment };
+            r.combine(&ep).ok()
+        };
+        let actual = SecretKey::from_slice(&partial_signature.sigma[..]).ok()
+            .map(|sigma| secp256k1::PublicKey::from_secret_key(&Secp256k1::signing_only(), &sigma));
+        if actual.is_none() || actual != expected {
+            return Err(Error::InvalidPartialSignature(signer));
+        }
+
+        self.partial_signatures.insert(signer, partial_signature);
+        Ok(())
+    }
+
+    /// Aggregate the partial signatures of the signing set into the Schnorr
+    /// signature of the aggregate public key, the proof of the block
+    pub fn aggregate<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>) -> Result<SchnorrSignature, Error> {
+        if self.partial_signatures.len() < self.threshold {
+            return Err(Error::NotEnoughPartialSignatures {
+                required: self.threshold,
+                received: self.partial_signatures.len(),
+            });
+        }
+        let r = self.nonce_point(secp)?;
+
+        let n = order();
+        let signers = self.signing_set();
+        let mut sigma = BigUint::from(0u32);
+        for (signer, partial_signature) in &self.partial_signatures {
+            sigma += lagrange_coefficient(*signer, &signers) * scalar(&partial_signature.sigma[..]);
+        }
+        sigma %= &n;
+
+        let mut signature = SchnorrSignature::default();
+        signature.r_x.copy_from_slice(&r.serialize()[1..33]);
+        signature.sigma = to_bytes(&sigma);
+        signature.verify(&self.sighash.into_inner(), &self.aggregate_public_key).map_err(Error::Signature)?;
+        Ok(signature)
+    }
+
+    /// The sum of the nonce commitments of the signing set, weighted by
+    /// their Lagrange coefficients, and whether the nonces are to be
+    /// negated for its y coordinate to be a quadratic residue
+    fn aggregated_nonce<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>) -> Result<(secp256k1::PublicKey, bool), Error> {
+        if self.commitments.len() < self.threshold {
+            return Err(Error::NotEnoughCommitments {
+                required: self.threshold,
+                received: self.commitments.len(),
+            });
+        }
+        let signers = self.signing_set();
+        let mut r: Option<secp256k1::PublicKey> = None;
+        for (signer, commitment) in &self.commitments {
+            let mut point = commitment.0;
+            point.mul_assign(secp, &to_bytes(&lagrange_coefficient(*signer, &signers))[..])?;
+            r = Some(match r {
+                Some(r) => r.combine(&point)?,
+                None => point,
+            });
+        }
+        let r = r.expect("the threshold is not 0");
+        let negated = jacobi(&r.serialize_uncompressed()[33..]) != 1;
+        Ok((r, negated))
+    }
+
+    /// The challenge of the Schnorr signature with nonce point `r`
+    fn challenge(&self, r: &secp256k1::PublicKey) -> Result<SecretKey, Error> {
+        SchnorrSignature::compute_e(&r.serialize()[1..33], &self.aggregate_public_key.key, &self.sighash.into_inner())
+            .map_err(Error::Secp256k1)
+    }
+}
+
+/// The order of the secp256k1 group
+fn order() -> BigUint {
+    BigUint::from_bytes_be(&prime::N[..])
+}
+
+fn scalar(bytes: &[u8]) -> BigUint {
+    BigUint::from_bytes_be(bytes)
+}
+
+/// Serialize a scalar lower than the group order to 32 bytes
+fn to_bytes(scalar: &BigUint) -> [u8; 32] {
+    let digits = scalar.to_bytes_be();
+    let mut ret = [0u8; 32];
+    ret[32 - digits.len()..].copy_from_slice(&digits);
+    ret
+}
+
+/// The Lagrange coefficient of `index` to interpolate at 0 the polynomial
+/// from its values at `indexes`, modulo the group order
+fn lagrange_coefficient(index: u32, indexes: &[u32]) -> BigUint {
+    let n = order();
+    let mut numerator = BigUint::from(1u32);
+    let mut denominator = BigUint::from(1u32);
+    for &j in indexes.iter().filter(|j| **j != index) {
+        numerator = numerator * j % &n;
+        // j - index, modulo the group order
+        denominator = denominator * ((BigUint::from(j) + &n - index) % &n) % &n;
+    }
+    // the group order is prime, so the inverse is denominator^(n - 2)
+    let inverse = denominator.modpow(&(&n - 2u32), &n);
+    numerator * inverse % &n
+}
+
+/// Negate a point, flipping the sign of its y coordinate
+fn negate(point: &secp256k1::PublicKey) -> secp256k1::PublicKey {
+    let mut data = point.serialize();
+    data[0] ^= 1;
+    secp256k1::PublicKey::from_slice(&data[..]).expect("the negation of a point is a point")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use blockdata::block::{BlockHeader, XField};
+    use hash_types::{BlockHash, TxMerkleNode};
+    use hashes::sha256;
+
+    /// Shares of the key 1 + 2x + 3x^2 at 1, 2, 3 and 4, a 3 of 4 federation
+    fn key_shares() -> (SecretKey, BTreeMap<u32, SecretKey>) {
+        let n = order();
+        let key = SecretKey::from_slice(&sha256::Hash::hash(b"aggregate")[..]).unwrap();
+        let a = scalar(&sha256::Hash::hash(b"a")[..]);
+        let b = scalar(&sha256::Hash::hash(b"b")[..]);
+        let shares = (1..5u32).map(|i| {
+            let share = (scalar(&key[..]) + &a * i + &b * i * i) % &n;
+            (i, SecretKey::from_slice(&to_bytes(&share)[..]).unwrap())
+        }).collect();
+        (key, shares)
+    }
+
+    fn nonce(seed: &str, signer: u32) -> SecretNonce {
+        let data = format!("{} {}", seed, signer);
+        SecretNonce::new(SecretKey::from_slice(&sha256::Hash::hash(data.as_bytes())[..]).unwrap())
+    }
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_blockhash: BlockHash::hash(&[0]),
+            merkle_root: TxMerkleNode::hash(&[0]),
+            im_merkle_root: TxMerkleNode::hash(&[1]),
+            time: 1575442161,
+            xfield: XField::None,
+            proof: None,
+        }
+    }
+
+    #[test]
+    fn test_threshold_signing() {
+        let secp = Secp256k1::new();
+        let (key, shares) = key_shares();
+        let aggregate_public_key = PublicKey { compressed: true, key: secp256k1::PublicKey::from_secret_key(&secp, &key) };
+        let public_shares: BTreeMap<u32, PublicKey> = shares.iter().map(|(i, share)| {
+            (*i, PublicKey { compressed: true, key: secp256k1::PublicKey::from_secret_key(&secp, share) })
+        }).collect();
+
+        // several sessions, for both signs of the aggregated nonce
+        let mut negated = vec![];
+        for seed in 0..8 {
+            let seed = seed.to_string();
+            let mut header = header();
+            let mut session = SigningSession::new(header.signature_hash(), aggregate_public_key, 3, public_shares.clone()).unwrap();
+
+            for signer in &[4, 1, 3] {
+                session.add_commitment(*signer, nonce(&seed, *signer).commitment(&secp)).unwrap();
+            }
+            assert_eq!(session.signing_set(), vec![1, 3, 4]);
+            match session.add_commitment(2, nonce(&seed, 2).commitment(&secp)) {
+                Err(Error::SigningSetComplete) => {}
+                _ => panic!("expected a complete signing set"),
+            }
+            negated.push(session.aggregated_nonce(&secp).unwrap().1);
+
+            for signer in &[1, 3, 4] {
+                let partial = session.sign(&secp, *signer, &shares[signer], nonce(&seed, *signer)).unwrap();
+                session.add_partial_signature(&secp, *signer, partial).unwrap();
+            }
+
+            header.proof = Some(session.aggregate(&secp).unwrap());
+            assert!(header.verify_proof(&aggregate_public_key).is_ok());
+        }
+        assert!(negated.contains(&true) && negated.contains(&false));
+    }
+
+    #[test]
+    fn test_signing_errors() {
+        let secp = Secp256k1::new();
+        let (key, shares) = key_shares();
+        let aggregate_public_key = PublicKey { compressed: true, key: secp256k1::PublicKey::from_secret_key(&secp, &key) };
+        let public_shares: BTreeMap<u32, PublicKey> = shares.iter().map(|(i, share)| {
+            (*i, PublicKey { compressed: true, key: secp256k1::PublicKey::from_secret_key(&secp, share) })
+        }).collect();
+        let sighash = header().signature_hash();
+
+        assert!(SigningSession::new(sighash, aggregate_public_key, 5, public_shares.clone()).is_err());
+        let mut session = SigningSession::new(sighash, aggregate_public_key, 2, public_shares).unwrap();
+
+        match session.add_commitment(5, nonce("", 5).commitment(&secp)) {
+            Err(Error::UnknownSigner(5)) => {}
+            _ => panic!("expected an unknown signer"),
+        }
+        session.add_commitment(1, nonce("", 1).commitment(&secp)).unwrap();
+        match session.sign(&secp, 1, &shares[&1], nonce("", 1)) {
+            Err(Error::NotEnoughCommitments { required: 2, received: 1 }) => {}
+            _ => panic!("expected missing commitments"),
+        }
+        session.add_commitment(2, nonce("", 2).commitment(&secp)).unwrap();
+
+        match session.sign(&secp, 1, &shares[&1], nonce("other", 1)) {
+            Err(Error::NonceMismatch(1)) => {}
+            _ => panic!("expected a nonce mismatch"),
+        }
+        match session.sign(&secp, 1, &shares[&2], nonce("", 1)) {
+            Err(Error::KeyShareMismatch(1)) => {}
+            _ => panic!("expected a key share mismatch"),
+        }
+        match session.sign(&secp, 3, &shares[&3], nonce("", 3)) {
+            Err(Error::NotInSigningSet(3)) => {}
+            _ => panic!("expected a signer out of the signing set"),
+        }
+
+        let partial = session.sign(&secp, 1, &shares[&1], nonce("", 1)).unwrap();
+        match session.add_partial_signature(&secp, 2, partial) {
+            Err(Error::InvalidPartialSignature(2)) => {}
+            _ => panic!("expected an invalid partial signature"),
+        }
+        session.add_partial_signature(&secp, 1, partial).unwrap();
+        match session.add_partial_signature(&secp, 1, partial) {
+            Err(Error::DuplicatePartialSignature(1)) => {}
+            _ => panic!("expected a duplicate partial signature"),
+        }
+        match session.aggregate(&secp) {
+            Err(Error::NotEnoughPartialSignatures { required: 2, received: 1 }) => {}
+            _ => panic!("expected missing partial signatures"),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Fee rates and weights
+//!
+//! This module defines `Weight`, the size of transactions in weight units
+//! as defined by BIP141, and `FeeRate`, the fee paid per weight unit. Both
+//! implement checked arithmetic and conversions to the virtual byte units
+//! used by fee estimators and relay policies.
+//!
+
+use core::fmt;
+use core::iter::Sum;
+use core::ops;
+
+use util::amount::Amount;
+
+/// Number of weight units in a virtual byte
+pub const WITNESS_SCALE_FACTOR: u64 = 4;
+
+/// The weight of a transaction or block, in weight units
+///
+/// The operations from [std::ops] panic on overflow, use the checked
+/// arithmetic methods to handle it.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Weight(u64);
+
+impl Weight {
+    /// Zero weight
+    pub const ZERO: Weight = Weight(0);
+    /// The maximum weight of a block
+    pub const MAX_BLOCK: Weight = Weight(4_000_000);
+
+    /// Create a weight from weight units
+    pub fn from_wu(wu: u64) -> Weight {
+        Weight(wu)
+    }
+
+    /// Create a weight from virtual bytes. Returns `None` on overflow.
+    pub fn from_vb(vb: u64) -> Option<Weight> {
+        vb.checked_mul(WITNESS_SCALE_FACTOR).map(Weight)
+    }
+
+    /// Create the weight of data without witness, from its size in bytes.
+    /// Returns `None` on overflow.
+    pub fn from_non_witness_size(size: u64) -> Option<Weight> {
+        Weight::from_vb(size)
+    }
+
+    /// The number of weight units
+    pub fn to_wu(self) -> u64 {
+        self.0
+    }
+
+    /// The number of virtual bytes, rounded down
+    pub fn to_vbytes_floor(self) -> u64 {
+        self.0 / WITNESS_SCALE_FACTOR
+    }
+
+    /// The number of virtual bytes, rounded up, as used to compute fees
+    pub fn to_vbytes_ceil(self) -> u64 {
+        (self.0 + WITNESS_SCALE_FACTOR - 1) / WITNESS_SCALE_FACTOR
+    }
+
+    /// Checked addition. Returns `None` on overflow.
+    pub fn checked_add(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_add(rhs.0).map(Weight)
+    }
+
+    /// Checked subtraction. Returns `None` on underflow.
+    pub fn checked_sub(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_sub(rhs.0).map(Weight)
+    }
+
+    /// Checked multiplication. Returns `None` on overflow.
+    pub fn checked_mul(self, rhs: u64) -> Option<Weight> {
+        self.0.checked_mul(rhs).map(Weight)
+    }
+
+    /// Checked division. Returns `None` if `rhs` is zero.
+    pub fn checked_div(self, rhs: u64) -> Option<Weight> {
+        self.0.checked_div(rhs).map(Weight)
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} wu", self.0)
+    }
+}
+
+impl ops::Add for Weight {
+    type Output = Weight;
+
+    fn add(self, rhs: Weight) -> Weight {
+        self.checked_add(rhs).expect("Weight addition error")
+    }
+}
+
+impl ops::AddAssign for Weight {
+    fn add_assign(&mut self, rhs: Weight) {
+        *self = *self + rhs
+    }
+}
+
+impl ops::Sub for Weight {
+    type Output = Weight;
+
+    fn sub(self, rhs: Weight) -> Weight {
+        self.checked_sub(rhs).expect("Weight subtraction error")
+    }
+}
+
+impl ops::SubAssign for Weight {
+    fn sub_assign(&mut self, rhs: Weight) {
+        *self = *self - rhs
+    }
+}
+
+impl ops::Mul<u64> for Weight {
+    type Output = Weight;
+
+    fn mul(self, rhs: u64) -> Weight {
+        self.checked_mul(rhs).expect("Weight multiplication error")
+    }
+}
+
+impl ops::Div<u64> for Weight {
+    type Output = Weight;
+
+    fn div(self, rhs: u64) -> Weight {
+        self.checked_div(rhs).expect("Weight division error")
+    }
+}
+
+impl Sum for Weight {
+    fn sum<I: Iterator<Item = Weight>>(iter: I) -> Weight {
+        iter.fold(Weight::ZERO, |acc, w| acc + w)
+    }
+}
+
+/// A fee rate, stored in satoshis per 1000 weight units
+///
+/// The operations from [std::ops] panic on overflow, use the checked
+/// arithmetic methods to handle it.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// Zero fee rate
+    pub const ZERO: FeeRate = FeeRate(0);
+    /// The default minimum relay fee rate of Tapyrus Core, 1000 sat/kvB
+    pub const DEFAULT_MIN_RELAY: FeeRate = FeeRate(250);
+    /// The default dust relay fee rate of Tapyrus Core, 3000 sat/kvB
+    pub const DEFAULT_DUST_RELAY: FeeRate = FeeRate(750);
+
+    /// Create a fee rate from satoshis per 1000 weight units
+    pub fn from_sat_per_kwu(sat_kwu: u64) -> FeeRate {
+        FeeRate(sat_kwu)
+    }
+
+    /// Create a fee rate from satoshis per virtual byte. Returns `None` on
+    /// overflow.
+    pub fn from_sat_per_vb(sat_vb: u64) -> Option<FeeRate> {
+        // 1 vB = 4 wu, so 1 sat/vB = 250 sat/kwu
+        sat_vb.checked_mul(1000 / WITNESS_SCALE_FACTOR).map(FeeRate)
+    }
+
+    /// Create a fee rate from satoshis per 1000 virtual bytes, as used by
+    /// Tapyrus Core. Rates are rounded down to whole sat/kwu.
+    pub fn from_sat_per_kvb(sat_kvb: u64) -> FeeRate {
+        FeeRate(sat_kvb / WITNESS_SCALE_FACTOR)
+    }
+
+    /// The fee rate paying `fee` for `weight`, rounded down. Returns `None`
+    /// if `weight` is zero or on overflow.
+    pub fn from_fee_and_weight(fee: Amount, weight: Weight) -> Option<FeeRate> {
+        fee.as_sat().checked_mul(1000)?.checked_div(weight.to_wu()).map(FeeRate)
+    }
+
+    /// The fee rate in satoshis per 1000 weight units
+    pub fn as_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+
+    /// The fee rate in satoshis per virtual byte, rounded down
+    pub fn as_sat_per_vb_floor(self) -> u64 {
+        self.0 / (1000 / WITNESS_SCALE_FACTOR)
+    }
+
+    /// The fee rate in satoshis per virtual byte, rounded up
+    pub fn as_sat_per_vb_ceil(self) -> u64 {
+        let per_vb = 1000 / WITNESS_SCALE_FACTOR;
+        self.0 / per_vb + if self.0 % per_vb == 0 { 0 } else { 1 }
+    }
+
+    /// The fee rate in satoshis per 1000 virtual bytes
+    pub fn as_sat_per_kvb(self) -> u64 {
+        self.0.saturating_mul(WITNESS_SCALE_FACTOR)
+    }
+
+    /// The fee to pay for `weight` at this rate, rounded up. Returns `None`
+    /// on overflow.
+    pub fn fee_wu(self, weight: Weight) -> Option<Amount> {
+        let fee = self.0.checked_mul(weight.to_wu())?.checked_add(999)? / 1000;
+        Some(Amount::from_sat(fee))
+    }
+
+    /// The fee to pay for `vb` virtual bytes at this rate, rounded up.
+    /// Returns `None` on overflow.
+    pub fn fee_vb(self, vb: u64) -> Option<Amount> {
+        self.fee_wu(Weight::from_vb(vb)?)
+    }
+
+    /// Checked addition. Returns `None` on overflow.
+    pub fn checked_add(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_add(rhs.0).map(FeeRate)
+    }
+
+    /// Checked subtraction. Returns `None` on underflow.
+    pub fn checked_sub(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_sub(rhs.0).map(FeeRate)
+    }
+
+    /// Checked multiplication. Returns `None` on overflow.
+    pub fn checked_mul(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_mul(rhs).map(FeeRate)
+    }
+
+    /// Checked division. Returns `None` if `rhs` is zero.
+    pub fn checked_div(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_div(rhs).map(FeeRate)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sat/kwu", self.0)
+    }
+}
+
+impl ops::Add for FeeRate {
+    type Output = FeeRate;
+
+    fn add(self, rhs: FeeRate) -> FeeRate {
+        self.checked_add(rhs).expect("FeeRate addition error")
+    }
+}
+
+impl ops::Sub for FeeRate {
+    type Output = FeeRate;
+
+    fn sub(self, rhs: FeeRate) -> FeeRate {
+        self.checked_sub(rhs).expect("FeeRate subtraction error")
+    }
+}
+
+impl ops::Mul<u64> for FeeRate {
+    type Output = FeeRate;
+
+    fn mul(self, rhs: u64) -> FeeRate {
+        self.checked_mul(rhs).expect("FeeRate multiplication error")
+    }
+}
+
+impl ops::Div<u64> for FeeRate {
+    type Output = FeeRate;
+
+    fn div(self, rhs: u64) -> FeeRate {
+        self.checked_div(rhs).expect("FeeRate division error")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+
+    use blockdata::transaction::Transaction;
+    use consensus::encode::deserialize;
+    use util::amount::Amount;
+    use super::{FeeRate, Weight};
+
+    #[test]
+    fn weight_test() {
+        let weight = Weight::from_vb(10).unwrap();
+        assert_eq!(weight.to_wu(), 40);
+        assert_eq!(Weight::from_wu(41).to_vbytes_floor(), 10);
+        assert_eq!(Weight::from_wu(41).to_vbytes_ceil(), 11);
+        assert_eq!(weight + Weight::from_wu(2), Weight::from_wu(42));
+        assert_eq!(weight - Weight::from_wu(2), Weight::from_wu(38));
+        assert_eq!(weight * 3, Weight::from_wu(120));
+        assert_eq!(weight / 4, Weight::from_wu(10));
+        assert_eq!(vec![weight, weight].into_iter().sum::<Weight>(), Weight::from_wu(80));
+        assert_eq!(Weight::from_vb(u64::max_value()), None);
+        assert_eq!(Weight::ZERO.checked_sub(weight), None);
+        assert_eq!(weight.checked_div(0), None);
+        assert_eq!(weight.to_string(), "40 wu");
+    }
+
+    #[test]
+    fn fee_rate_test() {
+        let rate = FeeRate::from_sat_per_vb(2).unwrap();
+        assert_eq!(rate.as_sat_per_kwu(), 500);
+        assert_eq!(rate.as_sat_per_kvb(), 2000);
+        assert_eq!(rate.as_sat_per_vb_floor(), 2);
+        assert_eq!(FeeRate::from_sat_per_kwu(251).as_sat_per_vb_floor(), 1);
+        assert_eq!(FeeRate::from_sat_per_kwu(251).as_sat_per_vb_ceil(), 2);
+        assert_eq!(FeeRate::from_sat_per_kvb(1000), FeeRate::DEFAULT_MIN_RELAY);
+        assert_eq!(FeeRate::from_sat_per_vb(u64::max_value()), None);
+
+        assert_eq!(rate.fee_vb(100), Some(Amount::from_sat(200)));
+        // rounded up
+        assert_eq!(rate.fee_wu(Weight::from_wu(3)), Some(Amount::from_sat(2)));
+        assert_eq!(FeeRate::from_sat_per_kwu(u64::max_value()).fee_wu(Weight::from_wu(2)), None);
+
+        assert_eq!(FeeRate::from_fee_and_weight(Amount::from_sat(200), Weight::from_vb(100).unwrap()), Some(rate));
+        assert_eq!(FeeRate::from_fee_and_weight(Amount::from_sat(200), Weight::ZERO), None);
+        assert_eq!(rate * 2 - rate, rate);
+        assert_eq!(rate.to_string(), "500 sat/kwu");
+    }
+
+    #[test]
+    fn transaction_fee_rate_test() {
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex("01000000010c7196428403d8b0c88fcb3ee8d64f56f55c8973c9ab7dd106bb4f3527f5888d000000006a4730440220503a696f55f2c00eee2ac5e65b17767cd88ed04866b5637d3c1d5d996a70656d02202c9aff698f343abb6d176704beda63fcdec503133ea4f6a5216b7f925fa9910c0121024d89b5a13d6521388969209df27a8469bd565aff10e8d42cef931fad5121bfb8ffffffff02b825b404000000001976a914ef79e7ee9fff98bcfd08473d2b76b02a48f8c69088ac0000000000000000296a2732363030393438363937313732333132373633313032313332353630353838373931323132373000000000").unwrap()).unwrap();
+        assert_eq!(tx.weight(), Weight::from_vb(tx.size() as u64).unwrap());
+        let fee = FeeRate::DEFAULT_MIN_RELAY.fee_wu(tx.weight()).unwrap();
+        assert_eq!(fee, Amount::from_sat(tx.size() as u64));
+        assert_eq!(tx.fee_rate(fee), FeeRate::DEFAULT_MIN_RELAY);
+    }
+}
@@ -21,22 +21,35 @@
 //! Keys used in Bitcoin that can be roundtrip (de)serialized.
 //!
 
-use std::fmt::{self, Write};
-use std::{io, ops, error};
-use std::str::FromStr;
+use core::fmt::{self, Write};
+use core::{cmp, ops};
+use core::str::FromStr;
+#[cfg(feature = "std")] use std::error;
 
+use blockdata::transaction::SigHashType;
 use consensus::{encode, Decodable, Encodable};
+use io;
+use prelude::*;
 use network::constants::Network;
 use secp256k1::{self, Secp256k1};
-use util::base58;
+use util::{base58, ecdsa};
 
 /// A key-related error.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Error {
     /// Base58 encoding error
     Base58(base58::Error),
     /// secp256k1-related error
     Secp256k1(secp256k1::Error),
+    /// The compression flag of a WIF key is not 1
+    InvalidCompressionFlag(u8),
+    /// The WIF key is not of the network it is required for
+    NetworkMismatch {
+        /// The network required
+        expected: Network,
+        /// The network of the key
+        found: Network,
+    },
 }
 
 
@@ -45,15 +58,20 @@ impl fmt::Display for Error {
         match *self {
             Error::Base58(ref e) => write!(f, "base58 error: {}", e),
             Error::Secp256k1(ref e) => write!(f, "secp256k1 error: {}", e),
+            Error::InvalidCompressionFlag(flag) => write!(f, "invalid WIF compression flag {}", flag),
+            Error::NetworkMismatch { expected, found } => write!(f,
+                "private key of network {} is not valid on network {}", found, expected),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::Base58(ref e) => Some(e),
             Error::Secp256k1(ref e) => Some(e),
+            Error::InvalidCompressionFlag(_) | Error::NetworkMismatch { .. } => None,
         }
     }
 
@@ -117,6 +135,17 @@ impl PublicKey {
         })
     }
 
+    /// Compare the keys by their serialization, the order in which BIP67
+    /// sorts the keys of multisig scripts. Unlike `Ord`, which compares the
+    /// internal representation of keys, it does not depend on the library.
+    pub fn cmp_serialized(&self, other: &PublicKey) -> cmp::Ordering {
+        if self.compressed && other.compressed {
+            self.key.serialize()[..].cmp(&other.key.serialize()[..])
+        } else {
+            self.to_bytes().cmp(&other.to_bytes())
+        }
+    }
+
     /// Computes the public key as supposed to be used with this secret
     pub fn from_private_key<C: secp256k1::Signing>(secp: &Secp256k1<C>, sk: &PrivateKey) -> PublicKey {
         sk.public_key(secp)
@@ -163,6 +192,13 @@ impl FromStr for PublicKey {
     }
 }
 
+/// Sort `keys` by their serialization, as BIP67 specifies for the keys of
+/// multisig scripts, so that scripts built by different parties from the
+/// same keys are identical
+pub fn sort_keys(keys: &mut [PublicKey]) {
+    keys.sort_by(PublicKey::cmp_serialized)
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 /// A Bitcoin ECDSA private key
 pub struct PrivateKey {
@@ -175,6 +211,16 @@ pub struct PrivateKey {
 }
 
 impl PrivateKey {
+    /// Generate a random private key of `network`, serialized compressed
+    #[cfg(feature = "rand")]
+    pub fn generate<R: ::secp256k1::rand::Rng + ?Sized>(rng: &mut R, network: Network) -> PrivateKey {
+        PrivateKey {
+            compressed: true,
+            network: network,
+            key: secp256k1::SecretKey::new(rng),
+        }
+    }
+
     /// Creates a public key from this private key
     pub fn public_key<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>) -> PublicKey {
         PublicKey {
@@ -183,6 +229,25 @@ impl PrivateKey {
         }
     }
 
+    /// Sign `message` with ECDSA, grinding the nonce as Tapyrus Core does
+    /// until R is lower than 2^255, which makes DER signatures one byte
+    /// shorter. Signatures are deterministic, and identical to Core's.
+    pub fn sign_low_r<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>, message: &[u8; 32]) -> secp256k1::Signature {
+        let msg = secp256k1::Message::from_slice(&message[..]).expect("messages are 32 bytes");
+        secp.sign_low_r(&msg, &self.key)
+    }
+
+    /// Sign the signature hash `sighash` with a low R signature, serialized
+    /// in DER and followed by `sighash_type`, as in script signatures
+    pub fn sign_for_script<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        sighash: &[u8; 32],
+        sighash_type: SigHashType,
+    ) -> Vec<u8> {
+        ecdsa::Signature::new(self.sign_low_r(secp, sighash), sighash_type).serialize()
+    }
+
     /// Serialize the private key to bytes
     pub fn to_bytes(&self) -> Vec<u8> {
         self.key[..].to_vec()
@@ -191,10 +256,7 @@ impl PrivateKey {
     /// Format the private key to WIF format.
     pub fn fmt_wif(&self, fmt: &mut fmt::Write) -> fmt::Result {
         let mut ret = [0; 34];
-        ret[0] = match self.network {
-            Network::Prod => 128,
-            Network::Dev => 239,
-        };
+        ret[0] = self.network.params().wif_prefix;
         ret[1..33].copy_from_slice(&self.key[..]);
         let privkey = if self.compressed {
             ret[33] = 1;
@@ -213,20 +275,39 @@ impl PrivateKey {
         buf
     }
 
-    /// Parse WIF encoded private key.
+    /// Parse WIF encoded private key of one of the default networks.
     pub fn from_wif(wif: &str) -> Result<PrivateKey, Error> {
+        PrivateKey::parse_wif(wif, &Network::defaults())
+    }
+
+    /// Parse WIF encoded private key of the given network, which may be a
+    /// custom one. Keys of another default network are rejected with
+    /// `Error::NetworkMismatch`.
+    pub fn from_wif_for_network(wif: &str, network: Network) -> Result<PrivateKey, Error> {
+        match PrivateKey::parse_wif(wif, &[network]) {
+            Err(Error::Base58(base58::Error::InvalidVersion(version))) => {
+                match Network::defaults().iter().find(|n| n.params().wif_prefix == version[0]) {
+                    Some(found) => Err(Error::NetworkMismatch { expected: network, found: *found }),
+                    None => Err(Error::Base58(base58::Error::InvalidVersion(version))),
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn parse_wif(wif: &str, networks: &[Network]) -> Result<PrivateKey, Error> {
         let data = base58::from_check(wif)?;
 
         let compressed = match data.len() {
             33 => false,
-            34 => true,
+            34 if data[33] == 1 => true,
+            34 => { return Err(Error::InvalidCompressionFlag(data[33])); }
             _ => { return Err(Error::Base58(base58::Error::InvalidLength(data.len()))); }
         };
 
-        let network = match data[0] {
-            128 => Network::Prod,
-            239 => Network::Dev,
-            x   => { return Err(Error::Base58(base58::Error::InvalidVersion(vec![x]))); }
+        let network = match networks.iter().find(|n| n.params().wif_prefix == data[0]) {
+            Some(network) => *network,
+            None => { return Err(Error::Base58(base58::Error::InvalidVersion(vec![data[0]]))); }
         };
 
         Ok(PrivateKey {
@@ -335,6 +416,22 @@ impl Encodable for PublicKey {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for PublicKey {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<PublicKey> {
+        // Not every x coordinate is on the curve
+        let mut data = [0u8; 33];
+        data[0] = if u.arbitrary()? { 0x02 } else { 0x03 };
+        data[1..].copy_from_slice(&u.arbitrary::<[u8; 32]>()?);
+        let key = secp256k1::PublicKey::from_slice(&data)
+            .map_err(|_| ::arbitrary::Error::IncorrectFormat)?;
+        Ok(PublicKey {
+            compressed: u.arbitrary()?,
+            key: key,
+        })
+    }
+}
+
 #[cfg(feature = "serde")]
 impl<'de> ::serde::Deserialize<'de> for PublicKey {
     fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<PublicKey, D::Error> {
@@ -392,9 +489,13 @@ impl<'de> ::serde::Deserialize<'de> for PublicKey {
 
 #[cfg(test)]
 mod tests {
-    use super::{PrivateKey, PublicKey};
+    use super::{sort_keys, Error, PrivateKey, PublicKey};
+    use blockdata::transaction::SigHashType;
+    use hex::decode as hex_decode;
+    use util::base58;
     use secp256k1::Secp256k1;
     use std::str::FromStr;
+    use network::constants::{Network, NetworkParams};
     use network::constants::Network::Dev;
     use network::constants::Network::Prod;
     use util::address::Address;
@@ -435,6 +536,120 @@ mod tests {
         assert_eq!(pk, PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap());
     }
 
+    #[test]
+    fn test_custom_network_wif() {
+        let params = NetworkParams { wif_prefix: 0x80 + 50, ..NetworkParams::dev_with_id(101) };
+        let network = Network::Custom(params);
+
+        let mut sk = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        sk.network = network;
+        let wif = sk.to_wif();
+        assert!(PrivateKey::from_wif(&wif).is_err());
+        let parsed = PrivateKey::from_wif_for_network(&wif, network).unwrap();
+        assert_eq!(parsed.network, network);
+        assert_eq!(parsed.key, sk.key);
+        assert!(PrivateKey::from_wif_for_network("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy", network).is_err());
+    }
+
+    #[test]
+    fn test_wif_errors() {
+        let sk = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        assert_eq!(PrivateKey::from_wif_for_network("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy", Dev), Ok(sk));
+        assert_eq!(
+            PrivateKey::from_wif_for_network("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy", Prod),
+            Err(Error::NetworkMismatch { expected: Prod, found: Dev })
+        );
+
+        let wif = base58::encode_check_with_version(&[0xef], &[&sk.key[..], &[2][..]].concat());
+        assert_eq!(PrivateKey::from_wif(&wif), Err(Error::InvalidCompressionFlag(2)));
+
+        let wif = base58::encode_check_with_version(&[0x30], &sk.key[..]);
+        assert_eq!(PrivateKey::from_wif(&wif), Err(Error::Base58(base58::Error::InvalidVersion(vec![0x30]))));
+        assert_eq!(
+            PrivateKey::from_wif_for_network(&wif, Prod),
+            Err(Error::Base58(base58::Error::InvalidVersion(vec![0x30])))
+        );
+
+        let wif = base58::encode_check_with_version(&[0xef], &sk.key[..31]);
+        assert_eq!(PrivateKey::from_wif(&wif), Err(Error::Base58(base58::Error::InvalidLength(32))));
+    }
+
+    #[test]
+    fn test_sign_low_r() {
+        let secp = Secp256k1::new();
+        let sk = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let mut message = [0u8; 32];
+
+        // libsecp256k1 returns a low R signature at once
+        message.copy_from_slice(&hex_decode("e51e305dce571a7114b06fb46c0883375198cf5d1c3535fa347496db41a45459").unwrap());
+        let msg = secp256k1::Message::from_slice(&message[..]).unwrap();
+        let signature = sk.sign_low_r(&secp, &message);
+        assert_eq!(signature, secp.sign(&msg, &sk.key));
+        assert_eq!(
+            hex::encode(&signature.serialize_der()[..]),
+            "30440220531fdb975aa9a8798b4bc5c8b906c692fa27faf9a0c631aab6881cb3030967e502203cabcd5100f46d8e8c18d775467db1a7ec399fbfaaa332534ee68b1a3cd256aa"
+        );
+
+        // two more nonces are needed
+        message.copy_from_slice(&hex_decode("f8624376501121446b7f5a49b9b7e261312039e746beff7301b1dc9e03480ed3").unwrap());
+        let msg = secp256k1::Message::from_slice(&message[..]).unwrap();
+        assert_eq!(
+            hex::encode(&secp.sign(&msg, &sk.key).serialize_der()[..]),
+            "3045022100f6cd6ddc33f23e819a0405d50be644a9f37714c9dbb77256f56a452e151a993c022071b466ced94427da966b0e257812adfa916dbe79cab179ff929419cda0ce8b5f"
+        );
+        let signature = sk.sign_low_r(&secp, &message);
+        assert_eq!(
+            hex::encode(&signature.serialize_der()[..]),
+            "304402201f139fc4e7acd04668d242fb85b354e01b42f09793b7b1624ab4e083ea13ed34022007f9455141d6105bc742f7cfdc4679c9ba664ae4bdb8e70bc10d77684f56a229"
+        );
+        assert!(secp.verify(&msg, &signature, &sk.public_key(&secp).key).is_ok());
+
+        let script_sig = sk.sign_for_script(&secp, &message, SigHashType::All);
+        assert_eq!(&script_sig[..70], &signature.serialize_der()[..]);
+        assert_eq!(script_sig[70], 0x01);
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let key = |s| PublicKey::from_str(s).unwrap();
+        let uncompressed = key("042e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af191923a2964c177f5b5923ae500fca49e99492d534aa3759d6b25a8bc971b133");
+        let compressed = key("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af");
+        // BIP67 test vector 1
+        let a = key("02fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f");
+        let b = key("02ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f8");
+
+        let mut keys = vec![uncompressed, b, compressed, a];
+        sort_keys(&mut keys);
+        assert_eq!(keys, vec![a, b, compressed, uncompressed]);
+        assert!(keys.windows(2).all(|pair| pair[0].cmp_serialized(&pair[1]) == ::std::cmp::Ordering::Less));
+        assert_eq!(a.cmp_serialized(&a), ::std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn test_private_key_eq() {
+        let sk = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        assert_eq!(sk, PrivateKey::from_wif(&sk.to_wif()).unwrap());
+        assert!(sk != PrivateKey { compressed: false, ..sk });
+        assert!(sk != PrivateKey { network: Prod, ..sk });
+        let other = PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        assert!(sk != PrivateKey { key: other.key, ..sk });
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_generate() {
+        let mut rng = ::secp256k1::rand::thread_rng();
+        let sk = PrivateKey::generate(&mut rng, Dev);
+        assert_eq!(sk.network, Dev);
+        assert!(sk.compressed);
+        assert_eq!(PrivateKey::from_wif(&sk.to_wif()).unwrap(), sk);
+        assert!(PrivateKey::generate(&mut rng, Dev) != sk);
+
+        let secp = Secp256k1::new();
+        let address = Address::p2pkh(&sk.public_key(&secp), sk.network);
+        assert!(address.is_valid_for_network(Dev));
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_key_serde() {
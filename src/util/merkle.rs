@@ -0,0 +1,186 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! # Merkle Trees
+//!
+//! Blocks commit to their transactions by the root of a merkle tree, whose
+//! leaves are the transaction ids and whose nodes hash the concatenation
+//! of their two children; a level with an odd number of nodes pairs its
+//! last node with itself. [MerkleTree] builds such a tree over any hash
+//! type, and extracts the [MerkleBranch] of a leaf: the siblings of the
+//! nodes on its path to the root, proving that the leaf is in the tree.
+//!
+
+use core::cmp;
+use io;
+use prelude::*;
+
+use hashes::{Hash, HashEngine};
+use consensus::encode::{self, Decodable, Encodable, VarInt};
+
+/// The maximum length of a branch, the depth of a tree of 2^32 leaves
+const MAX_BRANCH_LEN: usize = 32;
+
+/// The parent node of `left` and `right`
+pub fn merkle_node<T: Hash>(left: &T, right: &T) -> T {
+    let mut engine = T::engine();
+    engine.input(&left[..]);
+    engine.input(&right[..]);
+    T::from_engine(engine)
+}
+
+/// A merkle tree, holding all of its nodes
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MerkleTree<T: Hash> {
+    /// The levels of the tree, from the leaves up to the root
+    levels: Vec<Vec<T>>,
+}
+
+impl<T: Hash> MerkleTree<T> {
+    /// Build the tree of `leaves`
+    pub fn new(leaves: Vec<T>) -> MerkleTree<T> {
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1].chunks(2)
+                .map(|pair| merkle_node(&pair[0], &pair[pair.len() - 1]))
+                .collect();
+            levels.push(parents);
+        }
+        MerkleTree { levels: levels }
+    }
+
+    /// The leaves of the tree
+    pub fn leaves(&self) -> &[T] {
+        &self.levels[0]
+    }
+
+    /// The root of the tree, the default hash if the tree has no leaves
+    pub fn root(&self) -> T {
+        self.levels[self.levels.len() - 1].first().cloned().unwrap_or_default()
+    }
+
+    /// The branch of the leaf at `index`, `None` if there is no such leaf
+    pub fn branch(&self, index: usize) -> Option<MerkleBranch<T>> {
+        if index >= self.leaves().len() {
+            return None;
+        }
+        let hashes = self.levels[..self.levels.len() - 1].iter().enumerate().map(|(depth, level)| {
+            // the last node of an odd level is its own sibling
+            level[cmp::min((index >> depth) ^ 1, level.len() - 1)]
+        }).collect();
+        Some(MerkleBranch { index: index as u32, hashes: hashes })
+    }
+}
+
+/// The branch of a leaf of a merkle tree, proving that the leaf is in the
+/// tree of a given root
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MerkleBranch<T: Hash> {
+    /// The position of the leaf in the tree
+    pub index: u32,
+    /// The siblings of the nodes on the path from the leaf to the root
+    pub hashes: Vec<T>,
+}
+
+impl<T: Hash> MerkleBranch<T> {
+    /// The root of the tree holding `leaf` at the position of the branch
+    pub fn root(&self, leaf: T) -> T {
+        self.hashes.iter().enumerate().fold(leaf, |node, (depth, sibling)| {
+            if self.index.checked_shr(depth as u32).unwrap_or(0) & 1 == 0 {
+                merkle_node(&node, sibling)
+            } else {
+                merkle_node(sibling, &node)
+            }
+        })
+    }
+
+    /// Whether `leaf` is in the tree of `root`, at the position of the
+    /// branch. The position must be one of a tree as deep as the branch.
+    pub fn verify(&self, leaf: T, root: &T) -> bool {
+        let len = self.hashes.len() as u32;
+        self.index.checked_shr(len).unwrap_or(0) == 0 && self.root(leaf) == *root
+    }
+}
+
+impl<T: Hash + Encodable> Encodable for MerkleBranch<T> {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, encode::Error> {
+        let mut len = self.index.consensus_encode(&mut s)?;
+        len += VarInt(self.hashes.len() as u64).consensus_encode(&mut s)?;
+        for hash in &self.hashes {
+            len += hash.consensus_encode(&mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl<T: Hash + Decodable> Decodable for MerkleBranch<T> {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let index = u32::consensus_decode(&mut d)?;
+        let len = VarInt::consensus_decode(&mut d)?.0;
+        if len > MAX_BRANCH_LEN as u64 {
+            return Err(encode::Error::Parse(encode::ParseError::Other("merkle branch too long")));
+        }
+        let mut hashes = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            hashes.push(T::consensus_decode(&mut d)?);
+        }
+        Ok(MerkleBranch { index: index, hashes: hashes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merkle_node, MerkleBranch, MerkleTree};
+
+    use hashes::Hash;
+    use hash_types::TxMerkleNode;
+
+    use consensus::encode::{deserialize, serialize};
+    use util::hash::bitcoin_merkle_root;
+
+    fn leaves(count: u8) -> Vec<TxMerkleNode> {
+        (0..count).map(|i| TxMerkleNode::hash(&[i])).collect()
+    }
+
+    #[test]
+    fn test_root() {
+        for count in 0..20 {
+            let tree = MerkleTree::new(leaves(count));
+            assert_eq!(tree.leaves(), &leaves(count)[..]);
+            assert_eq!(tree.root(), bitcoin_merkle_root(leaves(count).into_iter()));
+        }
+        let leaves = leaves(3);
+        let root = merkle_node(&merkle_node(&leaves[0], &leaves[1]), &merkle_node(&leaves[2], &leaves[2]));
+        assert_eq!(MerkleTree::new(leaves).root(), root);
+    }
+
+    #[test]
+    fn test_branch() {
+        for count in 1..20 {
+            let tree = MerkleTree::new(leaves(count));
+            let root = tree.root();
+            for (index, leaf) in tree.leaves().iter().enumerate() {
+                let branch = tree.branch(index).unwrap();
+                assert_eq!(branch.index, index as u32);
+                assert!(branch.verify(*leaf, &root));
+                assert!(!branch.verify(TxMerkleNode::hash(&[0xff]), &root));
+                let beyond = MerkleBranch { index: branch.index | 1 << branch.hashes.len(), ..branch.clone() };
+                assert!(!beyond.verify(*leaf, &root));
+            }
+            assert_eq!(tree.branch(count as usize), None);
+        }
+        assert_eq!(MerkleTree::<TxMerkleNode>::new(vec![]).branch(0), None);
+    }
+
+    #[test]
+    fn test_branch_encoding() {
+        let branch = MerkleTree::new(leaves(10)).branch(7).unwrap();
+        let serialized = serialize(&branch);
+        assert_eq!(serialized.len(), 4 + 1 + 4 * 32);
+        assert_eq!(deserialize::<MerkleBranch<TxMerkleNode>>(&serialized).unwrap(), branch);
+
+        let too_long = MerkleBranch { index: 0, hashes: leaves(33) };
+        assert!(deserialize::<MerkleBranch<TxMerkleNode>>(&serialize(&too_long)).is_err());
+    }
+}
@@ -65,10 +65,11 @@
 //! ```
 
 use std::collections::HashSet;
-use std::io;
+use std::str::FromStr;
+use std::{fmt, io};
 
 use hashes::Hash;
-use hash_types::{Txid, TxMerkleNode};
+use hash_types::{MalFixTxid, Txid, TxMerkleNode};
 
 use blockdata::transaction::Transaction;
 use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
@@ -131,6 +132,21 @@ pub struct PartialMerkleTree {
     /// Transaction ids and internal hashes
     hashes: Vec<TxMerkleNode>,
 }
+serde_struct_impl!(PartialMerkleTree, num_transactions, bits, hashes);
+
+#[cfg(feature = "arbitrary")]
+impl ::arbitrary::Arbitrary for PartialMerkleTree {
+    fn arbitrary(u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<PartialMerkleTree> {
+        // Build the tree from a block's worth of txids so that it is consistent
+        let mut txids = vec![u.arbitrary::<Txid>()?];
+        txids.extend(u.arbitrary::<Vec<Txid>>()?);
+        let mut matches = Vec::with_capacity(txids.len());
+        for _ in 0..txids.len() {
+            matches.push(u.arbitrary()?);
+        }
+        Ok(PartialMerkleTree::from_txids(&txids, &matches))
+    }
+}
 
 impl PartialMerkleTree {
     /// Construct a partial merkle tree
@@ -164,13 +180,29 @@ impl PartialMerkleTree {
     /// # }
     /// ```
     pub fn from_txids(txids: &[Txid], matches: &[bool]) -> Self {
+        let leaves: Vec<TxMerkleNode> = txids.iter().map(|txid| TxMerkleNode::from_inner(txid.into_inner())).collect();
+        PartialMerkleTree::from_leaves(&leaves, matches)
+    }
+
+    /// Construct a partial merkle tree of the malleability-fixed txids of a
+    /// block, whose merkle root is the immutable merkle root of the block.
+    /// The `matches` flag the txids which should be included in the proof.
+    ///
+    /// Panics when `malfix_txids` is empty or when `matches` has a different length
+    pub fn from_malfix_txids(malfix_txids: &[MalFixTxid], matches: &[bool]) -> Self {
+        let leaves: Vec<TxMerkleNode> = malfix_txids.iter().map(|txid| TxMerkleNode::from_inner(txid.into_inner())).collect();
+        PartialMerkleTree::from_leaves(&leaves, matches)
+    }
+
+    /// Construct a partial merkle tree of the `leaves` of a merkle tree
+    fn from_leaves(leaves: &[TxMerkleNode], matches: &[bool]) -> Self {
         // We can never have zero txs in a merkle block, we always need the coinbase tx
-        assert_ne!(txids.len(), 0);
-        assert_eq!(txids.len(), matches.len());
+        assert_ne!(leaves.len(), 0);
+        assert_eq!(leaves.len(), matches.len());
 
         let mut pmt = PartialMerkleTree {
-            num_transactions: txids.len() as u32,
-            bits: Vec::with_capacity(txids.len()),
+            num_transactions: leaves.len() as u32,
+            bits: Vec::with_capacity(leaves.len()),
             hashes: vec![],
         };
         // calculate height of tree
@@ -179,7 +211,7 @@ impl PartialMerkleTree {
             height += 1;
         }
         // traverse the partial tree
-        pmt.traverse_and_build(height, 0, txids, matches);
+        pmt.traverse_and_build(height, 0, leaves, matches);
         pmt
     }
 
@@ -190,6 +222,29 @@ impl PartialMerkleTree {
         &self,
         matches: &mut Vec<Txid>,
         indexes: &mut Vec<u32>,
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
+        self.extract(matches, indexes, false)
+    }
+
+    /// Same as `extract_matches`, but also rejecting the trees Bitcoin Core
+    /// would not produce, which prove the same matches with other bits and
+    /// hashes: trees with non-zero padding bits, with nodes flagged as
+    /// parents of a match without any matched txid below them, or matching
+    /// the same txid at several positions, as duplicated leaves do.
+    pub fn extract_matches_strict(
+        &self,
+        matches: &mut Vec<Txid>,
+        indexes: &mut Vec<u32>,
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
+        self.extract(matches, indexes, true)
+    }
+
+    /// Extract the matching txids and their indices, strictly or not
+    fn extract(
+        &self,
+        matches: &mut Vec<Txid>,
+        indexes: &mut Vec<u32>,
+        strict: bool,
     ) -> Result<TxMerkleNode, MerkleBlockError> {
         matches.clear();
         indexes.clear();
@@ -220,7 +275,7 @@ impl PartialMerkleTree {
         let mut bits_used = 0u32;
         let mut hash_used = 0u32;
         let hash_merkle_root =
-            self.traverse_and_extract(height, 0, &mut bits_used, &mut hash_used, matches, indexes)?;
+            self.traverse_and_extract(height, 0, &mut bits_used, &mut hash_used, matches, indexes, strict)?;
         // Verify that all bits were consumed (except for the padding caused by
         // serializing it as a byte sequence)
         if (bits_used + 7) / 8 != (self.bits.len() as u32 + 7) / 8 {
@@ -230,9 +285,36 @@ impl PartialMerkleTree {
         if hash_used != self.hashes.len() as u32 {
             return Err(BadFormat("Not all hashes were consumed".to_owned()));
         }
+        if strict {
+            // The padding bits are zero
+            if self.bits[bits_used as usize..].iter().any(|bit| *bit) {
+                return Err(BadFormat("Found non-zero padding bits".to_owned()));
+            }
+            // Each txid is matched once
+            let unique: HashSet<&Txid> = matches.iter().collect();
+            if unique.len() != matches.len() {
+                return Err(BadFormat("Found duplicate matched txids".to_owned()));
+            }
+        }
         Ok(TxMerkleNode::from_inner(hash_merkle_root.into_inner()))
     }
 
+    /// Extract the matching malleability-fixed txids represented by this
+    /// partial merkle tree, built by `from_malfix_txids`, and their
+    /// respective indices within the partial tree.
+    /// returns the immutable merkle root, or error in case of failure
+    pub fn extract_malfix_matches(
+        &self,
+        matches: &mut Vec<MalFixTxid>,
+        indexes: &mut Vec<u32>,
+    ) -> Result<TxMerkleNode, MerkleBlockError> {
+        let mut txids = vec![];
+        let merkle_root = self.extract_matches(&mut txids, indexes);
+        matches.clear();
+        matches.extend(txids.into_iter().map(|txid| MalFixTxid::from_inner(txid.into_inner())));
+        merkle_root
+    }
+
     /// Helper function to efficiently calculate the number of nodes at given height
     /// in the merkle tree
     #[inline]
@@ -241,16 +323,16 @@ impl PartialMerkleTree {
     }
 
     /// Calculate the hash of a node in the merkle tree (at leaf level: the txid's themselves)
-    fn calc_hash(&self, height: u32, pos: u32, txids: &[Txid]) -> TxMerkleNode {
+    fn calc_hash(&self, height: u32, pos: u32, leaves: &[TxMerkleNode]) -> TxMerkleNode {
         if height == 0 {
             // Hash at height 0 is the txid itself
-            TxMerkleNode::from_inner(txids[pos as usize].into_inner())
+            leaves[pos as usize]
         } else {
             // Calculate left hash
-            let left = self.calc_hash(height - 1, pos * 2, txids);
+            let left = self.calc_hash(height - 1, pos * 2, leaves);
             // Calculate right hash if not beyond the end of the array - copy left hash otherwise
             let right = if pos * 2 + 1 < self.calc_tree_width(height - 1) {
-                self.calc_hash(height - 1, pos * 2 + 1, txids)
+                self.calc_hash(height - 1, pos * 2 + 1, leaves)
             } else {
                 left
             };
@@ -264,7 +346,7 @@ impl PartialMerkleTree {
         &mut self,
         height: u32,
         pos: u32,
-        txids: &[Txid],
+        leaves: &[TxMerkleNode],
         matches: &[bool],
     ) {
         // Determine whether this node is the parent of at least one matched txid
@@ -279,13 +361,13 @@ impl PartialMerkleTree {
 
         if height == 0 || !parent_of_match {
             // If at height 0, or nothing interesting below, store hash and stop
-            let hash = self.calc_hash(height, pos, txids);
+            let hash = self.calc_hash(height, pos, leaves);
             self.hashes.push(hash.into());
         } else {
             // Otherwise, don't store any hash, but descend into the subtrees
-            self.traverse_and_build(height - 1, pos * 2, txids, matches);
+            self.traverse_and_build(height - 1, pos * 2, leaves, matches);
             if pos * 2 + 1 < self.calc_tree_width(height - 1) {
-                self.traverse_and_build(height - 1, pos * 2 + 1, txids, matches);
+                self.traverse_and_build(height - 1, pos * 2 + 1, leaves, matches);
             }
         }
     }
@@ -300,6 +382,7 @@ impl PartialMerkleTree {
         hash_used: &mut u32,
         matches: &mut Vec<Txid>,
         indexes: &mut Vec<u32>,
+        strict: bool,
     ) -> Result<TxMerkleNode, MerkleBlockError> {
         if *bits_used as usize >= self.bits.len() {
             return Err(BadFormat("Overflowed the bits array".to_owned()));
@@ -321,6 +404,7 @@ impl PartialMerkleTree {
             Ok(hash)
         } else {
             // otherwise, descend into the subtrees to extract matched txids and hashes
+            let matched_before = matches.len();
             let left = self.traverse_and_extract(
                 height - 1,
                 pos * 2,
@@ -328,6 +412,7 @@ impl PartialMerkleTree {
                 hash_used,
                 matches,
                 indexes,
+                strict,
             )?;
             let right;
             if pos * 2 + 1 < self.calc_tree_width(height - 1) {
@@ -338,6 +423,7 @@ impl PartialMerkleTree {
                     hash_used,
                     matches,
                     indexes,
+                    strict,
                 )?;
                 if right == left {
                     // The left and right branches should never be identical, as the transaction
@@ -347,6 +433,9 @@ impl PartialMerkleTree {
             } else {
                 right = left;
             }
+            if strict && matches.len() == matched_before {
+                return Err(BadFormat("Found a parent of no matched txid".to_owned()));
+            }
             // and combine them before returning
             Ok(PartialMerkleTree::parent_hash(left, right))
         }
@@ -406,6 +495,8 @@ pub struct MerkleBlock {
     /// Transactions making up a partial merkle tree
     pub txn: PartialMerkleTree,
 }
+serde_struct_impl!(MerkleBlock, header, txn);
+arbitrary_struct_impl!(MerkleBlock, header, txn);
 
 impl MerkleBlock {
     /// Create a MerkleBlock from a block, that should contain proofs for the txids.
@@ -449,18 +540,31 @@ impl MerkleBlock {
     /// # }
     /// ```
     pub fn from_block(block: &Block, match_txids: &HashSet<Txid>) -> Self {
-        let header = block.header.clone();
+        MerkleBlock::from_block_with_predicate(block, |txid| match_txids.contains(txid))
+    }
 
-        let mut matches: Vec<bool> = Vec::with_capacity(block.txdata.len());
-        let mut hashes: Vec<Txid> = Vec::with_capacity(block.txdata.len());
+    /// Create a MerkleBlock from a block, that should contain proofs for the
+    /// txids for which `match_txid` returns true.
+    pub fn from_block_with_predicate<F>(block: &Block, match_txid: F) -> Self
+        where F: Fn(&Txid) -> bool {
+        let txids: Vec<Txid> = block.txdata.iter().map(Transaction::txid).collect();
+        let matches: Vec<bool> = txids.iter().map(|txid| match_txid(txid)).collect();
 
-        for hash in block.txdata.iter().map(Transaction::txid) {
-            matches.push(match_txids.contains(&hash));
-            hashes.push(hash);
-        }
+        let pmt = PartialMerkleTree::from_txids(&txids, &matches);
+        MerkleBlock { header: block.header.clone(), txn: pmt }
+    }
 
-        let pmt = PartialMerkleTree::from_txids(&hashes, &matches);
-        MerkleBlock { header, txn: pmt }
+    /// Create a MerkleBlock from a block, that should contain proofs for the
+    /// malleability-fixed txids for which `match_txid` returns true. Its
+    /// partial merkle tree commits to the immutable merkle root of the
+    /// block, and its matches are extracted by `extract_malfix_matches`.
+    pub fn from_block_with_malfix_predicate<F>(block: &Block, match_txid: F) -> Self
+        where F: Fn(&MalFixTxid) -> bool {
+        let txids: Vec<MalFixTxid> = block.txdata.iter().map(Transaction::malfix_txid).collect();
+        let matches: Vec<bool> = txids.iter().map(|txid| match_txid(txid)).collect();
+
+        let pmt = PartialMerkleTree::from_malfix_txids(&txids, &matches);
+        MerkleBlock { header: block.header.clone(), txn: pmt }
     }
 
     /// Extract the matching txid's represented by this partial merkle tree
@@ -479,6 +583,59 @@ impl MerkleBlock {
             Err(MerkleRootMismatch)
         }
     }
+
+    /// Same as `extract_matches`, but verifying the partial merkle tree as
+    /// strictly as `PartialMerkleTree::extract_matches_strict` does
+    pub fn extract_matches_strict(
+        &self,
+        matches: &mut Vec<Txid>,
+        indexes: &mut Vec<u32>,
+    ) -> Result<(), MerkleBlockError> {
+        let merkle_root = self.txn.extract_matches_strict(matches, indexes)?;
+
+        if merkle_root.eq(&self.header.merkle_root) {
+            Ok(())
+        } else {
+            Err(MerkleRootMismatch)
+        }
+    }
+
+    /// Extract the matching malleability-fixed txids represented by this
+    /// partial merkle tree, built by `from_block_with_malfix_predicate`,
+    /// and their respective indices within the partial tree.
+    /// returns Ok(()) on success, or error in case of failure
+    pub fn extract_malfix_matches(
+        &self,
+        matches: &mut Vec<MalFixTxid>,
+        indexes: &mut Vec<u32>,
+    ) -> Result<(), MerkleBlockError> {
+        let merkle_root = self.txn.extract_malfix_matches(matches, indexes)?;
+
+        if merkle_root.eq(&self.header.im_merkle_root) {
+            Ok(())
+        } else {
+            Err(MerkleRootMismatch)
+        }
+    }
+
+    /// The matching txids represented by this partial merkle tree, paired
+    /// with their indices within the block, once authenticated
+    pub fn matched_txids(&self) -> Result<Vec<(u32, Txid)>, MerkleBlockError> {
+        let mut matches = vec![];
+        let mut indexes = vec![];
+        self.extract_matches(&mut matches, &mut indexes)?;
+        Ok(indexes.into_iter().zip(matches).collect())
+    }
+
+    /// The matching malleability-fixed txids represented by this partial
+    /// merkle tree, paired with their indices within the block, once
+    /// authenticated
+    pub fn matched_malfix_txids(&self) -> Result<Vec<(u32, MalFixTxid)>, MerkleBlockError> {
+        let mut matches = vec![];
+        let mut indexes = vec![];
+        self.extract_malfix_matches(&mut matches, &mut indexes)?;
+        Ok(indexes.into_iter().zip(matches).collect())
+    }
 }
 
 impl Encodable for MerkleBlock {
@@ -501,18 +658,87 @@ impl Decodable for MerkleBlock {
     }
 }
 
+/// A proof that transactions are in a block, as returned by the
+/// `gettxoutproof` RPC of Tapyrus Core and accepted by its
+/// `verifytxoutproof` RPC: a merkle block, hex encoded.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TxOutProof(MerkleBlock);
+serde_consensus_impl!(TxOutProof);
+
+impl TxOutProof {
+    /// Create the proof that the transactions of `txids` are in `block`, as
+    /// `gettxoutproof` does. Returns `None` if `block` lacks any of them.
+    pub fn from_block(block: &Block, txids: &HashSet<Txid>) -> Option<TxOutProof> {
+        let found = block.txdata.iter().filter(|tx| txids.contains(&tx.txid())).count();
+        if found != txids.len() {
+            return None;
+        }
+        Some(TxOutProof(MerkleBlock::from_block(block, txids)))
+    }
+
+    /// The txids the proof commits to, as `verifytxoutproof` returns them.
+    /// Whether the block is in the best chain is left to the caller.
+    pub fn verify(&self) -> Result<Vec<Txid>, MerkleBlockError> {
+        let mut matches = vec![];
+        self.0.extract_matches(&mut matches, &mut vec![])?;
+        Ok(matches)
+    }
+
+    /// The merkle block of the proof
+    pub fn merkle_block(&self) -> &MerkleBlock {
+        &self.0
+    }
+
+    /// Unwrap the merkle block of the proof
+    pub fn into_merkle_block(self) -> MerkleBlock {
+        self.0
+    }
+}
+
+impl From<MerkleBlock> for TxOutProof {
+    fn from(merkle_block: MerkleBlock) -> TxOutProof {
+        TxOutProof(merkle_block)
+    }
+}
+
+impl Encodable for TxOutProof {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, encode::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for TxOutProof {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(TxOutProof(Decodable::consensus_decode(d)?))
+    }
+}
+
+impl fmt::Display for TxOutProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&encode::serialize_hex(self))
+    }
+}
+
+impl FromStr for TxOutProof {
+    type Err = encode::Error;
+
+    fn from_str(s: &str) -> Result<TxOutProof, encode::Error> {
+        encode::deserialize_hex(s)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::cmp::min;
 
     use hashes::Hash;
     use hashes::hex::{FromHex, ToHex};
-    use hash_types::{Txid, TxMerkleNode};
+    use hash_types::{MalFixTxid, Txid, TxMerkleNode};
     use secp256k1::rand::prelude::*;
 
     use consensus::encode::{deserialize, serialize};
     use util::hash::{bitcoin_merkle_root, BitcoinHash};
-    use util::merkleblock::{MerkleBlock, PartialMerkleTree};
+    use util::merkleblock::{MerkleBlock, PartialMerkleTree, TxOutProof};
     use {hex, Block};
 
     #[test]
@@ -580,6 +806,12 @@ mod tests {
                 // check that it contains the matched transactions (in the same order!)
                 assert_eq!(match_txid1, match_txid2);
 
+                // and that trees built from txids are strictly valid
+                let mut match_txid4 = vec![];
+                let mut indexes4 = vec![];
+                assert_eq!(pmt2.extract_matches_strict(&mut match_txid4, &mut indexes4), Ok(merkle_root_2));
+                assert_eq!((match_txid4, indexes4), (match_txid2, indexes.clone()));
+
                 // check that random bit flips break the authentication
                 for _ in 0..4 {
                     let mut pmt3: PartialMerkleTree = deserialize(&serialized).unwrap();
@@ -610,6 +842,41 @@ mod tests {
         // Should fail due to duplicate txs found
         let result = tree.extract_matches(&mut vec![], &mut vec![]);
         assert!(result.is_err());
+        assert!(tree.extract_matches_strict(&mut vec![], &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn pmt_strict() {
+        let txids: Vec<Txid> = (1..8)
+            .map(|i| Txid::from_hex(&format!("{:064x}", i)).unwrap())
+            .collect();
+        let matches = vec![false, true, false, false, false, false, true];
+        let tree = PartialMerkleTree::from_txids(&txids, &matches);
+        let root = tree.extract_matches(&mut vec![], &mut vec![]).unwrap();
+        assert_eq!(tree.extract_matches_strict(&mut vec![], &mut vec![]), Ok(root));
+
+        // a non-zero padding bit is consumed by neither
+        let mut padded: PartialMerkleTree = deserialize(&serialize(&tree)).unwrap();
+        let last = padded.bits.len() - 1;
+        assert!(!padded.bits[last]);
+        padded.bits[last] = true;
+        assert_eq!(padded.extract_matches(&mut vec![], &mut vec![]), Ok(root));
+        assert!(padded.extract_matches_strict(&mut vec![], &mut vec![]).is_err());
+
+        // a node flagged as a parent of a match, without any match below
+        let leaves: Vec<TxMerkleNode> = txids[..2].iter().map(|txid| TxMerkleNode::from_inner(txid.into_inner())).collect();
+        let flagged = PartialMerkleTree { num_transactions: 2, bits: vec![true, false, false], hashes: leaves };
+        let root = PartialMerkleTree::from_txids(&txids[..2], &[false, false]).extract_matches(&mut vec![], &mut vec![]).unwrap();
+        assert_eq!(flagged.extract_matches(&mut vec![], &mut vec![]), Ok(root));
+        assert!(flagged.extract_matches_strict(&mut vec![], &mut vec![]).is_err());
+
+        // the same txid matched at several positions
+        let duplicated = vec![txids[0], txids[1], txids[0], txids[2]];
+        let tree = PartialMerkleTree::from_txids(&duplicated, &[true, false, true, false]);
+        let mut found = vec![];
+        assert!(tree.extract_matches(&mut found, &mut vec![]).is_ok());
+        assert_eq!(found, vec![txids[0], txids[0]]);
+        assert!(tree.extract_matches_strict(&mut found, &mut vec![]).is_err());
     }
 
     #[test]
@@ -635,6 +902,39 @@ mod tests {
         assert_eq!(mb_hex, serialize(&mb).to_hex().as_str());
     }
 
+    #[test]
+    fn txoutproof_encoding() {
+        // `gettxoutproof '["220ebc64e21abece964927322cba69180ed853bb187fbc6923bac7d010b9d87a"]'`
+        let proof_hex =
+            "0100000090f0a9f110702f808219ebea1173056042a714bad51b916cb6800000000000005275289558f51c\
+            9966699404ae2294730c3c9f9bda53523ce50e9b95e558da2f0eab2fe48aeabac617a676aeea740f07e868e\
+            3441f2641c1bc040acc07966a45db261b4d00000900000005fac7708a6e81b2a986dea60db2663840ed1411\
+            30848162eb1bd1dee54f309a1b2ee1e12587e497ada70d9bd10d31e83f0a924825b96cb8d04e8936d793fb6\
+            0db7ad8b910d0c7ba2369bc7f18bb53d80e1869ba2c32274996cebe1ae264bc0e2289189ff0316cdc10511d\
+            a71da757e553cada9f3b5b1434f3923673adb57d83caac392c38af156d6fc30b55fad4112df2b95531e6811\
+            4e9ad10011e72f7b7cfdb025700";
+        let txid = Txid::from_hex("220ebc64e21abece964927322cba69180ed853bb187fbc6923bac7d010b9d87a").unwrap();
+
+        let proof: TxOutProof = proof_hex.parse().unwrap();
+        assert_eq!(proof.verify().unwrap(), vec![txid]);
+        assert_eq!(proof.to_string(), proof_hex);
+        assert_eq!(proof.merkle_block(), &deserialize::<MerkleBlock>(&hex::decode(proof_hex).unwrap()).unwrap());
+        assert!(proof_hex[1..].parse::<TxOutProof>().is_err());
+
+        // the same proof is built from the block
+        let block = get_block_13b8a();
+        let txids = vec![txid].into_iter().collect();
+        let built = TxOutProof::from_block(&block, &txids).unwrap();
+        assert_eq!(built.merkle_block().header.bitcoin_hash(), proof.merkle_block().header.bitcoin_hash());
+        assert_eq!(built.merkle_block().txn, proof.merkle_block().txn);
+        let missing = Txid::from_hex("c0ffee00003bafa802c8aa084379aa98d9fcd632ddc2ed9782b586ec87451f20").unwrap();
+        assert_eq!(TxOutProof::from_block(&block, &vec![txid, missing].into_iter().collect()), None);
+
+        let mut tampered = proof.into_merkle_block();
+        tampered.header.merkle_root = Default::default();
+        assert!(TxOutProof::from(tampered).verify().is_err());
+    }
+
     /// Create a CMerkleBlock using a list of txids which will be found in the
     /// given block.
     #[test]
@@ -704,6 +1004,43 @@ mod tests {
         assert_eq!(index.len(), 0);
     }
 
+    #[test]
+    fn merkleblock_construct_from_predicate() {
+        let block = get_block_13b8a();
+        let txid1 = block.txdata[8].txid();
+        let txid2 = block.txdata[1].txid();
+
+        let merkle_block = MerkleBlock::from_block_with_predicate(&block, |txid| *txid == txid1 || *txid == txid2);
+        assert_eq!(merkle_block, MerkleBlock::from_block(&block, &vec![txid1, txid2].into_iter().collect()));
+        assert_eq!(merkle_block.matched_txids().unwrap(), vec![(1, txid2), (8, txid1)]);
+
+        let merkle_block = MerkleBlock::from_block_with_predicate(&block, |_| false);
+        assert!(merkle_block.matched_txids().unwrap().is_empty());
+    }
+
+    #[test]
+    fn merkleblock_construct_from_malfix_predicate() {
+        let mut block = get_block_13b8a();
+        block.header.im_merkle_root = block.compute_immutable_merkle_root();
+        let malfix_txid = block.txdata[3].malfix_txid();
+
+        let merkle_block = MerkleBlock::from_block_with_malfix_predicate(&block, |txid| *txid == malfix_txid);
+        assert_eq!(merkle_block.matched_malfix_txids().unwrap(), vec![(3, malfix_txid)]);
+        let mut matches: Vec<MalFixTxid> = vec![];
+        let mut index: Vec<u32> = vec![];
+        assert_eq!(
+            merkle_block.txn.extract_malfix_matches(&mut matches, &mut index).unwrap(),
+            block.header.im_merkle_root
+        );
+        assert_eq!(matches, vec![malfix_txid]);
+
+        // the tree commits to the immutable merkle root, not the merkle root
+        assert!(merkle_block.matched_txids().is_err());
+        let txid = block.txdata[3].txid();
+        let merkle_block = MerkleBlock::from_block_with_predicate(&block, |other| *other == txid);
+        assert!(merkle_block.matched_malfix_txids().is_err());
+    }
+
     impl PartialMerkleTree {
         /// Flip one bit in one of the hashes - this should break the authentication
         fn damage(&mut self, rng: &mut ThreadRng) {
@@ -0,0 +1,265 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Signed messages
+//!
+//! Implementation of the message signing format of the `signmessage` and
+//! `verifymessage` RPCs of Tapyrus Core: the message is prefixed by a magic
+//! string and hashed, and signed with a recoverable ECDSA signature, which
+//! is exchanged as base64. Verification recovers the public key from the
+//! signature and compares its hash with the P2PKH address of the signer.
+//!
+//! This module requires the `secp-recovery` feature.
+//!
+
+use std::{error, fmt};
+use std::str::FromStr;
+
+use base64;
+use hashes::sha256d;
+use secp256k1::{self, Message, Secp256k1};
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
+
+use util::address::{Address, Payload};
+use util::key::{PrivateKey, PublicKey};
+use util::misc::signed_msg_hash;
+
+/// An error in parsing or verifying a signed message
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The signature is not valid base64
+    Base64(base64::DecodeError),
+    /// The signature is not 65 bytes long
+    InvalidLength(usize),
+    /// The header byte of the signature is out of range
+    InvalidHeader(u8),
+    /// The signature is invalid
+    Secp256k1(secp256k1::Error),
+    /// Only P2PKH addresses identify the key of a signer
+    UnsupportedAddress,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Base64(ref e) => write!(f, "malformed base64 encoding: {}", e),
+            Error::InvalidLength(len) => write!(f, "invalid signature length {}", len),
+            Error::InvalidHeader(header) => write!(f, "invalid signature header {}", header),
+            Error::Secp256k1(ref e) => fmt::Display::fmt(e, f),
+            Error::UnsupportedAddress => f.write_str("address does not refer to a key"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Base64(ref e) => Some(e),
+            Error::Secp256k1(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Base64(_) => "malformed base64 encoding",
+            Error::InvalidLength(_) => "invalid signature length",
+            Error::InvalidHeader(_) => "invalid signature header",
+            Error::Secp256k1(ref e) => error::Error::description(e),
+            Error::UnsupportedAddress => "address does not refer to a key",
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Error {
+        Error::Base64(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Secp256k1(e)
+    }
+}
+
+/// A signature over a message, along with whether the key of the signer
+/// is serialized compressed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MessageSignature {
+    /// The recoverable signature
+    pub signature: RecoverableSignature,
+    /// Whether the public key of the signer is compressed
+    pub compressed: bool,
+}
+
+impl MessageSignature {
+    /// Create a message signature
+    pub fn new(signature: RecoverableSignature, compressed: bool) -> MessageSignature {
+        MessageSignature { signature: signature, compressed: compressed }
+    }
+
+    /// Serialize to 65 bytes: a header byte encoding the recovery id and
+    /// the compression flag, followed by the compact signature
+    pub fn serialize(&self) -> [u8; 65] {
+        let (recid, raw) = self.signature.serialize_compact();
+        let mut ret = [0; 65];
+        ret[0] = 27 + recid.to_i32() as u8 + if self.compressed { 4 } else { 0 };
+        ret[1..].copy_from_slice(&raw[..]);
+        ret
+    }
+
+    /// Parse a 65-byte serialized signature
+    pub fn from_slice(bytes: &[u8]) -> Result<MessageSignature, Error> {
+        if bytes.len() != 65 {
+            return Err(Error::InvalidLength(bytes.len()));
+        }
+        let header = bytes[0];
+        if header < 27 || header > 34 {
+            return Err(Error::InvalidHeader(header));
+        }
+        let recid = RecoveryId::from_i32(((header - 27) & 3) as i32)?;
+        Ok(MessageSignature {
+            signature: RecoverableSignature::from_compact(&bytes[1..], recid)?,
+            compressed: header >= 31,
+        })
+    }
+
+    /// Recover the public key of the signer of the message hashed to
+    /// `msg_hash`
+    pub fn recover_pubkey<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        msg_hash: sha256d::Hash,
+    ) -> Result<PublicKey, Error> {
+        let msg = Message::from_slice(&msg_hash[..]).expect("hashes are 32 bytes");
+        Ok(PublicKey {
+            compressed: self.compressed,
+            key: secp.recover(&msg, &self.signature)?,
+        })
+    }
+
+    /// Check whether the message hashed to `msg_hash` was signed by the
+    /// key of the P2PKH `address`
+    pub fn is_signed_by_address<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        address: &Address,
+        msg_hash: sha256d::Hash,
+    ) -> Result<bool, Error> {
+        match address.payload {
+            Payload::PubkeyHash(_) => {
+                let pubkey = self.recover_pubkey(secp, msg_hash)?;
+                Ok(Address::p2pkh(&pubkey, address.network).payload == address.payload)
+            }
+            _ => Err(Error::UnsupportedAddress),
+        }
+    }
+}
+
+/// Base64, as output by the `signmessage` RPC
+impl fmt::Display for MessageSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&base64::encode(&self.serialize()[..]))
+    }
+}
+
+impl FromStr for MessageSignature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MessageSignature, Error> {
+        MessageSignature::from_slice(&base64::decode(s)?)
+    }
+}
+
+/// Sign `msg` with `key`, as the `signmessage` RPC does
+pub fn sign_message<C: secp256k1::Signing>(
+    secp: &Secp256k1<C>,
+    key: &PrivateKey,
+    msg: &str,
+) -> MessageSignature {
+    let hash = signed_msg_hash(msg);
+    let msg = Message::from_slice(&hash[..]).expect("hashes are 32 bytes");
+    MessageSignature::new(secp.sign_recoverable(&msg, &key.key), key.compressed)
+}
+
+/// Check the base64 `signature` of `msg` against the P2PKH `address`, as
+/// the `verifymessage` RPC does. Signatures from which no key can be
+/// recovered are not valid, while malformed ones are errors.
+pub fn verify_message<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    address: &Address,
+    signature: &str,
+    msg: &str,
+) -> Result<bool, Error> {
+    let signature = MessageSignature::from_str(signature)?;
+    match signature.is_signed_by_address(secp, address, signed_msg_hash(msg)) {
+        Err(Error::Secp256k1(_)) => Ok(false),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::script::Script;
+    use network::constants::Network;
+
+    #[test]
+    fn test_sign_verify_message() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let address = Address::p2pkh(&key.public_key(&secp), key.network);
+
+        let signature = sign_message(&secp, &key, "Hello, Tapyrus");
+        assert!(signature.compressed);
+        let s = signature.to_string();
+        assert_eq!(&s, "H9wGZc1wZZ41PLS6XtDUgS87P+5bFefp25HTlHOuBD1+DUpqaP1XkKjpHjhbNUXqJUruF5XLuFZnnBLLuEmwLjU=");
+        assert_eq!(MessageSignature::from_str(&s), Ok(signature));
+        assert_eq!(signature.recover_pubkey(&secp, signed_msg_hash("Hello, Tapyrus")), Ok(key.public_key(&secp)));
+
+        assert_eq!(verify_message(&secp, &address, &s, "Hello, Tapyrus"), Ok(true));
+        assert_eq!(verify_message(&secp, &address, &s, "Hello, Bitcoin"), Ok(false));
+        let other = Address::p2pkh(&PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap().public_key(&secp), Network::Dev);
+        assert_eq!(verify_message(&secp, &other, &s, "Hello, Tapyrus"), Ok(false));
+    }
+
+    #[test]
+    fn test_uncompressed_key() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("5JYkZjmN7PVMjJUfJWfRFwtuXTGB439XV6faajeHPAM9Z2PT2R3").unwrap();
+        let mut pubkey = key.public_key(&secp);
+        let address = Address::p2pkh(&pubkey, key.network);
+
+        let s = sign_message(&secp, &key, "Hello, Tapyrus").to_string();
+        assert_eq!(&s, "HJg8Rork0IecFNjpKJc+uItJZrJUy7ELSN0i56hmLipUczyNTpwoRoAwH6PfaP45UGUKfn3L/QJWfvUevtecv6Q=");
+        assert_eq!(verify_message(&secp, &address, &s, "Hello, Tapyrus"), Ok(true));
+
+        // the same key, but serialized compressed, has another address
+        pubkey.compressed = true;
+        let address = Address::p2pkh(&pubkey, key.network);
+        assert_eq!(verify_message(&secp, &address, &s, "Hello, Tapyrus"), Ok(false));
+    }
+
+    #[test]
+    fn test_invalid_signatures() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let address = Address::p2pkh(&key.public_key(&secp), key.network);
+        let s = "IN5ts2RMzgAQfhiSRktOZBMiDwo2OsZgg83xKcFaS+whHg/MARfuICDx2x8C3uX+3m3m3hg1SC39uR5gr9OdwVg=";
+        assert_eq!(verify_message(&secp, &address, s, "test"), Ok(true));
+
+        assert!(verify_message(&secp, &address, "not base64!", "test").is_err());
+        assert_eq!(verify_message(&secp, &address, "AAAA", "test"), Err(Error::InvalidLength(3)));
+        let mut bytes = base64::decode(s).unwrap();
+        bytes[0] = 35;
+        assert_eq!(verify_message(&secp, &address, &base64::encode(&bytes), "test"), Err(Error::InvalidHeader(35)));
+
+        let p2sh = Address::p2sh(&Script::from(vec![0x51]), key.network);
+        assert_eq!(verify_message(&secp, &p2sh, s, "test"), Err(Error::UnsupportedAddress));
+    }
+}
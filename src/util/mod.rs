@@ -21,29 +21,40 @@
 //!
 //! Functions needed by all parts of the Bitcoin library
 
-pub mod address;
+#[cfg(feature = "std")] pub mod address;
 pub mod amount;
 pub mod base58;
-pub mod bip143;
-pub mod bip158;
-pub mod bip32;
-pub mod contracthash;
+#[cfg(feature = "std")] pub mod bip143;
+#[cfg(feature = "std")] pub mod bip158;
+#[cfg(feature = "std")] pub mod bip32;
+#[cfg(feature = "std")] pub mod bloom;
+#[cfg(feature = "std")] pub mod contracthash;
+pub mod ecdsa;
+#[cfg(feature = "std")] pub mod federation;
+pub mod fee;
 pub mod hash;
+pub mod merkle;
 pub mod key;
-pub mod merkleblock;
-pub mod misc;
-pub mod psbt;
-pub mod uint;
+#[cfg(feature = "std")] pub mod merkleblock;
+#[cfg(feature = "secp-recovery")] pub mod message_signing;
+#[cfg(feature = "std")] pub mod misc;
+pub mod policy;
+#[cfg(feature = "std")] pub mod psbt;
+#[cfg(feature = "std")] pub mod uint;
 pub mod signature;
+pub mod sighash;
+pub mod token;
+#[cfg(feature = "std")] pub mod uri;
 pub mod prime;
 pub mod rfc6979;
+pub mod schnorr;
 
 pub(crate) mod endian;
 
-use std::{error, fmt};
+#[cfg(feature = "std")] use std::{error, fmt};
 
-use consensus::encode;
-use network;
+#[cfg(feature = "std")] use consensus::encode;
+#[cfg(feature = "std")] use network;
 
 /// A trait which allows numbers to act as fixed-size bit arrays
 pub trait BitArray {
@@ -66,8 +77,12 @@ pub trait BitArray {
     fn one() -> Self;
 }
 
+#[cfg(feature = "std")]
 /// A general error code, other errors should implement conversions to/from this
 /// if appropriate.
+///
+/// More variants may be added in later versions, so matches should include
+/// a wildcard arm.
 #[derive(Debug)]
 pub enum Error {
     /// Encoding error
@@ -76,24 +91,30 @@ pub enum Error {
     Network(network::Error),
     /// Signature error
     Signature(signature::Error),
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Encode(ref e) => fmt::Display::fmt(e, f),
             Error::Network(ref e) => fmt::Display::fmt(e, f),
             Error::Signature(ref e) => fmt::Display::fmt(e, f),
+            Error::__Nonexhaustive => f.write_str("unknown error"),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
         match *self {
             Error::Encode(ref e) => Some(e),
             Error::Network(ref e) => Some(e),
             Error::Signature(ref e) => Some(e),
+            Error::__Nonexhaustive => None,
         }
     }
 
@@ -102,10 +123,12 @@ impl error::Error for Error {
             Error::Encode(ref e) => e.description(),
             Error::Network(ref e) => e.description(),
             Error::Signature(ref e) => e.description(),
+            Error::__Nonexhaustive => "unknown error",
         }
     }
 }
 
+#[cfg(feature = "std")]
 #[doc(hidden)]
 impl From<encode::Error> for Error {
     fn from(e: encode::Error) -> Error {
@@ -113,6 +136,7 @@ impl From<encode::Error> for Error {
     }
 }
 
+#[cfg(feature = "std")]
 #[doc(hidden)]
 impl From<network::Error> for Error {
     fn from(e: network::Error) -> Error {
@@ -0,0 +1,261 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Standardness policy
+//!
+//! Tapyrus Core only relays and mines transactions following its default
+//! policy, which is stricter than consensus. This module mirrors its
+//! checks, so that wallets can tell whether a transaction will be relayed
+//! before broadcasting it.
+//!
+
+use core::fmt;
+#[cfg(feature = "std")] use std::error;
+
+use blockdata::script::{Script, ScriptType, MAX_OP_RETURN_RELAY, MAX_STANDARD_MULTISIG_KEYS};
+use blockdata::transaction::{Transaction, TxOut};
+use consensus::encode::VarInt;
+use util::fee::{FeeRate, Weight};
+
+/// The maximum weight of a standard transaction
+pub const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+/// The maximum size of a standard script_sig, in bytes, enough for a
+/// 15-of-15 P2SH multisig with compressed keys
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// The highest standard transaction version
+pub const MAX_STANDARD_VERSION: u32 = 2;
+
+/// The reasons for which Tapyrus Core rejects a transaction as non-standard
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The version of the transaction is 0 or above `MAX_STANDARD_VERSION`
+    Version,
+    /// The transaction weighs more than `MAX_STANDARD_TX_WEIGHT`
+    TxSize,
+    /// The script_sig of the input at this index is larger than
+    /// `MAX_STANDARD_SCRIPTSIG_SIZE`
+    ScriptSigSize(usize),
+    /// The script_sig of the input at this index does not only push data
+    ScriptSigNotPushOnly(usize),
+    /// The script_pubkey of the output at this index is not standard
+    ScriptPubkey(usize),
+    /// The uncolored output at this index is dust
+    Dust(usize),
+    /// The transaction has more than one OP_RETURN output
+    MultiOpReturn,
+}
+
+impl Error {
+    /// The reason for which Tapyrus Core rejects the transaction
+    pub fn reject_reason(&self) -> &'static str {
+        match *self {
+            Error::Version => "version",
+            Error::TxSize => "tx-size",
+            Error::ScriptSigSize(_) => "scriptsig-size",
+            Error::ScriptSigNotPushOnly(_) => "scriptsig-not-pushonly",
+            Error::ScriptPubkey(_) => "scriptpubkey",
+            Error::Dust(_) => "dust",
+            Error::MultiOpReturn => "multi-op-return",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ScriptSigSize(index) | Error::ScriptSigNotPushOnly(index) => {
+                write!(f, "{} (input {})", self.reject_reason(), index)
+            }
+            Error::ScriptPubkey(index) | Error::Dust(index) => {
+                write!(f, "{} (output {})", self.reject_reason(), index)
+            }
+            _ => f.write_str(self.reject_reason()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> { None }
+
+    fn description(&self) -> &'static str {
+        self.reject_reason()
+    }
+}
+
+/// Whether outputs locked by `script_pubkey` are standard: one of the
+/// templates of `ScriptType`, with multisigs of at most
+/// `MAX_STANDARD_MULTISIG_KEYS` keys and OP_RETURN scripts of at most
+/// `MAX_OP_RETURN_RELAY` bytes
+pub fn is_standard_script_pubkey(script_pubkey: &Script) -> bool {
+    match script_pubkey.classify() {
+        ScriptType::NonStandard => false,
+        ScriptType::Multisig { ref keys, .. } => keys.len() <= MAX_STANDARD_MULTISIG_KEYS,
+        ScriptType::OpReturn => script_pubkey.len() <= MAX_OP_RETURN_RELAY,
+        _ => true,
+    }
+}
+
+/// The value below which an output locked by `script_pubkey` is dust,
+/// costing more than it is worth to create and spend at `dust_relay_fee`.
+/// Computed as Tapyrus Core's `GetDustThreshold`; provably unspendable
+/// outputs are never dust.
+pub fn dust_threshold(script_pubkey: &Script, dust_relay_fee: FeeRate) -> u64 {
+    if script_pubkey.is_provably_unspendable() {
+        return 0;
+    }
+    let output_size = 8 + VarInt(script_pubkey.len() as u64).len() + script_pubkey.len();
+    // an input spending the output with a signature and a compressed
+    // public key
+    let input_size = 32 + 4 + 1 + 107 + 4;
+    dust_relay_fee.as_sat_per_kvb().saturating_mul((output_size + input_size) as u64) / 1000
+}
+
+/// Check that `tx` is standard, with outputs checked against the dust
+/// relay fee `dust_relay_fee`, as Tapyrus Core's `IsStandardTx` does.
+/// Only uncolored outputs may be dust, since colored ones carry tokens.
+pub fn check_standard_tx(tx: &Transaction, dust_relay_fee: FeeRate) -> Result<(), Error> {
+    if tx.version == 0 || tx.version > MAX_STANDARD_VERSION {
+        return Err(Error::Version);
+    }
+    if tx.weight() > Weight::from_wu(MAX_STANDARD_TX_WEIGHT) {
+        return Err(Error::TxSize);
+    }
+    for (index, input) in tx.input.iter().enumerate() {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(Error::ScriptSigSize(index));
+        }
+        if !input.script_sig.is_push_only() {
+            return Err(Error::ScriptSigNotPushOnly(index));
+        }
+    }
+    let mut op_returns = 0;
+    for (index, output) in tx.output.iter().enumerate() {
+        if !is_standard_script_pubkey(&output.script_pubkey) {
+            return Err(Error::ScriptPubkey(index));
+        }
+        if output.script_pubkey.is_op_return() {
+            op_returns += 1;
+        } else if !output.script_pubkey.is_colored() && is_dust(output, dust_relay_fee) {
+            return Err(Error::Dust(index));
+        }
+    }
+    if op_returns > 1 {
+        return Err(Error::MultiOpReturn);
+    }
+    Ok(())
+}
+
+/// Whether `tx` is standard at the default dust relay fee
+pub fn is_standard_tx(tx: &Transaction) -> bool {
+    check_standard_tx(tx, FeeRate::DEFAULT_DUST_RELAY).is_ok()
+}
+
+fn is_dust(output: &TxOut, dust_relay_fee: FeeRate) -> bool {
+    output.value < dust_threshold(&output.script_pubkey, dust_relay_fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::locktime::LockTime;
+    use blockdata::opcodes::all;
+    use blockdata::script::{Builder, ColorIdentifier, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use hash_types::{MalFixTxid, PubkeyHash};
+    use hashes::Hash;
+    use util::fee::FeeRate;
+    use test_helpers::test_pubkey;
+    use super::*;
+
+    fn p2pkh() -> Script {
+        Script::new_p2pkh(&PubkeyHash::hash(b"key"))
+    }
+
+    fn multisig(keys: usize) -> Script {
+        let key = test_pubkey();
+        (0..keys)
+            .fold(Builder::new().push_int(1), |builder, _| builder.push_key(&key))
+            .push_int(keys as i64)
+            .push_opcode(all::OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn tx(output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(MalFixTxid::hash(b"funding"), 0),
+                script_sig: Builder::new().push_slice(&[0x30; 72]).push_slice(&[0x02; 33]).into_script(),
+                ..Default::default()
+            }],
+            output: output,
+        }
+    }
+
+    #[test]
+    fn test_standard_script_pubkey() {
+        let color_id = ColorIdentifier::reissuable(p2pkh());
+        assert!(is_standard_script_pubkey(&p2pkh()));
+        assert!(is_standard_script_pubkey(&p2pkh().to_p2sh()));
+        assert!(is_standard_script_pubkey(&p2pkh().add_color(color_id.clone()).unwrap()));
+        assert!(is_standard_script_pubkey(&p2pkh().to_p2sh().add_color(color_id).unwrap()));
+        assert!(is_standard_script_pubkey(&multisig(3)));
+        assert!(!is_standard_script_pubkey(&multisig(4)));
+        assert!(is_standard_script_pubkey(&Script::new_op_return(&[0; 80]).unwrap()));
+        assert!(!is_standard_script_pubkey(&Builder::new().push_opcode(all::OP_RETURN).push_slice(&[0; 81]).into_script()));
+        assert!(!is_standard_script_pubkey(&Script::new()));
+        assert!(!is_standard_script_pubkey(&Builder::new().push_int(1).into_script()));
+    }
+
+    #[test]
+    fn test_dust_threshold() {
+        // 34 bytes of P2PKH output and 148 bytes of input at 3000 sat/kvB
+        assert_eq!(dust_threshold(&p2pkh(), FeeRate::DEFAULT_DUST_RELAY), 546);
+        assert_eq!(dust_threshold(&p2pkh().to_p2sh(), FeeRate::DEFAULT_DUST_RELAY), 540);
+        assert_eq!(dust_threshold(&p2pkh(), FeeRate::ZERO), 0);
+        assert_eq!(dust_threshold(&Script::new_op_return(b"data").unwrap(), FeeRate::DEFAULT_DUST_RELAY), 0);
+        let output = TxOut { value: 545, script_pubkey: p2pkh() };
+        assert_eq!(output.dust_threshold(FeeRate::DEFAULT_DUST_RELAY), 546);
+    }
+
+    #[test]
+    fn test_standard_tx() {
+        let payment = TxOut { value: 1000, script_pubkey: p2pkh() };
+        let mut standard = tx(vec![payment.clone()]);
+        assert_eq!(check_standard_tx(&standard, FeeRate::DEFAULT_DUST_RELAY), Ok(()));
+        assert!(is_standard_tx(&standard));
+
+        standard.version = 3;
+        assert_eq!(check_standard_tx(&standard, FeeRate::DEFAULT_DUST_RELAY), Err(Error::Version));
+        standard.version = 0;
+        assert!(!is_standard_tx(&standard));
+
+        let mut tx_ = tx(vec![payment.clone()]);
+        tx_.input[0].script_sig = Builder::new().push_slice(&[0; 520]).push_slice(&[0; 520]).push_slice(&[0; 520]).push_slice(&[0; 90]).into_script();
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Err(Error::ScriptSigSize(0)));
+        tx_.input[0].script_sig = Builder::new().push_int(1).push_opcode(all::OP_DUP).into_script();
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Err(Error::ScriptSigNotPushOnly(0)));
+
+        let tx_ = tx(vec![payment.clone(), TxOut { value: 1000, script_pubkey: multisig(4) }]);
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Err(Error::ScriptPubkey(1)));
+
+        // dust depends on the relay fee, and tokens are never dust
+        let tx_ = tx(vec![TxOut { value: 545, script_pubkey: p2pkh() }]);
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Err(Error::Dust(0)));
+        assert_eq!(check_standard_tx(&tx_, FeeRate::ZERO), Ok(()));
+        let colored = p2pkh().add_color(ColorIdentifier::reissuable(p2pkh())).unwrap();
+        let tx_ = tx(vec![payment.clone(), TxOut { value: 1, script_pubkey: colored }]);
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Ok(()));
+
+        let op_return = TxOut { value: 0, script_pubkey: Script::new_op_return(b"metadata").unwrap() };
+        let tx_ = tx(vec![payment.clone(), op_return.clone()]);
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Ok(()));
+        let tx_ = tx(vec![payment.clone(), op_return.clone(), op_return]);
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Err(Error::MultiOpReturn));
+
+        let tx_ = tx(vec![payment; 3_000]);
+        assert_eq!(check_standard_tx(&tx_, FeeRate::DEFAULT_DUST_RELAY), Err(Error::TxSize));
+    }
+}
@@ -6,8 +6,7 @@
 //!
 //!
 
-use rug::Integer;
-use rug::integer::Order;
+use num_bigint::BigUint;
 
 /// Prime number for secp256k1 field element.
 pub const P: [u8; 32] = [
@@ -17,65 +16,50 @@ pub const P: [u8; 32] = [
     0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xfc, 0x2f
 ];
 
+/// Order of the secp256k1 group, which is prime as well.
+pub const N: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b,
+    0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41
+];
+
 /// Calculate jacobi symbol
 pub fn jacobi(a: &[u8]) -> i8 {
-    let a = Integer::from_digits(a, Order::MsfBe);
-    let p = Integer::from_digits(&P[..], Order::MsfBe);
+    let a = BigUint::from_bytes_be(a);
+    let p = BigUint::from_bytes_be(&P[..]);
     jacobi_inner(&a, &p)
 }
 
-fn jacobi_inner(a: &Integer, n: &Integer) -> i8 {
-    assert!(*n >= Integer::from(3));
-    let a = Integer::from(a % n);
-    if a == Integer::from(0) {
-        return 0;
-    }
-    if a == Integer::from(1) {
-        return 1;
-    }
-
-    let mut a1: Integer = a.clone();
-    let mut e = 0;
-    while a1.is_even() {
-        a1 = a1.div_exact_u(2);
-        e += 1;
-    }
-    let mut s: i8 = if e & 1 == 0
-        || n.mod_u(8) == 1
-        || n.mod_u(8) == 7
-    {
+/// The Jacobi symbol of `a` modulo the odd prime `p`, i.e. its Legendre
+/// symbol, by Euler's criterion: a^((p-1)/2) is 1 modulo `p` for quadratic
+/// residues and -1 for non-residues
+fn jacobi_inner(a: &BigUint, p: &BigUint) -> i8 {
+    let one = BigUint::from(1u32);
+    let exponent = (p - &one) >> 1;
+    let r = a.modpow(&exponent, p);
+    if r == one {
         1
-    } else if n.mod_u(8) == 3
-        || n.mod_u(8) == 5
-    {
-        -1
-    } else {
+    } else if r == BigUint::from(0u32) {
         0
-    };
-    if n.mod_u(4) == 3 && a1.mod_u(4) == 3
-    {
-        s = -s
-    }
-
-    if a1 == Integer::from(1) {
-        s
     } else {
-        s * jacobi_inner(&(n % a1.clone()), &a1.clone())
+        -1
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use num_bigint::BigUint;
     use util::prime::{jacobi, jacobi_inner};
-    use rug::Integer;
 
     #[test]
     fn test_jacobi() {
-        assert_eq!(1, jacobi_inner(&Integer::from(1), &Integer::from(3)));
-        assert_eq!(-1, jacobi_inner(&Integer::from(2), &Integer::from(3)));
-        assert_eq!(0, jacobi_inner(&Integer::from(3), &Integer::from(3)));
+        let three = BigUint::from(3u32);
+        assert_eq!(1, jacobi_inner(&BigUint::from(1u32), &three));
+        assert_eq!(-1, jacobi_inner(&BigUint::from(2u32), &three));
+        assert_eq!(0, jacobi_inner(&BigUint::from(3u32), &three));
 
         let a = hex::decode("388f7b0f632de8140fe337e62a37f3566500a99934c2231b6cb9fd7584b8e672").unwrap();
         assert_eq!(-1, jacobi(&a[..]));
     }
-}
\ No newline at end of file
+}
@@ -15,7 +15,11 @@
 use std::error;
 use std::fmt;
 
+#[cfg(feature = "base64")] use base64;
+
+use blockdata::script::{ColorIdentifier, Script};
 use blockdata::transaction::Transaction;
+#[cfg(feature = "base64")] use consensus::encode;
 use util::psbt::raw;
 
 /// Ways that a Partially Signed Transaction might fail.
@@ -48,6 +52,37 @@ pub enum Error {
     },
     /// Unable to parse as a standard SigHash type.
     NonStandardSigHashType(u32),
+    /// Tapyrus has no segwit, but the input of this index has segwit
+    /// fields set.
+    WitnessFields(usize),
+    /// The transaction spent by the input of this index is not the one its
+    /// outpoint refers to.
+    SpentTxMismatch(usize),
+    /// The color or amount of tokens of the input of this index do not match
+    /// the output it spends.
+    TokenMismatch(usize),
+    /// Combined PSBTs have different values for this key.
+    ConflictingValues(raw::Key),
+    /// The output spent by the input of this index is unknown.
+    MissingUtxo(usize),
+    /// The total of amounts overflows.
+    AmountOverflow,
+    /// The outputs send more TPC than the inputs spend.
+    NegativeFee,
+    /// The outputs send tokens of this color which are neither spent by the
+    /// inputs nor issued.
+    TokenImbalance(ColorIdentifier),
+    /// The PSBT has no input or output of this index.
+    IndexOutOfRange(usize),
+    /// The input of this index can not be finalized with its partial
+    /// signatures.
+    CannotFinalize(usize),
+    /// The color or amount of tokens of the output of this index do not
+    /// match its script pubkey and value.
+    OutputTokenMismatch(usize),
+    /// Tokens can not be sent to this script: only uncolored P2PKH and P2SH
+    /// scripts can be colored.
+    UnsupportedScript(Script),
 }
 
 impl fmt::Display for Error {
@@ -59,6 +94,9 @@ impl fmt::Display for Error {
             Error::DuplicateKey(ref rkey) => {
                 write!(f, "{}: {}", error::Error::description(self), rkey)
             }
+            Error::ConflictingValues(ref rkey) => {
+                write!(f, "{}: {}", error::Error::description(self), rkey)
+            }
             Error::UnexpectedUnsignedTx {
                 expected: ref e,
                 actual: ref a,
@@ -72,12 +110,33 @@ impl fmt::Display for Error {
             Error::NonStandardSigHashType(ref sht) => {
                 write!(f, "{}: {}", error::Error::description(self), sht)
             }
+            Error::TokenImbalance(ref color_id) => {
+                write!(f, "{}: {}", error::Error::description(self), color_id)
+            }
+            Error::WitnessFields(index)
+            | Error::SpentTxMismatch(index)
+            | Error::TokenMismatch(index)
+            | Error::MissingUtxo(index)
+            | Error::CannotFinalize(index) => {
+                write!(f, "{}: input {}", error::Error::description(self), index)
+            }
+            Error::OutputTokenMismatch(index) => {
+                write!(f, "{}: output {}", error::Error::description(self), index)
+            }
+            Error::UnsupportedScript(ref script) => {
+                write!(f, "{}: {}", error::Error::description(self), script)
+            }
+            Error::IndexOutOfRange(index) => {
+                write!(f, "{}: {}", error::Error::description(self), index)
+            }
             Error::InvalidMagic
             | Error::InvalidSeparator
             | Error::UnsignedTxHasScriptSigs
             | Error::UnsignedTxHasScriptWitnesses
             | Error::MustHaveUnsignedTx
-            | Error::NoMorePairs => f.write_str(error::Error::description(self)),
+            | Error::NoMorePairs
+            | Error::AmountOverflow
+            | Error::NegativeFee => f.write_str(error::Error::description(self)),
         }
     }
 }
@@ -97,6 +156,55 @@ impl error::Error for Error {
             Error::NoMorePairs => "no more key-value pairs for this psbt map",
             Error::UnexpectedUnsignedTx { .. } => "different unsigned transaction",
             Error::NonStandardSigHashType(..) => "non-standard sighash type",
+            Error::WitnessFields(..) => "segwit fields in a tapyrus input",
+            Error::SpentTxMismatch(..) => "spent transaction does not match the outpoint",
+            Error::TokenMismatch(..) => "tokens do not match the spent output",
+            Error::ConflictingValues(..) => "conflicting values in combined psbts",
+            Error::MissingUtxo(..) => "missing spent output",
+            Error::AmountOverflow => "amount overflow",
+            Error::NegativeFee => "outputs exceed inputs",
+            Error::TokenImbalance(..) => "tokens neither spent nor issued",
+            Error::IndexOutOfRange(..) => "index out of range",
+            Error::CannotFinalize(..) => "cannot finalize input",
+            Error::OutputTokenMismatch(..) => "tokens do not match the output",
+            Error::UnsupportedScript(..) => "script can not be colored",
+        }
+    }
+}
+
+/// An error in parsing a base64 encoded PSBT
+#[cfg(feature = "base64")]
+#[derive(Debug)]
+pub enum PsbtParseError {
+    /// The string is not valid base64
+    Base64(base64::DecodeError),
+    /// The decoded bytes are not a valid PSBT, or are followed by other data
+    Encoding(encode::Error),
+}
+
+#[cfg(feature = "base64")]
+impl fmt::Display for PsbtParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PsbtParseError::Base64(ref e) => write!(f, "{}: {}", error::Error::description(self), e),
+            PsbtParseError::Encoding(ref e) => write!(f, "{}: {}", error::Error::description(self), e),
+        }
+    }
+}
+
+#[cfg(feature = "base64")]
+impl error::Error for PsbtParseError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            PsbtParseError::Base64(ref e) => Some(e),
+            PsbtParseError::Encoding(ref e) => Some(e),
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            PsbtParseError::Base64(..) => "invalid base64 encoding",
+            PsbtParseError::Encoding(..) => "invalid psbt encoding",
         }
     }
 }
@@ -148,7 +148,7 @@ impl Decodable for Global {
                                     });
 
                                     if decoder.position() != vlen as u64 {
-                                        return Err(encode::Error::ParseFailed("data not consumed entirely when explicitly deserializing"));
+                                        return Err(encode::Error::Parse(encode::ParseError::DataNotConsumed));
                                     }
                                 } else {
                                     return Err(Error::DuplicateKey(pair.key).into());
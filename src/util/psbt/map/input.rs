@@ -14,16 +14,21 @@
 
 use std::collections::BTreeMap;
 
-use blockdata::script::Script;
-use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use blockdata::script::{ColorIdentifier, Script};
+use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxOut};
 use consensus::encode;
-use util::bip32::{DerivationPath, Fingerprint};
+use util::bip32::{KeyOriginInfo, KeySource};
 use util::key::PublicKey;
 use util::psbt;
 use util::psbt::map::Map;
 use util::psbt::raw;
 use util::psbt::Error;
 
+/// Subtype of the Tapyrus proprietary key of the color of the tokens spent
+const PSBT_IN_TAPYRUS_COLOR_ID: u8 = 0x00;
+/// Subtype of the Tapyrus proprietary key of the amount of tokens spent
+const PSBT_IN_TAPYRUS_TOKEN_AMOUNT: u8 = 0x01;
+
 /// A key-value map for an input of the corresponding index in the unsigned
 /// transaction.
 #[derive(Clone, Default, Debug, PartialEq)]
@@ -34,7 +39,8 @@ pub struct Input {
     pub non_witness_utxo: Option<Transaction>,
     /// The transaction output this input spends from. Should only be
     /// [std::option::Option::Some] for inputs which spend segwit outputs,
-    /// including P2SH embedded ones.
+    /// including P2SH embedded ones. Tapyrus has no segwit, so this must be
+    /// [std::option::Option::None] for Tapyrus transactions.
     pub witness_utxo: Option<TxOut>,
     /// A map from public keys to their corresponding signature as would be
     /// pushed to the stack from a scriptSig or witness.
@@ -44,21 +50,53 @@ pub struct Input {
     pub sighash_type: Option<SigHashType>,
     /// The redeem script for this input.
     pub redeem_script: Option<Script>,
-    /// The witness script for this input.
+    /// The witness script for this input. Unused by Tapyrus.
     pub witness_script: Option<Script>,
     /// A map from public keys needed to sign this input to their corresponding
     /// master key fingerprints and derivation paths.
-    pub hd_keypaths: BTreeMap<PublicKey, (Fingerprint, DerivationPath)>,
+    pub hd_keypaths: BTreeMap<PublicKey, KeySource>,
     /// The finalized, fully-constructed scriptSig with signatures and any other
     /// scripts necessary for this input to pass validation.
     pub final_script_sig: Option<Script>,
     /// The finalized, fully-constructed scriptWitness with signatures and any
-    /// other scripts necessary for this input to pass validation.
+    /// other scripts necessary for this input to pass validation. Unused by
+    /// Tapyrus.
     pub final_script_witness: Option<Vec<Vec<u8>>>,
+    /// The color of the tokens spent by this input, a Tapyrus proprietary
+    /// field.
+    pub color_id: Option<ColorIdentifier>,
+    /// The amount of tokens spent by this input, a Tapyrus proprietary
+    /// field.
+    pub token_amount: Option<u64>,
     /// Unknown key-value pairs for this input.
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 }
 
+impl Input {
+    /// The output spent by this input, which spends `previous_output`, if
+    /// the transaction containing it is known. Tapyrus outpoints refer to
+    /// transactions by their malleability-fixed txid.
+    pub fn spent_output(&self, previous_output: &OutPoint) -> Option<&TxOut> {
+        match self.non_witness_utxo {
+            Some(ref tx) if tx.malfix_txid() == previous_output.txid => {
+                tx.output.get(previous_output.vout as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether any of the fields specific to segwit is set. Tapyrus has no
+    /// segwit, so these are not expected in Tapyrus PSBTs.
+    pub fn has_witness_fields(&self) -> bool {
+        self.witness_utxo.is_some() || self.witness_script.is_some() || self.final_script_witness.is_some()
+    }
+
+    /// The origin of `pubkey`, if it is one of the keys needed to sign this input
+    pub fn key_origin(&self, pubkey: &PublicKey) -> Option<KeyOriginInfo> {
+        self.hd_keypaths.get(pubkey).map(|source| KeyOriginInfo::from(source.clone()))
+    }
+}
+
 impl Map for Input {
     fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), encode::Error> {
         let raw::Pair {
@@ -109,8 +147,26 @@ impl Map for Input {
             }
             6u8 => {
                 impl_psbt_insert_pair! {
-                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: (Fingerprint, DerivationPath)>
+                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: KeySource>
+                }
+            }
+            raw::PROPRIETARY_TYPE
+                if raw::ProprietaryKey::from_key(&raw_key)
+                    == Some(raw::ProprietaryKey::tapyrus(PSBT_IN_TAPYRUS_COLOR_ID)) =>
+            {
+                if self.color_id.is_some() {
+                    return Err(Error::DuplicateKey(raw_key).into());
+                }
+                self.color_id = Some(psbt::serialize::Deserialize::deserialize(&raw_value)?);
+            }
+            raw::PROPRIETARY_TYPE
+                if raw::ProprietaryKey::from_key(&raw_key)
+                    == Some(raw::ProprietaryKey::tapyrus(PSBT_IN_TAPYRUS_TOKEN_AMOUNT)) =>
+            {
+                if self.token_amount.is_some() {
+                    return Err(Error::DuplicateKey(raw_key).into());
                 }
+                self.token_amount = Some(psbt::serialize::Deserialize::deserialize(&raw_value)?);
             }
             _ => {
                 if self.unknown.contains_key(&raw_key) {
@@ -152,7 +208,7 @@ impl Map for Input {
         }
 
         impl_psbt_get_pair! {
-            rv.push(self.hd_keypaths as <6u8, PublicKey>|<(Fingerprint, DerivationPath)>)
+            rv.push(self.hd_keypaths as <6u8, PublicKey>|<KeySource>)
         }
 
         impl_psbt_get_pair! {
@@ -163,6 +219,20 @@ impl Map for Input {
             rv.push(self.final_script_witness as <8u8, _>|<Script>)
         }
 
+        if let Some(ref color_id) = self.color_id {
+            rv.push(raw::Pair {
+                key: raw::ProprietaryKey::tapyrus(PSBT_IN_TAPYRUS_COLOR_ID).to_key(),
+                value: psbt::serialize::Serialize::serialize(color_id),
+            });
+        }
+
+        if let Some(ref token_amount) = self.token_amount {
+            rv.push(raw::Pair {
+                key: raw::ProprietaryKey::tapyrus(PSBT_IN_TAPYRUS_TOKEN_AMOUNT).to_key(),
+                value: psbt::serialize::Serialize::serialize(token_amount),
+            });
+        }
+
         for (key, value) in self.unknown.iter() {
             rv.push(raw::Pair {
                 key: key.clone(),
@@ -189,6 +259,8 @@ impl Map for Input {
         merge!(witness_script, self, other);
         merge!(final_script_sig, self, other);
         merge!(final_script_witness, self, other);
+        merge!(color_id, self, other);
+        merge!(token_amount, self, other);
 
         Ok(())
     }
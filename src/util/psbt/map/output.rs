@@ -14,15 +14,20 @@
 
 use std::collections::BTreeMap;
 
-use blockdata::script::Script;
+use blockdata::script::{ColorIdentifier, Script};
 use consensus::encode;
-use util::bip32::{DerivationPath, Fingerprint};
+use util::bip32::{KeyOriginInfo, KeySource};
 use util::key::PublicKey;
 use util::psbt;
 use util::psbt::map::Map;
 use util::psbt::raw;
 use util::psbt::Error;
 
+/// Subtype of the Tapyrus proprietary key of the color of the tokens sent
+const PSBT_OUT_TAPYRUS_COLOR_ID: u8 = 0x00;
+/// Subtype of the Tapyrus proprietary key of the amount of tokens sent
+const PSBT_OUT_TAPYRUS_TOKEN_AMOUNT: u8 = 0x01;
+
 /// A key-value map for an output of the corresponding index in the unsigned
 /// transaction.
 #[derive(Clone, Default, Debug, PartialEq)]
@@ -33,11 +38,24 @@ pub struct Output {
     pub witness_script: Option<Script>,
     /// A map from public keys needed to spend this output to their
     /// corresponding master key fingerprints and derivation paths.
-    pub hd_keypaths: BTreeMap<PublicKey, (Fingerprint, DerivationPath)>,
+    pub hd_keypaths: BTreeMap<PublicKey, KeySource>,
+    /// The color of the tokens sent to this output, a Tapyrus proprietary
+    /// field.
+    pub color_id: Option<ColorIdentifier>,
+    /// The amount of tokens sent to this output, a Tapyrus proprietary
+    /// field.
+    pub token_amount: Option<u64>,
     /// Unknown key-value pairs for this output.
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 }
 
+impl Output {
+    /// The origin of `pubkey`, if it is one of the keys needed to spend this output
+    pub fn key_origin(&self, pubkey: &PublicKey) -> Option<KeyOriginInfo> {
+        self.hd_keypaths.get(pubkey).map(|source| KeyOriginInfo::from(source.clone()))
+    }
+}
+
 impl Map for Output {
     fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), encode::Error> {
         let raw::Pair {
@@ -58,8 +76,26 @@ impl Map for Output {
             }
             2u8 => {
                 impl_psbt_insert_pair! {
-                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: (Fingerprint, DerivationPath)>
+                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: KeySource>
+                }
+            }
+            raw::PROPRIETARY_TYPE
+                if raw::ProprietaryKey::from_key(&raw_key)
+                    == Some(raw::ProprietaryKey::tapyrus(PSBT_OUT_TAPYRUS_COLOR_ID)) =>
+            {
+                if self.color_id.is_some() {
+                    return Err(Error::DuplicateKey(raw_key).into());
+                }
+                self.color_id = Some(psbt::serialize::Deserialize::deserialize(&raw_value)?);
+            }
+            raw::PROPRIETARY_TYPE
+                if raw::ProprietaryKey::from_key(&raw_key)
+                    == Some(raw::ProprietaryKey::tapyrus(PSBT_OUT_TAPYRUS_TOKEN_AMOUNT)) =>
+            {
+                if self.token_amount.is_some() {
+                    return Err(Error::DuplicateKey(raw_key).into());
                 }
+                self.token_amount = Some(psbt::serialize::Deserialize::deserialize(&raw_value)?);
             }
             _ => {
                 if self.unknown.contains_key(&raw_key) {
@@ -85,7 +121,21 @@ impl Map for Output {
         }
 
         impl_psbt_get_pair! {
-            rv.push(self.hd_keypaths as <2u8, PublicKey>|<(Fingerprint, DerivationPath)>)
+            rv.push(self.hd_keypaths as <2u8, PublicKey>|<KeySource>)
+        }
+
+        if let Some(ref color_id) = self.color_id {
+            rv.push(raw::Pair {
+                key: raw::ProprietaryKey::tapyrus(PSBT_OUT_TAPYRUS_COLOR_ID).to_key(),
+                value: psbt::serialize::Serialize::serialize(color_id),
+            });
+        }
+
+        if let Some(ref token_amount) = self.token_amount {
+            rv.push(raw::Pair {
+                key: raw::ProprietaryKey::tapyrus(PSBT_OUT_TAPYRUS_TOKEN_AMOUNT).to_key(),
+                value: psbt::serialize::Serialize::serialize(token_amount),
+            });
         }
 
         for (key, value) in self.unknown.iter() {
@@ -104,6 +154,8 @@ impl Map for Output {
 
         merge!(redeem_script, self, other);
         merge!(witness_script, self, other);
+        merge!(color_id, self, other);
+        merge!(token_amount, self, other);
 
         Ok(())
     }
@@ -17,15 +17,29 @@
 //! Implementation of BIP174 Partially Signed Bitcoin Transaction Format as
 //! defined at https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
 //! except we define PSBTs containing non-standard SigHash types as invalid.
+//!
+//! Tapyrus has no segwit, so the segwit fields of BIP174 are only kept to
+//! parse PSBTs of other tools, and the color and amount of the tokens spent
+//! by inputs are recorded in proprietary fields prefixed by `tapyrus`.
 
-use blockdata::script::Script;
-use blockdata::transaction::Transaction;
+use blockdata::script::{self, Script};
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
 use consensus::{encode, Decodable, Encodable};
+use util::amount::Amount;
+use util::ecdsa;
+use util::sighash::SighashCache;
+use util::token::{Color, TokenBalances};
 
+use std::collections::BTreeMap;
 use std::io;
+#[cfg(feature = "base64")] use std::fmt;
+#[cfg(feature = "base64")] use std::str::FromStr;
+
+#[cfg(feature = "base64")] use base64;
 
 mod error;
 pub use self::error::Error;
+#[cfg(feature = "base64")] pub use self::error::PsbtParseError;
 
 pub mod raw;
 
@@ -34,6 +48,13 @@ mod macros;
 
 pub mod serialize;
 
+mod signer;
+pub use self::signer::{ExtendedKeySigner, SignRequest, Signer};
+
+pub mod roles;
+
+mod token;
+
 mod map;
 pub use self::map::{Global, Input, Map, Output};
 
@@ -62,18 +83,62 @@ impl PartiallySignedTransaction {
     }
 
     /// Extract the Transaction from a PartiallySignedTransaction by filling in
-    /// the available signature information in place.
+    /// the available signature information in place. Tapyrus transactions
+    /// have no witnesses, so final script witnesses are ignored.
     pub fn extract_tx(self) -> Transaction {
         let mut tx: Transaction = self.global.unsigned_tx;
 
         for (vin, psbtin) in tx.input.iter_mut().zip(self.inputs.into_iter()) {
             vin.script_sig = psbtin.final_script_sig.unwrap_or_else(|| Script::new());
-            vin.witness = psbtin.final_script_witness.unwrap_or_else(|| Vec::new());
         }
 
         tx
     }
 
+    /// Check that the PSBT describes a Tapyrus transaction: no input has
+    /// segwit fields, the transactions spent by the inputs are those their
+    /// outpoints refer to by malleability-fixed txid, the tokens of the
+    /// inputs match the outputs they spend, when these are known, and the
+    /// tokens of the outputs match their script pubkeys and values.
+    pub fn check_tapyrus(&self) -> Result<(), self::Error> {
+        let txins = self.global.unsigned_tx.input.iter();
+        for (index, (txin, input)) in txins.zip(self.inputs.iter()).enumerate() {
+            if input.has_witness_fields() {
+                return Err(Error::WitnessFields(index));
+            }
+
+            if input.color_id.is_some() != input.token_amount.is_some() {
+                return Err(Error::TokenMismatch(index));
+            }
+
+            if input.non_witness_utxo.is_none() {
+                continue;
+            }
+            let spent = match input.spent_output(&txin.previous_output) {
+                Some(spent) => spent,
+                None => return Err(Error::SpentTxMismatch(index)),
+            };
+            if input.color_id.is_some() {
+                let color_id = spent.script_pubkey.color_id();
+                if input.color_id != color_id || input.token_amount != Some(spent.value) {
+                    return Err(Error::TokenMismatch(index));
+                }
+            }
+        }
+
+        let txouts = self.global.unsigned_tx.output.iter();
+        for (index, (txout, output)) in txouts.zip(self.outputs.iter()).enumerate() {
+            if output.color_id.is_none() && output.token_amount.is_none() {
+                continue;
+            }
+            if output.color_id != txout.script_pubkey.color_id() || output.token_amount != Some(txout.value) {
+                return Err(Error::OutputTokenMismatch(index));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Attempt to merge with another `PartiallySignedTransaction`.
     pub fn merge(&mut self, other: Self) -> Result<(), self::Error> {
         self.global.merge(other.global)?;
@@ -88,6 +153,162 @@ impl PartiallySignedTransaction {
 
         Ok(())
     }
+
+    /// Combine with another `PartiallySignedTransaction` of the same
+    /// unsigned transaction, e.g. signed by another signer. The partial
+    /// signatures, key origins, proprietary and unknown fields of both are
+    /// kept. Unlike `merge`, fields set by both to different values are
+    /// errors.
+    pub fn combine(mut self, other: Self) -> Result<Self, self::Error> {
+        if self.global.unsigned_tx != other.global.unsigned_tx {
+            return Err(Error::UnexpectedUnsignedTx {
+                expected: self.global.unsigned_tx,
+                actual: other.global.unsigned_tx,
+            });
+        }
+
+        check_conflicts(&self.global, &other.global)?;
+        for (self_input, other_input) in self.inputs.iter().zip(other.inputs.iter()) {
+            check_conflicts(self_input, other_input)?;
+        }
+        for (self_output, other_output) in self.outputs.iter().zip(other.outputs.iter()) {
+            check_conflicts(self_output, other_output)?;
+        }
+
+        self.merge(other)?;
+        Ok(self)
+    }
+}
+
+impl PartiallySignedTransaction {
+    /// The fee paid by the transaction, the TPC spent by its inputs but not
+    /// sent to its outputs. The outputs spent by all inputs must be known.
+    pub fn fee(&self) -> Result<Amount, self::Error> {
+        let spent = self.spent_outputs()?;
+        let inputs = TokenBalances::from_outputs(spent.iter()).ok_or(Error::AmountOverflow)?;
+        let outputs = TokenBalances::from_outputs(&self.global.unsigned_tx.output).ok_or(Error::AmountOverflow)?;
+        let fee = inputs.get(&Color::Tpc).checked_sub(outputs.get(&Color::Tpc)).ok_or(Error::NegativeFee)?;
+        Ok(Amount::from_sat(fee))
+    }
+
+    /// Check the PSBT before it is signed, or displayed to be confirmed: the
+    /// unsigned transaction has no script_sigs, the PSBT is a Tapyrus one
+    /// as `check_tapyrus` checks, the outputs spent by all inputs are known,
+    /// the amounts of each color do not overflow, and the tokens sent to the
+    /// outputs are spent by the inputs or issued.
+    pub fn sanity_check(&self) -> Result<(), self::Error> {
+        if self.global.unsigned_tx.input.iter().any(|txin| !txin.script_sig.is_empty()) {
+            return Err(Error::UnsignedTxHasScriptSigs);
+        }
+        self.check_tapyrus()?;
+
+        let spent = self.spent_outputs()?;
+        self.fee()?;
+        match self.global.unsigned_tx.check_token_balance(&spent) {
+            Ok(()) => Ok(()),
+            Err(script::Error::TokenBalance(color_id)) => Err(Error::TokenImbalance(color_id)),
+            Err(e) => unreachable!("unexpected token balance error {:?}", e),
+        }
+    }
+
+    /// Sign the inputs which are not finalized with `signer`, for each key
+    /// whose origin is recorded and which has not signed yet. The script
+    /// signed is the redeem script of the input if any, or else the script
+    /// pubkey of the output it spends, and inputs for which neither is known
+    /// are skipped. Returns the number of signatures added.
+    pub fn sign<S: Signer>(&mut self, signer: &mut S) -> Result<usize, S::Error> {
+        let tx = &self.global.unsigned_tx;
+        let mut cache = SighashCache::new(tx);
+        let mut signed = 0;
+
+        for (index, (txin, input)) in tx.input.iter().zip(self.inputs.iter_mut()).enumerate() {
+            if input.final_script_sig.is_some() {
+                continue;
+            }
+            let script_code = match (&input.redeem_script, input.spent_output(&txin.previous_output)) {
+                (&Some(ref redeem_script), _) => redeem_script.clone(),
+                (&None, Some(spent)) => spent.script_pubkey.clone(),
+                (&None, None) => continue,
+            };
+            let sighash_type = input.sighash_type.unwrap_or(SigHashType::All);
+            let sighash = cache.signature_hash(index, &script_code, sighash_type.as_u32());
+
+            let unsigned: Vec<_> = input.hd_keypaths.iter()
+                .filter(|&(public_key, _)| !input.partial_sigs.contains_key(public_key))
+                .map(|(public_key, source)| (*public_key, source.clone()))
+                .collect();
+            for (public_key, source) in unsigned {
+                let request = SignRequest {
+                    input_index: index,
+                    sighash: sighash,
+                    sighash_type: sighash_type,
+                    public_key: public_key,
+                    key_origin: Some(source.into()),
+                };
+                if let Some(signature) = signer.sign(&request)? {
+                    let signature = ecdsa::Signature::new(signature, sighash_type);
+                    input.partial_sigs.insert(public_key, signature.serialize());
+                    signed += 1;
+                }
+            }
+        }
+
+        Ok(signed)
+    }
+
+    /// The outputs spent by the inputs, which must all be known
+    fn spent_outputs(&self) -> Result<Vec<TxOut>, self::Error> {
+        self.global.unsigned_tx.input.iter().enumerate().map(|(index, txin)| {
+            self.inputs.get(index)
+                .and_then(|input| input.spent_output(&txin.previous_output))
+                .cloned()
+                .ok_or(Error::MissingUtxo(index))
+        }).collect()
+    }
+}
+
+/// Check that two maps do not have different values for the same key
+fn check_conflicts<M: Map>(map: &M, other: &M) -> Result<(), Error> {
+    let pairs = map.get_pairs().expect("in-memory serialization");
+    let values: BTreeMap<raw::Key, Vec<u8>> = pairs.into_iter().map(|pair| (pair.key, pair.value)).collect();
+    for pair in other.get_pairs().expect("in-memory serialization") {
+        match values.get(&pair.key) {
+            Some(value) if *value != pair.value => return Err(Error::ConflictingValues(pair.key)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl PartiallySignedTransaction {
+    /// Serialize as hex.
+    pub fn to_hex(&self) -> String {
+        encode::serialize_hex(self)
+    }
+
+    /// Parse from hex, failing if the PSBT is followed by other data.
+    pub fn from_hex(s: &str) -> Result<Self, encode::Error> {
+        encode::deserialize_hex(s)
+    }
+}
+
+/// Base64, as exchanged between wallets and the `*psbt` RPCs.
+#[cfg(feature = "base64")]
+impl fmt::Display for PartiallySignedTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&base64::encode(&encode::serialize(self)))
+    }
+}
+
+#[cfg(feature = "base64")]
+impl FromStr for PartiallySignedTransaction {
+    type Err = PsbtParseError;
+
+    /// Parse from base64, failing if the PSBT is followed by other data.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base64::decode(s).map_err(PsbtParseError::Base64)?;
+        encode::deserialize(&data).map_err(PsbtParseError::Encoding)
+    }
 }
 
 impl Encodable for PartiallySignedTransaction {
@@ -160,7 +381,7 @@ impl Decodable for PartiallySignedTransaction {
 #[cfg(test)]
 mod tests {
     use hashes::hex::FromHex;
-    use hash_types::Txid;
+    use hash_types::MalFixTxid;
 
     use std::collections::BTreeMap;
 
@@ -168,16 +389,19 @@ mod tests {
 
     use secp256k1::Secp256k1;
 
-    use blockdata::script::Script;
+    use blockdata::locktime::{LockTime, Sequence};
+    use blockdata::script::{Builder, ColorIdentifier, Script};
     use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
     use consensus::encode::{deserialize, serialize, serialize_hex};
     use network::constants::Network::Prod;
-    use util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
+    use util::address::Address;
+    use util::amount::Amount;
+    use util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint, KeyOriginInfo, KeySource};
     use util::key::PublicKey;
     use util::psbt::map::{Global, Output};
     use util::psbt::raw;
 
-    use super::PartiallySignedTransaction;
+    use super::{Error, ExtendedKeySigner, PartiallySignedTransaction};
 
     #[test]
     fn trivial_psbt() {
@@ -185,7 +409,7 @@ mod tests {
             global: Global {
                 unsigned_tx: Transaction {
                     version: 2,
-                    lock_time: 0,
+                    lock_time: LockTime::ZERO,
                     input: vec![],
                     output: vec![],
                 },
@@ -205,7 +429,7 @@ mod tests {
         let secp = &Secp256k1::new();
         let seed = hex_decode("000102030405060708090a0b0c0d0e0f").unwrap();
 
-        let mut hd_keypaths: BTreeMap<PublicKey, (Fingerprint, DerivationPath)> = Default::default();
+        let mut hd_keypaths: BTreeMap<PublicKey, KeySource> = Default::default();
 
         let mut sk: ExtendedPrivKey = ExtendedPrivKey::new_master(Prod, &seed).unwrap();
 
@@ -242,6 +466,228 @@ mod tests {
         let actual: Output = deserialize(&serialize(&expected)).unwrap();
 
         assert_eq!(expected, actual);
+        assert_eq!(
+            actual.key_origin(&pk.public_key),
+            Some(KeyOriginInfo::new(fprint, "m/0/1/2/4/42/69'/420/31337".parse().unwrap()))
+        );
+        let master = ExtendedPrivKey::new_master(Prod, &seed).unwrap();
+        assert_eq!(actual.key_origin(&ExtendedPubKey::from_private(&secp, &master).public_key), None);
+    }
+
+    #[test]
+    fn tapyrus_fields() {
+        let script_pubkey = hex_script!("76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac");
+        let color_id = ColorIdentifier::reissuable(script_pubkey.clone());
+        let colored = script_pubkey.add_color(color_id.clone()).unwrap();
+        let spent_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut { value: 100, script_pubkey: colored.clone() }],
+        };
+        let unsigned_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: spent_tx.malfix_txid(), vout: 0 },
+                ..Default::default()
+            }],
+            output: vec![TxOut { value: 100, script_pubkey: colored }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(spent_tx);
+        psbt.inputs[0].color_id = Some(color_id);
+        psbt.inputs[0].token_amount = Some(100);
+        assert!(psbt.check_tapyrus().is_ok());
+
+        // proprietary keys prefixed by "tapyrus", of subtypes 0 and 1
+        let hex = serialize_hex(&psbt);
+        assert!(hex.contains("0afc07746170797275730021c1"));
+        assert!(hex.contains("0afc077461707972757301086400000000000000"));
+        let decoded: PartiallySignedTransaction = deserialize(&serialize(&psbt)).unwrap();
+        assert_eq!(decoded, psbt);
+        assert!(decoded.inputs[0].unknown.is_empty());
+
+        let mut mismatch = psbt.clone();
+        mismatch.inputs[0].token_amount = Some(99);
+        match mismatch.check_tapyrus() {
+            Err(Error::TokenMismatch(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        mismatch.inputs[0].token_amount = None;
+        match mismatch.check_tapyrus() {
+            Err(Error::TokenMismatch(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let mut mismatch = psbt.clone();
+        mismatch.inputs[0].non_witness_utxo.as_mut().unwrap().version = 2;
+        match mismatch.check_tapyrus() {
+            Err(Error::SpentTxMismatch(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let mut witness = psbt.clone();
+        witness.inputs[0].final_script_witness = Some(vec![vec![1]]);
+        match witness.check_tapyrus() {
+            Err(Error::WitnessFields(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(witness.extract_tx().input[0].witness.is_empty());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let hex = "70736274ff01000a0200000000000000000000";
+        let psbt = PartiallySignedTransaction::from_hex(hex).unwrap();
+        assert_eq!(psbt.to_hex(), hex);
+
+        assert!(PartiallySignedTransaction::from_hex("70736274ff01000a020000000000000000000000").is_err());
+        assert!(PartiallySignedTransaction::from_hex("70736274ff01000a02000000000000000000000").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "base64")]
+    fn base64_round_trip() {
+        use std::str::FromStr;
+        use util::psbt::PsbtParseError;
+
+        let base64 = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+        let psbt = PartiallySignedTransaction::from_str(base64).unwrap();
+        assert_eq!(psbt.to_string(), base64);
+        assert_eq!(psbt, PartiallySignedTransaction::from_hex(&psbt.to_hex()).unwrap());
+
+        match PartiallySignedTransaction::from_str("cHNidP8=!") {
+            Err(PsbtParseError::Base64(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        // a valid PSBT followed by an extra byte
+        let mut data = serialize(&psbt);
+        data.push(0);
+        match PartiallySignedTransaction::from_str(&::base64::encode(&data)) {
+            Err(PsbtParseError::Encoding(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn fee_and_sanity_check() {
+        let script_pubkey = hex_script!("76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac");
+        let color_id = ColorIdentifier::reissuable(script_pubkey.clone());
+        let colored = script_pubkey.add_color(color_id.clone()).unwrap();
+        let spent_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut { value: 1000, script_pubkey: script_pubkey.clone() },
+                TxOut { value: 100, script_pubkey: colored.clone() },
+            ],
+        };
+        let unsigned_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: (0..2).map(|vout| TxIn {
+                previous_output: OutPoint { txid: spent_tx.malfix_txid(), vout: vout },
+                ..Default::default()
+            }).collect(),
+            output: vec![
+                TxOut { value: 900, script_pubkey: script_pubkey.clone() },
+                TxOut { value: 60, script_pubkey: colored.clone() },
+                TxOut { value: 40, script_pubkey: colored.clone() },
+            ],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        match psbt.fee() {
+            Err(Error::MissingUtxo(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        psbt.inputs[0].non_witness_utxo = Some(spent_tx.clone());
+        match psbt.sanity_check() {
+            Err(Error::MissingUtxo(1)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        psbt.inputs[1].non_witness_utxo = Some(spent_tx);
+        assert_eq!(psbt.fee().unwrap(), Amount::from_sat(100));
+        assert!(psbt.sanity_check().is_ok());
+
+        let mut overspent = psbt.clone();
+        overspent.global.unsigned_tx.output[0].value = 1001;
+        match overspent.fee() {
+            Err(Error::NegativeFee) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        overspent.global.unsigned_tx.output[0].value = u64::max_value();
+        overspent.global.unsigned_tx.output.push(TxOut { value: 1, script_pubkey: script_pubkey });
+        match overspent.sanity_check() {
+            Err(Error::AmountOverflow) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // tokens of another color, neither spent nor issued
+        let mut imbalanced = psbt.clone();
+        let other_color = ColorIdentifier::reissuable(Script::new());
+        imbalanced.global.unsigned_tx.output[2].script_pubkey = hex_script!("76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac")
+            .add_color(other_color.clone())
+            .unwrap();
+        match imbalanced.sanity_check() {
+            Err(Error::TokenImbalance(ref color_id)) if *color_id == other_color => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let mut signed = psbt;
+        signed.global.unsigned_tx.input[0].script_sig = hex_script!("51");
+        match signed.sanity_check() {
+            Err(Error::UnsignedTxHasScriptSigs) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn sign() {
+        let secp = Secp256k1::new();
+        let seed = hex_decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivKey::new_master(Prod, &seed).unwrap();
+        let path: DerivationPath = "m/0'/1".parse().unwrap();
+        let public_key = ExtendedPubKey::from_private(&secp, &master.derive_priv(&secp, &path).unwrap()).public_key;
+        let script_pubkey = Address::p2pkh(&public_key, Prod).script_pubkey();
+
+        let spent_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut { value: 1000, script_pubkey: script_pubkey.clone() }],
+        };
+        let unsigned_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: spent_tx.malfix_txid(), vout: 0 },
+                ..Default::default()
+            }],
+            output: vec![TxOut { value: 900, script_pubkey: script_pubkey }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        psbt.inputs[0].hd_keypaths.insert(public_key, (master.fingerprint(&secp), path));
+        let mut signer = ExtendedKeySigner::new(&secp, master);
+
+        // the script to sign is unknown
+        assert_eq!(psbt.sign(&mut signer), Ok(0));
+        psbt.inputs[0].non_witness_utxo = Some(spent_tx.clone());
+
+        // another master key does not hold the key
+        let other = ExtendedPrivKey::new_master(Prod, &[1u8; 32]).unwrap();
+        assert_eq!(psbt.sign(&mut ExtendedKeySigner::new(&secp, other)), Ok(0));
+
+        assert_eq!(psbt.sign(&mut signer), Ok(1));
+        assert_eq!(psbt.sign(&mut signer), Ok(0));
+
+        let signature = psbt.inputs[0].partial_sigs[&public_key].clone();
+        let mut tx = psbt.extract_tx();
+        tx.input[0].script_sig = Builder::new().push_slice(&signature).push_key(&public_key).into_script();
+        assert_eq!(tx.verify(|outpoint| spent_tx.output.get(outpoint.vout as usize).cloned()), Ok(()));
     }
 
     #[test]
@@ -249,17 +695,17 @@ mod tests {
         let expected = Global {
             unsigned_tx: Transaction {
                 version: 2,
-                lock_time: 1257139,
+                lock_time: LockTime::from(1257139),
                 input: vec![TxIn {
                     previous_output: OutPoint {
-                        txid: Txid::from_hex(
+                        txid: MalFixTxid::from_hex(
                             "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126",
                         )
                         .unwrap(),
                         vout: 0,
                     },
                     script_sig: Script::new(),
-                    sequence: 4294967294,
+                    sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
                     witness: vec![],
                 }],
                 output: vec![
@@ -307,13 +753,58 @@ mod tests {
         assert_eq!(hex, serialize_hex(&psbt));
     }
 
+    #[test]
+    fn combine() {
+        let hex = "70736274ff0100890200000001207ae985d787dfe6143d5c58fad79cc7105e0e799fcf033b7f2ba17e62d7b3200000000000ffffffff02563d03000000000022002019899534b9a011043c0dd57c3ff9a381c3522c5f27c6a42319085b56ca543a1d6adc020000000000220020618b47a07ebecca4e156edb1b9ea7c24bdee0139fc049237965ffdaf56d5ee73000000000001012b801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab82202025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee076473044022007e06b362e89912abd4661f47945430739b006a85d1b2a16c01dc1a4bd07acab022061576d7aa834988b7ab94ef21d8eebd996ea59ea20529a19b15f0c9cebe3d8ac01220202b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a473044022002787f926d6817504431ee281183b8119b6845bfaa6befae45e13b6d430c9d2f02202859f149a6cd26ae2f03a107e7f33c7d91730dade305fe077bae677b5d44952a01010547522102b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a21025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee07652ae0001014752210283ef76537f2d58ae3aa3a4bd8ae41c3f230ccadffb1a0bd3ca504d871cff05e7210353d79cc0cb1396f4ce278d005f16d948e02a6aec9ed1109f13747ecb1507b37b52ae00010147522102b3937241777b6665e0d694e52f9c1b188433641df852da6fc42187b5d8a368a321034cdd474f01cc5aa7ff834ad8bcc882a87e854affc775486bc2a9f62e8f49bd7852ae00";
+        let mut psbt = PartiallySignedTransaction::from_hex(hex).unwrap();
+        psbt.inputs[0].color_id = Some(ColorIdentifier::reissuable(Script::new()));
+        psbt.inputs[0].token_amount = Some(1);
+        let keys: Vec<PublicKey> = psbt.inputs[0].partial_sigs.keys().cloned().collect();
+        assert_eq!(keys.len(), 2);
+
+        // each signer only has its own signature, and one the token fields
+        let mut first = psbt.clone();
+        first.inputs[0].partial_sigs.remove(&keys[1]);
+        first.inputs[0].token_amount = None;
+        let mut second = psbt.clone();
+        second.inputs[0].partial_sigs.remove(&keys[0]);
+        second.inputs[0].color_id = None;
+        second.inputs[0].redeem_script = None;
+        assert_eq!(first.clone().combine(second.clone()).unwrap(), psbt);
+        assert_eq!(second.clone().combine(first.clone()).unwrap(), psbt);
+
+        let mut conflicting = second.clone();
+        conflicting.inputs[0].partial_sigs.insert(keys[0], vec![0x30, 0x01]);
+        match first.clone().combine(conflicting) {
+            Err(Error::ConflictingValues(key)) => {
+                assert_eq!(key, raw::Key { type_value: 2, key: keys[0].to_bytes() });
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let mut conflicting = second.clone();
+        conflicting.inputs[0].token_amount = Some(2);
+        first.inputs[0].token_amount = Some(1);
+        match first.clone().combine(conflicting) {
+            Err(Error::ConflictingValues(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let mut other_tx = second;
+        other_tx.global.unsigned_tx.lock_time = LockTime::from(1);
+        match first.combine(other_tx) {
+            Err(Error::UnexpectedUnsignedTx { .. }) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
     mod bip_vectors {
         use std::collections::BTreeMap;
 
         use hex::decode as hex_decode;
 
         use hashes::hex::FromHex;
-        use hash_types::Txid;
+        use hash_types::{MalFixTxid, Txid};
 
         use blockdata::script::Script;
         use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
@@ -358,16 +849,16 @@ mod tests {
                 global: Global {
                     unsigned_tx: Transaction {
                         version: 2,
-                        lock_time: 1257139,
+                        lock_time: LockTime::from(1257139),
                         input: vec![TxIn {
                             previous_output: OutPoint {
-                                txid: Txid::from_hex(
+                                txid: MalFixTxid::from_hex(
                                     "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126",
                                 ).unwrap(),
                                 vout: 0,
                             },
                             script_sig: Script::new(),
-                            sequence: 4294967294,
+                            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
                             witness: vec![],
                         }],
                         output: vec![
@@ -386,16 +877,16 @@ mod tests {
                 inputs: vec![Input {
                     non_witness_utxo: Some(Transaction {
                         version: 1,
-                        lock_time: 0,
+                        lock_time: LockTime::ZERO,
                         input: vec![TxIn {
                             previous_output: OutPoint {
-                                txid: Txid::from_hex(
+                                txid: MalFixTxid::from_hex(
                                     "e567952fb6cc33857f392efa3a46c995a28f69cca4bb1b37e0204dab1ec7a389",
                                 ).unwrap(),
                                 vout: 1,
                             },
                             script_sig: hex_script!("160014be18d152a9b012039daf3da7de4f53349eecb985"),
-                            sequence: 4294967295,
+                            sequence: Sequence::MAX,
                             witness: vec![
                                 hex_decode("304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c01").unwrap(),
                                 hex_decode("03d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f2105").unwrap(),
@@ -403,13 +894,13 @@ mod tests {
                         },
                         TxIn {
                             previous_output: OutPoint {
-                                txid: Txid::from_hex(
+                                txid: MalFixTxid::from_hex(
                                     "b490486aec3ae671012dddb2bb08466bef37720a533a894814ff1da743aaf886",
                                 ).unwrap(),
                                 vout: 1,
                             },
                             script_sig: hex_script!("160014fe3e9ef1a745e974d902c4355943abcb34bd5353"),
-                            sequence: 4294967295,
+                            sequence: Sequence::MAX,
                             witness: vec![
                                 hex_decode("3045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01").unwrap(),
                                 hex_decode("0223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab3").unwrap(),
@@ -478,7 +969,8 @@ mod tests {
             let tx_input = &psbt.global.unsigned_tx.input[0];
             let psbt_non_witness_utxo = (&psbt.inputs[0].non_witness_utxo).as_ref().unwrap();
 
-            assert_eq!(tx_input.previous_output.txid, psbt_non_witness_utxo.txid());
+            // bitcoin test vector, whose outpoints refer to the plain txid
+            assert_eq!(tx_input.previous_output.txid.as_hash(), psbt_non_witness_utxo.txid().as_hash());
             assert!(
                 psbt_non_witness_utxo.output[tx_input.previous_output.vout as usize]
                     .script_pubkey
@@ -32,6 +32,69 @@ pub struct Key {
     pub key: Vec<u8>,
 }
 
+/// The type of proprietary keys, whose meaning is defined by the
+/// applications that use them.
+pub const PROPRIETARY_TYPE: u8 = 0xFC;
+
+/// The identifier prefixing the proprietary keys defined for Tapyrus.
+pub const TAPYRUS_PROPRIETARY_PREFIX: &[u8] = b"tapyrus";
+
+/// A proprietary PSBT key: an identifier prefix, a subtype defined by the
+/// application identified by the prefix, and key data.
+#[derive(Debug, PartialEq, Hash, Eq, Clone, Ord, PartialOrd)]
+pub struct ProprietaryKey {
+    /// The identifier prefix of the application defining the key.
+    pub prefix: Vec<u8>,
+    /// The subtype of the key.
+    pub subtype: u8,
+    /// The key data.
+    pub key: Vec<u8>,
+}
+
+impl ProprietaryKey {
+    /// Create a Tapyrus proprietary key of the given subtype, without key
+    /// data.
+    pub fn tapyrus(subtype: u8) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: TAPYRUS_PROPRIETARY_PREFIX.to_vec(),
+            subtype: subtype,
+            key: vec![],
+        }
+    }
+
+    /// Parse a raw key as a proprietary key, `None` if it is of another
+    /// type or malformed.
+    pub fn from_key(key: &Key) -> Option<ProprietaryKey> {
+        if key.type_value != PROPRIETARY_TYPE {
+            return None;
+        }
+
+        let mut d = &key.key[..];
+        let prefix: Vec<u8> = Decodable::consensus_decode(&mut d).ok()?;
+        let VarInt(subtype) = Decodable::consensus_decode(&mut d).ok()?;
+        if subtype > 0xff {
+            return None;
+        }
+
+        Some(ProprietaryKey {
+            prefix: prefix,
+            subtype: subtype as u8,
+            key: d.to_vec(),
+        })
+    }
+
+    /// Serialize as a raw key.
+    pub fn to_key(&self) -> Key {
+        let mut key = encode::serialize(&self.prefix);
+        key.extend(encode::serialize(&VarInt(self.subtype as u64)));
+        key.extend_from_slice(&self.key);
+        Key {
+            type_value: PROPRIETARY_TYPE,
+            key: key,
+        }
+    }
+}
+
 /// A PSBT key-value pair in its raw byte form.
 #[derive(Debug, PartialEq)]
 pub struct Pair {
@@ -63,10 +126,10 @@ impl Decodable for Key {
         let key_byte_size: u64 = byte_size - 1;
 
         if key_byte_size > MAX_VEC_SIZE as u64 {
-            return Err(encode::Error::OversizedVectorAllocation {
+            return Err(encode::Error::Policy(encode::PolicyError::OversizedVectorAllocation {
                 requested: key_byte_size as usize,
                 max: MAX_VEC_SIZE,
-            });
+            }));
         }
 
         let type_value: u8 = Decodable::consensus_decode(&mut d)?;
@@ -0,0 +1,291 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! # PSBT Roles
+//!
+//! BIP174 splits the handling of a PSBT into roles. The types of this module
+//! each hold a PSBT in one role, and only expose the operations of that
+//! role, so that e.g. an updater can not add signatures. Each role hands the
+//! PSBT over to the next one: the [Creator] creates it from an unsigned
+//! transaction, the [Updater] adds the outputs spent by the inputs and the
+//! scripts and keys needed to sign them, [SignerRole]s sign it and combine
+//! their signatures, and the [Extractor] finalizes the inputs and extracts
+//! the signed transaction.
+//!
+
+use hashes::Hash;
+
+use blockdata::opcodes;
+use blockdata::script::{Builder, ColorIdentifier, Script, ScriptType};
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use hash_types::PubkeyHash;
+use util::bip32::KeySource;
+use util::key::PublicKey;
+use util::psbt::{self, Error, Input, PartiallySignedTransaction};
+
+macro_rules! impl_role {
+    ($role:ident) => {
+        impl $role {
+            /// The PSBT in this role
+            pub fn psbt(&self) -> &PartiallySignedTransaction {
+                &self.0
+            }
+
+            /// Give up the role, e.g. to serialize the PSBT
+            pub fn into_psbt(self) -> PartiallySignedTransaction {
+                self.0
+            }
+        }
+    };
+}
+
+/// The creator of a PSBT, from an unsigned transaction
+#[derive(Clone, PartialEq, Debug)]
+pub struct Creator(PartiallySignedTransaction);
+impl_role!(Creator);
+
+impl Creator {
+    /// Create the PSBT of `unsigned_tx`, which must have no script_sigs
+    pub fn new(unsigned_tx: Transaction) -> Result<Creator, Error> {
+        Ok(Creator(PartiallySignedTransaction::from_unsigned_tx(unsigned_tx)?))
+    }
+
+    /// Hand the PSBT over to its updater
+    pub fn into_updater(self) -> Updater {
+        Updater(self.0)
+    }
+}
+
+/// The updater of a PSBT, which adds the information needed to sign it
+#[derive(Clone, PartialEq, Debug)]
+pub struct Updater(PartiallySignedTransaction);
+impl_role!(Updater);
+
+impl Updater {
+    /// Update a PSBT received from another tool
+    pub fn new(psbt: PartiallySignedTransaction) -> Updater {
+        Updater(psbt)
+    }
+
+    /// Add the transaction containing the output spent by an input. Its
+    /// malleability-fixed txid must be the one of the outpoint of the input.
+    pub fn add_spent_tx(&mut self, input_index: usize, tx: Transaction) -> Result<(), Error> {
+        let txin = self.0.global.unsigned_tx.input.get(input_index).ok_or(Error::IndexOutOfRange(input_index))?;
+        if tx.malfix_txid() != txin.previous_output.txid {
+            return Err(Error::SpentTxMismatch(input_index));
+        }
+        self.input_mut(input_index)?.non_witness_utxo = Some(tx);
+        Ok(())
+    }
+
+    /// Set the redeem script of an input spending a P2SH or CP2SH output
+    pub fn set_redeem_script(&mut self, input_index: usize, redeem_script: Script) -> Result<(), Error> {
+        self.input_mut(input_index)?.redeem_script = Some(redeem_script);
+        Ok(())
+    }
+
+    /// Set the sighash type the signatures of an input must commit to
+    pub fn set_sighash_type(&mut self, input_index: usize, sighash_type: SigHashType) -> Result<(), Error> {
+        self.input_mut(input_index)?.sighash_type = Some(sighash_type);
+        Ok(())
+    }
+
+    /// Record the origin of a key needed to sign an input
+    pub fn add_input_key(&mut self, input_index: usize, public_key: PublicKey, source: KeySource) -> Result<(), Error> {
+        self.input_mut(input_index)?.hd_keypaths.insert(public_key, source);
+        Ok(())
+    }
+
+    /// Record the color and amount of the tokens spent by an input
+    pub fn set_token(&mut self, input_index: usize, color_id: ColorIdentifier, amount: u64) -> Result<(), Error> {
+        let input = self.input_mut(input_index)?;
+        input.color_id = Some(color_id);
+        input.token_amount = Some(amount);
+        Ok(())
+    }
+
+    /// Set the redeem script of a P2SH or CP2SH output
+    pub fn set_output_redeem_script(&mut self, output_index: usize, redeem_script: Script) -> Result<(), Error> {
+        let output = self.0.outputs.get_mut(output_index).ok_or(Error::IndexOutOfRange(output_index))?;
+        output.redeem_script = Some(redeem_script);
+        Ok(())
+    }
+
+    /// Record the origin of a key needed to spend an output
+    pub fn add_output_key(&mut self, output_index: usize, public_key: PublicKey, source: KeySource) -> Result<(), Error> {
+        let output = self.0.outputs.get_mut(output_index).ok_or(Error::IndexOutOfRange(output_index))?;
+        output.hd_keypaths.insert(public_key, source);
+        Ok(())
+    }
+
+    /// Hand the PSBT over to its signers
+    pub fn into_signer(self) -> SignerRole {
+        SignerRole(self.0)
+    }
+
+    fn input_mut(&mut self, input_index: usize) -> Result<&mut Input, Error> {
+        self.0.inputs.get_mut(input_index).ok_or(Error::IndexOutOfRange(input_index))
+    }
+}
+
+/// A signer of a PSBT, named apart from the `psbt::Signer` trait of the
+/// keys it signs with
+#[derive(Clone, PartialEq, Debug)]
+pub struct SignerRole(PartiallySignedTransaction);
+impl_role!(SignerRole);
+
+impl SignerRole {
+    /// Sign a PSBT received from another tool
+    pub fn new(psbt: PartiallySignedTransaction) -> SignerRole {
+        SignerRole(psbt)
+    }
+
+    /// Sign the inputs with `signer`, as `PartiallySignedTransaction::sign`
+    /// does. Returns the number of signatures added.
+    pub fn sign<S: psbt::Signer>(&mut self, signer: &mut S) -> Result<usize, S::Error> {
+        self.0.sign(signer)
+    }
+
+    /// Combine with the PSBT of another signer, as
+    /// `PartiallySignedTransaction::combine` does
+    pub fn combine(self, other: SignerRole) -> Result<SignerRole, Error> {
+        Ok(SignerRole(self.0.combine(other.0)?))
+    }
+
+    /// Hand the PSBT over to its extractor
+    pub fn into_extractor(self) -> Extractor {
+        Extractor(self.0)
+    }
+}
+
+/// The extractor of the signed transaction of a PSBT
+#[derive(Clone, PartialEq, Debug)]
+pub struct Extractor(PartiallySignedTransaction);
+impl_role!(Extractor);
+
+impl Extractor {
+    /// Extract the transaction of a PSBT received from another tool
+    pub fn new(psbt: PartiallySignedTransaction) -> Extractor {
+        Extractor(psbt)
+    }
+
+    /// Finalize the inputs, then extract the signed transaction. Besides
+    /// inputs already finalized, inputs spending P2PKH and CP2PKH outputs
+    /// are finalized with the signature of their key, and inputs with a
+    /// multisig redeem script with the signatures of the required number
+    /// of its keys. Any other input can not be finalized.
+    pub fn extract(mut self) -> Result<Transaction, Error> {
+        if self.0.inputs.len() < self.0.global.unsigned_tx.input.len() {
+            return Err(Error::CannotFinalize(self.0.inputs.len()));
+        }
+        let txins = self.0.global.unsigned_tx.input.iter();
+        for (index, (txin, input)) in txins.zip(self.0.inputs.iter_mut()).enumerate() {
+            if input.final_script_sig.is_some() {
+                continue;
+            }
+            let spent = input.spent_output(&txin.previous_output).cloned();
+            match finalize(input, spent) {
+                Some(script_sig) => input.final_script_sig = Some(script_sig),
+                None => return Err(Error::CannotFinalize(index)),
+            }
+        }
+        Ok(self.0.extract_tx())
+    }
+}
+
+/// The script_sig of an input, spending `spent`, from its partial signatures
+fn finalize(input: &Input, spent: Option<TxOut>) -> Option<Script> {
+    if let Some(ref redeem_script) = input.redeem_script {
+        let (required, keys) = redeem_script.parse_multisig()?;
+        let signatures: Vec<&Vec<u8>> = keys.iter().filter_map(|key| input.partial_sigs.get(key)).take(required).collect();
+        if signatures.len() < required {
+            return None;
+        }
+        // the extra element popped by OP_CHECKMULTISIG
+        let mut builder = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0);
+        for signature in signatures {
+            builder = builder.push_slice(signature);
+        }
+        return Some(builder.push_slice(redeem_script.as_bytes()).into_script());
+    }
+
+    let pubkey_hash = match spent?.script_pubkey.classify() {
+        ScriptType::P2pkh(hash) | ScriptType::Cp2pkh(_, hash) => hash,
+        _ => return None,
+    };
+    input.partial_sigs.iter().find(|&(key, _)| {
+        let mut engine = PubkeyHash::engine();
+        key.write_into(&mut engine);
+        PubkeyHash::from_engine(engine) == pubkey_hash
+    }).map(|(key, signature)| Builder::new().push_slice(signature).push_key(key).into_script())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use secp256k1::Secp256k1;
+
+    use blockdata::locktime::LockTime;
+    use blockdata::transaction::{OutPoint, TxIn};
+    use network::constants::Network::Prod;
+    use util::address::Address;
+    use util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+    use util::psbt::ExtendedKeySigner;
+
+    #[test]
+    fn test_roles() {
+        let secp = Secp256k1::new();
+        let first = ExtendedPrivKey::new_master(Prod, &[1u8; 32]).unwrap();
+        let second = ExtendedPrivKey::new_master(Prod, &[2u8; 32]).unwrap();
+        let path: DerivationPath = "m/0".parse().unwrap();
+        let first_key = ExtendedPubKey::from_private(&secp, &first.derive_priv(&secp, &path).unwrap()).public_key;
+        let second_key = ExtendedPubKey::from_private(&secp, &second.derive_priv(&secp, &path).unwrap()).public_key;
+
+        let multisig = Script::new_sorted_multisig(2, &[first_key, second_key]).unwrap();
+        let p2pkh = Address::p2pkh(&first_key, Prod).script_pubkey();
+        let spent_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut { value: 1000, script_pubkey: multisig.to_p2sh() },
+                TxOut { value: 1000, script_pubkey: p2pkh.clone() },
+            ],
+        };
+        let unsigned_tx = Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: (0..2).map(|vout| TxIn {
+                previous_output: OutPoint { txid: spent_tx.malfix_txid(), vout: vout },
+                ..Default::default()
+            }).collect(),
+            output: vec![TxOut { value: 1900, script_pubkey: p2pkh }],
+        };
+
+        let mut updater = Creator::new(unsigned_tx).unwrap().into_updater();
+        assert!(updater.add_spent_tx(0, Transaction { version: 2, ..spent_tx.clone() }).is_err());
+        assert!(updater.set_redeem_script(2, multisig.clone()).is_err());
+        for index in 0..2 {
+            updater.add_spent_tx(index, spent_tx.clone()).unwrap();
+            updater.add_input_key(index, first_key, (first.fingerprint(&secp), path.clone())).unwrap();
+        }
+        updater.set_redeem_script(0, multisig).unwrap();
+        updater.add_input_key(0, second_key, (second.fingerprint(&secp), path.clone())).unwrap();
+
+        // each signer signs the PSBT on its own, then they combine
+        let mut first_signer = updater.clone().into_signer();
+        assert_eq!(first_signer.sign(&mut ExtendedKeySigner::new(&secp, first)), Ok(2));
+        let mut second_signer = updater.into_signer();
+        assert_eq!(second_signer.sign(&mut ExtendedKeySigner::new(&secp, second)), Ok(1));
+
+        match second_signer.clone().into_extractor().extract() {
+            Err(Error::CannotFinalize(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let tx = first_signer.combine(second_signer).unwrap().into_extractor().extract().unwrap();
+        assert_eq!(tx.verify(|outpoint| spent_tx.output.get(outpoint.vout as usize).cloned()), Ok(()));
+    }
+}
@@ -19,10 +19,10 @@
 
 use std::io;
 
-use blockdata::script::Script;
+use blockdata::script::{ColorIdentifier, Script};
 use blockdata::transaction::{SigHashType, Transaction, TxOut};
 use consensus::encode::{self, serialize, Decodable};
-use util::bip32::{ChildNumber, DerivationPath, Fingerprint};
+use util::bip32::{ChildNumber, Fingerprint, KeySource};
 use util::key::PublicKey;
 use util::psbt;
 
@@ -42,6 +42,8 @@ pub trait Deserialize: Sized {
 impl_psbt_de_serialize!(Transaction);
 impl_psbt_de_serialize!(TxOut);
 impl_psbt_de_serialize!(Vec<Vec<u8>>); // scriptWitness
+impl_psbt_de_serialize!(ColorIdentifier);
+impl_psbt_de_serialize!(u64); // token amounts
 
 impl Serialize for Script {
     fn serialize(&self) -> Vec<u8> {
@@ -65,11 +67,11 @@ impl Serialize for PublicKey {
 
 impl Deserialize for PublicKey {
     fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
-        PublicKey::from_slice(bytes).map_err(|_| encode::Error::ParseFailed("invalid public key"))
+        PublicKey::from_slice(bytes).map_err(|_| encode::Error::Parse(encode::ParseError::Other("invalid public key")))
     }
 }
 
-impl Serialize for (Fingerprint, DerivationPath) {
+impl Serialize for KeySource {
     fn serialize(&self) -> Vec<u8> {
         let mut rv: Vec<u8> = Vec::with_capacity(4 + 4 * (self.1).as_ref().len());
 
@@ -83,7 +85,7 @@ impl Serialize for (Fingerprint, DerivationPath) {
     }
 }
 
-impl Deserialize for (Fingerprint, DerivationPath) {
+impl Deserialize for KeySource {
     fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
         if bytes.len() < 4 {
             return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
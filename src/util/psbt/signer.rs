@@ -0,0 +1,80 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! # PSBT Signers
+//!
+//! Signers produce the signatures of the inputs of a PSBT, whether their
+//! keys are held in memory, by an HSM or by a hardware wallet. For each key
+//! an input can be signed with, `PartiallySignedTransaction::sign` sends
+//! them a [SignRequest], and records the signatures they produce in the
+//! partial signatures of the input.
+//!
+
+use hashes::Hash;
+use secp256k1::{self, Secp256k1};
+
+use blockdata::transaction::SigHashType;
+use hash_types::SigHash;
+use util::bip32::{self, ExtendedPrivKey, Fingerprint, KeyOriginInfo};
+use util::key::PublicKey;
+
+/// A request to sign an input of a PSBT with a key
+#[derive(Clone, PartialEq, Debug)]
+pub struct SignRequest {
+    /// The index of the input
+    pub input_index: usize,
+    /// The hash to sign
+    pub sighash: SigHash,
+    /// The sighash type the signature commits to
+    pub sighash_type: SigHashType,
+    /// The key to sign with
+    pub public_key: PublicKey,
+    /// The origin of the key, if the PSBT records it
+    pub key_origin: Option<KeyOriginInfo>,
+}
+
+/// Produces the signatures of PSBT inputs
+pub trait Signer {
+    /// The error of a signer failing to sign
+    type Error;
+
+    /// Sign the sighash of `request` with its key, `None` if the key is not
+    /// held by this signer
+    fn sign(&mut self, request: &SignRequest) -> Result<Option<secp256k1::Signature>, Self::Error>;
+}
+
+/// A signer holding a master extended private key, which signs with the
+/// keys derived from it, identified by their key origins
+pub struct ExtendedKeySigner<'a, C: 'a> {
+    secp: &'a Secp256k1<C>,
+    master: ExtendedPrivKey,
+    fingerprint: Fingerprint,
+}
+
+impl<'a, C: secp256k1::Signing> ExtendedKeySigner<'a, C> {
+    /// Create a signer for the keys derived from `master`
+    pub fn new(secp: &'a Secp256k1<C>, master: ExtendedPrivKey) -> ExtendedKeySigner<'a, C> {
+        ExtendedKeySigner {
+            secp: secp,
+            master: master,
+            fingerprint: master.fingerprint(secp),
+        }
+    }
+}
+
+impl<'a, C: secp256k1::Signing> Signer for ExtendedKeySigner<'a, C> {
+    type Error = bip32::Error;
+
+    fn sign(&mut self, request: &SignRequest) -> Result<Option<secp256k1::Signature>, bip32::Error> {
+        let origin = match request.key_origin {
+            Some(ref origin) if origin.master_fingerprint == self.fingerprint => origin,
+            _ => return Ok(None),
+        };
+        let key = self.master.derive_priv(self.secp, &origin.path)?.private_key;
+        if key.public_key(self.secp).key != request.public_key.key {
+            return Ok(None);
+        }
+        Ok(Some(key.sign_low_r(self.secp, &request.sighash.into_inner())))
+    }
+}
@@ -0,0 +1,261 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! # Token PSBTs
+//!
+//! Templates of the PSBTs of transactions issuing and transferring tokens.
+//! Besides the colored outputs, they set the Tapyrus proprietary fields of
+//! the inputs and outputs holding tokens, so that signing devices can show
+//! which tokens are spent and sent.
+//!
+
+use blockdata::locktime::LockTime;
+use blockdata::script::{ColorIdentifier, Script, TokenTypes};
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use util::psbt::{Error, PartiallySignedTransaction};
+
+impl PartiallySignedTransaction {
+    /// Create the PSBT of a transaction issuing `amount` tokens of
+    /// `token_type` to `recipient`, an uncolored P2PKH or P2SH script. The
+    /// tokens are issued by spending `issuing_utxo`, an uncolored output
+    /// given as the transaction containing it and its index; the color of
+    /// reissuable tokens is the one of its script. The TPC it holds, but
+    /// `fee`, are sent back to its script.
+    pub fn new_token_issuance(
+        issuing_utxo: (Transaction, u32),
+        token_type: TokenTypes,
+        amount: u64,
+        recipient: &Script,
+        fee: u64,
+    ) -> Result<Self, Error> {
+        let (ref spent_tx, vout) = issuing_utxo;
+        let spent = spent_tx.output.get(vout as usize).ok_or(Error::MissingUtxo(0))?;
+        if spent.script_pubkey.is_colored() {
+            return Err(Error::UnsupportedScript(spent.script_pubkey.clone()));
+        }
+        let outpoint = OutPoint { txid: spent_tx.malfix_txid(), vout: vout };
+        let color_id = match token_type {
+            TokenTypes::Reissuable => ColorIdentifier::reissuable(spent.script_pubkey.clone()),
+            TokenTypes::NonReissuable => ColorIdentifier::non_reissuable(outpoint),
+            TokenTypes::Nft => ColorIdentifier::nft(outpoint),
+        };
+        // a single non fungible token is issued
+        if color_id.token_type == TokenTypes::Nft && amount != 1 {
+            return Err(Error::TokenImbalance(color_id));
+        }
+        let change = spent.value.checked_sub(fee).ok_or(Error::NegativeFee)?;
+
+        let mut outputs = vec![colored_output(recipient, &color_id, amount)?];
+        if change > 0 {
+            outputs.push(TxOut { value: change, script_pubkey: spent.script_pubkey.clone() });
+        }
+        new_token_psbt(vec![issuing_utxo], outputs)
+    }
+
+    /// Create the PSBT of a transaction sending `amount` tokens of
+    /// `color_id` to `recipient`, an uncolored P2PKH or P2SH script, by
+    /// spending `utxos`, outputs given as the transactions containing them
+    /// and their indexes. The utxos hold either TPC or tokens of `color_id`.
+    /// The tokens and TPC left, but `fee`, are sent to `change`, an
+    /// uncolored P2PKH or P2SH script.
+    pub fn new_token_transfer(
+        utxos: Vec<(Transaction, u32)>,
+        color_id: ColorIdentifier,
+        amount: u64,
+        recipient: &Script,
+        change: &Script,
+        fee: u64,
+    ) -> Result<Self, Error> {
+        let mut tokens = 0u64;
+        let mut tpc = 0u64;
+        for (index, &(ref tx, vout)) in utxos.iter().enumerate() {
+            let spent = tx.output.get(vout as usize).ok_or(Error::MissingUtxo(index))?;
+            let total = match spent.script_pubkey.color_id() {
+                None => &mut tpc,
+                Some(ref color) if *color == color_id => &mut tokens,
+                Some(_) => return Err(Error::TokenMismatch(index)),
+            };
+            *total = total.checked_add(spent.value).ok_or(Error::AmountOverflow)?;
+        }
+        let token_change = tokens.checked_sub(amount).ok_or_else(|| Error::TokenImbalance(color_id.clone()))?;
+        let tpc_change = tpc.checked_sub(fee).ok_or(Error::NegativeFee)?;
+
+        let mut outputs = vec![colored_output(recipient, &color_id, amount)?];
+        // checked even without token change, as TPC change must be uncolored
+        let colored_change = colored_output(change, &color_id, token_change)?;
+        if token_change > 0 {
+            outputs.push(colored_change);
+        }
+        if tpc_change > 0 {
+            outputs.push(TxOut { value: tpc_change, script_pubkey: change.clone() });
+        }
+        new_token_psbt(utxos, outputs)
+    }
+}
+
+/// The output sending `amount` tokens of `color_id` to `script_pubkey`
+fn colored_output(script_pubkey: &Script, color_id: &ColorIdentifier, amount: u64) -> Result<TxOut, Error> {
+    let colored = script_pubkey.add_color(color_id.clone())
+        .map_err(|_| Error::UnsupportedScript(script_pubkey.clone()))?;
+    Ok(TxOut { value: amount, script_pubkey: colored })
+}
+
+/// The PSBT of the transaction spending `utxos`, whose outputs are known to
+/// exist, to `outputs`, with the spent transactions and the fields of the
+/// tokens of the inputs and outputs set
+fn new_token_psbt(utxos: Vec<(Transaction, u32)>, outputs: Vec<TxOut>) -> Result<PartiallySignedTransaction, Error> {
+    let unsigned_tx = Transaction {
+        version: 1,
+        lock_time: LockTime::ZERO,
+        input: utxos.iter().map(|&(ref tx, vout)| TxIn {
+            previous_output: OutPoint { txid: tx.malfix_txid(), vout: vout },
+            ..Default::default()
+        }).collect(),
+        output: outputs,
+    };
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx)?;
+
+    for (input, (tx, vout)) in psbt.inputs.iter_mut().zip(utxos) {
+        let spent = tx.output[vout as usize].clone();
+        if let Some(color_id) = spent.script_pubkey.color_id() {
+            input.color_id = Some(color_id);
+            input.token_amount = Some(spent.value);
+        }
+        input.non_witness_utxo = Some(tx);
+    }
+    let txouts = psbt.global.unsigned_tx.output.iter();
+    for (output, txout) in psbt.outputs.iter_mut().zip(txouts) {
+        if let Some(color_id) = txout.script_pubkey.color_id() {
+            output.color_id = Some(color_id);
+            output.token_amount = Some(txout.value);
+        }
+    }
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::locktime::LockTime;
+    use blockdata::script::{ColorIdentifier, Script, TokenTypes};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use util::psbt::{Error, PartiallySignedTransaction};
+
+    fn funding_tx(outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: outputs,
+        }
+    }
+
+    #[test]
+    fn token_issuance() {
+        let issuer = hex_script!("76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac");
+        let recipient = hex_script!("a9143545e6e33b832c47050f24d3eeb93c9c03948bc787");
+        let tx = funding_tx(vec![TxOut { value: 1000, script_pubkey: issuer.clone() }]);
+
+        let psbt = PartiallySignedTransaction::new_token_issuance(
+            (tx.clone(), 0), TokenTypes::Reissuable, 500, &recipient, 100,
+        ).unwrap();
+        let color_id = ColorIdentifier::reissuable(issuer.clone());
+        let outputs = &psbt.global.unsigned_tx.output;
+        assert_eq!(outputs[0], TxOut { value: 500, script_pubkey: recipient.add_color(color_id.clone()).unwrap() });
+        assert_eq!(outputs[1], TxOut { value: 900, script_pubkey: issuer.clone() });
+        assert_eq!(psbt.outputs[0].color_id, Some(color_id));
+        assert_eq!(psbt.outputs[0].token_amount, Some(500));
+        assert_eq!(psbt.outputs[1].color_id, None);
+        assert_eq!(psbt.inputs[0].non_witness_utxo, Some(tx.clone()));
+        assert_eq!(psbt.fee().unwrap().as_sat(), 100);
+        assert!(psbt.sanity_check().is_ok());
+        let decoded: PartiallySignedTransaction = deserialize(&serialize(&psbt)).unwrap();
+        assert_eq!(decoded, psbt);
+
+        // the whole TPC amount is spent as fee
+        let psbt = PartiallySignedTransaction::new_token_issuance(
+            (tx.clone(), 0), TokenTypes::Nft, 1, &recipient, 1000,
+        ).unwrap();
+        let color_id = ColorIdentifier::nft(OutPoint { txid: tx.malfix_txid(), vout: 0 });
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.outputs[0].color_id, Some(color_id));
+        assert!(psbt.sanity_check().is_ok());
+
+        match PartiallySignedTransaction::new_token_issuance((tx.clone(), 0), TokenTypes::Nft, 2, &recipient, 100) {
+            Err(Error::TokenImbalance(..)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        match PartiallySignedTransaction::new_token_issuance((tx.clone(), 1), TokenTypes::Nft, 1, &recipient, 100) {
+            Err(Error::MissingUtxo(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        match PartiallySignedTransaction::new_token_issuance((tx.clone(), 0), TokenTypes::Nft, 1, &recipient, 1001) {
+            Err(Error::NegativeFee) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        match PartiallySignedTransaction::new_token_issuance((tx, 0), TokenTypes::Nft, 1, &Script::new(), 100) {
+            Err(Error::UnsupportedScript(..)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn token_transfer() {
+        let sender = hex_script!("76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac");
+        let recipient = hex_script!("a9143545e6e33b832c47050f24d3eeb93c9c03948bc787");
+        let color_id = ColorIdentifier::reissuable(sender.clone());
+        let other_color = ColorIdentifier::reissuable(recipient.clone());
+        let tx = funding_tx(vec![
+            TxOut { value: 300, script_pubkey: sender.add_color(color_id.clone()).unwrap() },
+            TxOut { value: 200, script_pubkey: sender.add_color(color_id.clone()).unwrap() },
+            TxOut { value: 1000, script_pubkey: sender.clone() },
+            TxOut { value: 10, script_pubkey: sender.add_color(other_color).unwrap() },
+        ]);
+        let utxos: Vec<_> = (0..3).map(|vout| (tx.clone(), vout)).collect();
+
+        let psbt = PartiallySignedTransaction::new_token_transfer(
+            utxos.clone(), color_id.clone(), 400, &recipient, &sender, 100,
+        ).unwrap();
+        let outputs = &psbt.global.unsigned_tx.output;
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0], TxOut { value: 400, script_pubkey: recipient.add_color(color_id.clone()).unwrap() });
+        assert_eq!(outputs[1], TxOut { value: 100, script_pubkey: sender.add_color(color_id.clone()).unwrap() });
+        assert_eq!(outputs[2], TxOut { value: 900, script_pubkey: sender.clone() });
+        assert_eq!(psbt.inputs[1].color_id, Some(color_id.clone()));
+        assert_eq!(psbt.inputs[1].token_amount, Some(200));
+        assert_eq!(psbt.inputs[2].color_id, None);
+        assert_eq!(psbt.outputs[1].token_amount, Some(100));
+        assert_eq!(psbt.outputs[2].token_amount, None);
+        assert!(psbt.sanity_check().is_ok());
+
+        let mut mismatch = psbt.clone();
+        mismatch.outputs[1].token_amount = Some(99);
+        match mismatch.check_tapyrus() {
+            Err(Error::OutputTokenMismatch(1)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // all tokens are sent, without change
+        let psbt = PartiallySignedTransaction::new_token_transfer(
+            utxos.clone(), color_id.clone(), 500, &recipient, &sender, 100,
+        ).unwrap();
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+
+        match PartiallySignedTransaction::new_token_transfer(utxos.clone(), color_id.clone(), 501, &recipient, &sender, 100) {
+            Err(Error::TokenImbalance(..)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        let mut mixed = utxos.clone();
+        mixed.push((tx, 3));
+        match PartiallySignedTransaction::new_token_transfer(mixed, color_id.clone(), 400, &recipient, &sender, 100) {
+            Err(Error::TokenMismatch(3)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        let colored = sender.add_color(color_id.clone()).unwrap();
+        match PartiallySignedTransaction::new_token_transfer(utxos, color_id, 400, &recipient, &colored, 100) {
+            Err(Error::UnsupportedScript(..)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
@@ -9,6 +9,8 @@ use hashes::{HmacEngine, HashEngine, Hmac, Hash};
 use hashes::sha256::Hash as SHA256;
 use secp256k1::SecretKey;
 
+use prelude::*;
+
 /// Generate nonce
 pub fn nonce_rfc6979(
     message: &[u8; 32],
@@ -0,0 +1,347 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Signature hashes
+//!
+//! Computes the hashes signed by the signatures of transaction inputs, as
+//! Tapyrus Core's `SignatureHash` does: over a copy of the transaction
+//! trimmed according to the sighash type, for every sighash type, `ALL`,
+//! `NONE` and `SINGLE` with or without `ANYONECANPAY`. `SighashCache` also
+//! computes the BIP143-style hashes, which commit to the amount spent by the
+//! input, for the scripts requiring them.
+//!
+
+use hashes::{Hash, HashEngine};
+use hash_types::SigHash;
+use blockdata::locktime::Sequence;
+use blockdata::script::Script;
+pub use blockdata::transaction::SigHashType;
+use blockdata::transaction::{Transaction, TxOut};
+use consensus::encode::{self, Encodable};
+use prelude::*;
+use VarInt;
+
+/// The sighash types, `ALL`, `NONE` and `SINGLE` without then with
+/// `ANYONECANPAY`
+pub const SIGHASH_TYPES: [SigHashType; 6] = [
+    SigHashType::All,
+    SigHashType::None,
+    SigHashType::Single,
+    SigHashType::AllPlusAnyoneCanPay,
+    SigHashType::NonePlusAnyoneCanPay,
+    SigHashType::SinglePlusAnyoneCanPay,
+];
+
+/// Compute the signature hash of an input of `tx`, as
+/// `Transaction::signature_hash` does. To sign several inputs, use a
+/// `SighashCache` instead.
+///
+/// # Panics
+/// Panics if `input_index` is greater than or equal to the number of inputs
+pub fn signature_hash(tx: &Transaction, input_index: usize, script_pubkey: &Script, sighash_u32: u32) -> SigHash {
+    SighashCache::new(tx).signature_hash(input_index, script_pubkey, sighash_u32)
+}
+
+/// Caches the parts of the signature hashes shared by all the inputs of a
+/// transaction, so that signing many inputs does not hash the whole
+/// transaction for each of them
+///
+/// The shared parts are computed on first use. For the sighashes to be
+/// valid, the transaction may not change except for its script_sigs and
+/// witnesses, which the borrow enforces.
+#[derive(Clone, Debug)]
+pub struct SighashCache<'tx> {
+    tx: &'tx Transaction,
+    /// Serialized outputs, signed by `SIGHASH_ALL` signatures
+    outputs: Option<Vec<u8>>,
+    /// BIP143 hash of the previous outputs of all the inputs
+    hash_prevouts: Option<SigHash>,
+    /// BIP143 hash of the sequence numbers of all the inputs
+    hash_sequence: Option<SigHash>,
+    /// BIP143 hash of all the outputs
+    hash_outputs: Option<SigHash>,
+}
+
+impl<'tx> SighashCache<'tx> {
+    /// Create a cache for the given transaction
+    pub fn new(tx: &'tx Transaction) -> SighashCache<'tx> {
+        SighashCache {
+            tx: tx,
+            outputs: None,
+            hash_prevouts: None,
+            hash_sequence: None,
+            hash_outputs: None,
+        }
+    }
+
+    /// The transaction whose inputs are signed
+    pub fn transaction(&self) -> &'tx Transaction {
+        self.tx
+    }
+
+    /// Compute the signature hash of an input, as
+    /// `Transaction::signature_hash` does, without cloning the transaction.
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to the number of
+    /// inputs
+    pub fn signature_hash(&mut self, input_index: usize, script_pubkey: &Script, sighash_u32: u32) -> SigHash {
+        assert!(input_index < self.tx.input.len());  // Panic on OOB
+
+        let (sighash, anyone_can_pay) = SigHashType::from_u32(sighash_u32).split_anyonecanpay_flag();
+
+        // The sighash_single bug, see `Transaction::signature_hash`
+        if sighash == SigHashType::Single && input_index >= self.tx.output.len() {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return SigHash::from_inner(one);
+        }
+
+        let tx = self.tx;
+        let mut enc = SigHash::engine();
+        tx.version.consensus_encode(&mut enc).unwrap();
+        if anyone_can_pay {
+            let input = &tx.input[input_index];
+            VarInt(1).consensus_encode(&mut enc).unwrap();
+            input.previous_output.consensus_encode(&mut enc).unwrap();
+            script_pubkey.consensus_encode(&mut enc).unwrap();
+            input.sequence.consensus_encode(&mut enc).unwrap();
+        } else {
+            VarInt(tx.input.len() as u64).consensus_encode(&mut enc).unwrap();
+            for (n, input) in tx.input.iter().enumerate() {
+                input.previous_output.consensus_encode(&mut enc).unwrap();
+                if n == input_index {
+                    script_pubkey.consensus_encode(&mut enc).unwrap();
+                    input.sequence.consensus_encode(&mut enc).unwrap();
+                } else {
+                    Script::new().consensus_encode(&mut enc).unwrap();
+                    let sequence = if sighash == SigHashType::All { input.sequence } else { Sequence::ZERO };
+                    sequence.consensus_encode(&mut enc).unwrap();
+                }
+            }
+        }
+        match sighash {
+            SigHashType::All => {
+                let outputs = self.outputs.get_or_insert_with(|| encode::serialize(&tx.output));
+                enc.input(outputs);
+            }
+            SigHashType::Single => {
+                // all outputs up to this input's one are signed, all but the
+                // last one erased
+                VarInt(input_index as u64 + 1).consensus_encode(&mut enc).unwrap();
+                for _ in 0..input_index {
+                    TxOut::default().consensus_encode(&mut enc).unwrap();
+                }
+                tx.output[input_index].consensus_encode(&mut enc).unwrap();
+            }
+            SigHashType::None => {
+                VarInt(0).consensus_encode(&mut enc).unwrap();
+            }
+            _ => unreachable!()
+        }
+        tx.lock_time.consensus_encode(&mut enc).unwrap();
+        sighash_u32.consensus_encode(&mut enc).unwrap();
+        SigHash::from_engine(enc)
+    }
+
+    /// Compute the BIP143 signature hash of an input spending `value`
+    /// tapyrus, for any sighash type
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to the number of
+    /// inputs
+    pub fn segwit_signature_hash(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: SigHashType,
+    ) -> SigHash {
+        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+        let tx = self.tx;
+        let txin = &tx.input[input_index];
+
+        let hash_prevouts = if anyone_can_pay {
+            SigHash::default()
+        } else {
+            *self.hash_prevouts.get_or_insert_with(|| {
+                let mut enc = SigHash::engine();
+                for txin in &tx.input {
+                    txin.previous_output.consensus_encode(&mut enc).unwrap();
+                }
+                SigHash::from_engine(enc)
+            })
+        };
+        let hash_sequence = if anyone_can_pay || sighash != SigHashType::All {
+            SigHash::default()
+        } else {
+            *self.hash_sequence.get_or_insert_with(|| {
+                let mut enc = SigHash::engine();
+                for txin in &tx.input {
+                    txin.sequence.consensus_encode(&mut enc).unwrap();
+                }
+                SigHash::from_engine(enc)
+            })
+        };
+        let hash_outputs = match sighash {
+            SigHashType::All => *self.hash_outputs.get_or_insert_with(|| {
+                let mut enc = SigHash::engine();
+                for txout in &tx.output {
+                    txout.consensus_encode(&mut enc).unwrap();
+                }
+                SigHash::from_engine(enc)
+            }),
+            SigHashType::Single if input_index < tx.output.len() => {
+                let mut enc = SigHash::engine();
+                tx.output[input_index].consensus_encode(&mut enc).unwrap();
+                SigHash::from_engine(enc)
+            }
+            _ => SigHash::default(),
+        };
+
+        let mut enc = SigHash::engine();
+        tx.version.consensus_encode(&mut enc).unwrap();
+        hash_prevouts.consensus_encode(&mut enc).unwrap();
+        hash_sequence.consensus_encode(&mut enc).unwrap();
+        txin.previous_output.consensus_encode(&mut enc).unwrap();
+        script_code.consensus_encode(&mut enc).unwrap();
+        value.consensus_encode(&mut enc).unwrap();
+        txin.sequence.consensus_encode(&mut enc).unwrap();
+        hash_outputs.consensus_encode(&mut enc).unwrap();
+        tx.lock_time.consensus_encode(&mut enc).unwrap();
+        sighash_type.as_u32().consensus_encode(&mut enc).unwrap();
+        SigHash::from_engine(enc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::Hash;
+    use hashes::hex::FromHex;
+
+    use hash_types::SigHash;
+    use blockdata::transaction::Transaction;
+    use consensus::encode::deserialize;
+    use super::*;
+
+    #[test]
+    fn sighash_test() {
+        // vectors of Tapyrus Core's sighash.json, one for each sighash type:
+        // transaction, script, input index, hash type, expected hash
+        let vectors = [
+            ("f2b539a401e4e8402869d5e1502dbc3156dbce93583f516a4947b333260d5af1a34810c6a00200000003525363ffffffff01d305e2000000000005acab535200a265fe77",
+             "", 0, -1435650456, "41617b27321a830c712638dbb156dae23d4ef181c7a06728ccbf3153ec53d7dd"),
+            ("2f7353dd02e395b0a4d16da0f7472db618857cd3de5b9e2789232952a9b154d249102245fd030000000151617fd88f103280b85b0a198198e438e7cab1a4c92ba58409709997cc7a65a619eb9eec3c0200000003636aabffffffff0397481c0200000000045300636a0dc97803000000000009d389030000000003ac6a53134007bb",
+             "0000536552526a", 0, -1912746174, "30c4cd4bd6b291f7e9489cc4b4440a083f93a7664ea1f93e77a9597dab8ded9c"),
+            ("6f62138301436f33a00b84a26a0457ccbfc0f82403288b9cbae39986b34357cb2ff9b889b302000000045253655335a7ff6701bac9960400000000086552ab656352635200000000",
+             "6aac51", 0, 1444414211, "502a2435fd02898d2ff3ab08a3c19078414b32ec9b73d64a944834efc9dae10c"),
+            ("c33028b301d5093e1e8397270d75a0b009b2a6509a01861061ab022ca122a6ba935b8513320200000000ffffffff013bcf5a0500000000015200000000",
+             "", 0, -513413204, "6b1459536f51482f5dbf42d7e561896557461e1e3b6bf67871e2b51faae2832c"),
+            ("32fa0b0804e6ea101e137665a041cc2350b794e59bf42d9b09088b01cde806ec1bbea077df0200000008515153650000006506a11c55904258fa418e57b88b12724b81153260d3f4c9f080439789a391ab147aabb0fa0000000007000052ac51ab510986f2a15c0d5e05d20dc876dd2dafa435276d53da7b47c393f20900e55f163b97ce0b800000000008ab526a520065636a8087df7d4d9c985fb42308fb09dce704650719140aa6050e8955fa5d2ea46b464a333f870000000009636300636a6565006affffffff01994a0d040000000002536500000000",
+             "516563530065", 2, -163068286, "f58637277d2bc42e18358dc55f7e87e7043f5e33f4ce1fc974e715ef0d3d1c2a"),
+            ("d3b7421e011f4de0f1cea9ba7458bf3486bee722519efab711a963fa8c100970cf7488b7bb0200000003525352dcd61b300148be5d05000000000000000000",
+             "535251536aac536a", 0, -1960128125, "29aa6d2d752d3310eba20442770ad345b7f6a35f96161ede5f07b33e92053e2a"),
+        ];
+
+        let mut sighash_types = Vec::new();
+        for &(tx, script, input_index, hash_type, expected) in vectors.iter() {
+            let tx: Transaction = deserialize(&Vec::<u8>::from_hex(tx).unwrap()).unwrap();
+            let script = Script::from(Vec::<u8>::from_hex(script).unwrap());
+            let mut raw_expected = Vec::<u8>::from_hex(expected).unwrap();
+            raw_expected.reverse();
+            let expected = SigHash::from_slice(&raw_expected).unwrap();
+
+            let mut cache = SighashCache::new(&tx);
+            assert_eq!(cache.signature_hash(input_index, &script, hash_type as u32), expected);
+            assert_eq!(signature_hash(&tx, input_index, &script, hash_type as u32), expected);
+            sighash_types.push(SigHashType::from_u32(hash_type as u32));
+        }
+        assert_eq!(sighash_types, SIGHASH_TYPES.to_vec());
+    }
+
+    #[test]
+    fn sighash_single_bug_test() {
+        // two inputs and a single output, so that SIGHASH_SINGLE has no
+        // output to sign for the second input, and signs 1 instead
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "a93e93440250f97012d466a6cc24839f572def241c814fe6ae94442cf58ea33eb0fdd9bcc1030000000600\
+             636a0065acffffffff5dee3a6e7e5ad6310dea3e5b3ddda1a56bf8de7d3b75889fc024b5e233ec10f80300\
+             000007ac53635253ab53ffffffff0160468b04000000000800526a5300ac526a00000000"
+        ).unwrap()).unwrap();
+        let script = hex_script!("ac00636a53");
+
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        let mut cache = SighashCache::new(&tx);
+        for &sighash_type in &[SigHashType::Single, SigHashType::SinglePlusAnyoneCanPay] {
+            assert_eq!(cache.signature_hash(1, &script, sighash_type.as_u32()), SigHash::from_inner(one));
+            assert!(cache.signature_hash(0, &script, sighash_type.as_u32()) != SigHash::from_inner(one));
+        }
+    }
+
+    #[test]
+    fn sighash_matches_transaction() {
+        // four inputs and outputs
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "a0aa3126041621a6dea5b800141aa696daf28408959dfb2df96095db9fa425ad3f427f2f6103000000015360\
+             290e9c6063fa26912c2e7fb6a0ad80f1c5fea1771d42f12976092e7a85a4229fdb6e890000000001abc109f6\
+             e47688ac0e4682988785744602b8c87228fcef0695085edf19088af1a9db126e93000000000665516aac536a\
+             ffffffff8fe53e0806e12dfd05d67ac68f4768fdbe23fc48ace22a5aa8ba04c96d58e2750300000009ac51ab\
+             ac63ab5153650524aa680455ce7b000000000000499e50030000000008636a00ac526563ac5051ee03000000\
+             0003abacabd2b6fe000000000003516563910fb6b5"
+        ).unwrap()).unwrap();
+        let script = hex_script!("76a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688ac");
+
+        let mut cache = SighashCache::new(&tx);
+        for input_index in 0..tx.input.len() {
+            // the standard types, and non-standard ones mapped to them
+            for &sighash_u32 in &[0x01, 0x02, 0x03, 0x81, 0x82, 0x83, 0x00, 0x1f, 0xad2b1f84] {
+                assert_eq!(
+                    cache.signature_hash(input_index, &script, sighash_u32),
+                    tx.signature_hash(input_index, &script, sighash_u32)
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn segwit_sighash_test() {
+        use util::bip143::SighashComponents;
+
+        // the BIP143 P2SH-P2WSH example, signed with each sighash type
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000\
+             ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f\
+             05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000"
+        ).unwrap()).unwrap();
+        let witness_script = hex_script!(
+            "56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28\
+             bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b\
+             9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58\
+             c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b1486\
+             2c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b\
+             56ae"
+        );
+        let value = 987654321;
+
+        let expected = [
+            "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c",
+            "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36",
+            "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea",
+            "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e",
+            "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a",
+            "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b",
+        ];
+        let mut cache = SighashCache::new(&tx);
+        for (&sighash_type, hash) in SIGHASH_TYPES.iter().zip(expected.iter()) {
+            let expected = hex_hash!(SigHash, hash);
+            assert_eq!(cache.segwit_signature_hash(0, &witness_script, value, sighash_type), expected);
+        }
+        assert_eq!(
+            cache.segwit_signature_hash(0, &witness_script, value, SigHashType::All),
+            SighashComponents::new(&tx).sighash_all(&tx.input[0], &witness_script, value)
+        );
+    }
+}
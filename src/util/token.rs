@@ -0,0 +1,530 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Token balances
+//!
+//! Outputs of Tapyrus transactions hold either TPC or an amount of the
+//! tokens of a color. This module provides [TokenBalances], the amounts
+//! held by a set of outputs for each color, TPC being a pseudo-color of
+//! its own.
+//!
+//! It also provides [IssuanceProof], the proof that tokens were issued by
+//! a transaction of a block signed by the federation, for auditors who do
+//! not hold the whole chain.
+//!
+
+use core::iter::FromIterator;
+use core::{fmt, ops};
+#[cfg(feature = "std")] use std::error;
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::script::{ColorIdentifier, Script, TokenTypes};
+use blockdata::transaction::{Transaction, TxIn, TxOut};
+use hash_types::{Txid, TxMerkleNode};
+use prelude::*;
+use util::key::PublicKey;
+use util::merkle::{MerkleBranch, MerkleTree};
+use util::signature;
+
+/// The kind of coins an output holds
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Color {
+    /// The native coin, TPC
+    Tpc,
+    /// The tokens of a color
+    Token(ColorIdentifier),
+}
+
+impl Color {
+    /// The color of the coins locked by `script_pubkey`
+    pub fn of(script_pubkey: &Script) -> Color {
+        match script_pubkey.color_id() {
+            Some(color_id) => Color::Token(color_id),
+            None => Color::Tpc,
+        }
+    }
+
+    /// The color identifier of tokens, `None` for TPC
+    pub fn color_id(&self) -> Option<&ColorIdentifier> {
+        match *self {
+            Color::Tpc => None,
+            Color::Token(ref color_id) => Some(color_id),
+        }
+    }
+}
+
+impl From<ColorIdentifier> for Color {
+    fn from(color_id: ColorIdentifier) -> Color {
+        Color::Token(color_id)
+    }
+}
+
+/// Amounts by color. Colors without any amount are not stored, so two
+/// balances are equal when they hold the same non-zero amounts.
+///
+/// Like [Amount](::util::amount::Amount), the operators panic on overflow
+/// and underflow, while the methods whose names start with `checked_` do
+/// not.
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+pub struct TokenBalances(BTreeMap<Color, u64>);
+
+impl TokenBalances {
+    /// Empty balances
+    pub fn new() -> TokenBalances {
+        TokenBalances(BTreeMap::new())
+    }
+
+    /// The balances of `outputs`, `None` if the total of a color overflows
+    pub fn from_outputs<'a, I>(outputs: I) -> Option<TokenBalances>
+        where I: IntoIterator<Item = &'a TxOut> {
+        let mut balances = TokenBalances::new();
+        for output in outputs {
+            balances.checked_add_amount(Color::of(&output.script_pubkey), output.value)?;
+        }
+        Some(balances)
+    }
+
+    /// The amount of `color`, 0 if there is none
+    pub fn get(&self, color: &Color) -> u64 {
+        self.0.get(color).cloned().unwrap_or(0)
+    }
+
+    /// The amount of TPC, in tapyrus
+    pub fn tpc(&self) -> u64 {
+        self.get(&Color::Tpc)
+    }
+
+    /// The amount of the tokens of `color_id`
+    pub fn token(&self, color_id: &ColorIdentifier) -> u64 {
+        // cloning a color identifier is cheap, no allocation is involved
+        self.get(&Color::Token(color_id.clone()))
+    }
+
+    /// The number of colors with a non-zero amount
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether there is no amount of any color
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over the colors and their amounts, TPC first
+    pub fn iter(&self) -> Iter {
+        Iter(self.0.iter())
+    }
+
+    /// Iterate over the tokens and their amounts, leaving TPC out
+    pub fn tokens<'a>(&'a self) -> impl Iterator<Item = (&'a ColorIdentifier, u64)> + 'a {
+        self.iter().filter_map(|(color, amount)| color.color_id().map(|color_id| (color_id, amount)))
+    }
+
+    /// Add `amount` of `color`. Panics on overflow.
+    pub fn add_amount(&mut self, color: Color, amount: u64) {
+        self.checked_add_amount(color, amount).expect("Addition error")
+    }
+
+    /// Subtract `amount` of `color`. Panics if there is less than `amount`.
+    pub fn sub_amount(&mut self, color: Color, amount: u64) {
+        self.checked_sub_amount(color, amount).expect("Subtraction error")
+    }
+
+    /// Add `amount` of `color`, leaving the balances unchanged and
+    /// returning `None` on overflow
+    pub fn checked_add_amount(&mut self, color: Color, amount: u64) -> Option<()> {
+        if amount == 0 {
+            return Some(());
+        }
+        let total = self.get(&color).checked_add(amount)?;
+        self.0.insert(color, total);
+        Some(())
+    }
+
+    /// Subtract `amount` of `color`, leaving the balances unchanged and
+    /// returning `None` if there is less than `amount`
+    pub fn checked_sub_amount(&mut self, color: Color, amount: u64) -> Option<()> {
+        if amount == 0 {
+            return Some(());
+        }
+        let total = self.get(&color).checked_sub(amount)?;
+        if total == 0 {
+            self.0.remove(&color);
+        } else {
+            self.0.insert(color, total);
+        }
+        Some(())
+    }
+
+    /// Checked addition of all the amounts of `other`.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_add(&self, other: &TokenBalances) -> Option<TokenBalances> {
+        let mut ret = self.clone();
+        for (color, amount) in other.iter() {
+            ret.checked_add_amount(color.clone(), amount)?;
+        }
+        Some(ret)
+    }
+
+    /// Checked subtraction of all the amounts of `other`.
+    /// Returns [None] if a color has less than its amount in `other`.
+    pub fn checked_sub(&self, other: &TokenBalances) -> Option<TokenBalances> {
+        let mut ret = self.clone();
+        for (color, amount) in other.iter() {
+            ret.checked_sub_amount(color.clone(), amount)?;
+        }
+        Some(ret)
+    }
+}
+
+/// Iterator over the colors of [TokenBalances] and their amounts
+pub struct Iter<'a>(btree_map::Iter<'a, Color, u64>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Color, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(color, amount)| (color, *amount))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> IntoIterator for &'a TokenBalances {
+    type Item = (&'a Color, u64);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for TokenBalances {
+    type Item = (Color, u64);
+    type IntoIter = btree_map::IntoIter<Color, u64>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Panics if the total of a color overflows
+impl FromIterator<(Color, u64)> for TokenBalances {
+    fn from_iter<I: IntoIterator<Item = (Color, u64)>>(iter: I) -> TokenBalances {
+        let mut balances = TokenBalances::new();
+        balances.extend(iter);
+        balances
+    }
+}
+
+impl Extend<(Color, u64)> for TokenBalances {
+    fn extend<I: IntoIterator<Item = (Color, u64)>>(&mut self, iter: I) {
+        for (color, amount) in iter {
+            self.add_amount(color, amount);
+        }
+    }
+}
+
+impl<'a> ops::Add<&'a TokenBalances> for TokenBalances {
+    type Output = TokenBalances;
+
+    fn add(self, rhs: &'a TokenBalances) -> Self::Output {
+        self.checked_add(rhs).expect("Addition error")
+    }
+}
+
+impl<'a> ops::AddAssign<&'a TokenBalances> for TokenBalances {
+    fn add_assign(&mut self, rhs: &'a TokenBalances) {
+        *self = self.checked_add(rhs).expect("Addition error")
+    }
+}
+
+impl<'a> ops::Sub<&'a TokenBalances> for TokenBalances {
+    type Output = TokenBalances;
+
+    fn sub(self, rhs: &'a TokenBalances) -> Self::Output {
+        self.checked_sub(rhs).expect("Subtraction error")
+    }
+}
+
+impl<'a> ops::SubAssign<&'a TokenBalances> for TokenBalances {
+    fn sub_assign(&mut self, rhs: &'a TokenBalances) {
+        *self = self.checked_sub(rhs).expect("Subtraction error")
+    }
+}
+
+/// An error verifying an [IssuanceProof]
+#[derive(Debug)]
+pub enum IssuanceProofError {
+    /// The block is not signed by the aggregate public key
+    InvalidBlockProof(signature::Error),
+    /// The merkle branch does not prove the issuance transaction to be in
+    /// the block
+    NotInBlock(Txid),
+    /// The transaction does not issue tokens of this color
+    NotIssuance(ColorIdentifier),
+}
+
+impl fmt::Display for IssuanceProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IssuanceProofError::InvalidBlockProof(ref e) => write!(f, "invalid block proof: {}", e),
+            IssuanceProofError::NotInBlock(ref txid) => write!(f, "transaction {} is not in the block", txid),
+            IssuanceProofError::NotIssuance(ref color_id) => write!(f, "transaction does not issue {}", color_id),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for IssuanceProofError {
+    fn description(&self) -> &str {
+        match *self {
+            IssuanceProofError::InvalidBlockProof(_) => "invalid block proof",
+            IssuanceProofError::NotInBlock(_) => "transaction is not in the block",
+            IssuanceProofError::NotIssuance(_) => "transaction does not issue the tokens",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            IssuanceProofError::InvalidBlockProof(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The proof that a transaction issuing tokens is in a block signed by the
+/// federation: the header of the block and the merkle branch of the
+/// transaction, along with the transaction itself.
+///
+/// Reissuable tokens are identified by the script of the outputs spent to
+/// issue them, so their proofs also hold the transaction of the spent
+/// output. Non-reissuable tokens and NFTs are identified by the spent
+/// outpoint, which the issuance transaction already tells.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IssuanceProof {
+    /// The header of the block
+    pub header: BlockHeader,
+    /// The merkle branch of the txid of the issuance transaction
+    pub merkle_branch: MerkleBranch<TxMerkleNode>,
+    /// The issuance transaction
+    pub issuance_tx: Transaction,
+    /// The transaction of the output spent to issue reissuable tokens
+    pub spent_tx: Option<Transaction>,
+}
+impl_consensus_encoding!(IssuanceProof, header, merkle_branch, issuance_tx, spent_tx);
+serde_consensus_impl!(IssuanceProof);
+
+impl IssuanceProof {
+    /// Build the proof of the issuance by the transaction `issuance_txid`
+    /// of `block`, or `None` if the block does not hold it. `spent_tx` is
+    /// the transaction of the output spent to issue reissuable tokens.
+    pub fn from_block(block: &Block, issuance_txid: &Txid, spent_tx: Option<Transaction>) -> Option<IssuanceProof> {
+        let txids: Vec<TxMerkleNode> = block.txdata.iter().map(|tx| tx.txid().as_hash().into()).collect();
+        let index = txids.iter().position(|txid| *txid == issuance_txid.as_hash().into())?;
+        Some(IssuanceProof {
+            header: block.header.clone(),
+            merkle_branch: MerkleTree::new(txids).branch(index).expect("the index of a leaf"),
+            issuance_tx: block.txdata[index].clone(),
+            spent_tx: spent_tx,
+        })
+    }
+
+    /// Verify that the issuance transaction issues tokens of `color_id`,
+    /// and that it is in a block signed by `aggregated_pubkey`, the key of
+    /// the federation at the height of the block, as told by an
+    /// [AggregatePubkeyTracker](::blockdata::aggkey::AggregatePubkeyTracker)
+    pub fn verify(&self, color_id: &ColorIdentifier, aggregated_pubkey: &PublicKey) -> Result<(), IssuanceProofError> {
+        self.header.verify_proof(aggregated_pubkey).map_err(IssuanceProofError::InvalidBlockProof)?;
+        let txid = self.issuance_tx.txid();
+        if !self.merkle_branch.verify(txid.as_hash().into(), &self.header.merkle_root) {
+            return Err(IssuanceProofError::NotInBlock(txid));
+        }
+
+        let colored = self.issuance_tx.output.iter().any(|output| output.script_pubkey.color_id().as_ref() == Some(color_id));
+        if !colored || !self.issuance_tx.input.iter().any(|txin| self.issues(txin, color_id)) {
+            return Err(IssuanceProofError::NotIssuance(color_id.clone()));
+        }
+        Ok(())
+    }
+
+    /// Whether spending the output of `txin` issues tokens of `color_id`
+    fn issues(&self, txin: &TxIn, color_id: &ColorIdentifier) -> bool {
+        let outpoint = txin.previous_output;
+        match color_id.token_type {
+            TokenTypes::Reissuable => self.spent_tx.as_ref()
+                .filter(|spent_tx| spent_tx.malfix_txid() == outpoint.txid)
+                .and_then(|spent_tx| spent_tx.output.get(outpoint.vout as usize))
+                .map_or(false, |spent| ColorIdentifier::reissuable(spent.script_pubkey.clone()) == *color_id),
+            TokenTypes::NonReissuable => ColorIdentifier::non_reissuable(outpoint) == *color_id,
+            TokenTypes::Nft => ColorIdentifier::nft(outpoint) == *color_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, IssuanceProof, IssuanceProofError, TokenBalances};
+
+    use hashes::Hash;
+    use secp256k1::Secp256k1;
+
+    use blockdata::block::{Block, BlockHeader, XField};
+    use blockdata::locktime::LockTime;
+    use blockdata::script::{ColorIdentifier, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use hex::decode as hex_decode;
+    use util::key::PrivateKey;
+    use util::signature::Signature;
+
+    fn colors() -> (Color, Color) {
+        let a = ColorIdentifier::reissuable(Script::from(vec![0x51]));
+        let b = ColorIdentifier::reissuable(Script::from(vec![0x52]));
+        (Color::Token(a), Color::Token(b))
+    }
+
+    #[test]
+    fn test_balances() {
+        let (a, b) = colors();
+        let mut balances = TokenBalances::new();
+        assert!(balances.is_empty());
+        balances.add_amount(Color::Tpc, 10);
+        balances.add_amount(a.clone(), 5);
+        balances.add_amount(a.clone(), 5);
+        balances.add_amount(b.clone(), 0);
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances.tpc(), 10);
+        assert_eq!(balances.get(&a), 10);
+        assert_eq!(balances.token(a.color_id().unwrap()), 10);
+        assert_eq!(balances.get(&b), 0);
+        assert_eq!(balances.iter().next(), Some((&Color::Tpc, 10)));
+        assert_eq!(balances.tokens().collect::<Vec<_>>(), vec![(a.color_id().unwrap(), 10)]);
+
+        assert_eq!(balances.checked_sub_amount(a.clone(), 11), None);
+        assert_eq!(balances.get(&a), 10);
+        balances.sub_amount(a.clone(), 10);
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances.checked_add_amount(Color::Tpc, u64::max_value()), None);
+        assert_eq!(balances.tpc(), 10);
+    }
+
+    #[test]
+    fn test_balances_arithmetic() {
+        let (a, b) = colors();
+        let x: TokenBalances = vec![(Color::Tpc, 10), (a.clone(), 3)].into_iter().collect();
+        let y: TokenBalances = vec![(a.clone(), 3), (b.clone(), 7)].into_iter().collect();
+
+        let sum = x.clone() + &y;
+        assert_eq!(sum, vec![(Color::Tpc, 10), (a.clone(), 6), (b.clone(), 7)].into_iter().collect::<TokenBalances>());
+        assert_eq!(sum.clone() - &y, x);
+        assert_eq!(x.checked_sub(&y), None);
+        assert_eq!(sum.checked_sub(&sum), Some(TokenBalances::new()));
+
+        let max: TokenBalances = vec![(b.clone(), u64::max_value())].into_iter().collect();
+        assert_eq!(y.checked_add(&max), None);
+    }
+
+    #[test]
+    fn test_balances_from_outputs() {
+        let p2pkh = Script::from(hex_decode("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac").unwrap());
+        let color_id = ColorIdentifier::reissuable(p2pkh.clone());
+        let colored = p2pkh.add_color(color_id.clone()).unwrap();
+        let outputs = vec![
+            TxOut { value: 1000, script_pubkey: p2pkh.clone() },
+            TxOut { value: 20, script_pubkey: colored.clone() },
+            TxOut { value: 500, script_pubkey: p2pkh },
+            TxOut { value: 30, script_pubkey: colored.clone() },
+        ];
+        let balances = TokenBalances::from_outputs(&outputs).unwrap();
+        assert_eq!(balances.tpc(), 1500);
+        assert_eq!(balances.token(&color_id), 50);
+        assert_eq!(Color::of(&colored), Color::from(color_id));
+
+        let overflow = vec![
+            TxOut { value: u64::max_value(), script_pubkey: colored.clone() },
+            TxOut { value: 1, script_pubkey: colored },
+        ];
+        assert_eq!(TokenBalances::from_outputs(&overflow), None);
+    }
+
+    #[test]
+    fn test_issuance_proof() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif("5HxWvvfubhXpYYpS3tJkw6fq9jE9j18THftkZjHHfmFiWtmAbrj").unwrap();
+        let pk = key.public_key(&secp);
+
+        let p2pkh = Script::from(hex_decode("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac").unwrap());
+        let tx = |input: Vec<OutPoint>, output: Vec<TxOut>| Transaction {
+            version: 1,
+            lock_time: LockTime::ZERO,
+            input: input.into_iter().map(|outpoint| TxIn { previous_output: outpoint, ..Default::default() }).collect(),
+            output: output,
+        };
+        let funding = tx(vec![OutPoint::default()], vec![TxOut { value: 1000, script_pubkey: p2pkh.clone() }; 2]);
+        let outpoint = |vout| OutPoint { txid: funding.malfix_txid(), vout: vout };
+        let reissuable = ColorIdentifier::reissuable(p2pkh.clone());
+        let non_reissuable = ColorIdentifier::non_reissuable(outpoint(1));
+        let issuance = tx(vec![outpoint(0), outpoint(1)], vec![
+            TxOut { value: 100, script_pubkey: p2pkh.add_color(reissuable.clone()).unwrap() },
+            TxOut { value: 200, script_pubkey: p2pkh.add_color(non_reissuable.clone()).unwrap() },
+        ]);
+
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                im_merkle_root: Default::default(),
+                time: 0,
+                xfield: XField::None,
+                proof: None,
+            },
+            txdata: vec![funding.clone(), issuance.clone(), tx(vec![outpoint(0)], vec![])],
+        };
+        block.header.merkle_root = block.compute_merkle_root();
+        block.header.im_merkle_root = block.compute_immutable_merkle_root();
+        block.header.proof = Some(Signature::sign(&key, &block.header.signature_hash().into_inner()).unwrap());
+
+        assert!(IssuanceProof::from_block(&block, &Transaction { version: 2, ..issuance.clone() }.txid(), None).is_none());
+        let proof = IssuanceProof::from_block(&block, &issuance.txid(), Some(funding.clone())).unwrap();
+        assert_eq!(deserialize::<IssuanceProof>(&serialize(&proof)).unwrap(), proof);
+        assert!(proof.verify(&reissuable, &pk).is_ok());
+        assert!(proof.verify(&non_reissuable, &pk).is_ok());
+        match proof.verify(&ColorIdentifier::nft(outpoint(1)), &pk) {
+            Err(IssuanceProofError::NotIssuance(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        let other_key = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        match proof.verify(&reissuable, &other_key.public_key(&secp)) {
+            Err(IssuanceProofError::InvalidBlockProof(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // reissuable tokens can not be proven without the spent transaction
+        let without_spent = IssuanceProof { spent_tx: None, ..proof.clone() };
+        assert_eq!(deserialize::<IssuanceProof>(&serialize(&without_spent)).unwrap(), without_spent);
+        assert!(without_spent.verify(&non_reissuable, &pk).is_ok());
+        match without_spent.verify(&reissuable, &pk) {
+            Err(IssuanceProofError::NotIssuance(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // the merkle path must prove the issuance transaction
+        let other = IssuanceProof { issuance_tx: block.txdata[2].clone(), ..proof.clone() };
+        match other.verify(&non_reissuable, &pk) {
+            Err(IssuanceProofError::NotInBlock(txid)) => assert_eq!(txid, block.txdata[2].txid()),
+            r => panic!("unexpected {:?}", r),
+        }
+        let mut tampered = proof.clone();
+        tampered.merkle_branch.index = 0;
+        match tampered.verify(&non_reissuable, &pk) {
+            Err(IssuanceProofError::NotInBlock(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(proof.merkle_branch.index, 1);
+    }
+}
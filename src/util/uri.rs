@@ -0,0 +1,427 @@
+// Copyright (c) 2020 Chaintope Inc.
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+//! Payment URIs
+//!
+//! BIP21-style URIs requesting a payment to an address, with the
+//! `tapyrus:` scheme, e.g.
+//! `tapyrus:1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY?amount=0.5&label=Luke-Jr`.
+//!
+//! Token payments are requested with the `colorid` parameter, the hex
+//! encoded color identifier of the tokens, or with a colored address. Their
+//! `amount` is then a whole number of tokens rather than a decimal amount
+//! of TPC, and is parsed into `token_amount` rather than `amount`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::str::FromStr;
+//! use tapyrus::util::uri::Uri;
+//!
+//! let uri = Uri::from_str("tapyrus:1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY?amount=0.5&message=Donation").unwrap();
+//! assert_eq!(uri.amount.unwrap().as_sat(), 50_000_000);
+//! assert_eq!(uri.message.as_ref().unwrap(), "Donation");
+//! ```
+//!
+
+use std::{error, fmt};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use blockdata::script::{ColorIdentifier, ParseColorIdentifierError};
+use util::address::{self, Address};
+use util::amount::{Amount, Denomination, ParseAmountError};
+
+/// The scheme of payment URIs
+pub const SCHEME: &str = "tapyrus";
+
+/// An error in parsing a payment URI
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The URI does not start with `tapyrus:`
+    InvalidScheme,
+    /// Invalid address
+    Address(address::Error),
+    /// Invalid amount
+    Amount(ParseAmountError),
+    /// Invalid token amount, which must be a whole number of tokens
+    TokenAmount(ParseIntError),
+    /// Invalid color identifier
+    ColorId(ParseColorIdentifierError),
+    /// The color identifier differs from the one of the colored address
+    ColorMismatch,
+    /// Invalid percent-encoding, or a value which is not valid UTF-8
+    InvalidEncoding,
+    /// A parameter appears more than once
+    DuplicateParameter(String),
+    /// A `req-` parameter this implementation does not know
+    UnknownRequiredParameter(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidScheme => write!(f, "URI scheme is not {}", SCHEME),
+            Error::Address(ref e) => write!(f, "invalid address: {}", e),
+            Error::Amount(ref e) => write!(f, "invalid amount: {}", e),
+            Error::TokenAmount(ref e) => write!(f, "invalid token amount: {}", e),
+            Error::ColorId(ref e) => write!(f, "invalid color identifier: {}", e),
+            Error::ColorMismatch => f.write_str("color identifier does not match the address"),
+            Error::InvalidEncoding => f.write_str("invalid percent-encoding"),
+            Error::DuplicateParameter(ref p) => write!(f, "duplicate parameter: {}", p),
+            Error::UnknownRequiredParameter(ref p) => write!(f, "unknown required parameter: {}", p),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Address(ref e) => Some(e),
+            Error::Amount(ref e) => Some(e),
+            Error::TokenAmount(ref e) => Some(e),
+            Error::ColorId(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        "payment URI error"
+    }
+}
+
+#[doc(hidden)]
+impl From<address::Error> for Error {
+    fn from(e: address::Error) -> Error {
+        Error::Address(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<ParseAmountError> for Error {
+    fn from(e: ParseAmountError) -> Error {
+        Error::Amount(e)
+    }
+}
+
+/// A payment URI
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Uri {
+    /// The address to pay to
+    pub address: Address,
+    /// The amount of TPC requested, only for payments without `color_id`
+    pub amount: Option<Amount>,
+    /// The number of tokens of `color_id` requested
+    pub token_amount: Option<u64>,
+    /// A label for the address, e.g. the name of the recipient
+    pub label: Option<String>,
+    /// A message describing the payment
+    pub message: Option<String>,
+    /// The color of the tokens requested, `None` for TPC
+    pub color_id: Option<ColorIdentifier>,
+    /// The optional parameters this implementation does not know, in order
+    pub other_params: Vec<(String, String)>,
+}
+
+impl Uri {
+    /// A URI requesting a payment to `address`. The color of colored
+    /// addresses is that of the payment.
+    pub fn new(address: Address) -> Uri {
+        let color_id = address.color_id().cloned();
+        Uri {
+            address: address,
+            amount: None,
+            token_amount: None,
+            label: None,
+            message: None,
+            color_id: color_id,
+            other_params: vec![],
+        }
+    }
+
+    /// Request `amount` of TPC
+    pub fn with_amount(mut self, amount: Amount) -> Uri {
+        self.amount = Some(amount);
+        self
+    }
+
+    /// Request `amount` tokens of `color_id`
+    pub fn with_token_amount(mut self, color_id: ColorIdentifier, amount: u64) -> Uri {
+        self.color_id = Some(color_id);
+        self.token_amount = Some(amount);
+        self
+    }
+
+    /// Set the label
+    pub fn with_label<S: Into<String>>(mut self, label: S) -> Uri {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Set the message
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> Uri {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Request the tokens of `color_id`
+    pub fn with_color_id(mut self, color_id: ColorIdentifier) -> Uri {
+        self.color_id = Some(color_id);
+        self
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", SCHEME, self.address)?;
+        let mut separator = '?';
+        let mut param = |f: &mut fmt::Formatter, name: &str, value: &str| {
+            write!(f, "{}", separator)?;
+            separator = '&';
+            percent_encode(f, name)?;
+            f.write_str("=")?;
+            percent_encode(f, value)
+        };
+        match (&self.color_id, self.amount, self.token_amount) {
+            (&Some(_), _, Some(amount)) => param(f, "amount", &amount.to_string())?,
+            (&None, Some(amount), _) => param(f, "amount", &amount.to_string_in(Denomination::Tpc))?,
+            _ => {}
+        }
+        if let Some(ref label) = self.label {
+            param(f, "label", label)?;
+        }
+        if let Some(ref message) = self.message {
+            param(f, "message", message)?;
+        }
+        // colored addresses carry their color, a different one is written
+        // out even though it makes an invalid URI
+        if let Some(ref color_id) = self.color_id {
+            if self.address.color_id() != Some(color_id) {
+                param(f, "colorid", &color_id.to_string())?;
+            }
+        }
+        for &(ref name, ref value) in &self.other_params {
+            param(f, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Uri {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Uri, Error> {
+        let colon = s.find(':').ok_or(Error::InvalidScheme)?;
+        if !s[..colon].eq_ignore_ascii_case(SCHEME) {
+            return Err(Error::InvalidScheme);
+        }
+        let rest = &s[colon + 1..];
+        let (address, query) = match rest.find('?') {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+            None => (rest, None),
+        };
+        let mut uri = Uri::new(Address::from_str(address)?);
+
+        let mut amount = None;
+        let mut color_id = None;
+        for pair in query.into_iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            let (name, value) = match pair.find('=') {
+                Some(pos) => (percent_decode(&pair[..pos])?, percent_decode(&pair[pos + 1..])?),
+                None => (percent_decode(pair)?, String::new()),
+            };
+            let duplicate = match &name[..] {
+                "amount" => amount.replace(value).is_some(),
+                "label" => uri.label.replace(value).is_some(),
+                "message" => uri.message.replace(value).is_some(),
+                "colorid" => color_id.replace(value).is_some(),
+                _ if name.starts_with("req-") => {
+                    return Err(Error::UnknownRequiredParameter(name.clone()));
+                }
+                _ => {
+                    uri.other_params.push((name.clone(), value));
+                    false
+                }
+            };
+            if duplicate {
+                return Err(Error::DuplicateParameter(name));
+            }
+        }
+
+        if let Some(color_id) = color_id {
+            let color_id = ColorIdentifier::from_str(&color_id).map_err(Error::ColorId)?;
+            match uri.color_id {
+                Some(ref address_color) if *address_color != color_id => return Err(Error::ColorMismatch),
+                _ => uri.color_id = Some(color_id),
+            }
+        }
+        if let Some(amount) = amount {
+            if uri.color_id.is_some() {
+                uri.token_amount = Some(u64::from_str(&amount).map_err(Error::TokenAmount)?);
+            } else {
+                uri.amount = Some(Amount::from_str_in(&amount, Denomination::Tpc)?);
+            }
+        }
+        Ok(uri)
+    }
+}
+
+/// Write `s`, percent-encoding all but the unreserved characters of RFC 3986
+fn percent_encode(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                write!(f, "{}", byte as char)?
+            }
+            _ => write!(f, "%{:02X}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+/// Decode the percent-encoded UTF-8 string `s`
+fn percent_decode(s: &str) -> Result<String, Error> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [iter.next(), iter.next()];
+        match (hex_digit(hex[0]), hex_digit(hex[1])) {
+            (Some(high), Some(low)) => bytes.push(high << 4 | low),
+            _ => return Err(Error::InvalidEncoding),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidEncoding)
+}
+
+fn hex_digit(c: Option<u8>) -> Option<u8> {
+    (c? as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{Error, Uri};
+
+    use blockdata::script::{ColorIdentifier, Script};
+    use network::constants::Network;
+    use util::address::Address;
+    use util::amount::Amount;
+
+    const ADDRESS: &str = "1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhY";
+
+    fn color_id() -> ColorIdentifier {
+        ColorIdentifier::reissuable(Script::from(vec![0x51]))
+    }
+
+    #[test]
+    fn test_parse() {
+        let uri = Uri::from_str(ADDRESS).map(|_| ());
+        assert_eq!(uri, Err(Error::InvalidScheme));
+        assert_eq!(Uri::from_str(&format!("bitcoin:{}", ADDRESS)), Err(Error::InvalidScheme));
+
+        let uri = Uri::from_str(&format!("TAPYRUS:{}", ADDRESS)).unwrap();
+        assert_eq!(uri, Uri::new(Address::from_str(ADDRESS).unwrap()));
+
+        let uri = Uri::from_str(&format!(
+            "tapyrus:{}?amount=20.3&label=Luke-Jr&message=Donation%20for%20project%20xyz&foo=bar",
+            ADDRESS
+        )).unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(20_3000_0000)));
+        assert_eq!(uri.label.as_ref().unwrap(), "Luke-Jr");
+        assert_eq!(uri.message.as_ref().unwrap(), "Donation for project xyz");
+        assert_eq!(uri.other_params, vec![("foo".to_owned(), "bar".to_owned())]);
+        assert_eq!(uri.color_id, None);
+
+        let uri = Uri::from_str(&format!("tapyrus:{}?label=%E3%81%82", ADDRESS)).unwrap();
+        assert_eq!(uri.label.as_ref().unwrap(), "\u{3042}");
+
+        // parameter names are percent-decoded too
+        let uri = Uri::from_str(&format!("tapyrus:{}?%6Cabel=a&f%20o=b", ADDRESS)).unwrap();
+        assert_eq!(uri.label.as_ref().unwrap(), "a");
+        assert_eq!(uri.other_params, vec![("f o".to_owned(), "b".to_owned())]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |s: &str| Uri::from_str(&format!("tapyrus:{}?{}", ADDRESS, s)).unwrap_err();
+        match err("amount=abc") {
+            Error::Amount(_) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(err("amount=1&amount=2"), Error::DuplicateParameter("amount".to_owned()));
+        assert_eq!(err("req-somethingyoudontunderstand=50"),
+                   Error::UnknownRequiredParameter("req-somethingyoudontunderstand".to_owned()));
+        assert_eq!(err("label=%E3%81"), Error::InvalidEncoding);
+        assert_eq!(err("label=%4"), Error::InvalidEncoding);
+        match err("colorid=c1") {
+            Error::ColorId(_) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match Uri::from_str("tapyrus:1QJVDzdqb1VpbDK7uDeyVXy9mR27CJiyhZ") {
+            Err(Error::Address(_)) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_token_payment() {
+        let address = Address::from_str(ADDRESS).unwrap();
+        let uri = Uri::from_str(&format!("tapyrus:{}?amount=150&colorid={}", ADDRESS, color_id())).unwrap();
+        assert_eq!(uri.color_id, Some(color_id()));
+        assert_eq!(uri.token_amount, Some(150));
+        assert_eq!(uri.amount, None);
+        assert_eq!(uri, Uri::new(address.clone()).with_token_amount(color_id(), 150));
+
+        // whole tokens only
+        let uri = format!("tapyrus:{}?amount=1.5&colorid={}", ADDRESS, color_id());
+        match Uri::from_str(&uri) {
+            Err(Error::TokenAmount(_)) => {}
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        // colored addresses carry their color
+        let colored = Address::cp2sh(&Script::from(vec![0x51]), &color_id(), Network::Prod);
+        let uri = Uri::from_str(&format!("tapyrus:{}?amount=7", colored)).unwrap();
+        assert_eq!(uri.color_id, Some(color_id()));
+        assert_eq!(uri.token_amount, Some(7));
+        let other = ColorIdentifier::reissuable(Script::from(vec![0x52]));
+        assert_eq!(Uri::from_str(&format!("tapyrus:{}?colorid={}", colored, other)), Err(Error::ColorMismatch));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let address = Address::from_str(ADDRESS).unwrap();
+        let uri = Uri::new(address.clone())
+            .with_amount(Amount::from_sat(50_000_000))
+            .with_label("Luke-Jr")
+            .with_message("Donation for project xyz & co");
+        let s = uri.to_string();
+        assert_eq!(s, format!("tapyrus:{}?amount=0.50000000&label=Luke-Jr&message=Donation%20for%20project%20xyz%20%26%20co", ADDRESS));
+        assert_eq!(Uri::from_str(&s).unwrap(), uri);
+
+        let uri = Uri::new(address.clone()).with_token_amount(color_id(), 3);
+        assert_eq!(uri.to_string(), format!("tapyrus:{}?amount=3&colorid={}", ADDRESS, color_id()));
+        assert_eq!(Uri::from_str(&uri.to_string()).unwrap(), uri);
+
+        let mut uri = Uri::new(address);
+        uri.other_params.push(("a&b=c".to_owned(), "d".to_owned()));
+        assert_eq!(uri.to_string(), format!("tapyrus:{}?a%26b%3Dc=d", ADDRESS));
+        assert_eq!(Uri::from_str(&uri.to_string()).unwrap(), uri);
+
+        let colored = Address::cp2pkh(&"03df154ebfcf29d29cc10d5c2565018bce2d9edbab267c31d2caf44a63056cf99f".parse().unwrap(), &color_id(), Network::Dev);
+        let uri = Uri::new(colored.clone());
+        assert_eq!(uri.to_string(), format!("tapyrus:{}", colored));
+        assert_eq!(Uri::from_str(&uri.to_string()).unwrap(), uri);
+
+        // a color differing from the one of the address is kept
+        let other = ColorIdentifier::reissuable(Script::from(vec![0x52]));
+        let uri = Uri::new(colored.clone()).with_color_id(other.clone());
+        assert_eq!(uri.to_string(), format!("tapyrus:{}?colorid={}", colored, other));
+        assert_eq!(Uri::from_str(&uri.to_string()), Err(Error::ColorMismatch));
+    }
+}